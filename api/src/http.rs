@@ -19,10 +19,13 @@ use micro_http::{HttpServer, MediaType, Request, Response, StatusCode};
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::http_endpoint::{
-    error_response, ApiError, ApiRequest, ApiResponse, EventsHandler, ExitHandler, FsBackendInfo,
-    HttpError, HttpResult, InfoHandler, MetricsBackendHandler, MetricsBlobcacheHandler,
-    MetricsFilesHandler, MetricsHandler, MetricsInflightHandler, MetricsPatternHandler,
-    MountHandler, SendFuseFdHandler, TakeoverHandler,
+    error_response, ApiError, ApiRequest, ApiResponse, BlobcacheCompactHandler, BlobcacheGcHandler,
+    CacheHandler, DrainHandler, EventsHandler, ExitHandler, FsBackendBlobHandler, FsBackendInfo,
+    FsBackendScrubHandler, FsCacheTypeHandler, FsPrefetchHandler, FuseSessionHandler, HttpError,
+    HttpResult, InfoHandler, MetricsAccountingHandler, MetricsBackendHandler,
+    MetricsBlobcacheHandler, MetricsBufferPoolHandler, MetricsFilesHandler, MetricsHandler,
+    MetricsInflightHandler, MetricsPatternHandler, MetricsPrometheusHandler, MountHandler,
+    PrefetchJobHandler, ProfileHandler, ReadTraceHandler, SendFuseFdHandler, TakeoverHandler,
 };
 
 const HTTP_ROOT: &str = "/api/v1";
@@ -63,16 +66,31 @@ lazy_static! {
         r.routes.insert(endpoint!("/daemon"), Box::new(InfoHandler{}));
         r.routes.insert(endpoint!("/daemon/events"), Box::new(EventsHandler{}));
         r.routes.insert(endpoint!("/daemon/backend"), Box::new(FsBackendInfo{}));
+        r.routes.insert(endpoint!("/daemon/backend/scrub"), Box::new(FsBackendScrubHandler{}));
+        r.routes.insert(endpoint!("/daemon/backend/blob"), Box::new(FsBackendBlobHandler{}));
+        r.routes.insert(endpoint!("/daemon/backend/cache"), Box::new(FsCacheTypeHandler{}));
+        r.routes.insert(endpoint!("/daemon/backend/prefetch"), Box::new(FsPrefetchHandler{}));
+        r.routes.insert(endpoint!("/daemon/blobcache/gc"), Box::new(BlobcacheGcHandler{}));
+        r.routes.insert(endpoint!("/daemon/blobcache/compact"), Box::new(BlobcacheCompactHandler{}));
         r.routes.insert(endpoint!("/daemon/exit"), Box::new(ExitHandler{}));
+        r.routes.insert(endpoint!("/daemon/drain"), Box::new(DrainHandler{}));
         r.routes.insert(endpoint!("/daemon/fuse/sendfd"), Box::new(SendFuseFdHandler{}));
         r.routes.insert(endpoint!("/daemon/fuse/takeover"), Box::new(TakeoverHandler{}));
+        r.routes.insert(endpoint!("/daemon/fuse/sessions"), Box::new(FuseSessionHandler{}));
+        r.routes.insert(endpoint!("/daemon/profile"), Box::new(ProfileHandler{}));
+        r.routes.insert(endpoint!("/daemon/trace"), Box::new(ReadTraceHandler{}));
+        r.routes.insert(endpoint!("/cache"), Box::new(CacheHandler{}));
         r.routes.insert(endpoint!("/mount"), Box::new(MountHandler{}));
+        r.routes.insert(endpoint!("/prefetch"), Box::new(PrefetchJobHandler{}));
         r.routes.insert(endpoint!("/metrics"), Box::new(MetricsHandler{}));
         r.routes.insert(endpoint!("/metrics/files"), Box::new(MetricsFilesHandler{}));
         r.routes.insert(endpoint!("/metrics/pattern"), Box::new(MetricsPatternHandler{}));
         r.routes.insert(endpoint!("/metrics/backend"), Box::new(MetricsBackendHandler{}));
         r.routes.insert(endpoint!("/metrics/blobcache"), Box::new(MetricsBlobcacheHandler{}));
+        r.routes.insert(endpoint!("/metrics/accounting"), Box::new(MetricsAccountingHandler{}));
+        r.routes.insert(endpoint!("/metrics/buffer_pool"), Box::new(MetricsBufferPoolHandler{}));
         r.routes.insert(endpoint!("/metrics/inflight"), Box::new(MetricsInflightHandler{}));
+        r.routes.insert(endpoint!("/metrics/prometheus"), Box::new(MetricsPrometheusHandler{}));
         r
     };
 }
@@ -60,6 +60,8 @@ pub enum ApiError {
     DaemonAbnormal(DaemonErrorKind),
     Events(String),
     Metrics(MetricsErrorKind),
+    /// Failed to scrub cached data of a filesystem instance.
+    Scrub(DaemonErrorKind),
 }
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
@@ -71,6 +73,12 @@ pub enum ApiResponsePayload {
     DaemonInfo(String),
     Events(String),
     FsBackendInfo(String),
+    /// Result of scrubbing cached chunks of a filesystem instance.
+    FsBackendScrub(String),
+    /// Number of cached chunks invalidated.
+    CacheInvalidate(String),
+    /// Progress of data prefetch of a filesystem instance.
+    FsPrefetchStatus(String),
     /// Nydus filesystem global metrics
     FsGlobalMetrics(String),
     /// Nydus filesystem per-file metrics
@@ -78,7 +86,25 @@ pub enum ApiResponsePayload {
     FsFilesPatterns(String),
     BackendMetrics(String),
     BlobcacheMetrics(String),
+    /// Access accounting of filesystem instances.
+    AccountingMetrics(String),
+    /// Metrics of the buffer pool shared by read requests.
+    BufferPoolMetrics(String),
     InflightMetrics(String),
+    /// Metrics in Prometheus text exposition format.
+    PrometheusMetrics(String),
+    /// Cache files reclaimed by garbage collection.
+    BlobcacheGc(String),
+    /// Space and files released by compacting cache files.
+    BlobcacheCompact(String),
+    /// Fuse sessions created in singleton mode.
+    FuseSessions(String),
+    /// CPU time of threads and memory usage of the daemon.
+    DaemonProfile(String),
+    /// Id or progress of a cache warm-up job.
+    PrefetchJob(String),
+    /// State of read path tracing, or number of spans exported.
+    ReadTrace(String),
 }
 
 /// This is the response sent by the API server through the mpsc channel.
@@ -98,11 +124,32 @@ pub enum ApiRequest {
     ExportAccessPatterns(Option<String>),
     ExportBackendMetrics(Option<String>),
     ExportBlobcacheMetrics(Option<String>),
+    ExportAccountingMetrics(Option<String>, bool),
+    ExportBufferPoolMetrics,
     ExportInflightMetrics,
-    ExportFsBackendInfo(String),
+    ExportPrometheusMetrics,
+    ExportFsBackendInfo(Option<String>),
+    ScrubFsBackend(String, Option<u32>),
+    InvalidateCache(ApiInvalidateCacheCmd),
+    SwitchBlobBackend(String, ApiBlobBackendCmd),
+    SwitchCacheType(String, ApiCacheTypeCmd),
+    GetFsPrefetchStatus(String),
+    ControlFsPrefetch(String, ApiPrefetchCmd),
+    GcBlobcache(bool),
+    CompactBlobcache,
     SendFuseFd,
     Takeover,
     Exit,
+    Drain(ApiDrainCmd),
+    ListFuseSessions,
+    CreateFuseSession(String, ApiMountCmd),
+    DestroyFuseSession(String),
+    ExportDaemonProfile,
+    StartProfileSampling(ApiProfileCmd),
+    StartPrefetchJob(ApiPrefetchJobCmd),
+    GetPrefetchJob(u64),
+    GetReadTrace,
+    ControlReadTrace(ApiReadTraceCmd),
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -115,11 +162,126 @@ pub struct ApiMountCmd {
     pub prefetch_files: Option<Vec<String>>,
 }
 
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiPrefetchCmd {
+    /// One of "pause", "resume", "stop" and "restart".
+    pub action: String,
+    /// Files to prefetch when restarting, default to the files prefetched at mount time.
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiPrefetchJobCmd {
+    /// Mountpoint of the filesystem instance to warm up the cache for.
+    pub mountpoint: String,
+    /// Files and directories to prefetch, exclusive with `all`.
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
+    /// Whether to prefetch the whole image.
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiBlobBackendCmd {
+    /// Id of the blob to switch storage backend for.
+    pub blob_id: String,
+    /// Object to fetch blob data from, default to the blob id.
+    #[serde(default)]
+    pub object_id: Option<String>,
+    /// Configuration of the new storage backend, in the same format as `device.backend` of rafs
+    /// configuration. The current storage backend is kept if it's absent.
+    #[serde(default)]
+    pub backend: Option<serde_json::Value>,
+    /// Whether to drop data already cached for the blob.
+    #[serde(default)]
+    pub flush_cache: bool,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiCacheTypeCmd {
+    /// Type of blob cache to switch to, one of "blobcache", "dummy" and "direct".
+    #[serde(rename = "type")]
+    pub cache_type: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiProfileCmd {
+    /// File to write the sampling profile into.
+    pub path: String,
+    /// Seconds to sample threads of the daemon.
+    #[serde(default = "default_profile_duration")]
+    pub duration_secs: u64,
+    /// Samples per second.
+    #[serde(default = "default_profile_frequency")]
+    pub frequency: u32,
+}
+
+fn default_profile_duration() -> u64 {
+    10
+}
+
+fn default_profile_frequency() -> u32 {
+    99
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiInvalidateCacheCmd {
+    /// Filesystem to invalidate cache for, all RAFS filesystems if None.
+    pub mountpoint: Option<String>,
+    /// Only invalidate cached chunks of the blob.
+    pub blob_id: Option<String>,
+    /// Only invalidate cached chunks of files at or under the path in the image.
+    pub path: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiReadTraceCmd {
+    /// One of "start", "stop" and "export".
+    pub action: String,
+    /// Upper limit of spans to keep when starting.
+    #[serde(default = "default_read_trace_capacity")]
+    pub capacity: usize,
+    /// Export format, either "chrome" or "otlp".
+    #[serde(default)]
+    pub format: Option<String>,
+    /// File to write the Chrome trace into.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// OTLP/HTTP endpoint to send spans to, e.g. "http://127.0.0.1:4318".
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+fn default_read_trace_capacity() -> usize {
+    100_000
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct ApiUmountCmd {
     pub mountpoint: String,
 }
 
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiDrainCmd {
+    /// Seconds to wait for inflight requests before umount.
+    #[serde(default = "default_drain_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_drain_timeout() -> u64 {
+    10
+}
+
+impl Default for ApiDrainCmd {
+    fn default() -> Self {
+        ApiDrainCmd {
+            timeout_secs: default_drain_timeout(),
+        }
+    }
+}
+
 fn parse_body<'a, F: Deserialize<'a>>(b: &'a Body) -> Result<F, HttpError> {
     serde_json::from_slice::<F>(b.raw()).map_err(HttpError::ParseBody)
 }
@@ -149,10 +311,25 @@ pub enum HttpError {
     Pattern(ApiError),
     Configure(ApiError),
     Upgrade(ApiError),
+    Drain(ApiError),
     BlobcacheMetrics(ApiError),
     BackendMetrics(ApiError),
+    AccountingMetrics(ApiError),
+    BufferPoolMetrics(ApiError),
     FsBackendInfo(ApiError),
+    FsBackendScrub(ApiError),
+    CacheInvalidate(ApiError),
+    FsBackendBlob(ApiError),
+    FsCacheType(ApiError),
+    FsPrefetch(ApiError),
     InflightMetrics(ApiError),
+    PrometheusMetrics(ApiError),
+    BlobcacheGc(ApiError),
+    BlobcacheCompact(ApiError),
+    FuseSession(ApiError),
+    Profile(ApiError),
+    PrefetchJob(ApiError),
+    ReadTrace(ApiError),
 }
 
 fn success_response(body: Option<String>) -> Response {
@@ -222,8 +399,20 @@ fn convert_to_response<O: FnOnce(ApiError) -> HttpError>(api_resp: ApiResponse,
                 FsFilesPatterns(d) => success_response(Some(d)),
                 BackendMetrics(d) => success_response(Some(d)),
                 BlobcacheMetrics(d) => success_response(Some(d)),
+                AccountingMetrics(d) => success_response(Some(d)),
+                BufferPoolMetrics(d) => success_response(Some(d)),
                 FsBackendInfo(d) => success_response(Some(d)),
+                FsBackendScrub(d) => success_response(Some(d)),
+                CacheInvalidate(d) => success_response(Some(d)),
+                FsPrefetchStatus(d) => success_response(Some(d)),
                 InflightMetrics(d) => success_response(Some(d)),
+                PrometheusMetrics(d) => success_response(Some(d)),
+                BlobcacheGc(d) => success_response(Some(d)),
+                BlobcacheCompact(d) => success_response(Some(d)),
+                FuseSessions(d) => success_response(Some(d)),
+                DaemonProfile(d) => success_response(Some(d)),
+                PrefetchJob(d) => success_response(Some(d)),
+                ReadTrace(d) => success_response(Some(d)),
             }
         }
         Err(e) => {
@@ -394,6 +583,46 @@ impl EndpointHandler for MetricsBlobcacheHandler {
     }
 }
 
+pub struct MetricsAccountingHandler {}
+impl EndpointHandler for MetricsAccountingHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        let id = extract_query_part(req, "id");
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportAccountingMetrics(id, false));
+                Ok(convert_to_response(r, HttpError::AccountingMetrics))
+            }
+            // Report and reset the counters.
+            (Method::Put, None) => {
+                let r = kicker(ApiRequest::ExportAccountingMetrics(id, true));
+                Ok(convert_to_response(r, HttpError::AccountingMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct MetricsBufferPoolHandler {}
+impl EndpointHandler for MetricsBufferPoolHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportBufferPoolMetrics);
+                Ok(convert_to_response(r, HttpError::BufferPoolMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 pub struct MetricsInflightHandler {}
 impl EndpointHandler for MetricsInflightHandler {
     fn handle_request(
@@ -411,6 +640,23 @@ impl EndpointHandler for MetricsInflightHandler {
     }
 }
 
+pub struct MetricsPrometheusHandler {}
+impl EndpointHandler for MetricsPrometheusHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportPrometheusMetrics);
+                Ok(convert_to_response(r, HttpError::PrometheusMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 pub struct SendFuseFdHandler {}
 impl EndpointHandler for SendFuseFdHandler {
     fn handle_request(
@@ -462,6 +708,110 @@ impl EndpointHandler for ExitHandler {
     }
 }
 
+pub struct DrainHandler {}
+impl EndpointHandler for DrainHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, None) => {
+                let r = kicker(ApiRequest::Drain(ApiDrainCmd::default()));
+                Ok(convert_to_response(r, HttpError::Drain))
+            }
+            (Method::Put, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::Drain(cmd));
+                Ok(convert_to_response(r, HttpError::Drain))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Manage fuse sessions of a nydusd working in singleton mode, each serving a filesystem at a
+/// separate mountpoint.
+pub struct FuseSessionHandler {}
+impl EndpointHandler for FuseSessionHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        let mountpoint = || {
+            extract_query_part(req, "mountpoint").ok_or_else(|| {
+                HttpError::QueryString(
+                    "'mountpoint' should be specified in query string".to_string(),
+                )
+            })
+        };
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ListFuseSessions);
+                Ok(convert_to_response(r, HttpError::FuseSession))
+            }
+            (Method::Post, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::CreateFuseSession(mountpoint()?, cmd));
+                Ok(convert_to_response(r, HttpError::FuseSession))
+            }
+            (Method::Delete, None) => {
+                let r = kicker(ApiRequest::DestroyFuseSession(mountpoint()?));
+                Ok(convert_to_response(r, HttpError::FuseSession))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Report CPU time of threads and memory usage of the daemon, or start sampling threads of the
+/// daemon in background and write the profile into a file.
+pub struct ProfileHandler {}
+impl EndpointHandler for ProfileHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportDaemonProfile);
+                Ok(convert_to_response(r, HttpError::Profile))
+            }
+            (Method::Put, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::StartProfileSampling(cmd));
+                Ok(convert_to_response(r, HttpError::Profile))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Get state of read path tracing, or start, stop tracing and export spans.
+pub struct ReadTraceHandler {}
+impl EndpointHandler for ReadTraceHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::GetReadTrace);
+                Ok(convert_to_response(r, HttpError::ReadTrace))
+            }
+            (Method::Put, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::ControlReadTrace(cmd));
+                Ok(convert_to_response(r, HttpError::ReadTrace))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 pub struct FsBackendInfo {}
 
 impl EndpointHandler for FsBackendInfo {
@@ -472,13 +822,213 @@ impl EndpointHandler for FsBackendInfo {
     ) -> HttpResult {
         match (req.method(), req.body.as_ref()) {
             (Method::Get, None) => {
+                let mountpoint = extract_query_part(req, "mountpoint");
+                let r = kicker(ApiRequest::ExportFsBackendInfo(mountpoint));
+                Ok(convert_to_response(r, HttpError::FsBackendInfo))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct FsBackendBlobHandler {}
+
+impl EndpointHandler for FsBackendBlobHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, Some(body)) => {
                 let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
                     HttpError::QueryString(
                         "'mountpoint' should be specified in query string".to_string(),
                     )
                 })?;
-                let r = kicker(ApiRequest::ExportFsBackendInfo(mountpoint));
-                Ok(convert_to_response(r, HttpError::FsBackendInfo))
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::SwitchBlobBackend(mountpoint, cmd));
+                Ok(convert_to_response(r, HttpError::FsBackendBlob))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct FsCacheTypeHandler {}
+
+impl EndpointHandler for FsCacheTypeHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, Some(body)) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::SwitchCacheType(mountpoint, cmd));
+                Ok(convert_to_response(r, HttpError::FsCacheType))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct FsPrefetchHandler {}
+
+impl EndpointHandler for FsPrefetchHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+            HttpError::QueryString("'mountpoint' should be specified in query string".to_string())
+        })?;
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::GetFsPrefetchStatus(mountpoint));
+                Ok(convert_to_response(r, HttpError::FsPrefetch))
+            }
+            (Method::Put, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::ControlFsPrefetch(mountpoint, cmd));
+                Ok(convert_to_response(r, HttpError::FsPrefetch))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Start a job to warm up the cache of a filesystem instance, or poll progress of the job.
+pub struct PrefetchJobHandler {}
+
+impl EndpointHandler for PrefetchJobHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let id = extract_query_part(req, "job_id")
+                    .and_then(|id| id.parse::<u64>().ok())
+                    .ok_or_else(|| {
+                        HttpError::QueryString(
+                            "'job_id' should be specified in query string".to_string(),
+                        )
+                    })?;
+                let r = kicker(ApiRequest::GetPrefetchJob(id));
+                Ok(convert_to_response(r, HttpError::PrefetchJob))
+            }
+            (Method::Post, Some(body)) => {
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::StartPrefetchJob(cmd));
+                Ok(convert_to_response(r, HttpError::PrefetchJob))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct BlobcacheGcHandler {}
+
+impl EndpointHandler for BlobcacheGcHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, None) => {
+                let force = match extract_query_part(req, "force") {
+                    Some(f) => f.parse::<bool>().map_err(|_| {
+                        HttpError::QueryString("'force' should be a boolean".to_string())
+                    })?,
+                    None => false,
+                };
+                let r = kicker(ApiRequest::GcBlobcache(force));
+                Ok(convert_to_response(r, HttpError::BlobcacheGc))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct BlobcacheCompactHandler {}
+
+impl EndpointHandler for BlobcacheCompactHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, None) => {
+                let r = kicker(ApiRequest::CompactBlobcache);
+                Ok(convert_to_response(r, HttpError::BlobcacheCompact))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct FsBackendScrubHandler {}
+
+impl EndpointHandler for FsBackendScrubHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Put, None) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let chunks = match extract_query_part(req, "chunks") {
+                    Some(c) => Some(c.parse::<u32>().map_err(|_| {
+                        HttpError::QueryString("'chunks' should be an integer".to_string())
+                    })?),
+                    None => None,
+                };
+                let r = kicker(ApiRequest::ScrubFsBackend(mountpoint, chunks));
+                Ok(convert_to_response(r, HttpError::FsBackendScrub))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+pub struct CacheHandler {}
+
+impl EndpointHandler for CacheHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Delete, None) => {
+                let cmd = ApiInvalidateCacheCmd {
+                    mountpoint: extract_query_part(req, "mountpoint"),
+                    blob_id: extract_query_part(req, "blob_id"),
+                    path: extract_query_part(req, "path"),
+                };
+                if cmd.blob_id.is_none() && cmd.path.is_none() {
+                    return Err(HttpError::QueryString(
+                        "'blob_id' or 'path' should be specified in query string".to_string(),
+                    ));
+                }
+                let r = kicker(ApiRequest::InvalidateCache(cmd));
+                Ok(convert_to_response(r, HttpError::CacheInvalidate))
             }
             _ => Err(HttpError::BadRequest),
         }
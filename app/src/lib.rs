@@ -11,6 +11,7 @@
 //! - Logging helpers: [`fn setup_logging()`](fn.set_logging.html) and
 //!   [`fn log_level_to_verbosity()`](fn.log_level_to_verbosity.html).
 //! - Signal handling: [`fn register_signal_handler()`](signal/fn.register_signal_handler.html).
+//! - Panic handling: [`fn install_panic_hook()`](fn.install_panic_hook.html).
 //!
 //! ```rust,ignore
 //! #[macro_use(crate_authors, crate_version)]
@@ -44,7 +45,10 @@ extern crate serde;
 
 use std::env::current_dir;
 use std::io::Result;
+use std::panic;
 use std::path::PathBuf;
+use std::sync::Once;
+use std::thread;
 
 use flexi_logger::{self, colored_opt_format, opt_format, Logger};
 use log::LevelFilter;
@@ -63,6 +67,25 @@ pub mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
+static PANIC_HOOK: Once = Once::new();
+
+/// Abort the process on panic as `panic = "abort"` does, except for panics of threads named in
+/// `recoverable`, which are left to unwind for the caller to catch and recover from.
+///
+/// Binaries are built with `panic = "unwind"`, so every binary should install the hook at startup.
+pub fn install_panic_hook(recoverable: &'static [&'static str]) {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            match thread::current().name() {
+                Some(name) if recoverable.contains(&name) => {}
+                _ => std::process::abort(),
+            }
+        }));
+    });
+}
+
 /// Dump program build and version information.
 pub fn dump_program_info(prog_version: &str) {
     info!(
@@ -0,0 +1,42 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// C interfaces to access RAFS filesystems in-process, provided by libnydus_clib.so.
+
+#ifndef NYDUS_H
+#define NYDUS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct NydusFs NydusFs;
+
+// Callback to receive directory entries, returning non-zero value to stop the enumeration.
+typedef int (*NydusDirentCallback)(const char *name, uint64_t ino, void *data);
+
+// Create a RAFS filesystem instance from a bootstrap file and a JSON format rafs configuration.
+// Returns NULL on failure with errno set.
+NydusFs *nydus_fs_new(const char *bootstrap, const char *config);
+
+// Read up to `size` bytes of the regular file at `path`, starting from `offset`.
+// Returns number of bytes read into `buf`, or a negative errno value on failure.
+int64_t nydus_fs_read(const NydusFs *fs, const char *path, uint64_t offset, uint8_t *buf,
+                      size_t size);
+
+// Enumerate entries of the directory at `path`, except "." and "..".
+// Returns 0 on success, or a negative errno value on failure.
+int nydus_fs_readdir(const NydusFs *fs, const char *path, NydusDirentCallback cb, void *data);
+
+// Destroy a RAFS filesystem instance created by `nydus_fs_new()`.
+void nydus_fs_destroy(NydusFs *fs);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // NYDUS_H
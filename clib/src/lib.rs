@@ -0,0 +1,469 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! C interfaces to access RAFS filesystems in-process.
+//!
+//! Runtimes may embed nydus through this library instead of spawning a nydusd process. A RAFS
+//! filesystem instance is created from a bootstrap file and a rafs configuration, then files
+//! can be read and directories can be enumerated by absolute paths within the filesystem.
+//! All interfaces return negative errno values on failure, see `include/nydus.h` for details.
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate nydus_error;
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Result};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, ZeroCopyWriter};
+use fuse_backend_rs::transport::{FileReadWriteVolatile, FileVolatileSlice};
+use rafs::fs::{Rafs, RafsConfig};
+use rafs::RafsIoRead;
+
+const ROOT_INODE: u64 = 1;
+const READDIR_BUF_SIZE: u32 = 0x1000;
+
+/// Callback to receive directory entries, returning non-zero value to stop the enumeration.
+pub type NydusDirentCallback =
+    extern "C" fn(name: *const c_char, ino: u64, data: *mut c_void) -> c_int;
+
+/// A RAFS filesystem instance.
+pub struct NydusFs {
+    rafs: Rafs,
+}
+
+/// Writer to receive file data into a buffer provided by the caller.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> io::Write for SliceWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let cnt = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+        self.buf[self.pos..self.pos + cnt].copy_from_slice(&buf[..cnt]);
+        self.pos += cnt;
+        Ok(cnt)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ZeroCopyWriter for SliceWriter<'a> {
+    fn write_from(
+        &mut self,
+        f: &mut dyn FileReadWriteVolatile,
+        count: usize,
+        off: u64,
+    ) -> Result<usize> {
+        let count = std::cmp::min(count, self.buf.len() - self.pos);
+        // Safe because the buffer is valid for `count` bytes starting at `pos`.
+        let slice = unsafe { FileVolatileSlice::new(self.buf[self.pos..].as_mut_ptr(), count) };
+        let cnt = f.read_vectored_at_volatile(&[slice], off)?;
+        self.pos += cnt;
+        Ok(cnt)
+    }
+}
+
+impl NydusFs {
+    fn new(bootstrap: &str, config: &str) -> Result<Self> {
+        let rafs_config = RafsConfig::from_str(config).map_err(|e| einval!(e))?;
+        let mut reader = <dyn RafsIoRead>::from_file(bootstrap).map_err(|e| einval!(e))?;
+        let mut rafs = Rafs::new(rafs_config, bootstrap, &mut reader).map_err(|e| einval!(e))?;
+        rafs.import(reader, None).map_err(|e| einval!(e))?;
+
+        Ok(NydusFs { rafs })
+    }
+
+    fn ctx() -> Context {
+        Context {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        }
+    }
+
+    fn lookup_path(&self, path: &str) -> Result<u64> {
+        let ctx = Self::ctx();
+        let mut ino = ROOT_INODE;
+
+        for name in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            let name = CString::new(name).map_err(|e| enoent!(e))?;
+            let entry = self.rafs.lookup(&ctx, ino, &name)?;
+            if entry.inode == 0 {
+                return Err(enoent!(path));
+            }
+            ino = entry.inode;
+        }
+
+        Ok(ino)
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let ctx = Self::ctx();
+        let ino = self.lookup_path(path)?;
+        let (attr, _) = self.rafs.getattr(&ctx, ino, None)?;
+        match attr.st_mode & libc::S_IFMT {
+            libc::S_IFREG => {}
+            libc::S_IFDIR => return Err(eisdir!(path)),
+            _ => return Err(einval!(path)),
+        }
+
+        let mut writer = SliceWriter { buf, pos: 0 };
+        while writer.pos < writer.buf.len() {
+            let size = std::cmp::min(writer.buf.len() - writer.pos, u32::MAX as usize) as u32;
+            let cnt = self.rafs.read(
+                &ctx,
+                ino,
+                0,
+                &mut writer,
+                size,
+                offset + writer.pos as u64,
+                None,
+                0,
+            )?;
+            if cnt == 0 {
+                break;
+            }
+        }
+
+        Ok(writer.pos)
+    }
+
+    fn readdir(&self, path: &str, cb: NydusDirentCallback, data: *mut c_void) -> Result<()> {
+        let ctx = Self::ctx();
+        let ino = self.lookup_path(path)?;
+        let (attr, _) = self.rafs.getattr(&ctx, ino, None)?;
+        if attr.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            return Err(enotdir!(path));
+        }
+
+        let mut offset = 0;
+        let mut stopped = false;
+
+        while !stopped {
+            let mut added = 0;
+            self.rafs
+                .readdir(&ctx, ino, 0, READDIR_BUF_SIZE, offset, &mut |entry| {
+                    offset = entry.offset;
+                    added += 1;
+                    if entry.name != b"." && entry.name != b".." {
+                        let name = CString::new(entry.name).map_err(|e| einval!(e))?;
+                        if cb(name.as_ptr(), entry.ino, data) != 0 {
+                            stopped = true;
+                            // Stop filling more entries.
+                            return Ok(0);
+                        }
+                    }
+                    Ok(entry.name.len())
+                })?;
+            if added == 0 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn to_errno(e: io::Error) -> c_int {
+    let errno = match (e.raw_os_error(), e.kind()) {
+        (Some(errno), _) => errno,
+        (None, io::ErrorKind::NotFound) => libc::ENOENT,
+        (None, io::ErrorKind::InvalidInput) => libc::EINVAL,
+        (None, io::ErrorKind::PermissionDenied) => libc::EACCES,
+        (None, _) => libc::EIO,
+    };
+    -errno
+}
+
+// Unwinding across the C boundary is undefined behavior, so convert panics into EIO errors.
+fn catch_panic<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(eio!("panicked in nydus C interfaces")))
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(einval!("null string pointer"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| einval!("invalid utf-8 string"))
+}
+
+/// Create a RAFS filesystem instance from a bootstrap file and a JSON format rafs configuration.
+///
+/// Returns a pointer to the instance, or NULL on failure with `errno` set.
+///
+/// # Safety
+/// `bootstrap` and `config` must be valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn nydus_fs_new(
+    bootstrap: *const c_char,
+    config: *const c_char,
+) -> *mut NydusFs {
+    let result = catch_panic(|| {
+        let bootstrap = to_str(bootstrap)?;
+        let config = to_str(config)?;
+        NydusFs::new(bootstrap, config)
+    });
+
+    match result {
+        Ok(fs) => Box::into_raw(Box::new(fs)),
+        Err(e) => {
+            error!("failed to create rafs instance, {}", e);
+            *libc::__errno_location() = -to_errno(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Read up to `size` bytes of the regular file at `path`, starting from `offset`.
+///
+/// Returns number of bytes read into `buf`, or a negative errno value on failure.
+///
+/// # Safety
+/// `fs` must be created by `nydus_fs_new()`, `path` must be a valid NUL terminated string, and
+/// `buf` must be valid for writing `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nydus_fs_read(
+    fs: *const NydusFs,
+    path: *const c_char,
+    offset: u64,
+    buf: *mut u8,
+    size: usize,
+) -> i64 {
+    if fs.is_null() || (buf.is_null() && size > 0) {
+        return -libc::EINVAL as i64;
+    }
+    let buf: &mut [u8] = if size == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(buf, size)
+    };
+
+    match catch_panic(|| (*fs).read(to_str(path)?, offset, buf)) {
+        Ok(cnt) => cnt as i64,
+        Err(e) => to_errno(e) as i64,
+    }
+}
+
+/// Enumerate entries of the directory at `path`, except "." and "..".
+///
+/// `cb` is invoked for each entry with `data`, and the enumeration stops if it returns a
+/// non-zero value. Returns 0 on success, or a negative errno value on failure.
+///
+/// # Safety
+/// `fs` must be created by `nydus_fs_new()`, and `path` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nydus_fs_readdir(
+    fs: *const NydusFs,
+    path: *const c_char,
+    cb: NydusDirentCallback,
+    data: *mut c_void,
+) -> c_int {
+    if fs.is_null() {
+        return -libc::EINVAL;
+    }
+
+    match catch_panic(|| (*fs).readdir(to_str(path)?, cb, data)) {
+        Ok(()) => 0,
+        Err(e) => to_errno(e),
+    }
+}
+
+/// Destroy a RAFS filesystem instance created by `nydus_fs_new()`.
+///
+/// # Safety
+/// `fs` must be created by `nydus_fs_new()` and must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn nydus_fs_destroy(fs: *mut NydusFs) {
+    if !fs.is_null() {
+        let mut fs = Box::from_raw(fs);
+        if let Err(e) = catch_panic(|| fs.rafs.destroy()) {
+            warn!("failed to destroy rafs instance, {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_arguments() {
+        unsafe {
+            assert!(nydus_fs_new(ptr::null(), ptr::null()).is_null());
+            assert_eq!(*libc::__errno_location(), libc::EINVAL);
+
+            let bootstrap = CString::new("/nonexistent/bootstrap").unwrap();
+            let config = CString::new("{}").unwrap();
+            assert!(nydus_fs_new(bootstrap.as_ptr(), config.as_ptr()).is_null());
+
+            let path = CString::new("/").unwrap();
+            let mut buf = [0u8; 16];
+            assert_eq!(
+                nydus_fs_read(ptr::null(), path.as_ptr(), 0, buf.as_mut_ptr(), buf.len()),
+                -libc::EINVAL as i64
+            );
+            nydus_fs_destroy(ptr::null_mut());
+        }
+    }
+
+    const TEXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/texture/repeatable");
+
+    extern "C" fn collect_names(name: *const c_char, _ino: u64, data: *mut c_void) -> c_int {
+        let names = unsafe { &mut *(data as *mut Vec<String>) };
+        let name = unsafe { CStr::from_ptr(name) };
+        names.push(name.to_str().unwrap().to_owned());
+        0
+    }
+
+    extern "C" fn stop_at_first(_name: *const c_char, _ino: u64, data: *mut c_void) -> c_int {
+        let count = unsafe { &mut *(data as *mut u32) };
+        *count += 1;
+        1
+    }
+
+    fn new_texture_fs() -> *mut NydusFs {
+        let bootstrap =
+            CString::new(format!("{}/sha256-nocompress-repeatable", TEXTURE_DIR)).unwrap();
+        let config = CString::new(format!(
+            r#"{{"device": {{"backend": {{"type": "localfs", "config": {{"dir": "{}/blobs"}}}}}}, "mode": "direct", "digest_validate": true}}"#,
+            TEXTURE_DIR
+        ))
+        .unwrap();
+        let fs = unsafe { nydus_fs_new(bootstrap.as_ptr(), config.as_ptr()) };
+        assert!(!fs.is_null());
+        fs
+    }
+
+    fn read_file(fs: *const NydusFs, path: &str, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let path = CString::new(path).unwrap();
+        let mut buf = vec![0u8; size];
+        let ret = unsafe { nydus_fs_read(fs, path.as_ptr(), offset, buf.as_mut_ptr(), size) };
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret as i32));
+        }
+        buf.truncate(ret as usize);
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_readdir_texture() {
+        let fs = new_texture_fs();
+        let mut names: Vec<String> = Vec::new();
+        let root = CString::new("/").unwrap();
+        let ret = unsafe {
+            nydus_fs_readdir(
+                fs,
+                root.as_ptr(),
+                collect_names,
+                &mut names as *mut Vec<String> as *mut c_void,
+            )
+        };
+        assert_eq!(ret, 0);
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["hardlink-test", "normal-file-test", "symlink-test"]
+        );
+
+        let mut names: Vec<String> = Vec::new();
+        let dir = CString::new("/hardlink-test/").unwrap();
+        let ret = unsafe {
+            nydus_fs_readdir(
+                fs,
+                dir.as_ptr(),
+                collect_names,
+                &mut names as *mut Vec<String> as *mut c_void,
+            )
+        };
+        assert_eq!(ret, 0);
+        names.sort();
+        assert_eq!(names, vec!["foo", "test.sh"]);
+
+        let mut count = 0u32;
+        let ret = unsafe {
+            nydus_fs_readdir(
+                fs,
+                root.as_ptr(),
+                stop_at_first,
+                &mut count as *mut u32 as *mut c_void,
+            )
+        };
+        assert_eq!(ret, 0);
+        assert_eq!(count, 1);
+
+        let file = CString::new("/hardlink-test/foo").unwrap();
+        let missing = CString::new("/nonexistent").unwrap();
+        unsafe {
+            assert_eq!(
+                nydus_fs_readdir(fs, missing.as_ptr(), collect_names, ptr::null_mut()),
+                -libc::ENOENT
+            );
+            assert_eq!(
+                nydus_fs_readdir(fs, file.as_ptr(), collect_names, ptr::null_mut()),
+                -libc::ENOTDIR
+            );
+            nydus_fs_destroy(fs);
+        }
+    }
+
+    #[test]
+    fn test_read_texture() {
+        let fs = new_texture_fs();
+
+        // Hardlinks and the file they were copied from share the same content.
+        let foo = read_file(fs, "/hardlink-test/foo", 0, 0x10000).unwrap();
+        assert!(!foo.is_empty());
+        assert!(foo.len() < 0x10000);
+        assert_eq!(
+            read_file(fs, "/hardlink-test/test.sh", 0, 0x10000).unwrap(),
+            foo
+        );
+        assert_eq!(
+            read_file(fs, "/normal-file-test/test.sh", 0, 0x10000).unwrap(),
+            foo
+        );
+
+        // Partial reads at an offset, and reads beyond the end of file.
+        assert_eq!(
+            read_file(fs, "/hardlink-test/foo", 1, 2).unwrap(),
+            &foo[1..3]
+        );
+        assert!(read_file(fs, "/hardlink-test/foo", foo.len() as u64, 16)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            read_file(fs, "/hardlink-test", 0, 16)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EISDIR)
+        );
+        assert_eq!(
+            read_file(fs, "/symlink-test/foo", 0, 16)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            read_file(fs, "/hardlink-test/nonexistent", 0, 16)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+
+        unsafe { nydus_fs_destroy(fs) };
+    }
+}
@@ -16,30 +16,48 @@
 
 use std::any::Any;
 use std::cmp;
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::ffi::{CStr, OsStr};
 use std::fmt;
 use std::fs::File;
-use std::io::Result;
+use std::io::{Error, Result, Seek, SeekFrom, Write};
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
+use arc_swap::ArcSwap;
 use nix::unistd::{getegid, geteuid};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use fuse_backend_rs::abi::linux_abi::Attr;
 use fuse_backend_rs::api::filesystem::*;
-use fuse_backend_rs::api::BackendFileSystem;
-use nydus_utils::metrics::{self, FopRecorder, StatsFop::*};
+use fuse_backend_rs::api::{BackendFileSystem, CreateIn};
+use nydus_utils::metrics::{self, FopRecorder, StatsFop::*, WriteFop};
+use nydus_utils::trace;
 use storage::cache::BlobPrefetchConfig;
-use storage::device::{BlobDevice, BlobPrefetchRequest};
-use storage::factory::FactoryConfig;
-
+use storage::device::v5::BlobV5ChunkInfo;
+use storage::device::{
+    BlobCacheState, BlobCachedExtent, BlobChunkInfo, BlobDevice, BlobInfo, BlobIoVec,
+    BlobPrefetchRequest, BlobScrubStat,
+};
+use storage::factory::{BackendConfig, BlobFactory, FactoryConfig};
+use storage::meta::BlobProvenance;
+
+use crate::image_info::{
+    ImageInfoFile, IMAGE_INFO_DIR, IMAGE_INFO_DIR_INO, IMAGE_INFO_FILE, IMAGE_INFO_FILE_INO,
+};
+use crate::metadata::delta;
 use crate::metadata::layout::RAFS_ROOT_INODE;
-use crate::metadata::{Inode, RafsInode, RafsSuper, RafsSuperMeta, RAFS_DEFAULT_CHUNK_SIZE};
+use crate::metadata::shared::SharedBootstrap;
+use crate::metadata::{
+    Inode, RafsInode, RafsMode, RafsSuper, RafsSuperMeta, RAFS_DEFAULT_CHUNK_SIZE,
+};
 use crate::{RafsError, RafsIoReader, RafsResult};
 
 /// Type of RAFS fuse handle.
@@ -52,6 +70,11 @@ pub const RAFS_DEFAULT_ENTRY_TIMEOUT: u64 = RAFS_DEFAULT_ATTR_TIMEOUT;
 
 const DOT: &str = ".";
 const DOTDOT: &str = "..";
+// Number of recent prefetch tasks to keep progress of, for polling warm-up jobs.
+const PREFETCH_JOB_HISTORY: usize = 16;
+
+// Prefetch tasks of all filesystem instances are numbered by this counter as job ids.
+static PREFETCH_JOB_ID: AtomicU64 = AtomicU64::new(1);
 
 fn default_threads_count() -> usize {
     8
@@ -69,8 +92,21 @@ fn default_amplify_io() -> u32 {
     128 * 1024
 }
 
+fn default_scrub_interval() -> u64 {
+    3600
+}
+
+fn default_scrub_chunks() -> u32 {
+    256
+}
+
+fn default_shared_bootstrap_dir() -> String {
+    "/dev/shm/nydus-bootstrap".to_string()
+}
+
 /// Configuration information for filesystem data prefetch.
-#[derive(Clone, Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct FsPrefetchControl {
     /// Whether the filesystem layer data prefetch is enable or not.
     #[serde(default)]
@@ -100,6 +136,142 @@ pub struct FsPrefetchControl {
     pub prefetch_all: bool,
 }
 
+/// Configuration information for the blob cache scrubber.
+///
+/// The scrubber periodically samples chunks already cached locally, re-verifies their digests
+/// and invalidates corrupted chunks, so they will be fetched from the storage backend again.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsScrubControl {
+    /// Whether to scrub cached chunks in background.
+    #[serde(default)]
+    pub enable: bool,
+
+    /// Interval in unit of seconds between two scrub rounds.
+    #[serde(default = "default_scrub_interval")]
+    pub interval: u64,
+
+    /// Maximum number of cached chunks to verify in each scrub round.
+    #[serde(default = "default_scrub_chunks")]
+    pub chunks: u32,
+}
+
+impl Default for FsScrubControl {
+    fn default() -> Self {
+        FsScrubControl {
+            enable: false,
+            interval: default_scrub_interval(),
+            chunks: default_scrub_chunks(),
+        }
+    }
+}
+
+/// Configuration information to read file data from the storage backend without caching.
+///
+/// Caching huge files which are read only once, such as multi-GB model files, may evict all other
+/// data from the blob cache. Reads of matching files are served directly from the storage backend,
+/// and the fetched data is not written into the blob cache.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsUncachedReadControl {
+    /// Whether to read matching files without caching.
+    #[serde(default)]
+    pub enable: bool,
+
+    /// Files with size equal to or bigger than the threshold are read without caching, zero
+    /// to disable the size based policy.
+    #[serde(default)]
+    pub size_threshold: u64,
+
+    /// Absolute paths of files to read without caching.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// Configuration information to check blobs against the bootstrap at mount time.
+///
+/// Mounting with missing or mismatched blobs causes obscure IO errors later, so blobs may be
+/// checked for existence, size and TOC if present before mounting. It may be disabled for setups
+/// where blobs are not available when mounting, e.g. air-gapped setups.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsBlobCheckControl {
+    /// Whether to check blobs when mounting.
+    #[serde(default)]
+    pub enable: bool,
+
+    /// Whether to verify blob data against digests in the TOC, which needs to read whole blobs.
+    #[serde(default)]
+    pub verify_digest: bool,
+}
+
+/// Configuration to verify the image digest when mounting.
+///
+/// The image digest recorded in the bootstrap is checked against content of the bootstrap, and
+/// against `expected` if specified, to pin the identity of the image.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsImageDigestControl {
+    /// Whether to verify the image digest when mounting.
+    #[serde(default)]
+    pub verify: bool,
+
+    /// Expected image digest in hex, optionally prefixed by `sha256:`, which implies `verify`.
+    #[serde(default)]
+    pub expected: Option<String>,
+}
+
+/// Configuration to share direct-mapped bootstraps among nydusd instances.
+///
+/// Bootstraps are copied into `dir` and named by digests of their content, so instances mounting
+/// the same bootstrap map the same file and share its page cache. Only for direct mode.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsSharedBootstrapControl {
+    /// Whether to share the bootstrap.
+    #[serde(default)]
+    pub enable: bool,
+
+    /// Directory to hold shared bootstraps, which should be on tmpfs.
+    #[serde(default = "default_shared_bootstrap_dir")]
+    pub dir: String,
+}
+
+impl Default for FsSharedBootstrapControl {
+    fn default() -> Self {
+        FsSharedBootstrapControl {
+            enable: false,
+            dir: default_shared_bootstrap_dir(),
+        }
+    }
+}
+
+/// Configuration to fetch bootstraps from the storage backend when mounting.
+///
+/// Bootstraps which don't exist locally are downloaded from object `object_id` of the storage
+/// backend. If `delta_object_id` is specified, the delta object is applied to the parent bootstrap
+/// cached in `cache_dir` instead, so only metadata pages changed against the parent are
+/// downloaded. The whole bootstrap is downloaded if the parent isn't cached.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsBootstrapFetchControl {
+    /// Whether to fetch missing bootstraps from the storage backend.
+    #[serde(default)]
+    pub enable: bool,
+
+    /// Object id of the bootstrap in the storage backend.
+    #[serde(default)]
+    pub object_id: String,
+
+    /// Object id of the delta against a parent bootstrap in the storage backend.
+    #[serde(default)]
+    pub delta_object_id: Option<String>,
+
+    /// Directory to cache fetched bootstraps, as parents of later deltas.
+    #[serde(default)]
+    pub cache_dir: String,
+}
+
 impl TryFrom<&RafsConfig> for BlobPrefetchConfig {
     type Error = RafsError;
 
@@ -137,6 +309,7 @@ macro_rules! trim_backend_config {
 
 /// Rafs storage backend configuration information.
 #[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct RafsConfig {
     /// Configuration for storage subsystem.
     pub device: FactoryConfig,
@@ -163,6 +336,43 @@ pub struct RafsConfig {
     // ZERO value means, amplifying user io is not enabled.
     #[serde(default = "default_amplify_io")]
     pub amplify_io: u32,
+    /// Blob cache scrubber configuration.
+    #[serde(default)]
+    pub fs_scrub: FsScrubControl,
+    /// Reply fuse read requests by splice(2) if data is cached in uncompressed form.
+    #[serde(default)]
+    pub splice_read: bool,
+    /// Load the bootstrap into memory in background after mounting, only for direct mode.
+    #[serde(default)]
+    pub metadata_prefetch: bool,
+    /// Policy to read huge files from the storage backend without caching.
+    #[serde(default)]
+    pub uncached_read: FsUncachedReadControl,
+    /// Check blobs against the bootstrap when mounting.
+    #[serde(default)]
+    pub blob_check: FsBlobCheckControl,
+    /// Verify the image digest when mounting.
+    #[serde(default)]
+    pub image_digest: FsImageDigestControl,
+    /// Absolute path of the directory in the image to present as the filesystem root, the
+    /// whole image is presented if not specified.
+    #[serde(default)]
+    pub root_path: Option<String>,
+    /// Share the direct-mapped bootstrap with other nydusd instances.
+    #[serde(default)]
+    pub shared_bootstrap: FsSharedBootstrapControl,
+    /// Log write-class operations rejected by the read-only filesystem.
+    #[serde(default)]
+    pub audit_writes: bool,
+    /// Expose image metadata as a virtual file `.nydus/image.json` in the root directory.
+    #[serde(default)]
+    pub image_info: bool,
+    /// Fetch the bootstrap from the storage backend if it doesn't exist locally.
+    #[serde(default)]
+    pub bootstrap_fetch: FsBootstrapFetchControl,
+    /// FUSE features of the daemon, which are parsed and validated by nydusd.
+    #[serde(default)]
+    pub fuse: Option<serde_json::Value>,
 }
 
 impl RafsConfig {
@@ -178,6 +388,100 @@ impl RafsConfig {
         let file = File::open(path).map_err(RafsError::LoadConfig)?;
         serde_json::from_reader::<File, RafsConfig>(file).map_err(RafsError::ParseConfig)
     }
+
+    /// Validate values of the configuration, reporting the invalid field and its expected values.
+    ///
+    /// Unknown fields are rejected when parsing the configuration, so it's the second step to
+    /// catch configuration errors before initializing storage backends.
+    pub fn validate(&self) -> RafsResult<()> {
+        if self.mode != "direct" && self.mode != "cached" {
+            return Err(invalid_field("mode", &self.mode, "direct or cached"));
+        }
+        self.device.validate().map_err(|e| {
+            RafsError::Configure(format!("invalid storage configuration in `device`, {}", e))
+        })?;
+
+        let prefetch = &self.fs_prefetch;
+        if prefetch.enable && !(1..=1024).contains(&prefetch.threads_count) {
+            return Err(invalid_field(
+                "fs_prefetch.threads_count",
+                prefetch.threads_count,
+                "1-1024",
+            ));
+        }
+        if prefetch.merging_size as u64 > RAFS_DEFAULT_CHUNK_SIZE {
+            return Err(invalid_field(
+                "fs_prefetch.merging_size",
+                prefetch.merging_size,
+                &format!("no more than {}", RAFS_DEFAULT_CHUNK_SIZE),
+            ));
+        }
+
+        if self.fs_scrub.enable {
+            if self.fs_scrub.interval == 0 {
+                return Err(invalid_field("fs_scrub.interval", 0, "a positive value"));
+            }
+            if self.fs_scrub.chunks == 0 {
+                return Err(invalid_field("fs_scrub.chunks", 0, "a positive value"));
+            }
+        }
+
+        let uncached = &self.uncached_read;
+        if uncached.enable && uncached.size_threshold == 0 && uncached.files.is_empty() {
+            return Err(RafsError::Configure(
+                "`uncached_read` is enabled without `size_threshold` or `files`".to_string(),
+            ));
+        }
+        if let Some(file) = uncached.files.iter().find(|f| !f.starts_with('/')) {
+            return Err(invalid_field("uncached_read.files", file, "absolute paths"));
+        }
+
+        if let Some(expected) = self.image_digest.expected.as_deref() {
+            let digest = expected.trim_start_matches("sha256:");
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid_field(
+                    "image_digest.expected",
+                    expected,
+                    "64 hex digits, optionally prefixed by `sha256:`",
+                ));
+            }
+        }
+        if let Some(path) = self.root_path.as_deref() {
+            if !path.starts_with('/') {
+                return Err(invalid_field("root_path", path, "an absolute path"));
+            }
+        }
+        if self.shared_bootstrap.enable && self.shared_bootstrap.dir.is_empty() {
+            return Err(invalid_field("shared_bootstrap.dir", "", "a directory"));
+        }
+
+        let fetch = &self.bootstrap_fetch;
+        if fetch.enable {
+            if fetch.object_id.is_empty() {
+                return Err(invalid_field(
+                    "bootstrap_fetch.object_id",
+                    "",
+                    "an object id",
+                ));
+            }
+            if fetch.cache_dir.is_empty() {
+                return Err(invalid_field(
+                    "bootstrap_fetch.cache_dir",
+                    "",
+                    "a directory",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_field(field: &str, value: impl fmt::Display, expected: &str) -> RafsError {
+    RafsError::Configure(format!(
+        "invalid value '{}' for `{}`, expected {}",
+        value, field, expected
+    ))
 }
 
 impl FromStr for RafsConfig {
@@ -198,6 +502,223 @@ impl fmt::Display for RafsConfig {
     }
 }
 
+/// State of the filesystem data prefetch task.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RafsPrefetchState {
+    /// The prefetch task hasn't been started.
+    Idle,
+    Running,
+    Paused,
+    /// The prefetch task has been stopped before finishing.
+    Stopped,
+    Finished,
+}
+
+/// Progress of the filesystem data prefetch task.
+#[derive(Clone, Debug, Serialize)]
+pub struct RafsPrefetchStatus {
+    pub state: RafsPrefetchState,
+    /// Number of files and directories to prefetch.
+    pub files_total: u64,
+    /// Number of files and directories which have been handled.
+    pub files_done: u64,
+    /// Size of file data to prefetch.
+    pub bytes_total: u64,
+    /// Size of file data which has been issued to the background prefetch workers.
+    pub bytes_fetched: u64,
+    /// Seconds spent on prefetching, excluding paused periods.
+    pub elapsed_secs: u64,
+    /// Estimated seconds to finish prefetching, available when the task is running.
+    pub eta_secs: Option<u64>,
+}
+
+/// Data prefetch policy and progress of a filesystem instance.
+#[derive(Clone, Serialize)]
+pub struct RafsPrefetchInfo {
+    #[serde(flatten)]
+    pub policy: FsPrefetchControl,
+    /// Number of files and directories specified to prefetch when mounting.
+    pub files: usize,
+    pub status: RafsPrefetchStatus,
+}
+
+/// Information and cache state of a data blob used by a filesystem instance.
+#[derive(Clone, Serialize)]
+pub struct RafsBlobInfo {
+    pub blob_id: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub chunk_count: u32,
+    pub readahead_size: u64,
+    /// Provenance recorded by the builder, absent for blobs generated by former builders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<RafsBlobProvenance>,
+    #[serde(flatten)]
+    pub cache: BlobCacheState,
+}
+
+/// Provenance information of a data blob, recorded by the builder.
+#[derive(Clone, Serialize)]
+pub struct RafsBlobProvenance {
+    /// Version of the builder, empty if unknown.
+    pub builder_version: String,
+    /// Seconds since UNIX epoch when the blob was built, 0 for repeatable builds.
+    pub build_time: u64,
+    /// Digest of the source content stored in the blob, in hex form.
+    pub source_digest: String,
+}
+
+impl From<&BlobProvenance> for RafsBlobProvenance {
+    fn from(provenance: &BlobProvenance) -> Self {
+        RafsBlobProvenance {
+            builder_version: provenance.builder_version.clone(),
+            build_time: provenance.build_time,
+            source_digest: provenance.source_digest.to_string(),
+        }
+    }
+}
+
+/// Information about a filesystem instance, for observability.
+#[derive(Clone, Serialize)]
+pub struct RafsInfo {
+    /// Super block metadata, flattened for compatibility with former versions.
+    #[serde(flatten)]
+    pub meta: RafsSuperMeta,
+    /// Rafs format version, "v5" or "v6".
+    pub rafs_version: String,
+    /// Type of the blob cache in use, which may be switched at runtime.
+    pub cache_type: String,
+    pub prefetch: RafsPrefetchInfo,
+    pub blobs: Vec<RafsBlobInfo>,
+}
+
+struct PrefetchState {
+    state: RafsPrefetchState,
+    elapsed: Duration,
+    resumed: Option<Instant>,
+}
+
+// Position of the cache scrubber, shared by the scrubber thread and the scrub API.
+//
+// Regular files are collected by walking the filesystem on the first round and cached, so each
+// round only resumes from where the last round stopped. The list is dropped when the bootstrap
+// is updated.
+#[derive(Default)]
+struct ScrubCursor {
+    files: Mutex<Option<Arc<Vec<Inode>>>>,
+    next: AtomicUsize,
+}
+
+impl ScrubCursor {
+    fn files(&self, sb: &RafsSuper) -> Result<Arc<Vec<Inode>>> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(files) = files.as_ref() {
+            return Ok(files.clone());
+        }
+
+        let mut inodes = Vec::new();
+        Rafs::collect_regular_files(sb, RAFS_ROOT_INODE, &mut inodes)?;
+        let list = Arc::new(
+            inodes
+                .iter()
+                .filter(|i| i.size() > 0)
+                .map(|i| i.ino())
+                .collect::<Vec<_>>(),
+        );
+        *files = Some(list.clone());
+
+        Ok(list)
+    }
+
+    fn reset(&self) {
+        *self.files.lock().unwrap() = None;
+        self.next.store(0, Ordering::Relaxed);
+    }
+}
+
+// Shared by the prefetch thread and the control interfaces of a filesystem instance.
+struct PrefetchControl {
+    id: u64,
+    state: Mutex<PrefetchState>,
+    cond: Condvar,
+    files_total: AtomicU64,
+    files_done: AtomicU64,
+    bytes_total: AtomicU64,
+    bytes_fetched: AtomicU64,
+}
+
+impl PrefetchControl {
+    fn new() -> Self {
+        PrefetchControl {
+            id: PREFETCH_JOB_ID.fetch_add(1, Ordering::Relaxed),
+            state: Mutex::new(PrefetchState {
+                state: RafsPrefetchState::Idle,
+                elapsed: Duration::from_secs(0),
+                resumed: None,
+            }),
+            cond: Condvar::new(),
+            files_total: AtomicU64::new(0),
+            files_done: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            bytes_fetched: AtomicU64::new(0),
+        }
+    }
+
+    // Switch to state `to` if the current state is one of `from`.
+    fn transit(&self, from: &[RafsPrefetchState], to: RafsPrefetchState) -> bool {
+        let mut guard = self.state.lock().unwrap();
+        if !from.contains(&guard.state) {
+            return false;
+        }
+        if let Some(resumed) = guard.resumed.take() {
+            guard.elapsed += resumed.elapsed();
+        }
+        if to == RafsPrefetchState::Running {
+            guard.resumed = Some(Instant::now());
+        }
+        guard.state = to;
+        self.cond.notify_all();
+
+        true
+    }
+
+    // Block while paused, and return whether the prefetch task should go on.
+    fn wait(&self) -> bool {
+        let mut guard = self.state.lock().unwrap();
+        while guard.state == RafsPrefetchState::Paused {
+            guard = self.cond.wait(guard).unwrap();
+        }
+        guard.state == RafsPrefetchState::Running
+    }
+
+    fn status(&self) -> RafsPrefetchStatus {
+        let guard = self.state.lock().unwrap();
+        let elapsed = match guard.resumed {
+            Some(resumed) => guard.elapsed + resumed.elapsed(),
+            None => guard.elapsed,
+        };
+        let bytes_total = self.bytes_total.load(Ordering::Relaxed);
+        let bytes_fetched = self.bytes_fetched.load(Ordering::Relaxed);
+        let eta_secs = if guard.state == RafsPrefetchState::Running && bytes_fetched > 0 {
+            let remaining = bytes_total.saturating_sub(bytes_fetched) as f64;
+            Some((remaining * elapsed.as_secs_f64() / bytes_fetched as f64) as u64)
+        } else {
+            None
+        };
+
+        RafsPrefetchStatus {
+            state: guard.state,
+            files_total: self.files_total.load(Ordering::Relaxed),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            bytes_total,
+            bytes_fetched,
+            elapsed_secs: elapsed.as_secs(),
+            eta_secs,
+        }
+    }
+}
+
 /// Struct to glue fuse, storage backend and filesystem metadata together.
 ///
 /// The [Rafs](struct.Rafs.html) structure implements the `fuse_backend_rs::FileSystem` trait,
@@ -208,14 +729,37 @@ pub struct Rafs {
     id: String,
     device: BlobDevice,
     ios: Arc<metrics::GlobalIoStats>,
+    accounting: Arc<metrics::AccessAccounting>,
     sb: Arc<RafsSuper>,
+    // Inode number of the directory presented as the filesystem root.
+    root_ino: Inode,
 
     initialized: bool,
     digest_validate: bool,
     fs_prefetch: bool,
     prefetch_all: bool,
+    prefetch_conf: FsPrefetchControl,
     xattr_enabled: bool,
     amplify_io: u32,
+    splice_read: bool,
+    metadata_prefetch: bool,
+    audit_writes: bool,
+    // Virtual file `.nydus/image.json`, regenerated when the bootstrap is updated.
+    image_info: Option<ArcSwap<ImageInfoFile>>,
+    fs_scrub: FsScrubControl,
+    scrub_cursor: Arc<ScrubCursor>,
+    scrub_stop: Arc<AtomicBool>,
+    uncached_read: FsUncachedReadControl,
+    // Files to read without caching, resolved from `uncached_read.files` when mounting.
+    uncached_inodes: HashSet<Inode>,
+    // Files and directories to prefetch when mounting.
+    prefetch_inodes: Vec<Inode>,
+    prefetch_ctl: Mutex<Arc<PrefetchControl>>,
+    // Recent prefetch tasks, including the current one.
+    prefetch_jobs: Mutex<VecDeque<Arc<PrefetchControl>>>,
+    prefetch_thread: Mutex<Option<JoinHandle<()>>>,
+    // Reference to the shared bootstrap mapped by the superblock.
+    shared_bootstrap: Option<SharedBootstrap>,
 
     // static inode attributes
     i_uid: u32,
@@ -226,26 +770,78 @@ pub struct Rafs {
 impl Rafs {
     /// Create a new instance of `Rafs`.
     pub fn new(conf: RafsConfig, id: &str, r: &mut RafsIoReader) -> RafsResult<Self> {
-        let storage_conf = Self::prepare_storage_conf(&conf)?;
+        conf.validate()?;
+        let storage_conf = Self::prepare_storage_conf(&conf, id)?;
         let mut sb = RafsSuper::new(&conf).map_err(RafsError::FillSuperblock)?;
-        sb.load(r).map_err(RafsError::FillSuperblock)?;
+        let verify_digest = conf.image_digest.verify || conf.image_digest.expected.is_some();
+        // Switch `r` to the bytes actually loaded, so the image digest is verified against them
+        // and `import()` reads the same bootstrap.
+        let shared_bootstrap = if conf.shared_bootstrap.enable && sb.mode == RafsMode::Direct {
+            let shared = SharedBootstrap::open(Path::new(&conf.shared_bootstrap.dir), r)
+                .map_err(RafsError::FillSuperblock)?;
+            *r = shared.reader().map_err(RafsError::FillSuperblock)?;
+            sb.load(r).map_err(RafsError::FillSuperblock)?;
+            info!("{} maps shared bootstrap {:?}", id, shared.path());
+            Some(shared)
+        } else {
+            if verify_digest {
+                *r = Self::seal_bootstrap(r).map_err(RafsError::FillSuperblock)?;
+            }
+            sb.load(r).map_err(RafsError::FillSuperblock)?;
+            None
+        };
+        if sb.meta.is_metadata_only() {
+            return Err(RafsError::Configure(
+                "image is built without data blobs and can't be mounted".to_string(),
+            ));
+        }
+        if verify_digest {
+            sb.verify_image_digest(r, conf.image_digest.expected.as_deref())
+                .map_err(RafsError::VerifyImageDigest)?;
+            info!("image digest of {} is verified", id);
+        }
+
+        let root_ino = match conf.root_path.as_deref() {
+            Some(path) => Self::resolve_root_path(&sb, path)?,
+            None => ROOT_ID,
+        };
 
         let blob_infos = sb.superblock.get_blob_infos();
+        if conf.blob_check.enable {
+            Self::check_blobs(&storage_conf, &blob_infos, conf.blob_check.verify_digest)?;
+        }
         let device =
             BlobDevice::new(&storage_conf, &blob_infos).map_err(RafsError::CreateDevice)?;
 
-        let rafs = Rafs {
+        let mut rafs = Rafs {
             id: id.to_string(),
             device,
             ios: metrics::new(id),
+            accounting: metrics::AccessAccounting::new(id),
             sb: Arc::new(sb),
+            root_ino,
 
             initialized: false,
             digest_validate: conf.digest_validate,
             fs_prefetch: conf.fs_prefetch.enable,
             amplify_io: conf.amplify_io,
+            splice_read: conf.splice_read,
+            metadata_prefetch: conf.metadata_prefetch,
+            audit_writes: conf.audit_writes,
+            image_info: None,
             prefetch_all: conf.fs_prefetch.prefetch_all,
+            prefetch_conf: conf.fs_prefetch.clone(),
             xattr_enabled: conf.enable_xattr,
+            fs_scrub: conf.fs_scrub.clone(),
+            scrub_cursor: Arc::new(ScrubCursor::default()),
+            scrub_stop: Arc::new(AtomicBool::new(false)),
+            uncached_read: conf.uncached_read.clone(),
+            uncached_inodes: HashSet::new(),
+            prefetch_inodes: Vec::new(),
+            prefetch_ctl: Mutex::new(Arc::new(PrefetchControl::new())),
+            prefetch_jobs: Mutex::new(VecDeque::new()),
+            prefetch_thread: Mutex::new(None),
+            shared_bootstrap,
 
             i_uid: geteuid().into(),
             i_gid: getegid().into(),
@@ -254,6 +850,9 @@ impl Rafs {
                 .unwrap()
                 .as_secs(),
         };
+        if conf.image_info {
+            rafs.image_info = rafs.new_image_info()?.map(|i| ArcSwap::new(Arc::new(i)));
+        }
 
         rafs.ios.toggle_files_recording(conf.iostats_files);
         rafs.ios.toggle_access_pattern(conf.access_pattern);
@@ -263,6 +862,69 @@ impl Rafs {
         Ok(rafs)
     }
 
+    /// Fetch the bootstrap from the storage backend into `path` as configured by
+    /// `bootstrap_fetch`, if it doesn't exist yet.
+    pub fn fetch_bootstrap(conf: &RafsConfig, path: &Path) -> RafsResult<()> {
+        let fetch = &conf.bootstrap_fetch;
+        if !fetch.enable || path.exists() {
+            return Ok(());
+        }
+        if fetch.object_id.is_empty() || fetch.cache_dir.is_empty() {
+            return Err(RafsError::Configure(
+                "try to fetch bootstrap without object id or cache directory".to_string(),
+            ));
+        }
+
+        let backend = BlobFactory::new_backend(conf.device.backend.clone(), &fetch.object_id)
+            .map_err(RafsError::FetchBootstrap)?;
+        let result = delta::fetch_bootstrap(
+            backend.as_ref(),
+            &fetch.object_id,
+            fetch.delta_object_id.as_deref(),
+            Path::new(&fetch.cache_dir),
+            path,
+        );
+        backend.shutdown();
+        let method = result.map_err(RafsError::FetchBootstrap)?;
+        info!(
+            "bootstrap {:?} is fetched from object {} by {:?}",
+            path, fetch.object_id, method
+        );
+
+        Ok(())
+    }
+
+    fn check_blobs(
+        storage_conf: &Arc<FactoryConfig>,
+        blob_infos: &[Arc<BlobInfo>],
+        verify_digest: bool,
+    ) -> RafsResult<()> {
+        let failures = BlobDevice::check_blobs(storage_conf, blob_infos, verify_digest)
+            .map_err(|e| RafsError::CheckBlobs(format!("failed to check blobs, {}", e)))?;
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        let (missing, mismatched): (Vec<_>, Vec<_>) = failures.into_iter().partition(|f| f.missing);
+        for f in missing.iter().chain(mismatched.iter()) {
+            error!("blob {} doesn't match bootstrap, {}", f.blob_id, f.reason);
+        }
+        let mut msg = String::from("blobs don't match bootstrap");
+        if !missing.is_empty() {
+            let ids: Vec<&str> = missing.iter().map(|f| f.blob_id.as_str()).collect();
+            msg.push_str(&format!(", missing: [{}]", ids.join(", ")));
+        }
+        if !mismatched.is_empty() {
+            let ids: Vec<String> = mismatched
+                .iter()
+                .map(|f| format!("{} ({})", f.blob_id, f.reason))
+                .collect();
+            msg.push_str(&format!(", mismatched: [{}]", ids.join(", ")));
+        }
+
+        Err(RafsError::CheckBlobs(msg))
+    }
+
     /// Update storage backend for blobs.
     pub fn update(&self, r: &mut RafsIoReader, conf: RafsConfig) -> RafsResult<()> {
         info!("update");
@@ -279,8 +941,17 @@ impl Rafs {
             e
         })?;
         info!("update sb is successful");
+        if conf.metadata_prefetch {
+            self.sb.superblock.prefetch_metadata();
+        }
+        self.scrub_cursor.reset();
+        if let Some(image_info) = self.image_info.as_ref() {
+            if let Some(info) = self.new_image_info()? {
+                image_info.store(Arc::new(info));
+            }
+        }
 
-        let storage_conf = Self::prepare_storage_conf(&conf)?;
+        let storage_conf = Self::prepare_storage_conf(&conf, &self.id)?;
         let blob_infos = self.sb.superblock.get_blob_infos();
 
         // step 2: update device (only localfs is supported)
@@ -292,28 +963,148 @@ impl Rafs {
         Ok(())
     }
 
+    /// Switch the storage backend object to fetch data of blob `blob_id` from, e.g. when the blob
+    /// has been relocated or mirrored.
+    pub fn switch_blob_backend(
+        &self,
+        blob_id: &str,
+        backend_config: Option<BackendConfig>,
+        object_id: Option<&str>,
+        flush_cache: bool,
+    ) -> RafsResult<()> {
+        if !self.initialized {
+            return Err(RafsError::Uninitialized);
+        }
+
+        self.device
+            .switch_blob_backend(blob_id, backend_config, object_id, flush_cache)
+            .map_err(RafsError::SwapBackend)
+    }
+
+    /// Switch the blob cache type, one of `blobcache`, `dummy` and `direct`, e.g. to stop caching
+    /// data temporarily when the disk is full.
+    pub fn switch_cache_type(&self, cache_type: &str) -> RafsResult<()> {
+        if !self.initialized {
+            return Err(RafsError::Uninitialized);
+        }
+
+        let blob_infos = self.sb.superblock.get_blob_infos();
+        self.device
+            .switch_cache_type(cache_type, &blob_infos)
+            .map_err(RafsError::SwapBackend)
+    }
+
+    /// Get extents of local cache files holding data of file `ino` in range [offset, offset + size).
+    ///
+    /// It's used to reply fuse read requests by splice(2) instead of copying data through user
+    /// space buffers. Returns `None` if it's disabled, or data of the range is not ready in cache
+    /// files in uncompressed form, and the request should be served by `read()` instead.
+    pub fn get_cached_extents(
+        &self,
+        ino: Inode,
+        offset: u64,
+        size: u32,
+    ) -> Result<Option<Vec<BlobCachedExtent>>> {
+        if !self.splice_read || !self.initialized {
+            return Ok(None);
+        }
+        if offset.checked_add(size as u64).is_none() {
+            return Err(einval!("offset + size wraps around."));
+        }
+
+        let inode = self.sb.get_inode(ino, false)?;
+        let inode_size = inode.size();
+        if !inode.is_reg() || size == 0 || offset >= inode_size {
+            return Ok(None);
+        }
+
+        let real_size = cmp::min(size as u64, inode_size - offset);
+        let descs = inode.alloc_bio_vecs(offset, real_size as usize, true)?;
+        // Zeros of holes can't be spliced from cache files.
+        if descs.iter().map(|d| d.bi_size as u64).sum::<u64>() < real_size {
+            return Ok(None);
+        }
+
+        let extents = self.device.get_cached_extents(&descs);
+        if extents.is_some() {
+            let mut recorder = FopRecorder::settle(Read, ino, &self.ios);
+            recorder.mark_success(real_size as usize);
+            self.accounting.account_request(real_size as usize);
+        }
+
+        Ok(extents)
+    }
+
     /// Import an rafs bootstrap to initialize the filesystem instance.
     pub fn import(
         &mut self,
-        r: RafsIoReader,
+        mut r: RafsIoReader,
         prefetch_files: Option<Vec<PathBuf>>,
     ) -> RafsResult<()> {
         if self.initialized {
             return Err(RafsError::AlreadyMounted);
         }
-        if self.fs_prefetch {
+        if self.metadata_prefetch {
+            self.sb.superblock.prefetch_metadata();
+        }
+        if self.fs_prefetch && self.sb.meta.is_v5() {
+            self.prefetch_inodes = self.get_prefetch_inodes(&mut r, prefetch_files);
+            let prefetches = self.get_blob_prefetches(&mut r);
             // Device should be ready before any prefetch.
-            self.prefetch(r, prefetch_files)
+            let handle = self.start_prefetch(self.prefetch_inodes.clone(), prefetches);
+            *self.prefetch_thread.lock().unwrap() = handle;
+        }
+        if self.fs_scrub.enable {
+            self.start_scrubber();
+        }
+        if self.uncached_read.enable {
+            self.uncached_inodes = self.get_uncached_inodes();
         }
         self.initialized = true;
 
         Ok(())
     }
 
+    // Copy the bootstrap into a sealed memfd, so it can't be changed by others between verifying
+    // the image digest and accessing the metadata.
+    fn seal_bootstrap(r: &mut RafsIoReader) -> Result<RafsIoReader> {
+        let name = CStr::from_bytes_with_nul(b"rafs-bootstrap\0").unwrap();
+        // Safe because the name is a valid C string.
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd < 0 {
+            return Err(last_error!("failed to create memfd for bootstrap"));
+        }
+        // Safe because we own the newly created fd.
+        let mut file = unsafe { File::from_raw_fd(fd) };
+
+        r.seek_to_offset(0)?;
+        std::io::copy(r, &mut file)?;
+        let seals =
+            libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        // Safe because the fd is valid.
+        if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(last_error!("failed to seal memfd for bootstrap"));
+        }
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(Box::new(file))
+    }
+
     /// Umount a mounted Rafs Fuse filesystem.
     pub fn destroy(&mut self) -> Result<()> {
         info! {"Destroy rafs"}
 
+        self.scrub_stop.store(true, Ordering::Release);
+        self.prefetch_ctl.lock().unwrap().transit(
+            &[RafsPrefetchState::Running, RafsPrefetchState::Paused],
+            RafsPrefetchState::Stopped,
+        );
+        // The prefetch thread holds a reference to the superblock.
+        if let Some(handle) = self.prefetch_thread.get_mut().unwrap().take() {
+            let _ = handle.join();
+        }
         if self.initialized {
             Arc::get_mut(&mut self.sb)
                 .expect("Superblock is no longer used")
@@ -321,6 +1112,8 @@ impl Rafs {
             self.device.close()?;
             self.initialized = false;
         }
+        // Release the shared bootstrap after it's unmapped.
+        self.shared_bootstrap.take();
 
         Ok(())
     }
@@ -335,13 +1128,184 @@ impl Rafs {
         &self.sb.meta
     }
 
-    fn prepare_storage_conf(conf: &RafsConfig) -> RafsResult<Arc<FactoryConfig>> {
+    /// Convert an absolute path in the image to an inode number of the filesystem.
+    pub fn ino_from_path(&self, path: &Path) -> Result<u64> {
+        self.sb.ino_from_path(path).map(|ino| self.fuse_ino(ino))
+    }
+
+    /// Get attributes of an inode as `getattr()` does, together with its birth time in seconds
+    /// since epoch, 0 if unknown.
+    ///
+    /// The FUSE library in use doesn't support `statx`, so birth time can't be served to FUSE
+    /// clients and is exposed by this method instead.
+    pub fn statx(&self, ino: u64) -> Result<(libc::stat64, u64)> {
+        let ino = self.rafs_ino(ino);
+        let attr = self.get_inode_attr(ino)?;
+        // The synthesized image info files have no birth time.
+        let btime = if self.is_image_info(ino) {
+            0
+        } else {
+            self.sb.get_inode(ino, false)?.get_btime()
+        };
+
+        Ok((attr.into(), btime))
+    }
+
+    fn prepare_storage_conf(conf: &RafsConfig, id: &str) -> RafsResult<Arc<FactoryConfig>> {
         let mut storage_conf = conf.device.clone();
+        if storage_conf.id.is_empty() {
+            storage_conf.id = id.to_string();
+        }
         storage_conf.cache.cache_validate = conf.digest_validate;
         storage_conf.cache.prefetch_config = TryFrom::try_from(conf)?;
         Ok(Arc::new(storage_conf))
     }
 
+    fn resolve_root_path(sb: &RafsSuper, path: &str) -> RafsResult<Inode> {
+        let ino = sb.ino_from_path(Path::new(path)).map_err(|e| {
+            RafsError::Configure(format!("failed to find root path {}: {}", path, e))
+        })?;
+        let inode = sb.get_inode(ino, false).map_err(|e| {
+            RafsError::Configure(format!("failed to load root path {}: {}", path, e))
+        })?;
+        if !inode.is_dir() {
+            return Err(RafsError::Configure(format!(
+                "root path {} is not a directory",
+                path
+            )));
+        }
+
+        Ok(ino)
+    }
+
+    // Map an inode number in fuse requests to the rafs inode number, the fuse root inode is the
+    // directory at `root_path` of the image.
+    #[inline]
+    fn rafs_ino(&self, ino: u64) -> Inode {
+        if ino == ROOT_ID {
+            self.root_ino
+        } else {
+            ino
+        }
+    }
+
+    // Count a write-class operation on `ino`, or on entry `name` under directory `ino`, and
+    // reject it because the filesystem is read-only.
+    fn reject_write(&self, ctx: &Context, fop: WriteFop, ino: u64, name: Option<&CStr>) -> Error {
+        self.ios.write_rejected(fop);
+        if self.audit_writes {
+            let mut path = self
+                .sb
+                .path_from_ino(self.rafs_ino(ino))
+                .unwrap_or_default();
+            if let Some(name) = name {
+                path.push(OsStr::from_bytes(name.to_bytes()));
+            }
+            let record = serde_json::json!({
+                "id": self.id,
+                "fop": fop.name(),
+                "path": path.to_string_lossy(),
+                "pid": ctx.pid,
+            });
+            warn!("rejected write to read-only filesystem: {}", record);
+        }
+
+        Error::from_raw_os_error(libc::EROFS)
+    }
+
+    // Generate the virtual file `.nydus/image.json`, unless the image has an entry of the same
+    // name in the root directory.
+    fn new_image_info(&self) -> RafsResult<Option<ImageInfoFile>> {
+        if let Ok(root) = self.sb.get_inode(self.root_ino, false) {
+            if root.get_child_by_name(OsStr::new(IMAGE_INFO_DIR)).is_ok() {
+                warn!(
+                    "{} has {} in the root directory, image information is hidden",
+                    self.id, IMAGE_INFO_DIR
+                );
+                return Ok(None);
+            }
+        }
+        if self.sb.get_max_ino() >= IMAGE_INFO_FILE_INO {
+            warn!(
+                "{} has inode numbers conflicting with {}, image information is hidden",
+                self.id, IMAGE_INFO_DIR
+            );
+            return Ok(None);
+        }
+
+        ImageInfoFile::new(&self.sb, self.i_uid, self.i_gid, self.i_time)
+            .map(Some)
+            .map_err(|e| RafsError::Configure(format!("failed to generate image info, {}", e)))
+    }
+
+    #[inline]
+    fn image_info(&self) -> Option<Arc<ImageInfoFile>> {
+        self.image_info.as_ref().map(|i| i.load_full())
+    }
+
+    #[inline]
+    fn is_image_info(&self, ino: Inode) -> bool {
+        self.image_info.is_some() && (ino == IMAGE_INFO_DIR_INO || ino == IMAGE_INFO_FILE_INO)
+    }
+
+    // Look up the virtual directory in the root directory, and entries in the virtual directory.
+    fn lookup_image_info(&self, parent: Inode, name: &OsStr) -> Option<Result<Entry>> {
+        let info = self.image_info()?;
+        if parent == self.root_ino && name == IMAGE_INFO_DIR {
+            info.entry(IMAGE_INFO_DIR_INO).map(Ok)
+        } else if parent == IMAGE_INFO_DIR_INO {
+            let entry = if name == DOT {
+                info.entry(IMAGE_INFO_DIR_INO)
+            } else if name == DOTDOT {
+                let root = self.sb.get_inode(self.root_ino, self.digest_validate);
+                return Some(root.map(|i| self.get_inode_entry(i)));
+            } else if name == IMAGE_INFO_FILE {
+                info.entry(IMAGE_INFO_FILE_INO)
+            } else {
+                None
+            };
+            Some(Ok(entry.unwrap_or_else(|| self.negative_entry())))
+        } else if parent == IMAGE_INFO_FILE_INO {
+            Some(Err(enotdir!()))
+        } else {
+            None
+        }
+    }
+
+    fn readdir_image_info<F>(&self, offset: u64, mut add_entry: F) -> Result<()>
+    where
+        F: FnMut(DirEntry) -> Result<usize>,
+    {
+        let entries = [
+            (IMAGE_INFO_DIR_INO, DOT),
+            (ROOT_ID, DOTDOT),
+            (IMAGE_INFO_FILE_INO, IMAGE_INFO_FILE),
+        ];
+        for (idx, (ino, name)) in entries.iter().enumerate().skip(offset as usize) {
+            let entry = DirEntry {
+                ino: *ino,
+                offset: idx as u64 + 1,
+                type_: 0,
+                name: name.as_bytes(),
+            };
+            if add_entry(entry)? == 0 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Map a rafs inode number to the inode number in fuse replies.
+    #[inline]
+    fn fuse_ino(&self, ino: Inode) -> u64 {
+        if ino == self.root_ino {
+            ROOT_ID
+        } else {
+            ino
+        }
+    }
+
     fn xattr_supported(&self) -> bool {
         self.xattr_enabled || self.sb.meta.has_xattr()
     }
@@ -353,6 +1317,9 @@ impl Rafs {
         if size == 0 {
             return Ok(());
         }
+        if ino == IMAGE_INFO_DIR_INO && self.is_image_info(ino) {
+            return self.readdir_image_info(offset, add_entry);
+        }
 
         let parent = self.sb.get_inode(ino, self.digest_validate)?;
         if !parent.is_dir() {
@@ -365,17 +1332,17 @@ impl Rafs {
         if cur_offset == 0 {
             cur_offset += 1;
             add_entry(DirEntry {
-                ino,
+                ino: self.fuse_ino(ino),
                 offset: cur_offset,
                 type_: 0,
                 name: DOT.as_bytes(),
             })?;
         }
         if cur_offset == 1 {
-            let parent = if ino == ROOT_ID {
+            let parent = if ino == self.root_ino {
                 ROOT_ID
             } else {
-                parent.parent()
+                self.fuse_ino(parent.parent())
             };
             cur_offset += 1;
             add_entry(DirEntry {
@@ -412,6 +1379,19 @@ impl Rafs {
             }
         }
 
+        // The virtual directory follows all children of the root directory.
+        if ino == self.root_ino
+            && idx == parent.get_child_count() as u64
+            && self.image_info.is_some()
+        {
+            add_entry(DirEntry {
+                ino: IMAGE_INFO_DIR_INO,
+                offset: cur_offset + 1,
+                type_: 0,
+                name: IMAGE_INFO_DIR.as_bytes(),
+            })?;
+        }
+
         Ok(())
     }
 
@@ -430,8 +1410,12 @@ impl Rafs {
     }
 
     fn get_inode_attr(&self, ino: u64) -> Result<Attr> {
+        if let Some(attr) = self.image_info().and_then(|i| i.attr(ino)) {
+            return Ok(attr);
+        }
         let inode = self.sb.get_inode(ino, false)?;
         let mut attr = inode.get_attr();
+        attr.ino = self.fuse_ino(attr.ino);
 
         // override uid/gid if there is no explicit inode uid/gid
         if !self.sb.meta.explicit_uidgid() {
@@ -460,6 +1444,8 @@ impl Rafs {
 
     fn get_inode_entry(&self, inode: Arc<dyn RafsInode>) -> Entry {
         let mut entry = inode.get_entry();
+        entry.inode = self.fuse_ino(entry.inode);
+        entry.attr.st_ino = entry.inode;
 
         // override uid/gid if there is no explicit inode uid/gid
         if !self.sb.meta.explicit_uidgid() {
@@ -484,19 +1470,580 @@ impl Rafs {
 
         entry
     }
+
+    // Get file offset of the first byte covered by the blob io vector.
+    fn bio_file_offset(desc: &BlobIoVec) -> Result<u64> {
+        let bio = &desc.bi_vec[0];
+        let chunk = bio.chunkinfo.as_v5()?;
+
+        Ok(chunk.file_offset() + bio.offset as u64)
+    }
+
+    // Read data described by `descs` into `w` with `read`, for the file range
+    // `[offset, offset + size)`.
+    //
+    // Holes before, between and after the io vectors are filled with zeros if `has_hole`.
+    // Reading stops at the first short read, so the rest of the range isn't filled with zeros
+    // as if it were a hole.
+    fn read_descs<F>(
+        w: &mut dyn ZeroCopyWriter,
+        descs: &mut [BlobIoVec],
+        offset: u64,
+        size: u64,
+        has_hole: bool,
+        mut read: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&mut dyn ZeroCopyWriter, &mut BlobIoVec) -> Result<usize>,
+    {
+        let mut result = 0;
+        let mut pos = offset;
+        let mut short_read = false;
+
+        for desc in descs.iter_mut() {
+            debug_assert!(desc.validate());
+            debug_assert!(!desc.bi_vec.is_empty());
+            debug_assert!(desc.bi_size != 0);
+
+            if has_hole {
+                let desc_offset = Self::bio_file_offset(desc)?;
+                if desc_offset > pos {
+                    let r = Self::fill_hole(w, (desc_offset - pos) as usize)?;
+                    result += r;
+                    pos += r as u64;
+                }
+            }
+
+            let r = read(w, desc)?;
+            result += r;
+            pos += r as u64;
+            if r != desc.bi_size {
+                short_read = true;
+                break;
+            }
+        }
+        if has_hole && !short_read && pos < offset + size {
+            result += Self::fill_hole(w, (offset + size - pos) as usize)?;
+        }
+
+        Ok(result)
+    }
+
+    // Fill a hole of sparse file with zero, without touching the storage backend.
+    fn fill_hole(w: &mut dyn ZeroCopyWriter, size: usize) -> Result<usize> {
+        let zeros = [0u8; 4096];
+        let mut remain = size;
+
+        while remain > 0 {
+            let cnt = cmp::min(remain, zeros.len());
+            w.write_all(&zeros[..cnt])?;
+            remain -= cnt;
+        }
+
+        Ok(size)
+    }
+
+    // Get data extents of a regular file within `[start, end)` as sorted `(start, end)` pairs,
+    // excluding holes of sparse files and hole chunks containing all zeros.
+    fn data_extents(inode: &dyn RafsInode, start: u64, end: u64) -> Result<Vec<(u64, u64)>> {
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        if start >= end {
+            return Ok(extents);
+        }
+
+        let mut pos = start;
+        for desc in inode.alloc_bio_vecs(start, (end - start) as usize, false)? {
+            for bio in desc.bi_vec.iter() {
+                // Chunks of Rafs v5 record their file offsets, chunks of Rafs v6 are contiguous.
+                if let Ok(chunk) = bio.chunkinfo.as_v5() {
+                    pos = chunk.file_offset() + bio.offset as u64;
+                }
+                let end = pos + bio.size as u64;
+                if !bio.chunkinfo.is_hole() {
+                    match extents.last_mut() {
+                        Some(last) if last.1 == pos => last.1 = end,
+                        _ => extents.push((pos, end)),
+                    }
+                }
+                pos = end;
+            }
+        }
+
+        Ok(extents)
+    }
+
+    // Handle `SEEK_DATA` and `SEEK_HOLE` by walking chunks from `offset` one at a time, stopping
+    // at the first chunk containing the requested data or hole.
+    fn seek_extent(&self, inode: &dyn RafsInode, offset: u64, whence: i32) -> Result<u64> {
+        let size = inode.size();
+        let chunk_size = cmp::max(self.metadata().chunk_size as u64, 1);
+        let mut pos = offset;
+
+        while pos < size {
+            let end = cmp::min(size, (pos / chunk_size + 1) * chunk_size);
+            let extents = Self::data_extents(inode, pos, end)?;
+            if whence == libc::SEEK_DATA {
+                if let Some(data) = seek_data(&extents, pos) {
+                    return Ok(data);
+                }
+            } else {
+                let hole = seek_hole(&extents, pos, end);
+                if hole < end {
+                    return Ok(hole);
+                }
+            }
+            pos = end;
+        }
+
+        if whence == libc::SEEK_DATA {
+            Err(std::io::Error::from_raw_os_error(libc::ENXIO))
+        } else {
+            Ok(size)
+        }
+    }
+}
+
+// Find the first data offset at or after `offset`.
+fn seek_data(extents: &[(u64, u64)], offset: u64) -> Option<u64> {
+    extents
+        .iter()
+        .find(|(_, end)| *end > offset)
+        .map(|(start, _)| cmp::max(*start, offset))
+}
+
+// Find the first hole offset at or after `offset`, the end of file is treated as a hole.
+fn seek_hole(extents: &[(u64, u64)], offset: u64, size: u64) -> u64 {
+    let mut pos = offset;
+    for (start, end) in extents.iter() {
+        if *start > pos {
+            break;
+        } else if *end > pos {
+            pos = *end;
+        }
+    }
+
+    cmp::min(pos, size)
 }
 
 impl Rafs {
-    fn prefetch(&self, reader: RafsIoReader, prefetch_files: Option<Vec<PathBuf>>) {
+    /// Get progress of the filesystem data prefetch task.
+    pub fn prefetch_status(&self) -> RafsPrefetchStatus {
+        self.prefetch_ctl.lock().unwrap().status()
+    }
+
+    /// Get information about the filesystem instance, including blobs and their cache state.
+    pub fn info(&self) -> RafsInfo {
+        let meta = self.sb.meta;
+        let cache_states = self.device.cache_states();
+        let blobs = self
+            .sb
+            .superblock
+            .get_blob_infos()
+            .iter()
+            .enumerate()
+            .map(|(idx, blob)| RafsBlobInfo {
+                blob_id: blob.blob_id().to_string(),
+                compressed_size: blob.compressed_size(),
+                uncompressed_size: blob.uncompressed_size(),
+                chunk_count: blob.chunk_count(),
+                readahead_size: blob.readahead_size(),
+                provenance: blob.provenance().map(RafsBlobProvenance::from),
+                cache: cache_states.get(idx).cloned().unwrap_or_default(),
+            })
+            .collect();
+
+        RafsInfo {
+            meta,
+            rafs_version: if meta.is_v6() { "v6" } else { "v5" }.to_string(),
+            cache_type: self.device.cache_type(),
+            prefetch: RafsPrefetchInfo {
+                policy: self.prefetch_conf.clone(),
+                files: self.prefetch_inodes.len(),
+                status: self.prefetch_status(),
+            },
+            blobs,
+        }
+    }
+
+    /// Pause the filesystem data prefetch task.
+    ///
+    /// Requests which have been issued to the background prefetch workers are not paused.
+    pub fn pause_prefetch(&self) -> RafsResult<()> {
+        self.control_prefetch(&[RafsPrefetchState::Running], RafsPrefetchState::Paused)
+    }
+
+    /// Resume the paused filesystem data prefetch task.
+    pub fn resume_prefetch(&self) -> RafsResult<()> {
+        self.control_prefetch(&[RafsPrefetchState::Paused], RafsPrefetchState::Running)
+    }
+
+    /// Stop the filesystem data prefetch task.
+    pub fn stop_prefetch(&self) -> RafsResult<()> {
+        self.control_prefetch(
+            &[RafsPrefetchState::Running, RafsPrefetchState::Paused],
+            RafsPrefetchState::Stopped,
+        )
+    }
+
+    /// Stop the current filesystem data prefetch task and start a new one to prefetch `files`.
+    ///
+    /// Files to prefetch when mounting are used if `files` is None, and all files under a
+    /// directory are prefetched if a directory is specified.
+    pub fn restart_prefetch(&self, files: Option<Vec<PathBuf>>) -> RafsResult<()> {
+        let inodes = match files {
+            Some(files) => self.resolve_prefetch_files(&files)?,
+            None => self.prefetch_inodes.clone(),
+        };
+
+        self.restart_prefetch_inodes(inodes).map(|_| ())
+    }
+
+    /// Warm up the blob cache by restarting the prefetch task for `files`, or for the whole
+    /// image if `files` is None, and return the job id of the new prefetch task.
+    ///
+    /// Progress of the job may be polled by `prefetch_job_status()`.
+    pub fn warm_up(&self, files: Option<Vec<PathBuf>>) -> RafsResult<u64> {
+        let inodes = match files {
+            Some(files) => self.resolve_prefetch_files(&files)?,
+            None => vec![RAFS_ROOT_INODE],
+        };
+
+        self.restart_prefetch_inodes(inodes)
+    }
+
+    /// Get progress of a recent prefetch task by its job id.
+    ///
+    /// A job superseded by a later one is reported as stopped, and None is returned if the job
+    /// doesn't belong to the filesystem instance or is too old.
+    pub fn prefetch_job_status(&self, id: u64) -> Option<RafsPrefetchStatus> {
+        self.prefetch_jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|job| job.id == id)
+            .map(|job| job.status())
+    }
+
+    fn resolve_prefetch_files(&self, files: &[PathBuf]) -> RafsResult<Vec<Inode>> {
+        let mut inodes = Vec::with_capacity(files.len());
+        for f in files.iter() {
+            let ino = self.sb.ino_from_path(f).map_err(|e| {
+                RafsError::Prefetch(format!("failed to find {}, {}", f.display(), e))
+            })?;
+            inodes.push(ino);
+        }
+
+        Ok(inodes)
+    }
+
+    fn restart_prefetch_inodes(&self, inodes: Vec<Inode>) -> RafsResult<u64> {
+        if !self.initialized {
+            return Err(RafsError::Uninitialized);
+        }
+        if !self.fs_prefetch {
+            return Err(RafsError::Prefetch(
+                "filesystem prefetch is disabled".to_string(),
+            ));
+        }
+
+        // Hold the lock to serialize concurrent restarts.
+        let mut thread = self.prefetch_thread.lock().unwrap();
+        self.prefetch_ctl.lock().unwrap().transit(
+            &[RafsPrefetchState::Running, RafsPrefetchState::Paused],
+            RafsPrefetchState::Stopped,
+        );
+        if let Some(handle) = thread.take() {
+            let _ = handle.join();
+        }
+        // The background prefetch workers are stopped when the last prefetch task exits.
+        self.device
+            .restart_prefetch()
+            .map_err(|e| RafsError::Prefetch(e.to_string()))?;
+        *thread = self.start_prefetch(inodes, Vec::new());
+        if thread.is_none() {
+            return Err(RafsError::Prefetch(
+                "failed to start prefetch thread".to_string(),
+            ));
+        }
+
+        Ok(self.prefetch_ctl.lock().unwrap().id)
+    }
+
+    fn control_prefetch(
+        &self,
+        from: &[RafsPrefetchState],
+        to: RafsPrefetchState,
+    ) -> RafsResult<()> {
+        if !self.initialized {
+            return Err(RafsError::Uninitialized);
+        }
+        let ctl = self.prefetch_ctl.lock().unwrap();
+        if ctl.transit(from, to) {
+            Ok(())
+        } else {
+            Err(RafsError::Prefetch(format!(
+                "can't switch prefetch task from {:?} to {:?}",
+                ctl.status().state,
+                to
+            )))
+        }
+    }
+
+    fn get_uncached_inodes(&self) -> HashSet<Inode> {
+        let mut inodes = HashSet::new();
+        for f in self.uncached_read.files.iter() {
+            match self.sb.ino_from_path(Path::new(f)) {
+                Ok(ino) => {
+                    inodes.insert(ino);
+                }
+                Err(e) => warn!("failed to get inode for uncached file {}, {}", f, e),
+            }
+        }
+
+        inodes
+    }
+
+    // Check whether data of the file should be read from the storage backend without caching.
+    fn is_uncached_read(&self, inode: &Arc<dyn RafsInode>) -> bool {
+        self.uncached_read.enable
+            && ((self.uncached_read.size_threshold > 0
+                && inode.size() >= self.uncached_read.size_threshold)
+                || self.uncached_inodes.contains(&inode.ino()))
+    }
+
+    fn get_prefetch_inodes(
+        &self,
+        r: &mut RafsIoReader,
+        prefetch_files: Option<Vec<PathBuf>>,
+    ) -> Vec<Inode> {
+        let mut inodes = match prefetch_files {
+            Some(files) => Self::convert_file_list(&files, &self.sb),
+            None => self.sb.get_prefetch_inodes(r).unwrap_or_else(|e| {
+                info!("No file to be prefetched {:?}", e);
+                Vec::new()
+            }),
+        };
+        if self.prefetch_all {
+            inodes.push(RAFS_ROOT_INODE);
+        }
+
+        inodes
+    }
+
+    // Get ranges of blobs to prefetch from the blob prefetch table, or fall back to the readahead
+    // range of each blob if the table is absent.
+    fn get_blob_prefetches(&self, r: &mut RafsIoReader) -> Vec<BlobPrefetchRequest> {
+        let blobs = self.sb.superblock.get_blobs();
+        let ranges = self.sb.get_blob_prefetch_ranges(r).unwrap_or_else(|e| {
+            warn!("failed to load blob prefetch table, {}", e);
+            Vec::new()
+        });
+
+        if ranges.is_empty() {
+            // Without too much layout concern, just prefetch a certain range from backend.
+            return blobs
+                .iter()
+                .map(|b| BlobPrefetchRequest {
+                    blob_id: b.blob_id().to_owned(),
+                    offset: b.readahead_offset() as u32,
+                    len: b.readahead_size() as u32,
+                })
+                .collect();
+        }
+
+        ranges
+            .into_iter()
+            .filter_map(|(blob_index, offset, size)| {
+                let blob = blobs.get(blob_index as usize)?;
+                if offset > u32::MAX as u64 {
+                    warn!("blob prefetch range at {:#x} is too far, skip it", offset);
+                    return None;
+                }
+                Some(BlobPrefetchRequest {
+                    blob_id: blob.blob_id().to_owned(),
+                    offset: offset as u32,
+                    len: size,
+                })
+            })
+            .collect()
+    }
+
+    fn start_prefetch(
+        &self,
+        inodes: Vec<Inode>,
+        prefetches: Vec<BlobPrefetchRequest>,
+    ) -> Option<JoinHandle<()>> {
         let sb = self.sb.clone();
         let device = self.device.clone();
-        let prefetch_all = self.prefetch_all;
+        let ctl = Arc::new(PrefetchControl::new());
+        let ctl2 = ctl.clone();
+
+        ctl.transit(&[RafsPrefetchState::Idle], RafsPrefetchState::Running);
+        *self.prefetch_ctl.lock().unwrap() = ctl.clone();
+        let mut jobs = self.prefetch_jobs.lock().unwrap();
+        if jobs.len() >= PREFETCH_JOB_HISTORY {
+            jobs.pop_front();
+        }
+        jobs.push_back(ctl.clone());
+        drop(jobs);
+        thread::Builder::new()
+            .name("rafs_prefetch".to_string())
+            .spawn(move || Self::do_prefetch(ctl2, sb, device, inodes, prefetches))
+            .map_err(|e| {
+                warn!("failed to start prefetch thread, {}", e);
+                ctl.transit(&[RafsPrefetchState::Running], RafsPrefetchState::Stopped);
+            })
+            .ok()
+    }
 
-        let _ = std::thread::spawn(move || {
-            if sb.meta.is_v5() {
-                Self::do_prefetch_v5(reader, prefetch_files, prefetch_all, sb, device);
+    /// Verify up to `chunks` cached chunks and invalidate corrupted ones.
+    ///
+    /// Scrubbing resumes from the file where the last round stopped, so successive rounds walk
+    /// through the whole filesystem. The configured per-round value is used if `chunks` is None.
+    pub fn scrub(&self, chunks: Option<u32>) -> Result<BlobScrubStat> {
+        let chunks = chunks.unwrap_or(self.fs_scrub.chunks);
+        Self::do_scrub(&self.sb, &self.device, &self.scrub_cursor, chunks)
+    }
+
+    /// Discard cached data of regular files at or under image path `path`, or of all files if
+    /// it's None, so the data will be fetched from the storage backend again. Only chunks of blob
+    /// `blob_id` are invalidated if specified. Returns the number of chunks invalidated.
+    pub fn invalidate_cache(&self, blob_id: Option<&str>, path: Option<&Path>) -> Result<u64> {
+        let blob_index = match blob_id {
+            Some(id) => {
+                let index = self
+                    .sb
+                    .superblock
+                    .get_blob_infos()
+                    .iter()
+                    .position(|b| b.blob_id() == id)
+                    .ok_or_else(|| enoent!(format!("blob {} not found", id)))?;
+                Some(index as u32)
             }
-        });
+            None => None,
+        };
+        let ino = match path {
+            Some(p) => self.sb.ino_from_path(p)?,
+            None => RAFS_ROOT_INODE,
+        };
+        let inode = self.sb.get_inode(ino, false)?;
+        let mut files = Vec::new();
+        if inode.is_dir() {
+            Self::collect_regular_files(&self.sb, ino, &mut files)?;
+        } else if inode.is_reg() {
+            files.push(inode);
+        }
+
+        let mut count = 0;
+        for inode in files.iter().filter(|i| i.size() > 0) {
+            let mut descs = inode.alloc_bio_vecs(0, inode.size() as usize, false)?;
+            if let Some(index) = blob_index {
+                descs.retain(|d| d.get_target_blob_index() == Some(index));
+            }
+            count += self.device.invalidate(&descs)?;
+        }
+        info!(
+            "invalidated cache of {} chunks, blob {:?} path {:?}",
+            count, blob_id, path
+        );
+
+        Ok(count)
+    }
+
+    fn start_scrubber(&self) {
+        let sb = self.sb.clone();
+        let device = self.device.clone();
+        let cursor = self.scrub_cursor.clone();
+        let stop = self.scrub_stop.clone();
+        let interval = self.fs_scrub.interval;
+        let chunks = self.fs_scrub.chunks;
+
+        let _ = thread::Builder::new()
+            .name("rafs_scrubber".to_string())
+            .spawn(move || loop {
+                // Sleep in small steps so the scrubber quits in time when umounting.
+                for _ in 0..interval {
+                    if stop.load(Ordering::Acquire) {
+                        return;
+                    }
+                    thread::sleep(Duration::from_secs(1));
+                }
+                if let Err(e) = Self::do_scrub(&sb, &device, &cursor, chunks) {
+                    warn!("cache scrubber failed to walk filesystem, {}", e);
+                }
+            })
+            .map_err(|e| warn!("failed to start cache scrubber, {}", e));
+    }
+
+    fn do_scrub(
+        sb: &RafsSuper,
+        device: &BlobDevice,
+        cursor: &ScrubCursor,
+        chunks: u32,
+    ) -> Result<BlobScrubStat> {
+        let mut stat = BlobScrubStat::default();
+        let files = cursor.files(sb)?;
+        if files.is_empty() {
+            return Ok(stat);
+        }
+
+        let start = cursor.next.load(Ordering::Relaxed) % files.len();
+        let mut idx = start;
+        loop {
+            let ino = files[idx];
+            idx = (idx + 1) % files.len();
+            match sb
+                .get_inode(ino, false)
+                .and_then(|inode| inode.alloc_bio_vecs(0, inode.size() as usize, false))
+            {
+                Ok(descs) => device.scrub(&descs, &mut stat),
+                Err(e) => warn!(
+                    "cache scrubber failed to get chunks of inode {}, {}",
+                    ino, e
+                ),
+            }
+            if stat.sampled >= chunks as u64 || idx == start {
+                break;
+            }
+        }
+        cursor.next.store(idx, Ordering::Relaxed);
+
+        if stat.corrupted > 0 {
+            let msg = format!(
+                "cache scrubber found {} corrupted chunks out of {} verified",
+                stat.corrupted, stat.verified
+            );
+            warn!("{}", msg);
+            metrics::ERROR_HOLDER
+                .lock()
+                .unwrap()
+                .push(&msg)
+                .unwrap_or_else(|_| error!("Failed when try to hold error"));
+        } else {
+            debug!(
+                "cache scrubber verified {} of {} sampled chunks",
+                stat.verified, stat.sampled
+            );
+        }
+
+        Ok(stat)
+    }
+
+    fn collect_regular_files(
+        sb: &RafsSuper,
+        ino: Inode,
+        files: &mut Vec<Arc<dyn RafsInode>>,
+    ) -> Result<()> {
+        let inode = sb.get_inode(ino, false)?;
+        for idx in 0..inode.get_child_count() {
+            let child = inode.get_child_by_index(idx)?;
+            if child.is_dir() {
+                Self::collect_regular_files(sb, child.ino(), files)?;
+            } else if child.is_reg() {
+                files.push(child);
+            }
+        }
+        Ok(())
     }
 
     /// for blobfs
@@ -504,52 +2051,89 @@ impl Rafs {
         self.device.fetch_range_synchronous(prefetches)
     }
 
-    fn do_prefetch_v5(
-        mut reader: RafsIoReader,
-        prefetch_files: Option<Vec<PathBuf>>,
-        prefetch_all: bool,
+    fn do_prefetch(
+        ctl: Arc<PrefetchControl>,
         sb: Arc<RafsSuper>,
         device: BlobDevice,
+        inodes: Vec<Inode>,
+        prefetches: Vec<BlobPrefetchRequest>,
     ) {
-        // Without too much layout concern, just prefetch a certain range from backend.
-        let prefetches = sb
-            .superblock
-            .get_blobs()
-            .iter()
-            .map(|b| BlobPrefetchRequest {
-                blob_id: b.blob_id().to_owned(),
-                offset: b.readahead_offset() as u32,
-                len: b.readahead_size() as u32,
-            })
-            .collect::<Vec<BlobPrefetchRequest>>();
-        device.prefetch(&[], &prefetches).unwrap_or_else(|e| {
-            warn!("Prefetch error, {:?}", e);
-        });
-
-        let inodes = prefetch_files.map(|files| Self::convert_file_list(&files, &sb));
-        // Prefetch procedure does not affect rafs mounting
-        sb.prefetch_files(&mut reader, inodes, &|desc| {
-            device.prefetch(&[desc], &[]).unwrap_or_else(|e| {
+        if !prefetches.is_empty() {
+            device.prefetch(&[], &prefetches).unwrap_or_else(|e| {
                 warn!("Prefetch error, {:?}", e);
             });
-        })
-        .unwrap_or_else(|e| {
-            info!("No file to be prefetched {:?}", e);
-        });
+        }
 
-        if prefetch_all {
-            let root = vec![RAFS_ROOT_INODE];
-            sb.prefetch_files(&mut reader, Some(root), &|desc| {
+        ctl.files_total
+            .store(inodes.len() as u64, Ordering::Relaxed);
+        let bytes_total = Self::get_prefetch_size(&ctl, &sb, &inodes);
+        ctl.bytes_total.store(bytes_total, Ordering::Relaxed);
+
+        // Avoid prefetching multiple times for hardlinks to the same file.
+        let mut hardlinks = HashSet::new();
+        let mut head_desc = BlobIoVec::new();
+        let fetcher = |desc: &mut BlobIoVec| {
+            // Drop pending requests if the prefetch task has been stopped.
+            if ctl.wait() {
+                ctl.bytes_fetched
+                    .fetch_add(desc.bi_size as u64, Ordering::Relaxed);
                 device.prefetch(&[desc], &[]).unwrap_or_else(|e| {
                     warn!("Prefetch error, {:?}", e);
                 });
-            })
-            .unwrap_or_else(|e| {
-                info!("No file to be prefetched {:?}", e);
-            });
+            }
+        };
+
+        for ino in inodes {
+            if !ctl.wait() {
+                break;
+            }
+            // Prefetch procedure does not affect rafs mounting
+            sb.prefetch_data(ino, &mut head_desc, &mut hardlinks, &fetcher)
+                .unwrap_or_else(|e| {
+                    warn!("failed to prefetch inode {}, {}", ino, e);
+                });
+            ctl.files_done.fetch_add(1, Ordering::Relaxed);
         }
+        // Flush the pending prefetch requests.
+        fetcher(&mut head_desc);
 
         device.stop_prefetch();
+        ctl.transit(
+            &[RafsPrefetchState::Running, RafsPrefetchState::Paused],
+            RafsPrefetchState::Finished,
+        );
+    }
+
+    // Get total size of files to prefetch. Directories are walked one at a time, so the walk
+    // stops early if the prefetch task is stopped meanwhile.
+    fn get_prefetch_size(ctl: &PrefetchControl, sb: &RafsSuper, inodes: &[Inode]) -> u64 {
+        let mut size = 0;
+        let mut dirs = Vec::new();
+
+        for ino in inodes {
+            if let Ok(inode) = sb.get_inode(*ino, false) {
+                if inode.is_dir() {
+                    dirs.push(inode);
+                } else if inode.is_reg() {
+                    size += inode.size();
+                }
+            }
+        }
+        while let Some(dir) = dirs.pop() {
+            if !ctl.wait() {
+                break;
+            }
+            for idx in 0..dir.get_child_count() {
+                match dir.get_child_by_index(idx) {
+                    Ok(child) if child.is_dir() => dirs.push(child),
+                    Ok(child) if child.is_reg() => size += child.size(),
+                    Ok(_) => {}
+                    Err(e) => warn!("failed to get child {} of inode {}, {}", idx, dir.ino(), e),
+                }
+            }
+        }
+
+        size
     }
 
     fn convert_file_list(files: &[PathBuf], sb: &Arc<RafsSuper>) -> Vec<Inode> {
@@ -567,7 +2151,7 @@ impl Rafs {
 
 impl BackendFileSystem for Rafs {
     fn mount(&self) -> Result<(Entry, u64)> {
-        let root_inode = self.sb.get_inode(ROOT_ID, self.digest_validate)?;
+        let root_inode = self.sb.get_inode(self.root_ino, self.digest_validate)?;
         self.ios
             .new_file_counter(root_inode.ino(), |i| self.sb.path_from_ino(i).unwrap());
 
@@ -603,18 +2187,21 @@ impl FileSystem for Rafs {
     fn destroy(&self) {}
 
     fn lookup(&self, _ctx: &Context, ino: u64, name: &CStr) -> Result<Entry> {
+        let ino = self.rafs_ino(ino);
         let mut rec = FopRecorder::settle(Lookup, ino, &self.ios);
         let target = OsStr::from_bytes(name.to_bytes());
+        if let Some(r) = self.lookup_image_info(ino, target) {
+            rec.mark_success(0);
+            return r;
+        }
         let parent = self.sb.get_inode(ino, self.digest_validate)?;
         if !parent.is_dir() {
             return Err(enotdir!());
         }
 
         rec.mark_success(0);
-        if target == DOT || (ino == ROOT_ID && target == DOTDOT) {
-            let mut entry = self.get_inode_entry(parent);
-            entry.inode = ino;
-            Ok(entry)
+        if target == DOT || (ino == self.root_ino && target == DOTDOT) {
+            Ok(self.get_inode_entry(parent))
         } else if target == DOTDOT {
             Ok(self
                 .sb
@@ -647,6 +2234,7 @@ impl FileSystem for Rafs {
         ino: u64,
         _handle: Option<u64>,
     ) -> Result<(libc::stat64, Duration)> {
+        let ino = self.rafs_ino(ino);
         let mut recorder = FopRecorder::settle(Getattr, ino, &self.ios);
         let attr = self.get_inode_attr(ino).map(|r| {
             recorder.mark_success(0);
@@ -657,6 +2245,7 @@ impl FileSystem for Rafs {
     }
 
     fn readlink(&self, _ctx: &Context, ino: u64) -> Result<Vec<u8>> {
+        let ino = self.rafs_ino(ino);
         let mut rec = FopRecorder::settle(Readlink, ino, &self.ios);
         let inode = self.sb.get_inode(ino, self.digest_validate)?;
 
@@ -686,6 +2275,16 @@ impl FileSystem for Rafs {
             return Err(einval!("offset + size wraps around."));
         }
 
+        let ino = self.rafs_ino(ino);
+        let mut span = trace::span("fuse_read");
+        span.arg("ino", ino);
+        span.arg("offset", offset);
+        span.arg("size", size as u64);
+        if ino == IMAGE_INFO_FILE_INO {
+            if let Some(info) = self.image_info() {
+                return info.read(w, size, offset);
+            }
+        }
         let inode = self.sb.get_inode(ino, false)?;
         let inode_size = inode.size();
         let mut recorder = FopRecorder::settle(Read, ino, &self.ios);
@@ -696,12 +2295,16 @@ impl FileSystem for Rafs {
         }
 
         let real_size = cmp::min(size as u64, inode_size - offset);
-        let mut result = 0;
         let mut descs = inode.alloc_bio_vecs(offset, real_size as usize, true)?;
-        debug_assert!(!descs.is_empty() && !descs[0].bi_vec.is_empty());
+        // Holes of sparse files are not backed by any chunk, so the io vectors don't cover
+        // the whole range and zeros should be synthesized for the holes.
+        let has_hole = descs.iter().map(|d| d.bi_size as u64).sum::<u64>() < real_size;
+        debug_assert!(has_hole || (!descs.is_empty() && !descs[0].bi_vec.is_empty()));
+
+        let uncached = self.is_uncached_read(&inode);
 
         // Try to amplify user io for Rafs v5, to improve performance.
-        if self.sb.meta.is_v5() && size < self.amplify_io {
+        if self.sb.meta.is_v5() && size < self.amplify_io && !has_hole && !uncached {
             let all_chunks_ready = self.device.is_all_chunk_ready(&descs);
             if !all_chunks_ready {
                 let chunk_size = self.metadata().chunk_size as u64;
@@ -722,31 +2325,32 @@ impl FileSystem for Rafs {
         }
 
         let start = self.ios.latency_start();
-        for desc in descs.iter_mut() {
-            debug_assert!(desc.validate());
-            debug_assert!(!desc.bi_vec.is_empty());
-            debug_assert!(desc.bi_size != 0);
-
+        let _guard = self.accounting.enter();
+        let result = Self::read_descs(w, &mut descs, offset, real_size, has_hole, |w, desc| {
             // Avoid copying `desc`
-            let r = self.device.read_to(w, desc)?;
-            result += r;
-            recorder.mark_success(r);
-            if r != desc.bi_size {
-                break;
+            if uncached {
+                self.device.read_to_uncached(w, desc)
+            } else {
+                self.device.read_to(w, desc)
             }
-        }
+        })?;
+        recorder.mark_success(result);
         self.ios.latency_end(&start, Read);
+        self.accounting.account_request(result);
 
         Ok(result)
     }
 
     fn open(
         &self,
-        _ctx: &Context,
-        _inode: Self::Inode,
-        _flags: u32,
+        ctx: &Context,
+        inode: Self::Inode,
+        flags: u32,
         _fuse_flags: u32,
     ) -> Result<(Option<Self::Handle>, OpenOptions)> {
+        if (flags as i32) & (libc::O_WRONLY | libc::O_RDWR | libc::O_TRUNC) != 0 {
+            return Err(self.reject_write(ctx, WriteFop::Open, inode, None));
+        }
         // Keep cache since we are readonly
         Ok((None, OpenOptions::KEEP_CACHE))
     }
@@ -785,12 +2389,16 @@ impl FileSystem for Rafs {
         name: &CStr,
         size: u32,
     ) -> Result<GetxattrReply> {
+        let inode = self.rafs_ino(inode);
         let mut recorder = FopRecorder::settle(Getxattr, inode, &self.ios);
 
         if !self.xattr_supported() {
             return Err(std::io::Error::from_raw_os_error(libc::ENOSYS));
         }
 
+        if self.is_image_info(inode) {
+            return Err(std::io::Error::from_raw_os_error(libc::ENODATA));
+        }
         let name = OsStr::from_bytes(name.to_bytes());
         let inode = self.sb.get_inode(inode, false)?;
         let value = inode.get_xattr(name)?;
@@ -816,15 +2424,20 @@ impl FileSystem for Rafs {
     }
 
     fn listxattr(&self, _ctx: &Context, inode: u64, size: u32) -> Result<ListxattrReply> {
+        let inode = self.rafs_ino(inode);
         let mut rec = FopRecorder::settle(Listxattr, inode, &self.ios);
         if !self.xattr_supported() {
             return Err(std::io::Error::from_raw_os_error(libc::ENOSYS));
         }
 
-        let inode = self.sb.get_inode(inode, false)?;
         let mut count = 0;
         let mut buf = Vec::new();
-        for mut name in inode.get_xattrs()? {
+        let names = if self.is_image_info(inode) {
+            Vec::new()
+        } else {
+            self.sb.get_inode(inode, false)?.get_xattrs()?
+        };
+        for mut name in names {
             count += name.len() + 1;
             if size != 0 {
                 buf.append(&mut name);
@@ -850,6 +2463,7 @@ impl FileSystem for Rafs {
         offset: u64,
         add_entry: &mut dyn FnMut(DirEntry) -> Result<usize>,
     ) -> Result<()> {
+        let inode = self.rafs_ino(inode);
         let mut rec = FopRecorder::settle(Readdir, inode, &self.ios);
 
         self.do_readdir(inode, size, offset, add_entry).map(|r| {
@@ -867,10 +2481,16 @@ impl FileSystem for Rafs {
         offset: u64,
         add_entry: &mut dyn FnMut(DirEntry, Entry) -> Result<usize>,
     ) -> Result<()> {
+        let ino = self.rafs_ino(ino);
         let mut rec = FopRecorder::settle(Readdirplus, ino, &self.ios);
 
         self.do_readdir(ino, size, offset, |dir_entry| {
-            let inode = self.sb.get_inode(dir_entry.ino, self.digest_validate)?;
+            if let Some(entry) = self.image_info().and_then(|i| i.entry(dir_entry.ino)) {
+                return add_entry(dir_entry, entry);
+            }
+            let inode = self
+                .sb
+                .get_inode(self.rafs_ino(dir_entry.ino), self.digest_validate)?;
             add_entry(dir_entry, self.get_inode_entry(inode))
         })
         .map(|r| {
@@ -893,7 +2513,33 @@ impl FileSystem for Rafs {
         Ok(())
     }
 
+    fn lseek(
+        &self,
+        _ctx: &Context,
+        ino: u64,
+        _handle: u64,
+        offset: u64,
+        whence: u32,
+    ) -> Result<u64> {
+        let inode = self.sb.get_inode(self.rafs_ino(ino), false)?;
+        if !inode.is_reg() {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let size = inode.size();
+
+        match whence as i32 {
+            libc::SEEK_DATA | libc::SEEK_HOLE if offset >= size => {
+                Err(std::io::Error::from_raw_os_error(libc::ENXIO))
+            }
+            libc::SEEK_DATA | libc::SEEK_HOLE => {
+                self.seek_extent(inode.as_ref(), offset, whence as i32)
+            }
+            _ => Err(std::io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
     fn access(&self, ctx: &Context, ino: u64, mask: u32) -> Result<()> {
+        let ino = self.rafs_ino(ino);
         let mut rec = FopRecorder::settle(Access, ino, &self.ios);
         let st = self.get_inode_attr(ino)?;
         let mode = mask as i32 & (libc::R_OK | libc::W_OK | libc::X_OK);
@@ -935,6 +2581,122 @@ impl FileSystem for Rafs {
         rec.mark_success(0);
         Ok(())
     }
+
+    fn setattr(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        _attr: libc::stat64,
+        _handle: Option<u64>,
+        _valid: SetattrValid,
+    ) -> Result<(libc::stat64, Duration)> {
+        Err(self.reject_write(ctx, WriteFop::Setattr, inode, None))
+    }
+
+    fn mknod(
+        &self,
+        ctx: &Context,
+        parent: u64,
+        name: &CStr,
+        _mode: u32,
+        _rdev: u32,
+        _umask: u32,
+    ) -> Result<Entry> {
+        Err(self.reject_write(ctx, WriteFop::Mknod, parent, Some(name)))
+    }
+
+    fn mkdir(
+        &self,
+        ctx: &Context,
+        parent: u64,
+        name: &CStr,
+        _mode: u32,
+        _umask: u32,
+    ) -> Result<Entry> {
+        Err(self.reject_write(ctx, WriteFop::Mkdir, parent, Some(name)))
+    }
+
+    fn unlink(&self, ctx: &Context, parent: u64, name: &CStr) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Unlink, parent, Some(name)))
+    }
+
+    fn rmdir(&self, ctx: &Context, parent: u64, name: &CStr) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Rmdir, parent, Some(name)))
+    }
+
+    fn symlink(&self, ctx: &Context, _linkname: &CStr, parent: u64, name: &CStr) -> Result<Entry> {
+        Err(self.reject_write(ctx, WriteFop::Symlink, parent, Some(name)))
+    }
+
+    fn rename(
+        &self,
+        ctx: &Context,
+        olddir: u64,
+        oldname: &CStr,
+        _newdir: u64,
+        _newname: &CStr,
+        _flags: u32,
+    ) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Rename, olddir, Some(oldname)))
+    }
+
+    fn link(&self, ctx: &Context, inode: u64, _newparent: u64, _newname: &CStr) -> Result<Entry> {
+        Err(self.reject_write(ctx, WriteFop::Link, inode, None))
+    }
+
+    fn create(
+        &self,
+        ctx: &Context,
+        parent: u64,
+        name: &CStr,
+        _args: CreateIn,
+    ) -> Result<(Entry, Option<u64>, OpenOptions)> {
+        Err(self.reject_write(ctx, WriteFop::Create, parent, Some(name)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        _handle: u64,
+        _r: &mut dyn ZeroCopyReader,
+        _size: u32,
+        _offset: u64,
+        _lock_owner: Option<u64>,
+        _delayed_write: bool,
+        _flags: u32,
+        _fuse_flags: u32,
+    ) -> Result<usize> {
+        Err(self.reject_write(ctx, WriteFop::Write, inode, None))
+    }
+
+    fn fallocate(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        _handle: u64,
+        _mode: u32,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Fallocate, inode, None))
+    }
+
+    fn setxattr(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        _name: &CStr,
+        _value: &[u8],
+        _flags: u32,
+    ) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Setxattr, inode, None))
+    }
+
+    fn removexattr(&self, ctx: &Context, inode: u64, _name: &CStr) -> Result<()> {
+        Err(self.reject_write(ctx, WriteFop::Removexattr, inode, None))
+    }
 }
 
 #[cfg(test)]
@@ -944,6 +2706,10 @@ pub(crate) mod tests {
     use storage::RAFS_MAX_CHUNK_SIZE;
 
     pub fn new_rafs_backend() -> Box<Rafs> {
+        new_rafs_backend_at(None)
+    }
+
+    fn new_rafs_backend_at(root_path: Option<String>) -> Box<Rafs> {
         let config = r#"
         {
             "device": {
@@ -974,7 +2740,8 @@ pub(crate) mod tests {
         let mut source_path = PathBuf::from(root_dir);
         source_path.push("../tests/texture/bootstrap/image_v2.boot");
         let mountpoint = "/mnt";
-        let rafs_config = RafsConfig::from_str(config).unwrap();
+        let mut rafs_config = RafsConfig::from_str(config).unwrap();
+        rafs_config.root_path = root_path;
         let bootstrapfile = source_path.to_str().unwrap();
         let mut bootstrap = <dyn RafsIoRead>::from_file(bootstrapfile).unwrap();
         let mut rafs = Rafs::new(rafs_config, mountpoint, &mut bootstrap).unwrap();
@@ -994,6 +2761,23 @@ pub(crate) mod tests {
         assert_eq!(attr.mode & 0o777, 0o755);
     }
 
+    #[test]
+    fn it_should_statx() {
+        let rafs = new_rafs_backend();
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let (stat, btime) = rafs.statx(1).unwrap();
+        let (stat2, _) = rafs.getattr(ctx, 1, None).unwrap();
+        assert_eq!(stat.st_ino, stat2.st_ino);
+        assert_eq!(stat.st_mode, stat2.st_mode);
+        // The image is built without birth time.
+        assert_eq!(btime, 0);
+        assert!(rafs.statx(0x10_0000).is_err());
+    }
+
     #[test]
     fn it_should_access() {
         let rafs = new_rafs_backend();
@@ -1069,6 +2853,358 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_reject_writes() {
+        let mut rafs = new_rafs_backend();
+        rafs.audit_writes = true;
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let name = std::ffi::CString::new("new_dir").unwrap();
+
+        let e = rafs.mkdir(ctx, 1, &name, 0o755, 0).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EROFS));
+        let e = rafs.unlink(ctx, 1, &name).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EROFS));
+        assert!(rafs.open(ctx, 1, libc::O_RDWR as u32, 0).is_err());
+        assert!(rafs.open(ctx, 1, libc::O_RDONLY as u32, 0).is_ok());
+
+        assert_eq!(rafs.ios.rejected_writes(WriteFop::Mkdir), 1);
+        assert_eq!(rafs.ios.rejected_writes(WriteFop::Unlink), 1);
+        assert_eq!(rafs.ios.rejected_writes(WriteFop::Open), 1);
+        assert_eq!(rafs.ios.rejected_writes(WriteFop::Write), 0);
+    }
+
+    #[test]
+    fn test_image_info() {
+        let mut rafs = new_rafs_backend();
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let dir = std::ffi::CString::new(IMAGE_INFO_DIR).unwrap();
+        let file = std::ffi::CString::new(IMAGE_INFO_FILE).unwrap();
+        assert_eq!(rafs.lookup(ctx, ROOT_ID, &dir).unwrap().inode, 0);
+
+        let info = rafs.new_image_info().unwrap().unwrap();
+        let v: serde_json::Value = serde_json::from_slice(info.content()).unwrap();
+        assert_eq!(v["rafs_version"], "v5");
+        assert_eq!(
+            v["blobs"].as_array().unwrap().len(),
+            rafs.sb.superblock.get_blob_infos().len()
+        );
+        let size = info.content().len() as i64;
+        rafs.image_info = Some(ArcSwap::new(Arc::new(info)));
+
+        let entry = rafs.lookup(ctx, ROOT_ID, &dir).unwrap();
+        assert_eq!(entry.inode, IMAGE_INFO_DIR_INO);
+        assert_eq!(entry.attr.st_mode & libc::S_IFMT, libc::S_IFDIR);
+        let entry = rafs.lookup(ctx, IMAGE_INFO_DIR_INO, &file).unwrap();
+        assert_eq!(entry.inode, IMAGE_INFO_FILE_INO);
+        assert_eq!(entry.attr.st_size, size);
+        let (attr, _) = rafs.getattr(ctx, IMAGE_INFO_FILE_INO, None).unwrap();
+        assert_eq!(attr.st_mode, libc::S_IFREG | 0o444);
+        assert!(rafs.lookup(ctx, IMAGE_INFO_FILE_INO, &file).is_err());
+        let dot = std::ffi::CString::new(DOTDOT).unwrap();
+        assert_eq!(
+            rafs.lookup(ctx, IMAGE_INFO_DIR_INO, &dot).unwrap().inode,
+            ROOT_ID
+        );
+
+        let mut names = Vec::new();
+        rafs.readdir(ctx, ROOT_ID, 0, 4096, 0, &mut |e| {
+            names.push(e.name.to_vec());
+            Ok(1)
+        })
+        .unwrap();
+        assert_eq!(names.last().unwrap(), IMAGE_INFO_DIR.as_bytes());
+        names.clear();
+        rafs.readdir(ctx, IMAGE_INFO_DIR_INO, 0, 4096, 0, &mut |e| {
+            names.push(e.name.to_vec());
+            Ok(1)
+        })
+        .unwrap();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[2], IMAGE_INFO_FILE.as_bytes());
+    }
+
+    #[test]
+    fn test_image_info_through_vfs() {
+        use fuse_backend_rs::api::{Vfs, VfsOptions};
+
+        let mut rafs = new_rafs_backend();
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+        let info = rafs.new_image_info().unwrap().unwrap();
+        rafs.image_info = Some(ArcSwap::new(Arc::new(info)));
+        let vfs = Vfs::new(VfsOptions::default());
+        vfs.mount(Box::new(rafs), "/").unwrap();
+
+        // Inode numbers of the virtual directory and file must be accepted by vfs.
+        let dir = std::ffi::CString::new(IMAGE_INFO_DIR).unwrap();
+        let file = std::ffi::CString::new(IMAGE_INFO_FILE).unwrap();
+        let dir_entry = vfs.lookup(ctx, ROOT_ID, &dir).unwrap();
+        assert_eq!(dir_entry.attr.st_mode & libc::S_IFMT, libc::S_IFDIR);
+        let file_entry = vfs.lookup(ctx, dir_entry.inode, &file).unwrap();
+        let (attr, _) = vfs.getattr(ctx, file_entry.inode, None).unwrap();
+        assert_eq!(attr.st_mode, libc::S_IFREG | 0o444);
+
+        let mut entries = Vec::new();
+        vfs.readdirplus(ctx, ROOT_ID, 0, 4096, 0, &mut |d, e| {
+            entries.push((d.name.to_vec(), e.inode));
+            Ok(1)
+        })
+        .unwrap();
+        let (name, ino) = entries.last().unwrap();
+        assert_eq!(name, IMAGE_INFO_DIR.as_bytes());
+        assert_eq!(*ino, dir_entry.inode);
+    }
+
+    #[test]
+    fn test_inode_number_stability() {
+        let rafs1 = new_rafs_backend();
+        let rafs2 = new_rafs_backend();
+        let root = rafs1.sb.get_inode(RAFS_ROOT_INODE, false).unwrap();
+        let mut dirs = vec![(PathBuf::from("/"), root)];
+        let mut checked = 0;
+
+        // Inode numbers are assigned from the bootstrap, so remounting the same bootstrap, e.g.
+        // after live-upgrade, yields identical inode numbers, including those of hardlinks.
+        while let Some((path, dir)) = dirs.pop() {
+            for idx in 0..dir.get_child_count() {
+                let child = dir.get_child_by_index(idx).unwrap();
+                let child_path = path.join(child.name());
+                if child.is_dir() || child.is_hardlink() {
+                    let ino = rafs2.sb.ino_from_path(&child_path).unwrap();
+                    assert_eq!(ino, child.ino());
+                    assert_eq!(rafs2.sb.get_inode(ino, false).unwrap().ino(), ino);
+                    checked += 1;
+                }
+                if child.is_dir() {
+                    dirs.push((child_path, child));
+                }
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn test_mount_root_path() {
+        let rafs = new_rafs_backend();
+        let root = rafs.sb.get_inode(RAFS_ROOT_INODE, false).unwrap();
+        let dir = (0..root.get_child_count())
+            .map(|idx| root.get_child_by_index(idx).unwrap())
+            .find(|c| c.is_dir() && c.get_child_count() > 0)
+            .unwrap();
+        let child = dir.get_child_by_index(0).unwrap();
+        let ctx = &Context {
+            gid: 0,
+            pid: 1,
+            uid: 0,
+        };
+
+        let rafs = new_rafs_backend_at(Some(format!("/{}", dir.name().to_str().unwrap())));
+        assert_eq!(rafs.root_ino, dir.ino());
+        let (entry, _) = rafs.mount().unwrap();
+        assert_eq!(entry.inode, ROOT_ID);
+        let (attr, _) = rafs.getattr(ctx, ROOT_ID, None).unwrap();
+        assert_eq!(attr.st_ino, ROOT_ID);
+        assert_eq!(attr.st_size, dir.size() as i64);
+
+        let name = std::ffi::CString::new(child.name().as_bytes()).unwrap();
+        let entry = rafs.lookup(ctx, ROOT_ID, &name).unwrap();
+        assert_eq!(entry.inode, child.ino());
+        // The parent of the root is the root itself.
+        let dotdot = std::ffi::CString::new(DOTDOT).unwrap();
+        assert_eq!(rafs.lookup(ctx, ROOT_ID, &dotdot).unwrap().inode, ROOT_ID);
+        if child.is_dir() {
+            assert_eq!(
+                rafs.lookup(ctx, child.ino(), &dotdot).unwrap().inode,
+                ROOT_ID
+            );
+        }
+
+        let mut entries = Vec::new();
+        rafs.readdir(ctx, ROOT_ID, 0, 4096, 0, &mut |e| {
+            entries.push((e.ino, e.name.to_vec()));
+            Ok(1)
+        })
+        .unwrap();
+        assert_eq!(entries[0], (ROOT_ID, DOT.as_bytes().to_vec()));
+        assert_eq!(entries[1], (ROOT_ID, DOTDOT.as_bytes().to_vec()));
+        assert_eq!(entries.len(), dir.get_child_count() as usize + 2);
+    }
+
+    #[test]
+    fn test_mount_invalid_root_path() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let path = PathBuf::from(root_dir).join("../tests/texture/bootstrap/image_v2.boot");
+        let mut config = RafsConfig::from_str(
+            r#"{"device":{"backend":{"type":"localfs","config":{"dir":"/tmp"}}},"mode":"direct"}"#,
+        )
+        .unwrap();
+        config.root_path = Some("/no/such/dir".to_string());
+        let mut bootstrap = <dyn RafsIoRead>::from_file(path.to_str().unwrap()).unwrap();
+        assert!(Rafs::new(config, "/mnt", &mut bootstrap).is_err());
+    }
+
+    #[test]
+    fn test_mount_shared_bootstrap() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let path = PathBuf::from(root_dir).join("../tests/texture/bootstrap/image_v2.boot");
+        let shared_dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        let mut config = RafsConfig::from_str(
+            r#"{"device":{"backend":{"type":"localfs","config":{"dir":"/tmp"}}},"mode":"direct"}"#,
+        )
+        .unwrap();
+        config.shared_bootstrap.enable = true;
+        config.shared_bootstrap.dir = shared_dir.as_path().to_str().unwrap().to_string();
+
+        let mut bootstrap1 = <dyn RafsIoRead>::from_file(path.to_str().unwrap()).unwrap();
+        let mut rafs1 = Rafs::new(config.clone(), "/mnt1", &mut bootstrap1).unwrap();
+        rafs1.import(bootstrap1, None).unwrap();
+        let mut bootstrap2 = <dyn RafsIoRead>::from_file(path.to_str().unwrap()).unwrap();
+        let mut rafs2 = Rafs::new(config, "/mnt2", &mut bootstrap2).unwrap();
+        rafs2.import(bootstrap2, None).unwrap();
+
+        let shared_path = rafs1
+            .shared_bootstrap
+            .as_ref()
+            .unwrap()
+            .path()
+            .to_path_buf();
+        assert_eq!(
+            rafs2.shared_bootstrap.as_ref().unwrap().path(),
+            shared_path.as_path()
+        );
+        assert_eq!(
+            std::fs::read(&shared_path).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+        assert_eq!(rafs2.get_inode_attr(ROOT_ID).unwrap().ino, ROOT_ID);
+
+        rafs1.destroy().unwrap();
+        assert!(shared_path.exists());
+        rafs2.destroy().unwrap();
+        assert!(!shared_path.exists());
+    }
+
+    #[test]
+    fn test_seek_data_hole() {
+        let extents = [(0, 0x1000), (0x3000, 0x5000)];
+
+        assert_eq!(seek_data(&extents, 0), Some(0));
+        assert_eq!(seek_data(&extents, 0x800), Some(0x800));
+        assert_eq!(seek_data(&extents, 0x1000), Some(0x3000));
+        assert_eq!(seek_data(&extents, 0x4000), Some(0x4000));
+        assert_eq!(seek_data(&extents, 0x5000), None);
+        assert_eq!(seek_data(&[], 0), None);
+
+        assert_eq!(seek_hole(&extents, 0, 0x6000), 0x1000);
+        assert_eq!(seek_hole(&extents, 0x2000, 0x6000), 0x2000);
+        assert_eq!(seek_hole(&extents, 0x3000, 0x6000), 0x5000);
+        assert_eq!(seek_hole(&extents, 0x3000, 0x4000), 0x4000);
+        assert_eq!(seek_hole(&[], 0x100, 0x6000), 0x100);
+    }
+
+    #[test]
+    fn test_uncached_read_config() {
+        let config = r#"
+        {
+            "device": {
+              "backend": {
+                "type": "localfs",
+                "config": {
+                  "dir": "/tmp"
+                }
+              }
+            },
+            "mode": "direct",
+            "uncached_read": {
+              "enable": true
+            }
+        }"#;
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let mut source_path = PathBuf::from(root_dir);
+        source_path.push("../tests/texture/bootstrap/image_v2.boot");
+        let bootstrapfile = source_path.to_str().unwrap();
+        let mut rafs_config = RafsConfig::from_str(config).unwrap();
+        assert!(rafs_config.uncached_read.enable);
+        assert_eq!(rafs_config.uncached_read.size_threshold, 0);
+        assert!(rafs_config.uncached_read.files.is_empty());
+
+        let mut bootstrap = <dyn RafsIoRead>::from_file(bootstrapfile).unwrap();
+        assert!(Rafs::new(rafs_config.clone(), "/mnt", &mut bootstrap).is_err());
+
+        rafs_config.uncached_read.size_threshold = 1 << 30;
+        let mut bootstrap = <dyn RafsIoRead>::from_file(bootstrapfile).unwrap();
+        let mut rafs = Rafs::new(rafs_config, "/mnt", &mut bootstrap).unwrap();
+        rafs.import(bootstrap, None).unwrap();
+        let root = rafs.sb.get_inode(RAFS_ROOT_INODE, false).unwrap();
+        assert!(!rafs.is_uncached_read(&root));
+        rafs.uncached_inodes.insert(RAFS_ROOT_INODE);
+        assert!(rafs.is_uncached_read(&root));
+    }
+
+    #[test]
+    fn test_rafs_config_validate() {
+        let mut config = RafsConfig::from_str(
+            r#"{
+              "device": {"backend": {"type": "localfs", "config": {"dir": "/tmp"}}},
+              "mode": "direct",
+              "fs_prefetch": {"enable": true, "threads_count": 4},
+              "fuse": {"writeback_cache": false}
+            }"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let err = RafsConfig::from_str(
+            r#"{"device": {"backend": {"type": "localfs", "config": {}}}, "mode": "direct", "fs_prefetch": {"enabled": true}}"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(err.contains("unknown field `enabled`"), "{}", err);
+
+        config.mode = "lazy".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`mode`, expected direct or cached"), "{}", err);
+        config.mode = "cached".to_string();
+
+        config.fs_prefetch.threads_count = 0;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`fs_prefetch.threads_count`"), "{}", err);
+        config.fs_prefetch.enable = false;
+        config.validate().unwrap();
+
+        config.device.backend.backend_type = "s3".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`backend.type`"), "{}", err);
+        config.device.backend.backend_type = "localfs".to_string();
+
+        config.image_digest.expected = Some("sha256:1234".to_string());
+        assert!(config.validate().is_err());
+        config.image_digest.expected = Some(format!("sha256:{}", "a".repeat(64)));
+        config.validate().unwrap();
+
+        config.uncached_read.enable = true;
+        config.uncached_read.files = vec!["usr/lib".to_string()];
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`uncached_read.files`"), "{}", err);
+        config.uncached_read.files = vec!["/usr/lib".to_string()];
+        config.validate().unwrap();
+
+        config.root_path = Some("home".to_string());
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_fsprefetchcontrol_from_rafs_config() {
         let mut config = RafsConfig {
@@ -1096,4 +3232,150 @@ pub(crate) mod tests {
         config.fs_prefetch.prefetch_all = true;
         assert!(BlobPrefetchConfig::try_from(&config).is_ok());
     }
+
+    #[test]
+    fn test_prefetch_jobs() {
+        let rafs = new_rafs_backend();
+        assert!(rafs
+            .warm_up(Some(vec![PathBuf::from("/no/such/file")]))
+            .is_err());
+
+        let id1 = rafs.warm_up(None).unwrap();
+        let id2 = rafs.warm_up(None).unwrap();
+        assert!(id2 > id1);
+        // The first job is stopped if it's superseded before finishing.
+        let state = rafs.prefetch_job_status(id1).unwrap().state;
+        assert!(state == RafsPrefetchState::Stopped || state == RafsPrefetchState::Finished);
+        assert!(rafs.prefetch_job_status(id2).is_some());
+        assert!(rafs.prefetch_job_status(0).is_none());
+    }
+
+    #[test]
+    fn test_prefetch_control() {
+        let ctl = PrefetchControl::new();
+        assert_eq!(ctl.status().state, RafsPrefetchState::Idle);
+        assert!(!ctl.transit(&[RafsPrefetchState::Running], RafsPrefetchState::Paused));
+        assert!(ctl.transit(&[RafsPrefetchState::Idle], RafsPrefetchState::Running));
+        assert!(ctl.wait());
+
+        ctl.bytes_total.store(100, Ordering::Relaxed);
+        ctl.bytes_fetched.store(50, Ordering::Relaxed);
+        assert!(ctl.status().eta_secs.is_some());
+        assert!(ctl.transit(&[RafsPrefetchState::Running], RafsPrefetchState::Paused));
+        assert!(ctl.status().eta_secs.is_none());
+
+        let ctl = Arc::new(ctl);
+        let ctl2 = ctl.clone();
+        let handle = thread::spawn(move || ctl2.wait());
+        thread::sleep(Duration::from_millis(100));
+        assert!(ctl.transit(
+            &[RafsPrefetchState::Running, RafsPrefetchState::Paused],
+            RafsPrefetchState::Stopped
+        ));
+        assert!(!handle.join().unwrap());
+        assert_eq!(ctl.status().state, RafsPrefetchState::Stopped);
+    }
+
+    #[test]
+    fn test_seal_bootstrap() {
+        use std::io::Read;
+
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+        std::fs::write(file.as_path(), b"bootstrap").unwrap();
+        let mut r = <dyn RafsIoRead>::from_file(file.as_path()).unwrap();
+        let mut sealed = Rafs::seal_bootstrap(&mut r).unwrap();
+
+        // Changes to the bootstrap file are not visible through the sealed copy.
+        std::fs::write(file.as_path(), b"modified").unwrap();
+        let mut buf = Vec::new();
+        sealed.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"bootstrap");
+    }
+
+    struct VecWriter(Vec<u8>);
+
+    impl Write for VecWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ZeroCopyWriter for VecWriter {
+        fn write_from(
+            &mut self,
+            _f: &mut dyn fuse_backend_rs::transport::FileReadWriteVolatile,
+            _count: usize,
+            _off: u64,
+        ) -> Result<usize> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_read_descs_with_hole() {
+        use crate::mock::MockChunkInfo;
+        use storage::device::BlobIoDesc;
+
+        // Chunks of 0x1000 bytes at file offsets 0x1000 and 0x3000, with holes around them.
+        let descs = || {
+            [0x1000u64, 0x3000]
+                .iter()
+                .map(|offset| {
+                    let chunk = Arc::new(MockChunkInfo::mock(*offset, 0, 0x1000, 0, 0x1000))
+                        as Arc<dyn BlobV5ChunkInfo>;
+                    let mut desc = BlobIoVec::new();
+                    desc.bi_vec.push(BlobIoDesc::new(
+                        Arc::new(BlobInfo::default()),
+                        chunk.into(),
+                        0,
+                        0x1000,
+                        true,
+                    ));
+                    desc.bi_size = 0x1000;
+                    desc
+                })
+                .collect::<Vec<_>>()
+        };
+        let read_all = |w: &mut dyn ZeroCopyWriter, desc: &mut BlobIoVec| -> Result<usize> {
+            w.write_all(&vec![1u8; desc.bi_size])?;
+            Ok(desc.bi_size)
+        };
+
+        let mut w = VecWriter(Vec::new());
+        let size = Rafs::read_descs(&mut w, &mut descs(), 0, 0x5000, true, read_all).unwrap();
+        assert_eq!(size, 0x5000);
+        assert_eq!(w.0.len(), 0x5000);
+        assert!(w.0[..0x1000].iter().all(|v| *v == 0));
+        assert!(w.0[0x1000..0x2000].iter().all(|v| *v == 1));
+        assert!(w.0[0x2000..0x3000].iter().all(|v| *v == 0));
+        assert!(w.0[0x3000..0x4000].iter().all(|v| *v == 1));
+        assert!(w.0[0x4000..].iter().all(|v| *v == 0));
+
+        // The backend returns less data than requested for the last chunk, the trailing hole
+        // must not be filled as if the chunk were read fully.
+        let mut w = VecWriter(Vec::new());
+        let size = Rafs::read_descs(&mut w, &mut descs(), 0, 0x5000, true, |w, desc| {
+            let file_offset = Rafs::bio_file_offset(desc)?;
+            let size = if file_offset == 0x3000 { 0x800 } else { 0x1000 };
+            w.write_all(&vec![1u8; size])?;
+            Ok(size)
+        })
+        .unwrap();
+        assert_eq!(size, 0x3800);
+        assert_eq!(w.0.len(), 0x3800);
+
+        // A short read of the first chunk stops reading further chunks.
+        let mut w = VecWriter(Vec::new());
+        let size = Rafs::read_descs(&mut w, &mut descs(), 0x800, 0x4800, true, |w, _desc| {
+            w.write_all(&[1u8; 0x10])?;
+            Ok(0x10)
+        })
+        .unwrap();
+        assert_eq!(size, 0x810);
+    }
 }
@@ -0,0 +1,153 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtual file `.nydus/image.json` in the root directory of filesystem instances.
+//!
+//! Tools inside containers may discover provenance of the image by reading the virtual file,
+//! which contains the image digest, data blobs of the image and build information recorded in
+//! the blobs. The virtual directory and file are read-only and not backed by the bootstrap.
+
+use std::cmp;
+use std::io::Result;
+use std::time::Duration;
+
+use fuse_backend_rs::abi::linux_abi::Attr;
+use fuse_backend_rs::api::filesystem::{Entry, ZeroCopyWriter};
+use fuse_backend_rs::api::VFS_MAX_INO;
+use serde::Serialize;
+
+use crate::fs::RafsBlobProvenance;
+use crate::metadata::RafsSuper;
+
+/// Name of the virtual directory in the root directory.
+pub(crate) const IMAGE_INFO_DIR: &str = ".nydus";
+/// Name of the virtual file in the virtual directory.
+pub(crate) const IMAGE_INFO_FILE: &str = "image.json";
+/// Inode number of the virtual directory. Vfs rejects inode numbers above `VFS_MAX_INO`, so the
+/// largest ones it accepts are taken, which are far beyond inode numbers of real bootstraps.
+pub(crate) const IMAGE_INFO_DIR_INO: u64 = VFS_MAX_INO;
+/// Inode number of the virtual file.
+pub(crate) const IMAGE_INFO_FILE_INO: u64 = VFS_MAX_INO - 1;
+
+#[derive(Serialize)]
+struct ImageInfoBlob {
+    blob_id: String,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    chunk_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<RafsBlobProvenance>,
+}
+
+#[derive(Serialize)]
+struct ImageInfo {
+    rafs_version: String,
+    /// Image digest recorded in the bootstrap, absent for images built by former builders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_digest: Option<String>,
+    chunk_size: u32,
+    inodes_count: u64,
+    blobs: Vec<ImageInfoBlob>,
+}
+
+/// The virtual directory and file, with content generated from the superblock.
+pub(crate) struct ImageInfoFile {
+    content: Vec<u8>,
+    uid: u32,
+    gid: u32,
+    time: u64,
+    attr_timeout: Duration,
+    entry_timeout: Duration,
+}
+
+impl ImageInfoFile {
+    /// Generate content of the virtual file, owned by `uid` and `gid` with timestamp `time`.
+    pub fn new(sb: &RafsSuper, uid: u32, gid: u32, time: u64) -> Result<Self> {
+        let meta = &sb.meta;
+        let blobs = sb
+            .superblock
+            .get_blob_infos()
+            .iter()
+            .map(|blob| ImageInfoBlob {
+                blob_id: blob.blob_id().to_string(),
+                compressed_size: blob.compressed_size(),
+                uncompressed_size: blob.uncompressed_size(),
+                chunk_count: blob.chunk_count(),
+                provenance: blob.provenance().map(RafsBlobProvenance::from),
+            })
+            .collect();
+        let info = ImageInfo {
+            rafs_version: if meta.is_v6() { "v6" } else { "v5" }.to_string(),
+            image_digest: meta.image_digest.map(|d| format!("sha256:{}", d)),
+            chunk_size: meta.chunk_size,
+            inodes_count: meta.inodes_count,
+            blobs,
+        };
+        let mut content = serde_json::to_vec_pretty(&info).map_err(|e| einval!(e))?;
+        content.push(b'\n');
+
+        Ok(ImageInfoFile {
+            content,
+            uid,
+            gid,
+            time,
+            attr_timeout: meta.attr_timeout,
+            entry_timeout: meta.entry_timeout,
+        })
+    }
+
+    /// Get attributes of the virtual directory or file, or None for other inodes.
+    pub fn attr(&self, ino: u64) -> Option<Attr> {
+        let (mode, size, nlink) = match ino {
+            IMAGE_INFO_DIR_INO => (libc::S_IFDIR | 0o555, 4096, 2),
+            IMAGE_INFO_FILE_INO => (libc::S_IFREG | 0o444, self.content.len() as u64, 1),
+            _ => return None,
+        };
+
+        Some(Attr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: self.time,
+            mtime: self.time,
+            ctime: self.time,
+            mode,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    /// Get the lookup entry of the virtual directory or file, or None for other inodes.
+    pub fn entry(&self, ino: u64) -> Option<Entry> {
+        self.attr(ino).map(|attr| Entry {
+            inode: ino,
+            generation: 0,
+            attr: attr.into(),
+            attr_flags: 0,
+            attr_timeout: self.attr_timeout,
+            entry_timeout: self.entry_timeout,
+        })
+    }
+
+    /// Read content of the virtual file in range [offset, offset + size).
+    pub fn read(&self, w: &mut dyn ZeroCopyWriter, size: u32, offset: u64) -> Result<usize> {
+        let len = self.content.len();
+        if offset >= len as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let end = cmp::min(len, start + size as usize);
+        w.write_all(&self.content[start..end])?;
+
+        Ok(end - start)
+    }
+
+    #[cfg(test)]
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
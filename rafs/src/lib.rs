@@ -38,15 +38,18 @@ extern crate nydus_error;
 extern crate storage;
 
 use std::any::Any;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufWriter, Error, Read, Result, Seek, SeekFrom, Write};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
 pub mod fs;
+mod image_info;
 pub mod metadata;
 #[cfg(test)]
 pub mod mock;
+pub mod overlay;
 
 /// Error codes for rafs related operations.
 #[derive(Debug)]
@@ -62,6 +65,20 @@ pub enum RafsError {
     CreateDevice(Error),
     Prefetch(String),
     Configure(String),
+    CheckBlobs(String),
+    VerifyImageDigest(Error),
+    FetchBootstrap(Error),
+}
+
+impl fmt::Display for RafsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LoadConfig(e) => write!(f, "failed to load configuration, {}", e),
+            Self::ParseConfig(e) => write!(f, "failed to parse configuration, {}", e),
+            Self::Configure(s) => write!(f, "invalid configuration, {}", s),
+            _ => write!(f, "{:?}", self),
+        }
+    }
 }
 
 /// Speicialized version of std::result::Result<> for Rafs.
@@ -238,6 +238,7 @@ pub struct CachedInodeV5 {
     i_rdev: u32,
     i_mtime_nsec: u32,
     i_mtime: u64,
+    i_btime: u64,
     i_target: OsString, // for symbol link
     i_xattr: HashMap<OsString, Vec<u8>>,
     i_data: Vec<Arc<CachedChunkInfoV5>>,
@@ -342,6 +343,7 @@ impl CachedInodeV5 {
         self.i_rdev = inode.i_rdev;
         self.i_mtime = inode.i_mtime;
         self.i_mtime_nsec = inode.i_mtime_nsec;
+        self.i_btime = inode.i_btime;
     }
 
     fn add_child(&mut self, child: Arc<CachedInodeV5>) {
@@ -562,6 +564,7 @@ impl RafsInode for CachedInodeV5 {
     impl_getter!(size, i_size, u64);
     impl_getter!(rdev, i_rdev, u32);
     impl_getter!(projid, i_projid, u32);
+    impl_getter!(get_btime, i_btime, u64);
 }
 
 impl RafsV5InodeChunkOps for CachedInodeV5 {
@@ -612,7 +615,7 @@ impl RafsV5InodeOps for CachedInodeV5 {
             i_rdev: self.i_rdev,
             i_mtime: self.i_mtime,
             i_mtime_nsec: self.i_mtime_nsec,
-            i_reserved: [0; 8],
+            i_btime: self.i_btime,
         })
     }
 }
@@ -725,11 +728,13 @@ mod cached_tests {
     use std::sync::Arc;
 
     use nydus_utils::ByteSize;
+    use storage::device::v5::BlobV5ChunkInfo;
     use storage::device::BlobFeatures;
 
     use crate::metadata::cached_v5::{CachedInodeV5, CachedSuperBlockV5};
     use crate::metadata::layout::v5::{
-        rafsv5_align, RafsV5BlobTable, RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeWrapper,
+        rafsv5_align, RafsV5BlobTable, RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeFlags,
+        RafsV5InodeWrapper,
     };
     use crate::metadata::layout::{RafsXAttrs, RAFS_ROOT_INODE};
     use crate::metadata::{RafsInode, RafsStore, RafsSuperMeta};
@@ -933,6 +938,86 @@ mod cached_tests {
         std::fs::remove_file("/tmp/buf_3").unwrap();
     }
 
+    #[test]
+    fn test_alloc_bio_desc_with_hole() {
+        let mut f = OpenOptions::new()
+            .truncate(true)
+            .create(true)
+            .write(true)
+            .read(true)
+            .open("/tmp/buf_4")
+            .unwrap();
+        let mut writer = BufWriter::new(f.try_clone().unwrap());
+        let mut reader = Box::new(f.try_clone().unwrap()) as RafsIoReader;
+        let file_name = OsString::from("c_inode_4");
+        let mut ondisk_inode = RafsV5Inode::new();
+        ondisk_inode.i_name_size = rafsv5_align(file_name.len()) as u16;
+        ondisk_inode.i_ino = 4;
+        ondisk_inode.i_parent = 0;
+        ondisk_inode.i_nlink = 1;
+        ondisk_inode.i_child_count = 2;
+        ondisk_inode.i_mode = libc::S_IFREG;
+        ondisk_inode.i_size = 1024 * 1024 * 4;
+        ondisk_inode.i_flags = RafsV5InodeFlags::HAS_HOLE;
+
+        let inode = RafsV5InodeWrapper {
+            name: file_name.as_os_str(),
+            symlink: None,
+            inode: &ondisk_inode,
+        };
+        inode.store(&mut writer).unwrap();
+
+        // The second and the last chunk are holes.
+        for i in 0..ondisk_inode.i_child_count {
+            let mut chunk = RafsV5ChunkInfo::new();
+            chunk.index = i;
+            chunk.uncompress_size = 1024 * 1024;
+            chunk.uncompress_offset = (i * 1024 * 1024) as u64;
+            chunk.compress_size = chunk.uncompress_size / 2;
+            chunk.compress_offset = ((i * 1024 * 1024) / 2) as u64;
+            chunk.file_offset = (i * 2 * 1024 * 1024) as u64;
+            chunk.store(&mut writer).unwrap();
+        }
+        f.seek(Start(0)).unwrap();
+        let mut meta = Arc::new(RafsSuperMeta::default());
+        Arc::get_mut(&mut meta).unwrap().chunk_size = 1024 * 1024;
+        let mut blob_table = Arc::new(RafsV5BlobTable::new());
+        Arc::get_mut(&mut blob_table).unwrap().add(
+            String::from("123333"),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            BlobFeatures::V5_NO_EXT_BLOB_TABLE,
+            meta.flags,
+        );
+        let mut cached_inode = CachedInodeV5::new(blob_table, meta.clone());
+        cached_inode.load(&meta, &mut reader).unwrap();
+
+        let descs = cached_inode
+            .alloc_bio_vecs(1024 * 1024 + 100, 100, true)
+            .unwrap();
+        assert!(descs.is_empty());
+
+        let descs = cached_inode
+            .alloc_bio_vecs(1024 * 1024 - 100, 1024 * 1024 * 2, true)
+            .unwrap();
+        assert_eq!(descs.len(), 2);
+        assert_eq!(descs[0].bi_size, 100);
+        assert_eq!(descs[0].bi_vec[0].offset, 1024 * 1024 - 100);
+        assert_eq!(descs[1].bi_size, 1024 * 1024 - 100);
+        assert_eq!(descs[1].bi_vec[0].offset, 0);
+        assert_eq!(
+            descs[1].bi_vec[0].chunkinfo.as_v5().unwrap().file_offset(),
+            1024 * 1024 * 2
+        );
+
+        drop(f);
+        std::fs::remove_file("/tmp/buf_4").unwrap();
+    }
+
     #[test]
     fn test_rafsv5_superblock() {
         let md = RafsSuperMeta::default();
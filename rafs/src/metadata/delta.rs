@@ -0,0 +1,433 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transfer bootstraps by pages changed against a parent bootstrap.
+//!
+//! Bootstraps of successive versions of an image are largely identical. A delta records pages of
+//! the target bootstrap which differ from a parent bootstrap, keyed by the superblock version and
+//! the sha256 digest of the parent bootstrap, so clients having the parent bootstrap cached only
+//! need to download the changed pages. A delta consists of a header, followed by changed pages
+//! each prefixed by its page index in little endian:
+//!
+//! | header (128 bytes) | index (u64) | page | index (u64) | page | ... |
+//!
+//! Clients cache fetched bootstraps in a directory as files named by their digests, to be used as
+//! parents of later deltas, and fall back to download the whole bootstrap if the delta can't be
+//! applied.
+
+use std::convert::TryInto;
+use std::fs::{self, OpenOptions};
+use std::io::{Result, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use nydus_utils::digest::{self, RafsDigest};
+use storage::backend::{BlobBackend, BlobReader};
+
+use crate::metadata::{RafsMode, RafsSuper, RAFS_MAX_METADATA_SIZE};
+
+/// Magic number of bootstrap deltas.
+pub const DELTA_MAGIC: u32 = 0x4e59_444c;
+/// Version of the bootstrap delta format.
+pub const DELTA_VERSION: u32 = 1;
+/// Size of pages compared between bootstraps.
+pub const DELTA_PAGE_SIZE: u32 = 0x1000;
+const DELTA_HEADER_SIZE: usize = 128;
+
+/// Header of a bootstrap delta.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BootstrapDeltaHeader {
+    /// Superblock version of the parent bootstrap.
+    pub sb_version: u32,
+    pub page_size: u32,
+    pub parent_size: u64,
+    pub target_size: u64,
+    /// Number of changed pages in the delta.
+    pub page_count: u64,
+    pub parent_digest: RafsDigest,
+    pub target_digest: RafsDigest,
+}
+
+impl BootstrapDeltaHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; DELTA_HEADER_SIZE];
+        buf[0..4].copy_from_slice(&DELTA_MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&DELTA_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.page_size.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.sb_version.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.parent_size.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.target_size.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.page_count.to_le_bytes());
+        buf[40..72].copy_from_slice(&self.parent_digest.data);
+        buf[72..104].copy_from_slice(&self.target_digest.data);
+        buf
+    }
+
+    /// Parse and validate the header at the beginning of a bootstrap delta.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < DELTA_HEADER_SIZE {
+            return Err(einval!("bootstrap delta is too small"));
+        }
+        let u32_at = |off: usize| u32::from_le_bytes(buf[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(buf[off..off + 8].try_into().unwrap());
+        let digest_at = |off: usize| RafsDigest {
+            data: buf[off..off + 32].try_into().unwrap(),
+        };
+
+        if u32_at(0) != DELTA_MAGIC {
+            return Err(einval!("invalid magic of bootstrap delta"));
+        }
+        if u32_at(4) != DELTA_VERSION {
+            return Err(einval!(format!(
+                "unsupported bootstrap delta version {}",
+                u32_at(4)
+            )));
+        }
+        let header = BootstrapDeltaHeader {
+            page_size: u32_at(8),
+            sb_version: u32_at(12),
+            parent_size: u64_at(16),
+            target_size: u64_at(24),
+            page_count: u64_at(32),
+            parent_digest: digest_at(40),
+            target_digest: digest_at(72),
+        };
+        if header.page_size != DELTA_PAGE_SIZE {
+            return Err(einval!(format!(
+                "invalid page size {} of bootstrap delta",
+                header.page_size
+            )));
+        }
+
+        Ok(header)
+    }
+}
+
+/// Generate a delta to transform bootstrap `parent` of superblock version `sb_version` into
+/// bootstrap `target`.
+pub fn generate_delta(parent: &[u8], target: &[u8], sb_version: u32) -> Vec<u8> {
+    let page_size = DELTA_PAGE_SIZE as usize;
+    let mut pages = Vec::new();
+    let mut page_count = 0u64;
+
+    for (idx, page) in target.chunks(page_size).enumerate() {
+        let start = idx * page_size;
+        let end = std::cmp::min(parent.len(), start + page_size);
+        let parent_page = parent.get(start..end).unwrap_or(&[]);
+        if !page_equal(page, parent_page) {
+            pages.extend_from_slice(&(idx as u64).to_le_bytes());
+            pages.extend_from_slice(page);
+            pages.resize(pages.len() + page_size - page.len(), 0);
+            page_count += 1;
+        }
+    }
+
+    let header = BootstrapDeltaHeader {
+        sb_version,
+        page_size: DELTA_PAGE_SIZE,
+        parent_size: parent.len() as u64,
+        target_size: target.len() as u64,
+        page_count,
+        parent_digest: RafsDigest::from_buf(parent, digest::Algorithm::Sha256),
+        target_digest: RafsDigest::from_buf(target, digest::Algorithm::Sha256),
+    };
+    let mut delta = header.to_bytes();
+    delta.append(&mut pages);
+
+    delta
+}
+
+// Compare pages as if both are padded by zeros to the page size.
+fn page_equal(a: &[u8], b: &[u8]) -> bool {
+    let len = std::cmp::min(a.len(), b.len());
+    a[..len] == b[..len] && a[len..].iter().all(|v| *v == 0) && b[len..].iter().all(|v| *v == 0)
+}
+
+/// Apply the delta to bootstrap `parent`, and return content of the target bootstrap.
+pub fn apply_delta(parent: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let header = BootstrapDeltaHeader::from_bytes(delta)?;
+    let page_size = header.page_size as usize;
+    let entry_size = 8 + page_size as u64;
+    if Some(delta.len() as u64 - DELTA_HEADER_SIZE as u64)
+        != header.page_count.checked_mul(entry_size)
+    {
+        return Err(einval!("bootstrap delta is truncated"));
+    }
+    if header.parent_size != parent.len() as u64
+        || header.parent_digest != RafsDigest::from_buf(parent, digest::Algorithm::Sha256)
+    {
+        return Err(einval!("parent bootstrap doesn't match the delta"));
+    }
+    // The target can't grow beyond the parent and all pages in the delta, so a corrupted or
+    // malicious header can't make us allocate huge buffers.
+    let max_size = header
+        .page_count
+        .checked_mul(page_size as u64)
+        .and_then(|v| v.checked_add(header.parent_size))
+        .map(|v| std::cmp::min(v, RAFS_MAX_METADATA_SIZE as u64))
+        .unwrap_or(RAFS_MAX_METADATA_SIZE as u64);
+    if header.target_size > max_size {
+        return Err(einval!(format!(
+            "target size {} of bootstrap delta exceeds {}",
+            header.target_size, max_size
+        )));
+    }
+
+    let pages = (header.target_size + page_size as u64 - 1) / page_size as u64;
+    let target_len = (pages as usize)
+        .checked_mul(page_size)
+        .ok_or_else(|| einval!("target size of bootstrap delta overflows"))?;
+    let mut target = vec![0u8; target_len];
+    let len = std::cmp::min(parent.len(), target.len());
+    target[..len].copy_from_slice(&parent[..len]);
+    for entry in delta[DELTA_HEADER_SIZE..].chunks(entry_size as usize) {
+        let idx = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        if idx >= pages {
+            return Err(einval!(format!(
+                "page {} of bootstrap delta is out of range",
+                idx
+            )));
+        }
+        let start = idx as usize * page_size;
+        target[start..start + page_size].copy_from_slice(&entry[8..]);
+    }
+    target.truncate(header.target_size as usize);
+
+    if header.target_digest != RafsDigest::from_buf(&target, digest::Algorithm::Sha256) {
+        return Err(einval!(
+            "digest of patched bootstrap doesn't match the delta"
+        ));
+    }
+
+    Ok(target)
+}
+
+/// Get the superblock version of the bootstrap file.
+pub fn bootstrap_version(path: &Path) -> Result<u32> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| einval!(format!("invalid bootstrap path {:?}", path)))?;
+    let sb = RafsSuper::load_from_metadata(path, RafsMode::Direct, false)?;
+    Ok(sb.meta.version)
+}
+
+/// How a bootstrap is fetched from the storage backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BootstrapFetchMethod {
+    /// Patched a cached parent bootstrap with a delta.
+    Delta,
+    /// Downloaded the whole bootstrap.
+    Full,
+}
+
+/// Fetch bootstrap object `object_id` from the storage backend into `path`.
+///
+/// If `delta_id` is specified, the delta object is applied to the parent bootstrap cached in
+/// `cache_dir` instead of downloading the whole bootstrap, if the parent is cached. The fetched
+/// bootstrap is cached in `cache_dir` too, as parent of later deltas.
+pub fn fetch_bootstrap(
+    backend: &dyn BlobBackend,
+    object_id: &str,
+    delta_id: Option<&str>,
+    cache_dir: &Path,
+    path: &Path,
+) -> Result<BootstrapFetchMethod> {
+    fs::create_dir_all(cache_dir)?;
+
+    let delta = delta_id.map(|id| fetch_by_delta(backend, id, cache_dir));
+    let (content, method) = match delta {
+        Some(Ok(content)) => (content, BootstrapFetchMethod::Delta),
+        Some(Err(e)) => {
+            warn!(
+                "failed to fetch bootstrap {} by delta, download it directly, {}",
+                object_id, e
+            );
+            (read_object(backend, object_id)?, BootstrapFetchMethod::Full)
+        }
+        None => (read_object(backend, object_id)?, BootstrapFetchMethod::Full),
+    };
+
+    let digest = RafsDigest::from_buf(&content, digest::Algorithm::Sha256);
+    write_file(&cache_dir.join(digest.to_string()), &content)?;
+    write_file(path, &content)?;
+
+    Ok(method)
+}
+
+fn fetch_by_delta(backend: &dyn BlobBackend, delta_id: &str, cache_dir: &Path) -> Result<Vec<u8>> {
+    let reader = get_reader(backend, delta_id)?;
+    let mut buf = vec![0u8; DELTA_HEADER_SIZE];
+    read_at(reader.as_ref(), &mut buf, 0)?;
+    let header = BootstrapDeltaHeader::from_bytes(&buf)?;
+
+    // Check the cached parent before downloading the whole delta.
+    let parent_path = cache_dir.join(header.parent_digest.to_string());
+    if !parent_path.exists() {
+        return Err(enoent!(format!(
+            "parent bootstrap {} isn't cached",
+            header.parent_digest
+        )));
+    }
+    let version = bootstrap_version(&parent_path)?;
+    if version != header.sb_version {
+        return Err(einval!(format!(
+            "superblock version {:x} of parent bootstrap doesn't match {:x}",
+            version, header.sb_version
+        )));
+    }
+
+    let parent = fs::read(&parent_path)?;
+    let delta = read_all(reader.as_ref())?;
+    apply_delta(&parent, &delta)
+}
+
+fn get_reader(backend: &dyn BlobBackend, object_id: &str) -> Result<Arc<dyn BlobReader>> {
+    backend
+        .get_reader(object_id)
+        .map_err(|e| eio!(format!("failed to get reader of {}, {:?}", object_id, e)))
+}
+
+fn read_object(backend: &dyn BlobBackend, object_id: &str) -> Result<Vec<u8>> {
+    read_all(get_reader(backend, object_id)?.as_ref())
+}
+
+fn read_all(reader: &dyn BlobReader) -> Result<Vec<u8>> {
+    let size = reader
+        .blob_size()
+        .map_err(|e| eio!(format!("failed to get object size, {:?}", e)))?;
+    let mut buf = vec![0u8; size as usize];
+    read_at(reader, &mut buf, 0)?;
+    Ok(buf)
+}
+
+fn read_at(reader: &dyn BlobReader, buf: &mut [u8], offset: u64) -> Result<()> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        let size = reader
+            .read(&mut buf[pos..], offset + pos as u64)
+            .map_err(|e| eio!(format!("failed to read object, {:?}", e)))?;
+        if size == 0 {
+            return Err(eio!("object is truncated"));
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+// Write the file by renaming a temporary file, so it's always complete.
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = path.with_file_name(format!(
+        "{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .and_then(|mut f| f.write_all(content).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use storage::factory::BlobFactory;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let parent: Vec<u8> = (0..0x5000u32).map(|v| v as u8).collect();
+        let mut target = parent.clone();
+        target[0x1010] = 0xff;
+        target.truncate(0x4800);
+        target.extend_from_slice(&[0x5a; 0x1000]);
+
+        let delta = generate_delta(&parent, &target, 0x500);
+        let header = BootstrapDeltaHeader::from_bytes(&delta).unwrap();
+        assert_eq!(header.sb_version, 0x500);
+        assert_eq!(header.target_size, 0x5800);
+        // Page 1 is modified, pages 4 and 5 are appended.
+        assert_eq!(header.page_count, 3);
+        assert_eq!(apply_delta(&parent, &delta).unwrap(), target);
+
+        // Shrinking bootstraps only needs the header.
+        let delta = generate_delta(&parent, &parent[..0x2000], 0x500);
+        assert_eq!(delta.len(), DELTA_HEADER_SIZE);
+        assert_eq!(apply_delta(&parent, &delta).unwrap(), &parent[..0x2000]);
+
+        let delta = generate_delta(&parent, &target, 0x500);
+        assert!(apply_delta(&target, &delta).is_err());
+        assert!(apply_delta(&parent, &delta[..delta.len() - 1]).is_err());
+        let mut corrupted = delta.clone();
+        corrupted[DELTA_HEADER_SIZE + 8] ^= 0xff;
+        assert!(apply_delta(&parent, &corrupted).is_err());
+        corrupted = delta.clone();
+        corrupted[0] = 0;
+        assert!(BootstrapDeltaHeader::from_bytes(&corrupted).is_err());
+
+        // Target size beyond the parent and pages in the delta is rejected before allocating.
+        corrupted = delta;
+        corrupted[24..32].copy_from_slice(&0x9000u64.to_le_bytes());
+        assert!(apply_delta(&parent, &corrupted).is_err());
+        corrupted[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(apply_delta(&parent, &corrupted).is_err());
+    }
+
+    #[test]
+    fn test_fetch_bootstrap() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let parent_path = PathBuf::from(root_dir).join("../tests/texture/bootstrap/image_v2.boot");
+        let parent = fs::read(&parent_path).unwrap();
+        let mut target = parent.clone();
+        target.extend_from_slice(&[0x5a; 0x1800]);
+
+        let backend_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let delta = generate_delta(&parent, &target, bootstrap_version(&parent_path).unwrap());
+        fs::write(backend_dir.as_path().join("target"), &target).unwrap();
+        fs::write(backend_dir.as_path().join("delta"), &delta).unwrap();
+        let config = serde_json::from_value(serde_json::json!({
+            "type": "localfs",
+            "config": {"dir": backend_dir.as_path().to_str().unwrap()},
+        }))
+        .unwrap();
+        let backend = BlobFactory::new_backend(config, "target").unwrap();
+        let output = cache_dir.as_path().join("bootstrap");
+
+        // The parent isn't cached yet.
+        let method = fetch_bootstrap(
+            backend.as_ref(),
+            "target",
+            Some("delta"),
+            cache_dir.as_path(),
+            &output,
+        )
+        .unwrap();
+        assert_eq!(method, BootstrapFetchMethod::Full);
+        assert_eq!(fs::read(&output).unwrap(), target);
+
+        let digest = RafsDigest::from_buf(&parent, digest::Algorithm::Sha256);
+        fs::write(cache_dir.as_path().join(digest.to_string()), &parent).unwrap();
+        fs::remove_file(&output).unwrap();
+        let method = fetch_bootstrap(
+            backend.as_ref(),
+            "target",
+            Some("delta"),
+            cache_dir.as_path(),
+            &output,
+        )
+        .unwrap();
+        assert_eq!(method, BootstrapFetchMethod::Delta);
+        assert_eq!(fs::read(&output).unwrap(), target);
+        let digest = RafsDigest::from_buf(&target, digest::Algorithm::Sha256);
+        assert!(cache_dir.as_path().join(digest.to_string()).exists());
+    }
+}
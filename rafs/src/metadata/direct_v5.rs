@@ -27,6 +27,7 @@ use std::ops::Deref;
 use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
 use std::slice;
 use std::sync::Arc;
+use std::thread;
 
 use arc_swap::{ArcSwap, Guard};
 use nydus_utils::digest::{Algorithm, RafsDigest};
@@ -43,8 +44,8 @@ use crate::metadata::layout::{
     bytes_to_os_str, parse_xattr_names, parse_xattr_value, MetaRange, XattrName, XattrValue,
 };
 use crate::metadata::{
-    Attr, Entry, Inode, RafsInode, RafsSuperBlobs, RafsSuperBlock, RafsSuperInodes, RafsSuperMeta,
-    RAFS_INODE_BLOCKSIZE, RAFS_MAX_METADATA_SIZE, RAFS_MAX_NAME,
+    populate_mapping, Attr, Entry, Inode, RafsInode, RafsSuperBlobs, RafsSuperBlock,
+    RafsSuperInodes, RafsSuperMeta, RAFS_INODE_BLOCKSIZE, RAFS_MAX_METADATA_SIZE, RAFS_MAX_NAME,
 };
 use crate::{RafsError, RafsIoReader, RafsResult};
 
@@ -397,6 +398,20 @@ impl RafsSuperBlock for DirectSuperBlockV5 {
     fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
         self.state.load().blob_table.entries.clone()
     }
+
+    fn prefetch_metadata(&self) {
+        let sb = self.clone();
+        let ret = thread::Builder::new()
+            .name("meta_prefetch".to_string())
+            .spawn(move || {
+                // Hold the state to keep the bootstrap mapped until done.
+                let state = sb.state.load();
+                populate_mapping(state.base, state.size);
+            });
+        if let Err(e) = ret {
+            warn!("failed to spawn metadata prefetch thread, {}", e);
+        }
+    }
 }
 
 pub struct OndiskInodeWrapper {
@@ -429,24 +444,35 @@ impl OndiskInodeWrapper {
         bytes_to_os_str(name)
     }
 
-    #[allow(clippy::cast_ptr_alignment)]
-    fn get_xattr_size(&self) -> Result<usize> {
-        let state = self.state();
-        let inode = self.inode(state.deref());
+    /// Get offset and size of the extended attribute table following the inode.
+    ///
+    /// Returns `(offset, size, aligned_size)`, where `offset` points to the content of the table,
+    /// and `aligned_size` includes the table header. The table header and content are checked
+    /// against the mapped bootstrap before use, so truncated bootstraps are detected on access.
+    fn xattr_table_range(
+        &self,
+        state: &DirectMappingState,
+        inode: &RafsV5Inode,
+    ) -> Result<(usize, usize, usize)> {
+        let offset = self.offset + inode.size();
+        if !inode.has_xattr() {
+            return Ok((offset, 0, 0));
+        }
 
-        if inode.has_xattr() {
-            let offset = self.offset + inode.size();
-            state.validate_range(offset, size_of::<RafsV5XAttrsTable>())?;
-            unsafe {
-                let xattrs = state.base.add(offset) as *const RafsV5XAttrsTable;
-                Ok(size_of::<RafsV5XAttrsTable>() + (*xattrs).aligned_size())
-            }
-        } else {
-            Ok(0)
+        let xattrs = state.cast_to_ref::<RafsV5XAttrsTable>(state.base, offset)?;
+        let size = xattrs.size();
+        if size > state.size {
+            return Err(einval!(format!(
+                "invalid xattr table size {}, ino {}",
+                size, inode.i_ino
+            )));
         }
+        let aligned_size = size_of::<RafsV5XAttrsTable>() + xattrs.aligned_size();
+        state.validate_range(offset, aligned_size)?;
+
+        Ok((offset + size_of::<RafsV5XAttrsTable>(), size, aligned_size))
     }
 
-    #[allow(clippy::cast_ptr_alignment)]
     fn get_xattr_data(&self) -> Result<(&[u8], usize)> {
         let state = self.state();
         let inode = self.inode(state.deref());
@@ -455,20 +481,8 @@ impl OndiskInodeWrapper {
             return Ok((&[], 0));
         }
 
-        let offset = self.offset + inode.size();
-        let start = unsafe { state.base.add(offset) };
-        let xattrs = start as *const RafsV5XAttrsTable;
-        let xattr_size = unsafe { (*xattrs).size() };
-        let xattrs_aligned_size = unsafe { (*xattrs).aligned_size() };
-
-        state.validate_range(offset, size_of::<RafsV5XAttrsTable>() + xattrs_aligned_size)?;
-
-        let xattr_data = unsafe {
-            slice::from_raw_parts(
-                start.wrapping_add(size_of::<RafsV5XAttrsTable>()),
-                xattr_size,
-            )
-        };
+        let (offset, xattr_size, _) = self.xattr_table_range(state.deref(), inode)?;
+        let xattr_data = unsafe { slice::from_raw_parts(state.base.add(offset), xattr_size) };
 
         Ok((xattr_data, xattr_size))
     }
@@ -481,14 +495,9 @@ impl OndiskInodeWrapper {
             return Err(enoent!("invalid chunk info"));
         }
 
-        let mut offset = self.offset + inode.size();
-        if inode.has_xattr() {
-            unsafe {
-                let xattrs = state.base.add(offset) as *const RafsV5XAttrsTable;
-                offset += size_of::<RafsV5XAttrsTable>() + (*xattrs).aligned_size();
-            }
-        }
-        offset += size_of::<RafsV5ChunkInfo>() * idx as usize;
+        let (_, _, xattr_size) = self.xattr_table_range(state.deref(), inode)?;
+        let offset =
+            self.offset + inode.size() + xattr_size + size_of::<RafsV5ChunkInfo>() * idx as usize;
 
         let chunk = state.cast_to_ref::<RafsV5ChunkInfo>(state.base, offset)?;
         let wrapper = DirectChunkInfoV5::new(chunk, self.mapping.clone(), offset);
@@ -511,6 +520,9 @@ impl RafsInode for OndiskInodeWrapper {
         // * - name_size must be less than 255. Due to alignment, the check is not so strict.
         // * - name_size and symlink_size must be correctly aligned.
         // Should we store raw size instead of aligned size for name and symlink?
+        //
+        // Only the fixed part of the inode is validated here, variable-length parts such as the
+        // xattr table and chunk array are resolved and checked on demand when accessed.
         if inode.i_ino > max_inode
             || inode.i_nlink == 0
             || inode.i_name_size as usize > (RAFS_MAX_NAME + 1)
@@ -521,11 +533,7 @@ impl RafsInode for OndiskInodeWrapper {
             )));
         }
 
-        let xattr_size = if inode.has_xattr() {
-            self.get_xattr_size()?
-        } else {
-            0
-        };
+        state.validate_range(self.offset, inode.size())?;
 
         if inode.is_reg() {
             let chunks = (inode.i_size + chunk_size - 1) / chunk_size;
@@ -535,18 +543,12 @@ impl RafsInode for OndiskInodeWrapper {
                     inode.i_ino, chunks, inode.i_child_count,
                 )));
             }
-            let size = inode.size()
-                + xattr_size
-                + inode.i_child_count as usize * size_of::<RafsV5ChunkInfo>();
-            state.validate_range(self.offset, size)?;
         } else if inode.is_dir() {
             if (inode.i_child_index as Inode) < inode.i_ino
                 || inode.i_child_count as u64 >= max_inode
             {
                 return Err(einval!("invalid directory"));
             }
-            let size = inode.size() + xattr_size;
-            state.validate_range(self.offset, size)?;
         } else if inode.is_symlink() {
             if inode.i_symlink_size == 0 {
                 return Err(einval!("invalid symlink target"));
@@ -595,13 +597,16 @@ impl RafsInode for OndiskInodeWrapper {
 
     /// Get symlink target of the inode.
     ///
-    /// # Safety
-    /// It depends on Self::validate() to ensure valid memory layout.
+    /// The symlink target is resolved from the mapped bootstrap on demand.
     fn get_symlink(&self) -> Result<OsString> {
         let state = self.state();
         let inode = self.inode(state.deref());
+        if !inode.is_symlink() {
+            return Err(einval!("inode is not a symlink"));
+        }
         let offset =
             self.offset + size_of::<RafsV5Inode>() + rafsv5_align(inode.i_name_size as usize);
+        state.validate_range(offset, inode.i_symlink_size as usize)?;
         // TODO: the symlink is aligned, should we store raw size?
         let symlink = unsafe {
             let start = state.base.add(offset);
@@ -785,6 +790,7 @@ impl RafsInode for OndiskInodeWrapper {
     impl_inode_getter!(size, i_size, u64);
     impl_inode_getter!(rdev, i_rdev, u32);
     impl_inode_getter!(projid, i_projid, u32);
+    impl_inode_getter!(get_btime, i_btime, u64);
     impl_inode_getter!(get_name_size, i_name_size, u16);
     impl_inode_getter!(get_symlink_size, i_symlink_size, u16);
 }
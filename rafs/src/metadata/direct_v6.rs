@@ -17,21 +17,30 @@
 /// The bootstrap file may be provided by untrusted parties, so we must ensure strong validations
 /// before making use of any bootstrap, especially we are using them in memory-mapped mode. The
 /// rule is to call validate() after creating any data structure from the on-disk bootstrap.
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{Result, SeekFrom};
-// use std::mem::size_of;
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
 use std::sync::Arc;
+use std::thread;
 
 use arc_swap::ArcSwap;
 
-use crate::metadata::layout::v6::RafsV6BlobTable;
+use crate::metadata::layout::v6::{
+    rafsv6_find_dirent, RafsV6BlobTable, RafsV6DirentIndex, RafsV6InodeCompact,
+    RafsV6InodeExtended, RafsV6OndiskInodeTrait, EROFS_BLOCK_SIZE, EROFS_INODE_FLAT_INLINE,
+    EROFS_INODE_FLAT_PLAIN, EROFS_INODE_LAYOUT_EXTENDED, EROFS_INODE_SLOT_SIZE,
+};
 // use crate::metadata::layout::MetaRange;
 use crate::metadata::{
-    Inode, RafsInode, RafsSuperBlobs, RafsSuperBlock, RafsSuperInodes, RafsSuperMeta,
+    populate_mapping, Inode, RafsInode, RafsSuperBlobs, RafsSuperBlock, RafsSuperInodes,
+    RafsSuperMeta,
 };
 use crate::{RafsError, RafsIoReader, RafsResult};
 use nydus_utils::digest::Algorithm;
+use nydus_utils::div_round_up;
 use storage::device::BlobInfo;
 use storage::utils::readahead;
 
@@ -45,6 +54,7 @@ use storage::utils::readahead;
 struct DirectMappingState {
     meta: RafsSuperMeta,
     blob_table: Arc<RafsV6BlobTable>,
+    dirent_index: Arc<RafsV6DirentIndex>,
     base: *const u8,
     end: *const u8,
     size: usize,
@@ -57,6 +67,7 @@ impl DirectMappingState {
         DirectMappingState {
             meta: *meta,
             blob_table: Arc::new(RafsV6BlobTable::default()),
+            dirent_index: Arc::new(RafsV6DirentIndex::default()),
             fd: -1,
             base: std::ptr::null(),
             end: std::ptr::null(),
@@ -66,6 +77,82 @@ impl DirectMappingState {
         }
     }
 
+    /// Get `len` bytes at `offset` into the mapped bootstrap.
+    fn slice(&self, offset: u64, len: usize) -> Result<&[u8]> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size as u64 => {
+                // Safe because the range has been validated to be within the mapped area.
+                Ok(unsafe { std::slice::from_raw_parts(self.base.add(offset as usize), len) })
+            }
+            _ => Err(einval!(format!(
+                "invalid metadata range 0x{:x}/0x{:x}",
+                offset, len
+            ))),
+        }
+    }
+
+    /// Load the on-disk inode at `nid`, returning the inode and its offset.
+    fn inode(&self, nid: u64) -> Result<(Box<dyn RafsV6OndiskInodeTrait>, u64)> {
+        let offset = nid
+            .checked_mul(EROFS_INODE_SLOT_SIZE as u64)
+            .and_then(|v| v.checked_add(self.meta.meta_addr))
+            .ok_or_else(|| einval!(format!("invalid nid {}", nid)))?;
+        let mut inode = RafsV6InodeCompact::new();
+        inode
+            .as_mut()
+            .copy_from_slice(self.slice(offset, size_of::<RafsV6InodeCompact>())?);
+        if inode.format() & 0x1 == EROFS_INODE_LAYOUT_EXTENDED {
+            let mut inode = RafsV6InodeExtended::new();
+            inode
+                .as_mut()
+                .copy_from_slice(self.slice(offset, size_of::<RafsV6InodeExtended>())?);
+            Ok((Box::new(inode), offset))
+        } else {
+            Ok((Box::new(inode), offset))
+        }
+    }
+
+    /// Find the child named `name` of directory `parent`, returning nid of the child.
+    ///
+    /// The directory name index is consulted before scanning dirents, so lookups of missing
+    /// entries in large directories fail fast without touching dirent blocks.
+    fn lookup(&self, parent: u64, name: &OsStr) -> Result<u64> {
+        if !self.dirent_index.may_contain(parent, name) {
+            return Err(enoent!());
+        }
+
+        let (inode, offset) = self.inode(parent)?;
+        if inode.mode() as u32 & libc::S_IFMT != libc::S_IFDIR {
+            return Err(enotdir!());
+        }
+        let size = inode.size();
+        let blocks = div_round_up(size, EROFS_BLOCK_SIZE);
+        for idx in 0..blocks {
+            let len = std::cmp::min(size - idx * EROFS_BLOCK_SIZE, EROFS_BLOCK_SIZE);
+            // The tail block of an inline directory follows the inode and its xattrs.
+            let block_offset = match inode.data_layout() {
+                EROFS_INODE_FLAT_INLINE if idx == blocks - 1 => {
+                    offset + inode.size_with_xattr() as u64
+                }
+                EROFS_INODE_FLAT_PLAIN | EROFS_INODE_FLAT_INLINE => {
+                    (inode.union() as u64 + idx) * EROFS_BLOCK_SIZE
+                }
+                layout => {
+                    return Err(einval!(format!(
+                        "invalid data layout {} of directory",
+                        layout
+                    )))
+                }
+            };
+            let block = self.slice(block_offset, len as usize)?;
+            if let Some(nid) = rafsv6_find_dirent(block, name.as_bytes())? {
+                return Ok(nid);
+            }
+        }
+
+        Err(enoent!())
+    }
+
     // /// Mmap to bootstrap ondisk data directly.
     // fn cast_to_ref<T>(&self, base: *const u8, offset: usize) -> Result<&T> {
     //     let start = base.wrapping_add(offset);
@@ -204,11 +291,19 @@ impl DirectSuperBlockV6 {
         r.seek(SeekFrom::Start(meta.blob_table_offset))?;
         blob_table.load(r, meta.blob_table_size, meta.chunk_size, meta.flags)?;
 
+        // Load directory name index if the bootstrap is built with it.
+        let mut dirent_index = RafsV6DirentIndex::new();
+        if meta.has_dirent_index() {
+            r.seek(SeekFrom::Start(meta.dirent_index_offset))?;
+            dirent_index.load(r, meta.dirent_index_size)?;
+        }
+
         let validate_digest = old_state.validate_digest;
 
         let state = DirectMappingState {
             meta: old_state.meta,
             blob_table: Arc::new(blob_table),
+            dirent_index: Arc::new(dirent_index),
             fd: file.into_raw_fd(),
             base,
             end,
@@ -268,4 +363,22 @@ impl RafsSuperBlock for DirectSuperBlockV6 {
     fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>> {
         self.state.load().blob_table.entries.clone()
     }
+
+    fn lookup_child(&self, parent: u64, name: &OsStr) -> Result<u64> {
+        self.state.load().lookup(parent, name)
+    }
+
+    fn prefetch_metadata(&self) {
+        let sb = self.clone();
+        let ret = thread::Builder::new()
+            .name("meta_prefetch".to_string())
+            .spawn(move || {
+                // Hold the state to keep the bootstrap mapped until done.
+                let state = sb.state.load();
+                populate_mapping(state.base, state.size);
+            });
+        if let Err(e) = ret {
+            warn!("failed to spawn metadata prefetch thread, {}", e);
+        }
+    }
 }
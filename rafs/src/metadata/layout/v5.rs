@@ -46,6 +46,7 @@ use nydus_utils::digest::{self, DigestHasher, RafsDigest};
 use nydus_utils::ByteSize;
 use storage::compress;
 use storage::device::{BlobFeatures, BlobIoDesc, BlobIoVec};
+use storage::meta::BlobProvenance;
 
 use crate::metadata::layout::{bytes_to_os_str, MetaRange, RafsXAttrs, RAFS_SUPER_VERSION_V5};
 use crate::metadata::{
@@ -65,8 +66,9 @@ pub(crate) const RAFSV5_SUPERBLOCK_SIZE: usize = 8192;
 pub(crate) const RAFSV5_EXT_BLOB_ENTRY_SIZE: usize = 64;
 
 const RAFSV5_SUPER_MAGIC: u32 = 0x5241_4653;
-const RAFSV5_SUPERBLOCK_RESERVED_SIZE: usize = RAFSV5_SUPERBLOCK_SIZE - 80;
-const RAFSV5_EXT_BLOB_RESERVED_SIZE: usize = RAFSV5_EXT_BLOB_ENTRY_SIZE - 24;
+const RAFSV5_SUPERBLOCK_RESERVED_SIZE: usize = RAFSV5_SUPERBLOCK_SIZE - 128;
+/// Offset of the image digest field in Rafs v5 super block.
+pub const RAFSV5_IMAGE_DIGEST_OFFSET: u64 = 80;
 
 /// Trait to get information about a Rafs v5 inode.
 pub(crate) trait RafsV5InodeOps {
@@ -113,6 +115,7 @@ impl From<RafsSuperFlags> for compress::Algorithm {
             x if x.contains(RafsSuperFlags::COMPRESS_NONE) => compress::Algorithm::None,
             x if x.contains(RafsSuperFlags::COMPRESS_LZ4_BLOCK) => compress::Algorithm::Lz4Block,
             x if x.contains(RafsSuperFlags::COMPRESS_GZIP) => compress::Algorithm::GZip,
+            x if x.contains(RafsSuperFlags::COMPRESS_ZSTD) => compress::Algorithm::Zstd,
             _ => compress::Algorithm::Lz4Block,
         }
     }
@@ -124,6 +127,7 @@ impl From<compress::Algorithm> for RafsSuperFlags {
             compress::Algorithm::None => RafsSuperFlags::COMPRESS_NONE,
             compress::Algorithm::Lz4Block => RafsSuperFlags::COMPRESS_LZ4_BLOCK,
             compress::Algorithm::GZip => RafsSuperFlags::COMPRESS_GZIP,
+            compress::Algorithm::Zstd => RafsSuperFlags::COMPRESS_ZSTD,
         }
     }
 }
@@ -161,6 +165,13 @@ pub struct RafsV5SuperBlock {
     s_extended_blob_table_entries: u32, // 72 bytes
     /// Extended Blob Table
     s_extended_blob_table_offset: u64, // 80 bytes --- reduce me from `RAFS_SUPERBLOCK_RESERVED_SIZE`
+    /// Sha256 digest of the bootstrap with this field zeroed, all zero if not calculated.
+    s_image_digest: [u8; 32], // 112 bytes
+    /// V5: Offset of blob prefetch range table
+    s_blob_prefetch_table_offset: u64,
+    /// V5: Entries of blob prefetch range table
+    s_blob_prefetch_table_entries: u32,
+    s_reserved2: u32, // 128 bytes
     /// Unused area
     s_reserved: [u8; RAFSV5_SUPERBLOCK_RESERVED_SIZE],
 }
@@ -266,6 +277,7 @@ impl RafsV5SuperBlock {
         self.s_flags &= !RafsSuperFlags::COMPRESS_NONE.bits();
         self.s_flags &= !RafsSuperFlags::COMPRESS_LZ4_BLOCK.bits();
         self.s_flags &= !RafsSuperFlags::COMPRESS_GZIP.bits();
+        self.s_flags &= !RafsSuperFlags::COMPRESS_ZSTD.bits();
         self.s_flags |= c.bits();
     }
 
@@ -288,6 +300,11 @@ impl RafsV5SuperBlock {
         self.s_flags |= RafsSuperFlags::HAS_XATTR.bits();
     }
 
+    /// Mark the filesystem as built without data blobs.
+    pub fn set_metadata_only(&mut self) {
+        self.s_flags |= RafsSuperFlags::METADATA_ONLY.bits();
+    }
+
     impl_pub_getter_setter!(magic, set_magic, s_magic, u32);
     impl_pub_getter_setter!(version, set_version, s_fs_version, u32);
     impl_pub_getter_setter!(sb_size, set_sb_size, s_sb_size, u32);
@@ -338,6 +355,24 @@ impl RafsV5SuperBlock {
         u32
     );
 
+    impl_pub_getter_setter!(
+        blob_prefetch_table_offset,
+        set_blob_prefetch_table_offset,
+        s_blob_prefetch_table_offset,
+        u64
+    );
+    impl_pub_getter_setter!(
+        blob_prefetch_table_entries,
+        set_blob_prefetch_table_entries,
+        s_blob_prefetch_table_entries,
+        u32
+    );
+
+    /// Get the image digest, all zero if not calculated.
+    pub fn image_digest(&self) -> [u8; 32] {
+        self.s_image_digest
+    }
+
     /// Load a super block from a `RafsIoReader` object.
     pub fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
@@ -379,6 +414,10 @@ impl Default for RafsV5SuperBlock {
             s_blob_table_offset: u64::to_le(0),
             s_extended_blob_table_offset: u64::to_le(0),
             s_extended_blob_table_entries: u32::to_le(0),
+            s_image_digest: [0u8; 32],
+            s_blob_prefetch_table_offset: u64::to_le(0),
+            s_blob_prefetch_table_entries: u32::to_le(0),
+            s_reserved2: u32::to_le(0),
             s_reserved: [0u8; RAFSV5_SUPERBLOCK_RESERVED_SIZE],
         }
     }
@@ -553,6 +592,90 @@ impl RafsV5PrefetchTable {
     }
 }
 
+/// Range of compressed data in a blob to prefetch, 16 bytes on disk.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RafsV5BlobPrefetchRange {
+    /// Offset of the range into the compressed blob.
+    offset: u64,
+    /// Size of the range.
+    size: u32,
+    /// Index of the blob in the blob table.
+    blob_index: u32,
+}
+
+impl RafsV5BlobPrefetchRange {
+    /// Create a new instance of `RafsV5BlobPrefetchRange`.
+    pub fn new(blob_index: u32, offset: u64, size: u32) -> Self {
+        RafsV5BlobPrefetchRange {
+            offset: u64::to_le(offset),
+            size: u32::to_le(size),
+            blob_index: u32::to_le(blob_index),
+        }
+    }
+
+    impl_pub_getter_setter!(offset, set_offset, offset, u64);
+    impl_pub_getter_setter!(size, set_size, size, u32);
+    impl_pub_getter_setter!(blob_index, set_blob_index, blob_index, u32);
+}
+
+/// Rafs v5 blob prefetch range table.
+///
+/// Ranges of blobs covering data of prefetched files, sorted by blob index and offset, so they
+/// can be prefetched from blobs by a small number of large reads when mounting.
+#[derive(Clone, Default)]
+pub struct RafsV5BlobPrefetchTable {
+    /// List of ranges to prefetch.
+    pub ranges: Vec<RafsV5BlobPrefetchRange>,
+}
+
+impl RafsV5BlobPrefetchTable {
+    /// Create a new instance of `RafsV5BlobPrefetchTable`.
+    pub fn new() -> Self {
+        RafsV5BlobPrefetchTable { ranges: Vec::new() }
+    }
+
+    /// Get content size of the blob prefetch range table.
+    pub fn size(&self) -> usize {
+        self.len() * size_of::<RafsV5BlobPrefetchRange>()
+    }
+
+    /// Get number of entries in the blob prefetch range table.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Check whether the blob prefetch range table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Add a range into the blob prefetch range table.
+    pub fn add_entry(&mut self, blob_index: u32, offset: u64, size: u32) {
+        self.ranges
+            .push(RafsV5BlobPrefetchRange::new(blob_index, offset, size));
+    }
+
+    /// Store the blob prefetch range table to a writer.
+    pub fn store(&self, w: &mut dyn RafsIoWrite) -> Result<usize> {
+        let (_, data, _) = unsafe { self.ranges.align_to::<u8>() };
+        w.write_all(data)?;
+
+        Ok(data.len())
+    }
+
+    /// Load a blob prefetch range table from a reader.
+    pub fn load_from(&mut self, r: &mut RafsIoReader, offset: u64, entries: usize) -> Result<()> {
+        self.ranges = vec![RafsV5BlobPrefetchRange::default(); entries];
+
+        let (_, data, _) = unsafe { self.ranges.align_to_mut::<u8>() };
+        r.seek_to_offset(offset)?;
+        r.read_exact(data)?;
+
+        Ok(())
+    }
+}
+
 /// Rafs v5 blob description table.
 #[derive(Clone, Debug, Default)]
 pub struct RafsV5BlobTable {
@@ -630,6 +753,19 @@ impl RafsV5BlobTable {
         Ok(self.entries[blob_index as usize].clone())
     }
 
+    /// Record provenance information of a blob added into the table.
+    pub fn set_provenance(&mut self, blob_index: u32, provenance: &BlobProvenance) -> Result<()> {
+        let index = blob_index as usize;
+        if index >= self.entries.len() || index >= self.extended.entries.len() {
+            return Err(enoent!("blob not found"));
+        }
+
+        Arc::make_mut(&mut self.entries[index]).set_provenance(Some(provenance.clone()));
+        Arc::make_mut(&mut self.extended.entries[index]).set_provenance(provenance);
+
+        Ok(())
+    }
+
     /// Load blob information table from a reader.
     pub fn load(
         &mut self,
@@ -670,6 +806,11 @@ impl RafsV5BlobTable {
             debug!("blob {:?} lies on", blob_id);
 
             let index = self.entries.len();
+            let provenance = self
+                .extended
+                .entries
+                .get(index)
+                .and_then(|entry| entry.provenance());
             let (chunk_count, uncompressed_size, compressed_size, blob_features) =
                 // For compatibility, blob table might not be associated with extended blob table.
                 if !self.extended.entries.is_empty() {
@@ -697,6 +838,7 @@ impl RafsV5BlobTable {
             blob_info.set_compressor(flags.into());
             blob_info.set_digester(flags.into());
             blob_info.set_readahead(readahead_offset as u64, readahead_size as u64);
+            blob_info.set_provenance(provenance);
 
             self.entries.push(Arc::new(blob_info));
         }
@@ -755,7 +897,10 @@ pub struct RafsV5ExtBlobEntry {
     pub reserved1: [u8; 4],     //   --  8 Bytes
     pub uncompressed_size: u64, // -- 16 Bytes
     pub compressed_size: u64,   // -- 24 Bytes
-    pub reserved2: [u8; RAFSV5_EXT_BLOB_RESERVED_SIZE],
+    /// Seconds since UNIX epoch when the blob was built, 0 for repeatable builds.
+    pub build_time: u64, // -- 32 Bytes
+    /// Sha256 digest of the source content stored in the blob.
+    pub source_digest: [u8; 32], // -- 64 Bytes
 }
 
 // Implement Debug trait ourselves, as rust prior to 1.47 doesn't impl Debug for array with size
@@ -766,6 +911,7 @@ impl Debug for RafsV5ExtBlobEntry {
             .field("chunk_count", &self.chunk_count)
             .field("blob_cache_size", &self.uncompressed_size)
             .field("compressed_blob_size", &self.compressed_size)
+            .field("build_time", &self.build_time)
             .finish()
     }
 }
@@ -777,7 +923,8 @@ impl Default for RafsV5ExtBlobEntry {
             reserved1: [0; 4],
             uncompressed_size: 0,
             compressed_size: 0,
-            reserved2: [0; RAFSV5_EXT_BLOB_RESERVED_SIZE],
+            build_time: 0,
+            source_digest: [0; 32],
         }
     }
 }
@@ -791,6 +938,29 @@ impl RafsV5ExtBlobEntry {
             ..Default::default()
         }
     }
+
+    /// Get provenance information of the blob, `None` if it's not recorded by the builder.
+    ///
+    /// The builder version is not available because there's no room for it.
+    pub fn provenance(&self) -> Option<BlobProvenance> {
+        let provenance = BlobProvenance {
+            builder_version: String::new(),
+            build_time: self.build_time,
+            source_digest: RafsDigest::from(self.source_digest),
+        };
+
+        if provenance.is_empty() {
+            None
+        } else {
+            Some(provenance)
+        }
+    }
+
+    /// Set provenance information of the blob.
+    pub fn set_provenance(&mut self, provenance: &BlobProvenance) {
+        self.build_time = provenance.build_time;
+        self.source_digest = provenance.source_digest.data;
+    }
 }
 
 /// Rafs v5 on disk extended blob information table.
@@ -870,7 +1040,8 @@ impl RafsStore for RafsV5ExtBlobTable {
                 w.write_all(&entry.reserved1)?;
                 w.write_all(&u64::to_le_bytes(entry.uncompressed_size))?;
                 w.write_all(&u64::to_le_bytes(entry.compressed_size))?;
-                w.write_all(&entry.reserved2)?;
+                w.write_all(&u64::to_le_bytes(entry.build_time))?;
+                w.write_all(&entry.source_digest)?;
                 size += RAFSV5_EXT_BLOB_ENTRY_SIZE;
                 Ok(())
             })?;
@@ -935,8 +1106,9 @@ pub struct RafsV5Inode {
     pub i_rdev: u32,
     // for alignment reason, we put nsec first
     pub i_mtime_nsec: u32,
-    pub i_mtime: u64,        // 120
-    pub i_reserved: [u8; 8], // 128
+    pub i_mtime: u64, // 120
+    /// birth time in seconds since epoch, 0 if unknown
+    pub i_btime: u64, // 128
 }
 
 impl RafsV5Inode {
@@ -977,6 +1149,12 @@ impl RafsV5Inode {
         (self.i_mtime, self.i_mtime_nsec)
     }
 
+    /// Get the birth time of the inode in seconds, 0 if unknown.
+    #[inline]
+    pub fn btime(&self) -> u64 {
+        self.i_btime
+    }
+
     /// Get the mode of the inode.
     #[inline]
     pub fn mode(&self) -> u32 {
@@ -1240,6 +1418,10 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
         return Ok(vec![]);
     }
 
+    if inode.has_hole() {
+        return rafsv5_alloc_bio_vecs_with_hole(inode, offset, end, index_end, user_io);
+    }
+
     let mut descs = Vec::with_capacity(4);
     let mut desc = BlobIoVec::new();
     let chunk = inode.get_chunk_info_v5(index_start)?;
@@ -1263,6 +1445,60 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
     Ok(descs)
 }
 
+/// Allocate `BlobIoVec` for files with holes, which have no chunks backing the holes.
+///
+/// Chunks are sorted by file offset, and a new `BlobIoVec` is started on every hole so each
+/// returned `BlobIoVec` covers a continuous file range. The caller should fill holes with zero.
+fn rafsv5_alloc_bio_vecs_with_hole<I: RafsInode + RafsV5InodeChunkOps + RafsV5InodeOps>(
+    inode: &I,
+    offset: u64,
+    end: u64,
+    index_end: u32,
+    user_io: bool,
+) -> Result<Vec<BlobIoVec>> {
+    let mut descs = Vec::with_capacity(4);
+    let mut desc = BlobIoVec::new();
+    let mut last_end = None;
+
+    // Find the first chunk ending after `offset` by binary search, instead of scanning from the
+    // first chunk.
+    let (mut start, mut count) = (0, index_end);
+    while count > 0 {
+        let step = count / 2;
+        let chunk = inode.get_chunk_info_v5(start + step)?;
+        if chunk.file_offset() + chunk.uncompress_size() as u64 <= offset {
+            start += step + 1;
+            count -= step + 1;
+        } else {
+            count = step;
+        }
+    }
+
+    for idx in start..index_end {
+        let chunk = inode.get_chunk_info_v5(idx)?;
+        let chunk_end = chunk.file_offset() + chunk.uncompress_size() as u64;
+        if chunk.file_offset() >= end {
+            break;
+        }
+
+        let blob = inode.get_blob_by_index(chunk.blob_index())?;
+        if !desc.bi_vec.is_empty()
+            && (blob.blob_index() != desc.bi_vec[0].blob.blob_index()
+                || last_end != Some(chunk.file_offset()))
+        {
+            descs.push(desc);
+            desc = BlobIoVec::new();
+        }
+        last_end = Some(chunk_end);
+        add_chunk_to_bio_desc(&mut desc, offset, end, chunk, blob, user_io);
+    }
+    if !desc.bi_vec.is_empty() {
+        descs.push(desc);
+    }
+
+    Ok(descs)
+}
+
 /// Add a new bio covering the IO range into the provided bio desc.
 ///
 /// Returns true if caller should continue checking more chunks.
@@ -1559,11 +1795,73 @@ pub mod tests {
             assert_eq!(table.get(i).unwrap().chunk_count, i * 3);
             assert_eq!(table.get(i).unwrap().reserved1, [0u8; 4]);
             assert_eq!(table.get(i).unwrap().uncompressed_size, 100);
-            assert_eq!(
-                table.get(i).unwrap().reserved2,
-                [0u8; RAFSV5_EXT_BLOB_RESERVED_SIZE]
+            assert_eq!(table.get(i).unwrap().build_time, 0);
+            assert!(table.get(i).unwrap().provenance().is_none());
+        }
+    }
+
+    #[test]
+    fn test_blob_table_provenance() {
+        let tmp_file = TempFile::new().unwrap();
+        let provenance = BlobProvenance {
+            builder_version: "v2.1.0".to_string(),
+            build_time: 1_600_000_000,
+            source_digest: RafsDigest { data: [0x5a; 32] },
+        };
+
+        let mut table = RafsV5BlobTable::new();
+        for i in 0..2 {
+            table.add(
+                format!("blob-{}", i),
+                0,
+                0,
+                RAFS_DEFAULT_CHUNK_SIZE as u32,
+                1,
+                0x1000,
+                0x100,
+                BlobFeatures::empty(),
+                RafsSuperFlags::empty(),
             );
         }
+        table.set_provenance(1, &provenance).unwrap();
+        assert!(table.set_provenance(2, &provenance).is_err());
+        assert_eq!(table.get(1).unwrap().provenance(), Some(&provenance));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp_file.as_path())
+            .unwrap();
+        let mut writer = BufWriter::new(file);
+        let size = table.store(&mut writer).unwrap();
+        table.store_extended(&mut writer).unwrap();
+        writer.flush().unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp_file.as_path())
+            .unwrap();
+        let mut reader = Box::new(file) as Box<dyn RafsIoRead>;
+        let mut table = RafsV5BlobTable::new();
+        reader.seek(SeekFrom::Start(size as u64)).unwrap();
+        table.extended.load(&mut reader, 2).unwrap();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        table
+            .load(
+                &mut reader,
+                size as u32,
+                RAFS_DEFAULT_CHUNK_SIZE as u32,
+                RafsSuperFlags::empty(),
+            )
+            .unwrap();
+
+        assert!(table.get(0).unwrap().provenance().is_none());
+        // The builder version is not stored in Rafs v5 bootstrap.
+        let loaded = table.get(1).unwrap().provenance().cloned().unwrap();
+        assert_eq!(loaded.build_time, provenance.build_time);
+        assert_eq!(loaded.source_digest, provenance.source_digest);
+        assert!(loaded.builder_version.is_empty());
     }
 
     #[derive(Default, Copy, Clone)]
@@ -1677,6 +1975,38 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_alloc_bio_vecs_with_hole() {
+        use crate::mock::{MockChunkInfo, MockInode};
+
+        // Chunks at 0x0, 0x2000, 0x3000 and 0x6000, with holes in between.
+        let chunks = [0x0u64, 0x2000, 0x3000, 0x6000]
+            .iter()
+            .map(|offset| Arc::new(MockChunkInfo::mock(*offset, 0, 0x1000, 0, 0x1000)))
+            .collect::<Vec<_>>();
+        let inode = MockInode::mock(1, 0x8000, chunks);
+
+        let file_offset = |desc: &BlobIoVec| {
+            let bio = &desc.bi_vec[0];
+            bio.chunkinfo.as_v5().unwrap().file_offset() + bio.offset as u64
+        };
+
+        let descs = rafsv5_alloc_bio_vecs_with_hole(&inode, 0x2800, 0x7000, 4, true).unwrap();
+        assert_eq!(descs.len(), 2);
+        assert_eq!(file_offset(&descs[0]), 0x2800);
+        assert_eq!(descs[0].bi_vec.len(), 2);
+        assert_eq!(descs[0].bi_size, 0x1800);
+        assert_eq!(file_offset(&descs[1]), 0x6000);
+        assert_eq!(descs[1].bi_size, 0x1000);
+
+        let descs = rafsv5_alloc_bio_vecs_with_hole(&inode, 0x1000, 0x2000, 4, true).unwrap();
+        assert!(descs.is_empty());
+        let descs = rafsv5_alloc_bio_vecs_with_hole(&inode, 0x7000, 0x8000, 4, true).unwrap();
+        assert!(descs.is_empty());
+        let descs = rafsv5_alloc_bio_vecs_with_hole(&inode, 0, 0x8000, 4, true).unwrap();
+        assert_eq!(descs.len(), 3);
+    }
+
     #[test]
     fn test_calculate_bio_chunk_index() {
         let (blksize, chunk_cnt) = (1024, 4);
@@ -1775,6 +2105,10 @@ pub mod tests {
             compress::Algorithm::from(RafsSuperFlags::COMPRESS_NONE),
             compress::Algorithm::None
         );
+        assert_eq!(
+            compress::Algorithm::from(RafsSuperFlags::from(compress::Algorithm::Zstd)),
+            compress::Algorithm::Zstd
+        );
     }
 
     #[test]
@@ -4,20 +4,24 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
 use std::io::{Read, Result};
 use std::mem::size_of;
-use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::sync::Arc;
 
-use nydus_utils::{digest, round_up, ByteSize};
+use nydus_utils::digest::{self, RafsDigest};
+use nydus_utils::{round_up, ByteSize};
 use storage::device::{BlobFeatures, BlobInfo};
-use storage::meta::BlobMetaHeaderOndisk;
+use storage::meta::{BlobMetaHeaderOndisk, BlobProvenance};
 use storage::{compress, RAFS_MAX_CHUNK_SIZE};
 
-use crate::metadata::{layout::RafsXAttrs, RafsStore, RafsSuperFlags};
+use crate::metadata::layout::{RafsXAttrs, XattrValue};
+use crate::metadata::{RafsStore, RafsSuperFlags};
 use crate::{impl_bootstrap_converter, impl_pub_getter_setter, RafsIoReader, RafsIoWrite};
 
 /// EROFS metadata slot size.
@@ -30,6 +34,8 @@ pub const EROFS_INODE_FLAT_PLAIN: u16 = 0;
 pub const EROFS_INODE_FLAT_INLINE: u16 = 2;
 /// EROFS chunked inode.
 pub const EROFS_INODE_CHUNK_BASED: u16 = 4;
+/// EROFS block address of chunks within holes, which are read as zeros.
+pub const EROFS_NULL_ADDR: u32 = u32::MAX;
 /// EROFS device table offset.
 pub const EROFS_DEVTABLE_OFFSET: u16 =
     EROFS_SUPER_OFFSET + EROFS_SUPER_BLOCK_SIZE + EROFS_EXT_SUPER_BLOCK_SIZE;
@@ -38,6 +44,9 @@ pub const EROFS_DEVTABLE_OFFSET: u16 =
 const EROFS_SUPER_OFFSET: u16 = 1024;
 // Size of EROFS super block.
 const EROFS_SUPER_BLOCK_SIZE: u16 = 128;
+/// Offset of the image digest field in Rafs v6 extended super block.
+pub const RAFSV6_IMAGE_DIGEST_OFFSET: u64 =
+    EROFS_SUPER_OFFSET as u64 + EROFS_SUPER_BLOCK_SIZE as u64 + 36;
 // Size of extended super block, used for rafs v6 specific fields
 const EROFS_EXT_SUPER_BLOCK_SIZE: u16 = 256;
 // Magic number for EROFS super block.
@@ -46,11 +55,10 @@ const EROFS_SUPER_MAGIC_V1: u32 = 0xE0F5_E1E2;
 const EROFS_BLOCK_BITS: u8 = 12;
 // Bits of EROFS metadata slot size.
 const EROFS_INODE_SLOT_BITS: u8 = 5;
-// 32-byte on-disk inode
-#[allow(dead_code)]
-const EROFS_INODE_LAYOUT_COMPACT: u16 = 0;
-// 64-byte on-disk inode
-const EROFS_INODE_LAYOUT_EXTENDED: u16 = 1;
+/// 32-byte on-disk inode.
+pub const EROFS_INODE_LAYOUT_COMPACT: u16 = 0;
+/// 64-byte on-disk inode.
+pub const EROFS_INODE_LAYOUT_EXTENDED: u16 = 1;
 // Bit flag indicating whether the inode is chunked or not.
 const EROFS_CHUNK_FORMAT_INDEXES_FLAG: u16 = 0x0020;
 // Encoded chunk size (log2(chunk_size) - EROFS_BLOCK_BITS).
@@ -186,12 +194,33 @@ impl RafsV6SuperBlock {
         self.s_root_nid = nid.to_le();
     }
 
+    /// Get EROFS root nid.
+    pub fn root_nid(&self) -> u16 {
+        u16::from_le(self.s_root_nid)
+    }
+
     /// Set EROFS meta block address.
     pub fn set_meta_addr(&mut self, meta_addr: u64) {
         debug_assert!(((meta_addr / EROFS_BLOCK_SIZE) >> 32) == 0);
         self.s_meta_blkaddr = u32::to_le((meta_addr / EROFS_BLOCK_SIZE) as u32);
     }
 
+    /// Get EROFS meta block address.
+    pub fn meta_addr(&self) -> u64 {
+        u32::from_le(self.s_meta_blkaddr) as u64 * EROFS_BLOCK_SIZE
+    }
+
+    /// Set start address of the shared xattr area.
+    pub fn set_xattr_addr(&mut self, xattr_addr: u64) {
+        debug_assert!(((xattr_addr / EROFS_BLOCK_SIZE) >> 32) == 0);
+        self.s_xattr_blkaddr = u32::to_le((xattr_addr / EROFS_BLOCK_SIZE) as u32);
+    }
+
+    /// Get start address of the shared xattr area.
+    pub fn xattr_addr(&self) -> u64 {
+        u32::from_le(self.s_xattr_blkaddr) as u64 * EROFS_BLOCK_SIZE
+    }
+
     /// Set number of extra devices.
     pub fn set_extra_devices(&mut self, count: u16) {
         self.s_extra_devices = count.to_le();
@@ -257,8 +286,14 @@ pub struct RafsV6SuperBlockExt {
     s_blob_table_size: u32,
     /// chunk size
     s_chunk_size: u32,
+    /// offset of directory name index
+    s_dirent_index_offset: u64,
+    /// size of directory name index
+    s_dirent_index_size: u32,
+    /// Sha256 digest of the bootstrap with this field zeroed, all zero if not calculated.
+    s_image_digest: [u8; 32],
     /// Reserved
-    s_reserved: [u8; 232],
+    s_reserved: [u8; 188],
 }
 
 impl_bootstrap_converter!(RafsV6SuperBlockExt);
@@ -280,11 +315,12 @@ impl RafsV6SuperBlockExt {
     }
 
     /// Validate the Rafs v6 super block.
-    pub fn validate(&self, _meta_size: u64) -> Result<()> {
+    pub fn validate(&self, meta_size: u64) -> Result<()> {
         let mut flags = self.flags();
         flags &= RafsSuperFlags::COMPRESS_NONE.bits()
             | RafsSuperFlags::COMPRESS_LZ4_BLOCK.bits()
-            | RafsSuperFlags::COMPRESS_GZIP.bits();
+            | RafsSuperFlags::COMPRESS_GZIP.bits()
+            | RafsSuperFlags::COMPRESS_ZSTD.bits();
         if flags.count_ones() != 1 {
             return Err(einval!(
                 "invalid flags related to compression algorithm in Rafs v6 extended superblock"
@@ -307,6 +343,19 @@ impl RafsV6SuperBlockExt {
             return Err(einval!("invalid chunk size in Rafs v6 extended superblock"));
         }
 
+        if self.flags() & RafsSuperFlags::HAS_DIRENT_INDEX.bits() != 0 {
+            let offset = self.dirent_index_offset();
+            let size = self.dirent_index_size() as u64;
+            if offset < EROFS_BLOCK_SIZE
+                || size < size_of::<RafsV6DirentIndexHeader>() as u64
+                || offset.checked_add(size).map(|v| v > meta_size) != Some(false)
+            {
+                return Err(einval!(
+                    "invalid directory name index in Rafs v6 extended superblock"
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -317,6 +366,7 @@ impl RafsV6SuperBlockExt {
         self.s_flags &= !RafsSuperFlags::COMPRESS_NONE.bits();
         self.s_flags &= !RafsSuperFlags::COMPRESS_LZ4_BLOCK.bits();
         self.s_flags &= !RafsSuperFlags::COMPRESS_GZIP.bits();
+        self.s_flags &= !RafsSuperFlags::COMPRESS_ZSTD.bits();
         self.s_flags |= c.bits();
     }
 
@@ -338,6 +388,23 @@ impl RafsV6SuperBlockExt {
         u64
     );
     impl_pub_getter_setter!(blob_table_size, set_blob_table_size, s_blob_table_size, u32);
+    impl_pub_getter_setter!(
+        dirent_index_offset,
+        set_dirent_index_offset,
+        s_dirent_index_offset,
+        u64
+    );
+    impl_pub_getter_setter!(
+        dirent_index_size,
+        set_dirent_index_size,
+        s_dirent_index_size,
+        u32
+    );
+
+    /// Get the image digest, all zero if not calculated.
+    pub fn image_digest(&self) -> [u8; 32] {
+        self.s_image_digest
+    }
 }
 
 impl RafsStore for RafsV6SuperBlockExt {
@@ -357,7 +424,10 @@ impl Default for RafsV6SuperBlockExt {
             s_blob_table_offset: u64::to_le(0),
             s_blob_table_size: u32::to_le(0),
             s_chunk_size: u32::to_le(0),
-            s_reserved: [0u8; 232],
+            s_dirent_index_offset: u64::to_le(0),
+            s_dirent_index_size: u32::to_le(0),
+            s_image_digest: [0u8; 32],
+            s_reserved: [0u8; 188],
         }
     }
 }
@@ -395,7 +465,40 @@ pub trait RafsV6OndiskInodeTrait: RafsStore {
     fn set_u(&mut self, u: u32);
     fn set_uidgid(&mut self, uid: u32, gid: u32);
     fn set_mtime(&mut self, _sec: u64, _nsec: u32);
+    fn set_btime(&mut self, _sec: u64);
     fn set_data_layout(&mut self, data_layout: u16);
+    fn format(&self) -> u16;
+    fn xattr_inline_count(&self) -> u16;
+    fn mode(&self) -> u16;
+    fn size(&self) -> u64;
+    fn union(&self) -> u32;
+    fn btime(&self) -> u64;
+
+    /// Get inode data layout format.
+    #[inline]
+    fn data_layout(&self) -> u16 {
+        (self.format() >> 1) & 0x7
+    }
+
+    /// Get size of the inode and its inline xattrs.
+    fn size_with_xattr(&self) -> usize {
+        let xattr_size = match self.xattr_inline_count() {
+            0 => 0,
+            count => {
+                size_of::<RafsV6XattrIbodyHeader>()
+                    + (count as usize - 1) * size_of::<RafsV6XattrEntry>()
+            }
+        };
+
+        let inode_size = if self.format() & 0x1 == EROFS_INODE_LAYOUT_EXTENDED {
+            size_of::<RafsV6InodeExtended>()
+        } else {
+            size_of::<RafsV6InodeCompact>()
+        };
+
+        inode_size + xattr_size
+    }
+
     /// Set inode data layout format to be PLAIN.
     #[inline]
     fn set_inline_plain_layout(&mut self) {
@@ -496,11 +599,44 @@ impl RafsV6OndiskInodeTrait for RafsV6InodeCompact {
     /// Set last modification time for the inode.
     fn set_mtime(&mut self, _sec: u64, _nsec: u32) {}
 
+    /// Set birth time for the inode.
+    fn set_btime(&mut self, _sec: u64) {}
+
     /// Set inode data layout format.
     fn set_data_layout(&mut self, data_layout: u16) {
         self.i_format = u16::to_le(EROFS_INODE_LAYOUT_COMPACT | (data_layout << 1));
     }
 
+    /// Get inode layout format.
+    fn format(&self) -> u16 {
+        u16::from_le(self.i_format)
+    }
+
+    /// Get xattr inline count.
+    fn xattr_inline_count(&self) -> u16 {
+        u16::from_le(self.i_xattr_icount)
+    }
+
+    /// Get file protection mode.
+    fn mode(&self) -> u16 {
+        u16::from_le(self.i_mode)
+    }
+
+    /// Get file size of the inode.
+    fn size(&self) -> u64 {
+        u32::from_le(self.i_size) as u64
+    }
+
+    /// Get the union field.
+    fn union(&self) -> u32 {
+        u32::from_le(self.i_u)
+    }
+
+    /// Get birth time of the inode, compact inodes don't carry it.
+    fn btime(&self) -> u64 {
+        0
+    }
+
     /// Load a `RafsV6InodeCompact` from a reader.
     fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
@@ -546,7 +682,9 @@ pub struct RafsV6InodeExtended {
     i_mtime_nsec: u32,
     /// Number of hard links.
     i_nlink: u32,
-    i_reserved2: [u8; 16],
+    /// Birth time in seconds since epoch, 0 if unknown.
+    i_btime: u64,
+    i_reserved2: [u8; 8],
 }
 
 impl RafsV6InodeExtended {
@@ -565,7 +703,8 @@ impl RafsV6InodeExtended {
             i_mtime: u64::to_le(0),
             i_mtime_nsec: u32::to_le(0),
             i_nlink: u32::to_le(0),
-            i_reserved2: [0u8; 16],
+            i_btime: u64::to_le(0),
+            i_reserved2: [0u8; 8],
         }
     }
 }
@@ -613,11 +752,46 @@ impl RafsV6OndiskInodeTrait for RafsV6InodeExtended {
         self.i_mtime_nsec = u32::to_le(nsec);
     }
 
+    /// Set birth time for the inode.
+    fn set_btime(&mut self, sec: u64) {
+        self.i_btime = u64::to_le(sec);
+    }
+
     /// Set inode data layout format.
     fn set_data_layout(&mut self, data_layout: u16) {
         self.i_format = u16::to_le(EROFS_INODE_LAYOUT_EXTENDED | (data_layout << 1));
     }
 
+    /// Get inode layout format.
+    fn format(&self) -> u16 {
+        u16::from_le(self.i_format)
+    }
+
+    /// Get xattr inline count.
+    fn xattr_inline_count(&self) -> u16 {
+        u16::from_le(self.i_xattr_icount)
+    }
+
+    /// Get file protection mode.
+    fn mode(&self) -> u16 {
+        u16::from_le(self.i_mode)
+    }
+
+    /// Get file size of the inode.
+    fn size(&self) -> u64 {
+        u64::from_le(self.i_size)
+    }
+
+    /// Get the union field.
+    fn union(&self) -> u32 {
+        u32::from_le(self.i_u)
+    }
+
+    /// Get birth time of the inode.
+    fn btime(&self) -> u64 {
+        u64::from_le(self.i_btime)
+    }
+
     /// Load a `RafsV6InodeExtended` from a reader.
     fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
@@ -864,6 +1038,11 @@ impl RafsV6Device {
         self.blocks = blocks.to_le();
     }
 
+    /// Get mapped block address.
+    pub fn mapped_blkaddr(&self) -> u32 {
+        u32::from_le(self.mapped_blkaddr)
+    }
+
     /// Set mapped block address.
     pub fn set_mapped_blkaddr(&mut self, addr: u32) {
         self.mapped_blkaddr = addr.to_le();
@@ -890,6 +1069,61 @@ pub fn align_offset(offset: u64, aligned_size: u64) -> u64 {
     round_up(offset, aligned_size)
 }
 
+/// Find the entry named `name` in a directory block of Rafs v6 dirents and names.
+///
+/// Dirents are sorted by name in a block, so binary search is used. Returns nid of the entry,
+/// or `None` if the block doesn't contain the entry.
+pub fn rafsv6_find_dirent(block: &[u8], name: &[u8]) -> Result<Option<u64>> {
+    let dirent_size = size_of::<RafsV6Dirent>();
+    let name_offset = |index: usize| -> Result<usize> {
+        let pos = index * dirent_size + 8;
+        if pos + 2 > block.len() {
+            return Err(einval!("invalid Rafs v6 dirent block"));
+        }
+        Ok(u16::from_le_bytes([block[pos], block[pos + 1]]) as usize)
+    };
+
+    let first = name_offset(0)?;
+    if first < dirent_size || first % dirent_size != 0 || first > block.len() {
+        return Err(einval!("invalid name offset of Rafs v6 dirent"));
+    }
+    let count = first / dirent_size;
+    let entry_name = |index: usize| -> Result<&[u8]> {
+        let start = name_offset(index)?;
+        let end = if index + 1 < count {
+            name_offset(index + 1)?
+        } else {
+            block.len()
+        };
+        if start < first || start > end || end > block.len() {
+            return Err(einval!("invalid name offset of Rafs v6 dirent"));
+        }
+        let name = &block[start..end];
+        // The last name in a block may be padded with zeros.
+        match name.iter().position(|c| *c == 0) {
+            Some(len) if index + 1 == count => Ok(&name[..len]),
+            _ => Ok(name),
+        }
+    };
+
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match entry_name(mid)?.cmp(name) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => {
+                let pos = mid * dirent_size;
+                let mut nid = [0u8; 8];
+                nid.copy_from_slice(&block[pos..pos + 8]);
+                return Ok(Some(u64::from_le_bytes(nid)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
 /// Generate EROFS `nid` from `offset`.
 pub fn calculate_nid(offset: u64, meta_size: u64) -> u64 {
     (offset - meta_size) >> EROFS_INODE_SLOT_BITS
@@ -928,8 +1162,12 @@ struct RafsV6Blob {
     ci_uncompressed_size: u64,
     // SHA256 digest of the compression information array in binary form.
     ci_digest: [u8; 32],
-
-    reserved2: [u8; 88],
+    // Seconds since UNIX epoch when the blob was built, 0 for repeatable builds.
+    build_time: u64,
+    // SHA256 digest of the source content stored in the blob.
+    source_digest: [u8; 32],
+    // Version of the builder which generated the blob, padded with '\0'.
+    builder_version: [u8; 48],
 }
 
 impl Default for RafsV6Blob {
@@ -950,7 +1188,9 @@ impl Default for RafsV6Blob {
             ci_compressed_size: 0u64.to_le(),
             ci_uncompressed_size: 0u64.to_le(),
             ci_digest: [0u8; 32],
-            reserved2: [0u8; 88],
+            build_time: 0u64.to_le(),
+            source_digest: [0u8; 32],
+            builder_version: [0u8; 48],
         }
     }
 }
@@ -988,6 +1228,14 @@ impl RafsV6Blob {
             u64::from_le(self.ci_uncompressed_size),
             u32::from_le(self.ci_compressor),
         );
+        let provenance = BlobProvenance {
+            builder_version: BlobProvenance::decode_builder_version(&self.builder_version),
+            build_time: u64::from_le(self.build_time),
+            source_digest: RafsDigest::from(self.source_digest),
+        };
+        if !provenance.is_empty() {
+            blob_info.set_provenance(Some(provenance));
+        }
 
         Ok(blob_info)
     }
@@ -1002,6 +1250,14 @@ impl RafsV6Blob {
 
         let mut blob_id = [0u8; BLOB_SHA256_LEN];
         blob_id.copy_from_slice(blob_info.blob_id().as_bytes());
+        let mut build_time = 0;
+        let mut source_digest = [0u8; 32];
+        let mut builder_version = [0u8; 48];
+        if let Some(provenance) = blob_info.provenance() {
+            build_time = provenance.build_time;
+            source_digest = provenance.source_digest.data;
+            provenance.encode_builder_version(&mut builder_version);
+        }
 
         Ok(RafsV6Blob {
             blob_id,
@@ -1019,7 +1275,9 @@ impl RafsV6Blob {
             ci_compressed_size: blob_info.meta_ci_compressed_size().to_le(),
             ci_uncompressed_size: blob_info.meta_ci_uncompressed_size().to_le(),
             ci_digest: [0u8; 32],
-            reserved2: [0u8; 88],
+            build_time: build_time.to_le(),
+            source_digest,
+            builder_version,
         })
     }
 
@@ -1157,6 +1415,7 @@ impl RafsV6BlobTable {
             header.ci_uncompressed_size(),
             header.ci_compressor() as u32,
         );
+        blob_info.set_provenance(header.provenance());
 
         self.entries.push(Arc::new(blob_info));
 
@@ -1212,6 +1471,212 @@ impl RafsStore for RafsV6BlobTable {
     }
 }
 
+/// Minimum number of entries for a directory to get a name index.
+pub const RAFSV6_DIRENT_INDEX_MIN_ENTRIES: usize = 64;
+
+// Number of bloom filter bits allocated for each directory entry, about 1% false positive rate.
+const RAFSV6_DIRENT_INDEX_BITS_PER_ENTRY: u64 = 10;
+// Number of hash functions applied to each entry name.
+const RAFSV6_DIRENT_INDEX_HASHES: u32 = 7;
+// Upper limit of hash functions accepted from on-disk data.
+const RAFSV6_DIRENT_INDEX_MAX_HASHES: u32 = 16;
+
+/// Rafs v6 directory name index header on-disk format, 8 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct RafsV6DirentIndexHeader {
+    /// Number of directories in the index.
+    h_count: u32,
+    h_reserved: u32,
+}
+
+impl_bootstrap_converter!(RafsV6DirentIndexHeader);
+
+/// Rafs v6 directory name index entry on-disk format, 24 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct RafsV6DirentIndexEntry {
+    /// Nid of the directory.
+    d_nid: u64,
+    /// Offset of the bloom filter, relative to the start of the index.
+    d_offset: u32,
+    /// Size of the bloom filter in bytes.
+    d_size: u32,
+    /// Number of hash functions used by the bloom filter.
+    d_hashes: u32,
+    d_reserved: u32,
+}
+
+impl_bootstrap_converter!(RafsV6DirentIndexEntry);
+
+/// Bloom filter built from names of all entries within a directory.
+///
+/// It never reports false negatives, so a name not contained by the filter doesn't exist in
+/// the directory and the lookup could be answered without scanning dirents.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RafsV6DirentBloom {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl RafsV6DirentBloom {
+    /// Create a new bloom filter sized for `entries` names.
+    pub fn new(entries: usize) -> Self {
+        let bits = entries as u64 * RAFSV6_DIRENT_INDEX_BITS_PER_ENTRY;
+        let size = round_up(std::cmp::max(bits, 64), 64) / 8;
+
+        RafsV6DirentBloom {
+            bits: vec![0u8; size as usize],
+            hashes: RAFSV6_DIRENT_INDEX_HASHES,
+        }
+    }
+
+    /// Add an entry name into the filter.
+    pub fn insert(&mut self, name: &OsStr) {
+        let nbits = self.bits.len() as u64 * 8;
+        let (h1, h2) = Self::hash(name);
+        for i in 0..self.hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % nbits;
+            self.bits[(bit >> 3) as usize] |= 1 << (bit & 0x7);
+        }
+    }
+
+    /// Check whether an entry name may exist in the directory.
+    pub fn may_contain(&self, name: &OsStr) -> bool {
+        let nbits = self.bits.len() as u64 * 8;
+        if nbits == 0 {
+            return true;
+        }
+        let (h1, h2) = Self::hash(name);
+        (0..self.hashes as u64).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % nbits;
+            self.bits[(bit >> 3) as usize] & (1 << (bit & 0x7)) != 0
+        })
+    }
+
+    // FNV-1a, split into two halves for double hashing. It must stay stable across releases
+    // because the result is persisted into bootstrap.
+    fn hash(name: &OsStr) -> (u64, u64) {
+        let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+        for b in name.as_bytes() {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+
+        (h & 0xffff_ffff, (h >> 32) | 1)
+    }
+}
+
+/// Rafs v6 directory name index, to answer negative lookups of large directories quickly.
+#[derive(Clone, Debug, Default)]
+pub struct RafsV6DirentIndex {
+    filters: BTreeMap<u64, RafsV6DirentBloom>,
+}
+
+impl RafsV6DirentIndex {
+    /// Create a new instance of `RafsV6DirentIndex`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the bloom filter for directory `nid`.
+    pub fn insert(&mut self, nid: u64, filter: RafsV6DirentBloom) {
+        self.filters.insert(nid, filter);
+    }
+
+    /// Check whether the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Get size of the on-disk directory name index.
+    pub fn size(&self) -> usize {
+        size_of::<RafsV6DirentIndexHeader>()
+            + self.filters.len() * size_of::<RafsV6DirentIndexEntry>()
+            + self.filters.values().map(|f| f.bits.len()).sum::<usize>()
+    }
+
+    /// Check whether `name` may exist in directory `nid`.
+    ///
+    /// Directories without a filter always report true.
+    pub fn may_contain(&self, nid: u64, name: &OsStr) -> bool {
+        match self.filters.get(&nid) {
+            Some(filter) => filter.may_contain(name),
+            None => true,
+        }
+    }
+
+    /// Load the directory name index from a reader.
+    pub fn load(&mut self, r: &mut RafsIoReader, size: u32) -> Result<()> {
+        let mut data = vec![0u8; size as usize];
+        r.read_exact(&mut data)?;
+
+        let header_size = size_of::<RafsV6DirentIndexHeader>();
+        let entry_size = size_of::<RafsV6DirentIndexEntry>();
+        if data.len() < header_size {
+            return Err(einval!("invalid Rafs v6 directory name index size"));
+        }
+        let mut header = RafsV6DirentIndexHeader::default();
+        header.as_mut().copy_from_slice(&data[..header_size]);
+        let count = u32::from_le(header.h_count) as usize;
+        if header_size + count * entry_size > data.len() {
+            return Err(einval!("invalid Rafs v6 directory name index count"));
+        }
+
+        for idx in 0..count {
+            let pos = header_size + idx * entry_size;
+            let mut entry = RafsV6DirentIndexEntry::default();
+            entry.as_mut().copy_from_slice(&data[pos..pos + entry_size]);
+            let offset = u32::from_le(entry.d_offset) as usize;
+            let len = u32::from_le(entry.d_size) as usize;
+            let hashes = u32::from_le(entry.d_hashes);
+            if len == 0
+                || offset.checked_add(len).map(|v| v > data.len()) != Some(false)
+                || hashes == 0
+                || hashes > RAFSV6_DIRENT_INDEX_MAX_HASHES
+            {
+                return Err(einval!("invalid Rafs v6 directory name index entry"));
+            }
+            let filter = RafsV6DirentBloom {
+                bits: data[offset..offset + len].to_vec(),
+                hashes,
+            };
+            self.filters.insert(u64::from_le(entry.d_nid), filter);
+        }
+
+        Ok(())
+    }
+}
+
+impl RafsStore for RafsV6DirentIndex {
+    fn store(&self, w: &mut dyn RafsIoWrite) -> Result<usize> {
+        let header = RafsV6DirentIndexHeader {
+            h_count: u32::to_le(self.filters.len() as u32),
+            h_reserved: 0,
+        };
+        w.write_all(header.as_ref())?;
+
+        let mut offset = size_of::<RafsV6DirentIndexHeader>()
+            + self.filters.len() * size_of::<RafsV6DirentIndexEntry>();
+        for (nid, filter) in self.filters.iter() {
+            let entry = RafsV6DirentIndexEntry {
+                d_nid: u64::to_le(*nid),
+                d_offset: u32::to_le(offset as u32),
+                d_size: u32::to_le(filter.bits.len() as u32),
+                d_hashes: u32::to_le(filter.hashes),
+                d_reserved: 0,
+            };
+            w.write_all(entry.as_ref())?;
+            offset += filter.bits.len();
+        }
+        for filter in self.filters.values() {
+            w.write_all(&filter.bits)?;
+        }
+
+        Ok(self.size())
+    }
+}
+
 // RafsV6 xattr
 const EROFS_XATTR_INDEX_USER: u8 = 1;
 const EROFS_XATTR_INDEX_POSIX_ACL_ACCESS: u8 = 2;
@@ -1301,6 +1766,16 @@ impl RafsV6XattrIbodyHeader {
     pub fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
     }
+
+    /// Set number of shared xattrs referenced by the inode.
+    pub fn set_shared_count(&mut self, count: u8) {
+        self.h_shared_count = count;
+    }
+
+    /// Get number of shared xattrs referenced by the inode.
+    pub fn shared_count(&self) -> u8 {
+        self.h_shared_count
+    }
 }
 
 // RafsV6 xattr entry (for both inline & shared xattrs)
@@ -1351,29 +1826,111 @@ impl RafsV6XattrEntry {
     }
 }
 
+/// Extended attributes shared by multiple inodes, which are stored once in the shared xattr area.
+///
+/// Inodes reference shared xattrs by id, which is the offset of the xattr entry in the shared
+/// xattr area in unit of 4 bytes.
+#[derive(Default)]
+pub struct RafsV6SharedXattrs {
+    entries: Vec<(OsString, XattrValue)>,
+    ids: HashMap<(OsString, XattrValue), u32>,
+    size: usize,
+}
+
+impl RafsV6SharedXattrs {
+    /// Create a new instance of `RafsV6SharedXattrs`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a shared xattr table with xattrs present in at least `threshold` xattr sets.
+    pub fn from_xattrs<'a, I: Iterator<Item = &'a RafsXAttrs>>(
+        xattrs: I,
+        threshold: usize,
+    ) -> Self {
+        let mut counts: BTreeMap<(OsString, XattrValue), usize> = BTreeMap::new();
+        for x in xattrs {
+            for (key, value) in x.pairs.iter() {
+                if RafsXAttrs::match_prefix(key).is_ok() {
+                    *counts.entry((key.clone(), value.clone())).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Entries are sorted by key and value to generate reproducible bootstraps.
+        let mut table = Self::new();
+        for ((key, value), count) in counts {
+            if count >= threshold {
+                table.add(key, value);
+            }
+        }
+
+        table
+    }
+
+    fn add(&mut self, key: OsString, value: XattrValue) {
+        let id = (self.size / size_of::<RafsV6XattrEntry>()) as u32;
+        self.size += RafsXAttrs::aligned_entry_size_v6(&key, &value);
+        self.ids.insert((key.clone(), value.clone()), id);
+        self.entries.push((key, value));
+    }
+
+    /// Get id of the shared xattr `key` with `value`.
+    pub fn get_id(&self, key: &OsStr, value: &[u8]) -> Option<u32> {
+        self.ids.get(&(key.to_os_string(), value.to_vec())).copied()
+    }
+
+    /// Check whether there's no shared xattr.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get number of shared xattrs.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Get size of the shared xattr area.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Write the shared xattr area.
+    pub fn store(&self, w: &mut dyn RafsIoWrite) -> Result<usize> {
+        for (key, value) in self.entries.iter() {
+            RafsXAttrs::store_entry_v6(w, key, value)?;
+        }
+
+        Ok(self.size)
+    }
+
+    /// Load the shared xattr with `id` from the shared xattr area `area`.
+    pub fn load_entry(area: &[u8], id: u32) -> Result<(OsString, XattrValue)> {
+        let offset = id as usize * size_of::<RafsV6XattrEntry>();
+        RafsXAttrs::parse_entry_v6(area, offset).map(|(key, value, _)| (key, value))
+    }
+}
+
 impl RafsXAttrs {
     /// Get the number of xattr pairs.
-    pub fn count_v6(&self) -> usize {
+    pub fn count_v6(&self, shared: Option<&RafsV6SharedXattrs>) -> usize {
         if self.is_empty() {
             0
         } else {
-            let size = self.aligned_size_v6();
+            let size = self.aligned_size_v6(shared);
             (size - size_of::<RafsV6XattrIbodyHeader>()) / size_of::<RafsV6XattrEntry>() + 1
         }
     }
 
     /// Get aligned size of all xattr pairs.
-    pub fn aligned_size_v6(&self) -> usize {
+    pub fn aligned_size_v6(&self, shared: Option<&RafsV6SharedXattrs>) -> usize {
         if self.is_empty() {
             0
         } else {
-            let mut size: usize = size_of::<RafsV6XattrIbodyHeader>();
-            for (key, value) in self.pairs.iter() {
-                let (_, prefix_len) = Self::match_prefix(key).expect("xattr is not valid");
-
-                size += size_of::<RafsV6XattrEntry>();
-                size += key.byte_size() - prefix_len + value.len();
-                size = round_up(size as u64, size_of::<RafsV6XattrEntry>() as u64) as usize;
+            let (ids, inline) = self.split_v6(shared);
+            let mut size = size_of::<RafsV6XattrIbodyHeader>() + ids.len() * size_of::<u32>();
+            for (key, value) in inline {
+                size += Self::aligned_entry_size_v6(key, value);
             }
 
             size
@@ -1381,35 +1938,134 @@ impl RafsXAttrs {
     }
 
     /// Write Xattr to rafsv6 ondisk inode.
-    pub fn store_v6(&self, w: &mut dyn RafsIoWrite) -> Result<usize> {
-        // TODO: check count of shared.
-        let header = RafsV6XattrIbodyHeader::new();
+    ///
+    /// Xattrs found in the `shared` table are referenced by ids instead of being stored inline.
+    pub fn store_v6(
+        &self,
+        w: &mut dyn RafsIoWrite,
+        shared: Option<&RafsV6SharedXattrs>,
+    ) -> Result<usize> {
+        let (ids, inline) = self.split_v6(shared);
+        let mut header = RafsV6XattrIbodyHeader::new();
+        header.set_shared_count(ids.len() as u8);
         w.write_all(header.as_ref())?;
 
-        if !self.pairs.is_empty() {
-            for (key, value) in self.pairs.iter() {
-                // TODO: fix error handling on unknown xattr.
-                let (index, prefix_len) = Self::match_prefix(key).expect("xattr is not valid");
+        for id in ids.iter() {
+            w.write_all(&id.to_le_bytes())?;
+        }
+        for (key, value) in inline {
+            Self::store_entry_v6(w, key, value)?;
+        }
 
-                let mut entry = RafsV6XattrEntry::new();
-                entry.set_name_len((key.byte_size() - prefix_len) as u8);
-                entry.set_name_index(index);
-                entry.set_value_size(value.len().try_into().unwrap());
+        Ok(0)
+    }
 
-                w.write_all(entry.as_ref())?;
-                w.write_all(&key.as_bytes()[prefix_len..])?;
-                w.write_all(value.as_ref())?;
+    /// Load xattrs of an inode from its xattr ibody `ibody`, and shared xattrs referenced by it
+    /// from the shared xattr area `shared_area`.
+    pub fn load_v6(ibody: &[u8], shared_area: &[u8]) -> Result<RafsXAttrs> {
+        let mut xattrs = RafsXAttrs::new();
+        let header_size = size_of::<RafsV6XattrIbodyHeader>();
+        if ibody.is_empty() {
+            return Ok(xattrs);
+        } else if ibody.len() < header_size {
+            return Err(einval!("xattr ibody is too small"));
+        }
 
-                let size =
-                    size_of::<RafsV6XattrEntry>() + key.byte_size() - prefix_len + value.len();
+        let shared_count = ibody[4] as usize;
+        let mut offset = header_size + shared_count * size_of::<u32>();
+        if offset > ibody.len() {
+            return Err(einval!("invalid shared xattr count in xattr ibody"));
+        }
+        for idx in 0..shared_count {
+            let pos = header_size + idx * size_of::<u32>();
+            let id = u32::from_le_bytes(ibody[pos..pos + 4].try_into().unwrap());
+            let (key, value) = RafsV6SharedXattrs::load_entry(shared_area, id)?;
+            xattrs.add(key, value);
+        }
 
-                let padding =
-                    round_up(size as u64, size_of::<RafsV6XattrEntry>() as u64) as usize - size;
-                w.write_padding(padding)?;
+        while offset < ibody.len() {
+            let (key, value, size) = Self::parse_entry_v6(ibody, offset)?;
+            xattrs.add(key, value);
+            offset += size;
+        }
+
+        Ok(xattrs)
+    }
+
+    // Split xattrs into ids of shared xattrs and pairs to be stored inline.
+    fn split_v6<'a>(
+        &'a self,
+        shared: Option<&RafsV6SharedXattrs>,
+    ) -> (Vec<u32>, Vec<(&'a OsString, &'a XattrValue)>) {
+        let mut ids = Vec::new();
+        let mut inline = Vec::new();
+
+        for (key, value) in self.pairs.iter() {
+            // The number of shared xattrs of an inode is limited by `h_shared_count`.
+            match shared.and_then(|s| s.get_id(key, value)) {
+                Some(id) if ids.len() < u8::MAX as usize => ids.push(id),
+                _ => inline.push((key, value)),
             }
         }
+        // Keep the order stable to get the same layout for size calculation and storing.
+        ids.sort_unstable();
+        inline.sort();
 
-        Ok(0)
+        (ids, inline)
+    }
+
+    fn aligned_entry_size_v6(key: &OsStr, value: &[u8]) -> usize {
+        let (_, prefix_len) = Self::match_prefix(key).expect("xattr is not valid");
+        let size = size_of::<RafsV6XattrEntry>() + key.byte_size() - prefix_len + value.len();
+        round_up(size as u64, size_of::<RafsV6XattrEntry>() as u64) as usize
+    }
+
+    fn store_entry_v6(w: &mut dyn RafsIoWrite, key: &OsStr, value: &[u8]) -> Result<usize> {
+        // TODO: fix error handling on unknown xattr.
+        let (index, prefix_len) = Self::match_prefix(key).expect("xattr is not valid");
+
+        let mut entry = RafsV6XattrEntry::new();
+        entry.set_name_len((key.byte_size() - prefix_len) as u8);
+        entry.set_name_index(index);
+        entry.set_value_size(value.len().try_into().unwrap());
+
+        w.write_all(entry.as_ref())?;
+        w.write_all(&key.as_bytes()[prefix_len..])?;
+        w.write_all(value)?;
+
+        let size = size_of::<RafsV6XattrEntry>() + key.byte_size() - prefix_len + value.len();
+        let aligned_size = Self::aligned_entry_size_v6(key, value);
+        w.write_padding(aligned_size - size)?;
+
+        Ok(aligned_size)
+    }
+
+    // Parse the xattr entry at `offset` of `buf`, return the xattr pair and aligned entry size.
+    fn parse_entry_v6(buf: &[u8], offset: usize) -> Result<(OsString, XattrValue, usize)> {
+        let entry_size = size_of::<RafsV6XattrEntry>();
+        if offset + entry_size > buf.len() {
+            return Err(einval!("xattr entry is out of range"));
+        }
+        let name_len = buf[offset] as usize;
+        let index = buf[offset + 1];
+        let value_size = u16::from_le_bytes([buf[offset + 2], buf[offset + 3]]) as usize;
+        let name_start = offset + entry_size;
+        let value_start = name_start + name_len;
+        let end = value_start + value_size;
+        if end > buf.len() {
+            return Err(einval!("xattr entry is out of range"));
+        }
+
+        let prefix = RAFSV6_XATTR_TYPES
+            .iter()
+            .find(|x| x.index == index)
+            .ok_or_else(|| einval!(format!("unknown xattr name index {}", index)))?;
+        let mut key = prefix.prefix.as_bytes().to_vec();
+        key.extend_from_slice(&buf[name_start..value_start]);
+        let value = buf[value_start..end].to_vec();
+        let size = round_up((end - offset) as u64, entry_size as u64) as usize;
+
+        Ok((OsString::from_vec(key), value, size))
     }
 
     fn match_prefix(key: &OsStr) -> Result<(u8, usize)> {
@@ -1514,6 +2170,7 @@ mod tests {
         );
         inode.set_uidgid(1, 2);
         inode.set_mtime(3, 4);
+        inode.set_btime(5);
         inode.store(&mut writer).unwrap();
 
         let mut inode2 = RafsV6InodeExtended::new();
@@ -1522,6 +2179,8 @@ mod tests {
         assert_eq!(inode2.i_gid, 2u32.to_le());
         assert_eq!(inode2.i_mtime, 3u64.to_le());
         assert_eq!(inode2.i_mtime_nsec, 4u32.to_le());
+        assert_eq!(inode2.i_btime, 5u64.to_le());
+        assert_eq!(inode2.btime(), 5);
         assert_eq!(
             inode2.i_format,
             u16::to_le(EROFS_INODE_LAYOUT_EXTENDED | (EROFS_INODE_CHUNK_BASED << 1))
@@ -1599,10 +2258,10 @@ mod tests {
         xattrs.add(OsString::from("user.a"), vec![1u8]);
         xattrs.add(OsString::from("trusted.b"), vec![2u8]);
 
-        assert_eq!(xattrs.count_v6(), 5);
+        assert_eq!(xattrs.count_v6(None), 5);
 
         let xattrs2 = RafsXAttrs::new();
-        assert_eq!(xattrs2.count_v6(), 0);
+        assert_eq!(xattrs2.count_v6(None), 0);
     }
 
     #[test]
@@ -1612,16 +2271,16 @@ mod tests {
         xattrs.add(OsString::from("trusted.b"), vec![2u8]);
 
         let size = 12 + 8 + 8;
-        assert_eq!(xattrs.aligned_size_v6(), size);
+        assert_eq!(xattrs.aligned_size_v6(None), size);
 
         let xattrs2 = RafsXAttrs::new();
-        assert_eq!(xattrs2.aligned_size_v6(), 0);
+        assert_eq!(xattrs2.aligned_size_v6(None), 0);
 
         // let mut xattrs2 = RafsXAttrs::new();
         // xattrs2.add(OsString::from("user.a"), vec![1u8]);
         // xattrs2.add(OsString::from("unknown.b"), vec![2u8]);
 
-        // assert_eq!(xattrs2.aligned_size_v6().is_error(), true);
+        // assert_eq!(xattrs2.aligned_size_v6(None).is_error(), true);
     }
 
     #[test]
@@ -1643,7 +2302,7 @@ mod tests {
         let mut xattrs = RafsXAttrs::new();
         xattrs.add(OsString::from("user.nydus"), vec![1u8]);
         xattrs.add(OsString::from("security.rafs"), vec![2u8, 3u8]);
-        xattrs.store_v6(&mut writer).unwrap();
+        xattrs.store_v6(&mut writer, None).unwrap();
 
         let mut header = RafsV6XattrIbodyHeader::new();
         header.load(&mut reader).unwrap();
@@ -1683,4 +2342,188 @@ mod tests {
             assert_eq!(entry2 == target1, true);
         }
     }
+
+    #[test]
+    fn test_rafs_shared_xattrs_v6() {
+        use std::io::Write;
+
+        let mut xattrs1 = RafsXAttrs::new();
+        xattrs1.add(OsString::from("security.selinux"), b"label".to_vec());
+        xattrs1.add(OsString::from("user.a"), vec![1u8]);
+        let mut xattrs2 = RafsXAttrs::new();
+        xattrs2.add(OsString::from("security.selinux"), b"label".to_vec());
+        xattrs2.add(OsString::from("user.a"), vec![2u8]);
+
+        let shared = RafsV6SharedXattrs::from_xattrs(vec![&xattrs1, &xattrs2].into_iter(), 2);
+        assert_eq!(shared.len(), 1);
+        // entry(4) + "selinux"(7) + "label"(5), aligned to 4.
+        assert_eq!(shared.size(), 16);
+        assert_eq!(
+            shared.get_id(OsStr::new("security.selinux"), b"label"),
+            Some(0)
+        );
+        assert_eq!(shared.get_id(OsStr::new("user.a"), &[1u8]), None);
+
+        // header(12) + shared id(4) + inline "user.a"(8)
+        assert_eq!(xattrs1.aligned_size_v6(Some(&shared)), 24);
+        assert_eq!(xattrs1.count_v6(Some(&shared)), 4);
+
+        let temp = TempFile::new().unwrap();
+        let w = OpenOptions::new().write(true).open(temp.as_path()).unwrap();
+        let mut writer = BufWriter::new(w);
+        shared.store(&mut writer).unwrap();
+        xattrs1.store_v6(&mut writer, Some(&shared)).unwrap();
+        writer.flush().unwrap();
+
+        let data = std::fs::read(temp.as_path()).unwrap();
+        assert_eq!(data.len(), shared.size() + 24);
+        let (area, ibody) = data.split_at(shared.size());
+        assert_eq!(ibody[4], 1);
+
+        let loaded = RafsXAttrs::load_v6(ibody, area).unwrap();
+        assert_eq!(
+            loaded.get(OsStr::new("security.selinux")),
+            Some(&b"label".to_vec())
+        );
+        assert_eq!(loaded.get(OsStr::new("user.a")), Some(&vec![1u8]));
+        assert!(RafsXAttrs::load_v6(ibody, &area[..8]).is_err());
+    }
+
+    #[test]
+    fn test_rafs_v6_dirent_index() {
+        use std::io::Write;
+
+        let temp = TempFile::new().unwrap();
+        let w = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp.as_path())
+            .unwrap();
+        let r = OpenOptions::new()
+            .read(true)
+            .write(false)
+            .open(temp.as_path())
+            .unwrap();
+        let mut writer = BufWriter::new(w);
+        let mut reader: Box<dyn RafsIoRead> = Box::new(r);
+
+        let mut filter = RafsV6DirentBloom::new(100);
+        for i in 0..100 {
+            filter.insert(OsStr::new(&format!("file-{}", i)));
+        }
+        for i in 0..100 {
+            assert!(filter.may_contain(OsStr::new(&format!("file-{}", i))));
+        }
+        let misses = (0..1000)
+            .filter(|i| !filter.may_contain(OsStr::new(&format!("none-{}", i))))
+            .count();
+        assert!(misses > 900);
+
+        let mut index = RafsV6DirentIndex::new();
+        assert!(index.is_empty());
+        index.insert(0x10, filter.clone());
+        index.insert(0x20, RafsV6DirentBloom::new(1));
+        let size = index.store(&mut writer).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(size, index.size());
+
+        let mut index2 = RafsV6DirentIndex::new();
+        index2.load(&mut reader, size as u32).unwrap();
+        assert_eq!(index2.filters.len(), 2);
+        assert_eq!(index2.filters.get(&0x10), Some(&filter));
+        assert!(index2.may_contain(0x10, OsStr::new("file-1")));
+        assert!(index2.may_contain(0x30, OsStr::new("no-filter")));
+
+        reader.seek_to_offset(0).unwrap();
+        let mut index3 = RafsV6DirentIndex::new();
+        assert!(index3.load(&mut reader, 4).is_err());
+    }
+
+    #[test]
+    fn test_rafs_v6_find_dirent() {
+        let names = [".", "..", "bar", "foo"];
+        let mut block = Vec::new();
+        let mut nameoff = names.len() * size_of::<RafsV6Dirent>();
+        for (nid, name) in names.iter().enumerate() {
+            let dirent = RafsV6Dirent::new(nid as u64 + 0x10, nameoff as u16, 0);
+            block.extend_from_slice(dirent.as_ref());
+            nameoff += name.len();
+        }
+        for name in names.iter() {
+            block.extend_from_slice(name.as_bytes());
+        }
+
+        for (nid, name) in names.iter().enumerate() {
+            assert_eq!(
+                rafsv6_find_dirent(&block, name.as_bytes()).unwrap(),
+                Some(nid as u64 + 0x10)
+            );
+        }
+        assert_eq!(rafsv6_find_dirent(&block, b"baz").unwrap(), None);
+
+        // The last name may be padded with zeros up to the block size.
+        let mut padded = block.clone();
+        padded.resize(EROFS_BLOCK_SIZE as usize, 0);
+        assert_eq!(rafsv6_find_dirent(&padded, b"foo").unwrap(), Some(0x13));
+
+        let mut corrupted = block.clone();
+        corrupted[8] = 0xff;
+        assert!(rafsv6_find_dirent(&corrupted, b"foo").is_err());
+        assert!(rafsv6_find_dirent(&block[..4], b"foo").is_err());
+    }
+
+    #[test]
+    fn test_rafs_v6_blob_table_provenance() {
+        use std::io::Write;
+
+        let temp = TempFile::new().unwrap();
+        let w = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp.as_path())
+            .unwrap();
+        let r = OpenOptions::new()
+            .read(true)
+            .write(false)
+            .open(temp.as_path())
+            .unwrap();
+        let mut writer = BufWriter::new(w);
+        let mut reader: Box<dyn RafsIoRead> = Box::new(r);
+
+        let flags = RafsSuperFlags::COMPRESS_LZ4_BLOCK | RafsSuperFlags::DIGESTER_BLAKE3;
+        let provenance = BlobProvenance {
+            builder_version: "v2.1.0-0123456789abcdef".to_string(),
+            build_time: 1_600_000_000,
+            source_digest: RafsDigest { data: [0x5a; 32] },
+        };
+        let mut header = BlobMetaHeaderOndisk::default();
+        header.set_provenance(&provenance);
+
+        let mut table = RafsV6BlobTable::new();
+        for (idx, header) in [header, BlobMetaHeaderOndisk::default()].iter().enumerate() {
+            table.add(
+                format!("{:064x}", idx),
+                0,
+                0,
+                0x10_0000,
+                1,
+                0x10_0000,
+                0x1000,
+                BlobFeatures::empty(),
+                flags,
+                *header,
+            );
+        }
+        let size = table.store(&mut writer).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(size, table.size());
+
+        let mut table2 = RafsV6BlobTable::new();
+        table2
+            .load(&mut reader, size as u32, 0x10_0000, flags)
+            .unwrap();
+        assert_eq!(table2.entries.len(), 2);
+        assert_eq!(table2.entries[0].provenance(), Some(&provenance));
+        assert!(table2.entries[1].provenance().is_none());
+    }
 }
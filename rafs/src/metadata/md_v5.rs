@@ -5,7 +5,7 @@
 
 use super::cached_v5::CachedSuperBlockV5;
 use super::direct_v5::DirectSuperBlockV5;
-use super::layout::v5::{RafsV5PrefetchTable, RafsV5SuperBlock};
+use super::layout::v5::{RafsV5BlobPrefetchTable, RafsV5PrefetchTable, RafsV5SuperBlock};
 use super::*;
 
 impl RafsSuper {
@@ -36,6 +36,9 @@ impl RafsSuper {
         self.meta.extended_blob_table_entries = sb.extended_blob_table_entries();
         self.meta.prefetch_table_entries = sb.prefetch_table_entries();
         self.meta.prefetch_table_offset = sb.prefetch_table_offset();
+        self.meta.image_digest = image_digest_from(sb.image_digest());
+        self.meta.blob_prefetch_table_offset = sb.blob_prefetch_table_offset();
+        self.meta.blob_prefetch_table_entries = sb.blob_prefetch_table_entries();
 
         match self.mode {
             RafsMode::Direct => {
@@ -92,12 +95,29 @@ impl RafsSuper {
             return Ok(0);
         }
 
-        let mut prefetch_table = RafsV5PrefetchTable::new();
         let mut hardlinks: HashSet<u64> = HashSet::new();
         let mut head_desc = BlobIoVec::new();
 
-        // Try to prefetch according to the list of files specified by the
-        // builder's `--prefetch-policy fs` option.
+        for ino in self.get_prefetch_inodes_v5(r)? {
+            debug!("hint prefetch inode {}", ino);
+            self.prefetch_data(ino, &mut head_desc, &mut hardlinks, &fetcher)
+                .map_err(|e| RafsError::Prefetch(e.to_string()))?;
+        }
+        // The left chunks whose size is smaller than 4MB will be fetched here.
+        fetcher(&mut head_desc);
+
+        Ok(hint_entries)
+    }
+
+    /// Load inodes from the prefetch table, which is generated by the builder's
+    /// `--prefetch-policy fs` option.
+    pub(crate) fn get_prefetch_inodes_v5(&self, r: &mut RafsIoReader) -> RafsResult<Vec<Inode>> {
+        let hint_entries = self.meta.prefetch_table_entries as usize;
+        if hint_entries == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut prefetch_table = RafsV5PrefetchTable::new();
         prefetch_table
             .load_prefetch_table_from(r, self.meta.prefetch_table_offset, hint_entries)
             .map_err(|e| {
@@ -107,19 +127,41 @@ impl RafsSuper {
                 ))
             })?;
 
-        for ino in prefetch_table.inodes {
+        Ok(prefetch_table
+            .inodes
+            .iter()
             // Inode number 0 is invalid, it was added because prefetch table has to be aligned.
-            if ino == 0 {
-                break;
-            }
-            debug!("hint prefetch inode {}", ino);
-            self.prefetch_data(ino as u64, &mut head_desc, &mut hardlinks, &fetcher)
-                .map_err(|e| RafsError::Prefetch(e.to_string()))?;
+            .take_while(|ino| **ino != 0)
+            .map(|ino| *ino as Inode)
+            .collect())
+    }
+
+    /// Load ranges from the blob prefetch range table, which is generated by the builder's
+    /// `--prefetch-policy blob` option.
+    pub(crate) fn get_blob_prefetch_ranges_v5(
+        &self,
+        r: &mut RafsIoReader,
+    ) -> RafsResult<Vec<(u32, u64, u32)>> {
+        let entries = self.meta.blob_prefetch_table_entries as usize;
+        if entries == 0 {
+            return Ok(Vec::new());
         }
-        // The left chunks whose size is smaller than 4MB will be fetched here.
-        fetcher(&mut head_desc);
 
-        Ok(hint_entries)
+        let mut table = RafsV5BlobPrefetchTable::new();
+        table
+            .load_from(r, self.meta.blob_prefetch_table_offset, entries)
+            .map_err(|e| {
+                RafsError::Prefetch(format!(
+                    "failed to load blob prefetch table at offset {}: {:?}",
+                    self.meta.blob_prefetch_table_offset, e
+                ))
+            })?;
+
+        Ok(table
+            .ranges
+            .iter()
+            .map(|r| (r.blob_index(), r.offset(), r.size()))
+            .collect())
     }
 
     pub(crate) fn skip_v5_superblock(&self, r: &mut RafsIoReader) -> Result<()> {
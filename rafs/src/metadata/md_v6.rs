@@ -8,7 +8,7 @@ use std::sync::Arc;
 use super::direct_v6::DirectSuperBlockV6;
 use super::layout::v6::{RafsV6SuperBlock, RafsV6SuperBlockExt};
 use super::layout::RAFS_SUPER_VERSION_V6;
-use super::{RafsMode, RafsSuper, RafsSuperBlock, RafsSuperFlags};
+use super::{image_digest_from, RafsMode, RafsSuper, RafsSuperBlock, RafsSuperFlags};
 use crate::RafsIoReader;
 
 impl RafsSuper {
@@ -26,6 +26,8 @@ impl RafsSuper {
         sb.validate(end)?;
         self.meta.magic = sb.magic();
         self.meta.version = RAFS_SUPER_VERSION_V6;
+        self.meta.meta_addr = sb.meta_addr();
+        self.meta.root_nid = sb.root_nid();
 
         let mut ext_sb = RafsV6SuperBlockExt::new();
         ext_sb.load(r)?;
@@ -33,6 +35,9 @@ impl RafsSuper {
         self.meta.chunk_size = ext_sb.chunk_size();
         self.meta.blob_table_offset = ext_sb.blob_table_offset();
         self.meta.blob_table_size = ext_sb.blob_table_size();
+        self.meta.dirent_index_offset = ext_sb.dirent_index_offset();
+        self.meta.dirent_index_size = ext_sb.dirent_index_size();
+        self.meta.image_digest = image_digest_from(ext_sb.image_digest());
         self.meta.flags = RafsSuperFlags::from_bits(ext_sb.flags())
             .ok_or_else(|| einval!(format!("invalid super flags {:x}", ext_sb.flags())))?;
         info!("rafs superblock features: {}", self.meta.flags);
@@ -10,33 +10,37 @@ use std::collections::HashSet;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::fs::OpenOptions;
-use std::io::{Error, Result};
+use std::io::{Error, Read, Result};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use fuse_backend_rs::abi::linux_abi::Attr;
 use fuse_backend_rs::api::filesystem::{Entry, ROOT_ID};
-use nydus_utils::digest::{self, RafsDigest};
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
 use serde::Serialize;
 use serde_with::{serde_as, DisplayFromStr};
 use storage::compress;
 use storage::device::{BlobChunkInfo, BlobInfo, BlobIoVec};
 
+use self::layout::v5::RAFSV5_IMAGE_DIGEST_OFFSET;
+use self::layout::v6::RAFSV6_IMAGE_DIGEST_OFFSET;
 use self::layout::{XattrName, XattrValue, RAFS_SUPER_VERSION_V5, RAFS_SUPER_VERSION_V6};
 use self::noop::NoopSuperBlock;
 use crate::fs::{RafsConfig, RAFS_DEFAULT_ATTR_TIMEOUT, RAFS_DEFAULT_ENTRY_TIMEOUT};
 use crate::{RafsError, RafsIoReader, RafsIoWrite, RafsResult};
 
 pub mod cached_v5;
+pub mod delta;
 pub mod direct_v5;
 pub mod direct_v6;
 pub mod layout;
 mod md_v5;
 mod md_v6;
 mod noop;
+pub mod shared;
 
 pub use storage::{RAFS_DEFAULT_CHUNK_SIZE, RAFS_MAX_CHUNK_SIZE};
 
@@ -92,6 +96,16 @@ pub trait RafsSuperBlock: RafsSuperBlobs + RafsSuperInodes + Send + Sync {
 
     /// Get all blob information objects used by the filesystem.
     fn get_blob_infos(&self) -> Vec<Arc<BlobInfo>>;
+
+    /// V6: find the child named `name` of the directory with nid `parent`, returning nid of
+    /// the child.
+    fn lookup_child(&self, _parent: u64, _name: &OsStr) -> Result<u64> {
+        Err(enosys!("lookup by nid is only supported by Rafs v6"))
+    }
+
+    /// Load metadata into memory in background, to avoid faulting in scattered pages of the
+    /// bootstrap on first accesses after mounting.
+    fn prefetch_metadata(&self) {}
 }
 
 /// Trait to access metadata and data for an inode.
@@ -180,6 +194,11 @@ pub trait RafsInode: Any {
     /// Get data size of the inode.
     fn size(&self) -> u64;
 
+    /// Get birth time of the inode in seconds since epoch, 0 if unknown.
+    fn get_btime(&self) -> u64 {
+        0
+    }
+
     /// Check whether the inode has no content.
     fn is_empty_size(&self) -> bool {
         self.size() == 0
@@ -226,6 +245,12 @@ bitflags! {
         const HAS_XATTR = 0x0000_0020;
         // V5: Data chunks are compressed with gzip
         const COMPRESS_GZIP = 0x0000_0040;
+        /// V6: Large directories carry a name index to speed up negative lookups.
+        const HAS_DIRENT_INDEX = 0x0000_0080;
+        /// Data blobs are not generated, the image only carries metadata for inspection.
+        const METADATA_ONLY = 0x0000_0100;
+        /// Data chunks are compressed with zstd.
+        const COMPRESS_ZSTD = 0x0000_0200;
     }
 }
 
@@ -281,6 +306,21 @@ pub struct RafsSuperMeta {
     pub prefetch_table_offset: u64,
     /// Size of the inode prefetch table.
     pub prefetch_table_entries: u32,
+    /// V5: Offset of the blob prefetch range table into the metadata blob.
+    pub blob_prefetch_table_offset: u64,
+    /// V5: Size of the blob prefetch range table.
+    pub blob_prefetch_table_entries: u32,
+    /// V6: Offset of the directory name index.
+    pub dirent_index_offset: u64,
+    /// V6: Size of the directory name index.
+    pub dirent_index_size: u32,
+    /// V6: Start address of the metadata area, inode offset = meta_addr + nid * 32.
+    pub meta_addr: u64,
+    /// V6: Nid of the root inode.
+    pub root_nid: u16,
+    /// Digest identifying the image, `None` if not calculated when building the image.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub image_digest: Option<RafsDigest>,
     /// Default attribute timeout value.
     pub attr_timeout: Duration,
     /// Default inode timeout value.
@@ -298,11 +338,30 @@ impl RafsSuperMeta {
         self.version == RAFS_SUPER_VERSION_V6
     }
 
+    /// Get offset of the image digest field into the metadata blob.
+    pub fn image_digest_offset(&self) -> u64 {
+        if self.is_v6() {
+            RAFSV6_IMAGE_DIGEST_OFFSET
+        } else {
+            RAFSV5_IMAGE_DIGEST_OFFSET
+        }
+    }
+
     /// Check whether the explicit UID/GID feature has been enable or not.
     pub fn explicit_uidgid(&self) -> bool {
         self.flags.contains(RafsSuperFlags::EXPLICIT_UID_GID)
     }
 
+    /// Check whether the filesystem has a directory name index or not.
+    pub fn has_dirent_index(&self) -> bool {
+        self.flags.contains(RafsSuperFlags::HAS_DIRENT_INDEX)
+    }
+
+    /// Check whether the image is built without data blobs.
+    pub fn is_metadata_only(&self) -> bool {
+        self.flags.contains(RafsSuperFlags::METADATA_ONLY)
+    }
+
     /// Check whether the filesystem supports extended attribute or not.
     pub fn has_xattr(&self) -> bool {
         self.flags.contains(RafsSuperFlags::HAS_XATTR)
@@ -347,12 +406,63 @@ impl Default for RafsSuperMeta {
             blob_readahead_size: 0,
             prefetch_table_offset: 0,
             prefetch_table_entries: 0,
+            blob_prefetch_table_offset: 0,
+            blob_prefetch_table_entries: 0,
+            dirent_index_offset: 0,
+            dirent_index_size: 0,
+            meta_addr: 0,
+            root_nid: 0,
+            image_digest: None,
             attr_timeout: Duration::from_secs(RAFS_DEFAULT_ATTR_TIMEOUT),
             entry_timeout: Duration::from_secs(RAFS_DEFAULT_ENTRY_TIMEOUT),
         }
     }
 }
 
+/// Calculate digest of the image whose metadata blob is read from `r`, starting from the current
+/// position, with the image digest field at `digest_offset` zeroed.
+///
+/// The image digest is the sha256 digest of the metadata blob. Blob ids, which are sha256 digests
+/// of data blobs unless specified when building, are recorded in order in the blob table, so the
+/// image digest identifies both metadata and data of the image.
+pub fn calculate_image_digest(r: &mut dyn Read, digest_offset: u64) -> Result<RafsDigest> {
+    let digest_range = digest_offset..digest_offset + digest::RAFS_DIGEST_LENGTH as u64;
+    let mut hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+    let mut buf = vec![0u8; 0x10_0000];
+    let mut pos = 0u64;
+
+    loop {
+        let size = r.read(&mut buf)?;
+        if size == 0 {
+            break;
+        }
+        let end = pos + size as u64;
+        if pos < digest_range.end && end > digest_range.start {
+            let start = digest_range.start.saturating_sub(pos) as usize;
+            let stop = std::cmp::min(digest_range.end - pos, size as u64) as usize;
+            for b in buf[start..stop].iter_mut() {
+                *b = 0;
+            }
+        }
+        hasher.digest_update(&buf[..size]);
+        pos = end;
+    }
+    if pos < digest_range.end {
+        return Err(einval!("metadata blob is too small to hold image digest"));
+    }
+
+    Ok(hasher.digest_finalize())
+}
+
+// An all-zero image digest field means the digest is not calculated.
+fn image_digest_from(data: [u8; digest::RAFS_DIGEST_LENGTH]) -> Option<RafsDigest> {
+    if data.iter().all(|b| *b == 0) {
+        None
+    } else {
+        Some(RafsDigest::from(data))
+    }
+}
+
 /// Rafs metadata working mode.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RafsMode {
@@ -459,6 +569,35 @@ impl RafsSuper {
         Err(einval!("invalid superblock version number"))
     }
 
+    /// Verify the image digest recorded in the metadata blob against its content, and against
+    /// `expected` if specified.
+    pub fn verify_image_digest(&self, r: &mut RafsIoReader, expected: Option<&str>) -> Result<()> {
+        let recorded = self
+            .meta
+            .image_digest
+            .ok_or_else(|| enoent!("image digest is not recorded in the metadata blob"))?;
+        if let Some(expected) = expected {
+            let expected = expected.trim_start_matches("sha256:");
+            if !recorded.to_string().eq_ignore_ascii_case(expected) {
+                return Err(einval!(format!(
+                    "image digest {} doesn't match the expected {}",
+                    recorded, expected
+                )));
+            }
+        }
+
+        r.seek_to_offset(0)?;
+        let actual = calculate_image_digest(r, self.meta.image_digest_offset())?;
+        if actual != recorded {
+            return Err(einval!(format!(
+                "image digest {} doesn't match the content of the metadata blob {}",
+                recorded, actual
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Update the filesystem metadata and storage backend.
     pub fn update(&self, r: &mut RafsIoReader) -> RafsResult<()> {
         if self.meta.is_v5() {
@@ -606,6 +745,29 @@ impl RafsSuper {
         }
     }
 
+    /// Get inodes to prefetch from the static file prefetch list recorded in the bootstrap.
+    /// Get ranges of blobs to prefetch, in the form of (blob index, offset, size).
+    pub fn get_blob_prefetch_ranges(
+        &self,
+        r: &mut RafsIoReader,
+    ) -> RafsResult<Vec<(u32, u64, u32)>> {
+        if self.meta.is_v5() {
+            self.get_blob_prefetch_ranges_v5(r)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    pub fn get_prefetch_inodes(&self, r: &mut RafsIoReader) -> RafsResult<Vec<Inode>> {
+        if self.meta.is_v5() {
+            self.get_prefetch_inodes_v5(r)
+        } else {
+            Err(RafsError::Prefetch(
+                "Unknown filesystem version, prefetch disabled".to_string(),
+            ))
+        }
+    }
+
     #[inline]
     fn prefetch_inode<F>(
         inode: &Arc<dyn RafsInode>,
@@ -638,7 +800,7 @@ impl RafsSuper {
         Ok(())
     }
 
-    fn prefetch_data<F>(
+    pub(crate) fn prefetch_data<F>(
         &self,
         ino: u64,
         head_desc: &mut BlobIoVec,
@@ -681,10 +843,61 @@ impl RafsSuper {
     }
 }
 
+/// Populate pages of a memory mapped bootstrap.
+///
+/// The kernel is advised to read the whole region, and then each page is touched to map it into
+/// the process, so following accesses to the metadata won't trigger page faults.
+pub(crate) fn populate_mapping(base: *const u8, size: usize) {
+    if base.is_null() || size == 0 {
+        return;
+    }
+
+    let start = Instant::now();
+    let ret = unsafe { libc::madvise(base as *mut libc::c_void, size, libc::MADV_WILLNEED) };
+    if ret < 0 {
+        warn!(
+            "failed to advise to load metadata, {}",
+            Error::last_os_error()
+        );
+    }
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut offset = 0;
+    while offset < size {
+        // Safe because the range [base, base + size) is mapped.
+        unsafe { std::ptr::read_volatile(base.add(offset)) };
+        offset += page_size;
+    }
+    info!(
+        "prefetched {} bytes of metadata in {:?}",
+        size,
+        start.elapsed()
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_populate_mapping() {
+        populate_mapping(std::ptr::null(), 4096);
+
+        let size = 3 * 4096 + 1;
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        populate_mapping(base as *const u8, size);
+        unsafe { libc::munmap(base, size) };
+    }
+
     #[test]
     fn test_rafs_mode() {
         assert!(RafsMode::from_str("").is_err());
@@ -696,4 +909,25 @@ mod tests {
         assert_eq!(&format!("{}", RafsMode::Direct), "direct");
         assert_eq!(&format!("{}", RafsMode::Cached), "cached");
     }
+
+    #[test]
+    fn test_calculate_image_digest() {
+        let mut data = vec![0x5au8; 0x20_0000];
+        let digest = calculate_image_digest(&mut data.as_slice(), 0x10_0000 - 8).unwrap();
+        // Content of the digest field is excluded, so storing the digest doesn't change it.
+        data[0x10_0000 - 8..0x10_0000 + 24].copy_from_slice(&digest.data);
+        assert_eq!(
+            calculate_image_digest(&mut data.as_slice(), 0x10_0000 - 8).unwrap(),
+            digest
+        );
+        data[0] = 0;
+        assert_ne!(
+            calculate_image_digest(&mut data.as_slice(), 0x10_0000 - 8).unwrap(),
+            digest
+        );
+        assert!(calculate_image_digest(&mut data.as_slice(), 0x20_0000 - 8).is_err());
+
+        assert!(image_digest_from([0u8; 32]).is_none());
+        assert_eq!(image_digest_from(digest.data), Some(digest));
+    }
 }
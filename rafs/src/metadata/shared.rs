@@ -0,0 +1,249 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Share direct-mapped bootstraps among nydusd instances.
+//!
+//! On dense nodes, many nydusd instances mount images built from the same base image, each with
+//! its own copy of the bootstrap. To share page cache and mappings, a bootstrap is copied into a
+//! shared directory, usually on tmpfs, as a file named by the sha256 digest of its content, and
+//! all instances map the shared file instead of their own copies.
+//!
+//! Users of a shared file are reference counted by shared `flock(2)` locks, so the count is
+//! released even if an instance crashes. The last user removes the shared file when it's done.
+//!
+//! Shared files are published read-only in a directory only accessible by the owner, and the
+//! content of an existing shared file is verified against its name before using it.
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::geteuid;
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+
+use crate::RafsIoReader;
+
+// Give up if the shared file keeps being removed by other instances when opening it.
+const SHARED_BOOTSTRAP_OPEN_RETRIES: u32 = 16;
+
+/// A bootstrap shared among nydusd instances, which holds a reference to the shared file.
+pub struct SharedBootstrap {
+    path: PathBuf,
+    file: File,
+}
+
+impl SharedBootstrap {
+    /// Open the shared copy of the bootstrap read from `r` in directory `dir`, the shared copy
+    /// is created if it doesn't exist yet.
+    pub fn open(dir: &Path, r: &mut RafsIoReader) -> Result<Self> {
+        Self::prepare_dir(dir)?;
+        let digest = Self::digest(r)?;
+        let path = dir.join(digest.to_string());
+
+        for _ in 0..SHARED_BOOTSTRAP_OPEN_RETRIES {
+            if let Some(file) = Self::try_open(&path, &digest)? {
+                return Ok(SharedBootstrap { path, file });
+            }
+            Self::create(dir, &path, r)?;
+        }
+
+        Err(eother!(format!(
+            "failed to open shared bootstrap {:?}, it's removed repeatedly",
+            path
+        )))
+    }
+
+    /// Path of the shared file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get a reader of the shared file.
+    pub fn reader(&self) -> Result<RafsIoReader> {
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(file))
+    }
+
+    // Other users could replace or modify shared files if they can write to the directory.
+    fn prepare_dir(dir: &Path) -> Result<()> {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        let md = fs::metadata(dir)?;
+        if md.uid() != geteuid().as_raw() || md.mode() & 0o077 != 0 {
+            return Err(eacces!(format!(
+                "shared bootstrap directory {:?} must be owned by current user with mode 0700",
+                dir
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn digest<R: Read + Seek + ?Sized>(r: &mut R) -> Result<RafsDigest> {
+        let mut hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+        let mut buf = vec![0u8; 0x10_0000];
+
+        r.seek(SeekFrom::Start(0))?;
+        loop {
+            let size = r.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            hasher.digest_update(&buf[..size]);
+        }
+        r.seek(SeekFrom::Start(0))?;
+
+        Ok(hasher.digest_finalize())
+    }
+
+    // Open the shared file and take a reference, return None if it doesn't exist, or has been
+    // removed by the last user in the meantime.
+    fn try_open(path: &Path, digest: &RafsDigest) -> Result<Option<File>> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        flock(&file, libc::LOCK_SH)?;
+
+        let md = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(m) if m.dev() == md.dev() && m.ino() == md.ino() => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        if md.mode() & 0o222 != 0 {
+            return Err(eacces!(format!("shared bootstrap {:?} is writable", path)));
+        }
+        let actual = Self::digest(&mut file)?;
+        if actual != *digest {
+            return Err(einval!(format!(
+                "shared bootstrap {:?} doesn't match its digest, got {}",
+                path, actual
+            )));
+        }
+
+        Ok(Some(file))
+    }
+
+    // Copy the bootstrap into a temporary file and publish it atomically, so the shared file is
+    // always complete. It's fine if another instance has published it first.
+    fn create(dir: &Path, path: &Path, r: &mut RafsIoReader) -> Result<()> {
+        let tmp_path = dir.join(format!(
+            "{}.{}.tmp",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id()
+        ));
+        let result = Self::publish(&tmp_path, path, r);
+        let _ = fs::remove_file(&tmp_path);
+        result?;
+        info!("bootstrap is shared at {:?}", path);
+
+        Ok(())
+    }
+
+    fn publish(tmp_path: &Path, path: &Path, r: &mut RafsIoReader) -> Result<()> {
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o444)
+            .open(tmp_path)?;
+        r.seek(SeekFrom::Start(0))?;
+        std::io::copy(r, &mut tmp)?;
+        r.seek(SeekFrom::Start(0))?;
+
+        match fs::hard_link(tmp_path, path) {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for SharedBootstrap {
+    fn drop(&mut self) {
+        // The exclusive lock is granted only if there's no other user.
+        if flock(&self.file, libc::LOCK_EX | libc::LOCK_NB).is_ok() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("failed to remove shared bootstrap {:?}, {}", self.path, e);
+            } else {
+                info!("shared bootstrap {:?} is removed", self.path);
+            }
+        }
+    }
+}
+
+fn flock(file: &File, operation: libc::c_int) -> Result<()> {
+    // Safe because the fd is valid and flock() has no other side effect.
+    let ret = unsafe { libc::flock(file.as_raw_fd(), operation) };
+    if ret < 0 {
+        return Err(last_error!("failed to lock shared bootstrap"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_shared_bootstrap() {
+        let dir = TempDir::new().unwrap();
+        let source = TempFile::new().unwrap();
+        std::fs::write(source.as_path(), vec![0x5au8; 0x2000]).unwrap();
+        let mut r = Box::new(File::open(source.as_path()).unwrap()) as RafsIoReader;
+
+        let shared1 = SharedBootstrap::open(dir.as_path(), &mut r).unwrap();
+        let shared2 = SharedBootstrap::open(dir.as_path(), &mut r).unwrap();
+        assert_eq!(shared1.path(), shared2.path());
+        assert_eq!(fs::read_dir(dir.as_path()).unwrap().count(), 1);
+
+        let mut data = Vec::new();
+        shared2.reader().unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![0x5au8; 0x2000]);
+
+        let md = fs::metadata(shared1.path()).unwrap();
+        assert_eq!(md.mode() & 0o777, 0o444);
+
+        // The shared file is removed with the last reference.
+        let path = shared1.path().to_path_buf();
+        drop(shared1);
+        assert!(path.exists());
+        drop(shared2);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_shared_bootstrap_tampered() {
+        let dir = TempDir::new().unwrap();
+        let source = TempFile::new().unwrap();
+        std::fs::write(source.as_path(), vec![0x5au8; 0x2000]).unwrap();
+        let mut r = Box::new(File::open(source.as_path()).unwrap()) as RafsIoReader;
+        let digest = SharedBootstrap::digest(&mut r).unwrap();
+        let path = dir.as_path().join(digest.to_string());
+
+        // Shared files with unexpected content or permission are rejected.
+        std::fs::write(&path, vec![0xa5u8; 0x2000]).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+        assert!(SharedBootstrap::open(dir.as_path(), &mut r).is_err());
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::write(&path, vec![0x5au8; 0x2000]).unwrap();
+        assert!(SharedBootstrap::open(dir.as_path(), &mut r).is_err());
+
+        // So are directories accessible by others.
+        fs::remove_file(&path).unwrap();
+        fs::set_permissions(dir.as_path(), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(SharedBootstrap::open(dir.as_path(), &mut r).is_err());
+        fs::set_permissions(dir.as_path(), fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(SharedBootstrap::open(dir.as_path(), &mut r).is_ok());
+    }
+}
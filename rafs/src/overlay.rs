@@ -0,0 +1,711 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read-only overlay of multiple RAFS filesystem instances.
+//!
+//! A [RafsOverlay] stacks an ordered list of [Rafs] instances, from lower to upper, into a single
+//! logical filesystem at runtime, so extension layers may be added to an image without merging
+//! bootstraps offline. Entries in upper layers hide entries with the same name in lower layers,
+//! and directories existing in multiple layers are merged. OCI whiteouts are supported: a
+//! `.wh.<name>` file hides `<name>` in lower layers and a `.wh..wh..opq` file makes the directory
+//! opaque, hiding all entries of the directory in lower layers.
+//!
+//! Inode numbers of layers are mapped into the overlay by storing the layer index in the bits
+//! above `LAYER_SHIFT`, with index 0 for the upper most layer. Merged directories are tracked
+//! until the kernel forgets them, and entries of a merged directory are merged once per open
+//! directory handle.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use fuse_backend_rs::abi::linux_abi::Attr;
+use fuse_backend_rs::api::filesystem::*;
+use fuse_backend_rs::api::BackendFileSystem;
+
+use crate::fs::{Handle, Rafs};
+use crate::metadata::layout::RAFS_ROOT_INODE;
+use crate::metadata::Inode;
+
+const ROOT_ID: Inode = RAFS_ROOT_INODE;
+const LAYER_SHIFT: u32 = 48;
+const MAX_LAYER_INO: Inode = (1 << LAYER_SHIFT) - 1;
+/// Maximum number of layers supported by [RafsOverlay].
+pub const MAX_OVERLAY_LAYERS: usize = 256;
+
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE_WHITEOUT: &[u8] = b".wh..wh..opq";
+
+// Inodes of a merged directory in layers, from upper to lower.
+type DirLayers = Arc<Vec<(usize, Inode)>>;
+// Merged entries of a directory as `(name, inode)` pairs.
+type DirEntries = Arc<Vec<(Vec<u8>, Inode)>>;
+
+// Result of looking up a name in a directory of a layer.
+struct LayerLookup<T> {
+    // The entry, its inode number in the layer and whether it's a directory, if found.
+    entry: Option<(T, Inode, bool)>,
+    // Whether the name is hidden in lower layers by a whiteout.
+    whiteout: bool,
+    // Whether the directory is opaque, hiding all entries in lower layers.
+    opaque: bool,
+}
+
+// Resolve a name in a merged directory `dir`, where `probe` looks up the name in a directory of a
+// layer. Return the entry found in the upper most layer, with its inodes in layers to be merged
+// from upper to lower if it's a directory.
+fn resolve<T, F>(
+    dir: &[(usize, Inode)],
+    mut probe: F,
+) -> Result<Option<(usize, T, Vec<(usize, Inode)>)>>
+where
+    F: FnMut(usize, Inode) -> Result<LayerLookup<T>>,
+{
+    let mut found = None;
+    let mut merged = Vec::new();
+
+    for (idx, ino) in dir.iter() {
+        let lookup = probe(*idx, *ino)?;
+        if let Some((entry, layer_ino, is_dir)) = lookup.entry {
+            if found.is_none() {
+                found = Some((*idx, entry));
+            } else if !is_dir {
+                // Non-directories in lower layers are hidden by upper directories.
+                break;
+            }
+            if !is_dir {
+                break;
+            }
+            merged.push((*idx, layer_ino));
+        }
+        if lookup.whiteout || lookup.opaque {
+            break;
+        }
+    }
+
+    Ok(found.map(|(idx, entry)| (idx, entry, merged)))
+}
+
+// Merge entries of a merged directory `dir`, where `list` gets entries of a directory of a layer.
+// Entries in upper layers hide entries with the same name in lower layers.
+fn merge<F>(dir: &[(usize, Inode)], mut list: F) -> Result<Vec<(Vec<u8>, Inode)>>
+where
+    F: FnMut(usize, Inode) -> Result<Vec<(Vec<u8>, Inode)>>,
+{
+    let mut hidden = HashSet::new();
+    let mut entries = Vec::new();
+
+    for (idx, ino) in dir.iter() {
+        let mut opaque = false;
+        let mut whiteouts = Vec::new();
+        for (name, child) in list(*idx, *ino)? {
+            if name == OPAQUE_WHITEOUT {
+                opaque = true;
+            } else if let Some(target) = name.strip_prefix(WHITEOUT_PREFIX) {
+                whiteouts.push(target.to_vec());
+            } else if hidden.insert(name.clone()) {
+                entries.push((name, RafsOverlay::encode(*idx, child)?));
+            }
+        }
+        if opaque {
+            break;
+        }
+        hidden.extend(whiteouts);
+    }
+
+    Ok(entries)
+}
+
+/// Read-only overlay of multiple RAFS filesystem instances.
+pub struct RafsOverlay {
+    // Layers from upper to lower.
+    layers: Vec<Rafs>,
+    // Merged directories which have been looked up, with their lookup counts.
+    dirs: RwLock<HashMap<Inode, (DirLayers, u64)>>,
+    // Merged entries of open directories, by handle, merged on the first readdir.
+    handles: Mutex<HashMap<Handle, Option<DirEntries>>>,
+    next_handle: AtomicU64,
+}
+
+impl RafsOverlay {
+    /// Create an overlay of `layers`, which are ordered from lower to upper.
+    pub fn new(mut layers: Vec<Rafs>) -> Result<Self> {
+        if layers.is_empty() || layers.len() > MAX_OVERLAY_LAYERS {
+            return Err(einval!(format!(
+                "overlay requires 1 to {} layers",
+                MAX_OVERLAY_LAYERS
+            )));
+        }
+        layers.reverse();
+
+        let root = (0..layers.len()).map(|idx| (idx, ROOT_ID)).collect();
+        let mut dirs = HashMap::new();
+        // The root is never forgotten.
+        dirs.insert(ROOT_ID, (Arc::new(root), 0));
+
+        Ok(RafsOverlay {
+            layers,
+            dirs: RwLock::new(dirs),
+            handles: Mutex::new(HashMap::new()),
+            next_handle: AtomicU64::new(1),
+        })
+    }
+
+    /// Get the layers of the overlay, from upper to lower.
+    pub fn layers(&self) -> &[Rafs] {
+        &self.layers
+    }
+
+    fn encode(idx: usize, ino: Inode) -> Result<Inode> {
+        if ino > MAX_LAYER_INO {
+            return Err(einval!(format!(
+                "inode number {} of layer {} is too big for overlay",
+                ino, idx
+            )));
+        }
+        Ok((idx as u64) << LAYER_SHIFT | ino)
+    }
+
+    fn decode(&self, ino: Inode) -> Result<(&Rafs, Inode)> {
+        let idx = (ino >> LAYER_SHIFT) as usize;
+        match self.layers.get(idx) {
+            Some(layer) => Ok((layer, ino & MAX_LAYER_INO)),
+            None => Err(enoent!(format!("invalid overlay inode {}", ino))),
+        }
+    }
+
+    fn get_dir(&self, ino: Inode) -> Result<DirLayers> {
+        if let Some((dir, _)) = self.dirs.read().unwrap().get(&ino) {
+            return Ok(dir.clone());
+        }
+        // Directories only existing in one layer are not recorded.
+        let (_, layer_ino) = self.decode(ino)?;
+        Ok(Arc::new(vec![((ino >> LAYER_SHIFT) as usize, layer_ino)]))
+    }
+
+    // Count a lookup of a merged directory.
+    fn hold_dir(&self, ino: Inode) {
+        if let Some((_, nlookup)) = self.dirs.write().unwrap().get_mut(&ino) {
+            *nlookup += 1;
+        }
+    }
+
+    // Drop a merged directory once the kernel forgets all lookups of it.
+    fn forget_dir(&self, ino: Inode, count: u64) {
+        if ino == ROOT_ID {
+            return;
+        }
+        let mut dirs = self.dirs.write().unwrap();
+        if let Some((_, nlookup)) = dirs.get_mut(&ino) {
+            *nlookup = nlookup.saturating_sub(count);
+            if *nlookup == 0 {
+                dirs.remove(&ino);
+            }
+        }
+    }
+
+    fn negative_entry(&self) -> Entry {
+        let meta = self.layers[0].metadata();
+        Entry {
+            inode: 0,
+            generation: 0,
+            attr: Attr {
+                ..Default::default()
+            }
+            .into(),
+            attr_flags: 0,
+            attr_timeout: meta.attr_timeout,
+            entry_timeout: meta.entry_timeout,
+        }
+    }
+
+    fn is_dir(entry: &Entry) -> bool {
+        entry.attr.st_mode & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    // Check whether `name` exists in a directory of a layer.
+    fn exists(ctx: &Context, layer: &Rafs, ino: Inode, name: &[u8]) -> Result<bool> {
+        let name = CString::new(name).map_err(|e| einval!(e))?;
+        layer.lookup(ctx, ino, &name).map(|e| e.inode != 0)
+    }
+
+    fn lookup_merged(&self, ctx: &Context, parent: Inode, name: &CStr) -> Result<Entry> {
+        let dir = self.get_dir(parent)?;
+        let whiteout = [WHITEOUT_PREFIX, name.to_bytes()].concat();
+        let found = resolve(&dir, |idx, ino| {
+            let layer = &self.layers[idx];
+            let entry = layer.lookup(ctx, ino, name)?;
+            Ok(LayerLookup {
+                entry: if entry.inode != 0 {
+                    let (layer_ino, is_dir) = (entry.inode, Self::is_dir(&entry));
+                    Some((entry, layer_ino, is_dir))
+                } else {
+                    None
+                },
+                whiteout: Self::exists(ctx, layer, ino, &whiteout)?,
+                opaque: Self::exists(ctx, layer, ino, OPAQUE_WHITEOUT)?,
+            })
+        })?;
+
+        match found {
+            Some((idx, mut entry, merged)) => {
+                entry.inode = Self::encode(idx, entry.inode)?;
+                entry.attr.st_ino = entry.inode;
+                if merged.len() > 1 {
+                    let mut dirs = self.dirs.write().unwrap();
+                    let dir = dirs
+                        .entry(entry.inode)
+                        .or_insert_with(|| (Arc::new(merged), 0));
+                    dir.1 += 1;
+                }
+                Ok(entry)
+            }
+            None => Ok(self.negative_entry()),
+        }
+    }
+
+    // Get merged entries of a directory, excluding "." and "..", which are merged once for each
+    // open directory handle so continued readdir requests don't read all layers again.
+    fn merged_entries(&self, ctx: &Context, ino: Inode, handle: Handle) -> Result<DirEntries> {
+        if let Some(Some(entries)) = self.handles.lock().unwrap().get(&handle) {
+            return Ok(entries.clone());
+        }
+
+        let dir = self.get_dir(ino)?;
+        let entries = Arc::new(merge(&dir, |idx, ino| {
+            let mut children = Vec::new();
+            // Offset 0 and 1 are for "." and "..".
+            self.layers[idx].readdir(ctx, ino, 0, u32::MAX, 2, &mut |e| {
+                children.push((e.name.to_vec(), e.ino));
+                Ok(1)
+            })?;
+            Ok(children)
+        })?);
+        if let Some(slot) = self.handles.lock().unwrap().get_mut(&handle) {
+            *slot = Some(entries.clone());
+        }
+
+        Ok(entries)
+    }
+
+    fn do_readdir<F>(
+        &self,
+        ctx: &Context,
+        ino: Inode,
+        handle: Handle,
+        size: u32,
+        offset: u64,
+        mut add_entry: F,
+    ) -> Result<()>
+    where
+        F: FnMut(DirEntry) -> Result<usize>,
+    {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let mut cur_offset = offset;
+        // offset 0 and 1 is for "." and ".." respectively.
+        if cur_offset == 0 {
+            cur_offset += 1;
+            if add_entry(DirEntry {
+                ino,
+                offset: cur_offset,
+                type_: 0,
+                name: b".",
+            })? == 0
+            {
+                return Ok(());
+            }
+        }
+        if cur_offset == 1 {
+            cur_offset += 1;
+            // Parent of merged directories is ambiguous, the kernel looks it up by itself.
+            if add_entry(DirEntry {
+                ino: ROOT_ID,
+                offset: cur_offset,
+                type_: 0,
+                name: b"..",
+            })? == 0
+            {
+                return Ok(());
+            }
+        }
+
+        let entries = self.merged_entries(ctx, ino, handle)?;
+        for (name, child) in entries.iter().skip((cur_offset - 2) as usize) {
+            cur_offset += 1;
+            if add_entry(DirEntry {
+                ino: *child,
+                offset: cur_offset,
+                type_: 0,
+                name,
+            })? == 0
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BackendFileSystem for RafsOverlay {
+    fn mount(&self) -> Result<(Entry, u64)> {
+        let mut root = None;
+        let mut max_ino = 0;
+        for (idx, layer) in self.layers.iter().enumerate() {
+            let (entry, ino) = layer.mount()?;
+            max_ino = Self::encode(idx, ino)?;
+            if root.is_none() {
+                root = Some(entry);
+            }
+        }
+
+        // There's at least one layer, and the root of the upper most layer is the overlay root.
+        Ok((root.unwrap(), max_ino))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl FileSystem for RafsOverlay {
+    type Inode = Inode;
+    type Handle = Handle;
+
+    fn init(&self, opts: FsOptions) -> Result<FsOptions> {
+        self.layers[0].init(opts)
+    }
+
+    fn destroy(&self) {}
+
+    fn lookup(&self, ctx: &Context, parent: u64, name: &CStr) -> Result<Entry> {
+        let bytes = name.to_bytes();
+        if bytes == b"." || (parent == ROOT_ID && bytes == b"..") {
+            let (attr, timeout) = self.getattr(ctx, parent, None)?;
+            let mut entry = self.negative_entry();
+            entry.inode = parent;
+            entry.attr = attr;
+            entry.attr_timeout = timeout;
+            Ok(entry)
+        } else if bytes == b".." {
+            let (layer, ino) = self.decode(parent)?;
+            let mut entry = layer.lookup(ctx, ino, name)?;
+            if entry.inode != 0 {
+                entry.inode = Self::encode((parent >> LAYER_SHIFT) as usize, entry.inode)?;
+                entry.attr.st_ino = entry.inode;
+                self.hold_dir(entry.inode);
+            }
+            Ok(entry)
+        } else if bytes.starts_with(WHITEOUT_PREFIX) {
+            Ok(self.negative_entry())
+        } else {
+            self.lookup_merged(ctx, parent, name)
+        }
+    }
+
+    fn forget(&self, ctx: &Context, ino: u64, count: u64) {
+        self.forget_dir(ino, count);
+        if let Ok((layer, layer_ino)) = self.decode(ino) {
+            layer.forget(ctx, layer_ino, count);
+        }
+    }
+
+    fn batch_forget(&self, ctx: &Context, requests: Vec<(u64, u64)>) {
+        for (ino, count) in requests {
+            self.forget(ctx, ino, count)
+        }
+    }
+
+    fn getattr(
+        &self,
+        ctx: &Context,
+        ino: u64,
+        handle: Option<u64>,
+    ) -> Result<(libc::stat64, Duration)> {
+        let (layer, layer_ino) = self.decode(ino)?;
+        let (mut attr, timeout) = layer.getattr(ctx, layer_ino, handle)?;
+        attr.st_ino = ino;
+        Ok((attr, timeout))
+    }
+
+    fn readlink(&self, ctx: &Context, ino: u64) -> Result<Vec<u8>> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.readlink(ctx, ino)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn read(
+        &self,
+        ctx: &Context,
+        ino: u64,
+        handle: u64,
+        w: &mut dyn ZeroCopyWriter,
+        size: u32,
+        offset: u64,
+        lock_owner: Option<u64>,
+        flags: u32,
+    ) -> Result<usize> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.read(ctx, ino, handle, w, size, offset, lock_owner, flags)
+    }
+
+    fn open(
+        &self,
+        ctx: &Context,
+        ino: Self::Inode,
+        flags: u32,
+        fuse_flags: u32,
+    ) -> Result<(Option<Self::Handle>, OpenOptions)> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.open(ctx, ino, flags, fuse_flags)
+    }
+
+    fn release(
+        &self,
+        _ctx: &Context,
+        _inode: u64,
+        _flags: u32,
+        _handle: u64,
+        _flush: bool,
+        _flock_release: bool,
+        _lock_owner: Option<u64>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn statfs(&self, ctx: &Context, _inode: u64) -> Result<libc::statvfs64> {
+        let mut st = self.layers[0].statfs(ctx, ROOT_ID)?;
+        for layer in self.layers.iter().skip(1) {
+            st.f_files += layer.statfs(ctx, ROOT_ID)?.f_files;
+        }
+        Ok(st)
+    }
+
+    fn getxattr(&self, ctx: &Context, ino: u64, name: &CStr, size: u32) -> Result<GetxattrReply> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.getxattr(ctx, ino, name, size)
+    }
+
+    fn listxattr(&self, ctx: &Context, ino: u64, size: u32) -> Result<ListxattrReply> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.listxattr(ctx, ino, size)
+    }
+
+    fn readdir(
+        &self,
+        ctx: &Context,
+        ino: u64,
+        handle: u64,
+        size: u32,
+        offset: u64,
+        add_entry: &mut dyn FnMut(DirEntry) -> Result<usize>,
+    ) -> Result<()> {
+        self.do_readdir(ctx, ino, handle, size, offset, add_entry)
+    }
+
+    fn readdirplus(
+        &self,
+        ctx: &Context,
+        ino: u64,
+        handle: u64,
+        size: u32,
+        offset: u64,
+        add_entry: &mut dyn FnMut(DirEntry, Entry) -> Result<usize>,
+    ) -> Result<()> {
+        self.do_readdir(ctx, ino, handle, size, offset, |dir_entry| {
+            let entry = if dir_entry.name == b"." || dir_entry.name == b".." {
+                self.negative_entry()
+            } else {
+                let name = CString::new(dir_entry.name).map_err(|e| einval!(e))?;
+                self.lookup(ctx, ino, &name)?
+            };
+            add_entry(dir_entry, entry)
+        })
+    }
+
+    fn opendir(
+        &self,
+        ctx: &Context,
+        ino: Self::Inode,
+        flags: u32,
+    ) -> Result<(Option<Self::Handle>, OpenOptions)> {
+        let (layer, ino) = self.decode(ino)?;
+        let (_, opts) = layer.opendir(ctx, ino, flags)?;
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(handle, None);
+        Ok((Some(handle), opts))
+    }
+
+    fn releasedir(&self, _ctx: &Context, _inode: u64, _flags: u32, handle: u64) -> Result<()> {
+        self.handles.lock().unwrap().remove(&handle);
+        Ok(())
+    }
+
+    fn lseek(&self, ctx: &Context, ino: u64, handle: u64, offset: u64, whence: u32) -> Result<u64> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.lseek(ctx, ino, handle, offset, whence)
+    }
+
+    fn access(&self, ctx: &Context, ino: u64, mask: u32) -> Result<()> {
+        let (layer, ino) = self.decode(ino)?;
+        layer.access(ctx, ino, mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay_inode_encoding() {
+        assert_eq!(RafsOverlay::encode(0, ROOT_ID).unwrap(), ROOT_ID);
+        assert_eq!(RafsOverlay::encode(2, 5).unwrap(), 2 << LAYER_SHIFT | 5);
+        assert!(RafsOverlay::encode(1, MAX_LAYER_INO + 1).is_err());
+        assert!(RafsOverlay::new(Vec::new()).is_err());
+    }
+
+    fn entries(names: &[(&str, Inode)]) -> Result<Vec<(Vec<u8>, Inode)>> {
+        Ok(names
+            .iter()
+            .map(|(name, ino)| (name.as_bytes().to_vec(), *ino))
+            .collect())
+    }
+
+    fn encoded(names: &[(&str, usize, Inode)]) -> Vec<(Vec<u8>, Inode)> {
+        names
+            .iter()
+            .map(|(name, idx, ino)| {
+                let ino = RafsOverlay::encode(*idx, *ino).unwrap();
+                (name.as_bytes().to_vec(), ino)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_overlay_merge_order() {
+        let dir = [(0, 10), (1, 20), (2, 30)];
+        let merged = merge(&dir, |idx, ino| match (idx, ino) {
+            (0, 10) => entries(&[("a", 11), ("c", 12)]),
+            (1, 20) => entries(&[("a", 21), ("b", 22)]),
+            (2, 30) => entries(&[("b", 31), ("d", 32)]),
+            _ => panic!("unexpected layer {} inode {}", idx, ino),
+        })
+        .unwrap();
+
+        assert_eq!(
+            merged,
+            encoded(&[("a", 0, 11), ("c", 0, 12), ("b", 1, 22), ("d", 2, 32)])
+        );
+    }
+
+    #[test]
+    fn test_overlay_merge_whiteout() {
+        let dir = [(0, 10), (1, 20), (2, 30)];
+        let merged = merge(&dir, |idx, _| match idx {
+            0 => entries(&[(".wh.a", 11), ("b", 12), (".wh.e", 13), ("e", 14)]),
+            1 => entries(&[("a", 21), ("b", 22), (".wh.c", 23), ("e", 24)]),
+            _ => entries(&[("c", 31), ("d", 32)]),
+        })
+        .unwrap();
+
+        // Whiteouts are hidden and only hide entries in lower layers.
+        assert_eq!(merged, encoded(&[("b", 0, 12), ("e", 0, 14), ("d", 2, 32)]));
+    }
+
+    #[test]
+    fn test_overlay_merge_opaque() {
+        let dir = [(0, 10), (1, 20), (2, 30)];
+        let merged = merge(&dir, |idx, _| match idx {
+            0 => entries(&[("a", 11)]),
+            1 => entries(&[(".wh..wh..opq", 21), ("b", 22)]),
+            _ => panic!("lower layers of an opaque directory shouldn't be read"),
+        })
+        .unwrap();
+
+        assert_eq!(merged, encoded(&[("a", 0, 11), ("b", 1, 22)]));
+    }
+
+    fn probe(
+        entry: Option<(Inode, bool)>,
+        whiteout: bool,
+        opaque: bool,
+    ) -> Result<LayerLookup<()>> {
+        Ok(LayerLookup {
+            entry: entry.map(|(ino, is_dir)| ((), ino, is_dir)),
+            whiteout,
+            opaque,
+        })
+    }
+
+    fn resolved<F>(probe: F) -> Option<(usize, Vec<(usize, Inode)>)>
+    where
+        F: FnMut(usize, Inode) -> Result<LayerLookup<()>>,
+    {
+        let dir = [(0, 10), (1, 20), (2, 30)];
+        resolve(&dir, probe)
+            .unwrap()
+            .map(|(idx, _, merged)| (idx, merged))
+    }
+
+    #[test]
+    fn test_overlay_resolve_order() {
+        // Directories are merged down to the first non-directory.
+        let found = resolved(|idx, _| match idx {
+            0 => probe(Some((11, true)), false, false),
+            1 => probe(Some((21, true)), false, false),
+            _ => probe(Some((31, false)), false, false),
+        });
+        assert_eq!(found, Some((0, vec![(0, 11), (1, 21)])));
+
+        // Files in upper layers hide directories in lower layers.
+        let found = resolved(|idx, _| match idx {
+            0 => probe(None, false, false),
+            1 => probe(Some((21, false)), false, false),
+            _ => probe(Some((31, true)), false, false),
+        });
+        assert_eq!(found, Some((1, vec![])));
+
+        let found = resolved(|_, _| probe(None, false, false));
+        assert_eq!(found, None);
+    }
+
+    #[test]
+    fn test_overlay_resolve_whiteout() {
+        let found = resolved(|idx, _| match idx {
+            0 => probe(None, true, false),
+            _ => probe(Some((21, false)), false, false),
+        });
+        assert_eq!(found, None);
+
+        // Whiteouts don't hide entries in the same layer.
+        let found = resolved(|idx, _| match idx {
+            0 => probe(None, false, false),
+            1 => probe(Some((21, true)), true, false),
+            _ => probe(Some((31, true)), false, false),
+        });
+        assert_eq!(found, Some((1, vec![(1, 21)])));
+    }
+
+    #[test]
+    fn test_overlay_resolve_opaque() {
+        let found = resolved(|idx, _| match idx {
+            0 => probe(Some((11, true)), false, true),
+            _ => probe(Some((21, true)), false, false),
+        });
+        assert_eq!(found, Some((0, vec![(0, 11)])));
+
+        let found = resolved(|idx, _| match idx {
+            0 => probe(None, false, true),
+            _ => probe(Some((21, false)), false, false),
+        });
+        assert_eq!(found, None);
+    }
+}
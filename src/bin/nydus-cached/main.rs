@@ -17,7 +17,7 @@ use std::sync::{
 
 use clap::{App, Arg};
 use event_manager::{EventManager, EventOps, EventSubscriber, Events, SubscriberOps};
-use nydus_app::{dump_program_info, setup_logging, BuildTimeInfo};
+use nydus_app::{dump_program_info, install_panic_hook, setup_logging, BuildTimeInfo};
 use storage::remote::{RemoteBlobMgr, Server};
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
@@ -30,6 +30,7 @@ lazy_static! {
 }
 
 fn main() -> Result<()> {
+    install_panic_hook(&[]);
     let (bti_string, _bti) = BuildTimeInfo::dump(crate_version!());
 
     let cmd_arguments = App::new("")
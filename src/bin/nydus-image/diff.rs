@@ -0,0 +1,288 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Diff filesystem metadata trees of two RAFS images.
+//!
+//! Inodes of the parent and the target image are matched by path. An inode present in both images
+//! is modified if its attributes, xattrs, symlink target, digest or chunks differ. Chunks are
+//! compared by digest, location in blobs and blob id, so a chunk moved to another blob is a
+//! modification even though the file content is unchanged.
+//!
+//! Inodes are loaded without digest validation, so the diff is much cheaper than validating the
+//! whole target image.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rafs::metadata::layout::RAFS_ROOT_INODE;
+use rafs::metadata::{Inode, RafsInode, RafsSuper};
+use storage::device::BlobInfo;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug)]
+pub struct DiffEntry {
+    pub path: PathBuf,
+    pub kind: DiffKind,
+    /// Inode number in the target image, none for removed inodes.
+    pub ino: Option<Inode>,
+}
+
+/// Walk the target image and diff it against the parent image, return added and modified inodes
+/// of the target image, and removed inodes of the parent image.
+///
+/// Descendants of a removed directory are not reported.
+pub fn diff_trees(parent: &RafsSuper, target: &RafsSuper) -> Result<Vec<DiffEntry>> {
+    let differ = TreeDiffer {
+        parent,
+        parent_blobs: parent.superblock.get_blob_infos(),
+        target_blobs: target.superblock.get_blob_infos(),
+    };
+    let mut entries = Vec::new();
+
+    let parent_root = parent
+        .get_inode(RAFS_ROOT_INODE, false)
+        .context("failed to load root inode of parent")?;
+    let target_root = target
+        .get_inode(RAFS_ROOT_INODE, false)
+        .context("failed to load root inode")?;
+    let root = Path::new("/");
+    if !differ.same_inode(&parent_root, &target_root, root)? {
+        entries.push(DiffEntry {
+            path: root.to_path_buf(),
+            kind: DiffKind::Modified,
+            ino: Some(RAFS_ROOT_INODE),
+        });
+    }
+
+    let mut dirs = vec![(RAFS_ROOT_INODE, Some(RAFS_ROOT_INODE), root.to_path_buf())];
+    while let Some((ino, parent_ino, path)) = dirs.pop() {
+        let mut parent_children = match parent_ino {
+            Some(parent_ino) => differ.parent_children(parent_ino, &path)?,
+            None => HashMap::new(),
+        };
+        let dir = target
+            .get_inode(ino, false)
+            .with_context(|| format!("failed to load directory {:?}", path))?;
+        let child_index = dir.get_child_index()? as u64;
+        for idx in 0..dir.get_child_count() {
+            let child = target
+                .get_inode(child_index + idx as u64, false)
+                .with_context(|| format!("failed to load child {} of {:?}", idx, path))?;
+            let name = child.name();
+            let child_path = path.join(&name);
+
+            let mut parent_dir = None;
+            match parent_children.remove(&name) {
+                Some(p) => {
+                    if !differ.same_inode(&p, &child, &child_path)? {
+                        entries.push(DiffEntry {
+                            path: child_path.clone(),
+                            kind: DiffKind::Modified,
+                            ino: Some(child.ino()),
+                        });
+                    }
+                    if p.is_dir() {
+                        parent_dir = Some(p.ino());
+                    }
+                }
+                None => entries.push(DiffEntry {
+                    path: child_path.clone(),
+                    kind: DiffKind::Added,
+                    ino: Some(child.ino()),
+                }),
+            }
+            // Inode numbers of subdirectories always grow, so there's no loop.
+            if child.is_dir() && child.ino() > ino {
+                dirs.push((child.ino(), parent_dir, child_path));
+            }
+        }
+
+        let mut removed: Vec<OsString> = parent_children.into_iter().map(|(n, _)| n).collect();
+        removed.sort();
+        for name in removed {
+            entries.push(DiffEntry {
+                path: path.join(name),
+                kind: DiffKind::Removed,
+                ino: None,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+struct TreeDiffer<'a> {
+    parent: &'a RafsSuper,
+    parent_blobs: Vec<Arc<BlobInfo>>,
+    target_blobs: Vec<Arc<BlobInfo>>,
+}
+
+impl<'a> TreeDiffer<'a> {
+    fn parent_children(
+        &self,
+        ino: Inode,
+        path: &Path,
+    ) -> Result<HashMap<OsString, Arc<dyn RafsInode>>> {
+        let dir = self
+            .parent
+            .get_inode(ino, false)
+            .with_context(|| format!("failed to load directory {:?} of parent", path))?;
+        let child_index = dir.get_child_index()? as u64;
+        let mut children = HashMap::with_capacity(dir.get_child_count() as usize);
+        for idx in 0..dir.get_child_count() {
+            let child = self
+                .parent
+                .get_inode(child_index + idx as u64, false)
+                .with_context(|| format!("failed to load child {} of {:?} of parent", idx, path))?;
+            children.insert(child.name(), child);
+        }
+
+        Ok(children)
+    }
+
+    fn same_inode(
+        &self,
+        parent: &Arc<dyn RafsInode>,
+        target: &Arc<dyn RafsInode>,
+        path: &Path,
+    ) -> Result<bool> {
+        let (pa, ta) = (parent.get_attr(), target.get_attr());
+        if pa.mode != ta.mode
+            || pa.size != ta.size
+            || pa.uid != ta.uid
+            || pa.gid != ta.gid
+            || pa.mtime != ta.mtime
+            || pa.mtimensec != ta.mtimensec
+            || pa.rdev != ta.rdev
+            || pa.nlink != ta.nlink
+            || parent.flags() != target.flags()
+            || parent.get_digest() != target.get_digest()
+        {
+            return Ok(false);
+        }
+        if target.is_symlink() && parent.get_symlink()? != target.get_symlink()? {
+            return Ok(false);
+        }
+
+        if parent.has_xattr() != target.has_xattr() {
+            return Ok(false);
+        }
+        if target.has_xattr() {
+            let mut names = target.get_xattrs()?;
+            let mut parent_names = parent.get_xattrs()?;
+            names.sort();
+            parent_names.sort();
+            if names != parent_names {
+                return Ok(false);
+            }
+            for name in names.iter() {
+                let name = OsStr::from_bytes(name);
+                if parent.get_xattr(name)? != target.get_xattr(name)? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        if !target.is_reg() {
+            return Ok(true);
+        }
+        if parent.get_chunk_count() != target.get_chunk_count() {
+            return Ok(false);
+        }
+        for idx in 0..target.get_chunk_count() {
+            let pc = parent
+                .get_chunk_info(idx)
+                .with_context(|| format!("failed to load chunk {} of {:?} of parent", idx, path))?;
+            let tc = target
+                .get_chunk_info(idx)
+                .with_context(|| format!("failed to load chunk {} of {:?}", idx, path))?;
+            let parent_blob = self.parent_blobs.get(pc.blob_index() as usize);
+            let target_blob = self.target_blobs.get(tc.blob_index() as usize);
+            let same_blob = match (parent_blob, target_blob) {
+                (Some(p), Some(t)) => p.blob_id() == t.blob_id(),
+                // Leave invalid blob indexes to the validator.
+                _ => false,
+            };
+            if !same_blob
+                || pc.chunk_id() != tc.chunk_id()
+                || pc.compress_offset() != tc.compress_offset()
+                || pc.compress_size() != tc.compress_size()
+                || pc.uncompress_offset() != tc.uncompress_offset()
+                || pc.uncompress_size() != tc.uncompress_size()
+                || pc.is_compressed() != tc.is_compressed()
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus::builder::ImageBuilder;
+    use rafs::metadata::RafsMode;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_diff_trees() {
+        let lower = TempDir::new().unwrap();
+        std::fs::write(lower.as_path().join("same"), b"same data").unwrap();
+        std::fs::write(lower.as_path().join("foo"), b"foo data").unwrap();
+        std::fs::write(lower.as_path().join("bar"), b"bar data").unwrap();
+        let upper = TempDir::new().unwrap();
+        std::fs::write(upper.as_path().join("foo"), b"new foo data").unwrap();
+        std::fs::write(upper.as_path().join("baz"), b"baz data").unwrap();
+        std::fs::write(upper.as_path().join(".wh.bar"), b"").unwrap();
+
+        let output_dir = TempDir::new().unwrap();
+        let parent_bootstrap = output_dir.as_path().join("parent");
+        let bootstrap = output_dir.as_path().join("bootstrap");
+        ImageBuilder::new(lower.as_path())
+            .bootstrap(&parent_bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .unwrap();
+        ImageBuilder::new(upper.as_path())
+            .parent_bootstrap(&parent_bootstrap)
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .unwrap();
+
+        let parent = RafsSuper::load_from_metadata(
+            parent_bootstrap.to_str().unwrap(),
+            RafsMode::Direct,
+            false,
+        )
+        .unwrap();
+        let target =
+            RafsSuper::load_from_metadata(bootstrap.to_str().unwrap(), RafsMode::Direct, false)
+                .unwrap();
+        let entries = diff_trees(&parent, &target).unwrap();
+        let find = |path: &str| entries.iter().find(|e| e.path == Path::new(path));
+
+        assert_eq!(find("/foo").unwrap().kind, DiffKind::Modified);
+        assert_eq!(find("/baz").unwrap().kind, DiffKind::Added);
+        let removed = find("/bar").unwrap();
+        assert_eq!(removed.kind, DiffKind::Removed);
+        assert!(removed.ino.is_none());
+        assert!(find("/same").is_none());
+        assert!(find("/.wh.bar").is_none());
+
+        assert!(diff_trees(&target, &target).unwrap().is_empty());
+    }
+}
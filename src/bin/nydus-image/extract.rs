@@ -0,0 +1,381 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! List directories and read files of an image without mounting it.
+//!
+//! Paths are resolved by the bootstrap, and file data is fetched from data blobs in a local
+//! directory by the storage subsystem, which is handy for CI checks and debugging.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, ZeroCopyWriter};
+use fuse_backend_rs::transport::{FileReadWriteVolatile, FileVolatileSlice};
+use rafs::fs::{Rafs, RafsConfig};
+use rafs::RafsIoRead;
+
+const READ_BUF_SIZE: u32 = 0x10_0000;
+const READDIR_BUF_SIZE: u32 = 0x1000;
+
+const CTX: Context = Context {
+    uid: 0,
+    gid: 0,
+    pid: 0,
+};
+
+/// Load the image with bootstrap at `bootstrap` and data blobs in `blob_dir` as filesystem `id`.
+pub fn load_rafs(bootstrap: &Path, blob_dir: &Path, id: &str) -> Result<Rafs> {
+    let config = serde_json::json!({
+        "device": {
+            "backend": {
+                "type": "localfs",
+                "config": { "dir": blob_dir },
+            },
+        },
+        "mode": "direct",
+        "enable_xattr": true,
+    });
+    let config: RafsConfig =
+        serde_json::from_value(config).context("invalid rafs configuration")?;
+    let mut reader = <dyn RafsIoRead>::from_file(bootstrap)
+        .map_err(|e| anyhow!("failed to open bootstrap {:?}, {:?}", bootstrap, e))?;
+    let mut rafs = Rafs::new(config, id, &mut reader)
+        .map_err(|e| anyhow!("failed to load bootstrap {:?}, {:?}", bootstrap, e))?;
+    rafs.import(reader, None)
+        .map_err(|e| anyhow!("failed to import bootstrap {:?}, {:?}", bootstrap, e))?;
+
+    Ok(rafs)
+}
+
+// Writer to copy file data from the filesystem into an output stream.
+struct OutputWriter<'a> {
+    w: &'a mut dyn Write,
+}
+
+impl Write for OutputWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.w.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+impl ZeroCopyWriter for OutputWriter<'_> {
+    fn write_from(
+        &mut self,
+        f: &mut dyn FileReadWriteVolatile,
+        count: usize,
+        off: u64,
+    ) -> io::Result<usize> {
+        let mut buf = vec![0u8; count];
+        // Safe because the buffer is allocated with `count` bytes above.
+        let slice = unsafe { FileVolatileSlice::new(buf.as_mut_ptr(), count) };
+        let cnt = f.read_vectored_at_volatile(&[slice], off)?;
+        self.w.write_all(&buf[..cnt])?;
+        Ok(cnt)
+    }
+}
+
+/// An entry of the image listed by `ls`.
+#[derive(Debug, PartialEq)]
+pub struct ListEntry {
+    pub name: String,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Target of symlinks.
+    pub target: Option<String>,
+}
+
+impl ListEntry {
+    /// Format the entry like `ls -l`, without timestamps.
+    pub fn format(&self) -> String {
+        let mut s = format!(
+            "{} {:>6} {:>6} {:>12} {}",
+            mode_string(self.mode),
+            self.uid,
+            self.gid,
+            self.size,
+            self.name
+        );
+        if let Some(target) = self.target.as_ref() {
+            s.push_str(" -> ");
+            s.push_str(target);
+        }
+        s
+    }
+}
+
+fn mode_string(mode: u32) -> String {
+    let kind = match mode & libc::S_IFMT {
+        libc::S_IFDIR => 'd',
+        libc::S_IFLNK => 'l',
+        libc::S_IFCHR => 'c',
+        libc::S_IFBLK => 'b',
+        libc::S_IFIFO => 'p',
+        libc::S_IFSOCK => 's',
+        _ => '-',
+    };
+    let mut s = String::with_capacity(10);
+    s.push(kind);
+    for (shift, special, set, unset) in [
+        (6, libc::S_ISUID, 's', 'S'),
+        (3, libc::S_ISGID, 's', 'S'),
+        (0, libc::S_ISVTX, 't', 'T'),
+    ]
+    .iter()
+    {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => *set,
+            (false, true) => *unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+/// Reader of files and directories in an image.
+pub struct ImageReader {
+    rafs: Rafs,
+}
+
+impl ImageReader {
+    pub fn new(bootstrap: &Path, blob_dir: &Path) -> Result<Self> {
+        let rafs = load_rafs(bootstrap, blob_dir, "/")?;
+        Ok(ImageReader { rafs })
+    }
+
+    fn lookup(&self, path: &str) -> Result<(u64, libc::stat64)> {
+        let ino = self
+            .rafs
+            .ino_from_path(Path::new(path))
+            .with_context(|| format!("{} isn't found in image", path))?;
+        let (attr, _) = self
+            .rafs
+            .getattr(&CTX, ino, None)
+            .with_context(|| format!("failed to get attributes of {}", path))?;
+        Ok((ino, attr))
+    }
+
+    fn list_entry(&self, ino: u64, name: String, attr: &libc::stat64) -> Result<ListEntry> {
+        let target = if attr.st_mode & libc::S_IFMT == libc::S_IFLNK {
+            let target = self
+                .rafs
+                .readlink(&CTX, ino)
+                .with_context(|| format!("failed to read symlink {}", name))?;
+            Some(String::from_utf8_lossy(&target).to_string())
+        } else {
+            None
+        };
+
+        Ok(ListEntry {
+            name,
+            mode: attr.st_mode,
+            uid: attr.st_uid,
+            gid: attr.st_gid,
+            size: attr.st_size as u64,
+            target,
+        })
+    }
+
+    /// List entries of the directory at `path` sorted by name, or the entry itself if it isn't a
+    /// directory.
+    pub fn list(&self, path: &str) -> Result<Vec<ListEntry>> {
+        let (ino, attr) = self.lookup(path)?;
+        if attr.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            return Ok(vec![self.list_entry(ino, path.to_string(), &attr)?]);
+        }
+
+        let mut children = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut added = 0;
+            self.rafs
+                .readdirplus(
+                    &CTX,
+                    ino,
+                    0,
+                    READDIR_BUF_SIZE,
+                    offset,
+                    &mut |dir_entry, entry| {
+                        offset = dir_entry.offset;
+                        added += 1;
+                        if dir_entry.name != b"." && dir_entry.name != b".." {
+                            let name = String::from_utf8_lossy(dir_entry.name).to_string();
+                            children.push((name, entry.inode, entry.attr));
+                        }
+                        Ok(dir_entry.name.len())
+                    },
+                )
+                .with_context(|| format!("failed to read directory {}", path))?;
+            if added == 0 {
+                break;
+            }
+        }
+
+        let mut entries = Vec::with_capacity(children.len());
+        for (name, ino, attr) in children {
+            entries.push(self.list_entry(ino, name, &attr)?);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(entries)
+    }
+
+    fn lookup_file(&self, path: &str) -> Result<(u64, u64)> {
+        let (ino, attr) = self.lookup(path)?;
+        match attr.st_mode & libc::S_IFMT {
+            libc::S_IFREG => Ok((ino, attr.st_size as u64)),
+            libc::S_IFDIR => bail!("{} is a directory", path),
+            _ => bail!("{} isn't a regular file", path),
+        }
+    }
+
+    // Copy data of file `ino` in range [offset, end) into `w`.
+    fn copy_range(
+        &self,
+        path: &str,
+        ino: u64,
+        mut offset: u64,
+        end: u64,
+        w: &mut dyn Write,
+    ) -> Result<()> {
+        let mut writer = OutputWriter { w };
+        while offset < end {
+            let size = std::cmp::min(end - offset, READ_BUF_SIZE as u64) as u32;
+            let cnt = self
+                .rafs
+                .read(&CTX, ino, 0, &mut writer, size, offset, None, 0)
+                .with_context(|| format!("failed to read {} at offset {}", path, offset))?;
+            if cnt == 0 {
+                bail!("unexpected end of {} at offset {}", path, offset);
+            }
+            offset += cnt as u64;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Write content of the regular file at `path` into `w`, and return its size.
+    pub fn cat(&self, path: &str, w: &mut dyn Write) -> Result<u64> {
+        let (ino, size) = self.lookup_file(path)?;
+        self.copy_range(path, ino, 0, size, w)?;
+
+        Ok(size)
+    }
+
+    /// Copy the regular file at `path` into a new file at `target`, and return its size.
+    ///
+    /// Only data ranges reported by `SEEK_DATA/SEEK_HOLE` are written, so holes of sparse files
+    /// are kept as holes in `target` instead of being filled with zeros.
+    pub fn extract(&self, path: &str, target: &Path) -> Result<u64> {
+        let (ino, size) = self.lookup_file(path)?;
+        let mut file =
+            File::create(target).with_context(|| format!("failed to create {:?}", target))?;
+
+        let mut offset = 0;
+        while offset < size {
+            let start = match self
+                .rafs
+                .lseek(&CTX, ino, 0, offset, libc::SEEK_DATA as u32)
+            {
+                Ok(start) => start,
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("failed to seek data of {} at {}", path, offset))
+                }
+            };
+            let end = self
+                .rafs
+                .lseek(&CTX, ino, 0, start, libc::SEEK_HOLE as u32)
+                .with_context(|| format!("failed to seek hole of {} at {}", path, start))?;
+            file.seek(SeekFrom::Start(start))
+                .with_context(|| format!("failed to seek {:?}", target))?;
+            self.copy_range(path, ino, start, end, &mut file)?;
+            offset = end;
+        }
+        // Trailing holes are created by extending the file.
+        file.set_len(size)
+            .with_context(|| format!("failed to set size of {:?}", target))?;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus::builder::ImageBuilder;
+    use std::os::unix::fs::MetadataExt;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_list_entry_format() {
+        assert_eq!(mode_string(libc::S_IFDIR | 0o755), "drwxr-xr-x");
+        assert_eq!(mode_string(libc::S_IFREG | 0o4755), "-rwsr-xr-x");
+        assert_eq!(mode_string(libc::S_IFREG | 0o2644), "-rw-r-Sr--");
+        assert_eq!(mode_string(libc::S_IFDIR | 0o1777), "drwxrwxrwt");
+
+        let entry = ListEntry {
+            name: "lib".to_string(),
+            mode: libc::S_IFLNK | 0o777,
+            uid: 0,
+            gid: 0,
+            size: 7,
+            target: Some("usr/lib".to_string()),
+        };
+        assert_eq!(
+            entry.format(),
+            "lrwxrwxrwx      0      0            7 lib -> usr/lib"
+        );
+    }
+
+    #[test]
+    fn test_extract_sparse_file() {
+        let source = TempDir::new().unwrap();
+        let file = source.as_path().join("sparse");
+        {
+            let mut f = File::create(&file).unwrap();
+            f.write_all(&[0x5a; 0x1000]).unwrap();
+            f.seek(SeekFrom::Start(0x100000)).unwrap();
+            f.write_all(&[0xa5; 0x800]).unwrap();
+            f.set_len(0x200000).unwrap();
+        }
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+        ImageBuilder::new(source.as_path())
+            .chunk_size(0x1000)
+            .sparse_file(true)
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .unwrap();
+
+        let reader = ImageReader::new(&bootstrap, output_dir.as_path()).unwrap();
+        let target = output_dir.as_path().join("extracted");
+        assert_eq!(reader.extract("/sparse", &target).unwrap(), 0x200000);
+        assert_eq!(
+            std::fs::read(&target).unwrap(),
+            std::fs::read(&file).unwrap()
+        );
+        // Only the two data chunks are allocated.
+        let blocks = std::fs::metadata(&target).unwrap().blocks();
+        assert!(blocks * 512 < 0x10000, "{} blocks allocated", blocks);
+
+        let mut data = Vec::new();
+        assert_eq!(reader.cat("/sparse", &mut data).unwrap(), 0x200000);
+        assert_eq!(data, std::fs::read(&file).unwrap());
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Garbage collection of blob directories.
+//!
+//! A blob directory shared by many builds accumulates data blobs and bootstraps of stale images.
+//! Blobs referenced by a set of live bootstraps are kept, and other blobs and bootstraps which
+//! are older than the retention window are removed.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use rafs::metadata::{RafsMode, RafsSuper};
+use serde::Serialize;
+
+/// Kind of artifacts found in the blob directory.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Blob,
+    Bootstrap,
+}
+
+/// An unreferenced artifact to be removed.
+#[derive(Clone, Debug, Serialize)]
+pub struct GcEntry {
+    pub path: PathBuf,
+    pub kind: ArtifactKind,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// Number of blobs referenced by live bootstraps.
+    pub live_blobs: usize,
+    /// Unreferenced artifacts which have been removed, or would be removed on dry run.
+    pub removed: Vec<GcEntry>,
+    /// Number of unreferenced artifacts kept for being newer than the retention window.
+    pub retained: usize,
+    /// Number of files skipped for being neither a blob nor a bootstrap.
+    pub skipped: usize,
+    pub reclaimed_bytes: u64,
+}
+
+pub struct BlobGc {
+    blob_dir: PathBuf,
+    retention: Duration,
+    dry_run: bool,
+    live_blobs: HashSet<String>,
+    live_bootstraps: HashSet<PathBuf>,
+}
+
+impl BlobGc {
+    pub fn new(blob_dir: &Path, retention: Duration, dry_run: bool) -> Self {
+        Self {
+            blob_dir: blob_dir.to_path_buf(),
+            retention,
+            dry_run,
+            live_blobs: HashSet::new(),
+            live_bootstraps: HashSet::new(),
+        }
+    }
+
+    /// Mark all blobs referenced by the bootstrap as live.
+    pub fn add_live_bootstrap(&mut self, path: &Path) -> Result<()> {
+        let sb = load_bootstrap(path)
+            .with_context(|| format!("failed to load live bootstrap {:?}", path))?;
+        for blob in sb.superblock.get_blob_infos() {
+            self.live_blobs.insert(blob.blob_id().to_string());
+        }
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize {:?}", path))?;
+        self.live_bootstraps.insert(path);
+
+        Ok(())
+    }
+
+    /// Remove unreferenced artifacts, or only collect them if in dry run mode.
+    pub fn collect(&self) -> Result<GcReport> {
+        if self.live_bootstraps.is_empty() {
+            bail!("no live bootstrap specified, refusing to collect all blobs");
+        }
+
+        let mut report = GcReport {
+            live_blobs: self.live_blobs.len(),
+            ..Default::default()
+        };
+        let now = SystemTime::now();
+        let entries = fs::read_dir(&self.blob_dir)
+            .with_context(|| format!("failed to read dir {:?}", self.blob_dir))?;
+
+        for entry in entries {
+            let entry = entry?;
+            let md = entry.metadata()?;
+            if !md.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let name = entry.file_name();
+            if let Some(name) = name.to_str() {
+                if self.live_blobs.contains(name) {
+                    continue;
+                }
+            }
+            if self.live_bootstraps.contains(&path.canonicalize()?) {
+                continue;
+            }
+
+            let kind = match classify(&path, &name.to_string_lossy()) {
+                Some(kind) => kind,
+                None => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            // Files with modification time in the future are always retained.
+            let age = now.duration_since(md.modified()?).unwrap_or_default();
+            if age < self.retention {
+                report.retained += 1;
+                continue;
+            }
+
+            if !self.dry_run {
+                fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+            }
+            report.reclaimed_bytes += md.len();
+            report.removed.push(GcEntry {
+                path,
+                kind,
+                size: md.len(),
+            });
+        }
+
+        Ok(report)
+    }
+}
+
+fn load_bootstrap(path: &Path) -> Result<RafsSuper> {
+    let path = path
+        .to_str()
+        .with_context(|| format!("invalid bootstrap path {:?}", path))?;
+    let sb = RafsSuper::load_from_metadata(path, RafsMode::Direct, false)?;
+    Ok(sb)
+}
+
+/// Data blobs are named by hex digests, and files with other names are only collected if they
+/// are RAFS bootstraps, so unrelated files in the directory are never removed.
+fn classify(path: &Path, name: &str) -> Option<ArtifactKind> {
+    if name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(ArtifactKind::Blob)
+    } else if load_bootstrap(path).is_ok() {
+        Some(ArtifactKind::Bootstrap)
+    } else {
+        None
+    }
+}
+
+/// Parse retention window like `3600`, `30m`, `12h` or `7d`, in seconds if no unit given.
+pub fn parse_retention(s: &str) -> Result<Duration> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+    let num = num
+        .parse::<u64>()
+        .with_context(|| format!("invalid retention {}", s))?;
+    let secs = match unit {
+        "s" => num,
+        "m" => num * 60,
+        "h" => num * 3600,
+        "d" => num * 86400,
+        _ => bail!("invalid unit of retention {}", s),
+    };
+
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_parse_retention() {
+        assert_eq!(parse_retention("0").unwrap(), Duration::from_secs(0));
+        assert_eq!(parse_retention("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_retention("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_retention("12h").unwrap(), Duration::from_secs(43200));
+        assert_eq!(parse_retention("7d").unwrap(), Duration::from_secs(604800));
+        assert!(parse_retention("").is_err());
+        assert!(parse_retention("h").is_err());
+        assert!(parse_retention("7w").is_err());
+        assert!(parse_retention("-1").is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let dir = TempDir::new().unwrap();
+        let blob = dir.as_path().join("a".repeat(64));
+        fs::write(&blob, b"blob").unwrap();
+        assert_eq!(classify(&blob, &"a".repeat(64)), Some(ArtifactKind::Blob));
+
+        let other = dir.as_path().join("notes.txt");
+        fs::write(&other, b"not a bootstrap").unwrap();
+        assert_eq!(classify(&other, "notes.txt"), None);
+        assert_eq!(classify(&other, &"g".repeat(64)), None);
+    }
+
+    #[test]
+    fn test_collect_without_live_bootstrap() {
+        let dir = TempDir::new().unwrap();
+        let gc = BlobGc::new(dir.as_path(), Duration::from_secs(0), true);
+        assert!(gc.collect().is_err());
+    }
+}
@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::Permissions;
 use std::io::Write;
@@ -11,6 +12,7 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
+use serde::Serialize;
 use serde_json::Value;
 
 use rafs::metadata::layout::v5::{
@@ -19,10 +21,12 @@ use rafs::metadata::layout::v5::{
 };
 use rafs::metadata::RafsSuperFlags;
 use rafs::{RafsIoRead, RafsIoReader};
+use storage::device::BlobChunkFlags;
 use storage::RAFS_DEFAULT_CHUNK_SIZE;
 
-use crate::core::context::RafsVersion;
-use crate::core::node::InodeWrapper;
+use nydus::builder::core::chunk_dict::import_chunk_dict;
+use nydus::builder::core::context::RafsVersion;
+use nydus::builder::core::node::InodeWrapper;
 
 /// | Superblock | inode table | prefetch table |inode + name + symlink pointer + xattr size + xattr pairs + chunk info
 #[allow(dead_code)]
@@ -85,6 +89,115 @@ pub enum Action {
     Continue,
 }
 
+/// Version of the JSON schema generated by `RafsInspector::export()`, it must be increased on
+/// incompatible changes, such as renaming or removing fields.
+pub(crate) const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Machine readable dump of the bootstrap metadata.
+#[derive(Serialize)]
+struct ExportedRafs {
+    schema_version: u32,
+    superblock: ExportedSuperBlock,
+    counts: ExportedCounts,
+    blobs: Vec<ExportedBlob>,
+    prefetch: Vec<ExportedPrefetchEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes: Option<Vec<ExportedInode>>,
+}
+
+#[derive(Serialize)]
+struct ExportedSuperBlock {
+    rafs_version: String,
+    fs_version: u32,
+    chunk_size: u32,
+    flags: String,
+    inodes_count: u64,
+    inode_table_offset: u64,
+    inode_table_entries: u32,
+    prefetch_table_offset: u64,
+    prefetch_table_entries: u32,
+    blob_table_offset: u64,
+    blob_table_size: u32,
+    extended_blob_table_offset: u64,
+    extended_blob_table_entries: u32,
+}
+
+#[derive(Serialize)]
+struct ExportedCounts {
+    inodes: u64,
+    blobs: usize,
+    prefetch_entries: usize,
+}
+
+#[derive(Serialize)]
+struct ExportedBlob {
+    index: usize,
+    blob_id: String,
+    readahead_offset: u64,
+    readahead_size: u64,
+    /// Only available if the bootstrap has the extended blob table.
+    chunk_count: Option<u32>,
+    compressed_size: Option<u64>,
+    decompressed_size: Option<u64>,
+    /// Only available if recorded by the builder.
+    build_time: Option<u64>,
+    source_digest: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportedPrefetchEntry {
+    inode: u32,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ExportedInode {
+    inode: u64,
+    path: PathBuf,
+    mode: u32,
+    size: u64,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u64,
+    digest: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    chunks: Vec<ExportedChunk>,
+}
+
+#[derive(Serialize)]
+struct ExportedChunk {
+    index: u32,
+    file_offset: u64,
+    blob_index: u32,
+    compressed_offset: u64,
+    compressed_size: u32,
+    decompressed_offset: u64,
+    decompressed_size: u32,
+    compressed: bool,
+    digest: String,
+}
+
+/// Chunk deduplication statistics of a single blob.
+#[derive(Default, Serialize)]
+struct BlobDedupStat {
+    blob_id: String,
+    /// Number of chunk references from files to the blob.
+    total_chunks: u64,
+    /// Number of distinct chunks referenced from the blob.
+    unique_chunks: u64,
+    /// Uncompressed size of distinct chunks.
+    unique_bytes: u64,
+    /// Number of chunk references duplicated with another reference within the image.
+    duplicated_chunks: u64,
+    /// Uncompressed size of duplicated chunk references.
+    duplicated_bytes: u64,
+    /// Number of distinct chunks which also exist in the chunk dictionary.
+    dict_chunks: u64,
+    /// Compressed size of distinct chunks which also exist in the chunk dictionary.
+    dict_bytes: u64,
+}
+
 struct RafsV5State {
     inodes_table: RafsV5InodeTable,
     blobs_table: RafsV5BlobTable,
@@ -159,7 +272,7 @@ impl RafsInspector {
 
     /// Index is u32, by which the inode can be found.
     /// NOTE: `index` is inode index within inodes table, which equals to inode number plus ONE
-    fn load_inode_by_index(&self, index: usize) -> Result<(InodeWrapper, OsString)> {
+    pub(crate) fn load_inode_by_index(&self, index: usize) -> Result<(InodeWrapper, OsString)> {
         match self.rafs_meta.version {
             RafsVersion::V5 => self.load_ondisk_inode_v5(index),
             RafsVersion::V6 => todo!(),
@@ -218,6 +331,7 @@ UID:                {uid}
 GID:                {gid}
 Mtime:              {mtime}
 MtimeNsec:          {mtime_nsec}
+Btime:              {btime}
 Blocks:             {blocks}"#,
             inode_number = inode.ino(),
             name = name,
@@ -231,18 +345,30 @@ Blocks:             {blocks}"#,
             gid = inode.gid(),
             mtime = inode.mtime(),
             mtime_nsec = inode.mtime_nsec(),
+            btime = inode.btime(),
             blocks = inode.blocks(),
         );
     }
 
     pub fn iter_dir(
         &self,
+        op: impl FnMut(&OsStr, &InodeWrapper, u32, u32) -> Action,
+    ) -> Result<()> {
+        self.iter_children(self.cur_dir_index, op)
+    }
+
+    pub(crate) fn iter_children(
+        &self,
+        dir_index: u32,
         mut op: impl FnMut(&OsStr, &InodeWrapper, u32, u32) -> Action,
     ) -> Result<()> {
-        let (dir_inode, _) = self.load_inode_by_index(self.cur_dir_index as usize)?;
+        let (dir_inode, _) = self.load_inode_by_index(dir_index as usize)?;
         let parent_ino = dir_inode.ino();
 
         let children_count = dir_inode.child_count();
+        if !dir_inode.is_dir() || children_count == 0 {
+            return Ok(());
+        }
         // Somehow, the it has subtract 1 to identify the first child file's index in inode table.
         let first_index = dir_inode.child_index() - 1;
         let last_index = first_index + children_count - 1;
@@ -268,7 +394,7 @@ Blocks:             {blocks}"#,
         Ok(())
     }
 
-    fn walk_fs(
+    pub(crate) fn walk_fs(
         &self,
         top_index: u32,
         op: &mut dyn FnMut(&OsStr, &InodeWrapper, u32, u32) -> Action,
@@ -310,7 +436,68 @@ Blocks:             {blocks}"#,
         Ok(())
     }
 
-    fn path_from_ino(&self, mut ino: u64) -> Result<PathBuf> {
+    /// Resolve `path` to index and offset of its inode, relative paths are resolved from the
+    /// current directory.
+    fn lookup_path(&self, path: &str) -> Result<(InodeWrapper, u32, u32)> {
+        let mut index = if path.starts_with('/') {
+            0
+        } else {
+            self.cur_dir_index
+        };
+
+        for name in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            if name == ".." {
+                let (inode, _) = self.load_inode_by_index(index as usize)?;
+                if inode.parent() != 0 {
+                    index = inode.parent() as u32 - 1;
+                }
+                continue;
+            }
+
+            let mut found = None;
+            self.iter_children(index, |f, _inode, idx, _offset| {
+                if f == OsStr::new(name) {
+                    found = Some(idx);
+                    return Action::Break;
+                }
+                Action::Continue
+            })?;
+            index = found.ok_or_else(|| anyhow!("file {:?} does not exist", path))?;
+        }
+
+        let (inode, _) = self.load_inode_by_index(index as usize)?;
+
+        Ok((inode, index, self.inode_offset(index)))
+    }
+
+    /// Get offset of the inode with `index` in the bootstrap.
+    pub(crate) fn inode_offset(&self, index: u32) -> u32 {
+        match &self.state {
+            RafsState::V5(s) => s.inodes_table.data[index as usize] << 3,
+        }
+    }
+
+    /// Load chunks of a regular file together with IDs of blobs containing them.
+    pub(crate) fn load_file_chunks(
+        &self,
+        inode: &InodeWrapper,
+        offset: u32,
+    ) -> Result<Vec<(RafsV5ChunkInfo, String)>> {
+        let chunks = {
+            let mut guard = self.bootstrap.lock().unwrap();
+            Self::list_chunks(guard.deref_mut(), inode, offset)?.unwrap_or_default()
+        };
+
+        chunks
+            .into_iter()
+            .map(|c| {
+                let blob_id = self.state.get_blob_id(c.blob_index)?;
+                Ok((c, blob_id))
+            })
+            .collect()
+    }
+
+    pub(crate) fn path_from_ino(&self, mut ino: u64) -> Result<PathBuf> {
         let mut path = PathBuf::new();
         let mut entries = Vec::<PathBuf>::new();
 
@@ -374,6 +561,105 @@ Blocks:             {blocks}"#,
         Ok(None)
     }
 
+    fn cmd_dedup(&self, chunk_dict: Option<&str>) -> Result<Option<Value>> {
+        let dict = chunk_dict.map(import_chunk_dict).transpose()?;
+        let b = self.bootstrap.clone();
+        let mut stats: BTreeMap<u32, BlobDedupStat> = BTreeMap::new();
+        let mut chunks_seen = HashSet::new();
+        // Digests of chunks seen per blob, to count unique chunks per blob.
+        let mut blob_chunks_seen: HashMap<u32, HashSet<_>> = HashMap::new();
+        // Hardlinks share the same inode number and chunks, only count them once.
+        let mut inodes_seen = HashSet::new();
+        let mut error = None;
+
+        self.walk_fs(0, &mut |_name, inode, _index, offset| {
+            if !inode.is_reg() || !inodes_seen.insert(inode.ino()) {
+                return Action::Continue;
+            }
+
+            let mut guard = b.lock().unwrap();
+            let chunks = match Self::list_chunks(&mut *guard, inode, offset) {
+                Ok(Some(chunks)) => chunks,
+                Ok(None) => return Action::Continue,
+                Err(e) => {
+                    error = Some(e);
+                    return Action::Break;
+                }
+            };
+            drop(guard);
+
+            for c in chunks {
+                let stat = stats.entry(c.blob_index).or_default();
+                stat.total_chunks += 1;
+                if !chunks_seen.insert(c.block_id) {
+                    stat.duplicated_chunks += 1;
+                    stat.duplicated_bytes += c.uncompress_size as u64;
+                }
+                if blob_chunks_seen
+                    .entry(c.blob_index)
+                    .or_default()
+                    .insert(c.block_id)
+                {
+                    stat.unique_chunks += 1;
+                    stat.unique_bytes += c.uncompress_size as u64;
+                    if let Some(dict) = dict.as_ref() {
+                        if dict.get_chunk(&c.block_id).is_some() {
+                            stat.dict_chunks += 1;
+                            stat.dict_bytes += c.compress_size as u64;
+                        }
+                    }
+                }
+            }
+
+            Action::Continue
+        })?;
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let mut result = Vec::new();
+        for (blob_index, mut stat) in stats {
+            stat.blob_id = self.state.get_blob_id(blob_index)?;
+            result.push(stat);
+        }
+
+        let o = if self.request_mode {
+            Some(serde_json::to_value(&result)?)
+        } else {
+            for stat in result.iter() {
+                print!(
+                    r#"
+    Blob ID:            {blob_id}
+    Total Chunks:       {total_chunks}
+    Unique Chunks:      {unique_chunks}
+    Unique Bytes:       {unique_bytes}
+    Duplicated Chunks:  {duplicated_chunks}
+    Duplicated Bytes:   {duplicated_bytes}
+"#,
+                    blob_id = stat.blob_id,
+                    total_chunks = stat.total_chunks,
+                    unique_chunks = stat.unique_chunks,
+                    unique_bytes = stat.unique_bytes,
+                    duplicated_chunks = stat.duplicated_chunks,
+                    duplicated_bytes = stat.duplicated_bytes,
+                );
+                if dict.is_some() {
+                    print!(
+                        r#"    Dict Chunks:        {dict_chunks}
+    Dict Bytes:         {dict_bytes}
+"#,
+                        dict_chunks = stat.dict_chunks,
+                        dict_bytes = stat.dict_bytes,
+                    );
+                }
+            }
+            None
+        };
+
+        Ok(o)
+    }
+
     fn cmd_check_inode(&self, ino: u64) -> Result<Option<Value>> {
         self.walk_fs(0, &mut |name, inode, index, _offset| {
             // Not expect poisoned lock
@@ -424,17 +710,20 @@ Blocks:             {blocks}"#,
         Ok(None)
     }
 
-    fn cmd_list_prefetch(&mut self) -> Result<Option<Value>> {
+    fn load_prefetch_table(&self) -> Result<RafsV5PrefetchTable> {
         let mut pt = RafsV5PrefetchTable::new();
         let mut guard = self.bootstrap.lock().unwrap();
-        let bootstrap = guard.deref_mut();
         pt.load_prefetch_table_from(
-            bootstrap,
+            guard.deref_mut(),
             self.rafs_meta.prefetch_table_offset,
             self.rafs_meta.prefetch_table_entries as usize,
         )?;
 
-        drop(guard);
+        Ok(pt)
+    }
+
+    fn cmd_list_prefetch(&mut self) -> Result<Option<Value>> {
+        let pt = self.load_prefetch_table()?;
 
         let o = if self.request_mode {
             let mut value = json!([]);
@@ -524,6 +813,70 @@ Blocks:             {blocks}"#,
         Ok(None)
     }
 
+    fn cmd_show_file_chunks(&self, path: &str) -> Result<Option<Value>> {
+        let (inode, _index, offset) = self.lookup_path(path)?;
+        if !inode.is_reg() {
+            bail!("file {:?} is not a regular file", path);
+        }
+        let chunks = self.load_file_chunks(&inode, offset)?;
+
+        let o = if self.request_mode {
+            let mut value = json!([]);
+            for (c, blob_id) in chunks.iter() {
+                let v = json!({"index": c.index, "file_offset": c.file_offset,
+                    "blob_index": c.blob_index, "blob_id": blob_id,
+                    "compressed_offset": c.compress_offset, "compressed_size": c.compress_size,
+                    "decompressed_offset": c.uncompress_offset, "decompressed_size": c.uncompress_size,
+                    "compressed": c.flags.contains(BlobChunkFlags::COMPRESSED),
+                    "digest": c.block_id.to_string()});
+                value.as_array_mut().unwrap().push(v);
+            }
+            Some(json!({"path": path, "inode": inode.ino(), "size": inode.size(), "chunks": value}))
+        } else {
+            println!(
+                r#"
+    Path:               {path}
+    Inode Number:       {inode_number}
+    Size:               {size}
+    Chunks:             {chunks}
+"#,
+                path = path,
+                inode_number = inode.ino(),
+                size = inode.size(),
+                chunks = chunks.len(),
+            );
+            println!(
+                "{:>8} {:>12} {:>12} {:>10} {:>12} {:>11} {:>10}  {:64}  {:64}",
+                "Index",
+                "File Offset",
+                "Comp Offset",
+                "Comp Size",
+                "Decomp Off",
+                "Decomp Size",
+                "Compressed",
+                "Blob ID",
+                "Digest"
+            );
+            for (c, blob_id) in chunks.iter() {
+                println!(
+                    "{:>8} {:>12} {:>12} {:>10} {:>12} {:>11} {:>10}  {:64}  {}",
+                    c.index,
+                    c.file_offset,
+                    c.compress_offset,
+                    c.compress_size,
+                    c.uncompress_offset,
+                    c.uncompress_size,
+                    c.flags.contains(BlobChunkFlags::COMPRESSED),
+                    blob_id,
+                    c.block_id
+                );
+            }
+            None
+        };
+
+        Ok(o)
+    }
+
     fn cmd_change_dir(&mut self, name: &str) -> Result<Option<Value>> {
         if name == "." {
             return Ok(None);
@@ -613,9 +966,12 @@ Blocks:             {blocks}"#,
                         } else {
                             (None, None)
                         };
+                        let provenance =
+                            extended.as_ref().and_then(|et| et.entries[i].provenance());
 
                         let v = json!({"blob_id": b.blob_id(), "readahead_offset": b.readahead_offset(),
-                "readahead_size":b.readahead_size(), "decompressed_size": decompressed_size, "compressed_size": compressed_size});
+                "readahead_size":b.readahead_size(), "decompressed_size": decompressed_size, "compressed_size": compressed_size,
+                "build_time": provenance.as_ref().map(|p| p.build_time), "source_digest": provenance.map(|p| p.source_digest.to_string())});
                         value.as_array_mut().unwrap().push(v);
                     }
                     Some(value)
@@ -641,6 +997,18 @@ Blocks:             {blocks}"#,
                                 compressed_size = et.entries[i].compressed_size
                             )
                         }
+
+                        if let Some(provenance) =
+                            extended.as_ref().and_then(|et| et.entries[i].provenance())
+                        {
+                            print!(
+                                r#"Build Time:         {build_time}
+    Source Digest:      {source_digest}
+    "#,
+                                build_time = provenance.build_time,
+                                source_digest = provenance.source_digest
+                            )
+                        }
                     }
                     None
                 };
@@ -652,6 +1020,159 @@ Blocks:             {blocks}"#,
 }
 
 impl RafsInspector {
+    /// Export superblock, blob table and prefetch table of the bootstrap as a versioned JSON
+    /// document, together with all inodes and their chunks if `include_inodes` is true.
+    pub fn export(&self, include_inodes: bool) -> Result<Value> {
+        let meta = &self.rafs_meta;
+        let superblock = ExportedSuperBlock {
+            rafs_version: match meta.version {
+                RafsVersion::V5 => "v5".to_string(),
+                RafsVersion::V6 => "v6".to_string(),
+            },
+            fs_version: meta.fs_version,
+            chunk_size: meta.chunk_size,
+            flags: meta.flags.to_string(),
+            inodes_count: meta.inodes_count,
+            inode_table_offset: meta.inode_table_offset,
+            inode_table_entries: meta.inode_table_entries,
+            prefetch_table_offset: meta.prefetch_table_offset,
+            prefetch_table_entries: meta.prefetch_table_entries,
+            blob_table_offset: meta.blob_table_offset,
+            blob_table_size: meta.blob_table_size,
+            extended_blob_table_offset: meta.extended_blob_table_offset,
+            extended_blob_table_entries: meta.extended_blob_table_entries,
+        };
+
+        let blobs = match &self.state {
+            RafsState::V5(s) => s
+                .blobs_table
+                .entries
+                .iter()
+                .enumerate()
+                .map(|(index, b)| {
+                    let ext = s
+                        .extended_blobs_table
+                        .as_ref()
+                        .and_then(|et| et.entries.get(index));
+                    let provenance = ext.and_then(|e| e.provenance());
+                    ExportedBlob {
+                        index,
+                        blob_id: b.blob_id().to_string(),
+                        readahead_offset: b.readahead_offset(),
+                        readahead_size: b.readahead_size(),
+                        chunk_count: ext.map(|e| e.chunk_count),
+                        compressed_size: ext.map(|e| e.compressed_size),
+                        decompressed_size: ext.map(|e| e.uncompressed_size),
+                        build_time: provenance.as_ref().map(|p| p.build_time),
+                        source_digest: provenance.map(|p| p.source_digest.to_string()),
+                    }
+                })
+                .collect::<Vec<_>>(),
+        };
+
+        let prefetch = self
+            .load_prefetch_table()?
+            .inodes
+            .into_iter()
+            .map(|ino| {
+                Ok(ExportedPrefetchEntry {
+                    inode: ino,
+                    path: self.path_from_ino(ino as u64)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let inodes = if include_inodes {
+            Some(self.export_inodes()?)
+        } else {
+            None
+        };
+
+        let exported = ExportedRafs {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            superblock,
+            counts: ExportedCounts {
+                inodes: meta.inodes_count,
+                blobs: blobs.len(),
+                prefetch_entries: prefetch.len(),
+            },
+            blobs,
+            prefetch,
+            inodes,
+        };
+
+        Ok(serde_json::to_value(exported)?)
+    }
+
+    fn export_inodes(&self) -> Result<Vec<ExportedInode>> {
+        let (root, _) = self.load_inode_by_index(0)?;
+        let mut inodes =
+            vec![self.export_inode(&root, PathBuf::from("/"), self.inode_offset(0))?];
+        if root.child_count() == 0 {
+            return Ok(inodes);
+        }
+
+        // Walk the tree instead of the inode table, so all hardlinks are exported.
+        let mut result = Ok(());
+        self.walk_fs(0, &mut |name, inode, _index, offset| {
+            let exported = self
+                .path_from_ino(inode.parent())
+                .and_then(|p| self.export_inode(inode, Path::new("/").join(p).join(name), offset));
+            match exported {
+                Ok(i) => {
+                    inodes.push(i);
+                    Action::Continue
+                }
+                Err(e) => {
+                    result = Err(e);
+                    Action::Break
+                }
+            }
+        })?;
+        result?;
+
+        Ok(inodes)
+    }
+
+    fn export_inode(
+        &self,
+        inode: &InodeWrapper,
+        path: PathBuf,
+        offset: u32,
+    ) -> Result<ExportedInode> {
+        let chunks = if inode.is_reg() {
+            self.load_file_chunks(inode, offset)?
+                .into_iter()
+                .map(|(c, _)| ExportedChunk {
+                    index: c.index,
+                    file_offset: c.file_offset,
+                    blob_index: c.blob_index,
+                    compressed_offset: c.compress_offset,
+                    compressed_size: c.compress_size,
+                    decompressed_offset: c.uncompress_offset,
+                    decompressed_size: c.uncompress_size,
+                    compressed: c.flags.contains(BlobChunkFlags::COMPRESSED),
+                    digest: c.block_id.to_string(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(ExportedInode {
+            inode: inode.ino(),
+            path,
+            mode: inode.mode(),
+            size: inode.size(),
+            nlink: inode.nlink(),
+            uid: inode.uid(),
+            gid: inode.gid(),
+            mtime: inode.mtime(),
+            digest: inode.digest().to_string(),
+            chunks,
+        })
+    }
+
     fn load_state_v5(f: &mut RafsIoReader, meta: &RafsMeta) -> Result<RafsState> {
         let mut inodes_table = RafsV5InodeTable::new(meta.inode_table_entries as usize);
         f.seek_to_offset(meta.inode_table_offset)?;
@@ -749,8 +1270,10 @@ impl Executor {
             ("ls", None) => inspector.cmd_list_dir(),
             ("cd", Some(dir)) => inspector.cmd_change_dir(dir),
             ("stat", Some(file_name)) => inspector.cmd_stat_file(file_name),
+            ("chunks", Some(path)) => inspector.cmd_show_file_chunks(path),
             ("blobs", None) => inspector.cmd_list_blobs(),
             ("prefetch", None) => inspector.cmd_list_prefetch(),
+            ("dedup", dict) => inspector.cmd_dedup(dict),
             ("chunk", Some(argument)) => {
                 let offset: u64 = argument.parse().unwrap();
                 inspector.cmd_show_chunk(offset)
@@ -789,8 +1312,10 @@ impl Executor {
     ls:                 Show files in current directory
     cd DIR:             Change current directory
     stat FILE_NAME:     Show particular information of rafs inode
+    chunks PATH:        Show chunk layout of a regular file, e.g. chunks /usr/bin/bash
     blobs:              Show blobs table
     prefetch:           Show prefetch table
+    dedup [CHUNK_DICT]: Show chunk deduplication statistics of blobs, optionally against a chunk dictionary
     chunk OFFSET:       List basic info of a single chunk together with a list of files that share it
     icheck INODE:       Show path of the inode and basic information
     index INDEX:        Show information about a file by its index
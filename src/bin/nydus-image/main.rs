@@ -13,39 +13,57 @@ extern crate serde;
 #[macro_use]
 extern crate serde_json;
 #[macro_use]
-extern crate lazy_static;
+extern crate nydus;
 
-use std::fs::{self, metadata, DirEntry, OpenOptions};
+use std::ffi::OsStr;
+use std::fs::{self, metadata, DirEntry, File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context, Result};
 use clap::{App, Arg, SubCommand};
 use nix::unistd::{getegid, geteuid};
 use serde::Serialize;
 
-use nydus_app::{setup_logging, BuildTimeInfo};
+use nydus::builder::core::analysis::{BuildAnalysis, BuildAnalysisReport, DEFAULT_TOP_FILES};
+use nydus::builder::core::build_cache::BuildCache;
+use nydus::builder::core::chunk_dict::import_chunk_dict;
+use nydus::builder::core::context::{
+    ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput, BuildOutputBlob,
+    BuildOutputLayer, RafsVersion, SourceType,
+};
+use nydus::builder::core::exclude::ExcludePatterns;
+use nydus::builder::core::node::{self, SpecialFilePolicy, WhiteoutSpec, XattrFilter};
+use nydus::builder::core::platform::SourceDefaults;
+use nydus::builder::core::prefetch::{Prefetch, PrefetchPolicy};
+use nydus::builder::core::tree;
+use nydus::builder::oci;
+use nydus::builder::trace::{EventTracerClass, PeriodicTraceDumper, TimingTracerClass, TraceClass};
+use nydus::builder::{
+    Builder, DiffBuilder, DirectoryBuilder, OciV1Builder, RecompressBuilder, StargzBuilder,
+    ZstdChunkedBuilder,
+};
+use nydus_app::{install_panic_hook, setup_logging, BuildTimeInfo};
 use nydus_utils::digest;
+use rafs::metadata::delta;
 use rafs::RafsIoReader;
+use storage::backend::localfs::LocalFs;
+use storage::backend::registry::Registry;
+use storage::backend::BlobBackend;
+use storage::meta::toc::BlobTocOndisk;
 use storage::{compress, RAFS_DEFAULT_CHUNK_SIZE};
 
-use crate::builder::{Builder, DiffBuilder, DirectoryBuilder, StargzBuilder};
-use crate::core::chunk_dict::import_chunk_dict;
-use crate::core::context::{
-    ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput, BuildOutputBlob,
-    RafsVersion, SourceType,
-};
-use crate::core::node::{self, WhiteoutSpec};
-use crate::core::prefetch::Prefetch;
-use crate::core::tree;
-use crate::trace::{EventTracerClass, TimingTracerClass, TraceClass};
-use crate::validator::Validator;
+use crate::extract::ImageReader;
+use crate::validator::{Validator, CAPABILITY_XATTR};
 
-#[macro_use]
-mod trace;
-mod builder;
-mod core;
+mod diff;
+mod extract;
+mod gc;
 mod inspect;
+#[cfg(feature = "fusedev")]
+mod mount;
 mod stat;
+mod tui;
 mod validator;
 
 const BLOB_ID_MAXIMUM_LENGTH: usize = 255;
@@ -61,9 +79,17 @@ pub struct OutputSerializer {
     /// Represents all blob in blob table ordered by layer, this field
     /// include the layer that does not have a blob.
     ordered_blobs: Vec<Option<BuildOutputBlob>>,
+    /// Represents the source path, blob and bootstrap of every snapshot in
+    /// diff build, ordered by snapshot index, include the skipped snapshots.
+    layers: Vec<BuildOutputLayer>,
     /// Represents all bootstrap names for every snapshot in diff build,
     /// ordered by snapshot index, not include the skipped (cached) snapshots.
     bootstraps: Vec<String>,
+    /// Image digest of the bootstrap, which identifies the whole image.
+    image_digest: Option<String>,
+    /// Statistics of files in the image, only present if enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    analysis: Option<BuildAnalysisReport>,
     /// Performance trace info for current build.
     trace: serde_json::Map<String, serde_json::Value>,
 }
@@ -92,7 +118,10 @@ impl OutputSerializer {
                 version,
                 blobs: build_output.get_exists_blobs(),
                 ordered_blobs: build_output.blobs.clone(),
+                layers: build_output.layers.clone(),
                 bootstraps: build_output.bootstraps.clone(),
+                image_digest: build_output.image_digest.clone(),
+                analysis: build_output.analysis.clone(),
                 trace,
             };
 
@@ -106,6 +135,7 @@ impl OutputSerializer {
         matches: &clap::ArgMatches,
         build_info: &BuildTimeInfo,
         blob_ids: Vec<String>,
+        image_digest: Option<String>,
     ) -> Result<()> {
         let output_json: Option<PathBuf> = matches
             .value_of("output-json")
@@ -125,7 +155,10 @@ impl OutputSerializer {
                 version,
                 blobs: blob_ids,
                 ordered_blobs: Vec::new(),
+                layers: Vec::new(),
                 bootstraps: Vec::new(),
+                image_digest,
+                analysis: None,
                 trace,
             };
 
@@ -137,10 +170,11 @@ impl OutputSerializer {
 }
 
 fn main() -> Result<()> {
+    install_panic_hook(&[]);
     let (bti_string, build_info) = BuildTimeInfo::dump(crate_version!());
 
     // TODO: Try to use yaml to define below options
-    let cmd = App::new("")
+    let app = App::new("")
         .version(bti_string.as_str())
         .author(crate_authors!())
         .about("Build or inspect RAFS filesystems for nydus accelerated container images.")
@@ -150,7 +184,7 @@ fn main() -> Result<()> {
                 .arg(
                     Arg::with_name("SOURCE")
                         .help("source path to build the nydus image from")
-                        .required(true)
+                        .required_unless("from-ociv1")
                         .multiple(true),
                 )
                 .arg(
@@ -160,7 +194,21 @@ fn main() -> Result<()> {
                         .help("type of the source:")
                         .takes_value(true)
                         .default_value("directory")
-                        .possible_values(&["directory", "stargz_index", "diff"])
+                        .possible_values(&["directory", "stargz_index", "zstd_chunked", "diff", "ociv1"])
+                )
+                .arg(
+                    Arg::with_name("from-ociv1")
+                        .long("from-ociv1")
+                        .value_name("IMAGE_TAR")
+                        .help("build from an uncompressed OCI or docker image tarball, e.g. generated by `docker save`, same as `--source-type ociv1 IMAGE_TAR`")
+                        .conflicts_with("SOURCE")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("ociv1-work-dir")
+                        .long("ociv1-work-dir")
+                        .help("specify a directory to unpack image layers into on ociv1 build, defaults to the system temporary directory")
+                        .takes_value(true)
                 )
                 .arg(
                     Arg::with_name("diff-overlay-hint")
@@ -181,6 +229,14 @@ fn main() -> Result<()> {
                         .help("specify the index of layer to skip and start building from there for speeding up diff build")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("build-cache")
+                        .long("build-cache")
+                        .help("specify a directory to cache blobs and chunks of layers by layer digest, and reuse them across diff builds")
+                        .requires("blob-dir")
+                        .conflicts_with("chunk-dict")
+                        .takes_value(true)
+                )
                 .arg(
                     Arg::with_name("bootstrap")
                         .long("bootstrap")
@@ -197,6 +253,14 @@ fn main() -> Result<()> {
                         .required_unless("backend-type")
                         .required_unless("source-type")
                         .required_unless("blob-dir")
+                        .required_unless("no-blob")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("blob-offset")
+                        .long("blob-offset")
+                        .help("write data blob at the offset of the blob file, data before the offset is kept, e.g. to put the blob after a header")
+                        .requires("blob")
                         .takes_value(true)
                 )
                 .arg(
@@ -224,6 +288,27 @@ fn main() -> Result<()> {
                         .default_value("lz4_block")
                         .possible_values(&["none", "lz4_block", "gzip"]),
                 )
+                .arg(
+                    Arg::with_name("trace-dump-file")
+                        .long("trace-dump-file")
+                        .help("periodically dump traces collected so far to the file, to watch progress of long builds")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("trace-dump-interval")
+                        .long("trace-dump-interval")
+                        .help("interval in seconds to dump traces to \"--trace-dump-file\"")
+                        .takes_value(true)
+                        .required(false)
+                        .default_value("60"),
+                )
+                .arg(
+                    Arg::with_name("compress-heuristics")
+                        .long("compress-heuristics")
+                        .help("store chunks uncompressed if they are estimated incompressible or compression doesn't save enough space")
+                        .takes_value(false)
+                )
                 .arg(
                     Arg::with_name("digester")
                         .long("digester")
@@ -261,6 +346,13 @@ fn main() -> Result<()> {
                         .default_value("none")
                         .possible_values(&["fs", "blob", "none"]),
                 )
+                .arg(
+                    Arg::with_name("prefetch-list")
+                        .long("prefetch-list")
+                        .help("file containing files, directories and glob patterns to prefetch, one per line, instead of reading from stdin")
+                        .takes_value(true)
+                        .required(false),
+                )
                 .arg(
                     Arg::with_name("repeatable")
                         .long("repeatable")
@@ -269,6 +361,42 @@ fn main() -> Result<()> {
                         .takes_value(false)
                         .required(false),
                 )
+                .arg(
+                    Arg::with_name("owner")
+                        .long("owner")
+                        .help("set owner of all files in the source directory, in format uid:gid, files are owned by 0:0 by default on platforms without file owners")
+                        .takes_value(true)
+                        .required(false)
+                        .conflicts_with("repeatable"),
+                )
+                .arg(
+                    Arg::with_name("uid-map")
+                        .long("uid-map")
+                        .help("remap uids of files in the source directory, in format container_id:host_id[:size], may be specified multiple times")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .conflicts_with_all(&["owner", "repeatable"]),
+                )
+                .arg(
+                    Arg::with_name("gid-map")
+                        .long("gid-map")
+                        .help("remap gids of files in the source directory, in format container_id:host_id[:size], may be specified multiple times")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .conflicts_with_all(&["owner", "repeatable"]),
+                )
+                .arg(
+                    Arg::with_name("default-mode")
+                        .long("default-mode")
+                        .help("octal permission bits of files and directories on platforms without them, e.g. Windows, in format file_mode:dir_mode")
+                        .takes_value(true)
+                        .required(false)
+                        .default_value("644:755"),
+                )
                 .arg(
                     Arg::with_name("disable-check")
                         .long("disable-check")
@@ -280,11 +408,11 @@ fn main() -> Result<()> {
                     Arg::with_name("whiteout-spec")
                         .long("whiteout-spec")
                         .short("W")
-                        .help("type of whiteout specification:")
+                        .help("type of whiteout specification, `none` to keep whiteout files verbatim:")
                         .takes_value(true)
                         .required(true)
                         .default_value("oci")
-                        .possible_values(&["oci", "overlayfs"])
+                        .possible_values(&["oci", "overlayfs", "none"])
                 )
                 .arg(
                     Arg::with_name("output-json")
@@ -293,6 +421,27 @@ fn main() -> Result<()> {
                         .help("JSON output path for build result")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("analysis")
+                        .long("analysis")
+                        .help("report size by file type, the largest files and duplicate files of the image in the output JSON")
+                        .requires("output-json")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("analysis-top-files")
+                        .long("analysis-top-files")
+                        .help("number of the largest files to report in the analysis, defaults to 10")
+                        .requires("analysis")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("file-type-filter")
+                        .long("file-type-filter")
+                        .help("only account files of the comma separated types in the analysis, types are library, archive, bytecode, source, text, media, model, executable and other")
+                        .requires("analysis")
+                        .takes_value(true)
+                )
                 .arg(
                     Arg::with_name("aligned-chunk")
                         .long("aligned-chunk")
@@ -311,7 +460,143 @@ fn main() -> Result<()> {
                     Arg::with_name("chunk-dict")
                         .long("chunk-dict")
                         .short("M")
-                        .help("Specify a chunk dictionary for chunk deduplication")
+                        .help("Specify a chunk dictionary for chunk deduplication, a bootstrap file or an http(s) URL of a chunk dictionary service")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("dirent-index")
+                        .long("dirent-index")
+                        .help("generate name index for large directories to speed up negative lookups, only for fs-version 6")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("sparse-file")
+                        .long("sparse-file")
+                        .help("preserve holes of sparse files instead of storing zero chunks")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("blob-toc")
+                        .long("blob-toc")
+                        .help("append a TOC to the data blob, describing the blob, digests of blob data, chunk info and bootstrap, and build metadata")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("blob-size-limit")
+                        .long("blob-size-limit")
+                        .help("maximum size in bytes of each data blob, data is split into multiple blobs in the blob directory when exceeded")
+                        .requires("blob-dir")
+                        .conflicts_with_all(&["blob", "blob-id", "blob-offset", "blob-toc"])
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("spill-threshold")
+                        .long("spill-threshold")
+                        .help("move chunk information into a temporary file to bound memory usage, if the image has more files than the threshold")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("spill-dir")
+                        .long("spill-dir")
+                        .help("directory to create the temporary file for --spill-threshold in [default: system temporary directory]")
+                        .requires("spill-threshold")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("no-blob")
+                        .long("no-blob")
+                        .help("compute chunks and digests without writing the data blob, generating a metadata-only bootstrap which can't be mounted")
+                        .conflicts_with_all(&["blob", "blob-dir", "blob-offset", "blob-toc"])
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("exclude")
+                        .long("exclude")
+                        .help("exclude files and directories matching the glob pattern, relative to the source directory, e.g. '**/*.sock', 'var/cache/**'")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::with_name("xattr-exclude")
+                        .long("xattr-exclude")
+                        .help("exclude xattrs matching the pattern from the image, a pattern ending with '*' matches keys by prefix, e.g. 'user.overlay.*'")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::with_name("xattr-exclude-profile")
+                        .long("xattr-exclude-profile")
+                        .help("predefined xattr exclusion rules, 'default' excludes host specific xattrs such as security.selinux")
+                        .takes_value(true)
+                        .default_value("none")
+                        .possible_values(&["none", "default"])
+                )
+                .arg(
+                    Arg::with_name("strict-xattrs")
+                        .long("strict-xattrs")
+                        .help("fail the build if xattrs of source files can't be read due to insufficient permission, instead of dropping them")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("allow-devices")
+                        .long("allow-devices")
+                        .help("keep character and block device files of the source in the image [default: enabled]")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("drop-devices")
+                        .long("drop-devices")
+                        .help("drop character and block device files of the source from the image, except overlayfs whiteouts")
+                        .conflicts_with("allow-devices")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("allow-sockets")
+                        .long("allow-sockets")
+                        .help("keep unix domain sockets of the source in the image [default: enabled]")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("drop-sockets")
+                        .long("drop-sockets")
+                        .help("drop unix domain sockets of the source from the image")
+                        .conflicts_with("allow-sockets")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("fail-on-dropped-caps")
+                        .long("fail-on-dropped-caps")
+                        .help("fail the build if setuid/setgid bits or file capabilities of source files are missing in the built image, only for directory source")
+                        .takes_value(false)
+                )
+                .arg(
+                    Arg::with_name("oci-artifact")
+                        .long("oci-artifact")
+                        .help("directory to export bootstrap and blobs as an OCI image layout of nydus artifact")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("oci-subject")
+                        .long("oci-subject")
+                        .help("path to the manifest of original image, to be referenced as subject by the generated OCI artifact")
+                        .requires("oci-artifact")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("oci-push")
+                        .long("oci-push")
+                        .help("push the generated OCI artifact to registry, with registry backend configuration in the JSON file")
+                        .value_name("BACKEND_CONFIG_FILE")
+                        .requires("oci-artifact")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("oci-tag")
+                        .long("oci-tag")
+                        .help("tag of the pushed OCI artifact, the manifest is referenced by digest if not specified")
+                        .requires("oci-push")
                         .takes_value(true)
                 )
                 .arg(
@@ -354,6 +639,26 @@ fn main() -> Result<()> {
                         .help("path to JSON output file")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("parent-bootstrap")
+                        .long("parent-bootstrap")
+                        .short("p")
+                        .help("path to parent image's metadata blob, only inodes changed relative to it are validated")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("image-digest")
+                        .long("image-digest")
+                        .help("expected image digest of the bootstrap, e.g. sha256:<hex>")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("blob")
+                        .long("blob")
+                        .short("b")
+                        .help("path to a data blob with TOC generated by --blob-toc, to verify the blob and the bootstrap against the TOC")
+                        .takes_value(true)
+                )
         )
         .subcommand(
             SubCommand::with_name("inspect")
@@ -374,6 +679,28 @@ fn main() -> Result<()> {
                         .required(false)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("tui")
+                        .long("tui")
+                        .help("explore nydus image's filesystem metadata in an interactive terminal UI")
+                        .takes_value(false)
+                        .conflicts_with("request"),
+                )
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .help("export superblock, blob table and prefetch table of nydus image's filesystem metadata to stdout")
+                        .takes_value(true)
+                        .possible_values(&["json"])
+                        .conflicts_with_all(&["request", "tui"]),
+                )
+                .arg(
+                    Arg::with_name("export-inodes")
+                        .long("export-inodes")
+                        .help("include all inodes and their chunks in the exported metadata")
+                        .takes_value(false)
+                        .requires("export"),
+                )
         )
         .subcommand(
             SubCommand::with_name("stat")
@@ -402,6 +729,77 @@ fn main() -> Result<()> {
                         .required(false)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("chunk-dict")
+                        .long("chunk-dict")
+                        .short("M")
+                        .help("estimate data to pull for each image, assuming chunks in the chunk dictionary are already cached")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("output-json")
+                        .long("output-json")
+                        .short("J")
+                        .help("path to JSON output file")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .help("output per-image statistics in machine-readable format, one row for each image")
+                        .possible_values(&["csv", "jsonl"])
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("report-file")
+                        .long("report-file")
+                        .help("path to the report file, default to stdout")
+                        .requires("report")
+                        .takes_value(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("recompress")
+                .about("Recompresses data blobs of a nydus image with another compression algorithm")
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("path to nydus image's metadata blob (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("blob-dir")
+                        .long("blob-dir")
+                        .short("D")
+                        .help("directory holding data blobs of the image, named by blob id (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("compressor")
+                        .long("compressor")
+                        .short("c")
+                        .help("algorithm to recompress image data blob:")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["none", "lz4_block", "gzip"]),
+                )
+                .arg(
+                    Arg::with_name("output-bootstrap")
+                        .long("output-bootstrap")
+                        .help("path to the new metadata blob (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output-blob-dir")
+                        .long("output-blob-dir")
+                        .help("directory to store recompressed data blobs, named by their digests (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("output-json")
                         .long("output-json")
@@ -410,6 +808,136 @@ fn main() -> Result<()> {
                         .takes_value(true)
                 )
         )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Removes data blobs and metadata blobs not referenced by live images from a blob directory")
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("metadata blob of live images, whose data blobs are kept (required)")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("blob-dir")
+                        .long("blob-dir")
+                        .short("D")
+                        .help("directory holding data blobs named by blob id, and metadata blobs (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("retention")
+                        .long("retention")
+                        .help("only remove unreferenced files not modified within the window, e.g. 3600, 30m, 12h or 7d")
+                        .default_value("1d")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("list unreferenced files to remove without removing them")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("output-json")
+                        .long("output-json")
+                        .short("J")
+                        .help("path to JSON output file")
+                        .takes_value(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("delta")
+                .about("Generates a delta of changed metadata pages to transfer a metadata blob against its parent")
+                .arg(
+                    Arg::with_name("parent-bootstrap")
+                        .long("parent-bootstrap")
+                        .short("p")
+                        .help("path to metadata blob cached by clients, which the delta applies to (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("path to metadata blob to transfer (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .help("path to output delta file (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("Lists a directory in a nydus image without mounting it")
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("path to nydus image's metadata blob (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("blob-dir")
+                        .long("blob-dir")
+                        .short("D")
+                        .help("directory holding data blobs of the image, named by blob id (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("absolute path of the directory or file in the image")
+                        .default_value("/")
+                        .index(1),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Writes content of files in a nydus image to stdout without mounting it")
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("path to nydus image's metadata blob (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("blob-dir")
+                        .long("blob-dir")
+                        .short("D")
+                        .help("directory holding data blobs of the image, named by blob id (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .help("write the file to a new file instead of stdout, keeping holes of sparse files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("absolute paths of regular files in the image")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                )
+        )
         .arg(
             Arg::with_name("log-level")
                 .long("log-level")
@@ -421,7 +949,64 @@ fn main() -> Result<()> {
                 .required(false)
                 .global(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("disable-tracer")
+                .long("disable-tracer")
+                .help("disable tracer classes:")
+                .env("NYDUS_IMAGE_DISABLE_TRACERS")
+                .possible_values(&["timing", "event"])
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("trace-max-records")
+                .long("trace-max-records")
+                .help("maximum number of records kept by each tracer class, 0 means unlimited")
+                .default_value("4096")
+                .takes_value(true)
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("trace-sample-rate")
+                .long("trace-sample-rate")
+                .help("measure only one of every N invocations of each timing tracing point")
+                .default_value("1")
+                .takes_value(true)
+                .required(false)
+                .global(true),
+        );
+    #[cfg(feature = "fusedev")]
+    let app = app.subcommand(
+        SubCommand::with_name("mount")
+            .about("Mounts a nydus image by an embedded fuse session for quick local inspection")
+            .arg(
+                Arg::with_name("bootstrap")
+                    .long("bootstrap")
+                    .short("B")
+                    .help("path to nydus image's metadata blob (required)")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("blob-dir")
+                    .long("blob-dir")
+                    .short("D")
+                    .help("directory holding data blobs of the image, named by blob id (required)")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("MOUNTPOINT")
+                    .help("directory to mount the image at")
+                    .required(true)
+                    .index(1),
+            ),
+    );
+    let cmd = app.get_matches();
 
     // Safe to unwrap because it has a default value and possible values are defined.
     let level = cmd.value_of("log-level").unwrap().parse().unwrap();
@@ -429,6 +1014,7 @@ fn main() -> Result<()> {
 
     register_tracer!(TraceClass::Timing, TimingTracerClass);
     register_tracer!(TraceClass::Event, EventTracerClass);
+    setup_tracers(&cmd)?;
 
     if let Some(matches) = cmd.subcommand_matches("create") {
         Command::create(matches, &build_info)
@@ -438,12 +1024,49 @@ fn main() -> Result<()> {
         Command::inspect(matches)
     } else if let Some(matches) = cmd.subcommand_matches("stat") {
         Command::stat(matches)
+    } else if let Some(matches) = cmd.subcommand_matches("recompress") {
+        Command::recompress(matches, &build_info)
+    } else if let Some(matches) = cmd.subcommand_matches("gc") {
+        Command::gc(matches)
+    } else if let Some(matches) = cmd.subcommand_matches("delta") {
+        Command::delta(matches)
+    } else if let Some(matches) = cmd.subcommand_matches("ls") {
+        Command::ls(matches)
+    } else if let Some(matches) = cmd.subcommand_matches("cat") {
+        Command::cat(matches)
+    } else if let Some(matches) = cmd.subcommand_matches("mount") {
+        Command::mount(matches)
     } else {
         println!("{}", cmd.usage());
         Ok(())
     }
 }
 
+fn setup_tracers(matches: &clap::ArgMatches) -> Result<()> {
+    // Safe to unwrap because they have default values.
+    let max_records = matches
+        .value_of("trace-max-records")
+        .unwrap()
+        .parse()
+        .context("invalid --trace-max-records")?;
+    let sample_rate = matches
+        .value_of("trace-sample-rate")
+        .unwrap()
+        .parse()
+        .context("invalid --trace-sample-rate")?;
+    ensure!(sample_rate > 0, "--trace-sample-rate must be positive");
+
+    root_tracer!().set_max_records(max_records);
+    root_tracer!().set_sample_rate(sample_rate);
+    if let Some(classes) = matches.values_of("disable-tracer") {
+        for class in classes {
+            root_tracer!().set_enabled(class.parse()?, false);
+        }
+    }
+
+    Ok(())
+}
+
 struct Command {}
 
 impl Command {
@@ -451,12 +1074,18 @@ impl Command {
         let blob_id = Self::get_blob_id(&matches)?;
         let chunk_size = Self::get_chunk_size(&matches)?;
         let parent_bootstrap = Self::get_parent_bootstrap(&matches)?;
-        let source_path = PathBuf::from(matches.value_of("SOURCE").unwrap());
+        let mut source_type: SourceType = matches.value_of("source-type").unwrap().parse()?;
+        let source_path = if let Some(tarball) = matches.value_of("from-ociv1") {
+            source_type = SourceType::OciV1;
+            PathBuf::from(tarball)
+        } else {
+            // Safe to unwrap because `SOURCE` is required unless `from-ociv1` is specified.
+            PathBuf::from(matches.value_of("SOURCE").unwrap())
+        };
         let extra_paths: Vec<PathBuf> = matches
             .values_of("SOURCE")
             .map(|paths| paths.map(PathBuf::from).skip(1).collect())
-            .unwrap();
-        let source_type: SourceType = matches.value_of("source-type").unwrap().parse()?;
+            .unwrap_or_default();
         let blob_stor = Self::get_blob_storage(&matches, source_type)?;
         let repeatable = matches.is_present("repeatable");
         let version = Self::get_fs_version(&matches)?;
@@ -478,6 +1107,12 @@ impl Command {
             SourceType::Directory | SourceType::Diff => {
                 Self::ensure_directory(&source_path)?;
             }
+            SourceType::OciV1 => {
+                Self::ensure_file(&source_path)?;
+                if let Some(work_dir) = matches.value_of("ociv1-work-dir") {
+                    Self::ensure_directory(work_dir)?;
+                }
+            }
             SourceType::StargzIndex => {
                 Self::ensure_file(&source_path)?;
                 if blob_id.trim() == "" {
@@ -492,13 +1127,33 @@ impl Command {
                 }
                 digester = digest::Algorithm::Sha256;
             }
+            SourceType::ZstdChunked => {
+                Self::ensure_file(&source_path)?;
+                if blob_id.trim() == "" {
+                    bail!("blob-id can't be empty");
+                }
+                if !version.is_v5() {
+                    bail!("zstd_chunked source is only supported by fs-version 5");
+                }
+                if compressor != compress::Algorithm::Zstd {
+                    trace!("compressor set to {}", compress::Algorithm::Zstd);
+                }
+                compressor = compress::Algorithm::Zstd;
+                if digester != digest::Algorithm::Sha256 {
+                    trace!("digester set to {}", digest::Algorithm::Sha256);
+                }
+                digester = digest::Algorithm::Sha256;
+            }
         }
 
         let prefetch_policy = matches
             .value_of("prefetch-policy")
             .unwrap_or_default()
             .parse()?;
-        let prefetch = Prefetch::new(prefetch_policy)?;
+        let prefetch = Prefetch::new(
+            prefetch_policy,
+            matches.value_of("prefetch-list").map(Path::new),
+        )?;
 
         let mut build_ctx = BuildContext::new(
             blob_id,
@@ -514,6 +1169,148 @@ impl Command {
         );
         build_ctx.set_fs_version(version);
         build_ctx.set_chunk_size(chunk_size);
+        if matches.is_present("compress-heuristics") {
+            if source_type == SourceType::StargzIndex || source_type == SourceType::ZstdChunked {
+                bail!(
+                    "compress-heuristics is not supported by stargz index and zstd_chunked sources"
+                );
+            }
+            build_ctx.set_compress_heuristics(true);
+        }
+        if matches.is_present("dirent-index") {
+            if !version.is_v6() {
+                bail!("dirent-index is only supported by fs-version 6");
+            }
+            build_ctx.set_dirent_index(true);
+        }
+        if matches.is_present("sparse-file") {
+            build_ctx.set_sparse_file(true);
+        }
+        let builder_version = format!("{}-{}", build_info.package_ver, build_info.git_commit);
+        build_ctx.set_builder_version(builder_version);
+        if !repeatable {
+            build_ctx.set_build_time(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            );
+        }
+        if matches.is_present("blob-toc") {
+            if source_type != SourceType::Directory {
+                bail!("blob-toc is only supported by directory source");
+            }
+            build_ctx.set_blob_toc(true);
+        }
+        if matches.is_present("no-blob") {
+            if source_type != SourceType::Directory {
+                bail!("no-blob is only supported by directory source");
+            }
+            build_ctx.set_metadata_only(true);
+        }
+        if let Some(offset) = matches.value_of("blob-offset") {
+            if source_type != SourceType::Directory {
+                bail!("blob-offset is only supported by directory source");
+            }
+            let offset = offset
+                .parse::<u64>()
+                .context(format!("invalid blob offset {}", offset))?;
+            build_ctx.set_blob_offset(offset);
+        }
+        if let Some(limit) = matches.value_of("blob-size-limit") {
+            if source_type != SourceType::Directory && source_type != SourceType::OciV1 {
+                bail!("blob-size-limit is only supported by directory and ociv1 sources");
+            }
+            let limit = limit
+                .parse::<u64>()
+                .context(format!("invalid blob size limit {}", limit))?;
+            if limit < chunk_size as u64 {
+                bail!(
+                    "blob size limit {} is smaller than chunk size {}",
+                    limit,
+                    chunk_size
+                );
+            }
+            build_ctx.set_blob_size_limit(limit);
+        }
+        if let Some(threshold) = matches.value_of("spill-threshold") {
+            if source_type != SourceType::Directory && source_type != SourceType::OciV1 {
+                bail!("spill-threshold is only supported by directory and ociv1 sources");
+            }
+            let threshold = threshold
+                .parse::<usize>()
+                .context(format!("invalid spill threshold {}", threshold))?;
+            let dir = matches.value_of("spill-dir").map(PathBuf::from);
+            if let Some(dir) = dir.as_ref() {
+                Self::ensure_directory(dir)?;
+            }
+            build_ctx.set_chunk_spill(threshold, dir);
+        }
+        if let Some(patterns) = matches.values_of("exclude") {
+            if source_type != SourceType::Directory {
+                bail!("exclude is only supported by directory source");
+            }
+            let mut excludes = ExcludePatterns::default();
+            for pattern in patterns {
+                excludes.add(pattern)?;
+            }
+            build_ctx.set_excludes(excludes);
+        }
+        if matches.is_present("analysis") {
+            let top_files = match matches.value_of("analysis-top-files") {
+                Some(n) => n
+                    .parse::<usize>()
+                    .context(format!("invalid number of top files {}", n))?,
+                None => DEFAULT_TOP_FILES,
+            };
+            build_ctx.set_analysis(BuildAnalysis::new(
+                top_files,
+                matches.value_of("file-type-filter"),
+            )?);
+        }
+        let mut source_defaults: SourceDefaults =
+            matches.value_of("default-mode").unwrap().parse()?;
+        if let Some(owner) = matches.value_of("owner") {
+            if source_type != SourceType::Directory {
+                bail!("owner is only supported by directory source");
+            }
+            source_defaults.owner = Some(SourceDefaults::parse_owner(owner)?);
+        }
+        for (name, map) in vec![
+            ("uid-map", &mut source_defaults.uid_map),
+            ("gid-map", &mut source_defaults.gid_map),
+        ] {
+            if let Some(ranges) = matches.values_of(name) {
+                if source_type != SourceType::Directory {
+                    bail!("{} is only supported by directory source", name);
+                }
+                for range in ranges {
+                    map.add(range)?;
+                }
+            }
+        }
+        build_ctx.set_source_defaults(source_defaults);
+        let mut xattr_filter =
+            XattrFilter::from_profile(matches.value_of("xattr-exclude-profile").unwrap())?;
+        if let Some(patterns) = matches.values_of("xattr-exclude") {
+            for pattern in patterns {
+                xattr_filter.add(pattern)?;
+            }
+        }
+        if !xattr_filter.is_empty()
+            && (source_type == SourceType::StargzIndex || source_type == SourceType::ZstdChunked)
+        {
+            bail!("xattr exclusion is not supported by stargz index and zstd_chunked sources");
+        }
+        xattr_filter.set_strict(matches.is_present("strict-xattrs"));
+        if matches.is_present("fail-on-dropped-caps") && source_type != SourceType::Directory {
+            bail!("fail-on-dropped-caps is only supported by directory source");
+        }
+        build_ctx.set_xattr_filter(xattr_filter);
+        build_ctx.set_special_files(SpecialFilePolicy {
+            allow_devices: !matches.is_present("drop-devices"),
+            allow_sockets: !matches.is_present("drop-sockets"),
+        });
 
         let mut blob_mgr = BlobManager::new();
         if let Some(chunk_dict_arg) = matches.value_of("chunk-dict") {
@@ -521,6 +1318,12 @@ impl Command {
                 { import_chunk_dict(chunk_dict_arg) },
                 "import_chunk_dict"
             )?);
+            if blob_mgr.get_chunk_dict().as_remote().is_some()
+                && source_type != SourceType::Directory
+                && source_type != SourceType::OciV1
+            {
+                bail!("remote chunk dict is only supported by directory and ociv1 sources");
+            }
         }
 
         let mut bootstrap_mgr = if source_type == SourceType::Diff {
@@ -542,16 +1345,31 @@ impl Command {
         };
 
         let diff_overlay_hint = matches.is_present("diff-overlay-hint");
+        let build_cache = if let Some(dir) = matches.value_of("build-cache") {
+            if source_type != SourceType::Diff {
+                bail!("build-cache is only supported by diff source");
+            }
+            if build_ctx.prefetch.policy != PrefetchPolicy::None {
+                bail!("build-cache can't be used with prefetch policy");
+            }
+            Some(BuildCache::new(Path::new(dir))?)
+        } else {
+            None
+        };
         let mut builder: Box<dyn Builder> = match source_type {
             SourceType::Directory => Box::new(DirectoryBuilder::new()),
             SourceType::StargzIndex => Box::new(StargzBuilder::new()),
+            SourceType::ZstdChunked => Box::new(ZstdChunkedBuilder::new()),
             SourceType::Diff => Box::new(DiffBuilder::new(
                 extra_paths,
                 diff_overlay_hint,
                 matches.value_of("diff-skip-layer"),
+                build_cache,
             )?),
+            SourceType::OciV1 => Box::new(OciV1Builder::new(matches.value_of("ociv1-work-dir"))),
         };
-        let build_output = timing_tracer!(
+        let trace_dumper = Self::get_trace_dumper(&matches)?;
+        let mut build_output = timing_tracer!(
             {
                 builder
                     .build(&mut build_ctx, &mut bootstrap_mgr, &mut blob_mgr)
@@ -559,31 +1377,179 @@ impl Command {
             },
             "total_build"
         )?;
+        build_output.analysis = build_ctx.analysis.as_ref().map(|a| a.report());
+        // Write the last snapshot of traces.
+        drop(trace_dumper);
 
         // Some operations like listing xattr pairs of certain namespace need the process
         // to be privileged. Therefore, trace what euid and egid are
         event_tracer!("euid", "{}", geteuid());
         event_tracer!("egid", "{}", getegid());
+        Self::report_xattr_denials(&build_ctx);
 
         // Validate output bootstrap file
         let bootstrap_path = bootstrap_mgr.get_bootstrap_path(&build_output.bootstrap_name);
         Self::validate_image(&matches, &bootstrap_path)?;
+        Self::verify_privileges(&matches, &build_ctx, &bootstrap_path)?;
+        Self::export_oci_artifact(&matches, &build_ctx, &build_output, &bootstrap_path)?;
         OutputSerializer::dump(matches, &build_output, &build_info)?;
         info!("build successfully: {:?}", build_output,);
 
         Ok(())
     }
 
+    // Warn about xattrs dropped due to insufficient permission, e.g. `security.*` xattrs when
+    // building as non-root.
+    fn report_xattr_denials(build_ctx: &BuildContext) {
+        for (namespace, files) in build_ctx.xattr_filter.denials() {
+            warn!(
+                "xattrs in namespace {} of {} files are dropped due to insufficient permission, build as root or with --strict-xattrs to fail instead",
+                namespace, files
+            );
+            event_tracer!(&format!("xattr_denied_{}", namespace), files);
+        }
+    }
+
+    // Verify setuid/setgid bits and file capabilities of source files are kept in the image,
+    // which may be dropped silently when building as non-root from certain filesystems.
+    fn verify_privileges(
+        matches: &clap::ArgMatches,
+        build_ctx: &BuildContext,
+        bootstrap_path: &Path,
+    ) -> Result<()> {
+        let fail = matches.is_present("fail-on-dropped-caps");
+        if build_ctx.source_type != SourceType::Directory
+            || (matches.is_present("disable-check") && !fail)
+        {
+            return Ok(());
+        }
+
+        let check_caps = !build_ctx
+            .xattr_filter
+            .is_excluded(OsStr::new(CAPABILITY_XATTR));
+        let validator = Validator::new(bootstrap_path)?;
+        let dropped = timing_tracer!(
+            {
+                validator
+                    .check_privileges(&build_ctx.source_path, check_caps)
+                    .context("failed to verify privileges")
+            },
+            "verify_privileges"
+        )?;
+        for entry in dropped.iter() {
+            warn!(
+                "{} of {:?} is dropped from the image",
+                entry.what, entry.path
+            );
+        }
+        event_tracer!("dropped_privileges", dropped.len());
+        if fail && !dropped.is_empty() {
+            bail!(
+                "setuid/setgid bits or file capabilities of {} files are dropped, build as root to keep them",
+                dropped.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn export_oci_artifact(
+        matches: &clap::ArgMatches,
+        build_ctx: &BuildContext,
+        build_output: &BuildOutput,
+        bootstrap_path: &Path,
+    ) -> Result<()> {
+        if let Some(dir) = matches.value_of("oci-artifact") {
+            let subject = matches
+                .value_of("oci-subject")
+                .map(oci::OciArtifact::load_subject)
+                .transpose()?;
+            let artifact = oci::OciArtifact::new(dir)?;
+            let manifest = timing_tracer!(
+                {
+                    artifact
+                        .export(
+                            build_output,
+                            build_ctx.blob_storage.as_ref(),
+                            bootstrap_path,
+                            subject,
+                        )
+                        .context("failed to export OCI artifact")
+                },
+                "export_oci_artifact"
+            )?;
+            info!("exported OCI artifact {} to {}", manifest.digest, dir);
+
+            if let Some(config_file) = matches.value_of("oci-push") {
+                let config = fs::read(config_file).with_context(|| {
+                    format!("failed to read registry config file {}", config_file)
+                })?;
+                let config: serde_json::Value = serde_json::from_slice(&config)
+                    .with_context(|| format!("invalid registry config file {}", config_file))?;
+                let registry = Registry::new(config, Some("oci-push"))
+                    .context("failed to create registry backend")?;
+                timing_tracer!(
+                    {
+                        artifact
+                            .push(&manifest, &registry, matches.value_of("oci-tag"))
+                            .context("failed to push OCI artifact")
+                    },
+                    "push_oci_artifact"
+                )?;
+                info!("pushed OCI artifact {} to registry", manifest.digest);
+            }
+        }
+
+        Ok(())
+    }
+
     fn check(matches: &clap::ArgMatches, build_info: &BuildTimeInfo) -> Result<()> {
         let bootstrap_path = Self::get_bootstrap(matches)?;
         let verbose = matches.is_present("verbose");
         let mut validator = Validator::new(bootstrap_path)?;
-        let blob_ids = validator
-            .check(verbose)
+        let blob_ids = match matches.value_of("parent-bootstrap") {
+            Some(parent) => validator.check_incremental(Path::new(parent), verbose),
+            None => validator.check(verbose),
+        }
+        .with_context(|| format!("failed to check bootstrap {:?}", bootstrap_path))?;
+
+        let image_digest = validator
+            .check_image_digest(matches.value_of("image-digest"))
             .with_context(|| format!("failed to check bootstrap {:?}", bootstrap_path))?;
 
-        info!("bootstrap is valid, blobs: {:?}", blob_ids);
-        OutputSerializer::dump_with_check(matches, &build_info, blob_ids)?;
+        if let Some(blob_path) = matches.value_of("blob") {
+            Self::verify_blob_toc(blob_path, bootstrap_path)?;
+        }
+
+        info!(
+            "bootstrap is valid, blobs: {:?}, image digest: {:?}",
+            blob_ids, image_digest
+        );
+        OutputSerializer::dump_with_check(matches, &build_info, blob_ids, image_digest)?;
+
+        Ok(())
+    }
+
+    fn verify_blob_toc(blob_path: &str, bootstrap_path: &Path) -> Result<()> {
+        let config = serde_json::json!({ "blob_file": blob_path });
+        let backend = LocalFs::new(config, Some(blob_path))
+            .with_context(|| format!("failed to open blob {}", blob_path))?;
+        let reader = backend
+            .get_reader(blob_path)
+            .map_err(|e| anyhow!("failed to open blob {}, {:?}", blob_path, e))?;
+        let toc = BlobTocOndisk::read_from(reader.as_ref())
+            .with_context(|| format!("failed to load TOC of blob {}", blob_path))?
+            .ok_or_else(|| anyhow!("blob {} has no TOC", blob_path))?;
+        let bootstrap = fs::read(bootstrap_path)
+            .with_context(|| format!("failed to read bootstrap {:?}", bootstrap_path))?;
+        toc.verify(reader.as_ref(), Some(&bootstrap))
+            .with_context(|| format!("failed to verify blob {} against TOC", blob_path))?;
+        info!(
+            "blob {} matches its TOC, built by {} at {}",
+            blob_path,
+            toc.builder_version(),
+            toc.build_time()
+        );
 
         Ok(())
     }
@@ -597,10 +1563,16 @@ impl Command {
                 e
             })?;
 
-        if let Some(c) = cmd {
+        if matches.is_present("export") {
+            let o = inspector.export(matches.is_present("export-inodes"))?;
+            serde_json::to_writer_pretty(std::io::stdout(), &o)
+                .context("failed to write exported metadata")?;
+        } else if let Some(c) = cmd {
             let o = inspect::Executor::execute(&mut inspector, c.to_string()).unwrap();
             serde_json::to_writer(std::io::stdout(), &o)
                 .unwrap_or_else(|e| error!("Failed to serialize, {:?}", e));
+        } else if matches.is_present("tui") {
+            tui::Tui::run(inspector)?;
         } else {
             inspect::Prompt::run(inspector);
         }
@@ -608,8 +1580,195 @@ impl Command {
         Ok(())
     }
 
+    #[cfg(feature = "fusedev")]
+    fn mount(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap_path = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments.
+        let blob_dir = matches.value_of("blob-dir").unwrap();
+        let mountpoint = matches.value_of("MOUNTPOINT").unwrap();
+        Self::ensure_directory(blob_dir)?;
+        Self::ensure_directory(mountpoint)?;
+
+        mount::mount(bootstrap_path, Path::new(blob_dir), Path::new(mountpoint))
+    }
+
+    #[cfg(not(feature = "fusedev"))]
+    fn mount(_matches: &clap::ArgMatches) -> Result<()> {
+        bail!("nydus-image is built without fusedev support")
+    }
+
+    fn gc(matches: &clap::ArgMatches) -> Result<()> {
+        // Safe to unwrap because they are required arguments or have default values.
+        let blob_dir = matches.value_of("blob-dir").unwrap();
+        Self::ensure_directory(blob_dir)?;
+        let retention = gc::parse_retention(matches.value_of("retention").unwrap())?;
+        let dry_run = matches.is_present("dry-run");
+
+        let mut collector = gc::BlobGc::new(Path::new(blob_dir), retention, dry_run);
+        for bootstrap in matches.values_of("bootstrap").unwrap() {
+            collector.add_live_bootstrap(Path::new(bootstrap))?;
+        }
+        let report = collector.collect()?;
+
+        for entry in report.removed.iter() {
+            if dry_run {
+                println!("would remove {:?} ({} bytes)", entry.path, entry.size);
+            } else {
+                info!("removed {:?} ({} bytes)", entry.path, entry.size);
+            }
+        }
+        info!(
+            "{} {} unreferenced files with {} bytes, {} retained, {} unknown files skipped",
+            if dry_run { "found" } else { "removed" },
+            report.removed.len(),
+            report.reclaimed_bytes,
+            report.retained,
+            report.skipped
+        );
+
+        if let Some(f) = matches.value_of("output-json") {
+            let w = OpenOptions::new()
+                .truncate(true)
+                .create(true)
+                .write(true)
+                .open(f)
+                .with_context(|| format!("Output file {} can't be opened", f))?;
+            serde_json::to_writer(w, &report).context("Write output file failed")?;
+        }
+
+        Ok(())
+    }
+
+    fn ls(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap_path = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments or have default values.
+        let blob_dir = matches.value_of("blob-dir").unwrap();
+        let path = matches.value_of("PATH").unwrap();
+        Self::ensure_directory(blob_dir)?;
+
+        let reader = ImageReader::new(bootstrap_path, Path::new(blob_dir))?;
+        for entry in reader.list(path)? {
+            println!("{}", entry.format());
+        }
+
+        Ok(())
+    }
+
+    fn cat(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap_path = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments.
+        let blob_dir = matches.value_of("blob-dir").unwrap();
+        Self::ensure_directory(blob_dir)?;
+
+        let reader = ImageReader::new(bootstrap_path, Path::new(blob_dir))?;
+        if let Some(output) = matches.value_of("output") {
+            let paths: Vec<&str> = matches.values_of("PATH").unwrap().collect();
+            if paths.len() != 1 {
+                bail!("only one file can be written to --output");
+            }
+            reader.extract(paths[0], Path::new(output))?;
+            return Ok(());
+        }
+
+        let stdout = std::io::stdout();
+        let mut w = stdout.lock();
+        for path in matches.values_of("PATH").unwrap() {
+            reader.cat(path, &mut w)?;
+        }
+
+        Ok(())
+    }
+
+    fn delta(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap_path = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments.
+        let parent_path = Path::new(matches.value_of("parent-bootstrap").unwrap());
+        let output = matches.value_of("output").unwrap();
+
+        // The delta is keyed by superblock version of the parent, and both must be valid.
+        let sb_version = delta::bootstrap_version(parent_path)
+            .with_context(|| format!("failed to load parent bootstrap {:?}", parent_path))?;
+        delta::bootstrap_version(bootstrap_path)
+            .with_context(|| format!("failed to load bootstrap {:?}", bootstrap_path))?;
+        let parent = fs::read(parent_path)
+            .with_context(|| format!("failed to read parent bootstrap {:?}", parent_path))?;
+        let target = fs::read(bootstrap_path)
+            .with_context(|| format!("failed to read bootstrap {:?}", bootstrap_path))?;
+
+        let content = delta::generate_delta(&parent, &target, sb_version);
+        fs::write(output, &content)
+            .with_context(|| format!("failed to write bootstrap delta {}", output))?;
+        let header = delta::BootstrapDeltaHeader::from_bytes(&content)?;
+        let page_size = header.page_size as u64;
+        info!(
+            "generated bootstrap delta {} from {} to {}, {} of {} pages changed, {} bytes",
+            output,
+            header.parent_digest,
+            header.target_digest,
+            header.page_count,
+            (header.target_size + page_size - 1) / page_size,
+            content.len()
+        );
+
+        Ok(())
+    }
+
+    fn recompress(matches: &clap::ArgMatches, build_info: &BuildTimeInfo) -> Result<()> {
+        let bootstrap_path = Self::get_bootstrap(matches)?;
+        // Safe to unwrap because they are required arguments.
+        let blob_dir = matches.value_of("blob-dir").unwrap();
+        let output_blob_dir = matches.value_of("output-blob-dir").unwrap();
+        let output_bootstrap = matches.value_of("output-bootstrap").unwrap();
+        Self::ensure_directory(blob_dir)?;
+        Self::ensure_directory(output_blob_dir)?;
+        let compressor = matches.value_of("compressor").unwrap().parse()?;
+
+        // Filesystem properties are taken from the source bootstrap by the builder.
+        let mut build_ctx = BuildContext::new(
+            String::new(),
+            false,
+            compressor,
+            digest::Algorithm::default(),
+            true,
+            WhiteoutSpec::default(),
+            SourceType::Directory,
+            bootstrap_path.to_path_buf(),
+            Prefetch::default(),
+            Some(ArtifactStorage::FileDir(PathBuf::from(output_blob_dir))),
+        );
+        let mut bootstrap_mgr = BootstrapManager::new(
+            ArtifactStorage::SingleFile(PathBuf::from(output_bootstrap)),
+            None,
+        );
+        let mut blob_mgr = BlobManager::new();
+        let mut builder = RecompressBuilder::new(PathBuf::from(blob_dir));
+        let build_output = timing_tracer!(
+            {
+                builder
+                    .build(&mut build_ctx, &mut bootstrap_mgr, &mut blob_mgr)
+                    .context("recompress failed")
+            },
+            "total_build"
+        )?;
+
+        let bootstrap_path = bootstrap_mgr.get_bootstrap_path(&build_output.bootstrap_name);
+        Self::validate_image(matches, &bootstrap_path)?;
+        OutputSerializer::dump(matches, &build_output, build_info)?;
+        info!("recompress successfully: {:?}", build_output);
+
+        Ok(())
+    }
+
     fn stat(matches: &clap::ArgMatches) -> Result<()> {
         let mut stat = stat::ImageStat::new();
+        let report = match matches.value_of("report") {
+            Some(v) => Some(v.parse::<stat::ReportFormat>()?),
+            None => None,
+        };
+        stat.report_enabled = report.is_some();
+        if let Some(dict) = matches.value_of("chunk-dict") {
+            stat.set_chunk_dict(import_chunk_dict(dict)?)?;
+        }
 
         if let Some(blob) = matches.value_of("bootstrap").map(PathBuf::from) {
             stat.stat(&blob, true)?;
@@ -646,9 +1805,19 @@ impl Command {
 
         stat.finalize();
 
+        if let Some(format) = report {
+            if let Some(path) = matches.value_of("report-file") {
+                let mut file = File::create(path)
+                    .with_context(|| format!("failed to create report file {}", path))?;
+                stat.dump_report(&mut file, format)?;
+            } else {
+                stat.dump_report(&mut std::io::stdout(), format)?;
+            }
+        }
+
         if let Some(path) = matches.value_of("output-json").map(PathBuf::from) {
             stat.dump_json(&path)?;
-        } else {
+        } else if report.is_none() {
             stat.dump();
         }
 
@@ -672,7 +1841,13 @@ impl Command {
         // Must specify a path to blob file.
         // For cli/binary interface compatibility sake, keep option `backend-config`, but
         // it only receives "localfs" backend type and it will be REMOVED in the future
-        let blob_stor = if source_type == SourceType::Directory || source_type == SourceType::Diff {
+        if matches.is_present("no-blob") {
+            return Ok(None);
+        }
+        let blob_stor = if source_type == SourceType::Directory
+            || source_type == SourceType::Diff
+            || source_type == SourceType::OciV1
+        {
             if let Some(p) = matches
                 .value_of("blob")
                 .map(|b| ArtifactStorage::SingleFile(b.into()))
@@ -732,6 +1907,25 @@ impl Command {
         }
     }
 
+    fn get_trace_dumper(matches: &clap::ArgMatches) -> Result<Option<PeriodicTraceDumper>> {
+        match matches.value_of("trace-dump-file") {
+            None => Ok(None),
+            Some(path) => {
+                // Safe to unwrap because it has a default value.
+                let interval = matches
+                    .value_of("trace-dump-interval")
+                    .unwrap()
+                    .parse::<u64>()
+                    .context("invalid --trace-dump-interval")?;
+                ensure!(interval > 0, "--trace-dump-interval must be positive");
+                let dumper =
+                    PeriodicTraceDumper::start(PathBuf::from(path), Duration::from_secs(interval))
+                        .context("failed to start trace dumper")?;
+                Ok(Some(dumper))
+            }
+        }
+    }
+
     fn get_blob_id(matches: &clap::ArgMatches) -> Result<String> {
         let mut blob_id = String::new();
 
@@ -757,6 +1951,9 @@ impl Command {
                 },
                 "validate_bootstrap"
             )?;
+            validator
+                .check_image_digest(None)
+                .context("failed to validate bootstrap")?;
         }
 
         Ok(())
@@ -0,0 +1,96 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mount a RAFS image by an embedded fuse session for quick local inspection.
+//!
+//! The image is served by a single thread with data blobs read from a local directory, there's
+//! no blob cache, API server or live upgrade as in nydusd. The filesystem is unmounted on
+//! SIGINT or SIGTERM.
+
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use fuse_backend_rs::api::server::Server;
+use fuse_backend_rs::api::{Vfs, VfsOptions};
+use fuse_backend_rs::transport::fusedev::FuseSession;
+use nix::sys::signal;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::extract::load_rafs;
+
+// Raw fd of the eventfd to wake up the fuse service loop, which is written by signal handlers.
+static EXIT_EVENT_FD: AtomicI32 = AtomicI32::new(-1);
+static EXITING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn sig_exit(_sig: std::os::raw::c_int) {
+    EXITING.store(true, Ordering::Release);
+    let fd = EXIT_EVENT_FD.load(Ordering::Acquire);
+    if fd >= 0 {
+        let val = 1u64;
+        // Safe because write(2) is async-signal-safe and the buffer is valid.
+        unsafe { libc::write(fd, &val as *const u64 as *const libc::c_void, 8) };
+    }
+}
+
+/// Mount the image with bootstrap at `bootstrap` and data blobs in `blob_dir` at `mountpoint`,
+/// and serve it until the process is interrupted or the filesystem is unmounted.
+pub fn mount(bootstrap: &Path, blob_dir: &Path, mountpoint: &Path) -> Result<()> {
+    let rafs = load_rafs(bootstrap, blob_dir, &mountpoint.to_string_lossy())?;
+
+    let vfs = Vfs::new(VfsOptions {
+        no_open: true,
+        ..Default::default()
+    });
+    vfs.mount(Box::new(rafs), "/")
+        .map_err(|e| anyhow!("failed to mount rafs into vfs, {:?}", e))?;
+    let server = Server::new(Arc::new(vfs));
+
+    let event_fd = EventFd::new(0).context("failed to create eventfd")?;
+    let mut session = FuseSession::new(mountpoint, "rafs", "", true)
+        .map_err(|e| anyhow!("failed to create fuse session, {:?}", e))?;
+    session
+        .mount()
+        .map_err(|e| anyhow!("failed to mount {:?}, {:?}", mountpoint, e))?;
+    EXIT_EVENT_FD.store(event_fd.as_raw_fd(), Ordering::Release);
+    nydus_app::signal::register_signal_handler(signal::SIGINT, sig_exit);
+    nydus_app::signal::register_signal_handler(signal::SIGTERM, sig_exit);
+    info!("image mounted at {:?}, press Ctrl-C to unmount", mountpoint);
+
+    let result = serve(&server, &session, event_fd);
+    EXIT_EVENT_FD.store(-1, Ordering::Release);
+    session
+        .umount()
+        .map_err(|e| anyhow!("failed to umount {:?}, {:?}", mountpoint, e))?;
+    info!("image unmounted from {:?}", mountpoint);
+
+    result
+}
+
+fn serve(server: &Server<Arc<Vfs>>, session: &FuseSession, event_fd: EventFd) -> Result<()> {
+    let mut ch = session
+        .new_channel(event_fd)
+        .map_err(|e| anyhow!("failed to create fuse channel, {:?}", e))?;
+
+    while !EXITING.load(Ordering::Acquire) {
+        let (reader, writer) = match ch.get_request() {
+            Ok(Some(req)) => req,
+            Ok(None) => break,
+            // Waiting for requests may be interrupted by signals.
+            Err(_) if EXITING.load(Ordering::Acquire) => break,
+            Err(e) => bail!("failed to get fuse request, {:?}", e),
+        };
+        if let Err(e) = server.handle_message(reader, writer, None, None) {
+            match e {
+                // The fuse connection is gone, e.g. the filesystem is unmounted by others.
+                fuse_backend_rs::Error::EncodeMessage(_) => break,
+                _ => error!("failed to handle fuse message, {:?}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
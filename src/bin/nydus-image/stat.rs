@@ -4,15 +4,19 @@
 
 use std::collections::HashSet;
 use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, Context, Error, Result};
+use nydus_utils::digest::RafsDigest;
 use rafs::metadata::{RafsMode, RafsSuper};
 use serde::Serialize;
 
-use crate::core::chunk_dict::{ChunkDict, HashChunkDict};
-use crate::core::tree::Tree;
+use nydus::builder::core::chunk_dict::{ChunkDict, HashChunkDict};
+use nydus::builder::core::tree::Tree;
 
 #[derive(Copy, Clone, Default, Serialize)]
 struct DedupInfo {
@@ -26,6 +30,121 @@ struct DedupInfo {
     uncomp_image_size: u64,
 }
 
+/// Format of machine-readable per-image reports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ReportFormat {
+    Csv,
+    JsonLines,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => bail!("invalid report format {}", s),
+        }
+    }
+}
+
+const REPORT_COLUMNS: [&str; 16] = [
+    "image",
+    "role",
+    "dirs",
+    "files",
+    "symlinks",
+    "chunks",
+    "unique_chunks",
+    "unique_comp_size",
+    "unique_uncomp_size",
+    "shared_chunks",
+    "shared_uncomp_size",
+    "dedup_ratio",
+    "dict_chunks",
+    "dict_comp_size",
+    "pull_comp_size",
+    "pull_saving_ratio",
+];
+
+/// Statistics of a single image, written as a row of the report.
+///
+/// For a base image, chunks shared with any other base image are counted as shared. For the
+/// target image, chunks existing in the base image are counted as shared. `dedup_ratio` is the
+/// ratio of shared uncompressed size to unique uncompressed size of the image.
+///
+/// Unique chunks found in the chunk dictionary, if given, are assumed to be in a warmed cache, and
+/// `pull_comp_size` is the compressed size of the remaining unique chunks to download from the
+/// registry. `pull_saving_ratio` is the ratio of `dict_comp_size` to `unique_comp_size`.
+#[derive(Default, Serialize)]
+struct ImageReport {
+    image: String,
+    role: &'static str,
+    dirs: u32,
+    files: u32,
+    symlinks: u32,
+    chunks: u32,
+    unique_chunks: u64,
+    unique_comp_size: u64,
+    unique_uncomp_size: u64,
+    shared_chunks: u64,
+    shared_uncomp_size: u64,
+    dedup_ratio: f64,
+    dict_chunks: u64,
+    dict_comp_size: u64,
+    pull_comp_size: u64,
+    pull_saving_ratio: f64,
+    // Digests of unique chunks of a base image, to count shared chunks after loading all images.
+    #[serde(skip)]
+    chunk_ids: Vec<RafsDigest>,
+}
+
+impl ImageReport {
+    fn update_dedup_ratio(&mut self) {
+        self.dedup_ratio = if self.unique_uncomp_size == 0 {
+            0.0
+        } else {
+            self.shared_uncomp_size as f64 / self.unique_uncomp_size as f64
+        };
+        self.pull_saving_ratio = if self.unique_comp_size == 0 {
+            0.0
+        } else {
+            self.dict_comp_size as f64 / self.unique_comp_size as f64
+        };
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.image.clone(),
+            self.role.to_string(),
+            self.dirs.to_string(),
+            self.files.to_string(),
+            self.symlinks.to_string(),
+            self.chunks.to_string(),
+            self.unique_chunks.to_string(),
+            self.unique_comp_size.to_string(),
+            self.unique_uncomp_size.to_string(),
+            self.shared_chunks.to_string(),
+            self.shared_uncomp_size.to_string(),
+            format!("{:.4}", self.dedup_ratio),
+            self.dict_chunks.to_string(),
+            self.dict_comp_size.to_string(),
+            self.pull_comp_size.to_string(),
+            format!("{:.4}", self.pull_saving_ratio),
+        ]
+    }
+}
+
+// Quote a CSV field if it contains separators, quotes or line breaks.
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
 #[derive(Serialize)]
 struct ImageInfo {
     dirs: u32,
@@ -58,6 +177,14 @@ struct ImageInfo {
     ref_comp_size: u64,
     // Sum of uncompressed size of all reference chunks.
     ref_uncomp_size: u64,
+    // Number of unique chunks available from the chunk dictionary.
+    dict_chunks: u64,
+    // Sum of compressed size of unique chunks available from the chunk dictionary.
+    dict_comp_size: u64,
+    // Number of unique chunks to download, excluding those available from the chunk dictionary.
+    pull_chunks: u64,
+    // Sum of compressed size of unique chunks to download.
+    pull_comp_size: u64,
 }
 
 impl ImageInfo {
@@ -82,6 +209,10 @@ impl ImageInfo {
             ref_chunks: 0,
             ref_comp_size: 0,
             ref_uncomp_size: 0,
+            dict_chunks: 0,
+            dict_comp_size: 0,
+            pull_chunks: 0,
+            pull_comp_size: 0,
         }
     }
 
@@ -129,6 +260,13 @@ Compressed Size:        {comp_size}"#,
         println!("Referenced Uncomp Size:\t{}", self.ref_uncomp_size);
         println!("Referenced Chunk Count:\t{}", self.ref_chunks);
     }
+
+    fn dump_pull(&self) {
+        println!("Dict Comp Size:\t\t{}", self.dict_comp_size);
+        println!("Dict Chunk Count:\t{}", self.dict_chunks);
+        println!("Pull Comp Size:\t\t{}", self.pull_comp_size);
+        println!("Pull Chunk Count:\t{}", self.pull_chunks);
+    }
 }
 
 #[derive(Serialize)]
@@ -142,6 +280,14 @@ pub(crate) struct ImageStat {
     dedup_dict: HashChunkDict,
     #[serde(skip)]
     dedup_info: [DedupInfo; 20],
+    /// Collect per-image statistics for machine-readable reports.
+    #[serde(skip)]
+    pub report_enabled: bool,
+    #[serde(skip)]
+    reports: Vec<ImageReport>,
+    /// Chunk dictionary assumed to be in a warmed cache, to estimate data to pull for images.
+    #[serde(skip)]
+    chunk_dict: Option<Arc<dyn ChunkDict>>,
 }
 
 impl ImageStat {
@@ -154,9 +300,21 @@ impl ImageStat {
             target_image: ImageInfo::new(),
             dedup_dict: Default::default(),
             dedup_info: [Default::default(); 20],
+            report_enabled: false,
+            reports: Vec::new(),
+            chunk_dict: None,
         }
     }
 
+    /// Estimate data to pull for images, assuming chunks of the dictionary are already cached.
+    pub fn set_chunk_dict(&mut self, dict: Arc<dyn ChunkDict>) -> Result<()> {
+        if dict.as_remote().is_some() {
+            bail!("remote chunk dictionary is not supported by stat");
+        }
+        self.chunk_dict = Some(dict);
+        Ok(())
+    }
+
     pub fn stat(&mut self, path: &Path, is_base: bool) -> Result<()> {
         let p = match path.to_str() {
             None => bail!("invalid path to nydus image metadata blob"),
@@ -173,10 +331,16 @@ impl ImageStat {
         } else {
             &mut self.target_image
         };
+        let mut report = ImageReport {
+            image: p.to_string(),
+            role: if is_base { "base" } else { "target" },
+            ..Default::default()
+        };
 
         tree.iterate(&mut |node| {
             if node.is_reg() {
                 image.files += 1;
+                report.files += 1;
                 if node.is_hardlink() {
                     if hardlinks.contains(&node.inode.ino()) {
                         return true;
@@ -190,6 +354,7 @@ impl ImageStat {
                 image.padding_size += ((file_size + 0xfff) & !0xfff) - file_size;
 
                 image.chunks += node.chunks.len() as u32;
+                report.chunks += node.chunks.len() as u32;
                 for chunk in node.chunks.iter() {
                     image.comp_size += chunk.compressed_size() as u64;
                     image.uncomp_size += chunk.uncompressed_size() as u64;
@@ -200,12 +365,46 @@ impl ImageStat {
                 }
             } else if node.is_dir() {
                 image.dirs += 1;
+                report.dirs += 1;
             } else if node.is_symlink() {
                 image.symlinks += 1;
+                report.symlinks += 1;
             }
             true
         })?;
 
+        for entry in dict.m.values() {
+            report.unique_chunks += 1;
+            report.unique_comp_size += entry.0.compressed_size() as u64;
+            report.unique_uncomp_size += entry.0.uncompressed_size() as u64;
+            let comp_size = entry.0.compressed_size() as u64;
+            let cached = match self.chunk_dict.as_ref() {
+                Some(chunk_dict) => chunk_dict.get_chunk(entry.0.id()).is_some(),
+                None => false,
+            };
+            if cached {
+                image.dict_chunks += 1;
+                image.dict_comp_size += comp_size;
+                report.dict_chunks += 1;
+                report.dict_comp_size += comp_size;
+            } else {
+                image.pull_chunks += 1;
+                image.pull_comp_size += comp_size;
+                report.pull_comp_size += comp_size;
+            }
+            if is_base {
+                if self.report_enabled {
+                    report.chunk_ids.push(*entry.0.id());
+                }
+            } else if self.dedup_dict.get_chunk(entry.0.id()).is_some() {
+                report.shared_chunks += 1;
+                report.shared_uncomp_size += entry.0.uncompressed_size() as u64;
+            }
+        }
+        if self.report_enabled {
+            self.reports.push(report);
+        }
+
         if is_base {
             for entry in dict.m.values() {
                 image.own_chunks += 1;
@@ -233,6 +432,18 @@ impl ImageStat {
     pub fn finalize(&mut self) {
         self.base_image.uncomp_size += self.base_image.padding_size;
 
+        for report in self.reports.iter_mut() {
+            for id in report.chunk_ids.drain(..) {
+                if let Some(entry) = self.dedup_dict.m.get(&id) {
+                    if entry.1.load(Ordering::Relaxed) > 1 {
+                        report.shared_chunks += 1;
+                        report.shared_uncomp_size += entry.0.uncompressed_size() as u64;
+                    }
+                }
+            }
+            report.update_dedup_ratio();
+        }
+
         if self.target_enabled {
             self.target_image.uncomp_size += self.target_image.padding_size;
         }
@@ -283,6 +494,33 @@ impl ImageStat {
         Ok(())
     }
 
+    /// Write per-image statistics in `format`, one row for each image.
+    pub fn dump_report(&self, w: &mut dyn Write, format: ReportFormat) -> Result<()> {
+        match format {
+            ReportFormat::Csv => {
+                writeln!(w, "{}", REPORT_COLUMNS.join(","))?;
+                for report in self.reports.iter() {
+                    let values = report.values();
+                    let row = values
+                        .iter()
+                        .map(|v| csv_escape(v))
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    writeln!(w, "{}", row)?;
+                }
+            }
+            ReportFormat::JsonLines => {
+                for report in self.reports.iter() {
+                    serde_json::to_writer(&mut *w, report).context("failed to write report")?;
+                    writeln!(w)?;
+                }
+            }
+        }
+        w.flush()?;
+
+        Ok(())
+    }
+
     pub fn dump(&self) {
         if self.target_enabled {
             println!("Target Image Statistics:");
@@ -292,6 +530,16 @@ impl ImageStat {
         println!("\n\nBase Image Statistics:");
         self.base_image.dump();
 
+        if self.chunk_dict.is_some() {
+            println!("\n\nPull Estimation With Chunk Dictionary:");
+            if self.target_enabled {
+                println!("Target Image:");
+                self.target_image.dump_pull();
+            }
+            println!("Base Image:");
+            self.base_image.dump_pull();
+        }
+
         if self.dedup_enabled {
             println!("\n\nChunk Deduplication Statistics:");
             println!("Global Dedup Thresh:\tRaw Chunks:\tDedup Chunks:\tComp Content Size:\tComp Base Size:\tComp Image Size:\tUncomp Content Size:\tUncomp Base Size\tUncomp Image Size");
@@ -315,3 +563,104 @@ impl ImageStat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_report() {
+        assert_eq!("csv".parse::<ReportFormat>().unwrap(), ReportFormat::Csv);
+        assert_eq!(
+            "jsonl".parse::<ReportFormat>().unwrap(),
+            ReportFormat::JsonLines
+        );
+        assert!("json".parse::<ReportFormat>().is_err());
+        assert_eq!(csv_escape("a,b\"c"), "\"a,b\"\"c\"");
+
+        let mut stat = ImageStat::new();
+        let mut report = ImageReport {
+            image: "/images/a,b".to_string(),
+            role: "target",
+            files: 2,
+            chunks: 4,
+            unique_chunks: 3,
+            unique_uncomp_size: 0x4000,
+            shared_chunks: 1,
+            shared_uncomp_size: 0x1000,
+            ..Default::default()
+        };
+        report.update_dedup_ratio();
+        stat.reports.push(report);
+
+        let mut buf = Vec::new();
+        stat.dump_report(&mut buf, ReportFormat::Csv).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], REPORT_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "\"/images/a,b\",target,0,2,0,4,3,0,16384,1,4096,0.2500,0,0,0,0.0000"
+        );
+
+        let mut buf = Vec::new();
+        stat.dump_report(&mut buf, ReportFormat::JsonLines).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(value["image"], "/images/a,b");
+        assert_eq!(value["shared_chunks"], 1);
+        assert!(value.get("chunk_ids").is_none());
+    }
+
+    #[test]
+    fn test_pull_estimation() {
+        use nydus::builder::core::chunk_dict::import_chunk_dict;
+        use nydus::builder::ImageBuilder;
+        use vmm_sys_util::tempdir::TempDir;
+
+        let work_dir = TempDir::new().unwrap();
+        let mut bootstraps = Vec::new();
+        for (idx, files) in [["a", "b"], ["b", "c"]].iter().enumerate() {
+            let source = work_dir.as_path().join(format!("source-{}", idx));
+            std::fs::create_dir(&source).unwrap();
+            for name in files.iter() {
+                std::fs::write(source.join(name), name.repeat(0x1000)).unwrap();
+            }
+            let bootstrap = work_dir.as_path().join(format!("bootstrap-{}", idx));
+            ImageBuilder::new(&source)
+                .repeatable(true)
+                .bootstrap(&bootstrap)
+                .blob_dir(work_dir.as_path())
+                .build()
+                .unwrap();
+            bootstraps.push(bootstrap);
+        }
+
+        let mut stat = ImageStat::new();
+        stat.report_enabled = true;
+        stat.target_enabled = true;
+        stat.set_chunk_dict(import_chunk_dict(bootstraps[0].to_str().unwrap()).unwrap())
+            .unwrap();
+        stat.stat(&bootstraps[0], true).unwrap();
+        stat.stat(&bootstraps[1], false).unwrap();
+        stat.finalize();
+
+        // All chunks of the dictionary image itself are cached.
+        let base = &stat.reports[0];
+        assert_eq!(base.dict_chunks, 2);
+        assert_eq!(base.pull_comp_size, 0);
+        assert_eq!(base.pull_saving_ratio, 1.0);
+
+        // Only the chunk of file "c" needs to be pulled for the target image.
+        let target = &stat.reports[1];
+        assert_eq!(target.unique_chunks, 2);
+        assert_eq!(target.dict_chunks, 1);
+        assert_eq!(
+            target.dict_comp_size + target.pull_comp_size,
+            target.unique_comp_size
+        );
+        assert!(target.pull_saving_ratio > 0.0 && target.pull_saving_ratio < 1.0);
+        assert_eq!(stat.target_image.pull_chunks, 1);
+        assert_eq!(stat.target_image.pull_comp_size, target.pull_comp_size);
+    }
+}
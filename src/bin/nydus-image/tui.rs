@@ -0,0 +1,624 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interactive terminal UI to explore RAFS bootstraps.
+//!
+//! The screen is split into a tree pane to navigate the directory tree, and a detail pane showing
+//! attributes and chunks of the selected file. It's drawn with plain ANSI escape sequences while
+//! the terminal is in raw mode, on top of query primitives of `RafsInspector`.
+
+use std::ffi::OsString;
+use std::fs::Permissions;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::RawFd;
+
+use anyhow::Result;
+use nix::sys::termios::{self, SetArg, Termios};
+use rafs::metadata::layout::v5::RafsV5ChunkInfo;
+use storage::device::BlobChunkFlags;
+
+use crate::inspect::{Action, RafsInspector};
+
+const STDIN_FD: RawFd = 0;
+const STDOUT_FD: RawFd = 1;
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    Char(char),
+    Unknown,
+}
+
+impl Key {
+    fn parse(buf: &[u8]) -> Key {
+        match buf {
+            [b'\x1b', b'[', b'A'] | [b'\x1b', b'O', b'A'] => Key::Up,
+            [b'\x1b', b'[', b'B'] | [b'\x1b', b'O', b'B'] => Key::Down,
+            [b'\x1b', b'[', b'C'] | [b'\x1b', b'O', b'C'] => Key::Right,
+            [b'\x1b', b'[', b'D'] | [b'\x1b', b'O', b'D'] => Key::Left,
+            [b'\x1b', b'[', b'H'] | [b'\x1b', b'[', b'1', b'~'] => Key::Home,
+            [b'\x1b', b'[', b'F'] | [b'\x1b', b'[', b'4', b'~'] => Key::End,
+            [b'\x1b', b'[', b'5', b'~'] => Key::PageUp,
+            [b'\x1b', b'[', b'6', b'~'] => Key::PageDown,
+            [b'\x1b'] => Key::Esc,
+            [b'\r'] | [b'\n'] => Key::Enter,
+            [b'\t'] => Key::Tab,
+            [0x7f] | [0x08] => Key::Backspace,
+            _ => match std::str::from_utf8(buf) {
+                Ok(s) if s.chars().count() == 1 && !s.starts_with('\x1b') => {
+                    Key::Char(s.chars().next().unwrap())
+                }
+                _ => Key::Unknown,
+            },
+        }
+    }
+}
+
+// Switch the terminal into raw mode and alternate screen, and restore it when dropped.
+struct RawTerminal {
+    orig: Termios,
+}
+
+impl RawTerminal {
+    fn new() -> Result<Self> {
+        let orig = termios::tcgetattr(STDIN_FD)?;
+        let mut raw = orig.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(STDIN_FD, SetArg::TCSANOW, &raw)?;
+        print!("\x1b[?1049h\x1b[?25l");
+        std::io::stdout().flush()?;
+
+        Ok(RawTerminal { orig })
+    }
+
+    fn size() -> (usize, usize) {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let ret = unsafe { libc::ioctl(STDOUT_FD, libc::TIOCGWINSZ, &mut ws) };
+        if ret < 0 || ws.ws_row == 0 || ws.ws_col == 0 {
+            (24, 80)
+        } else {
+            (ws.ws_row as usize, ws.ws_col as usize)
+        }
+    }
+
+    fn read_key(&self) -> Result<Key> {
+        let mut buf = [0u8; 8];
+        let cnt = std::io::stdin().read(&mut buf)?;
+        Ok(Key::parse(&buf[..cnt]))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        let _ = termios::tcsetattr(STDIN_FD, SetArg::TCSANOW, &self.orig);
+    }
+}
+
+// A visible row of the tree pane.
+struct Row {
+    index: u32,
+    name: OsString,
+    depth: usize,
+    is_dir: bool,
+    expanded: bool,
+}
+
+#[derive(PartialEq)]
+enum Focus {
+    Tree,
+    Chunks,
+}
+
+// Information of the selected file shown in the detail pane.
+#[derive(Default)]
+struct Detail {
+    index: Option<u32>,
+    attrs: Vec<String>,
+    chunks: Vec<(RafsV5ChunkInfo, String)>,
+    selected: usize,
+    top: usize,
+}
+
+pub(crate) struct Tui {
+    inspector: RafsInspector,
+    rows: Vec<Row>,
+    selected: usize,
+    top: usize,
+    detail: Detail,
+    focus: Focus,
+    // Pattern being typed in search mode.
+    input: Option<String>,
+    // Inode indexes of files matching the last search pattern.
+    matches: Vec<u32>,
+    next_match: usize,
+    message: String,
+}
+
+impl Tui {
+    pub(crate) fn run(inspector: RafsInspector) -> Result<()> {
+        let mut tui = Tui {
+            inspector,
+            rows: Vec::new(),
+            selected: 0,
+            top: 0,
+            detail: Detail::default(),
+            focus: Focus::Tree,
+            input: None,
+            matches: Vec::new(),
+            next_match: 0,
+            message: String::new(),
+        };
+        tui.rows.push(Row {
+            index: 0,
+            name: OsString::from("/"),
+            depth: 0,
+            is_dir: true,
+            expanded: false,
+        });
+        tui.expand(0)?;
+
+        let term = RawTerminal::new()?;
+        loop {
+            tui.load_detail();
+            tui.draw()?;
+            let key = term.read_key()?;
+            tui.message.clear();
+            if tui.input.is_some() {
+                tui.handle_input(key);
+            } else if !tui.handle_key(key) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Handle a key in navigation mode, return false to quit.
+    fn handle_key(&mut self, key: Key) -> bool {
+        let (height, _) = RawTerminal::size();
+        let page = height.saturating_sub(2).max(1);
+
+        if self.focus == Focus::Chunks {
+            let count = self.detail.chunks.len();
+            match key {
+                Key::Up | Key::Char('k') => {
+                    self.detail.selected = self.detail.selected.saturating_sub(1)
+                }
+                Key::Down | Key::Char('j') => {
+                    if self.detail.selected + 1 < count {
+                        self.detail.selected += 1;
+                    }
+                }
+                Key::PageUp => self.detail.selected = self.detail.selected.saturating_sub(page),
+                Key::PageDown => {
+                    self.detail.selected = (self.detail.selected + page).min(count.max(1) - 1)
+                }
+                Key::Home => self.detail.selected = 0,
+                Key::End => self.detail.selected = count.max(1) - 1,
+                Key::Tab | Key::Esc | Key::Left | Key::Char('h') => self.focus = Focus::Tree,
+                Key::Char('q') => return false,
+                _ => {}
+            }
+            return true;
+        }
+
+        let result = match key {
+            Key::Up | Key::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                Ok(())
+            }
+            Key::Down | Key::Char('j') => {
+                if self.selected + 1 < self.rows.len() {
+                    self.selected += 1;
+                }
+                Ok(())
+            }
+            Key::PageUp => {
+                self.selected = self.selected.saturating_sub(page);
+                Ok(())
+            }
+            Key::PageDown => {
+                self.selected = (self.selected + page).min(self.rows.len() - 1);
+                Ok(())
+            }
+            Key::Home => {
+                self.selected = 0;
+                Ok(())
+            }
+            Key::End => {
+                self.selected = self.rows.len() - 1;
+                Ok(())
+            }
+            Key::Right | Key::Enter | Key::Char('l') => {
+                let (is_dir, expanded) = {
+                    let row = &self.rows[self.selected];
+                    (row.is_dir, row.expanded)
+                };
+                if is_dir && !expanded {
+                    self.expand(self.selected)
+                } else if !is_dir && !self.detail.chunks.is_empty() {
+                    self.focus = Focus::Chunks;
+                    Ok(())
+                } else {
+                    Ok(())
+                }
+            }
+            Key::Left | Key::Backspace | Key::Char('h') => {
+                if self.rows[self.selected].expanded {
+                    self.collapse(self.selected);
+                } else if let Some(parent) = self.parent_row(self.selected) {
+                    self.selected = parent;
+                }
+                Ok(())
+            }
+            Key::Tab => {
+                if !self.detail.chunks.is_empty() {
+                    self.focus = Focus::Chunks;
+                }
+                Ok(())
+            }
+            Key::Char('/') => {
+                self.input = Some(String::new());
+                Ok(())
+            }
+            Key::Char('n') => self.goto_next_match(),
+            Key::Char('q') => return false,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.message = format!("{}", e);
+        }
+
+        true
+    }
+
+    // Handle a key when typing the search pattern.
+    fn handle_input(&mut self, key: Key) {
+        let input = self.input.as_mut().unwrap();
+        match key {
+            Key::Char(c) if !c.is_control() => input.push(c),
+            Key::Backspace => {
+                input.pop();
+            }
+            Key::Esc => self.input = None,
+            Key::Enter => {
+                let pattern = self.input.take().unwrap();
+                if !pattern.is_empty() {
+                    if let Err(e) = self.search(&pattern) {
+                        self.message = format!("{}", e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Insert children of the directory at `row` after it.
+    fn expand(&mut self, row: usize) -> Result<()> {
+        let index = self.rows[row].index;
+        let depth = self.rows[row].depth + 1;
+        let mut children = Vec::new();
+        self.inspector
+            .iter_children(index, |name, inode, idx, _offset| {
+                children.push(Row {
+                    index: idx,
+                    name: name.to_os_string(),
+                    depth,
+                    is_dir: inode.is_dir(),
+                    expanded: false,
+                });
+                Action::Continue
+            })?;
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.rows[row].expanded = true;
+        let tail = self.rows.split_off(row + 1);
+        self.rows.extend(children);
+        self.rows.extend(tail);
+
+        Ok(())
+    }
+
+    fn collapse(&mut self, row: usize) {
+        let depth = self.rows[row].depth;
+        let end = self.rows[row + 1..]
+            .iter()
+            .position(|r| r.depth <= depth)
+            .map(|p| row + 1 + p)
+            .unwrap_or_else(|| self.rows.len());
+        self.rows.drain(row + 1..end);
+        self.rows[row].expanded = false;
+    }
+
+    fn parent_row(&self, row: usize) -> Option<usize> {
+        let depth = self.rows[row].depth;
+        self.rows[..row].iter().rposition(|r| r.depth < depth)
+    }
+
+    fn search(&mut self, pattern: &str) -> Result<()> {
+        let mut matches = Vec::new();
+        self.inspector
+            .walk_fs(0, &mut |name, _inode, idx, _offset| {
+                if name.to_string_lossy().contains(pattern) {
+                    matches.push(idx);
+                }
+                Action::Continue
+            })?;
+        if matches.is_empty() {
+            self.message = format!("no file matching {:?}", pattern);
+            return Ok(());
+        }
+
+        matches.sort_unstable();
+        self.matches = matches;
+        self.next_match = 0;
+        self.goto_next_match()
+    }
+
+    // Reveal the next file matching the search pattern in the tree pane.
+    fn goto_next_match(&mut self) -> Result<()> {
+        if self.matches.is_empty() {
+            return Ok(());
+        }
+        let target = self.matches[self.next_match];
+        self.message = format!("match {} of {}", self.next_match + 1, self.matches.len());
+        self.next_match = (self.next_match + 1) % self.matches.len();
+
+        // Inode index equals to inode number minus one.
+        let mut ancestors = vec![target];
+        let mut index = target;
+        while index != 0 {
+            let (inode, _) = self.inspector.load_inode_by_index(index as usize)?;
+            index = inode.parent().saturating_sub(1) as u32;
+            ancestors.push(index);
+        }
+        ancestors.reverse();
+
+        let mut row = 0;
+        for (depth, index) in ancestors.iter().enumerate().skip(1) {
+            if !self.rows[row].expanded {
+                self.expand(row)?;
+            }
+            row = self.rows[row + 1..]
+                .iter()
+                .position(|r| r.depth == depth && r.index == *index)
+                .map(|p| row + 1 + p)
+                .ok_or_else(|| anyhow!("can't find inode with index {} in tree", index))?;
+        }
+        self.selected = row;
+        self.focus = Focus::Tree;
+
+        Ok(())
+    }
+
+    fn load_detail(&mut self) {
+        let index = self.rows[self.selected].index;
+        if self.detail.index == Some(index) {
+            return;
+        }
+        self.detail = Detail {
+            index: Some(index),
+            ..Default::default()
+        };
+
+        let (inode, name) = match self.inspector.load_inode_by_index(index as usize) {
+            Ok(v) => v,
+            Err(e) => {
+                self.detail
+                    .attrs
+                    .push(format!("failed to load inode, {}", e));
+                return;
+            }
+        };
+        let path = self
+            .inspector
+            .path_from_ino(inode.ino())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| name.to_string_lossy().to_string());
+        let attrs = &mut self.detail.attrs;
+        attrs.push(format!("Path:         {}", path));
+        attrs.push(format!("Inode Number: {}", inode.ino()));
+        attrs.push(format!("Index:        {}", index));
+        attrs.push(format!("Size:         {}", inode.size()));
+        attrs.push(format!("Mode:         0x{:X}", inode.mode()));
+        attrs.push(format!(
+            "Permissions:  {:o}",
+            Permissions::from_mode(inode.mode()).mode()
+        ));
+        attrs.push(format!("Nlink:        {}", inode.nlink()));
+        attrs.push(format!("UID:          {}", inode.uid()));
+        attrs.push(format!("GID:          {}", inode.gid()));
+        attrs.push(format!(
+            "Mtime:        {}.{:09}",
+            inode.mtime(),
+            inode.mtime_nsec()
+        ));
+        if inode.is_dir() {
+            attrs.push(format!("Children:     {}", inode.child_count()));
+        }
+
+        if inode.is_reg() {
+            let offset = self.inspector.inode_offset(index);
+            match self.inspector.load_file_chunks(&inode, offset) {
+                Ok(chunks) => self.detail.chunks = chunks,
+                Err(e) => self
+                    .detail
+                    .attrs
+                    .push(format!("failed to load chunks, {}", e)),
+            }
+        }
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let (height, width) = RawTerminal::size();
+        let body = height.saturating_sub(1).max(1);
+        let left = (width * 2 / 5).max(1);
+        let right = width.saturating_sub(left + 1);
+
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + body {
+            self.top = self.selected + 1 - body;
+        }
+        let detail = self.detail_lines(body);
+
+        let mut out = String::from("\x1b[H");
+        for line in 0..body {
+            out.push_str(&format!("\x1b[{};1H", line + 1));
+            if let Some(row) = self.rows.get(self.top + line) {
+                let mark = if !row.is_dir {
+                    ' '
+                } else if row.expanded {
+                    '-'
+                } else {
+                    '+'
+                };
+                let text = format!(
+                    "{}{} {}",
+                    "  ".repeat(row.depth),
+                    mark,
+                    row.name.to_string_lossy()
+                );
+                let text = fit(&text, left);
+                if self.top + line == self.selected {
+                    let style = if self.focus == Focus::Tree { "7" } else { "4" };
+                    out.push_str(&format!("\x1b[{}m{}\x1b[0m", style, text));
+                } else {
+                    out.push_str(&text);
+                }
+            } else {
+                out.push_str(&fit("", left));
+            }
+            out.push('|');
+            if let Some((text, highlight)) = detail.get(line) {
+                if *highlight {
+                    out.push_str(&format!("\x1b[7m{}\x1b[0m", fit(text, right)));
+                } else {
+                    out.push_str(&fit(text, right));
+                }
+            }
+            out.push_str("\x1b[K");
+        }
+
+        out.push_str(&format!("\x1b[{};1H\x1b[K", height));
+        let status = if let Some(input) = self.input.as_ref() {
+            format!("/{}", input)
+        } else if !self.message.is_empty() {
+            self.message.clone()
+        } else {
+            "q:quit  arrows/hjkl:navigate  enter:expand  tab:chunks  /:search  n:next match"
+                .to_string()
+        };
+        out.push_str(&fit(&status, width));
+
+        let mut stdout = std::io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    // Generate lines of the detail pane, with flags whether to highlight them.
+    fn detail_lines(&mut self, height: usize) -> Vec<(String, bool)> {
+        let mut lines: Vec<(String, bool)> = self
+            .detail
+            .attrs
+            .iter()
+            .map(|l| (l.clone(), false))
+            .collect();
+        let chunks = &self.detail.chunks;
+        if chunks.is_empty() {
+            return lines;
+        }
+
+        lines.push((String::new(), false));
+        lines.push((format!("Chunks:       {}", chunks.len()), false));
+        lines.push((
+            format!(
+                "{:>6} {:>12} {:>12} {:>9} {:>12} {:>9} {:>5}",
+                "Index", "File Off", "Comp Off", "Comp Sz", "Decomp Off", "Decomp Sz", "Comp"
+            ),
+            false,
+        ));
+
+        // Reserve lines for details of the selected chunk.
+        let (selected_chunk, blob_id) = &chunks[self.detail.selected];
+        let table = height.saturating_sub(lines.len() + 3).max(1);
+        if self.detail.selected < self.detail.top {
+            self.detail.top = self.detail.selected;
+        } else if self.detail.selected >= self.detail.top + table {
+            self.detail.top = self.detail.selected + 1 - table;
+        }
+        for (idx, (c, _)) in chunks.iter().enumerate().skip(self.detail.top).take(table) {
+            let text = format!(
+                "{:>6} {:>12} {:>12} {:>9} {:>12} {:>9} {:>5}",
+                c.index,
+                c.file_offset,
+                c.compress_offset,
+                c.compress_size,
+                c.uncompress_offset,
+                c.uncompress_size,
+                if c.flags.contains(BlobChunkFlags::COMPRESSED) {
+                    "y"
+                } else {
+                    "n"
+                }
+            );
+            let highlight = self.focus == Focus::Chunks && idx == self.detail.selected;
+            lines.push((text, highlight));
+        }
+
+        lines.push((String::new(), false));
+        lines.push((format!("Blob:   {}", blob_id), false));
+        lines.push((format!("Digest: {}", selected_chunk.block_id), false));
+
+        lines
+    }
+}
+
+// Truncate or pad `text` to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    let mut s: String = text.chars().take(width).collect();
+    let len = s.chars().count();
+    s.extend(std::iter::repeat(' ').take(width - len));
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(Key::parse(b"\x1b[A"), Key::Up);
+        assert_eq!(Key::parse(b"\x1bOB"), Key::Down);
+        assert_eq!(Key::parse(b"\x1b[6~"), Key::PageDown);
+        assert_eq!(Key::parse(b"\x1b"), Key::Esc);
+        assert_eq!(Key::parse(b"\r"), Key::Enter);
+        assert_eq!(Key::parse(b"q"), Key::Char('q'));
+        assert_eq!(Key::parse("é".as_bytes()), Key::Char('é'));
+        assert_eq!(Key::parse(b"\x1b[Z"), Key::Unknown);
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcdef", 3), "abc");
+        assert_eq!(fit("", 0), "");
+    }
+}
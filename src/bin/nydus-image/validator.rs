@@ -3,16 +3,61 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Validator for RAFS format
+//!
+//! The validator walks the inode tree directly on the mmapped bootstrap instead of building an
+//! in-memory tree, so bootstraps larger than available memory can be validated. Only directories
+//! pending to be walked are kept in memory.
 
-use std::path::Path;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{Context, Error, Result};
-use rafs::metadata::{RafsMode, RafsSuper};
+use nydus::builder::core::platform;
+use rafs::metadata::layout::RAFS_ROOT_INODE;
+use rafs::metadata::{RafsInode, RafsMode, RafsSuper};
+use rafs::RafsIoReader;
+use storage::device::BlobInfo;
 
-use crate::tree::Tree;
+use crate::diff::{diff_trees, DiffKind};
+
+/// Extended attribute holding file capabilities.
+pub const CAPABILITY_XATTR: &str = "security.capability";
+
+/// Privilege related metadata of a source file, which is dropped from the built image.
+#[derive(Debug)]
+pub struct DroppedPrivilege {
+    /// Path of the file in the image.
+    pub path: PathBuf,
+    /// Dropped metadata, `setuid`, `setgid` or `security.capability`.
+    pub what: &'static str,
+}
+
+// Compare setuid/setgid bits and file capabilities of a source file with those in the image.
+fn dropped_privileges(
+    src_mode: u32,
+    src_caps: Option<&[u8]>,
+    mode: u32,
+    caps: Option<&[u8]>,
+) -> Vec<&'static str> {
+    let mut dropped = Vec::new();
+    if src_mode & libc::S_ISUID != 0 && mode & libc::S_ISUID == 0 {
+        dropped.push("setuid");
+    }
+    if src_mode & libc::S_ISGID != 0 && mode & libc::S_ISGID == 0 {
+        dropped.push("setgid");
+    }
+    if src_caps.is_some() && src_caps != caps {
+        dropped.push(CAPABILITY_XATTR);
+    }
+    dropped
+}
 
 pub struct Validator {
     sb: RafsSuper,
+    path: PathBuf,
 }
 
 impl Validator {
@@ -22,31 +67,313 @@ impl Validator {
             .ok_or_else(|| Error::msg("bootstrap path is invalid"))?;
         let sb = RafsSuper::load_from_metadata(path, RafsMode::Direct, true)?;
 
-        Ok(Self { sb })
+        Ok(Self {
+            sb,
+            path: bootstrap_path.to_path_buf(),
+        })
+    }
+
+    /// Verify the image digest recorded in the bootstrap, and against `expected` if specified.
+    ///
+    /// Return the recorded image digest, or `None` if the image is built without it and no
+    /// expected digest is specified.
+    pub fn check_image_digest(&self, expected: Option<&str>) -> Result<Option<String>> {
+        let digest = match self.sb.meta.image_digest {
+            Some(digest) => digest,
+            None if expected.is_none() => {
+                warn!("image digest is not recorded in bootstrap {:?}", self.path);
+                return Ok(None);
+            }
+            None => bail!("image digest is not recorded in bootstrap {:?}", self.path),
+        };
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open bootstrap {:?}", self.path))?;
+        let mut reader = Box::new(file) as RafsIoReader;
+        self.sb
+            .verify_image_digest(&mut reader, expected)
+            .context("failed to verify image digest")?;
+
+        Ok(Some(digest.to_string()))
     }
 
     pub fn check(&mut self, verbosity: bool) -> Result<Vec<String>> {
-        let err = "failed to load bootstrap for validator";
-        let tree = Tree::from_bootstrap(&self.sb, &mut ()).context(err)?;
+        let blobs = self.sb.superblock.get_blob_infos();
+        let max_inodes = self.sb.meta.inodes_count;
+        let mut inodes = 0u64;
+        let mut dirs = vec![(RAFS_ROOT_INODE, PathBuf::from("/"))];
 
-        tree.iterate(&mut |node| {
-            if verbosity {
-                info!("{}", node);
-                for chunk in &node.chunks {
-                    debug!("chunk {}", chunk);
+        // Digest of each inode is validated when loading it, and digest of a directory covers
+        // digests of its children, so the whole tree is validated after walking all inodes.
+        let root = self
+            .sb
+            .get_inode(RAFS_ROOT_INODE, true)
+            .context("failed to load root inode")?;
+        self.check_inode(&root, Path::new("/"), &blobs, verbosity)?;
+        inodes += 1;
+
+        while let Some((ino, path)) = dirs.pop() {
+            let dir = self
+                .sb
+                .get_inode(ino, true)
+                .with_context(|| format!("failed to load directory {:?}", path))?;
+            let child_index = dir.get_child_index()? as u64;
+            for idx in 0..dir.get_child_count() {
+                let child = self
+                    .sb
+                    .get_inode(child_index + idx as u64, true)
+                    .with_context(|| format!("failed to load child {} of {:?}", idx, path))?;
+                let child_path = path.join(child.name());
+                inodes += 1;
+                if inodes > max_inodes {
+                    bail!("more inodes than recorded in super block {}", max_inodes);
+                }
+                // Hardlinks share the inode, whose parent may be another directory.
+                if child.parent() != ino && !child.is_hardlink() {
+                    bail!(
+                        "parent of {:?} is {}, expected {}",
+                        child_path,
+                        child.parent(),
+                        ino
+                    );
+                }
+                self.check_inode(&child, &child_path, &blobs, verbosity)?;
+                if child.is_dir() {
+                    // Inode numbers of subdirectories always grow, so there's no loop.
+                    if child.ino() <= ino {
+                        bail!(
+                            "invalid inode number {} of directory {:?}",
+                            child.ino(),
+                            child_path
+                        );
+                    }
+                    dirs.push((child.ino(), child_path));
                 }
             }
-            true
-        })?;
+        }
 
-        let blob_ids = self
-            .sb
-            .superblock
-            .get_blob_infos()
+        Ok(blobs
             .iter()
             .map(|entry| entry.blob_id().to_owned())
-            .collect::<Vec<String>>();
+            .collect::<Vec<String>>())
+    }
+
+    /// Validate only inodes added or modified relative to the image whose bootstrap is at
+    /// `parent_path`, which is assumed to have been validated.
+    ///
+    /// Unchanged inodes are loaded without digest validation to diff the two images, so it's
+    /// much faster than a full check for images sharing most files with their parents.
+    pub fn check_incremental(
+        &mut self,
+        parent_path: &Path,
+        verbosity: bool,
+    ) -> Result<Vec<String>> {
+        let path = parent_path
+            .to_str()
+            .ok_or_else(|| Error::msg("parent bootstrap path is invalid"))?;
+        let parent = RafsSuper::load_from_metadata(path, RafsMode::Direct, false)
+            .with_context(|| format!("failed to load parent bootstrap {:?}", parent_path))?;
+        let blobs = self.sb.superblock.get_blob_infos();
+        let entries = diff_trees(&parent, &self.sb)?;
+
+        let (mut added, mut modified, mut removed) = (0, 0, 0);
+        for entry in entries.iter() {
+            let ino = match entry.ino {
+                Some(ino) => ino,
+                None => {
+                    if verbosity {
+                        info!("{:?}: removed", entry.path);
+                    }
+                    removed += 1;
+                    continue;
+                }
+            };
+            if entry.kind == DiffKind::Added {
+                added += 1;
+            } else {
+                modified += 1;
+            }
+            let inode = self
+                .sb
+                .get_inode(ino, true)
+                .with_context(|| format!("failed to load {:?}", entry.path))?;
+            self.check_inode(&inode, &entry.path, &blobs, verbosity)?;
+        }
+        info!(
+            "validated {} added and {} modified inodes, {} inodes removed",
+            added, modified, removed
+        );
+
+        Ok(blobs
+            .iter()
+            .map(|entry| entry.blob_id().to_owned())
+            .collect::<Vec<String>>())
+    }
+
+    /// Compare setuid/setgid bits and file capabilities of files in the image with those of
+    /// the source directory, returning metadata dropped from the image.
+    ///
+    /// They may be dropped silently when building as non-root from certain filesystems. Files
+    /// which are missing in the source directory are skipped. File capabilities are not
+    /// compared if `check_caps` is false, e.g. excluded deliberately.
+    pub fn check_privileges(
+        &self,
+        source: &Path,
+        check_caps: bool,
+    ) -> Result<Vec<DroppedPrivilege>> {
+        let mut dropped = Vec::new();
+        let mut dirs = vec![(RAFS_ROOT_INODE, PathBuf::from("/"))];
+        let key = OsStr::new(CAPABILITY_XATTR);
+
+        while let Some((ino, path)) = dirs.pop() {
+            let dir = self
+                .sb
+                .get_inode(ino, false)
+                .with_context(|| format!("failed to load directory {:?}", path))?;
+            let child_index = dir.get_child_index()? as u64;
+            let mut inodes = vec![(dir.clone(), path.clone())];
+            for idx in 0..dir.get_child_count() {
+                let child = self
+                    .sb
+                    .get_inode(child_index + idx as u64, false)
+                    .with_context(|| format!("failed to load child {} of {:?}", idx, path))?;
+                let child_path = path.join(child.name());
+                if child.is_dir() {
+                    dirs.push((child.ino(), child_path));
+                } else if child.is_reg() {
+                    inodes.push((child, child_path));
+                }
+            }
+
+            for (inode, path) in inodes {
+                let src_path = source.join(path.strip_prefix("/").unwrap_or(&path));
+                let meta = match src_path.symlink_metadata() {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        debug!("skip checking privileges of {:?}, {}", src_path, e);
+                        continue;
+                    }
+                };
+                let (src_caps, caps) = if check_caps && inode.is_reg() {
+                    let src_caps = platform::get_xattr(&src_path, key).unwrap_or_else(|e| {
+                        debug!("failed to get capabilities of {:?}, {}", src_path, e);
+                        None
+                    });
+                    let caps = inode
+                        .get_xattr(key)
+                        .with_context(|| format!("failed to get xattr of {:?}", path))?;
+                    (src_caps, caps)
+                } else {
+                    (None, None)
+                };
+                for what in dropped_privileges(
+                    meta.mode(),
+                    src_caps.as_deref(),
+                    inode.get_attr().mode,
+                    caps.as_deref(),
+                ) {
+                    dropped.push(DroppedPrivilege {
+                        path: path.clone(),
+                        what,
+                    });
+                }
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    fn check_inode(
+        &self,
+        inode: &Arc<dyn RafsInode>,
+        path: &Path,
+        blobs: &[Arc<BlobInfo>],
+        verbosity: bool,
+    ) -> Result<()> {
+        if verbosity {
+            info!(
+                "{:?}: ino {} i_parent {} child_count {} i_size {} is_dir {} is_hardlink {}",
+                path,
+                inode.ino(),
+                inode.parent(),
+                inode.get_child_count(),
+                inode.size(),
+                inode.is_dir(),
+                inode.is_hardlink(),
+            );
+        }
+        if !inode.is_reg() {
+            return Ok(());
+        }
+
+        let chunk_size = self.sb.meta.chunk_size;
+        for idx in 0..inode.get_chunk_count() {
+            let chunk = inode
+                .get_chunk_info(idx)
+                .with_context(|| format!("failed to load chunk {} of {:?}", idx, path))?;
+            if verbosity {
+                debug!(
+                    "chunk {}: blob_index {} compress_offset {} compress_size {} uncompress_offset {} uncompress_size {}",
+                    chunk.chunk_id(),
+                    chunk.blob_index(),
+                    chunk.compress_offset(),
+                    chunk.compress_size(),
+                    chunk.uncompress_offset(),
+                    chunk.uncompress_size()
+                );
+            }
+
+            let blob = blobs.get(chunk.blob_index() as usize).ok_or_else(|| {
+                anyhow!(
+                    "invalid blob index {} of chunk {} of {:?}",
+                    chunk.blob_index(),
+                    idx,
+                    path
+                )
+            })?;
+            if chunk.uncompress_size() > chunk_size {
+                bail!("chunk {} of {:?} is bigger than chunk size", idx, path);
+            }
+            // Sizes of blobs may be unknown for images built by old versions.
+            let compress_end = chunk.compress_offset() + chunk.compress_size() as u64;
+            if blob.compressed_size() > 0 && compress_end > blob.compressed_size() {
+                bail!(
+                    "chunk {} of {:?} is out of compressed range of blob {}",
+                    idx,
+                    path,
+                    blob.blob_id()
+                );
+            }
+            let uncompress_end = chunk.uncompress_offset() + chunk.uncompress_size() as u64;
+            if blob.uncompressed_size() > 0 && uncompress_end > blob.uncompressed_size() {
+                bail!(
+                    "chunk {} of {:?} is out of uncompressed range of blob {}",
+                    idx,
+                    path,
+                    blob.blob_id()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(blob_ids)
+    #[test]
+    fn test_dropped_privileges() {
+        let caps = [1u8, 0, 0, 2];
+        assert!(dropped_privileges(0o4755, Some(&caps), 0o4755, Some(&caps)).is_empty());
+        assert!(dropped_privileges(0o755, None, 0o755, None).is_empty());
+        assert_eq!(
+            dropped_privileges(0o6755, Some(&caps), 0o755, None),
+            vec!["setuid", "setgid", CAPABILITY_XATTR]
+        );
+        assert_eq!(
+            dropped_privileges(0o2755, Some(&caps), 0o2755, Some(&caps[..2])),
+            vec![CAPABILITY_XATTR]
+        );
     }
 }
@@ -26,6 +26,7 @@ use commands::{
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    nydus_app::install_panic_hook(&[]);
     let app = App::new("A client to query and configure nydusd")
         .version(crate_version!())
         .author(crate_authors!())
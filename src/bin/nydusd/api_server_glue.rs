@@ -4,9 +4,11 @@
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
 use std::convert::From;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
+use std::time::Duration;
 
 use event_manager::{EventOps, EventSubscriber, Events};
 use nix::sys::signal::{kill, SIGTERM};
@@ -15,14 +17,20 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use nydus::{FsBackendType, NydusError};
 use nydus_api::http_endpoint::{
-    ApiError, ApiMountCmd, ApiRequest, ApiResponse, ApiResponsePayload, ApiResult, DaemonConf,
-    DaemonErrorKind, MetricsErrorKind,
+    ApiBlobBackendCmd, ApiCacheTypeCmd, ApiDrainCmd, ApiError, ApiInvalidateCacheCmd, ApiMountCmd,
+    ApiPrefetchCmd, ApiPrefetchJobCmd, ApiProfileCmd, ApiReadTraceCmd, ApiRequest, ApiResponse,
+    ApiResponsePayload, ApiResult, DaemonConf, DaemonErrorKind, MetricsErrorKind,
 };
-use nydus_utils::metrics;
+use nydus_utils::{metrics, trace};
+use storage::factory::BLOB_FACTORY;
 
-use crate::daemon::{DaemonError, FsBackendMountCmd, FsBackendUmountCmd, NydusDaemon};
+use crate::daemon::{
+    DaemonError, FsBackendBlobCmd, FsBackendMountCmd, FsBackendUmountCmd, FsPrefetchCmd,
+    FsPrefetchJobCmd, NydusDaemon,
+};
 #[cfg(fusedev)]
 use crate::fusedev::FusedevDaemon;
+use crate::profile;
 
 type Result<T> = ApiResult<T>;
 
@@ -69,9 +77,28 @@ impl ApiServer {
 
         let resp = match request {
             ApiRequest::DaemonInfo => self.daemon_info(),
-            ApiRequest::ExportFsBackendInfo(mountpoint) => self.backend_info(&mountpoint),
+            ApiRequest::ExportFsBackendInfo(mountpoint) => self.backend_info(mountpoint.as_deref()),
+            ApiRequest::ScrubFsBackend(mountpoint, chunks) => {
+                self.backend_scrub(&mountpoint, chunks)
+            }
+            ApiRequest::SwitchBlobBackend(mountpoint, cmd) => {
+                self.switch_blob_backend(&mountpoint, cmd)
+            }
+            ApiRequest::SwitchCacheType(mountpoint, cmd) => {
+                self.switch_cache_type(&mountpoint, cmd)
+            }
+            ApiRequest::InvalidateCache(cmd) => self.invalidate_cache(cmd),
+            ApiRequest::GetFsPrefetchStatus(mountpoint) => self.prefetch_status(&mountpoint),
+            ApiRequest::ControlFsPrefetch(mountpoint, cmd) => {
+                self.control_prefetch(&mountpoint, cmd)
+            }
+            ApiRequest::StartPrefetchJob(cmd) => self.start_prefetch_job(cmd),
+            ApiRequest::GetPrefetchJob(id) => self.prefetch_job_status(id),
+            ApiRequest::GcBlobcache(force) => Self::gc_blobcache(force),
+            ApiRequest::CompactBlobcache => Self::compact_blobcache(),
             ApiRequest::ConfigureDaemon(conf) => self.configure_daemon(conf),
             ApiRequest::Exit => self.do_exit(),
+            ApiRequest::Drain(cmd) => self.do_drain(cmd),
 
             ApiRequest::Mount(mountpoint, info) => self.do_mount(mountpoint, info),
             ApiRequest::Remount(mountpoint, info) => self.do_remount(mountpoint, info),
@@ -85,10 +112,24 @@ impl ApiServer {
             ApiRequest::ExportAccessPatterns(id) => Self::export_access_patterns(id),
             ApiRequest::ExportBackendMetrics(id) => Self::export_backend_metrics(id),
             ApiRequest::ExportBlobcacheMetrics(id) => Self::export_blobcache_metrics(id),
+            ApiRequest::ExportAccountingMetrics(id, reset) => {
+                Self::export_accounting_metrics(id, reset)
+            }
+            ApiRequest::ExportBufferPoolMetrics => Self::export_buffer_pool_metrics(),
             ApiRequest::ExportInflightMetrics => self.export_inflight_metrics(),
+            ApiRequest::ExportPrometheusMetrics => Self::export_prometheus_metrics(),
 
             ApiRequest::SendFuseFd => self.send_fuse_fd(),
             ApiRequest::Takeover => self.do_takeover(),
+            ApiRequest::ListFuseSessions => self.list_fuse_sessions(),
+            ApiRequest::CreateFuseSession(mountpoint, info) => {
+                self.create_fuse_session(mountpoint, info)
+            }
+            ApiRequest::DestroyFuseSession(mountpoint) => self.destroy_fuse_session(mountpoint),
+            ApiRequest::ExportDaemonProfile => self.export_profile(),
+            ApiRequest::StartProfileSampling(cmd) => Self::start_profile_sampling(cmd),
+            ApiRequest::GetReadTrace => Self::read_trace_status(),
+            ApiRequest::ControlReadTrace(cmd) => Self::control_read_trace(cmd),
         };
 
         self.respond(resp);
@@ -110,7 +151,70 @@ impl ApiServer {
         Ok(ApiResponsePayload::DaemonInfo(info))
     }
 
-    fn backend_info(&self, mountpoint: &str) -> ApiResponse {
+    fn export_profile(&self) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        let profile = d
+            .export_profile()
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(e.into())))?;
+        Ok(ApiResponsePayload::DaemonProfile(profile))
+    }
+
+    fn start_profile_sampling(cmd: ApiProfileCmd) -> ApiResponse {
+        profile::start_sampling(
+            PathBuf::from(cmd.path),
+            Duration::from_secs(cmd.duration_secs),
+            cmd.frequency,
+        )
+        .map(|_| ApiResponsePayload::Empty)
+        .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Other(e.to_string())))
+    }
+
+    fn read_trace_status() -> ApiResponse {
+        let status = serde_json::to_string(&trace::read_tracer().status())
+            .map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Other(e.to_string())))?;
+        Ok(ApiResponsePayload::ReadTrace(status))
+    }
+
+    fn control_read_trace(cmd: ApiReadTraceCmd) -> ApiResponse {
+        let tracer = trace::read_tracer();
+        match cmd.action.as_str() {
+            "start" => tracer.start(cmd.capacity),
+            "stop" => tracer.stop(),
+            "export" => {
+                let spans = tracer.take_spans();
+                let result = match (cmd.format.as_deref(), cmd.path, cmd.endpoint) {
+                    (Some("chrome"), Some(path), _) => std::fs::File::create(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|f| {
+                            serde_json::to_writer(f, &trace::to_chrome_trace(&spans))
+                                .map_err(|e| e.to_string())
+                        }),
+                    (Some("otlp"), _, Some(endpoint)) => {
+                        trace::export_otlp(&endpoint, &spans, "nydusd").map_err(|e| e.to_string())
+                    }
+                    (Some("chrome"), None, _) => {
+                        Err("path is required by chrome format".to_string())
+                    }
+                    (Some("otlp"), _, None) => {
+                        Err("endpoint is required by otlp format".to_string())
+                    }
+                    (f, _, _) => Err(format!("unknown trace format {:?}", f)),
+                };
+                result.map_err(|e| ApiError::DaemonAbnormal(DaemonErrorKind::Other(e)))?;
+                let result = serde_json::json!({ "spans": spans.len() });
+                return Ok(ApiResponsePayload::ReadTrace(result.to_string()));
+            }
+            _ => {
+                return Err(ApiError::DaemonAbnormal(DaemonErrorKind::Other(format!(
+                    "unknown read trace action {}",
+                    cmd.action
+                ))))
+            }
+        }
+        Ok(ApiResponsePayload::Empty)
+    }
+
+    fn backend_info(&self, mountpoint: Option<&str>) -> ApiResponse {
         let d = self.daemon.as_ref();
         let info = d
             .export_backend_info(mountpoint)
@@ -118,6 +222,109 @@ impl ApiServer {
         Ok(ApiResponsePayload::FsBackendInfo(info))
     }
 
+    fn backend_scrub(&self, mountpoint: &str, chunks: Option<u32>) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        let stat = d
+            .scrub_backend(mountpoint, chunks)
+            .map_err(|e| ApiError::Scrub(e.into()))?;
+        Ok(ApiResponsePayload::FsBackendScrub(stat))
+    }
+
+    fn invalidate_cache(&self, cmd: ApiInvalidateCacheCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        let result = d
+            .invalidate_cache(
+                cmd.mountpoint.as_deref(),
+                cmd.blob_id.as_deref(),
+                cmd.path.as_deref(),
+            )
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Daemon(e.into())))?;
+        Ok(ApiResponsePayload::CacheInvalidate(result))
+    }
+
+    fn gc_blobcache(force: bool) -> ApiResponse {
+        let reclaimed = BLOB_FACTORY.reclaim_cache_files(force);
+        info!(
+            "reclaimed {} unreferenced blob cache files",
+            reclaimed.len()
+        );
+        let result = serde_json::json!({ "reclaimed": reclaimed });
+        Ok(ApiResponsePayload::BlobcacheGc(result.to_string()))
+    }
+
+    fn compact_blobcache() -> ApiResponse {
+        let stat = BLOB_FACTORY.compact_cache_files();
+        info!(
+            "compacted blob cache files, released {} bytes and removed {} files",
+            stat.punched_bytes,
+            stat.removed.len()
+        );
+        let result = serde_json::json!(stat);
+        Ok(ApiResponsePayload::BlobcacheCompact(result.to_string()))
+    }
+
+    fn switch_blob_backend(&self, mountpoint: &str, cmd: ApiBlobBackendCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        d.switch_blob_backend(
+            mountpoint,
+            FsBackendBlobCmd {
+                blob_id: cmd.blob_id,
+                object_id: cmd.object_id,
+                backend: cmd.backend,
+                flush_cache: cmd.flush_cache,
+            },
+        )
+        .map(|_| ApiResponsePayload::Empty)
+        .map_err(|e| ApiError::DaemonAbnormal(e.into()))
+    }
+
+    fn switch_cache_type(&self, mountpoint: &str, cmd: ApiCacheTypeCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        d.switch_cache_type(mountpoint, &cmd.cache_type)
+            .map(|_| ApiResponsePayload::Empty)
+            .map_err(|e| ApiError::DaemonAbnormal(e.into()))
+    }
+
+    fn prefetch_status(&self, mountpoint: &str) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        let status = d
+            .prefetch_status(mountpoint)
+            .map_err(|e| ApiError::DaemonAbnormal(e.into()))?;
+        Ok(ApiResponsePayload::FsPrefetchStatus(status))
+    }
+
+    fn control_prefetch(&self, mountpoint: &str, cmd: ApiPrefetchCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        d.control_prefetch(
+            mountpoint,
+            FsPrefetchCmd {
+                action: cmd.action,
+                files: cmd.files,
+            },
+        )
+        .map(|_| ApiResponsePayload::Empty)
+        .map_err(|e| ApiError::DaemonAbnormal(e.into()))
+    }
+
+    fn start_prefetch_job(&self, cmd: ApiPrefetchJobCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        d.start_prefetch_job(FsPrefetchJobCmd {
+            mountpoint: cmd.mountpoint,
+            files: cmd.files,
+            all: cmd.all,
+        })
+        .map(ApiResponsePayload::PrefetchJob)
+        .map_err(|e| ApiError::DaemonAbnormal(e.into()))
+    }
+
+    fn prefetch_job_status(&self, id: u64) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        let status = d
+            .prefetch_job_status(id)
+            .map_err(|e| ApiError::DaemonAbnormal(e.into()))?;
+        Ok(ApiResponsePayload::PrefetchJob(status))
+    }
+
     fn configure_daemon(&self, conf: DaemonConf) -> ApiResponse {
         conf.log_level
             .parse::<log::LevelFilter>()
@@ -167,6 +374,24 @@ impl ApiServer {
             .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
     }
 
+    fn export_accounting_metrics(id: Option<String>, reset: bool) -> ApiResponse {
+        metrics::export_accounting(&id, reset)
+            .map(ApiResponsePayload::AccountingMetrics)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
+    fn export_buffer_pool_metrics() -> ApiResponse {
+        metrics::export_buffer_pool_metrics()
+            .map(ApiResponsePayload::BufferPoolMetrics)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
+    fn export_prometheus_metrics() -> ApiResponse {
+        metrics::export_prometheus_metrics()
+            .map(ApiResponsePayload::PrometheusMetrics)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
     /// Detect if there is fop being hang.
     /// `ApiResponsePayload::Empty` will be converted to http status code 204, which means
     /// there is no requests being processed right now.
@@ -227,6 +452,18 @@ impl ApiServer {
         Ok(ApiResponsePayload::Empty)
     }
 
+    fn do_drain(&self, cmd: ApiDrainCmd) -> ApiResponse {
+        let d = self.daemon.as_ref();
+        d.trigger_drain(Duration::from_secs(cmd.timeout_secs))
+            .map_err(|e| ApiError::DaemonAbnormal(e.into()))?;
+        info!("drain daemon by http request");
+
+        // Fuse session has been umounted, so quit nydusd as `exit` does.
+        kill(Pid::this(), SIGTERM).unwrap_or_else(|e| error!("Send signal error. {}", e));
+
+        Ok(ApiResponsePayload::Empty)
+    }
+
     fn do_mount(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
         let fs_type = FsBackendType::from_str(&cmd.fs_type)
             .map_err(|e| ApiError::MountFailure(DaemonError::from(e).into()))?;
@@ -264,6 +501,35 @@ impl ApiServer {
             .map_err(|e| ApiError::MountFailure(e.into()))
     }
 
+    fn list_fuse_sessions(&self) -> ApiResponse {
+        self.daemon
+            .export_fuse_sessions()
+            .map(ApiResponsePayload::FuseSessions)
+            .map_err(|e| ApiError::DaemonAbnormal(e.into()))
+    }
+
+    fn create_fuse_session(&self, mountpoint: String, cmd: ApiMountCmd) -> ApiResponse {
+        let fs_type = FsBackendType::from_str(&cmd.fs_type)
+            .map_err(|e| ApiError::MountFailure(DaemonError::from(e).into()))?;
+        self.daemon
+            .create_fuse_session(FsBackendMountCmd {
+                fs_type,
+                mountpoint,
+                config: cmd.config,
+                source: cmd.source,
+                prefetch_files: cmd.prefetch_files,
+            })
+            .map(|_| ApiResponsePayload::Empty)
+            .map_err(|e| ApiError::MountFailure(e.into()))
+    }
+
+    fn destroy_fuse_session(&self, mountpoint: String) -> ApiResponse {
+        self.daemon
+            .destroy_fuse_session(&mountpoint)
+            .map(|_| ApiResponsePayload::Empty)
+            .map_err(|e| ApiError::MountFailure(e.into()))
+    }
+
     fn send_fuse_fd(&self) -> ApiResponse {
         let d = self.daemon.as_ref();
 
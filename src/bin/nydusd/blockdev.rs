@@ -0,0 +1,517 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Export a RAFS v6 image as a read-only block device through the Linux NBD driver.
+//!
+//! RAFS v6 images are EROFS compatible, so they may be mounted by the in-kernel EROFS filesystem
+//! instead of fuse. The bootstrap and all data blobs of the image are composed into a flat block
+//! device: the bootstrap comes first, followed by uncompressed data of each blob at a block
+//! aligned address. The address is recorded as `mapped_blkaddr` into the device slot of the blob
+//! when serving the bootstrap, so EROFS reads blob data from the same block device. Reads of
+//! blob data are served by the blob cache, which fetches chunks from the storage backend on
+//! demand, so the blob cache must keep data in uncompressed form.
+//!
+//! The NBD device is connected to one end of a socket pair through ioctls, so no NBD handshake
+//! is needed. Exporting the image through ublk is not supported yet.
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Result, Write};
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use rafs::fs::RafsConfig;
+use rafs::metadata::layout::v6::{RafsV6Device, EROFS_BLOCK_SIZE, EROFS_DEVTABLE_OFFSET};
+use rafs::metadata::{RafsMode, RafsSuper};
+use storage::device::BlobDevice;
+
+use crate::exit_event_manager;
+
+// Ioctl commands of the NBD driver, defined in `include/uapi/linux/nbd.h`.
+const NBD_SET_SOCK: u64 = 0xab00;
+const NBD_SET_BLKSIZE: u64 = 0xab01;
+const NBD_DO_IT: u64 = 0xab03;
+const NBD_CLEAR_SOCK: u64 = 0xab04;
+const NBD_CLEAR_QUE: u64 = 0xab05;
+const NBD_SET_SIZE_BLOCKS: u64 = 0xab07;
+const NBD_DISCONNECT: u64 = 0xab08;
+const NBD_SET_FLAGS: u64 = 0xab0a;
+
+const NBD_FLAG_HAS_FLAGS: u64 = 1 << 0;
+const NBD_FLAG_READ_ONLY: u64 = 1 << 1;
+const NBD_FLAG_SEND_FLUSH: u64 = 1 << 2;
+
+const NBD_REQUEST_MAGIC: u32 = 0x2560_9513;
+const NBD_REPLY_MAGIC: u32 = 0x6744_6698;
+const NBD_REQUEST_SIZE: usize = 28;
+const NBD_REPLY_SIZE: usize = 16;
+
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_CMD_FLUSH: u16 = 3;
+
+// Upper limit of data size of a single read request, the NBD driver splits requests at
+// `max_sectors` which is much smaller.
+const MAX_READ_SIZE: u32 = 0x200_0000;
+
+fn round_up_block(size: u64) -> u64 {
+    (size + EROFS_BLOCK_SIZE - 1) & !(EROFS_BLOCK_SIZE - 1)
+}
+
+/// A region of the block device backed by uncompressed data of a blob.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BlobRegion {
+    blob_index: u32,
+    // Offset of the region on the block device.
+    start: u64,
+    // Size of uncompressed blob data, the tail of the last block is filled with zero.
+    size: u64,
+}
+
+/// Layout of a RAFS v6 image composed into a flat block device.
+struct BlockLayout {
+    meta: Vec<u8>,
+    blobs: Vec<BlobRegion>,
+    size: u64,
+}
+
+impl BlockLayout {
+    /// Compose bootstrap content `meta` and blobs with uncompressed size `blob_sizes` into a flat
+    /// block device, and update device slots in the bootstrap to point to the blob regions.
+    fn new(mut meta: Vec<u8>, blob_sizes: &[u64]) -> Result<Self> {
+        let slot_size = size_of::<RafsV6Device>();
+        let slot_start = EROFS_DEVTABLE_OFFSET as usize;
+        if meta.len() < slot_start + slot_size * blob_sizes.len() {
+            return Err(einval!("bootstrap is too small to hold the device table"));
+        }
+        meta.resize(round_up_block(meta.len() as u64) as usize, 0);
+
+        let mut blobs = Vec::with_capacity(blob_sizes.len());
+        let mut pos = meta.len() as u64;
+        for (idx, size) in blob_sizes.iter().enumerate() {
+            let blkaddr = pos / EROFS_BLOCK_SIZE;
+            let blocks = round_up_block(*size) / EROFS_BLOCK_SIZE;
+            if blkaddr + blocks > u32::MAX as u64 {
+                return Err(einval!("image is too big to export as a block device"));
+            }
+
+            let offset = slot_start + idx * slot_size;
+            let mut slot = RafsV6Device::new();
+            slot.as_mut()
+                .copy_from_slice(&meta[offset..offset + slot_size]);
+            slot.set_blocks(blocks as u32);
+            slot.set_mapped_blkaddr(blkaddr as u32);
+            meta[offset..offset + slot_size].copy_from_slice(slot.as_ref());
+
+            blobs.push(BlobRegion {
+                blob_index: idx as u32,
+                start: pos,
+                size: *size,
+            });
+            pos += blocks * EROFS_BLOCK_SIZE;
+        }
+
+        Ok(BlockLayout {
+            meta,
+            blobs,
+            size: pos,
+        })
+    }
+
+    /// Fill `buf` with data of the block device at `offset`, reading blob data by `read_blob`.
+    fn read_at<F>(&self, buf: &mut [u8], offset: u64, mut read_blob: F) -> Result<()>
+    where
+        F: FnMut(u32, &mut [u8], u64) -> Result<usize>,
+    {
+        if offset
+            .checked_add(buf.len() as u64)
+            .map(|end| end > self.size)
+            .unwrap_or(true)
+        {
+            return Err(einval!("read beyond end of the block device"));
+        }
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let off = offset + pos as u64;
+            let remaining = buf.len() - pos;
+            if off < self.meta.len() as u64 {
+                let start = off as usize;
+                let cnt = cmp::min(remaining, self.meta.len() - start);
+                buf[pos..pos + cnt].copy_from_slice(&self.meta[start..start + cnt]);
+                pos += cnt;
+                continue;
+            }
+
+            // The meta region is followed by blob regions, so `off` must be within a blob region.
+            let idx = match self.blobs.binary_search_by(|r| r.start.cmp(&off)) {
+                Ok(idx) => idx,
+                Err(idx) => idx - 1,
+            };
+            let region = &self.blobs[idx];
+            let end = self
+                .blobs
+                .get(idx + 1)
+                .map(|r| r.start)
+                .unwrap_or(self.size);
+            let cnt = cmp::min(remaining as u64, end - off) as usize;
+            let blob_offset = off - region.start;
+            let data = if blob_offset < region.size {
+                cmp::min(cnt as u64, region.size - blob_offset) as usize
+            } else {
+                0
+            };
+
+            let mut done = 0;
+            while done < data {
+                let sz = read_blob(
+                    region.blob_index,
+                    &mut buf[pos + done..pos + data],
+                    blob_offset + done as u64,
+                )?;
+                if sz == 0 {
+                    return Err(eio!(format!(
+                        "unexpected end of blob {} at {}",
+                        region.blob_index,
+                        blob_offset + done as u64
+                    )));
+                }
+                done += sz;
+            }
+            for b in buf[pos + data..pos + cnt].iter_mut() {
+                *b = 0;
+            }
+            pos += cnt;
+        }
+
+        Ok(())
+    }
+}
+
+/// A RAFS v6 image composed into a flat read-only block device.
+pub struct BlockImage {
+    layout: BlockLayout,
+    device: BlobDevice,
+}
+
+impl BlockImage {
+    /// Create a block image from the RAFS v6 bootstrap, with storage configured by `config`.
+    pub fn new(id: &str, bootstrap: &str, config: &str) -> Result<Self> {
+        let rafs_config = RafsConfig::from_str(config)
+            .map_err(|e| einval!(format!("failed to parse configuration, {:?}", e)))?;
+        rafs_config.validate().map_err(|e| einval!(e.to_string()))?;
+        let sb = RafsSuper::load_from_metadata(bootstrap, RafsMode::Direct, false)?;
+        if !sb.meta.is_v6() {
+            return Err(einval!("only RAFS v6 images can be exported"));
+        }
+
+        let mut storage_conf = rafs_config.device;
+        if storage_conf.id.is_empty() {
+            storage_conf.id = id.to_string();
+        }
+        let blob_infos = sb.superblock.get_blob_infos();
+        let device = BlobDevice::new(&Arc::new(storage_conf), &blob_infos)?;
+        // Make sure blob data may be addressed by uncompressed offset before exporting.
+        for blob in blob_infos.iter() {
+            device.read_uncompressed(blob.blob_index(), &mut [], 0)?;
+        }
+
+        let sizes: Vec<u64> = blob_infos.iter().map(|b| b.uncompressed_size()).collect();
+        let layout = BlockLayout::new(std::fs::read(bootstrap)?, &sizes)?;
+
+        Ok(BlockImage { layout, device })
+    }
+
+    /// Get size of the block device in bytes.
+    pub fn size(&self) -> u64 {
+        self.layout.size
+    }
+
+    /// Fill `buf` with data of the block device at `offset`.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.layout.read_at(buf, offset, |idx, buf, off| {
+            self.device.read_uncompressed(idx, buf, off)
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct NbdRequest {
+    cmd: u16,
+    handle: [u8; 8],
+    offset: u64,
+    len: u32,
+}
+
+impl NbdRequest {
+    fn parse(buf: &[u8; NBD_REQUEST_SIZE]) -> Result<Self> {
+        let magic = u32::from_be_bytes(buf[0..4].try_into().unwrap());
+        if magic != NBD_REQUEST_MAGIC {
+            return Err(einval!(format!("invalid NBD request magic 0x{:x}", magic)));
+        }
+
+        // The high 16 bits of the type field are command flags.
+        Ok(NbdRequest {
+            cmd: u16::from_be_bytes(buf[6..8].try_into().unwrap()),
+            handle: buf[8..16].try_into().unwrap(),
+            offset: u64::from_be_bytes(buf[16..24].try_into().unwrap()),
+            len: u32::from_be_bytes(buf[24..28].try_into().unwrap()),
+        })
+    }
+
+    fn reply(&self, error: i32) -> [u8; NBD_REPLY_SIZE] {
+        let mut buf = [0u8; NBD_REPLY_SIZE];
+        buf[0..4].copy_from_slice(&NBD_REPLY_MAGIC.to_be_bytes());
+        buf[4..8].copy_from_slice(&(error as u32).to_be_bytes());
+        buf[8..16].copy_from_slice(&self.handle);
+        buf
+    }
+}
+
+/// Serve a block image through a Linux NBD device, such as `/dev/nbd0`.
+pub struct NbdServer {
+    path: String,
+    device: Arc<File>,
+    image: Arc<BlockImage>,
+}
+
+impl NbdServer {
+    pub fn new(path: &str, image: Arc<BlockImage>) -> Result<Self> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to open NBD device {}, {}", path, e),
+                )
+            })?;
+
+        Ok(NbdServer {
+            path: path.to_string(),
+            device: Arc::new(device),
+            image,
+        })
+    }
+
+    /// Connect the NBD device to the block image and start serving block requests.
+    ///
+    /// The returned thread exits after the NBD device has been disconnected, and then nydusd
+    /// exits too.
+    pub fn start(&self) -> Result<JoinHandle<Result<()>>> {
+        let (local, remote) = UnixStream::pair()?;
+        let blocks = self.image.size() / EROFS_BLOCK_SIZE;
+        let flags = NBD_FLAG_HAS_FLAGS | NBD_FLAG_READ_ONLY | NBD_FLAG_SEND_FLUSH;
+        Self::ioctl(&self.device, NBD_SET_BLKSIZE, EROFS_BLOCK_SIZE)?;
+        Self::ioctl(&self.device, NBD_SET_SIZE_BLOCKS, blocks)?;
+        Self::ioctl(&self.device, NBD_SET_FLAGS, flags)?;
+        Self::ioctl(&self.device, NBD_SET_SOCK, remote.as_raw_fd() as u64)?;
+
+        let image = self.image.clone();
+        thread::Builder::new()
+            .name("nbd_server".to_string())
+            .spawn(move || {
+                if let Err(e) = Self::serve(&image, local) {
+                    error!("failed to serve NBD requests, {}", e);
+                }
+            })?;
+
+        let device = self.device.clone();
+        let path = self.path.clone();
+        thread::Builder::new()
+            .name("nbd_device".to_string())
+            .spawn(move || {
+                // NBD_DO_IT blocks until the NBD device gets disconnected.
+                let ret = Self::ioctl(&device, NBD_DO_IT, 0);
+                let _ = Self::ioctl(&device, NBD_CLEAR_QUE, 0);
+                let _ = Self::ioctl(&device, NBD_CLEAR_SOCK, 0);
+                drop(remote);
+                info!("NBD device {} disconnected", path);
+                exit_event_manager();
+                ret
+            })
+    }
+
+    /// Disconnect the NBD device, the block device must have been umounted.
+    pub fn stop(&self) -> Result<()> {
+        Self::ioctl(&self.device, NBD_DISCONNECT, 0)
+    }
+
+    fn ioctl(device: &File, cmd: u64, arg: u64) -> Result<()> {
+        let ret = unsafe { libc::ioctl(device.as_raw_fd(), cmd as _, arg as libc::c_ulong) };
+        if ret < 0 {
+            return Err(last_error!(format!("NBD ioctl 0x{:x} failed", cmd)));
+        }
+
+        Ok(())
+    }
+
+    fn serve(image: &BlockImage, mut sock: UnixStream) -> Result<()> {
+        let mut buf = [0u8; NBD_REQUEST_SIZE];
+        loop {
+            match sock.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+
+            let req = NbdRequest::parse(&buf)?;
+            match req.cmd {
+                NBD_CMD_READ if req.len <= MAX_READ_SIZE => {
+                    let mut data = vec![0u8; NBD_REPLY_SIZE + req.len as usize];
+                    match image.read_at(&mut data[NBD_REPLY_SIZE..], req.offset) {
+                        Ok(()) => {
+                            data[..NBD_REPLY_SIZE].copy_from_slice(&req.reply(0));
+                            sock.write_all(&data)?;
+                        }
+                        Err(e) => {
+                            warn!(
+                                "failed to read block device at {} size {}, {}",
+                                req.offset, req.len, e
+                            );
+                            sock.write_all(&req.reply(libc::EIO))?;
+                        }
+                    }
+                }
+                NBD_CMD_WRITE => {
+                    // Discard the payload to keep the stream in sync.
+                    io::copy(&mut (&sock).take(req.len as u64), &mut io::sink())?;
+                    sock.write_all(&req.reply(libc::EPERM))?;
+                }
+                NBD_CMD_FLUSH => sock.write_all(&req.reply(0))?,
+                NBD_CMD_DISC => return Ok(()),
+                _ => sock.write_all(&req.reply(libc::EINVAL))?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_meta(size: usize, slots: usize) -> Vec<u8> {
+        let mut meta = vec![0xa5u8; size];
+        for idx in 0..slots {
+            let mut slot = RafsV6Device::new();
+            slot.set_blob_id(&[b'0' + idx as u8; 64]);
+            let offset = EROFS_DEVTABLE_OFFSET as usize + idx * size_of::<RafsV6Device>();
+            meta[offset..offset + size_of::<RafsV6Device>()].copy_from_slice(slot.as_ref());
+        }
+        meta
+    }
+
+    fn load_slot(layout: &BlockLayout, idx: usize) -> RafsV6Device {
+        let offset = EROFS_DEVTABLE_OFFSET as usize + idx * size_of::<RafsV6Device>();
+        let mut slot = RafsV6Device::new();
+        slot.as_mut()
+            .copy_from_slice(&layout.meta[offset..offset + size_of::<RafsV6Device>()]);
+        slot
+    }
+
+    #[test]
+    fn test_block_layout() {
+        let layout = BlockLayout::new(new_meta(5000, 2), &[4096, 5000]).unwrap();
+        assert_eq!(layout.meta.len(), 8192);
+        assert_eq!(layout.size, 8192 + 4096 + 8192);
+        assert_eq!(
+            layout.blobs,
+            vec![
+                BlobRegion {
+                    blob_index: 0,
+                    start: 8192,
+                    size: 4096
+                },
+                BlobRegion {
+                    blob_index: 1,
+                    start: 12288,
+                    size: 5000
+                },
+            ]
+        );
+
+        let slot = load_slot(&layout, 0);
+        assert_eq!(slot.blob_id(), &[b'0'; 64][..]);
+        assert_eq!(slot.mapped_blkaddr(), 2);
+        assert_eq!(slot.blocks(), 1);
+        let slot = load_slot(&layout, 1);
+        assert_eq!(slot.blob_id(), &[b'1'; 64][..]);
+        assert_eq!(slot.mapped_blkaddr(), 3);
+        assert_eq!(slot.blocks(), 2);
+
+        assert!(BlockLayout::new(vec![0u8; 1024], &[4096]).is_err());
+    }
+
+    #[test]
+    fn test_block_layout_read() {
+        let layout = BlockLayout::new(new_meta(5000, 2), &[4096, 5000]).unwrap();
+        let read_blob = |idx: u32, buf: &mut [u8], off: u64| -> Result<usize> {
+            assert!(off + buf.len() as u64 <= [4096, 5000][idx as usize]);
+            // Return data in small pieces to exercise short reads.
+            let cnt = cmp::min(buf.len(), 1000);
+            for b in buf[..cnt].iter_mut() {
+                *b = idx as u8 + 1;
+            }
+            Ok(cnt)
+        };
+
+        // Read across the meta region, the padding and the first blob.
+        let mut buf = vec![0u8; 4096];
+        layout.read_at(&mut buf, 4096, read_blob).unwrap();
+        assert_eq!(buf[..904], layout.meta[4096..5000]);
+        assert!(buf[904..].iter().all(|v| *v == 0));
+        let mut buf = vec![0u8; 8192];
+        layout.read_at(&mut buf, 6144, read_blob).unwrap();
+        assert!(buf[..2048].iter().all(|v| *v == 0));
+        assert!(buf[2048..6144].iter().all(|v| *v == 1));
+        assert!(buf[6144..].iter().all(|v| *v == 2));
+
+        // Read the tail of the last blob, with zero padding.
+        let mut buf = vec![0xffu8; 8192];
+        layout.read_at(&mut buf, 12288, read_blob).unwrap();
+        assert!(buf[..5000].iter().all(|v| *v == 2));
+        assert!(buf[5000..].iter().all(|v| *v == 0));
+
+        let mut buf = vec![0u8; 4096];
+        let offset = layout.size - 2048;
+        assert!(layout.read_at(&mut buf, offset, read_blob).is_err());
+        assert!(layout.read_at(&mut buf, u64::MAX, read_blob).is_err());
+    }
+
+    #[test]
+    fn test_nbd_request() {
+        let mut buf = [0u8; NBD_REQUEST_SIZE];
+        buf[0..4].copy_from_slice(&NBD_REQUEST_MAGIC.to_be_bytes());
+        buf[4..8].copy_from_slice(&(0x1_0000u32 | NBD_CMD_READ as u32).to_be_bytes());
+        buf[8..16].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        buf[16..24].copy_from_slice(&0x1000u64.to_be_bytes());
+        buf[24..28].copy_from_slice(&0x2000u32.to_be_bytes());
+
+        let req = NbdRequest::parse(&buf).unwrap();
+        assert_eq!(
+            req,
+            NbdRequest {
+                cmd: NBD_CMD_READ,
+                handle: [1, 2, 3, 4, 5, 6, 7, 8],
+                offset: 0x1000,
+                len: 0x2000,
+            }
+        );
+
+        let reply = req.reply(libc::EIO);
+        assert_eq!(reply[0..4], NBD_REPLY_MAGIC.to_be_bytes());
+        assert_eq!(reply[4..8], (libc::EIO as u32).to_be_bytes());
+        assert_eq!(reply[8..16], [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        buf[0] = 0;
+        assert!(NbdRequest::parse(&buf).is_err());
+    }
+}
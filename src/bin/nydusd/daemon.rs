@@ -6,7 +6,7 @@
 
 use std::any::Any;
 use std::cmp::PartialEq;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::From;
 use std::fmt::{Display, Formatter};
 use std::io::Result;
@@ -20,10 +20,12 @@ use std::sync::{
     Arc, MutexGuard,
 };
 use std::thread;
+use std::time::Duration;
 use std::{error, fmt, io};
 
 use event_manager::{EventOps, EventSubscriber, Events};
-use fuse_backend_rs::api::{vfs::VfsError, BackendFileSystem, Vfs};
+use fuse_backend_rs::abi::linux_abi::FsOptions;
+use fuse_backend_rs::api::{vfs::VfsError, BackendFileSystem, Vfs, VfsOptions};
 use fuse_backend_rs::passthrough::{Config, PassthroughFs};
 use fuse_backend_rs::transport::Error as FuseTransportError;
 use fuse_backend_rs::Error as FuseError;
@@ -35,15 +37,19 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 use nydus::{FsBackendDesc, FsBackendType};
 use nydus_app::BuildTimeInfo;
 use rafs::{
-    fs::{Rafs, RafsConfig},
+    fs::{Rafs, RafsConfig, RafsInfo, RafsPrefetchStatus},
+    overlay::RafsOverlay,
     trim_backend_config, RafsError, RafsIoRead,
 };
+use storage::factory::{BackendConfig, BLOB_FACTORY};
 
+use crate::profile;
 use crate::upgrade::{self, UpgradeManager, UpgradeMgrError};
 use crate::EVENT_MANAGER_RUN;
 
 //TODO: Try to public below type from fuse-rs thus no need to redefine it here.
-type BackFileSystem = Box<dyn BackendFileSystem<Inode = u64, Handle = u64> + Send + Sync>;
+pub(crate) type BackFileSystem =
+    Box<dyn BackendFileSystem<Inode = u64, Handle = u64> + Send + Sync>;
 
 #[allow(dead_code)]
 #[allow(clippy::upper_case_acronyms)]
@@ -55,6 +61,8 @@ pub enum DaemonState {
     INTERRUPTED = 4,
     STOPPED = 5,
     UNKNOWN = 6,
+    DRAINING = 7,
+    FAILED = 8,
 }
 
 impl Display for DaemonState {
@@ -71,6 +79,8 @@ impl From<i32> for DaemonState {
             3 => DaemonState::UPGRADING,
             4 => DaemonState::INTERRUPTED,
             5 => DaemonState::STOPPED,
+            7 => DaemonState::DRAINING,
+            8 => DaemonState::FAILED,
             _ => DaemonState::UNKNOWN,
         }
     }
@@ -172,9 +182,23 @@ pub struct DaemonInfo {
     pub supervisor: Option<String>,
     pub state: DaemonState,
     pub backend_collection: FsBackendCollection,
+    /// The image mounted by the in-kernel EROFS filesystem instead of fuse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erofs_mount: Option<ErofsMountInfo>,
 }
 
-#[derive(Clone)]
+/// Information of an image mounted by the in-kernel EROFS filesystem.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErofsMountInfo {
+    pub mountpoint: String,
+    pub bootstrap: String,
+    /// Loop device attached to the bootstrap.
+    pub device: String,
+    /// Loop devices attached to data blobs, in the order of the device table.
+    pub blob_devices: Vec<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct FsBackendMountCmd {
     pub fs_type: FsBackendType,
     pub source: String,
@@ -183,11 +207,120 @@ pub struct FsBackendMountCmd {
     pub prefetch_files: Option<Vec<String>>,
 }
 
+/// FUSE features negotiated with the kernel when initializing a fuse session, configured by the
+/// `fuse` section of the daemon or mount configuration. Absent options keep the defaults.
+#[derive(Clone, Default, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FuseInitConfig {
+    /// Cache writes in the kernel, which only takes effect for passthroughfs in hybrid mode.
+    #[serde(default)]
+    pub writeback_cache: Option<bool>,
+    /// Invalidate cached file data when the file size or mtime is found changed.
+    #[serde(default)]
+    pub auto_inval_data: Option<bool>,
+    /// Keep cached file data until invalidated by the filesystem explicitly.
+    #[serde(default)]
+    pub explicit_inval_data: Option<bool>,
+}
+
+impl FuseInitConfig {
+    /// Parse the `fuse` section from a JSON configuration, which may be empty.
+    pub fn from_config(config: &str) -> DaemonResult<Self> {
+        if config.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let mut value: serde_json::Value =
+            serde_json::from_str(config).map_err(DaemonError::Serde)?;
+        let fuse_config: Self = match value.get_mut("fuse") {
+            Some(v) => serde_json::from_value(v.take())
+                .map_err(|e| DaemonError::InvalidConfig(format!("invalid `fuse`, {}", e)))?,
+            None => Self::default(),
+        };
+        if fuse_config.auto_inval_data == Some(true)
+            && fuse_config.explicit_inval_data == Some(true)
+        {
+            return Err(DaemonError::InvalidConfig(
+                "auto_inval_data and explicit_inval_data are mutually exclusive".to_string(),
+            ));
+        }
+
+        Ok(fuse_config)
+    }
+
+    /// Apply the configured features to options of the fuse session.
+    pub fn apply(&self, opts: &mut VfsOptions) {
+        if let Some(enable) = self.writeback_cache {
+            opts.no_writeback = !enable;
+        }
+        if let Some(enable) = self.auto_inval_data {
+            opts.out_opts.set(FsOptions::AUTO_INVAL_DATA, enable);
+        }
+        if let Some(enable) = self.explicit_inval_data {
+            opts.out_opts.set(FsOptions::EXPLICIT_INVAL_DATA, enable);
+            if enable {
+                opts.out_opts.remove(FsOptions::AUTO_INVAL_DATA);
+            }
+        }
+    }
+}
+
+/// Validate the configuration to mount RAFS filesystems, without initializing storage backends.
+pub fn validate_config(config: &str) -> DaemonResult<()> {
+    RafsConfig::from_str(config)
+        .and_then(|c| c.validate())
+        .map_err(|e| DaemonError::InvalidConfig(e.to_string()))?;
+    FuseInitConfig::from_config(config)?;
+
+    Ok(())
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct FsBackendUmountCmd {
     pub mountpoint: String,
 }
 
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct FsBackendBlobCmd {
+    pub blob_id: String,
+    pub object_id: Option<String>,
+    pub backend: Option<serde_json::Value>,
+    pub flush_cache: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct FsPrefetchCmd {
+    pub action: String,
+    pub files: Option<Vec<String>>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct FsPrefetchJobCmd {
+    pub mountpoint: String,
+    pub files: Option<Vec<String>>,
+    pub all: bool,
+}
+
+/// A job to warm up the cache of a filesystem instance.
+#[derive(Serialize)]
+struct FsPrefetchJob {
+    job_id: u64,
+    mountpoint: String,
+    /// Progress of the job, absent when the job is just started.
+    #[serde(flatten)]
+    status: Option<RafsPrefetchStatus>,
+}
+
+/// Information about a mounted filesystem instance, for observability.
+#[derive(Serialize)]
+struct FsBackendInfo {
+    backend_type: FsBackendType,
+    mountpoint: String,
+    mounted_time: String,
+    /// Versions, blobs and prefetch policy of RAFS instances.
+    #[serde(flatten)]
+    rafs: Option<RafsInfo>,
+}
+
 #[derive(Default, Serialize, Clone)]
 pub struct FsBackendCollection(HashMap<String, FsBackendDesc>);
 
@@ -195,7 +328,7 @@ impl FsBackendCollection {
     fn add(&mut self, id: &str, cmd: &FsBackendMountCmd) -> DaemonResult<()> {
         // We only wash Rafs backend now.
         let fs_config = match cmd.fs_type {
-            FsBackendType::Rafs => {
+            FsBackendType::Rafs | FsBackendType::RafsOverlay => {
                 let mut config: serde_json::Value =
                     serde_json::from_str(&cmd.config).map_err(DaemonError::Serde)?;
                 trim_backend_config!(
@@ -216,6 +349,7 @@ impl FsBackendCollection {
         let desc = FsBackendDesc {
             backend_type: cmd.fs_type.clone(),
             mountpoint: cmd.mountpoint.clone(),
+            source: cmd.source.clone(),
             mounted_time: chrono::Local::now(),
             config: fs_config,
         };
@@ -248,6 +382,14 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
         self.wait().map_err(|_| DaemonError::ServiceStop)?;
         Ok(())
     }
+    /// Stop fetching new fuse requests, wait for inflight requests up to `timeout` and then
+    /// umount the fuse session.
+    fn trigger_drain(&self, _timeout: Duration) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
+    fn drain(&self) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
     fn trigger_takeover(&self) -> DaemonResult<()> {
         self.on_event(DaemonStateMachineInput::Takeover)?;
         self.on_event(DaemonStateMachineInput::Successful)?;
@@ -260,7 +402,15 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
     fn get_vfs(&self) -> &Vfs;
     fn upgrade_mgr(&self) -> Option<MutexGuard<UpgradeManager>>;
     fn backend_collection(&self) -> MutexGuard<FsBackendCollection>;
+    // Called after a filesystem is mounted at `mountpoint` with vfs index `index`.
+    fn on_mounted(&self, _index: u8, _mountpoint: &str) {}
+    // Called before the filesystem mounted at `mountpoint` is umounted.
+    fn on_umounting(&self, _mountpoint: &str) {}
     fn version(&self) -> BuildTimeInfo;
+    /// Get information of the image mounted by EROFS instead of fuse, if any.
+    fn erofs_mount(&self) -> Option<ErofsMountInfo> {
+        None
+    }
     fn export_info(&self) -> DaemonResult<String> {
         let response = DaemonInfo {
             version: self.version(),
@@ -268,11 +418,182 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
             supervisor: self.supervisor(),
             state: self.get_state(),
             backend_collection: self.backend_collection().deref().clone(),
+            erofs_mount: self.erofs_mount(),
         };
 
         serde_json::to_string(&response).map_err(DaemonError::Serde)
     }
-    fn export_backend_info(&self, mountpoint: &str) -> DaemonResult<String> {
+    /// Export CPU time of threads and a breakdown of resident memory of the daemon.
+    fn export_profile(&self) -> DaemonResult<String> {
+        let mut metadata_files = Vec::new();
+        let mut cache_dirs = Vec::new();
+        for desc in self.backend_collection().0.values() {
+            if desc.backend_type == FsBackendType::PassthroughFs {
+                continue;
+            }
+            metadata_files.push(PathBuf::from(&desc.source));
+            let cache = &desc.config["device"]["cache"];
+            if cache["type"] == "blobcache" {
+                if let Some(dir) = cache["config"]["work_dir"].as_str() {
+                    // Cache directory may be templated by filesystem id, e.g. `/cache/{id}`.
+                    let dir = dir.split('{').next().unwrap_or_default();
+                    if !dir.is_empty() {
+                        cache_dirs.push(PathBuf::from(dir));
+                    }
+                }
+            }
+        }
+
+        let report = profile::collect_report(&metadata_files, &cache_dirs)
+            .map_err(|e| DaemonError::Common(format!("failed to collect profile, {}", e)))?;
+        serde_json::to_string(&report).map_err(DaemonError::Serde)
+    }
+    /// Export information about the filesystem mounted at `mountpoint`, or all mounted
+    /// filesystems keyed by mountpoint if it's None.
+    fn export_backend_info(&self, mountpoint: Option<&str>) -> DaemonResult<String> {
+        let descs: Vec<FsBackendDesc> = {
+            let collection = self.backend_collection();
+            match mountpoint {
+                Some(mp) => vec![collection.0.get(mp).cloned().ok_or(DaemonError::NotFound)?],
+                None => collection.0.values().cloned().collect(),
+            }
+        };
+
+        let mut infos = BTreeMap::new();
+        for desc in descs {
+            let fs = self
+                .backend_from_mountpoint(&desc.mountpoint)?
+                .ok_or(DaemonError::NotFound)?;
+            let rafs = fs.deref().as_any().downcast_ref::<Rafs>().map(|r| r.info());
+            let info = FsBackendInfo {
+                backend_type: desc.backend_type,
+                mountpoint: desc.mountpoint.clone(),
+                mounted_time: desc.mounted_time.to_string(),
+                rafs,
+            };
+            if mountpoint.is_some() {
+                return serde_json::to_string(&info).map_err(DaemonError::Serde);
+            }
+            infos.insert(desc.mountpoint, info);
+        }
+
+        serde_json::to_string(&infos).map_err(DaemonError::Serde)
+    }
+    /// Get ids of mounted images, which are image digests recorded in bootstraps, or paths of
+    /// bootstraps built without image digests.
+    fn image_ids(&self) -> Vec<String> {
+        let descs: Vec<FsBackendDesc> = self.backend_collection().0.values().cloned().collect();
+        let mut ids = Vec::new();
+        for desc in descs {
+            if desc.backend_type == FsBackendType::PassthroughFs {
+                continue;
+            }
+            let digest = self
+                .backend_from_mountpoint(&desc.mountpoint)
+                .ok()
+                .flatten()
+                .and_then(|fs| {
+                    let rafs = fs.deref().as_any().downcast_ref::<Rafs>();
+                    rafs.and_then(|r| r.metadata().image_digest)
+                });
+            ids.push(match digest {
+                Some(d) => format!("sha256:{}", d),
+                None => desc.source,
+            });
+        }
+        ids.sort();
+
+        ids
+    }
+    fn scrub_backend(&self, mountpoint: &str, chunks: Option<u32>) -> DaemonResult<String> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let any_fs = fs.deref().as_any();
+        let rafs = any_fs
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        let stat = rafs
+            .scrub(chunks)
+            .map_err(|e| DaemonError::Common(e.to_string()))?;
+        serde_json::to_string(&stat).map_err(DaemonError::Serde)
+    }
+    fn switch_blob_backend(&self, mountpoint: &str, cmd: FsBackendBlobCmd) -> DaemonResult<()> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let any_fs = fs.deref().as_any();
+        let rafs = any_fs
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        let backend_config = match cmd.backend {
+            Some(v) => {
+                Some(serde_json::from_value::<BackendConfig>(v).map_err(DaemonError::Serde)?)
+            }
+            None => None,
+        };
+        rafs.switch_blob_backend(
+            &cmd.blob_id,
+            backend_config,
+            cmd.object_id.as_deref(),
+            cmd.flush_cache,
+        )
+        .map_err(DaemonError::Rafs)
+    }
+    fn switch_cache_type(&self, mountpoint: &str, cache_type: &str) -> DaemonResult<()> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let any_fs = fs.deref().as_any();
+        let rafs = any_fs
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        rafs.switch_cache_type(cache_type)
+            .map_err(DaemonError::Rafs)
+    }
+    /// Invalidate cached data of blob `blob_id` and/or files at or under image path `path`, of
+    /// the filesystem at `mountpoint` or of all RAFS filesystems if it's None.
+    fn invalidate_cache(
+        &self,
+        mountpoint: Option<&str>,
+        blob_id: Option<&str>,
+        path: Option<&str>,
+    ) -> DaemonResult<String> {
+        let mountpoints: Vec<String> = match mountpoint {
+            Some(mp) => vec![mp.to_string()],
+            None => self.backend_collection().0.keys().cloned().collect(),
+        };
+
+        let mut found = false;
+        let mut invalidated = 0;
+        for mp in mountpoints {
+            let fs = self
+                .backend_from_mountpoint(&mp)?
+                .ok_or(DaemonError::NotFound)?;
+            let rafs = match fs.deref().as_any().downcast_ref::<Rafs>() {
+                Some(rafs) => rafs,
+                None if mountpoint.is_none() => continue,
+                None => return Err(DaemonError::FsTypeMismatch("to rafs".to_string())),
+            };
+            match rafs.invalidate_cache(blob_id, path.map(Path::new)) {
+                Ok(n) => {
+                    found = true;
+                    invalidated += n;
+                }
+                // The blob or path may only exist in some of the filesystems.
+                Err(e) if e.kind() == io::ErrorKind::NotFound && mountpoint.is_none() => {}
+                Err(e) => return Err(DaemonError::Common(e.to_string())),
+            }
+        }
+        if !found {
+            return Err(DaemonError::NotFound);
+        }
+
+        let result = serde_json::json!({ "invalidated": invalidated });
+        Ok(result.to_string())
+    }
+
+    fn prefetch_status(&self, mountpoint: &str) -> DaemonResult<String> {
         let fs = self
             .backend_from_mountpoint(mountpoint)?
             .ok_or(DaemonError::NotFound)?;
@@ -280,8 +601,89 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
         let rafs = any_fs
             .downcast_ref::<Rafs>()
             .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
-        let resp = serde_json::to_string(rafs.metadata()).map_err(DaemonError::Serde)?;
-        Ok(resp)
+        serde_json::to_string(&rafs.prefetch_status()).map_err(DaemonError::Serde)
+    }
+    fn control_prefetch(&self, mountpoint: &str, cmd: FsPrefetchCmd) -> DaemonResult<()> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let any_fs = fs.deref().as_any();
+        let rafs = any_fs
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        match cmd.action.as_str() {
+            "pause" => rafs.pause_prefetch(),
+            "resume" => rafs.resume_prefetch(),
+            "stop" => rafs.stop_prefetch(),
+            "restart" => rafs.restart_prefetch(
+                cmd.files
+                    .map(|files| files.iter().map(PathBuf::from).collect()),
+            ),
+            action => {
+                return Err(DaemonError::InvalidArguments(format!(
+                    "unknown prefetch action {}",
+                    action
+                )))
+            }
+        }
+        .map_err(DaemonError::Rafs)
+    }
+
+    /// Start a job to warm up the cache of a filesystem instance by prefetching files, or the
+    /// whole image, and return the job id.
+    fn start_prefetch_job(&self, cmd: FsPrefetchJobCmd) -> DaemonResult<String> {
+        let files = match (cmd.files, cmd.all) {
+            (Some(files), false) if !files.is_empty() => {
+                Some(files.iter().map(PathBuf::from).collect())
+            }
+            (None, true) => None,
+            _ => {
+                return Err(DaemonError::InvalidArguments(
+                    "either files or all should be specified to prefetch".to_string(),
+                ))
+            }
+        };
+        let fs = self
+            .backend_from_mountpoint(&cmd.mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let rafs = fs
+            .deref()
+            .as_any()
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+        let job_id = rafs.warm_up(files).map_err(DaemonError::Rafs)?;
+        info!("prefetch job {} started for {}", job_id, cmd.mountpoint);
+
+        let job = FsPrefetchJob {
+            job_id,
+            mountpoint: cmd.mountpoint,
+            status: None,
+        };
+        serde_json::to_string(&job).map_err(DaemonError::Serde)
+    }
+    /// Get progress of a cache warm-up job.
+    fn prefetch_job_status(&self, job_id: u64) -> DaemonResult<String> {
+        let mountpoints: Vec<String> = self.backend_collection().0.keys().cloned().collect();
+        for mountpoint in mountpoints {
+            let fs = match self.backend_from_mountpoint(&mountpoint)? {
+                Some(fs) => fs,
+                None => continue,
+            };
+            let status = match fs.deref().as_any().downcast_ref::<Rafs>() {
+                Some(rafs) => rafs.prefetch_job_status(job_id),
+                None => continue,
+            };
+            if status.is_some() {
+                let job = FsPrefetchJob {
+                    job_id,
+                    mountpoint,
+                    status,
+                };
+                return serde_json::to_string(&job).map_err(DaemonError::Serde);
+            }
+        }
+
+        Err(DaemonError::NotFound)
     }
 
     fn backend_from_mountpoint(&self, mp: &str) -> DaemonResult<Option<Arc<BackFileSystem>>> {
@@ -290,6 +692,20 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
     }
     fn export_inflight_ops(&self) -> DaemonResult<Option<String>>;
 
+    /// Create a fuse session at `cmd.mountpoint` serving a new filesystem instance, only
+    /// supported in singleton mode.
+    fn create_fuse_session(&self, _cmd: FsBackendMountCmd) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
+    /// Umount the fuse session created at `mountpoint` and release its filesystem instance.
+    fn destroy_fuse_session(&self, _mountpoint: &str) -> DaemonResult<()> {
+        Err(DaemonError::Unsupported)
+    }
+    /// Export information about fuse sessions created in singleton mode.
+    fn export_fuse_sessions(&self) -> DaemonResult<String> {
+        Err(DaemonError::Unsupported)
+    }
+
     // NOTE: This method is not thread-safe, however, it is acceptable as
     // mount/umount/remount/restore_mount is invoked from single thread in FSM
     fn mount(&self, cmd: FsBackendMountCmd) -> DaemonResult<()> {
@@ -299,6 +715,7 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
         let backend = fs_backend_factory(&cmd)?;
         let index = self.get_vfs().mount(backend, &cmd.mountpoint)?;
         info!("{} mounted at {}", &cmd.fs_type, &cmd.mountpoint);
+        self.on_mounted(index, &cmd.mountpoint);
         self.backend_collection().add(&cmd.mountpoint, &cmd)?;
 
         // Add mounts opaque to UpgradeManager
@@ -309,11 +726,39 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
         Ok(())
     }
 
+    // Mount a filesystem mounted by the previous nydusd process. Vfs assigns indexes in mount
+    // order and can't mount a filesystem at a given index, so inode numbers presented to fuse are
+    // only kept unchanged if the filesystem gets the same index as `vfs_index`.
+    fn restore_mount(&self, cmd: &FsBackendMountCmd, vfs_index: u8) -> DaemonResult<()> {
+        let backend = fs_backend_factory(cmd)?;
+        let index = self.get_vfs().mount(backend, &cmd.mountpoint)?;
+        if index == vfs_index {
+            info!(
+                "{} restored at {} with vfs index {}",
+                &cmd.fs_type, &cmd.mountpoint, index
+            );
+        } else {
+            warn!(
+                "{} restored at {} with vfs index {} instead of {}, inode numbers are changed",
+                &cmd.fs_type, &cmd.mountpoint, index, vfs_index
+            );
+        }
+        self.on_mounted(index, &cmd.mountpoint);
+        self.backend_collection().add(&cmd.mountpoint, cmd)?;
+
+        if let Some(mut mgr_guard) = self.upgrade_mgr() {
+            upgrade::add_mounts_state(&mut mgr_guard, cmd.clone(), index)?;
+        }
+
+        Ok(())
+    }
+
     fn remount(&self, cmd: FsBackendMountCmd) -> DaemonResult<()> {
         let rootfs = self
             .backend_from_mountpoint(&cmd.mountpoint)?
             .ok_or(DaemonError::NotFound)?;
         let rafs_config = RafsConfig::from_str(&&cmd.config)?;
+        Rafs::fetch_bootstrap(&rafs_config, Path::new(&cmd.source))?;
         let mut bootstrap = <dyn RafsIoRead>::from_file(&&cmd.source)?;
         let any_fs = rootfs.deref().as_any();
         let rafs = any_fs
@@ -338,9 +783,12 @@ pub trait NydusDaemon: DaemonStateMachineSubscriber {
         let _ = self
             .backend_from_mountpoint(&cmd.mountpoint)?
             .ok_or(DaemonError::NotFound)?;
+        self.on_umounting(&cmd.mountpoint);
         self.get_vfs().umount(&cmd.mountpoint)?;
 
         self.backend_collection().del(&cmd.mountpoint);
+        // Release blob caches not used by any filesystem instance anymore.
+        BLOB_FACTORY.gc();
 
         // Remove mount opaque from UpgradeManager
         if let Some(mut mgr_guard) = self.upgrade_mgr() {
@@ -374,18 +822,38 @@ fn input_prefetch_files_verify(input: &Option<Vec<String>>) -> DaemonResult<Opti
     Ok(prefetch_files)
 }
 
-fn fs_backend_factory(cmd: &FsBackendMountCmd) -> DaemonResult<BackFileSystem> {
+pub(crate) fn fs_backend_factory(cmd: &FsBackendMountCmd) -> DaemonResult<BackFileSystem> {
     let prefetch_files = input_prefetch_files_verify(&cmd.prefetch_files)?;
 
     match cmd.fs_type {
         FsBackendType::Rafs => {
             let rafs_config = RafsConfig::from_str(cmd.config.as_str())?;
+            Rafs::fetch_bootstrap(&rafs_config, Path::new(&cmd.source))?;
             let mut bootstrap = <dyn RafsIoRead>::from_file(&cmd.source)?;
             let mut rafs = Rafs::new(rafs_config, &cmd.mountpoint, &mut bootstrap)?;
             rafs.import(bootstrap, prefetch_files)?;
             info!("Rafs imported");
             Ok(Box::new(rafs))
         }
+        FsBackendType::RafsOverlay => {
+            let rafs_config = RafsConfig::from_str(cmd.config.as_str())?;
+            // Bootstrap paths may contain any character, so they are passed as a JSON array.
+            let sources: Vec<String> =
+                serde_json::from_str(&cmd.source).map_err(DaemonError::Serde)?;
+            let mut layers = Vec::with_capacity(sources.len());
+            for (idx, source) in sources.iter().enumerate() {
+                // Each layer needs a distinct id for metrics.
+                let id = format!("{}#{}", cmd.mountpoint, idx);
+                let mut bootstrap = <dyn RafsIoRead>::from_file(source)?;
+                let mut rafs = Rafs::new(rafs_config.clone(), &id, &mut bootstrap)?;
+                rafs.import(bootstrap, prefetch_files.clone())?;
+                layers.push(rafs);
+            }
+            let overlay =
+                RafsOverlay::new(layers).map_err(|e| DaemonError::Common(e.to_string()))?;
+            info!("Rafs overlay of {} layers imported", sources.len());
+            Ok(Box::new(overlay))
+        }
         FsBackendType::PassthroughFs => {
             // Vfs by default enables no_open and writeback, passthroughfs
             // needs to specify them explicitly.
@@ -475,6 +943,10 @@ impl EventSubscriber for NydusDaemonSubscriber {
 // - `Interrupt` state means nydusd has shutdown fuse server, which means no more message will
 //    be read from kernel and handled and no pending and in-flight fuse message exists. But the
 //    nydusd daemon should be alive and wait for coming events.
+// - `Draining` state means nydusd has stopped fetching new fuse messages from kernel, waited
+//    for in-flight fuse messages to complete and umounted the fuse session.
+// - `Failed` state means fuse service threads kept dying and couldn't be recovered by the
+//    watchdog, so nydusd has stopped serving fuse requests and is going to quit.
 // - `Die` state means the whole nydusd process is going to die.
 state_machine! {
     derive(Debug, Clone)
@@ -490,10 +962,15 @@ state_machine! {
     Running => {
         Exit => Interrupted [TerminateFuseService],
         Stop => Die[Umount],
+        Drain => Draining [DrainService],
+        Fail => Failed [FailService],
     },
     Upgrading(Successful) => Running [StartService],
     // Quit from daemon but not disconnect from fuse front-end.
     Interrupted(Stop) => Die,
+    // Fuse session has been umounted when draining.
+    Draining(Stop) => Die,
+    Failed(Stop) => Die[Umount],
 }
 
 pub struct DaemonStateMachineContext {
@@ -575,6 +1052,12 @@ impl DaemonStateMachineContext {
                             d.set_state(DaemonState::UPGRADING);
                             d.restore()
                         }
+                        DrainService => d.drain(),
+                        FailService => {
+                            d.interrupt();
+                            d.set_state(DaemonState::FAILED);
+                            Ok(())
+                        }
                     },
                     _ => Ok(()), // With no output action involved, caller should also have reply back
                 }
@@ -617,6 +1100,12 @@ mod tests {
         assert_eq!(stat, DaemonState::UNKNOWN);
 
         let stat = DaemonState::from(7);
+        assert_eq!(stat, DaemonState::DRAINING);
+
+        let stat = DaemonState::from(8);
+        assert_eq!(stat, DaemonState::FAILED);
+
+        let stat = DaemonState::from(9);
         assert_eq!(stat, DaemonState::UNKNOWN);
     }
 
@@ -625,6 +1114,9 @@ mod tests {
         let backend_type: FsBackendType = "rafs".parse().unwrap();
         assert!(backend_type == FsBackendType::Rafs);
 
+        let backend_type: FsBackendType = "rafs_overlay".parse().unwrap();
+        assert!(backend_type == FsBackendType::RafsOverlay);
+
         let backend_type: FsBackendType = "passthrough_fs".parse().unwrap();
         assert!(backend_type == FsBackendType::PassthroughFs);
 
@@ -655,6 +1147,63 @@ mod tests {
         assert_eq!(col.0.len(), 0);
     }
 
+    #[test]
+    fn it_should_parse_fuse_init_config() {
+        assert_eq!(
+            FuseInitConfig::from_config("").unwrap(),
+            FuseInitConfig::default()
+        );
+        assert_eq!(
+            FuseInitConfig::from_config(r#"{"mode": "direct"}"#).unwrap(),
+            FuseInitConfig::default()
+        );
+        let config = FuseInitConfig::from_config(
+            r#"{"fuse": {"writeback_cache": false, "explicit_inval_data": true}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.writeback_cache, Some(false));
+        assert_eq!(config.explicit_inval_data, Some(true));
+        assert!(FuseInitConfig::from_config(
+            r#"{"fuse": {"auto_inval_data": true, "explicit_inval_data": true}}"#
+        )
+        .is_err());
+        assert!(FuseInitConfig::from_config(r#"{"fuse": {"writeback": true}}"#).is_err());
+
+        let mut opts = VfsOptions::default();
+        opts.out_opts.insert(FsOptions::AUTO_INVAL_DATA);
+        config.apply(&mut opts);
+        assert!(opts.no_writeback);
+        assert!(opts.out_opts.contains(FsOptions::EXPLICIT_INVAL_DATA));
+        assert!(!opts.out_opts.contains(FsOptions::AUTO_INVAL_DATA));
+    }
+
+    #[test]
+    fn it_should_validate_config() {
+        validate_config(
+            r#"{
+              "device": {"backend": {"type": "localfs", "config": {"dir": "/tmp"}}},
+              "mode": "direct",
+              "fuse": {"explicit_inval_data": true}
+            }"#,
+        )
+        .unwrap();
+
+        let err = validate_config(
+            r#"{"device": {"backend": {"type": "localfs", "config": {}}}, "mode": "direct", "iostat_files": true}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown field `iostat_files`"));
+        let err = validate_config(
+            r#"{"device": {"backend": {"type": "localfs", "config": {}}}, "mode": "cache"}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("`mode`"));
+        assert!(validate_config(
+            r#"{"device": {"backend": {"type": "localfs", "config": {}}}, "mode": "direct", "fuse": {"writeback": true}}"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn it_should_verify_prefetch_files() {
         match input_prefetch_files_verify(&Some(vec!["/etc/passwd".to_string()])) {
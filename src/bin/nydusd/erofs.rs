@@ -0,0 +1,264 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mount RAFS v6 images by the in-kernel EROFS filesystem instead of fuse.
+//!
+//! RAFS v6 images are EROFS compatible. If the kernel supports EROFS and all data blobs of an
+//! image are present in the directory of the localfs backend in uncompressed layout, i.e. chunks
+//! are stored at their uncompressed offsets, the bootstrap and blob files are attached to
+//! read-only loop devices, and the bootstrap device is mounted by EROFS with blob devices passed
+//! by `device=` mount options in the order of the device table. Reads are served by the kernel
+//! directly without any fuse session then.
+//!
+//! Loop devices are attached with the autoclear flag, so they're released by the kernel once
+//! the filesystem is umounted, or once closed if mounting fails.
+
+use std::ffi::CString;
+use std::fs::{metadata, File, OpenOptions};
+use std::io::Result;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rafs::fs::RafsConfig;
+use rafs::metadata::{RafsMode, RafsSuper};
+use storage::compress;
+
+use crate::daemon::ErofsMountInfo;
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+// Ioctl commands of the loop driver, defined in `include/uapi/linux/loop.h`.
+const LOOP_SET_FD: u64 = 0x4c00;
+const LOOP_CLR_FD: u64 = 0x4c01;
+const LOOP_SET_STATUS64: u64 = 0x4c04;
+const LOOP_CTL_GET_FREE: u64 = 0x4c82;
+
+const LO_FLAGS_READ_ONLY: u32 = 1;
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_NAME_SIZE: usize = 64;
+// Free loop devices may be taken by others before being attached.
+const LOOP_ATTACH_RETRIES: u32 = 8;
+
+/// `struct loop_info64` of the loop driver.
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; LO_NAME_SIZE],
+    lo_crypt_name: [u8; LO_NAME_SIZE],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+fn ioctl(file: &File, cmd: u64, arg: u64) -> Result<i32> {
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), cmd as _, arg as libc::c_ulong) };
+    if ret < 0 {
+        return Err(last_error!(format!("loop ioctl 0x{:x} failed", cmd)));
+    }
+
+    Ok(ret)
+}
+
+// Check whether `fs_type` is listed in content of `/proc/filesystems`.
+fn has_filesystem(filesystems: &str, fs_type: &str) -> bool {
+    filesystems
+        .lines()
+        .any(|l| l.split_whitespace().last() == Some(fs_type))
+}
+
+/// Check whether the kernel supports the EROFS filesystem.
+pub fn is_erofs_supported() -> bool {
+    std::fs::read_to_string("/proc/filesystems")
+        .map(|s| has_filesystem(&s, "erofs"))
+        .unwrap_or(false)
+}
+
+/// A loop device attached to a file in read-only mode.
+struct LoopDevice {
+    path: String,
+    // Keep the loop device open until mounted, so it's not released by autoclear.
+    _file: File,
+}
+
+impl LoopDevice {
+    fn attach(backing: &Path) -> Result<Self> {
+        let backing_file = File::open(backing)?;
+        let control = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(LOOP_CONTROL)?;
+
+        for _ in 0..LOOP_ATTACH_RETRIES {
+            let num = ioctl(&control, LOOP_CTL_GET_FREE, 0)?;
+            let path = format!("/dev/loop{}", num);
+            let file = OpenOptions::new().read(true).open(&path)?;
+            match ioctl(&file, LOOP_SET_FD, backing_file.as_raw_fd() as u64) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => continue,
+                Err(e) => return Err(e),
+            }
+
+            let mut info: LoopInfo64 = unsafe { mem::zeroed() };
+            info.lo_flags = LO_FLAGS_READ_ONLY | LO_FLAGS_AUTOCLEAR;
+            let name = backing.as_os_str().as_bytes();
+            let len = std::cmp::min(name.len(), LO_NAME_SIZE - 1);
+            info.lo_file_name[..len].copy_from_slice(&name[..len]);
+            if let Err(e) = ioctl(&file, LOOP_SET_STATUS64, &info as *const _ as u64) {
+                let _ = ioctl(&file, LOOP_CLR_FD, 0);
+                return Err(e);
+            }
+            debug!("{:?} is attached to {}", backing, path);
+
+            return Ok(LoopDevice { path, _file: file });
+        }
+
+        Err(eother!(format!("no free loop device for {:?}", backing)))
+    }
+}
+
+/// An image mounted by the in-kernel EROFS filesystem.
+pub struct ErofsMount {
+    info: ErofsMountInfo,
+}
+
+impl ErofsMount {
+    /// Check whether the image may be mounted by EROFS directly, and get paths of its blob files
+    /// in the order of the device table.
+    pub fn check(bootstrap: &str, config: &str) -> Result<Vec<PathBuf>> {
+        if !is_erofs_supported() {
+            return Err(enosys!("kernel doesn't support erofs"));
+        }
+        let rafs_config = RafsConfig::from_str(config)
+            .map_err(|e| einval!(format!("failed to parse configuration, {:?}", e)))?;
+        let backend = &rafs_config.device.backend;
+        if backend.backend_type != "localfs" {
+            return Err(einval!(format!(
+                "blobs in {} backend are not local",
+                backend.backend_type
+            )));
+        }
+        let sb = RafsSuper::load_from_metadata(bootstrap, RafsMode::Direct, false)?;
+        if !sb.meta.is_v6() {
+            return Err(einval!("only RAFS v6 images can be mounted by erofs"));
+        }
+
+        let blob_file = backend.backend_config["blob_file"]
+            .as_str()
+            .unwrap_or_default();
+        let dir = backend.backend_config["dir"].as_str().unwrap_or_default();
+        let mut files = Vec::new();
+        for blob in sb.superblock.get_blob_infos().iter() {
+            // EROFS addresses chunks by uncompressed offset, which equals to the offset in blob
+            // file only if no chunk is compressed or padded.
+            if blob.compressor() != compress::Algorithm::None
+                || blob.compressed_size() != blob.uncompressed_size()
+            {
+                return Err(einval!(format!(
+                    "blob {} isn't in uncompressed layout",
+                    blob.blob_id()
+                )));
+            }
+            let path = if !blob_file.is_empty() {
+                PathBuf::from(blob_file)
+            } else {
+                Path::new(dir).join(blob.blob_id())
+            };
+            let size = metadata(&path)
+                .map_err(|e| enoent!(format!("blob file {:?} is not found, {}", path, e)))?
+                .len();
+            if size < blob.uncompressed_size() {
+                return Err(einval!(format!("blob file {:?} is incomplete", path)));
+            }
+            files.push(path);
+        }
+
+        Ok(files)
+    }
+
+    /// Mount the image with `bootstrap` and storage configured by `config` at `mountpoint`.
+    pub fn mount(mountpoint: &str, bootstrap: &str, config: &str) -> Result<Self> {
+        let blobs = Self::check(bootstrap, config)?;
+        let meta_dev = LoopDevice::attach(Path::new(bootstrap))?;
+        let mut blob_devs = Vec::with_capacity(blobs.len());
+        for blob in blobs.iter() {
+            blob_devs.push(LoopDevice::attach(blob)?);
+        }
+
+        let data = blob_devs
+            .iter()
+            .map(|d| format!("device={}", d.path))
+            .collect::<Vec<String>>()
+            .join(",");
+        let source = CString::new(meta_dev.path.as_str()).map_err(|e| einval!(e))?;
+        let target = CString::new(mountpoint).map_err(|e| einval!(e))?;
+        let fs_type = CString::new("erofs").unwrap();
+        let data = CString::new(data).map_err(|e| einval!(e))?;
+        let ret = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fs_type.as_ptr(),
+                libc::MS_RDONLY,
+                data.as_ptr() as *const libc::c_void,
+            )
+        };
+        if ret < 0 {
+            return Err(last_error!(format!(
+                "failed to mount erofs at {}",
+                mountpoint
+            )));
+        }
+
+        Ok(ErofsMount {
+            info: ErofsMountInfo {
+                mountpoint: mountpoint.to_string(),
+                bootstrap: bootstrap.to_string(),
+                device: meta_dev.path,
+                blob_devices: blob_devs.into_iter().map(|d| d.path).collect(),
+            },
+        })
+    }
+
+    pub fn info(&self) -> &ErofsMountInfo {
+        &self.info
+    }
+
+    /// Umount the filesystem, loop devices are released by the kernel afterwards.
+    pub fn umount(&self) -> Result<()> {
+        let target = CString::new(self.info.mountpoint.as_str()).map_err(|e| einval!(e))?;
+        let ret = unsafe { libc::umount2(target.as_ptr(), 0) };
+        if ret < 0 {
+            return Err(last_error!(format!(
+                "failed to umount erofs at {}",
+                self.info.mountpoint
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_filesystem() {
+        let filesystems = "nodev\tsysfs\nnodev\tproc\n\text4\n\terofs\nnodev\tfuse\n";
+        assert!(has_filesystem(filesystems, "erofs"));
+        assert!(has_filesystem(filesystems, "proc"));
+        assert!(!has_filesystem(filesystems, "nodev"));
+        assert!(!has_filesystem(filesystems, "xfs"));
+        assert_eq!(mem::size_of::<LoopInfo64>(), 232);
+    }
+}
@@ -4,38 +4,63 @@
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
 use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{CStr, CString};
-use std::fs::metadata;
+use std::fs::{metadata, File, OpenOptions};
 use std::io::Result;
-use std::ops::Deref;
+use std::mem::size_of;
 use std::os::linux::fs::MetadataExt;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixStream;
-use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicI32, AtomicU64, Ordering},
-    mpsc::{channel, Receiver},
-    Arc, Mutex, MutexGuard,
+    atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex, MutexGuard, RwLock,
 };
 use std::thread::{self, JoinHandle};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use fuse_backend_rs::abi::linux_abi::{InHeader, OutHeader};
+use fuse_backend_rs::abi::linux_abi::{InHeader, Opcode, OutHeader, ReadIn};
 use fuse_backend_rs::api::server::{MetricsHook, Server};
-use fuse_backend_rs::api::Vfs;
+use fuse_backend_rs::api::{BackFileSystem, Vfs, VfsOptions};
 use fuse_backend_rs::transport::fusedev::{FuseChannel, FuseSession};
-use nix::sys::stat::{major, minor};
+use fuse_backend_rs::transport::{FuseBuf, Reader, Writer};
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+use nix::sys::stat::{fstat, major, minor};
+use nix::sys::uio::IoVec;
+use nydus::FsBackendType;
 use nydus_app::BuildTimeInfo;
+use rafs::fs::Rafs;
 use serde::Serialize;
+use storage::factory::BLOB_FACTORY;
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::poll::{PollContext, WatchingEvents};
 
 use crate::daemon::{
-    DaemonError, DaemonResult, DaemonState, DaemonStateMachineContext, DaemonStateMachineInput,
-    DaemonStateMachineSubscriber, FsBackendCollection, FsBackendMountCmd, NydusDaemon, Trigger,
+    fs_backend_factory, DaemonError, DaemonResult, DaemonState, DaemonStateMachineContext,
+    DaemonStateMachineInput, DaemonStateMachineSubscriber, ErofsMountInfo, FsBackendCollection,
+    FsBackendMountCmd, FuseInitConfig, NydusDaemon, Trigger,
 };
+use crate::erofs::ErofsMount;
 use crate::exit_event_manager;
+use crate::splice::SplicePipe;
 use crate::upgrade::{self, FailoverPolicy, UpgradeManager};
 
+// Interval to check whether in-flight fuse requests are done when draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+pub const FUSE_SERVER_THREAD_NAME: &str = "fuse_server";
+// Vfs encodes index of the mounted filesystem in the high bits of inode numbers.
+const VFS_INDEX_SHIFT: u64 = 56;
+const VFS_INODE_MASK: u64 = (1 << VFS_INDEX_SHIFT) - 1;
+// _IOR(229, 0, uint32_t), clone a fuse connection onto a newly opened `/dev/fuse` fd.
+const FUSE_DEV_IOC_CLONE: u64 = 0x8004_e500;
+const FUSE_DEV_EVENT: u32 = 0;
+const EXIT_FUSE_EVENT: u32 = 1;
+
 #[derive(Serialize)]
 struct FuseOp {
     inode: u64,
@@ -91,22 +116,327 @@ impl MetricsHook for FuseOpWrapper {
     }
 }
 
+impl FuseOpWrapper {
+    // Forget the request being handled and return its unique id, e.g. when the fuse service
+    // thread handling it panicked.
+    fn take_unique(&self) -> Option<u64> {
+        match self.op.lock() {
+            Ok(mut op) => op.take().map(|op| op.unique),
+            Err(e) => e.into_inner().take().map(|op| op.unique),
+        }
+    }
+}
+
+/// Options of fuse service threads.
+#[derive(Clone, Debug, Default)]
+pub struct FuseThreadConfig {
+    /// Number of fuse service threads of each fuse session.
+    pub count: u32,
+    /// Read requests through a cloned `/dev/fuse` fd per thread instead of a shared one.
+    pub clone_fd: bool,
+    /// CPUs to pin fuse service threads to in a round-robin way, no pinning if empty.
+    pub cpus: Vec<usize>,
+}
+
+impl FuseThreadConfig {
+    /// Parse a CPU list like "0-3,8".
+    pub fn parse_cpus(list: &str) -> Result<Vec<usize>> {
+        let mut cpus = Vec::new();
+        for item in list.split(',').map(|s| s.trim()) {
+            let (start, end) = match item.find('-') {
+                Some(pos) => (&item[..pos], &item[pos + 1..]),
+                None => (item, item),
+            };
+            let start = start
+                .parse::<usize>()
+                .map_err(|_| einval!(format!("invalid cpu list {}", list)))?;
+            let end = end
+                .parse::<usize>()
+                .map_err(|_| einval!(format!("invalid cpu list {}", list)))?;
+            if start > end || end >= libc::CPU_SETSIZE as usize {
+                return Err(einval!(format!("invalid cpu range {} in {}", item, list)));
+            }
+            cpus.extend(start..=end);
+        }
+
+        Ok(cpus)
+    }
+
+    // CPU to pin the `index`th fuse service thread to.
+    fn cpu_of(&self, index: u32) -> Option<usize> {
+        if self.cpus.is_empty() {
+            None
+        } else {
+            Some(self.cpus[index as usize % self.cpus.len()])
+        }
+    }
+}
+
+// Pin the current thread to `cpu`, failures are ignored since it's only an optimization.
+fn pin_current_thread(cpu: usize) {
+    let ret = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if ret < 0 {
+        warn!(
+            "failed to pin fuse service thread to cpu {}, {}",
+            cpu,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+// Clone the fuse connection of `file` onto a newly opened `/dev/fuse` fd. Requests read from
+// the cloned fd must be replied through it too.
+fn clone_fuse_file(file: &File) -> Result<File> {
+    let cloned = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/fuse")?;
+    let mut fd = file.as_raw_fd() as u32;
+    let ret = unsafe { libc::ioctl(cloned.as_raw_fd(), FUSE_DEV_IOC_CLONE as _, &mut fd) };
+    if ret < 0 {
+        return Err(last_error!("failed to clone fuse device fd"));
+    }
+
+    Ok(cloned)
+}
+
+// Fuse channels poll the `/dev/fuse` fd and expect reads to fail with EAGAIN instead of blocking,
+// as the fd opened by `FuseSession::mount()` does.
+pub(crate) fn set_nonblocking(file: &File) -> Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(last_error!("failed to set O_NONBLOCK on fuse device fd"));
+    }
+
+    Ok(())
+}
+
+// Channel to read fuse requests from a cloned `/dev/fuse` fd. It works the same way as
+// `FuseChannel`, which can only be created from a `FuseSession` and dropping a session umounts
+// the mountpoint.
+struct ClonedChannel {
+    file: File,
+    poll_ctx: PollContext<u32>,
+    buf: Vec<u8>,
+}
+
+impl ClonedChannel {
+    fn new(file: File, evtfd: &EventFd, bufsize: usize) -> Result<Self> {
+        let poll_ctx = PollContext::new()?;
+        poll_ctx.add_fd_with_events(&file, WatchingEvents::empty().set_read(), FUSE_DEV_EVENT)?;
+        poll_ctx.add_fd_with_events(evtfd, WatchingEvents::empty().set_read(), EXIT_FUSE_EVENT)?;
+
+        Ok(ClonedChannel {
+            file,
+            poll_ctx,
+            buf: vec![0u8; bufsize],
+        })
+    }
+
+    // Get the next fuse request, returns None when the exit event fd is signaled or the
+    // filesystem is umounted.
+    fn get_request(&mut self) -> Result<Option<(Reader, Writer)>> {
+        loop {
+            let events = self.poll_ctx.wait()?;
+            for event in events.iter() {
+                if !event.readable() {
+                    return Err(eio!("fuse channel is closed"));
+                }
+                if event.token() == EXIT_FUSE_EVENT {
+                    // Don't consume the level triggered event so that all threads exit.
+                    return Ok(None);
+                }
+
+                let fd = self.file.as_raw_fd();
+                let ret = unsafe {
+                    libc::read(
+                        fd,
+                        self.buf.as_mut_ptr() as *mut libc::c_void,
+                        self.buf.len(),
+                    )
+                };
+                if ret < 0 {
+                    let e = std::io::Error::last_os_error();
+                    match e.raw_os_error() {
+                        // ENOENT means the request was interrupted.
+                        Some(libc::ENOENT) | Some(libc::EAGAIN) | Some(libc::EINTR) => continue,
+                        Some(libc::ENODEV) => {
+                            info!("fuse filesystem umounted");
+                            return Ok(None);
+                        }
+                        _ => return Err(e),
+                    }
+                }
+
+                // Share the buffer between the reader and the writer like `FuseChannel` does,
+                // the request is not read anymore once the reply starts to be written.
+                let buf = unsafe {
+                    std::slice::from_raw_parts_mut(self.buf.as_mut_ptr(), self.buf.len())
+                };
+                let reader = Reader::new(FuseBuf::new(&mut self.buf[..ret as usize]))
+                    .map_err(|e| eother!(e))?;
+                let writer = Writer::new(fd, buf).map_err(|e| eother!(e))?;
+                return Ok(Some((reader, writer)));
+            }
+        }
+    }
+}
+
+// Channel of a fuse service thread, to read requests through either the `/dev/fuse` fd of the
+// fuse session or a cloned one.
+enum ServerChannel {
+    Session(FuseChannel),
+    Cloned(ClonedChannel),
+}
+
+impl ServerChannel {
+    fn get_request(&mut self) -> Result<Option<(Reader, Writer)>> {
+        match self {
+            ServerChannel::Session(ch) => ch.get_request().map_err(|e| eother!(e)),
+            ServerChannel::Cloned(ch) => ch.get_request(),
+        }
+    }
+}
+
+// Rafs filesystems mounted into vfs, indexed by their vfs indexes.
+type SpliceMounts = Arc<RwLock<HashMap<u8, Arc<BackFileSystem>>>>;
+
+// States to reply fuse read requests by splice, kept apart from the fuse channel which is
+// borrowed by the request being handled.
+struct SpliceReader {
+    mounts: SpliceMounts,
+    pipe: Option<SplicePipe>,
+    // Set once failed to create the pipe, so that it's not tried again.
+    disabled: bool,
+}
+
 struct FuseServer {
     server: Arc<Server<Arc<Vfs>>>,
-    ch: FuseChannel,
+    ch: ServerChannel,
+    fuse_file: Option<File>,
+    splice: SpliceReader,
+}
+
+impl SpliceReader {
+    // Try to reply a read request by splicing data from blob cache files into the fuse device.
+    // Returns false if the request should be handled by the fuse server as usual, e.g. data to
+    // read is not cached in uncompressed form.
+    fn read(&mut self, fuse_fd: RawFd, reader: &Reader, metrics_hook: &dyn MetricsHook) -> bool {
+        if self.disabled || self.mounts.read().unwrap().is_empty() {
+            return false;
+        }
+
+        // Peek the request with a cloned reader, so the request is kept intact for fallback.
+        let mut r = reader.clone();
+        let ih: InHeader = match r.read_obj() {
+            Ok(ih) => ih,
+            Err(_) => return false,
+        };
+        if ih.opcode != Opcode::Read as u32 {
+            return false;
+        }
+        let arg: ReadIn = match r.read_obj() {
+            Ok(arg) => arg,
+            Err(_) => return false,
+        };
+
+        let fs = match self
+            .mounts
+            .read()
+            .unwrap()
+            .get(&((ih.nodeid >> VFS_INDEX_SHIFT) as u8))
+        {
+            Some(fs) => fs.clone(),
+            None => return false,
+        };
+        let rafs = match fs.as_any().downcast_ref::<Rafs>() {
+            Some(rafs) => rafs,
+            None => return false,
+        };
+        let extents =
+            match rafs.get_cached_extents(ih.nodeid & VFS_INODE_MASK, arg.offset, arg.size) {
+                Ok(Some(extents)) if !extents.is_empty() => extents,
+                _ => return false,
+            };
+
+        if self.pipe.is_none() {
+            match SplicePipe::new() {
+                Ok(pipe) => self.pipe = Some(pipe),
+                Err(e) => {
+                    warn!("failed to create pipe for splice read, {}", e);
+                    // Don't try again.
+                    self.disabled = true;
+                    return false;
+                }
+            }
+        }
+
+        metrics_hook.collect(&ih);
+        let ret = self
+            .pipe
+            .as_mut()
+            .unwrap()
+            .reply(fuse_fd, ih.unique, &extents);
+        metrics_hook.release(None);
+        match ret {
+            Ok(replied) => replied,
+            Err(e) => {
+                debug!("failed to reply read request by splice, {}", e);
+                false
+            }
+        }
+    }
 }
 
 impl FuseServer {
-    fn new(server: Arc<Server<Arc<Vfs>>>, se: &FuseSession, evtfd: EventFd) -> Result<FuseServer> {
+    fn new(
+        server: Arc<Server<Arc<Vfs>>>,
+        se: &mut FuseSession,
+        evtfd: EventFd,
+        mounts: SpliceMounts,
+        clone_fd: bool,
+    ) -> Result<FuseServer> {
+        let bufsize = se.bufsize();
+        // `get_fuse_file()` takes `&mut self`, so the fd is cloned while holding the session.
+        let (ch, fuse_file) = match se.get_fuse_file() {
+            Some(f) if clone_fd => {
+                let cloned = clone_fuse_file(f)?;
+                let fuse_file = cloned.try_clone()?;
+                let ch = ClonedChannel::new(cloned, &evtfd, bufsize)?;
+                (ServerChannel::Cloned(ch), Some(fuse_file))
+            }
+            Some(f) => {
+                let fuse_file = f.try_clone()?;
+                (
+                    ServerChannel::Session(se.new_channel(evtfd)?),
+                    Some(fuse_file),
+                )
+            }
+            None => (ServerChannel::Session(se.new_channel(evtfd)?), None),
+        };
+
         Ok(FuseServer {
             server,
-            ch: se.new_channel(evtfd)?,
+            ch,
+            fuse_file,
+            splice: SpliceReader {
+                mounts,
+                pipe: None,
+                disabled: false,
+            },
         })
     }
 
     fn svc_loop(&mut self, metrics_hook: &dyn MetricsHook) -> Result<()> {
         // Given error EBADF, it means kernel has shut down this session.
         let _ebadf = std::io::Error::from_raw_os_error(libc::EBADF);
+        let fuse_fd = self.fuse_file.as_ref().map(|f| f.as_raw_fd());
 
         loop {
             if let Some((reader, writer)) = self
@@ -114,6 +444,11 @@ impl FuseServer {
                 .get_request()
                 .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?
             {
+                if let Some(fd) = fuse_fd {
+                    if self.splice.read(fd, &reader, metrics_hook) {
+                        continue;
+                    }
+                }
                 if let Err(e) = self
                     .server
                     .handle_message(reader, writer, None, Some(metrics_hook))
@@ -136,19 +471,190 @@ impl FuseServer {
 
         Ok(())
     }
+
+    // Reply `errno` to the request `unique`, so that the requester doesn't wait for the reply
+    // forever when the fuse service thread panicked while handling the request.
+    fn reply_error(&self, unique: u64, errno: i32) {
+        let file = match self.fuse_file.as_ref() {
+            Some(f) => f,
+            None => return,
+        };
+        let header = OutHeader {
+            len: size_of::<OutHeader>() as u32,
+            error: -errno,
+            unique,
+        };
+        // Safe because `OutHeader` is a plain C struct.
+        let ret = unsafe {
+            libc::write(
+                file.as_raw_fd(),
+                &header as *const OutHeader as *const libc::c_void,
+                size_of::<OutHeader>(),
+            )
+        };
+        if ret < 0 {
+            warn!(
+                "failed to reply error to fuse request {}, {}",
+                unique,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Information about a fuse session created in singleton mode.
+#[derive(Clone, Serialize)]
+struct FuseSessionInfo {
+    backend_type: FsBackendType,
+    source: String,
+    mounted_time: String,
+}
+
+// A fuse session created through the admin API in singleton mode, which serves its own
+// filesystem instance with its own fuse service threads. Blob caches are shared with filesystem
+// instances of other sessions through the blob factory.
+struct SingletonSession {
+    session: FuseSession,
+    info: FuseSessionInfo,
+    event_fd: EventFd,
+    inflight_ops: Vec<FuseOpWrapper>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl SingletonSession {
+    fn kick_server(
+        &mut self,
+        server: &Arc<Server<Arc<Vfs>>>,
+        mounts: &SpliceMounts,
+        inflight_op: FuseOpWrapper,
+        config: &FuseThreadConfig,
+    ) -> Result<()> {
+        let evtfd = self.event_fd.try_clone()?;
+        let mut s = FuseServer::new(
+            server.clone(),
+            &mut self.session,
+            evtfd,
+            mounts.clone(),
+            config.clone_fd,
+        )?;
+        let mountpoint = self.session.mountpoint().to_path_buf();
+        let cpu = config.cpu_of(self.threads.len() as u32);
+
+        self.inflight_ops.push(inflight_op.clone());
+        let thread = thread::Builder::new()
+            .name(FUSE_SERVER_THREAD_NAME.to_string())
+            .spawn(move || {
+                if let Some(cpu) = cpu {
+                    pin_current_thread(cpu);
+                }
+                match panic::catch_unwind(AssertUnwindSafe(|| s.svc_loop(&inflight_op))) {
+                    Ok(r) => info!("fuse service thread for {:?} exits, {:?}", mountpoint, r),
+                    Err(_) => {
+                        // Dead threads of the session are not re-spawned.
+                        error!("fuse service thread for {:?} panicked", mountpoint);
+                        if let Some(unique) = inflight_op.take_unique() {
+                            s.reply_error(unique, libc::EIO);
+                        }
+                    }
+                }
+            })?;
+        self.threads.push(thread);
+
+        Ok(())
+    }
+
+    // Umount the fuse session and wait for its fuse service threads to exit.
+    fn stop(&mut self) -> DaemonResult<()> {
+        let result = self.session.umount().map_err(DaemonError::SessionShutdown);
+        self.event_fd
+            .write(1)
+            .unwrap_or_else(|e| error!("failed to stop fuse service threads, {}", e));
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("failed to join fuse service thread");
+            }
+        }
+
+        result
+    }
+}
+
+/// Source of the opened `/dev/fuse` file, when the filesystem is mounted by a privileged helper
+/// instead of nydusd itself.
+pub enum FuseFdSource {
+    /// File descriptor inherited from the parent process, which is either the `/dev/fuse` file
+    /// or a unix domain socket to receive the `/dev/fuse` file from, e.g. the `_FUSE_COMMFD`
+    /// socket of fusermount3.
+    Fd(RawFd),
+    /// Path of a unix domain socket to connect to and receive the `/dev/fuse` file from.
+    Socket(PathBuf),
+}
+
+impl FuseFdSource {
+    fn open(&self) -> Result<File> {
+        let file = self.open_file()?;
+        set_nonblocking(&file)?;
+
+        Ok(file)
+    }
+
+    fn open_file(&self) -> Result<File> {
+        match self {
+            FuseFdSource::Fd(fd) => {
+                let st = fstat(*fd).map_err(|e| eother!(e))?;
+                match st.st_mode & libc::S_IFMT {
+                    libc::S_IFSOCK => Self::recv_fuse_file(*fd),
+                    // Safe because the fd is inherited for nydusd to use exclusively.
+                    libc::S_IFCHR => Ok(unsafe { File::from_raw_fd(*fd) }),
+                    _ => Err(einval!(format!(
+                        "fd {} is neither /dev/fuse nor a unix domain socket",
+                        fd
+                    ))),
+                }
+            }
+            FuseFdSource::Socket(path) => {
+                let stream = UnixStream::connect(path)?;
+                Self::recv_fuse_file(stream.as_raw_fd())
+            }
+        }
+    }
+
+    // Receive the `/dev/fuse` fd from a unix domain socket by SCM_RIGHTS.
+    fn recv_fuse_file(sock: RawFd) -> Result<File> {
+        let mut buf = [0u8; 1];
+        let iov = [IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg =
+            recvmsg(sock, &iov, Some(&mut cmsg_buf), MsgFlags::empty()).map_err(|e| eother!(e))?;
+
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(fd) = fds.first() {
+                    // Safe because the fd is received for nydusd to use exclusively.
+                    return Ok(unsafe { File::from_raw_fd(*fd) });
+                }
+            }
+        }
+
+        Err(einval!("no fuse fd received from unix domain socket"))
+    }
 }
 
 pub struct FusedevDaemon {
     /// Fuse connection ID which usually equals to `st_dev`
     pub conn: AtomicU64,
     pub failover_policy: FailoverPolicy,
-    pub session: Mutex<FuseSession>,
+    /// The fuse session at the mountpoint specified by command line, which is optional in
+    /// singleton mode.
+    pub session: Mutex<Option<FuseSession>>,
 
     bti: BuildTimeInfo,
     id: Option<String>,
     supervisor: Option<String>,
     vfs: Arc<Vfs>,
-    threads_cnt: u32,
+    thread_config: FuseThreadConfig,
+    // Number of fuse service threads spawned, to assign CPUs to them.
+    threads_spawned: AtomicU32,
 
     event_fd: EventFd,
     state: AtomicI32,
@@ -156,28 +662,94 @@ pub struct FusedevDaemon {
     upgrade_mgr: Option<Mutex<UpgradeManager>>,
 
     backend_collection: Mutex<FsBackendCollection>,
+    splice_mounts: SpliceMounts,
     inflight_ops: Mutex<Vec<FuseOpWrapper>>,
+    drain_timeout: AtomicU64,
     result_receiver: Mutex<Receiver<DaemonResult<()>>>,
     trigger: Arc<Mutex<Trigger>>,
     threads: Mutex<Vec<JoinHandle<Result<()>>>>,
+    // Fuse service threads notify the watchdog through it when they die.
+    watchdog_notifier: Mutex<Sender<()>>,
+    restart_limit: u32,
+    restarts: AtomicU32,
+    // The filesystem is mounted by a privileged helper, which passes the `/dev/fuse` fd to us.
+    mounted_by_helper: bool,
+    readonly: bool,
+    // Whether fuse sessions can be created through the admin API.
+    singleton: bool,
+    // Fuse sessions created in singleton mode, indexed by mountpoint.
+    sessions: Mutex<HashMap<String, SingletonSession>>,
+    // The image mounted by EROFS instead of the fuse session.
+    erofs_mount: Mutex<Option<ErofsMount>>,
 }
 
 impl FusedevDaemon {
+    // Try to mount the image by EROFS at `mountpoint` instead of the fuse session, and return
+    // whether it's mounted.
+    fn mount_erofs(&self, mountpoint: &str, cmd: &FsBackendMountCmd) -> bool {
+        if cmd.fs_type != FsBackendType::Rafs {
+            warn!("only a single bootstrap can be mounted by erofs, fall back to fuse");
+            return false;
+        }
+        match ErofsMount::mount(mountpoint, &cmd.source, &cmd.config) {
+            Ok(m) => {
+                info!(
+                    "image {} is mounted by erofs at {} on {}",
+                    cmd.source,
+                    mountpoint,
+                    m.info().device
+                );
+                *self.session.lock().unwrap() = None;
+                *self.erofs_mount.lock().unwrap() = Some(m);
+                true
+            }
+            Err(e) => {
+                warn!("failed to mount image by erofs, fall back to fuse, {}", e);
+                false
+            }
+        }
+    }
+
     fn kick_one_server(&self) -> Result<()> {
         // Clone event fd must succeed, otherwise fusedev daemon should not work.
         let evtfd = self.event_fd.try_clone()?;
-        let mut s = FuseServer::new(
-            self.server.clone(),
-            self.session.lock().unwrap().deref(),
-            evtfd,
-        )?;
+        let mut s = {
+            let mut guard = self.session.lock().unwrap();
+            let session = guard
+                .as_mut()
+                .ok_or_else(|| einval!("no fuse session to serve"))?;
+            FuseServer::new(
+                self.server.clone(),
+                session,
+                evtfd,
+                self.splice_mounts.clone(),
+                self.thread_config.clone_fd,
+            )?
+        };
 
         let inflight_op = self.create_inflight_op();
+        let notifier = self.watchdog_notifier.lock().unwrap().clone();
+        let cpu = self
+            .thread_config
+            .cpu_of(self.threads_spawned.fetch_add(1, Ordering::Relaxed));
         let thread = thread::Builder::new()
-            .name("fuse_server".to_string())
+            .name(FUSE_SERVER_THREAD_NAME.to_string())
             .spawn(move || {
-                let _ = s.svc_loop(&inflight_op);
-                exit_event_manager();
+                if let Some(cpu) = cpu {
+                    pin_current_thread(cpu);
+                }
+                match panic::catch_unwind(AssertUnwindSafe(|| s.svc_loop(&inflight_op))) {
+                    Ok(_) => exit_event_manager(),
+                    Err(_) => {
+                        error!("fuse service thread panicked");
+                        // The request being handled won't be replied by the fuse server, fail it
+                        // and don't count it as inflight anymore.
+                        if let Some(unique) = inflight_op.take_unique() {
+                            s.reply_error(unique, libc::EIO);
+                        }
+                        let _ = notifier.send(());
+                    }
+                }
                 Ok(())
             })
             .map_err(DaemonError::ThreadSpawn)?;
@@ -195,6 +767,49 @@ impl FusedevDaemon {
 
         inflight_op
     }
+
+    // Supervise fuse service threads, re-spawn dead ones up to `restart_limit` times, and turn
+    // the daemon into `FAILED` state once the limit is exceeded.
+    fn start_watchdog(daemon: Arc<Self>, rx: Receiver<()>) -> Result<()> {
+        thread::Builder::new()
+            .name("fuse_watchdog".to_string())
+            .spawn(move || {
+                while rx.recv().is_ok() {
+                    if daemon.get_state() != DaemonState::RUNNING {
+                        continue;
+                    }
+                    let restarts = daemon.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+                    if restarts <= daemon.restart_limit {
+                        warn!(
+                            "re-spawn dead fuse service thread, restarts {}/{}",
+                            restarts, daemon.restart_limit
+                        );
+                        match daemon.kick_one_server() {
+                            Ok(_) => continue,
+                            Err(e) => error!("failed to re-spawn fuse service thread, {:?}", e),
+                        }
+                    } else {
+                        error!(
+                            "fuse service threads died too many times, restart limit {}",
+                            daemon.restart_limit
+                        );
+                    }
+                    if let Err(e) = daemon.on_event(DaemonStateMachineInput::Fail) {
+                        error!("failed to turn daemon into failed state, {}", e);
+                    }
+                }
+            })
+            .map(|_| ())
+    }
+
+    fn inflight_ops_count(&self) -> usize {
+        self.inflight_ops
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|w| w.op.lock().unwrap().is_some())
+            .count()
+    }
 }
 
 impl DaemonStateMachineSubscriber for FusedevDaemon {
@@ -220,7 +835,11 @@ impl NydusDaemon for FusedevDaemon {
     }
 
     fn start(&self) -> DaemonResult<()> {
-        for _ in 0..self.threads_cnt {
+        // Fuse sessions may be created through the admin API later in singleton mode.
+        if self.session.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        for _ in 0..self.thread_config.count {
             self.kick_one_server()
                 .map_err(|e| DaemonError::StartService(format!("{:?}", e)))?;
         }
@@ -246,12 +865,72 @@ impl NydusDaemon for FusedevDaemon {
         Ok(())
     }
 
+    fn trigger_drain(&self, timeout: Duration) -> DaemonResult<()> {
+        self.drain_timeout
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+        self.on_event(DaemonStateMachineInput::Drain)
+    }
+
+    fn drain(&self) -> DaemonResult<()> {
+        let timeout = Duration::from_millis(self.drain_timeout.load(Ordering::Relaxed));
+        self.set_state(DaemonState::DRAINING);
+        // Fuse service loops stop fetching new messages, but messages being handled are
+        // not affected.
+        self.interrupt();
+
+        let start = Instant::now();
+        loop {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+            let count = self.inflight_ops_count();
+            if count == 0 {
+                break;
+            }
+            if start.elapsed() >= timeout {
+                warn!(
+                    "drain timed out, {} fuse requests are still inflight",
+                    count
+                );
+                break;
+            }
+        }
+        info!("fuse service drained in {:?}", start.elapsed());
+
+        self.disconnect()?;
+        self.set_state(DaemonState::STOPPED);
+
+        Ok(())
+    }
+
     fn disconnect(&self) -> DaemonResult<()> {
-        self.session
+        if let Some(m) = self.erofs_mount.lock().unwrap().take() {
+            m.umount()
+                .map_err(|e| DaemonError::DaemonFailure(format!("{}", e)))?;
+        }
+        for (mountpoint, mut session) in self.sessions.lock().unwrap().drain() {
+            session.stop().unwrap_or_else(|e| {
+                error!("failed to umount fuse session at {}, {}", mountpoint, e)
+            });
+        }
+
+        let result = match self
+            .session
             .lock()
             .expect("Not expect poisoned lock.")
-            .umount()
-            .map_err(DaemonError::SessionShutdown)
+            .as_mut()
+        {
+            Some(session) => session.umount(),
+            None => return Ok(()),
+        };
+
+        match result {
+            // Unprivileged nydusd can't umount the filesystem, the fuse connection is aborted by
+            // closing the fuse fd and it's up to the helper to umount it.
+            Err(e) if self.mounted_by_helper => {
+                warn!("failed to umount filesystem mounted by helper, {:?}", e);
+                Ok(())
+            }
+            r => r.map_err(DaemonError::SessionShutdown),
+        }
     }
 
     #[inline]
@@ -301,10 +980,35 @@ impl NydusDaemon for FusedevDaemon {
         self.backend_collection.lock().unwrap()
     }
 
+    fn on_mounted(&self, index: u8, mountpoint: &str) {
+        if let Ok(Some(fs)) = self.backend_from_mountpoint(mountpoint) {
+            if fs.as_any().is::<Rafs>() {
+                self.splice_mounts.write().unwrap().insert(index, fs);
+            }
+        }
+    }
+
+    fn on_umounting(&self, mountpoint: &str) {
+        if let Ok(Some(fs)) = self.backend_from_mountpoint(mountpoint) {
+            self.splice_mounts
+                .write()
+                .unwrap()
+                .retain(|_, v| !Arc::ptr_eq(v, &fs));
+        }
+    }
+
     fn version(&self) -> BuildTimeInfo {
         self.bti.clone()
     }
 
+    fn erofs_mount(&self) -> Option<ErofsMountInfo> {
+        self.erofs_mount
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|m| m.info().clone())
+    }
+
     fn export_inflight_ops(&self) -> DaemonResult<Option<String>> {
         let ops = self.inflight_ops.lock().unwrap();
 
@@ -321,6 +1025,105 @@ impl NydusDaemon for FusedevDaemon {
             Ok(Some(resp))
         }
     }
+
+    fn create_fuse_session(&self, cmd: FsBackendMountCmd) -> DaemonResult<()> {
+        if !self.singleton {
+            return Err(DaemonError::Unsupported);
+        }
+        if self.get_state() != DaemonState::RUNNING {
+            return Err(DaemonError::NotReady);
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(&cmd.mountpoint) {
+            return Err(DaemonError::AlreadyExists);
+        }
+
+        let mut opts = VfsOptions::default();
+        if cmd.fs_type == FsBackendType::PassthroughFs {
+            // passthroughfs requires !no_open
+            opts.no_open = false;
+            opts.killpriv_v2 = true;
+        } else {
+            opts.no_open = true;
+        }
+        FuseInitConfig::from_config(&cmd.config)?.apply(&mut opts);
+        let vfs = Arc::new(Vfs::new(opts));
+        let index = vfs.mount(fs_backend_factory(&cmd)?, "/")?;
+        let splice_mounts = SpliceMounts::default();
+        if let Some(fs) = vfs.get_rootfs("/")? {
+            if fs.as_any().is::<Rafs>() {
+                splice_mounts.write().unwrap().insert(index, fs);
+            }
+        }
+
+        let mut session = FuseSession::new(Path::new(&cmd.mountpoint), "rafs", "", self.readonly)
+            .map_err(|e| DaemonError::StartService(format!("{:?}", e)))?;
+        session
+            .mount()
+            .map_err(|e| DaemonError::StartService(format!("{:?}", e)))?;
+        let mut instance = SingletonSession {
+            session,
+            info: FuseSessionInfo {
+                backend_type: cmd.fs_type.clone(),
+                source: cmd.source.clone(),
+                mounted_time: chrono::Local::now().to_string(),
+            },
+            event_fd: EventFd::new(0).map_err(DaemonError::EventFdClone)?,
+            inflight_ops: Vec::new(),
+            threads: Vec::new(),
+        };
+
+        let server = Arc::new(Server::new(vfs));
+        for _ in 0..self.thread_config.count {
+            let inflight_op = self.create_inflight_op();
+            if let Err(e) =
+                instance.kick_server(&server, &splice_mounts, inflight_op, &self.thread_config)
+            {
+                instance.stop().unwrap_or_else(|e| error!("{}", e));
+                return Err(DaemonError::StartService(format!("{:?}", e)));
+            }
+        }
+        info!(
+            "fuse session of {} created at {}",
+            cmd.fs_type, cmd.mountpoint
+        );
+        sessions.insert(cmd.mountpoint, instance);
+
+        Ok(())
+    }
+
+    fn destroy_fuse_session(&self, mountpoint: &str) -> DaemonResult<()> {
+        let mut instance = self
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(mountpoint)
+            .ok_or(DaemonError::NotFound)?;
+        let result = instance.stop();
+        self.inflight_ops.lock().unwrap().retain(|w| {
+            !instance
+                .inflight_ops
+                .iter()
+                .any(|op| Arc::ptr_eq(&op.op, &w.op))
+        });
+        drop(instance);
+        // Release blob caches not used by any filesystem instance anymore.
+        BLOB_FACTORY.gc();
+        info!("fuse session at {} destroyed", mountpoint);
+
+        result
+    }
+
+    fn export_fuse_sessions(&self) -> DaemonResult<String> {
+        if !self.singleton {
+            return Err(DaemonError::Unsupported);
+        }
+        let sessions = self.sessions.lock().unwrap();
+        let infos: BTreeMap<&String, &FuseSessionInfo> =
+            sessions.iter().map(|(mp, s)| (mp, &s.info)).collect();
+
+        serde_json::to_string(&infos).map_err(DaemonError::Serde)
+    }
 }
 
 // TODO: Perhaps, we can't rely on `/proc/self/mounts` to tell if it is mounted.
@@ -375,7 +1178,7 @@ fn is_crashed(path: impl AsRef<Path>, sock: &impl AsRef<Path>) -> Result<bool> {
     Ok(false)
 }
 
-fn calc_fuse_conn(mp: impl AsRef<Path>) -> Result<u64> {
+pub(crate) fn calc_fuse_conn(mp: impl AsRef<Path>) -> Result<u64> {
     let st = metadata(mp)?;
     let dev = st.st_dev();
     let (major, minor) = (major(dev), minor(dev));
@@ -386,19 +1189,33 @@ fn calc_fuse_conn(mp: impl AsRef<Path>) -> Result<u64> {
 
 #[allow(clippy::too_many_arguments)]
 pub fn create_nydus_daemon(
-    mountpoint: &str,
+    mountpoint: Option<&str>,
     vfs: Arc<Vfs>,
     supervisor: Option<String>,
     id: Option<String>,
-    threads_cnt: u32,
+    threads: FuseThreadConfig,
+    restart_limit: u32,
     api_sock: Option<impl AsRef<Path>>,
     upgrade: bool,
     readonly: bool,
+    fuse_fd: Option<FuseFdSource>,
     fp: FailoverPolicy,
     mount_cmd: Option<FsBackendMountCmd>,
+    singleton: bool,
+    erofs: bool,
     bti: BuildTimeInfo,
 ) -> Result<Arc<dyn NydusDaemon + Send + Sync>> {
-    let session = FuseSession::new(Path::new(mountpoint), "rafs", "", readonly)?;
+    let mounted_by_helper = fuse_fd.is_some();
+    let session = match mountpoint {
+        Some(mp) => {
+            let mut session = FuseSession::new(Path::new(mp), "rafs", "", readonly)?;
+            if let Some(source) = fuse_fd {
+                session.set_fuse_file(source.open()?);
+            }
+            Some(session)
+        }
+        None => None,
+    };
 
     // Create upgrade manager
     let upgrade_mgr = supervisor
@@ -407,6 +1224,7 @@ pub fn create_nydus_daemon(
 
     let (trigger, events_rx) = channel::<DaemonStateMachineInput>();
     let (result_sender, result_receiver) = channel::<DaemonResult<()>>();
+    let (watchdog_notifier, watchdog_rx) = channel::<()>();
 
     let daemon = Arc::new(FusedevDaemon {
         conn: AtomicU64::new(0),
@@ -416,7 +1234,8 @@ pub fn create_nydus_daemon(
         bti,
         id,
         supervisor,
-        threads_cnt,
+        thread_config: threads,
+        threads_spawned: AtomicU32::new(0),
         vfs: vfs.clone(),
 
         event_fd: EventFd::new(0).unwrap(),
@@ -425,33 +1244,80 @@ pub fn create_nydus_daemon(
         upgrade_mgr,
 
         backend_collection: Default::default(),
+        splice_mounts: Default::default(),
         inflight_ops: Mutex::new(Vec::new()),
+        drain_timeout: AtomicU64::new(0),
         result_receiver: Mutex::new(result_receiver),
         trigger: Arc::new(Mutex::new(trigger)),
         threads: Mutex::new(Vec::new()),
+        watchdog_notifier: Mutex::new(watchdog_notifier),
+        restart_limit,
+        restarts: AtomicU32::new(0),
+        mounted_by_helper,
+        readonly,
+        singleton,
+        sessions: Mutex::new(HashMap::new()),
+        erofs_mount: Mutex::new(None),
     });
 
     let machine = DaemonStateMachineContext::new(daemon.clone(), events_rx, result_sender);
     machine.kick_state_machine()?;
+    FusedevDaemon::start_watchdog(daemon.clone(), watchdog_rx)?;
 
     // Without api socket, nydusd can't do neither live-upgrade nor failover, so the helper
     // finding a victim is not necessary.
-    if (api_sock.as_ref().is_some()
-        && !upgrade
-        && !is_crashed(mountpoint, api_sock.as_ref().unwrap())?)
-        || api_sock.is_none()
-    {
+    let fresh_start = match (api_sock.as_ref(), mountpoint) {
+        (None, _) => true,
+        (Some(_), _) if upgrade => false,
+        (Some(sock), Some(mp)) => !is_crashed(mp, sock)?,
+        (Some(_), None) => true,
+    };
+    if fresh_start {
+        let mut erofs_mounted = false;
         if let Some(cmd) = mount_cmd {
-            daemon.mount(cmd)?;
+            if let (true, Some(mp)) = (erofs, mountpoint) {
+                erofs_mounted = daemon.mount_erofs(mp, &cmd);
+            }
+            if !erofs_mounted {
+                daemon.mount(cmd)?;
+            }
+        }
+        if !mounted_by_helper {
+            if let Some(session) = daemon.session.lock().unwrap().as_mut() {
+                session.mount()?;
+            }
         }
-        daemon.session.lock().unwrap().mount()?;
         daemon
             .on_event(DaemonStateMachineInput::Mount)
             .map_err(|e| eother!(e))?;
-        daemon
-            .conn
-            .store(calc_fuse_conn(mountpoint)?, Ordering::Relaxed);
+        if let (false, Some(mp)) = (erofs_mounted, mountpoint) {
+            daemon.conn.store(calc_fuse_conn(mp)?, Ordering::Relaxed);
+        }
     }
 
     Ok(daemon)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuse_thread_config() {
+        assert_eq!(FuseThreadConfig::parse_cpus("3").unwrap(), vec![3]);
+        assert_eq!(
+            FuseThreadConfig::parse_cpus("0-2, 8").unwrap(),
+            vec![0, 1, 2, 8]
+        );
+        assert!(FuseThreadConfig::parse_cpus("").is_err());
+        assert!(FuseThreadConfig::parse_cpus("2-1").is_err());
+        assert!(FuseThreadConfig::parse_cpus("0-").is_err());
+        assert!(FuseThreadConfig::parse_cpus("100000").is_err());
+
+        let mut config = FuseThreadConfig::default();
+        assert_eq!(config.cpu_of(1), None);
+        config.cpus = vec![4, 6];
+        assert_eq!(config.cpu_of(0), Some(4));
+        assert_eq!(config.cpu_of(3), Some(6));
+    }
+}
@@ -0,0 +1,492 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A simple read-only HTTP server exposing a filesystem backend for debugging.
+//!
+//! The server walks the filesystem tree through the `FileSystem` interface of the backend, so
+//! it shares metadata and storage stack with the fuse server and does not need any privilege to
+//! mount a fuse filesystem. Directories are rendered as HTML listings and regular files are
+//! served with support of single byte range `Range` requests.
+
+use std::cmp;
+use std::ffi::CString;
+use std::io::{self, BufRead, BufReader, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use fuse_backend_rs::api::filesystem::{Context, Entry, ZeroCopyWriter};
+use fuse_backend_rs::transport::{FileReadWriteVolatile, FileVolatileSlice};
+
+use crate::daemon::BackFileSystem;
+
+const ROOT_INODE: u64 = 1;
+const READ_BUF_SIZE: u32 = 0x10_0000;
+const READDIR_BUF_SIZE: u32 = 0x1000;
+const MAX_HEADER_LINES: usize = 128;
+
+/// Buffer to receive file data from the filesystem backend.
+pub(crate) struct DataBuffer {
+    pub(crate) buf: Vec<u8>,
+}
+
+impl io::Write for DataBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ZeroCopyWriter for DataBuffer {
+    fn write_from(
+        &mut self,
+        f: &mut dyn FileReadWriteVolatile,
+        count: usize,
+        off: u64,
+    ) -> Result<usize> {
+        let pos = self.buf.len();
+        self.buf.resize(pos + count, 0);
+        // Safe because the buffer has been resized to hold `count` bytes above.
+        let slice = unsafe { FileVolatileSlice::new(self.buf[pos..].as_mut_ptr(), count) };
+        let cnt = f.read_vectored_at_volatile(&[slice], off)?;
+        self.buf.truncate(pos + cnt);
+        Ok(cnt)
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+struct HttpResponse {
+    code: u16,
+    reason: &'static str,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn new(code: u16, reason: &'static str) -> Self {
+        HttpResponse {
+            code,
+            reason,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn error(code: u16, reason: &'static str) -> Self {
+        let mut resp = Self::new(code, reason);
+        resp.set_body("text/plain", format!("{} {}\n", code, reason).into_bytes());
+        resp
+    }
+
+    fn set_body(&mut self, content_type: &str, body: Vec<u8>) {
+        self.add_header("Content-Type", content_type);
+        self.body = body;
+    }
+
+    fn add_header(&mut self, key: &str, value: &str) {
+        self.headers.push((key.to_string(), value.to_string()));
+    }
+
+    fn write_head(&self, w: &mut dyn Write, content_length: u64) -> Result<()> {
+        write!(w, "HTTP/1.1 {} {}\r\n", self.code, self.reason)?;
+        for (k, v) in self.headers.iter() {
+            write!(w, "{}: {}\r\n", k, v)?;
+        }
+        write!(
+            w,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            content_length
+        )
+    }
+
+    fn send(&self, w: &mut dyn Write, head_only: bool) -> Result<()> {
+        self.write_head(w, self.body.len() as u64)?;
+        if !head_only {
+            w.write_all(&self.body)?;
+        }
+        w.flush()
+    }
+}
+
+/// Read-only HTTP file server backed by a filesystem instance.
+pub struct HttpFsServer {
+    listener: TcpListener,
+    fs: Arc<BackFileSystem>,
+}
+
+impl HttpFsServer {
+    pub fn new(addr: &str, fs: Arc<BackFileSystem>) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(HttpFsServer { listener, fs })
+    }
+
+    /// Spawn a thread to accept connections, each connection is served by a dedicated thread.
+    pub fn start(self) -> Result<JoinHandle<Result<()>>> {
+        thread::Builder::new()
+            .name("http_fs_server".to_string())
+            .spawn(move || {
+                for stream in self.listener.incoming() {
+                    let stream = match stream {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("http fs server: failed to accept connection, {}", e);
+                            continue;
+                        }
+                    };
+                    let fs = self.fs.clone();
+                    let _ = thread::Builder::new()
+                        .name("http_fs_conn".to_string())
+                        .spawn(move || {
+                            if let Err(e) = Self::handle_connection(fs, stream) {
+                                debug!("http fs server: connection error, {}", e);
+                            }
+                        });
+                }
+                Ok(())
+            })
+    }
+
+    fn handle_connection(fs: Arc<BackFileSystem>, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let req = match Self::parse_request(&mut reader) {
+            Ok(r) => r,
+            Err(_) => return HttpResponse::error(400, "Bad Request").send(&mut writer, false),
+        };
+        let head_only = req.method == "HEAD";
+        if req.method != "GET" && !head_only {
+            return HttpResponse::error(405, "Method Not Allowed").send(&mut writer, false);
+        }
+
+        let ctx = Context {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let mut inodes = Vec::new();
+        let result = Self::serve(&fs, &ctx, &req, &mut inodes, &mut writer, head_only);
+        for ino in inodes {
+            fs.forget(&ctx, ino, 1);
+        }
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let resp = match (e.raw_os_error(), e.kind()) {
+                    (Some(libc::ENOENT), _)
+                    | (Some(libc::ENOTDIR), _)
+                    | (_, io::ErrorKind::NotFound) => HttpResponse::error(404, "Not Found"),
+                    (Some(libc::EACCES), _)
+                    | (Some(libc::EPERM), _)
+                    | (_, io::ErrorKind::PermissionDenied) => HttpResponse::error(403, "Forbidden"),
+                    _ => {
+                        warn!("http fs server: failed to serve {}, {}", req.path, e);
+                        HttpResponse::error(500, "Internal Server Error")
+                    }
+                };
+                resp.send(&mut writer, head_only)
+            }
+        }
+    }
+
+    fn parse_request(reader: &mut dyn BufRead) -> Result<HttpRequest> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().ok_or_else(|| einval!())?.to_string();
+        let target = parts.next().ok_or_else(|| einval!())?;
+        let path = percent_decode(target.split('?').next().unwrap_or_default())?;
+
+        let mut range = None;
+        for _ in 0..MAX_HEADER_LINES {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                if k.trim().eq_ignore_ascii_case("range") {
+                    range = Some(v.trim().to_string());
+                }
+            }
+        }
+
+        Ok(HttpRequest {
+            method,
+            path,
+            range,
+        })
+    }
+
+    fn serve(
+        fs: &BackFileSystem,
+        ctx: &Context,
+        req: &HttpRequest,
+        inodes: &mut Vec<u64>,
+        w: &mut dyn Write,
+        head_only: bool,
+    ) -> Result<()> {
+        let mut ino = ROOT_INODE;
+        for name in req.path.split('/').filter(|s| !s.is_empty()) {
+            if name == "." || name == ".." {
+                return Err(io::Error::from_raw_os_error(libc::ENOENT));
+            }
+            let name =
+                CString::new(name).map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+            let entry: Entry = fs.lookup(ctx, ino, &name)?;
+            if entry.inode == 0 {
+                return Err(io::Error::from_raw_os_error(libc::ENOENT));
+            }
+            inodes.push(entry.inode);
+            ino = entry.inode;
+        }
+
+        let (attr, _) = fs.getattr(ctx, ino, None)?;
+        match attr.st_mode & libc::S_IFMT {
+            libc::S_IFDIR => {
+                if !req.path.ends_with('/') {
+                    let mut resp = HttpResponse::new(301, "Moved Permanently");
+                    resp.add_header("Location", &format!("{}/", percent_encode(&req.path)));
+                    return resp.send(w, head_only);
+                }
+                Self::serve_dir(fs, ctx, ino, &req.path, w, head_only)
+            }
+            libc::S_IFREG => Self::serve_file(fs, ctx, ino, attr.st_size as u64, req, w, head_only),
+            libc::S_IFLNK => {
+                let target = fs.readlink(ctx, ino)?;
+                let mut resp = HttpResponse::new(200, "OK");
+                resp.add_header("X-Symlink-Target", &String::from_utf8_lossy(&target));
+                resp.set_body("text/plain", target);
+                resp.send(w, head_only)
+            }
+            _ => HttpResponse::error(403, "Forbidden").send(w, head_only),
+        }
+    }
+
+    fn serve_dir(
+        fs: &BackFileSystem,
+        ctx: &Context,
+        ino: u64,
+        path: &str,
+        w: &mut dyn Write,
+        head_only: bool,
+    ) -> Result<()> {
+        let (handle, _) = fs.opendir(ctx, ino, libc::O_RDONLY as u32)?;
+        let handle = handle.unwrap_or_default();
+        let mut names = Vec::new();
+        let mut offset = 0;
+        let result = loop {
+            let mut added = 0;
+            let r = fs.readdir(ctx, ino, handle, READDIR_BUF_SIZE, offset, &mut |entry| {
+                offset = entry.offset;
+                added += 1;
+                if entry.name != b"." && entry.name != b".." {
+                    names.push(String::from_utf8_lossy(entry.name).to_string());
+                }
+                Ok(entry.name.len())
+            });
+            if r.is_err() || added == 0 {
+                break r;
+            }
+        };
+        let _ = fs.releasedir(ctx, ino, libc::O_RDONLY as u32, handle);
+        result?;
+        names.sort();
+
+        let title = html_escape(path);
+        let mut body = format!(
+            "<html><head><title>Index of {0}</title></head><body><h1>Index of {0}</h1><hr><pre>\n",
+            title
+        );
+        if path != "/" {
+            body.push_str("<a href=\"../\">../</a>\n");
+        }
+        for name in names {
+            let href = percent_encode(&name);
+            body.push_str(&format!(
+                "<a href=\"{}\">{}</a>\n",
+                href,
+                html_escape(&name)
+            ));
+        }
+        body.push_str("</pre><hr></body></html>\n");
+
+        let mut resp = HttpResponse::new(200, "OK");
+        resp.set_body("text/html; charset=utf-8", body.into_bytes());
+        resp.send(w, head_only)
+    }
+
+    fn serve_file(
+        fs: &BackFileSystem,
+        ctx: &Context,
+        ino: u64,
+        size: u64,
+        req: &HttpRequest,
+        w: &mut dyn Write,
+        head_only: bool,
+    ) -> Result<()> {
+        let (start, end) = match req.range.as_deref().map(|r| parse_range(r, size)) {
+            None => (0, size),
+            Some(Some(range)) => range,
+            Some(None) => {
+                let mut resp = HttpResponse::error(416, "Range Not Satisfiable");
+                resp.add_header("Content-Range", &format!("bytes */{}", size));
+                return resp.send(w, head_only);
+            }
+        };
+
+        let mut resp = if req.range.is_some() {
+            let mut resp = HttpResponse::new(206, "Partial Content");
+            resp.add_header(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end.saturating_sub(1), size),
+            );
+            resp
+        } else {
+            HttpResponse::new(200, "OK")
+        };
+        resp.add_header("Content-Type", "application/octet-stream");
+        resp.add_header("Accept-Ranges", "bytes");
+        resp.write_head(w, end - start)?;
+        if head_only {
+            return w.flush();
+        }
+
+        let (handle, _) = fs.open(ctx, ino, libc::O_RDONLY as u32, 0)?;
+        let handle = handle.unwrap_or_default();
+        let result = Self::copy_file_data(fs, ctx, ino, handle, start, end, w);
+        let _ = fs.release(ctx, ino, libc::O_RDONLY as u32, handle, false, false, None);
+        result?;
+
+        w.flush()
+    }
+
+    fn copy_file_data(
+        fs: &BackFileSystem,
+        ctx: &Context,
+        ino: u64,
+        handle: u64,
+        start: u64,
+        end: u64,
+        w: &mut dyn Write,
+    ) -> Result<()> {
+        let mut offset = start;
+        let mut buf = DataBuffer {
+            buf: Vec::with_capacity(READ_BUF_SIZE as usize),
+        };
+
+        while offset < end {
+            let size = cmp::min(READ_BUF_SIZE as u64, end - offset) as u32;
+            buf.buf.clear();
+            let cnt = fs.read(ctx, ino, handle, &mut buf, size, offset, None, 0)?;
+            if cnt == 0 {
+                // The response header has been sent out, so just close the connection.
+                return Err(eio!("unexpected end of file"));
+            }
+            w.write_all(&buf.buf[..cnt])?;
+            offset += cnt as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a single `bytes=start-end` range into a half-open interval within `size`.
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        // Suffix range, the last `end` bytes.
+        ("", n) => {
+            let n: u64 = n.parse().ok()?;
+            (size.saturating_sub(n), size)
+        }
+        (s, "") => (s.parse().ok()?, size),
+        (s, e) => {
+            let e: u64 = e.parse().ok()?;
+            (s.parse().ok()?, cmp::min(e.checked_add(1)?, size))
+        }
+    };
+
+    if start >= end || start >= size {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or_else(|| einval!())?;
+            let hex = std::str::from_utf8(hex).map_err(|_| einval!())?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| einval!())?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| einval!())
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=0-4096", 1000), Some((0, 1000)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_percent_coding() {
+        assert_eq!(percent_decode("/a%20b/c").unwrap(), "/a b/c");
+        assert!(percent_decode("/a%2").is_err());
+        assert_eq!(percent_encode("a b&c"), "a%20b%26c");
+        assert_eq!(html_escape("<a&b>"), "&lt;a&amp;b&gt;");
+    }
+}
@@ -20,12 +20,17 @@ use std::convert::TryInto;
 use std::fs::File;
 use std::io::{Read, Result};
 use std::ops::DerefMut;
+#[cfg(feature = "fusedev")]
+use std::os::unix::io::RawFd;
+#[cfg(feature = "fusedev")]
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     mpsc::channel,
     Arc, Mutex,
 };
 use std::thread;
+use std::time::Duration;
 use std::{io, process};
 
 use clap::{App, Arg};
@@ -37,10 +42,18 @@ use vmm_sys_util::eventfd::EventFd;
 
 use nydus::FsBackendType;
 use nydus_api::http::start_http_thread;
-use nydus_app::{dump_program_info, setup_logging, BuildTimeInfo};
+use nydus_app::{dump_program_info, install_panic_hook, setup_logging, BuildTimeInfo};
+use nydus_utils::{metrics, trace};
 
 use self::api_server_glue::{ApiServer, ApiSeverSubscriber};
-use self::daemon::{DaemonError, FsBackendMountCmd, NydusDaemonSubscriber};
+use self::blockdev::{BlockImage, NbdServer};
+use self::daemon::{
+    fs_backend_factory, validate_config, DaemonError, FsBackendMountCmd, FuseInitConfig,
+    NydusDaemonSubscriber,
+};
+use self::http_fs::HttpFsServer;
+use self::otlp::OtlpExporter;
+use self::smoke_test::run_smoke_test;
 
 #[cfg(feature = "virtiofs")]
 mod virtiofs;
@@ -49,10 +62,19 @@ use self::virtiofs::create_nydus_daemon;
 #[cfg(feature = "fusedev")]
 mod fusedev;
 #[cfg(feature = "fusedev")]
-use self::fusedev::create_nydus_daemon;
+use self::fusedev::{create_nydus_daemon, FuseFdSource, FuseThreadConfig};
+#[cfg(feature = "fusedev")]
+mod splice;
 
 mod api_server_glue;
+mod blockdev;
 mod daemon;
+#[cfg(feature = "fusedev")]
+mod erofs;
+mod http_fs;
+mod otlp;
+mod profile;
+mod smoke_test;
 mod upgrade;
 
 lazy_static! {
@@ -111,7 +133,93 @@ extern "C" fn sig_exit(_sig: std::os::raw::c_int) {
     }
 }
 
+/// Log access accounting of all filesystem instances every `interval`.
+fn start_accounting_reporter(interval: Duration) -> Result<()> {
+    thread::Builder::new()
+        .name("accounting_reporter".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            match metrics::export_accounting(&None, false) {
+                Ok(r) => info!("access accounting: {}", r),
+                Err(e) => error!("failed to export access accounting, {:?}", e),
+            }
+        })
+        .map(|_| ())
+}
+
+/// Serve the filesystem over read-only HTTP without setting up any fuse/virtiofs frontend.
+fn serve_http_only(addr: &str, mount_cmd: Option<FsBackendMountCmd>) -> Result<()> {
+    let cmd = mount_cmd.ok_or_else(|| {
+        DaemonError::InvalidArguments(
+            "bootstrap or shared-dir must be provided to serve http".to_string(),
+        )
+    })?;
+    let backend = fs_backend_factory(&cmd)?;
+    HttpFsServer::new(addr, Arc::new(backend))?.start()?;
+    info!("http file server running at {}", addr);
+
+    let mut event_manager = EventManager::<Arc<dyn EventSubscriber>>::new().unwrap();
+    let daemon_subscriber = Arc::new(NydusDaemonSubscriber::new()?);
+    *EXIT_EVTFD.lock().unwrap().deref_mut() = Some(daemon_subscriber.get_event_fd()?);
+    event_manager.add_subscriber(daemon_subscriber);
+    nydus_app::signal::register_signal_handler(signal::SIGINT, sig_exit);
+    nydus_app::signal::register_signal_handler(signal::SIGTERM, sig_exit);
+
+    while EVENT_MANAGER_RUN.load(Ordering::Relaxed) {
+        event_manager.run().unwrap();
+    }
+    info!("nydusd quits");
+
+    Ok(())
+}
+
+/// Export the RAFS v6 image as a read-only NBD block device without any fuse/virtiofs frontend.
+fn serve_block_device_only(
+    path: &str,
+    id: Option<&str>,
+    mount_cmd: Option<FsBackendMountCmd>,
+) -> Result<()> {
+    let cmd = mount_cmd
+        .filter(|cmd| cmd.fs_type == FsBackendType::Rafs)
+        .ok_or_else(|| {
+            DaemonError::InvalidArguments(
+                "a single bootstrap must be provided to export block device".to_string(),
+            )
+        })?;
+    let image = BlockImage::new(id.unwrap_or("blockdev"), &cmd.source, &cmd.config)?;
+    let size = image.size();
+
+    let mut event_manager = EventManager::<Arc<dyn EventSubscriber>>::new().unwrap();
+    let daemon_subscriber = Arc::new(NydusDaemonSubscriber::new()?);
+    *EXIT_EVTFD.lock().unwrap().deref_mut() = Some(daemon_subscriber.get_event_fd()?);
+    event_manager.add_subscriber(daemon_subscriber);
+    nydus_app::signal::register_signal_handler(signal::SIGINT, sig_exit);
+    nydus_app::signal::register_signal_handler(signal::SIGTERM, sig_exit);
+
+    let server = NbdServer::new(path, Arc::new(image))?;
+    let handle = server.start()?;
+    info!("block device {} exported with {} bytes", path, size);
+
+    while EVENT_MANAGER_RUN.load(Ordering::Relaxed) {
+        event_manager.run().unwrap();
+    }
+    if let Err(e) = server.stop() {
+        warn!("failed to disconnect block device {}, {}", path, e);
+    }
+    if let Ok(Err(e)) = handle.join() {
+        warn!("block device {} exited with error, {}", path, e);
+    }
+    info!("nydusd quits");
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    // Panics of fuse service threads are caught and recovered by the fusedev daemon.
+    #[cfg(feature = "fusedev")]
+    install_panic_hook(&[fusedev::FUSE_SERVER_THREAD_NAME]);
+    #[cfg(not(feature = "fusedev"))]
+    install_panic_hook(&[]);
     let (bti_string, bti) = BuildTimeInfo::dump(crate_version!());
 
     let cmd_arguments = App::new("")
@@ -222,8 +330,10 @@ fn main() -> Result<()> {
             Arg::with_name("bootstrap")
                 .long("bootstrap")
                 .short("B")
-                .help("Rafs filesystem bootstrap/metadata file")
+                .help("Rafs filesystem bootstrap/metadata file, may be repeated to overlay bootstraps from lower to upper")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .conflicts_with("shared-dir")
         )
         .arg(
@@ -234,6 +344,113 @@ fn main() -> Result<()> {
                 .takes_value(true)
                 .conflicts_with("bootstrap"),
         )
+        .arg(
+            Arg::with_name("serve-http")
+                .long("serve-http")
+                .help("Expose the filesystem over read-only HTTP at the address for debugging, e.g. 127.0.0.1:8080")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("block-device")
+                .long("block-device")
+                .help("Export the RAFS v6 image as a read-only block device through the NBD device, e.g. /dev/nbd0")
+                .takes_value(true)
+                .required(false)
+                .requires("bootstrap")
+                .conflicts_with("serve-http"),
+        )
+        .arg(
+            Arg::with_name("smoke-test")
+                .long("smoke-test")
+                .help("Mount a built-in tiny image, verify lookups and reads through the filesystem stack, report the result and exit")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("validate-config")
+                .long("validate-config")
+                .help("Validate the configuration file specified by --config, report the first error found and exit")
+                .takes_value(false)
+                .requires("config"),
+        )
+        .arg(
+            Arg::with_name("accounting-interval")
+                .long("accounting-interval")
+                .help("Interval in seconds to log access accounting of all mountpoints, 0 to disable")
+                .default_value("0")
+                .takes_value(true)
+                .required(false)
+                .global(true)
+                .validator(|v| {
+                    v.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "Input accounting interval is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("decompress-threads")
+                .long("decompress-threads")
+                .help("Number of threads shared by all mountpoints to decompress chunks, 0 to decompress inline")
+                .default_value("0")
+                .takes_value(true)
+                .required(false)
+                .global(true)
+                .validator(|v| {
+                    v.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|_| "Input decompress threads is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("buffer-pool-size")
+                .long("buffer-pool-size")
+                .help("Size limit in MiB of data buffers cached for reuse by read requests of all mountpoints, 0 to disable")
+                .default_value("0")
+                .takes_value(true)
+                .required(false)
+                .global(true)
+                .validator(|v| {
+                    v.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|_| "Input buffer pool size is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("otlp-endpoint")
+                .long("otlp-endpoint")
+                .help("OTLP/HTTP endpoint to push metrics to, e.g. http://localhost:4318/v1/metrics")
+                .takes_value(true)
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("otlp-interval")
+                .long("otlp-interval")
+                .help("Interval in seconds to push metrics to the OTLP endpoint")
+                .default_value("60")
+                .takes_value(true)
+                .required(false)
+                .global(true)
+                .validator(|v| match v.parse::<u64>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("Input OTLP interval is not legal".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("read-trace-spans")
+                .long("read-trace-spans")
+                .help("Trace the read path and keep the latest N spans for exporting, 0 to disable")
+                .default_value("0")
+                .takes_value(true)
+                .required(false)
+                .global(true)
+                .validator(|v| {
+                    v.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|_| "Input read trace spans is not legal".to_string())
+                }),
+        )
         .arg(
             Arg::with_name("hybrid-mode").long("hybrid-mode")
             .help("run nydusd in rafs and passthroughfs hybrid mode")
@@ -250,7 +467,20 @@ fn main() -> Result<()> {
                 .short("M")
                 .help("Fuse mount point")
                 .takes_value(true)
-                .required(true),
+                .required_unless_one(&[
+                    "serve-http",
+                    "block-device",
+                    "singleton",
+                    "smoke-test",
+                    "validate-config",
+                ]),
+        )
+        .arg(
+            Arg::with_name("singleton")
+                .long("singleton")
+                .help("Create and manage multiple fuse mountpoints through the admin API")
+                .takes_value(false)
+                .requires("apisock"),
         )
         .arg(
             Arg::with_name("threads")
@@ -274,11 +504,73 @@ fn main() -> Result<()> {
                     }
                 }),
         )
+        .arg(
+            Arg::with_name("restart-limit")
+                .long("fuse-restart-limit")
+                .default_value("3")
+                .help("Max times to re-spawn dead fuse service threads before giving up")
+                .takes_value(true)
+                .required(false)
+                .validator(|v| {
+                    v.parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| "Input restart limit is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("fuse-clone-fd")
+                .long("fuse-clone-fd")
+                .help("Read fuse requests through a cloned /dev/fuse fd per fuse service thread, instead of a shared one")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("fuse-cpu-affinity")
+                .long("fuse-cpu-affinity")
+                .help("CPUs to pin fuse service threads to in a round-robin way, e.g. \"0-3,8\"")
+                .takes_value(true)
+                .required(false)
+                .validator(|v| {
+                    FuseThreadConfig::parse_cpus(&v)
+                        .map(|_| ())
+                        .map_err(|_| "Input cpu list is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("fuse-fd")
+                .long("fuse-fd")
+                .help("Inherited fd of opened /dev/fuse, or of a unix domain socket to receive it from, for unprivileged mounting")
+                .takes_value(true)
+                .required(false)
+                .conflicts_with("fuse-fd-socket")
+                .validator(|v| {
+                    v.parse::<RawFd>()
+                        .ok()
+                        .filter(|fd| *fd >= 0)
+                        .map(|_| ())
+                        .ok_or_else(|| "Input fuse fd is not legal".to_string())
+                }),
+        )
+        .arg(
+            Arg::with_name("fuse-fd-socket")
+                .long("fuse-fd-socket")
+                .help("Unix domain socket to receive opened /dev/fuse fd from, for unprivileged mounting")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("writable")
                 .long("writable")
                 .help("set fuse mountpoint non-readonly")
                 .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("erofs-mount")
+                .long("erofs-mount")
+                .help("Mount the RAFS v6 image by in-kernel EROFS instead of fuse if the kernel supports it and all blobs are local and uncompressed, fall back to fuse otherwise")
+                .takes_value(false)
+                .requires("bootstrap")
+                .conflicts_with_all(&["fuse-fd", "fuse-fd-socket", "singleton", "writable"]),
         );
 
     #[cfg(feature = "virtiofs")]
@@ -287,7 +579,12 @@ fn main() -> Result<()> {
             .long("sock")
             .help("Vhost-user API socket")
             .takes_value(true)
-            .required(true),
+            .required_unless_one(&[
+                "serve-http",
+                "block-device",
+                "smoke-test",
+                "validate-config",
+            ]),
     );
 
     let cmd_arguments_parsed = cmd_arguments.get_matches();
@@ -303,11 +600,37 @@ fn main() -> Result<()> {
 
     dump_program_info(crate_version!());
 
+    if cmd_arguments_parsed.is_present("smoke-test") {
+        let report = run_smoke_test()?;
+        report.write_to(&mut io::stdout())?;
+        process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    if cmd_arguments_parsed.is_present("validate-config") {
+        // Safe to unwrap because `validate-config` requires `config`.
+        let path = cmd_arguments_parsed.value_of("config").unwrap();
+        let result = std::fs::read_to_string(path)
+            .map_err(|e| DaemonError::InvalidConfig(format!("failed to read {}, {}", path, e)))
+            .and_then(|config| validate_config(&config));
+        match result {
+            Ok(()) => {
+                println!("configuration file {} is valid", path);
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("configuration file {} is invalid: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
     // Retrieve arguments
     // shared-dir means fs passthrough
     let shared_dir = cmd_arguments_parsed.value_of("shared-dir");
     // bootstrap means rafs only
-    let bootstrap = cmd_arguments_parsed.value_of("bootstrap");
+    let bootstrap: Option<Vec<&str>> = cmd_arguments_parsed
+        .values_of("bootstrap")
+        .map(|b| b.collect());
     // safe as virtual_mountpoint default to "/"
     let virtual_mnt = cmd_arguments_parsed.value_of("virtual-mountpoint").unwrap();
     // apisock means admin api socket support
@@ -318,6 +641,11 @@ fn main() -> Result<()> {
         .map(|n| n.parse().unwrap_or(rlimit_nofile_default))
         .unwrap_or(rlimit_nofile_default);
 
+    let config = match cmd_arguments_parsed.value_of("config") {
+        Some(path) => Some(std::fs::read_to_string(path)?),
+        None => None,
+    };
+
     let mut opts = VfsOptions::default();
     let mount_cmd = if let Some(shared_dir) = shared_dir {
         if rlimit_nofile != 0 {
@@ -342,7 +670,7 @@ fn main() -> Result<()> {
 
         Some(cmd)
     } else if let Some(b) = bootstrap {
-        let config = cmd_arguments_parsed.value_of("config").ok_or_else(|| {
+        let config = config.clone().ok_or_else(|| {
             DaemonError::InvalidArguments("config file is not provided".to_string())
         })?;
 
@@ -350,10 +678,17 @@ fn main() -> Result<()> {
             .values_of("prefetch-files")
             .map(|files| files.map(|s| s.to_string()).collect());
 
+        // Multiple bootstraps are overlaid at runtime.
+        let (fs_type, source) = if b.len() > 1 {
+            let source = serde_json::to_string(&b).map_err(DaemonError::Serde)?;
+            (FsBackendType::RafsOverlay, source)
+        } else {
+            (FsBackendType::Rafs, b[0].to_string())
+        };
         let cmd = FsBackendMountCmd {
-            fs_type: FsBackendType::Rafs,
-            source: b.to_string(),
-            config: std::fs::read_to_string(config)?,
+            fs_type,
+            source,
+            config,
             mountpoint: virtual_mnt.to_string(),
             prefetch_files,
         };
@@ -371,6 +706,56 @@ fn main() -> Result<()> {
         opts.no_open = false;
         opts.killpriv_v2 = true;
     }
+    if let Some(config) = config.as_ref() {
+        FuseInitConfig::from_config(config)?.apply(&mut opts);
+    }
+
+    // Validated by clap, so it's safe to unwrap.
+    let accounting_interval: u64 = cmd_arguments_parsed
+        .value_of("accounting-interval")
+        .map(|n| n.parse().unwrap())
+        .unwrap_or(0);
+    if accounting_interval > 0 {
+        start_accounting_reporter(Duration::from_secs(accounting_interval))?;
+    }
+    let decompress_threads: usize = cmd_arguments_parsed
+        .value_of("decompress-threads")
+        .map(|n| n.parse().unwrap())
+        .unwrap_or(0);
+    if decompress_threads > 0 {
+        storage::cache::start_decompress_pool(decompress_threads)?;
+    }
+    let buffer_pool_size: usize = cmd_arguments_parsed
+        .value_of("buffer-pool-size")
+        .map(|n| n.parse().unwrap())
+        .unwrap_or(0);
+    if buffer_pool_size > 0 {
+        storage::cache::start_buffer_pool(buffer_pool_size << 20)?;
+    }
+    let read_trace_spans: usize = cmd_arguments_parsed
+        .value_of("read-trace-spans")
+        .map(|n| n.parse().unwrap())
+        .unwrap_or(0);
+    if read_trace_spans > 0 {
+        trace::read_tracer().start(read_trace_spans);
+    }
+
+    if let Some(path) = cmd_arguments_parsed.value_of("block-device") {
+        let id = cmd_arguments_parsed.value_of("id");
+        return serve_block_device_only(path, id, mount_cmd);
+    }
+
+    let serve_http = cmd_arguments_parsed.value_of("serve-http");
+    #[cfg(feature = "fusedev")]
+    let has_frontend = cmd_arguments_parsed.is_present("mountpoint")
+        || cmd_arguments_parsed.is_present("singleton");
+    #[cfg(feature = "virtiofs")]
+    let has_frontend = cmd_arguments_parsed.is_present("sock");
+    if let Some(addr) = serve_http {
+        if !has_frontend {
+            return serve_http_only(addr, mount_cmd);
+        }
+    }
 
     let vfs = Vfs::new(opts);
 
@@ -402,6 +787,19 @@ fn main() -> Result<()> {
             .value_of("threads")
             .map(|n| n.parse().unwrap_or(1))
             .unwrap_or(1);
+        // Validated by clap, so it's safe to unwrap.
+        let restart_limit: u32 = cmd_arguments_parsed
+            .value_of("restart-limit")
+            .map(|n| n.parse().unwrap())
+            .unwrap_or(3);
+        let thread_config = FuseThreadConfig {
+            count: threads,
+            clone_fd: cmd_arguments_parsed.is_present("fuse-clone-fd"),
+            cpus: cmd_arguments_parsed
+                .value_of("fuse-cpu-affinity")
+                .map(|v| FuseThreadConfig::parse_cpus(v).unwrap())
+                .unwrap_or_default(),
+        };
 
         let p = cmd_arguments_parsed
             .value_of("failover-policy")
@@ -412,22 +810,39 @@ fn main() -> Result<()> {
                 e
             })?;
 
-        // mountpoint means fuse device only
-        let mountpoint = cmd_arguments_parsed.value_of("mountpoint").ok_or_else(|| {
-            DaemonError::InvalidArguments("Mountpoint must be provided!".to_string())
-        })?;
+        // Validated by clap, so it's safe to unwrap.
+        let fuse_fd = if let Some(fd) = cmd_arguments_parsed.value_of("fuse-fd") {
+            Some(FuseFdSource::Fd(fd.parse().unwrap()))
+        } else {
+            cmd_arguments_parsed
+                .value_of("fuse-fd-socket")
+                .map(|p| FuseFdSource::Socket(PathBuf::from(p)))
+        };
+
+        // mountpoint means fuse device only, which is optional in singleton mode
+        let mountpoint = cmd_arguments_parsed.value_of("mountpoint");
+        let singleton = cmd_arguments_parsed.is_present("singleton");
+        if mountpoint.is_none() && (!singleton || mount_cmd.is_some()) {
+            return Err(
+                DaemonError::InvalidArguments("Mountpoint must be provided!".to_string()).into(),
+            );
+        }
 
         create_nydus_daemon(
             mountpoint,
             vfs,
             supervisor,
             daemon_id,
-            threads,
+            thread_config,
+            restart_limit,
             apisock,
             cmd_arguments_parsed.is_present("upgrade"),
             !cmd_arguments_parsed.is_present("writable"),
+            fuse_fd,
             p,
             mount_cmd,
+            singleton,
+            cmd_arguments_parsed.is_present("erofs-mount"),
             bti,
         )
         .map(|d| {
@@ -436,6 +851,25 @@ fn main() -> Result<()> {
         })?
     };
 
+    if let Some(endpoint) = cmd_arguments_parsed.value_of("otlp-endpoint") {
+        // Validated by clap, so it's safe to unwrap.
+        let interval: u64 = cmd_arguments_parsed
+            .value_of("otlp-interval")
+            .map(|n| n.parse().unwrap())
+            .unwrap_or(60);
+        OtlpExporter::new(endpoint, Duration::from_secs(interval))?.start(daemon.clone())?;
+    }
+
+    if let Some(addr) = serve_http {
+        match daemon.backend_from_mountpoint(virtual_mnt)? {
+            Some(fs) => {
+                HttpFsServer::new(addr, fs)?.start()?;
+                info!("http file server running at {}", addr);
+            }
+            None => warn!("no filesystem mounted at {}, skip serving http", virtual_mnt),
+        }
+    }
+
     let mut http_thread: Option<thread::JoinHandle<Result<()>>> = None;
     let http_exit_evtfd = EventFd::new(0).unwrap();
     if let Some(apisock) = apisock {
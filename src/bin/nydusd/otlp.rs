@@ -0,0 +1,100 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Push metrics to an OpenTelemetry collector by OTLP/HTTP.
+//!
+//! Some environments can't scrape the metrics API of nydusd, so the same counters and histograms
+//! are pushed to an OTLP/HTTP endpoint periodically in JSON encoding, with resource attributes to
+//! tell nodes, daemons and images apart.
+
+use std::io::Result;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use nix::unistd::gethostname;
+use nydus_utils::metrics::{self, OtlpResource};
+
+use crate::daemon::NydusDaemon;
+
+const OTLP_TIMEOUT_SECS: u64 = 10;
+
+pub struct OtlpExporter {
+    endpoint: String,
+    interval: Duration,
+    client: reqwest::blocking::Client,
+    // Start time of cumulative counters.
+    start: SystemTime,
+}
+
+impl OtlpExporter {
+    /// Create an exporter pushing metrics to `endpoint`, e.g. `http://localhost:4318/v1/metrics`,
+    /// every `interval`.
+    pub fn new(endpoint: &str, interval: Duration) -> Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(OTLP_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| eother!(format!("failed to create http client for otlp, {}", e)))?;
+
+        Ok(OtlpExporter {
+            endpoint: endpoint.to_string(),
+            interval,
+            client,
+            start: SystemTime::now(),
+        })
+    }
+
+    fn resource(daemon: &dyn NydusDaemon) -> OtlpResource {
+        let mut resource = OtlpResource::default();
+        resource.add_str("service.name", "nydusd");
+        let mut buf = [0u8; 256];
+        if let Ok(host) = gethostname(&mut buf) {
+            resource.add_str("host.name", &host.to_string_lossy());
+        }
+        let id = daemon
+            .id()
+            .unwrap_or_else(|| format!("nydusd-{}", process::id()));
+        resource.add_str("service.instance.id", &id);
+        resource.add_str_array("nydus.image.ids", &daemon.image_ids());
+
+        resource
+    }
+
+    fn push(&self, daemon: &dyn NydusDaemon) -> Result<()> {
+        let body = metrics::export_otlp_metrics(&Self::resource(daemon), self.start)
+            .map_err(|e| eother!(format!("failed to export metrics, {:?}", e)))?;
+        let resp = self
+            .client
+            .post(&self.endpoint)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .map_err(|e| eio!(format!("failed to send metrics, {}", e)))?;
+        if !resp.status().is_success() {
+            return Err(eio!(format!("collector responds {}", resp.status())));
+        }
+
+        Ok(())
+    }
+
+    /// Start a thread pushing metrics periodically, failures are logged and retried on the next
+    /// interval.
+    pub fn start(self, daemon: Arc<dyn NydusDaemon + Send + Sync>) -> Result<()> {
+        info!(
+            "pushing metrics to {} every {}s",
+            self.endpoint,
+            self.interval.as_secs()
+        );
+        thread::Builder::new()
+            .name("otlp_exporter".to_string())
+            .spawn(move || loop {
+                thread::sleep(self.interval);
+                if let Err(e) = self.push(daemon.as_ref()) {
+                    warn!("failed to push metrics to {}, {}", self.endpoint, e);
+                }
+            })
+            .map(|_| ())
+    }
+}
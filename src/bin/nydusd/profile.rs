@@ -0,0 +1,299 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Self-profiling of the nydusd process, based on procfs.
+//!
+//! A snapshot report contains CPU time consumed by each thread and a breakdown of resident memory
+//! into RAFS metadata mappings, blob cache mappings, anonymous memory and other file mappings.
+//!
+//! An on-demand sampling profile periodically samples the state of all threads in the background
+//! and writes the number of samples per thread and state into a file, in the folded format
+//! accepted by flame graph tools, e.g. `fuse_server;running 1024`. It shows which threads are
+//! busy and whether they are running or blocked, no call stacks are sampled.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Upper limit of sampling duration.
+pub const MAX_SAMPLING_DURATION: Duration = Duration::from_secs(300);
+/// Upper limit of sampling frequency in Hz.
+pub const MAX_SAMPLING_FREQUENCY: u32 = 1000;
+
+// Only one sampling profile may be in progress at any time.
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+
+/// CPU time consumed by a thread since it was created.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ThreadCpuTime {
+    pub tid: u32,
+    pub name: String,
+    /// Scheduling state, e.g. `R` for running and `D` for uninterruptible sleep.
+    pub state: String,
+    pub user_ms: u64,
+    pub system_ms: u64,
+}
+
+/// Resident memory of the process, in KiB.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub rss_kb: u64,
+    /// Mappings of RAFS bootstrap files.
+    pub metadata_kb: u64,
+    /// Mappings of files in blob cache directories, e.g. chunk maps.
+    pub cache_kb: u64,
+    /// Anonymous memory, including heap and stacks, where data buffers are allocated.
+    pub anonymous_kb: u64,
+    /// Mappings of other files, e.g. the executable and shared libraries.
+    pub file_kb: u64,
+}
+
+#[derive(Serialize)]
+pub struct ProfileReport {
+    pub threads: Vec<ThreadCpuTime>,
+    pub memory: MemoryUsage,
+    /// Whether a sampling profile is in progress.
+    pub sampling: bool,
+}
+
+/// Collect a snapshot report, with mappings of `metadata_files` and of files under `cache_dirs`
+/// accounted separately.
+pub fn collect_report(metadata_files: &[PathBuf], cache_dirs: &[PathBuf]) -> Result<ProfileReport> {
+    let smaps = fs::read_to_string("/proc/self/smaps")?;
+
+    Ok(ProfileReport {
+        threads: thread_cpu_times()?,
+        memory: parse_smaps(&smaps, metadata_files, cache_dirs),
+        sampling: SAMPLING.load(Ordering::Acquire),
+    })
+}
+
+/// Start sampling threads of the process at `frequency` Hz for `duration` in the background, and
+/// write the profile to `path` when done.
+pub fn start_sampling(path: PathBuf, duration: Duration, frequency: u32) -> Result<()> {
+    if duration.as_secs() == 0 || duration > MAX_SAMPLING_DURATION {
+        return Err(einval!(format!(
+            "sampling duration should be in range [1, {}] seconds",
+            MAX_SAMPLING_DURATION.as_secs()
+        )));
+    }
+    if frequency == 0 || frequency > MAX_SAMPLING_FREQUENCY {
+        return Err(einval!(format!(
+            "sampling frequency should be in range [1, {}]",
+            MAX_SAMPLING_FREQUENCY
+        )));
+    }
+    if SAMPLING
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Err(eother!("another sampling profile is in progress"));
+    }
+    // Fail early if the profile can't be written.
+    let file = match File::create(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            SAMPLING.store(false, Ordering::Release);
+            return Err(e);
+        }
+    };
+
+    let result = thread::Builder::new()
+        .name("profiler".to_string())
+        .spawn(move || {
+            let samples = sample_threads(duration, frequency);
+            if let Err(e) = write_samples(file, &samples) {
+                error!("failed to write sampling profile to {:?}: {}", path, e);
+            } else {
+                info!("sampling profile is written to {:?}", path);
+            }
+            SAMPLING.store(false, Ordering::Release);
+        });
+    if let Err(e) = result {
+        SAMPLING.store(false, Ordering::Release);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn sample_threads(duration: Duration, frequency: u32) -> BTreeMap<String, u64> {
+    let interval = Duration::from_secs(1) / frequency;
+    let end = Instant::now() + duration;
+    let mut samples = BTreeMap::new();
+
+    while Instant::now() < end {
+        let threads = thread_cpu_times().unwrap_or_else(|e| {
+            warn!("failed to sample threads: {}", e);
+            Vec::new()
+        });
+        for t in threads {
+            let state = match t.state.as_str() {
+                "R" => "running",
+                "D" => "blocked_io",
+                _ => "sleeping",
+            };
+            *samples.entry(format!("{};{}", t.name, state)).or_insert(0) += 1;
+        }
+        thread::sleep(interval);
+    }
+
+    samples
+}
+
+fn write_samples(mut file: File, samples: &BTreeMap<String, u64>) -> Result<()> {
+    for (key, count) in samples.iter() {
+        writeln!(file, "{} {}", key, count)?;
+    }
+    file.sync_all()
+}
+
+fn thread_cpu_times() -> Result<Vec<ThreadCpuTime>> {
+    // Safe because sysconf() has no side effect.
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks = if ticks > 0 { ticks as u64 } else { 100 };
+    let mut threads = Vec::new();
+
+    for entry in fs::read_dir("/proc/self/task")? {
+        let entry = entry?;
+        let tid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(tid) => tid,
+            None => continue,
+        };
+        // The thread may have exited.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        if let Some(t) = parse_thread_stat(tid, &stat, ticks) {
+            threads.push(t);
+        }
+    }
+    threads.sort_by_key(|t| t.tid);
+
+    Ok(threads)
+}
+
+// Parse `/proc/<pid>/task/<tid>/stat`, the thread name is enclosed in parentheses and may contain
+// spaces, so fields are located from the last ')'.
+fn parse_thread_stat(tid: u32, stat: &str, ticks: u64) -> Option<ThreadCpuTime> {
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat.get(start + 1..end)?.to_string();
+    let fields: Vec<&str> = stat.get(end + 1..)?.split_whitespace().collect();
+    // Fields after the name start from the 3rd one, `state`, utime and stime are the 14th and
+    // 15th fields.
+    let state = fields.first()?.to_string();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+
+    Some(ThreadCpuTime {
+        tid,
+        name,
+        state,
+        user_ms: utime * 1000 / ticks,
+        system_ms: stime * 1000 / ticks,
+    })
+}
+
+fn parse_smaps(smaps: &str, metadata_files: &[PathBuf], cache_dirs: &[PathBuf]) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    let mut path: Option<&str> = None;
+
+    for line in smaps.lines() {
+        let mut fields = line.split_whitespace();
+        let first = match fields.next() {
+            Some(f) => f,
+            None => continue,
+        };
+        if !first.ends_with(':') {
+            // Mapping header: `address perms offset dev inode [pathname]`.
+            path = fields.nth(4);
+            continue;
+        }
+        if first != "Rss:" {
+            continue;
+        }
+
+        let rss: u64 = fields.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+        usage.rss_kb += rss;
+        match path {
+            Some(p) if p.starts_with('/') => {
+                let p = Path::new(p);
+                if metadata_files.iter().any(|f| f == p) {
+                    usage.metadata_kb += rss;
+                } else if cache_dirs.iter().any(|d| p.starts_with(d)) {
+                    usage.cache_kb += rss;
+                } else {
+                    usage.file_kb += rss;
+                }
+            }
+            // Anonymous mappings, or special ones like `[heap]` and `[stack]`.
+            _ => usage.anonymous_kb += rss,
+        }
+    }
+
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_thread_stat() {
+        let stat = "1234 (fuse server) S 1 1234 1234 0 -1 4194624 90 0 0 0 250 30 0 0 20 0 8 0";
+        let t = parse_thread_stat(1234, stat, 100).unwrap();
+        assert_eq!(
+            t,
+            ThreadCpuTime {
+                tid: 1234,
+                name: "fuse server".to_string(),
+                state: "S".to_string(),
+                user_ms: 2500,
+                system_ms: 300,
+            }
+        );
+        assert!(parse_thread_stat(1234, "1234 (fuse", 100).is_none());
+    }
+
+    #[test]
+    fn test_parse_smaps() {
+        let smaps = "\
+7f0000000000-7f0000100000 r--s 00000000 08:01 100 /var/lib/nydus/bootstrap
+Size:               1024 kB
+Rss:                 400 kB
+7f0000100000-7f0000200000 rw-s 00000000 08:01 101 /var/cache/nydus/blob1.chunk_map
+Rss:                  40 kB
+VmFlags: rd sh mr mw me ms sd
+7f0000200000-7f0000300000 rw-p 00000000 00:00 0
+Rss:                1000 kB
+55d000000000-55d000100000 rw-p 00000000 00:00 0 [heap]
+Rss:                 200 kB
+55c000000000-55c000100000 r-xp 00000000 08:01 102 /usr/bin/nydusd
+Rss:                 300 kB
+";
+        let usage = parse_smaps(
+            smaps,
+            &[PathBuf::from("/var/lib/nydus/bootstrap")],
+            &[PathBuf::from("/var/cache/nydus")],
+        );
+        assert_eq!(
+            usage,
+            MemoryUsage {
+                rss_kb: 1940,
+                metadata_kb: 400,
+                cache_kb: 40,
+                anonymous_kb: 1200,
+                file_kb: 300,
+            }
+        );
+    }
+}
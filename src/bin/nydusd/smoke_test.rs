@@ -0,0 +1,380 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Self test of the filesystem stack, as a health check after packaging and deployment.
+//!
+//! A tiny RAFS image is built from file contents embedded in nydusd into a temporary directory,
+//! and mounted by the same code path as the fuse and virtiofs daemons, with the localfs storage
+//! backend and the blob cache. A scripted set of lookups and reads is then issued to the `Vfs`
+//! without any fuse/virtiofs frontend, so no privilege is needed, and the results are verified
+//! against the embedded contents.
+
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Result, Write};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use fuse_backend_rs::api::filesystem::{Context, Entry, FileSystem};
+use fuse_backend_rs::api::{Vfs, VfsOptions};
+use nydus::builder::ImageBuilder;
+use nydus::FsBackendType;
+use vmm_sys_util::tempdir::TempDir;
+
+use crate::daemon::{fs_backend_factory, FsBackendMountCmd};
+use crate::http_fs::DataBuffer;
+
+const ROOT_INODE: u64 = 1;
+const READ_BUF_SIZE: u32 = 0x2_0000;
+const READDIR_BUF_SIZE: u32 = 0x1000;
+
+const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+tempor incididunt ut labore et dolore magna aliqua.\n";
+
+enum Content {
+    Dir,
+    File(Vec<u8>),
+    Symlink(&'static str),
+}
+
+impl Content {
+    fn file_type(&self) -> u32 {
+        match self {
+            Content::Dir => libc::S_IFDIR,
+            Content::File(_) => libc::S_IFREG,
+            Content::Symlink(_) => libc::S_IFLNK,
+        }
+    }
+}
+
+// Files of the built-in image, parent directories come before their children.
+fn image_files() -> Vec<(&'static str, Content)> {
+    // Spans multiple chunks of the default chunk size, with a partial chunk at the end.
+    let data = (0..0x28_0123u32).map(|v| (v % 251) as u8).collect();
+
+    vec![
+        ("hello.txt", Content::File(b"hello, nydus\n".to_vec())),
+        ("empty", Content::File(Vec::new())),
+        ("dir", Content::Dir),
+        ("dir/lorem.txt", Content::File(LOREM.as_bytes().to_vec())),
+        ("dir/sub", Content::Dir),
+        ("dir/sub/data.bin", Content::File(data)),
+        ("link", Content::Symlink("dir/lorem.txt")),
+    ]
+}
+
+struct StepResult {
+    name: String,
+    elapsed: Duration,
+    error: Option<String>,
+}
+
+/// Result and timing of each step of the self test.
+#[derive(Default)]
+pub struct SmokeTestReport {
+    steps: Vec<StepResult>,
+}
+
+impl SmokeTestReport {
+    /// Check whether all steps have passed.
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.error.is_none())
+    }
+
+    /// Write the report in human readable form.
+    pub fn write_to(&self, w: &mut dyn Write) -> Result<()> {
+        let mut total = Duration::default();
+        for s in self.steps.iter() {
+            let millis = s.elapsed.as_secs_f64() * 1000.0;
+            match s.error.as_ref() {
+                None => writeln!(w, "PASS {:<32} {:>10.3}ms", s.name, millis)?,
+                Some(e) => writeln!(w, "FAIL {:<32} {:>10.3}ms  {}", s.name, millis, e)?,
+            }
+            total += s.elapsed;
+        }
+        let passed = self.steps.iter().filter(|s| s.error.is_none()).count();
+        writeln!(
+            w,
+            "smoke test {}: {} of {} steps passed in {:.3}ms",
+            if self.passed() { "passed" } else { "failed" },
+            passed,
+            self.steps.len(),
+            total.as_secs_f64() * 1000.0
+        )
+    }
+
+    // Run a step and record its result, return None if the step fails.
+    fn step<T, F>(&mut self, name: &str, f: F) -> Option<T>
+    where
+        F: FnOnce() -> std::result::Result<T, String>,
+    {
+        let begin = Instant::now();
+        let result = f();
+        let elapsed = begin.elapsed();
+        let (value, error) = match result {
+            Ok(v) => (Some(v), None),
+            Err(e) => (None, Some(e)),
+        };
+        self.steps.push(StepResult {
+            name: name.to_string(),
+            elapsed,
+            error,
+        });
+
+        value
+    }
+}
+
+/// Build and mount the built-in image, verify it through the filesystem stack and report.
+pub fn run_smoke_test() -> Result<SmokeTestReport> {
+    let work_dir = TempDir::new_with_prefix(std::env::temp_dir().join("nydusd-smoke-"))
+        .map_err(|e| io::Error::from_raw_os_error(e.errno()))?;
+    let dir = work_dir.as_path();
+    let files = image_files();
+    let mut report = SmokeTestReport::default();
+
+    if report
+        .step("build image", || build_image(dir, &files))
+        .is_none()
+    {
+        return Ok(report);
+    }
+    let vfs = match report.step("mount image", || mount_image(dir)) {
+        Some(vfs) => vfs,
+        None => return Ok(report),
+    };
+    let ctx = Context {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+
+    let mut dirs = vec![(String::new(), ROOT_INODE)];
+    for (path, content) in files.iter() {
+        let ino = report.step(&format!("lookup /{}", path), || {
+            let entry = lookup(&vfs, &ctx, path).map_err(|e| e.to_string())?;
+            let file_type = entry.attr.st_mode & libc::S_IFMT;
+            if file_type != content.file_type() {
+                return Err(format!("unexpected file type {:o}", file_type));
+            }
+            let size = match content {
+                Content::Dir => None,
+                Content::File(data) => Some(data.len()),
+                Content::Symlink(target) => Some(target.len()),
+            };
+            match size {
+                Some(size) if entry.attr.st_size as u64 != size as u64 => Err(format!(
+                    "file size is {}, expect {}",
+                    entry.attr.st_size, size
+                )),
+                _ => Ok(entry.inode),
+            }
+        });
+        let ino = match ino {
+            Some(ino) => ino,
+            None => continue,
+        };
+
+        match content {
+            Content::Dir => dirs.push((path.to_string(), ino)),
+            Content::File(data) => {
+                report.step(&format!("read /{}", path), || {
+                    let buf = read_file(&vfs, &ctx, ino, data.len()).map_err(|e| e.to_string())?;
+                    if &buf != data {
+                        Err("file content mismatch".to_string())
+                    } else {
+                        Ok(())
+                    }
+                });
+            }
+            Content::Symlink(target) => {
+                report.step(&format!("readlink /{}", path), || {
+                    let buf = vfs.readlink(&ctx, ino).map_err(|e| e.to_string())?;
+                    if buf != target.as_bytes() {
+                        Err(format!("link target is {}", String::from_utf8_lossy(&buf)))
+                    } else {
+                        Ok(())
+                    }
+                });
+            }
+        }
+    }
+
+    for (path, ino) in dirs.iter() {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        let mut expected: Vec<&str> = files
+            .iter()
+            .filter_map(|(p, _)| p.strip_prefix(prefix.as_str()))
+            .filter(|p| !p.contains('/'))
+            .collect();
+        expected.sort_unstable();
+        report.step(&format!("readdir /{}", path), || {
+            let names = read_dir(&vfs, &ctx, *ino).map_err(|e| e.to_string())?;
+            if names != expected {
+                Err(format!("directory entries are {:?}", names))
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    report.step("lookup nonexistent file", || {
+        match lookup(&vfs, &ctx, "dir/nonexistent") {
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            Err(e) => Err(format!("unexpected error {}", e)),
+            Ok(_) => Err("nonexistent file found".to_string()),
+        }
+    });
+    report.step("umount image", || {
+        vfs.umount("/").map_err(|e| format!("{:?}", e))
+    });
+
+    Ok(report)
+}
+
+fn build_image(dir: &Path, files: &[(&str, Content)]) -> std::result::Result<(), String> {
+    let source = dir.join("source");
+    let blob_dir = dir.join("blobs");
+    for path in [&source, &blob_dir].iter() {
+        fs::create_dir(path).map_err(|e| format!("failed to create {:?}: {}", path, e))?;
+    }
+    for (path, content) in files.iter() {
+        let path = source.join(path);
+        match content {
+            Content::Dir => fs::create_dir(&path),
+            Content::File(data) => fs::write(&path, data),
+            Content::Symlink(target) => symlink(target, &path),
+        }
+        .map_err(|e| format!("failed to create {:?}: {}", path, e))?;
+    }
+
+    ImageBuilder::new(&source)
+        .bootstrap(dir.join("bootstrap"))
+        .blob_dir(&blob_dir)
+        .repeatable(true)
+        .build()
+        .map(|_| ())
+        .map_err(|e| format!("{:#}", e))
+}
+
+fn mount_image(dir: &Path) -> std::result::Result<Vfs, String> {
+    let config = serde_json::json!({
+        "device": {
+            "backend": {
+                "type": "localfs",
+                "config": { "dir": dir.join("blobs") }
+            },
+            "cache": {
+                "type": "blobcache",
+                "config": { "work_dir": dir.join("cache") }
+            }
+        },
+        "mode": "direct",
+        "digest_validate": true
+    });
+    let cmd = FsBackendMountCmd {
+        fs_type: FsBackendType::Rafs,
+        source: dir.join("bootstrap").to_string_lossy().to_string(),
+        config: config.to_string(),
+        mountpoint: "/".to_string(),
+        prefetch_files: None,
+    };
+    let fs = fs_backend_factory(&cmd).map_err(|e| e.to_string())?;
+    let vfs = Vfs::new(VfsOptions::default());
+    vfs.mount(fs, "/").map_err(|e| format!("{:?}", e))?;
+
+    Ok(vfs)
+}
+
+fn lookup(vfs: &Vfs, ctx: &Context, path: &str) -> Result<Entry> {
+    let mut ino = ROOT_INODE;
+    let mut entry = None;
+    for name in path.split('/') {
+        let name = CString::new(name).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let e = vfs.lookup(ctx, ino, &name)?;
+        // Negative entries are returned for nonexistent files.
+        if e.inode == 0 {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        ino = e.inode;
+        entry = Some(e);
+    }
+
+    entry.ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+// Read the whole file with size `size`, without opening it as the `Vfs` works in no-open mode.
+fn read_file(vfs: &Vfs, ctx: &Context, ino: u64, size: usize) -> Result<Vec<u8>> {
+    let mut buf = DataBuffer {
+        buf: Vec::with_capacity(size),
+    };
+    while buf.buf.len() < size {
+        let offset = buf.buf.len() as u64;
+        if vfs.read(ctx, ino, 0, &mut buf, READ_BUF_SIZE, offset, None, 0)? == 0 {
+            break;
+        }
+    }
+
+    Ok(buf.buf)
+}
+
+fn read_dir(vfs: &Vfs, ctx: &Context, ino: u64) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut added = 0;
+        vfs.readdir(ctx, ino, 0, READDIR_BUF_SIZE, offset, &mut |entry| {
+            offset = entry.offset;
+            added += 1;
+            if entry.name != b"." && entry.name != b".." {
+                names.push(String::from_utf8_lossy(entry.name).to_string());
+            }
+            Ok(entry.name.len())
+        })?;
+        if added == 0 {
+            break;
+        }
+    }
+    names.sort_unstable();
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoke_test() {
+        let report = run_smoke_test().unwrap();
+        let mut out = Vec::new();
+        report.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(report.passed(), "{}", out);
+        assert!(out.contains("PASS read /dir/sub/data.bin"));
+        assert!(out.contains("smoke test passed"));
+    }
+
+    #[test]
+    fn test_smoke_test_report() {
+        let mut report = SmokeTestReport::default();
+        assert_eq!(report.step("first", || Ok(1)), Some(1));
+        assert_eq!(
+            report.step::<(), _>("second", || Err("bad".to_string())),
+            None
+        );
+        assert!(!report.passed());
+
+        let mut out = Vec::new();
+        report.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("FAIL second"));
+        assert!(out.contains("smoke test failed: 1 of 2 steps passed"));
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reply fuse read requests by splice(2).
+//!
+//! When file data is cached in uncompressed form, the reply is assembled in a pipe by splicing
+//! the fuse reply header and ranges of cache files into it, and then spliced into `/dev/fuse`.
+//! So the data is moved by reference to page cache, instead of being copied from cache files to
+//! user space buffers and then to the fuse device.
+
+use std::fs::File;
+use std::io::{Read, Result, Write};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+
+use fuse_backend_rs::abi::linux_abi::OutHeader;
+use storage::device::BlobCachedExtent;
+
+const PAGE_SIZE: usize = 4096;
+
+/// A pipe to assemble fuse replies.
+pub(crate) struct SplicePipe {
+    rd: File,
+    wr: File,
+    capacity: usize,
+}
+
+impl SplicePipe {
+    pub fn new() -> Result<Self> {
+        let mut fds = [0 as RawFd; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(last_error!("failed to create pipe"));
+        }
+        let rd = unsafe { File::from_raw_fd(fds[0]) };
+        let wr = unsafe { File::from_raw_fd(fds[1]) };
+        let capacity = unsafe { libc::fcntl(wr.as_raw_fd(), libc::F_GETPIPE_SZ) };
+        if capacity < 0 {
+            return Err(last_error!("failed to get pipe size"));
+        }
+
+        Ok(SplicePipe {
+            rd,
+            wr,
+            capacity: capacity as usize,
+        })
+    }
+
+    /// Reply the fuse request `unique` with data from the cache file extents.
+    ///
+    /// Returns `Ok(false)` if the reply can't be assembled in the pipe, e.g. exceeding the max
+    /// pipe size allowed, and the request should be replied through the normal path.
+    pub fn reply(
+        &mut self,
+        fuse_fd: RawFd,
+        unique: u64,
+        extents: &[BlobCachedExtent],
+    ) -> Result<bool> {
+        let data_size = extents.iter().map(|e| e.size).sum::<usize>();
+        let len = size_of::<OutHeader>() + data_size;
+        // Ranges not aligned to page boundary may take an extra pipe buffer each.
+        if !self.reserve(len + (extents.len() + 1) * PAGE_SIZE) {
+            return Ok(false);
+        }
+
+        // Layout of `struct fuse_out_header`.
+        let mut header = Vec::with_capacity(size_of::<OutHeader>());
+        header.extend_from_slice(&(len as u32).to_ne_bytes());
+        header.extend_from_slice(&0i32.to_ne_bytes());
+        header.extend_from_slice(&unique.to_ne_bytes());
+        if let Err(e) = self.fill(&header, extents) {
+            self.drain()?;
+            return Err(e);
+        }
+
+        // The whole reply must be written to `/dev/fuse` at once.
+        let ret = unsafe {
+            libc::splice(
+                self.rd.as_raw_fd(),
+                ptr::null_mut(),
+                fuse_fd,
+                ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE,
+            )
+        };
+        if ret < 0 {
+            let e = last_error!("failed to splice reply to fuse device");
+            self.drain()?;
+            return Err(e);
+        } else if ret as usize != len {
+            self.drain()?;
+            return Err(eio!(format!(
+                "short splice to fuse device, {} of {}",
+                ret, len
+            )));
+        }
+
+        Ok(true)
+    }
+
+    // Enlarge the pipe to hold at least `size` bytes, return false if it's not allowed.
+    fn reserve(&mut self, size: usize) -> bool {
+        if size <= self.capacity {
+            return true;
+        }
+        let ret = unsafe { libc::fcntl(self.wr.as_raw_fd(), libc::F_SETPIPE_SZ, size as i32) };
+        if ret < 0 {
+            debug!(
+                "failed to enlarge pipe to {} bytes, {}",
+                size,
+                std::io::Error::last_os_error()
+            );
+            return false;
+        }
+        self.capacity = ret as usize;
+
+        true
+    }
+
+    fn fill(&mut self, header: &[u8], extents: &[BlobCachedExtent]) -> Result<()> {
+        self.wr.write_all(header)?;
+
+        for extent in extents.iter() {
+            let mut offset = extent.offset as libc::loff_t;
+            let mut remain = extent.size;
+            while remain > 0 {
+                let ret = unsafe {
+                    libc::splice(
+                        extent.fd,
+                        &mut offset,
+                        self.wr.as_raw_fd(),
+                        ptr::null_mut(),
+                        remain,
+                        libc::SPLICE_F_MOVE,
+                    )
+                };
+                if ret < 0 {
+                    return Err(last_error!("failed to splice from cache file"));
+                } else if ret == 0 {
+                    return Err(eio!("unexpected end of cache file"));
+                }
+                remain -= ret as usize;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Discard data left in the pipe by a failed reply.
+    fn drain(&mut self) -> Result<()> {
+        let mut pending = 0 as libc::c_int;
+        if unsafe { libc::ioctl(self.rd.as_raw_fd(), libc::FIONREAD, &mut pending) } < 0 {
+            return Err(last_error!("failed to get pending data of pipe"));
+        }
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        let mut remain = pending as usize;
+        while remain > 0 {
+            let cnt = std::cmp::min(remain, buf.len());
+            self.rd.read_exact(&mut buf[..cnt])?;
+            remain -= cnt;
+        }
+
+        Ok(())
+    }
+}
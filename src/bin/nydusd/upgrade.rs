@@ -1,17 +1,117 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Save and restore nydusd state for live-upgrade and failover.
+//!
+//! Inode numbers presented to fuse are encoded as `vfs_index << 56 | ino`, where `ino` is assigned
+//! by the filesystem from its bootstrap and `vfs_index` is assigned by the Vfs in mount order when
+//! mounting the filesystem. Pseudo directories leading to mountpoints get inode numbers in mount
+//! order too, so the new nydusd process restores filesystems in the order they were mounted. The
+//! upgrade manager also records the vfs index of each mounted filesystem, to detect filesystems
+//! restored with a different index, e.g. when another filesystem was umounted before upgrade.
+
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
-#[cfg(feature = "fusedev")]
+use std::io;
 use std::path::PathBuf;
 
-use crate::daemon::{DaemonResult, FsBackendMountCmd, FsBackendUmountCmd};
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeError;
+
+use crate::daemon::{DaemonError, DaemonResult, FsBackendMountCmd, FsBackendUmountCmd};
 
 #[derive(Debug)]
-pub enum UpgradeMgrError {}
-pub struct UpgradeManager {}
+pub enum UpgradeMgrError {
+    /// Failed to serialize or deserialize the daemon state.
+    Serde(SerdeError),
+    /// Failed to exchange the daemon state with the supervisor.
+    Supervisor(io::Error),
+    /// The fuse session has no `/dev/fuse` fd to save.
+    NoFuseFd,
+    /// The filesystem to update is not mounted.
+    NotMounted(String),
+}
+
+impl From<UpgradeMgrError> for DaemonError {
+    fn from(e: UpgradeMgrError) -> Self {
+        DaemonError::UpgradeManager(e)
+    }
+}
+
+/// State of a mounted filesystem, to restore it with the same vfs index.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MountState {
+    pub cmd: FsBackendMountCmd,
+    pub vfs_index: u8,
+    // Order of the mount operation.
+    seq: u64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct DaemonUpgradeState {
+    mounts: Vec<MountState>,
+}
+
+pub struct UpgradeManager {
+    supervisor: PathBuf,
+    mounts: BTreeMap<String, MountState>,
+    next_seq: u64,
+}
 
-#[cfg(feature = "fusedev")]
 impl UpgradeManager {
-    pub fn new(_: PathBuf) -> Self {
-        UpgradeManager {}
+    #[cfg(feature = "fusedev")]
+    pub fn new(supervisor: PathBuf) -> Self {
+        UpgradeManager {
+            supervisor,
+            mounts: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Get states of mounted filesystems in mount order.
+    pub fn mounts(&self) -> Vec<MountState> {
+        let mut mounts = self.mounts.values().cloned().collect::<Vec<_>>();
+        mounts.sort_by_key(|m| m.seq);
+        mounts
+    }
+
+    fn add_mount(&mut self, cmd: FsBackendMountCmd, vfs_index: u8) {
+        let state = MountState {
+            cmd,
+            vfs_index,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.mounts.insert(state.cmd.mountpoint.clone(), state);
+    }
+
+    fn update_mount(&mut self, cmd: FsBackendMountCmd) -> std::result::Result<(), UpgradeMgrError> {
+        match self.mounts.get_mut(&cmd.mountpoint) {
+            Some(state) => {
+                state.cmd = cmd;
+                Ok(())
+            }
+            None => Err(UpgradeMgrError::NotMounted(cmd.mountpoint)),
+        }
+    }
+
+    fn remove_mount(&mut self, mountpoint: &str) {
+        self.mounts.remove(mountpoint);
+    }
+
+    fn dump(&self) -> std::result::Result<Vec<u8>, UpgradeMgrError> {
+        let state = DaemonUpgradeState {
+            mounts: self.mounts(),
+        };
+        serde_json::to_vec(&state).map_err(UpgradeMgrError::Serde)
+    }
+
+    fn load(data: &[u8]) -> std::result::Result<Vec<MountState>, UpgradeMgrError> {
+        let mut state: DaemonUpgradeState =
+            serde_json::from_slice(data).map_err(UpgradeMgrError::Serde)?;
+        state.mounts.sort_by_key(|m| m.seq);
+        Ok(state.mounts)
     }
 }
 
@@ -34,33 +134,243 @@ impl TryFrom<&str> for FailoverPolicy {
 }
 
 pub fn add_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendMountCmd,
-    _vfs_index: u8,
+    mgr: &mut UpgradeManager,
+    cmd: FsBackendMountCmd,
+    vfs_index: u8,
 ) -> DaemonResult<()> {
+    mgr.add_mount(cmd, vfs_index);
     Ok(())
 }
 
-pub fn update_mounts_state(_mgr: &mut UpgradeManager, _cmd: FsBackendMountCmd) -> DaemonResult<()> {
+pub fn update_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendMountCmd) -> DaemonResult<()> {
+    mgr.update_mount(cmd)?;
     Ok(())
 }
 
-pub fn remove_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendUmountCmd,
-) -> DaemonResult<()> {
+pub fn remove_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendUmountCmd) -> DaemonResult<()> {
+    mgr.remove_mount(&cmd.mountpoint);
     Ok(())
 }
 
 #[cfg(feature = "fusedev")]
 pub mod fusedev_upgrade {
-    use crate::daemon::DaemonResult;
-    use crate::fusedev::FusedevDaemon;
-    pub fn save(_daemon: &FusedevDaemon) -> DaemonResult<()> {
+    use std::fs::File;
+    use std::io::{Read, Result, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::Ordering;
+
+    use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+    use nix::sys::uio::IoVec;
+
+    use super::{UpgradeManager, UpgradeMgrError};
+    use crate::daemon::{DaemonError, DaemonResult, NydusDaemon};
+    use crate::fusedev::{calc_fuse_conn, set_nonblocking, FusedevDaemon};
+
+    // Maximum size of the serialized daemon state received from the supervisor.
+    const MAX_STATE_SIZE: u64 = 16 << 20;
+
+    // Send the `/dev/fuse` fd and the daemon state, which is prefixed by its length.
+    fn send_state(sock: &UnixStream, fd: RawFd, state: &[u8]) -> Result<()> {
+        let len = (state.len() as u64).to_le_bytes();
+        let iov = [IoVec::from_slice(&len)];
+        let fds = [fd];
+        let cmsgs = [ControlMessage::ScmRights(&fds)];
+        let cnt = sendmsg(sock.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None)
+            .map_err(|e| eother!(e))?;
+        if cnt != len.len() {
+            return Err(eio!("short write of daemon state header"));
+        }
+
+        let mut sock = sock;
+        sock.write_all(state)
+    }
+
+    // Receive the `/dev/fuse` fd and the daemon state sent by `send_state()`.
+    fn recv_state(sock: &UnixStream) -> Result<(File, Vec<u8>)> {
+        let mut len = [0u8; 8];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let mut file = None;
+        let cnt = {
+            let iov = [IoVec::from_mut_slice(&mut len)];
+            let msg = recvmsg(
+                sock.as_raw_fd(),
+                &iov,
+                Some(&mut cmsg_buf),
+                MsgFlags::empty(),
+            )
+            .map_err(|e| eother!(e))?;
+            for cmsg in msg.cmsgs() {
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    if let Some(fd) = fds.first() {
+                        // Safe because the fd is received for nydusd to use exclusively.
+                        file = Some(unsafe { File::from_raw_fd(*fd) });
+                    }
+                }
+            }
+            msg.bytes
+        };
+        let file = file.ok_or_else(|| einval!("no fuse fd received from supervisor"))?;
+
+        let mut sock = sock;
+        if cnt < len.len() {
+            sock.read_exact(&mut len[cnt..])?;
+        }
+        let size = u64::from_le_bytes(len);
+        if size > MAX_STATE_SIZE {
+            return Err(einval!(format!("daemon state is too big, {} bytes", size)));
+        }
+        let mut state = vec![0u8; size as usize];
+        sock.read_exact(&mut state)?;
+
+        Ok((file, state))
+    }
+
+    /// Send the `/dev/fuse` fd and states of mounted filesystems to the supervisor.
+    pub fn save(daemon: &FusedevDaemon) -> DaemonResult<()> {
+        let mgr = daemon.upgrade_mgr().ok_or(DaemonError::Unsupported)?;
+        let state = mgr.dump()?;
+        let mut session = daemon.session.lock().unwrap();
+        let file = session
+            .as_mut()
+            .and_then(|s| s.get_fuse_file())
+            .ok_or(UpgradeMgrError::NoFuseFd)?;
+        let sock = UnixStream::connect(&mgr.supervisor).map_err(UpgradeMgrError::Supervisor)?;
+        send_state(&sock, file.as_raw_fd(), &state).map_err(UpgradeMgrError::Supervisor)?;
+        info!(
+            "saved fuse fd and state of {} mounts to supervisor",
+            mgr.mounts.len()
+        );
+
         Ok(())
     }
 
-    pub fn restore(_daemon: &FusedevDaemon) -> DaemonResult<()> {
+    /// Fetch the `/dev/fuse` fd and states of mounted filesystems from the supervisor, and
+    /// restore the filesystems in mount order.
+    pub fn restore(daemon: &FusedevDaemon) -> DaemonResult<()> {
+        let (file, state) = {
+            let mgr = daemon.upgrade_mgr().ok_or(DaemonError::Unsupported)?;
+            let sock = UnixStream::connect(&mgr.supervisor).map_err(UpgradeMgrError::Supervisor)?;
+            recv_state(&sock).map_err(UpgradeMgrError::Supervisor)?
+        };
+        set_nonblocking(&file).map_err(UpgradeMgrError::Supervisor)?;
+        let mounts = UpgradeManager::load(&state)?;
+
+        let mountpoint = {
+            let mut guard = daemon.session.lock().unwrap();
+            let session = guard.as_mut().ok_or(DaemonError::Unsupported)?;
+            session.set_fuse_file(file);
+            session.mountpoint().to_path_buf()
+        };
+        for m in mounts.iter() {
+            daemon.restore_mount(&m.cmd, m.vfs_index)?;
+        }
+        daemon.conn.store(
+            calc_fuse_conn(&mountpoint).map_err(|e| DaemonError::Common(e.to_string()))?,
+            Ordering::Relaxed,
+        );
+        info!(
+            "restored fuse fd and {} mounts from supervisor",
+            mounts.len()
+        );
+
         Ok(())
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_send_recv_state() {
+            let (sock1, sock2) = UnixStream::pair().unwrap();
+            let tmp_file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+            let file = tmp_file.as_file();
+            let state = vec![0x5au8; 0x10000];
+
+            let sender = std::thread::spawn({
+                let state = state.clone();
+                let fd = file.as_raw_fd();
+                move || send_state(&sock1, fd, &state)
+            });
+            let (received, data) = recv_state(&sock2).unwrap();
+            sender.join().unwrap().unwrap();
+            assert_eq!(data, state);
+
+            let m1 = file.metadata().unwrap();
+            let m2 = received.metadata().unwrap();
+            assert_eq!(
+                std::os::unix::fs::MetadataExt::ino(&m1),
+                std::os::unix::fs::MetadataExt::ino(&m2)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus::FsBackendType;
+
+    fn mount_cmd(mountpoint: &str, source: &str) -> FsBackendMountCmd {
+        FsBackendMountCmd {
+            fs_type: FsBackendType::Rafs,
+            source: source.to_string(),
+            config: "{}".to_string(),
+            mountpoint: mountpoint.to_string(),
+            prefetch_files: None,
+        }
+    }
+
+    fn new_manager() -> UpgradeManager {
+        UpgradeManager {
+            supervisor: PathBuf::from("/tmp/supervisor.sock"),
+            mounts: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    #[test]
+    fn test_mounts_state() {
+        let mut mgr = new_manager();
+        add_mounts_state(&mut mgr, mount_cmd("/z", "/boot1"), 1).unwrap();
+        add_mounts_state(&mut mgr, mount_cmd("/a", "/boot2"), 2).unwrap();
+        add_mounts_state(&mut mgr, mount_cmd("/m", "/boot3"), 3).unwrap();
+        remove_mounts_state(
+            &mut mgr,
+            FsBackendUmountCmd {
+                mountpoint: "/a".to_string(),
+            },
+        )
+        .unwrap();
+        // A vfs index may be reused after the previous filesystem has been umounted.
+        add_mounts_state(&mut mgr, mount_cmd("/b", "/boot4"), 2).unwrap();
+        update_mounts_state(&mut mgr, mount_cmd("/m", "/boot5")).unwrap();
+        assert!(update_mounts_state(&mut mgr, mount_cmd("/a", "/boot6")).is_err());
+
+        let mounts = mgr.mounts();
+        let expected = [
+            ("/z", 1, "/boot1"),
+            ("/m", 3, "/boot5"),
+            ("/b", 2, "/boot4"),
+        ];
+        assert_eq!(mounts.len(), expected.len());
+        for (m, (mp, index, source)) in mounts.iter().zip(expected.iter()) {
+            assert_eq!(m.cmd.mountpoint, *mp);
+            assert_eq!(m.vfs_index, *index);
+            assert_eq!(m.cmd.source, *source);
+        }
+
+        // Filesystems are restored in mount order, and so get the same vfs indexes if none is
+        // umounted in between.
+        let restored = UpgradeManager::load(&mgr.dump().unwrap()).unwrap();
+        let mut mgr2 = new_manager();
+        for m in restored.iter() {
+            add_mounts_state(&mut mgr2, m.cmd.clone(), m.vfs_index).unwrap();
+        }
+        for (m1, m2) in mounts.iter().zip(mgr2.mounts().iter()) {
+            assert_eq!(m1.cmd.mountpoint, m2.cmd.mountpoint);
+            assert_eq!(m1.vfs_index, m2.vfs_index);
+        }
+    }
 }
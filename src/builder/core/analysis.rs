@@ -0,0 +1,297 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Statistics of files in the built image, to show what dominates the image.
+//!
+//! Regular files are classified by extension, e.g. `library` for `*.so*` and `archive` for
+//! `*.tar.gz`, and accounted when dumping the bootstrap. The report contains total size by file
+//! class, the largest files, and files with the same content as others detected by digest.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use nydus_utils::digest::RafsDigest;
+use serde::Serialize;
+
+use super::node::Node;
+
+/// Default number of largest files to report.
+pub const DEFAULT_TOP_FILES: usize = 10;
+
+/// Supported file classes.
+pub const FILE_CLASSES: [&str; 9] = [
+    "library",
+    "archive",
+    "bytecode",
+    "source",
+    "text",
+    "media",
+    "model",
+    "executable",
+    "other",
+];
+
+/// Classify a regular file by its name, or by mode if it has no extension.
+pub fn file_class(name: &str, mode: u32) -> &'static str {
+    let name = name.to_ascii_lowercase();
+    if name.ends_with(".so") || name.contains(".so.") {
+        return "library";
+    }
+    let ext = match name.rfind('.') {
+        Some(pos) if pos > 0 => &name[pos + 1..],
+        _ => {
+            return if mode & 0o111 != 0 {
+                "executable"
+            } else {
+                "other"
+            };
+        }
+    };
+
+    match ext {
+        "a" | "dll" | "dylib" => "library",
+        "tar" | "gz" | "tgz" | "xz" | "bz2" | "zst" | "zip" | "jar" | "war" | "whl" | "egg"
+        | "rpm" | "deb" => "archive",
+        "pyc" | "pyo" | "class" | "wasm" => "bytecode",
+        "c" | "h" | "cc" | "cpp" | "hpp" | "go" | "rs" | "py" | "js" | "ts" | "java" | "rb"
+        | "pl" | "php" | "sh" => "source",
+        "txt" | "md" | "rst" | "json" | "yaml" | "yml" | "xml" | "html" | "css" | "conf"
+        | "cfg" | "ini" | "toml" => "text",
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "ico" | "webp" | "ttf" | "otf" | "woff"
+        | "woff2" | "mp3" | "mp4" => "media",
+        "pt" | "pth" | "onnx" | "safetensors" | "h5" | "pb" | "gguf" | "tflite" => "model",
+        _ => "other",
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FileClassStats {
+    /// Number of files in the class.
+    pub files: u64,
+    /// Total size of files in the class.
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LargestFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub class: String,
+}
+
+/// BuildAnalysisReport is the analysis section in the output JSON of a build.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BuildAnalysisReport {
+    /// Number and total size of files by file class.
+    pub size_by_class: BTreeMap<String, FileClassStats>,
+    /// The largest files in descending order of size.
+    pub largest_files: Vec<LargestFile>,
+    /// Number of files with the same content as a previous file, hardlinks are not counted.
+    pub duplicate_files: u64,
+    /// Total size of duplicate files.
+    pub duplicate_size: u64,
+}
+
+/// BuildAnalysis accumulates statistics of regular files in the image.
+#[derive(Clone)]
+pub struct BuildAnalysis {
+    top_files: usize,
+    // Only account files of these classes if specified.
+    filter: Option<HashSet<&'static str>>,
+    classes: BTreeMap<&'static str, FileClassStats>,
+    // Min-heap to keep the `top_files` largest files.
+    largest: BinaryHeap<Reverse<(u64, PathBuf, &'static str)>>,
+    digests: HashSet<RafsDigest>,
+    duplicate_files: u64,
+    duplicate_size: u64,
+}
+
+impl BuildAnalysis {
+    /// Create an analysis reporting `top_files` largest files, optionally only accounting files
+    /// of classes in the comma separated `filter`.
+    pub fn new(top_files: usize, filter: Option<&str>) -> Result<Self> {
+        let filter = match filter {
+            Some(filter) => {
+                let mut classes = HashSet::new();
+                for class in filter
+                    .split(',')
+                    .map(|c| c.trim())
+                    .filter(|c| !c.is_empty())
+                {
+                    match FILE_CLASSES.iter().find(|c| **c == class) {
+                        Some(c) => classes.insert(*c),
+                        None => bail!(
+                            "invalid file type {:?}, should be one of {}",
+                            class,
+                            FILE_CLASSES.join(", ")
+                        ),
+                    };
+                }
+                if classes.is_empty() {
+                    bail!("file type filter is empty");
+                }
+                Some(classes)
+            }
+            None => None,
+        };
+
+        Ok(BuildAnalysis {
+            top_files,
+            filter,
+            classes: BTreeMap::new(),
+            largest: BinaryHeap::new(),
+            digests: HashSet::new(),
+            duplicate_files: 0,
+            duplicate_size: 0,
+        })
+    }
+
+    /// Clear statistics accumulated so far, e.g. those of a previous layer.
+    pub fn reset(&mut self) {
+        self.classes.clear();
+        self.largest.clear();
+        self.digests.clear();
+        self.duplicate_files = 0;
+        self.duplicate_size = 0;
+    }
+
+    /// Account a node whose inode digest has been calculated.
+    pub fn record(&mut self, node: &Node) {
+        // Hardlinks share data with the first node of the same inode.
+        if !node.is_reg() || node.inode.ino() != node.index {
+            return;
+        }
+        self.account(
+            &node.name().to_string_lossy(),
+            node.target(),
+            node.inode.mode(),
+            node.inode.size(),
+            node.inode.digest(),
+        );
+    }
+
+    fn account(&mut self, name: &str, path: &Path, mode: u32, size: u64, digest: &RafsDigest) {
+        let class = file_class(name, mode);
+        if let Some(filter) = self.filter.as_ref() {
+            if !filter.contains(class) {
+                return;
+            }
+        }
+
+        let stats = self.classes.entry(class).or_default();
+        stats.files += 1;
+        stats.size += size;
+
+        if self.top_files > 0 {
+            self.largest
+                .push(Reverse((size, path.to_path_buf(), class)));
+            if self.largest.len() > self.top_files {
+                self.largest.pop();
+            }
+        }
+
+        // All empty files have the same digest.
+        if size > 0 && !self.digests.insert(*digest) {
+            self.duplicate_files += 1;
+            self.duplicate_size += size;
+        }
+    }
+
+    pub fn report(&self) -> BuildAnalysisReport {
+        let mut largest_files: Vec<LargestFile> = self
+            .largest
+            .iter()
+            .map(|Reverse((size, path, class))| LargestFile {
+                path: path.clone(),
+                size: *size,
+                class: class.to_string(),
+            })
+            .collect();
+        largest_files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+
+        BuildAnalysisReport {
+            size_by_class: self
+                .classes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+            largest_files,
+            duplicate_files: self.duplicate_files,
+            duplicate_size: self.duplicate_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_utils::digest::Algorithm;
+
+    #[test]
+    fn test_file_class() {
+        assert_eq!(file_class("libc.so.6", 0o755), "library");
+        assert_eq!(file_class("libz.so", 0o644), "library");
+        assert_eq!(file_class("libz.a", 0o644), "library");
+        assert_eq!(file_class("data.tar.gz", 0o644), "archive");
+        assert_eq!(file_class("main.PY", 0o644), "source");
+        assert_eq!(file_class("model.safetensors", 0o644), "model");
+        assert_eq!(file_class("bash", 0o755), "executable");
+        assert_eq!(file_class(".bashrc", 0o644), "other");
+        assert_eq!(file_class("unknown.xyz", 0o755), "other");
+    }
+
+    #[test]
+    fn test_build_analysis() {
+        assert!(BuildAnalysis::new(2, Some("library,unknown")).is_err());
+        assert!(BuildAnalysis::new(2, Some(",")).is_err());
+
+        let d1 = RafsDigest::from_buf(b"1", Algorithm::Sha256);
+        let d2 = RafsDigest::from_buf(b"2", Algorithm::Sha256);
+        let d3 = RafsDigest::from_buf(b"3", Algorithm::Sha256);
+        let empty = RafsDigest::from_buf(b"", Algorithm::Sha256);
+        let files = [
+            ("libc.so.6", "/lib/libc.so.6", 0o755, 2000, &d1),
+            ("libc.so.6", "/opt/lib/libc.so.6", 0o755, 2000, &d1),
+            ("bash", "/bin/bash", 0o755, 1000, &d2),
+            ("a.txt", "/a.txt", 0o644, 10, &d3),
+            ("b.txt", "/b.txt", 0o644, 0, &empty),
+            ("c.txt", "/c.txt", 0o644, 0, &empty),
+        ];
+
+        let mut analysis = BuildAnalysis::new(2, None).unwrap();
+        for (name, path, mode, size, digest) in files.iter() {
+            analysis.account(name, Path::new(path), *mode, *size, digest);
+        }
+        let report = analysis.report();
+        assert_eq!(report.size_by_class.len(), 3);
+        assert_eq!(report.size_by_class["library"].files, 2);
+        assert_eq!(report.size_by_class["library"].size, 4000);
+        assert_eq!(report.size_by_class["text"].files, 3);
+        assert_eq!(report.largest_files.len(), 2);
+        assert_eq!(report.largest_files[0].path, Path::new("/lib/libc.so.6"));
+        assert_eq!(
+            report.largest_files[1].path,
+            Path::new("/opt/lib/libc.so.6")
+        );
+        assert_eq!(report.duplicate_files, 1);
+        assert_eq!(report.duplicate_size, 2000);
+
+        let mut analysis = BuildAnalysis::new(5, Some("executable, text")).unwrap();
+        for (name, path, mode, size, digest) in files.iter() {
+            analysis.account(name, Path::new(path), *mode, *size, digest);
+        }
+        let report = analysis.report();
+        assert_eq!(report.size_by_class.len(), 2);
+        assert_eq!(report.largest_files.len(), 4);
+        assert_eq!(report.largest_files[0].path, Path::new("/bin/bash"));
+        assert_eq!(report.duplicate_files, 0);
+
+        analysis.reset();
+        let report = analysis.report();
+        assert!(report.size_by_class.is_empty());
+        assert!(report.largest_files.is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+use sha2::Digest;
+use storage::compress;
+use storage::meta::toc::{BlobTocOndisk, BLOB_TOC_SIZE};
+use storage::meta::{BlobChunkInfoOndisk, BlobMetaHeaderOndisk, BlobProvenance};
+
+use super::chunk_dict::ChunkDict;
+use super::context::{BlobContext, BuildContext, SourceType};
+use super::node::Node;
+
+#[derive(Default)]
+pub struct Blob {}
+
+impl Blob {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Dump blob file and generate chunks
+    pub fn dump<'a, T: ChunkDict>(
+        &mut self,
+        ctx: &BuildContext,
+        blob_ctx: &'a mut BlobContext,
+        blob_index: u32,
+        nodes: &mut Vec<Node>,
+        chunk_dict: &mut T,
+    ) -> Result<bool> {
+        match ctx.source_type {
+            SourceType::Directory | SourceType::Diff | SourceType::OciV1 => {
+                let (inodes, prefetch_entries) = blob_ctx
+                    .blob_layout
+                    .layout_blob_simple(&ctx.prefetch, nodes)?;
+                for (idx, inode) in inodes.iter().enumerate() {
+                    let node = &mut nodes[*inode];
+                    let size = node
+                        .dump_blob(ctx, blob_ctx, blob_index, chunk_dict)
+                        .context("failed to dump blob chunks")?;
+                    if idx < prefetch_entries {
+                        blob_ctx.blob_readahead_size += size;
+                    }
+                    if let Some(spill) = ctx.chunk_spill.as_ref() {
+                        node.spill_chunks(spill)?;
+                    }
+                }
+                self.dump_meta_data(ctx, blob_ctx)?;
+            }
+            SourceType::StargzIndex | SourceType::ZstdChunked => {
+                for node in nodes {
+                    if node.overlay.is_lower_layer() {
+                        continue;
+                    } else if node.is_symlink() {
+                        node.inode.set_digest(RafsDigest::from_buf(
+                            node.symlink.as_ref().unwrap().as_bytes(),
+                            digest::Algorithm::Sha256,
+                        ));
+                    } else {
+                        // Set blob index and inode digest for upper nodes
+                        let mut inode_hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+                        for chunk in node.chunks.iter_mut() {
+                            chunk.set_blob_index(blob_index);
+                            inode_hasher.digest_update(chunk.id().as_ref());
+                        }
+                        node.inode.set_digest(inode_hasher.digest_finalize());
+                    }
+                }
+            }
+        }
+
+        Self::seal(ctx, blob_ctx)?;
+
+        let blob_exists = blob_ctx.compressed_blob_size > 0;
+
+        Ok(blob_exists)
+    }
+
+    /// Seal the blob being dumped and continue with a new blob, after reaching the blob size
+    /// limit. The sealed blob is moved into `sealed_blobs` of the new blob context.
+    pub fn split(ctx: &BuildContext, blob_ctx: &mut BlobContext) -> Result<()> {
+        let mut next = BlobContext::new(String::new(), ctx.blob_storage.clone(), 0)?;
+        next.set_chunk_dict(blob_ctx.chunk_dict.clone());
+        next.set_chunk_size(blob_ctx.chunk_size);
+        next.set_meta_info_enabled(blob_ctx.blob_meta_info_enabled);
+
+        let mut sealed = std::mem::replace(blob_ctx, next);
+        blob_ctx.sealed_blobs = std::mem::take(&mut sealed.sealed_blobs);
+        Self::new().dump_meta_data(ctx, &mut sealed)?;
+        Self::seal(ctx, &mut sealed)?;
+        info!(
+            "blob {} sealed with {} chunks, compressed size {}",
+            sealed.blob_id, sealed.chunk_count, sealed.compressed_blob_size
+        );
+        blob_ctx.sealed_blobs.push(sealed);
+
+        Ok(())
+    }
+
+    // Name the blob by its hash if not specified, and flush blob data to the storage.
+    fn seal(ctx: &BuildContext, blob_ctx: &mut BlobContext) -> Result<()> {
+        if blob_ctx.blob_id.is_empty() {
+            blob_ctx.blob_id = format!("{:x}", blob_ctx.blob_hash.clone().finalize());
+        }
+
+        blob_ctx.set_blob_readahead_size(ctx);
+        blob_ctx.flush()
+    }
+
+    /// Append a TOC footer to the blob file, so the blob can be verified without the bootstrap.
+    ///
+    /// The blob id is computed before the TOC is appended, so the TOC isn't covered by the blob
+    /// digest and may carry build metadata.
+    pub fn dump_toc(
+        ctx: &BuildContext,
+        blob_ctx: &BlobContext,
+        bootstrap_path: &Path,
+    ) -> Result<()> {
+        let blob_path = match ctx.blob_storage.as_ref() {
+            Some(storage) => storage.get_path(&blob_ctx.blob_id),
+            None => return Ok(()),
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&blob_path)
+            .with_context(|| format!("failed to open blob {:?}", blob_path))?;
+        let end = file.seek(SeekFrom::End(0))?;
+        let blob_size = end
+            .checked_sub(ctx.blob_offset)
+            .ok_or_else(|| anyhow!("blob {:?} is truncated", blob_path))?;
+
+        // The blob may have been generated by a former build, with the TOC appended.
+        if blob_size >= BLOB_TOC_SIZE {
+            let mut buf = vec![0u8; BLOB_TOC_SIZE as usize];
+            file.read_exact_at(&mut buf, end - BLOB_TOC_SIZE)?;
+            if BlobTocOndisk::from_bytes(&buf).is_ok() {
+                info!("blob {} already has a TOC", blob_ctx.blob_id);
+                return Ok(());
+            }
+        }
+
+        let mut toc = BlobTocOndisk::default();
+        toc.set_compressor(ctx.compressor);
+        toc.set_digester(ctx.digester);
+        toc.set_chunk_count(blob_ctx.chunk_count);
+        toc.set_chunk_size(blob_ctx.chunk_size);
+        toc.set_blob_range(ctx.blob_offset, blob_size);
+        toc.set_blob_digest(&RafsDigest {
+            data: blob_ctx.blob_hash.clone().finalize().into(),
+        });
+        if blob_ctx.blob_meta_info_enabled {
+            let header = &blob_ctx.blob_meta_header;
+            toc.set_ci_range(header.ci_compressed_offset(), header.ci_compressed_size());
+            toc.set_ci_digest(&blob_ctx.blob_meta_digest);
+        }
+        toc.set_bootstrap_digest(&Self::digest_file(bootstrap_path)?);
+        toc.set_builder_version(&ctx.builder_version);
+        toc.set_build_time(ctx.build_time);
+        toc.validate()?;
+
+        file.write_all(toc.as_bytes())
+            .with_context(|| format!("failed to write TOC to blob {:?}", blob_path))?;
+
+        Ok(())
+    }
+
+    fn digest_file(path: &Path) -> Result<RafsDigest> {
+        let mut file =
+            File::open(path).with_context(|| format!("failed to open bootstrap {:?}", path))?;
+        let mut hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+        let mut buf = vec![0u8; 0x10_0000];
+
+        loop {
+            let size = file.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            hasher.digest_update(&buf[..size]);
+        }
+
+        Ok(hasher.digest_finalize())
+    }
+
+    fn dump_meta_data(&mut self, ctx: &BuildContext, blob_ctx: &mut BlobContext) -> Result<()> {
+        if !blob_ctx.blob_meta_info_enabled {
+            return Ok(());
+        }
+
+        // Compute the chunk info array even without writer, so the blob id is the same as the
+        // one with blob data written.
+        let pos = match blob_ctx.writer.as_mut() {
+            Some(writer) => writer.get_pos()?,
+            None => blob_ctx.compress_offset,
+        };
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                blob_ctx.blob_meta_info.as_ptr() as *const u8,
+                blob_ctx.blob_meta_info.len() * std::mem::size_of::<BlobChunkInfoOndisk>(),
+            )
+        };
+        let (buf, compressed) = compress::compress(data, compress::Algorithm::Lz4Block)
+            .with_context(|| "failed to compress blob chunk info array".to_string())?;
+        let mut header = BlobMetaHeaderOndisk::default();
+
+        if compressed {
+            header.set_ci_compressor(compress::Algorithm::Lz4Block);
+        } else {
+            header.set_ci_compressor(compress::Algorithm::None);
+        }
+        header.set_ci_entries(blob_ctx.blob_meta_info.len() as u32);
+        header.set_ci_compressed_offset(pos);
+        header.set_ci_compressed_size(buf.len() as u64);
+        header.set_ci_uncompressed_size(data.len() as u64);
+        header.set_4k_aligned(true);
+        let source_digest = RafsDigest {
+            data: blob_ctx.source_hash.clone().finalize().into(),
+        };
+        header.set_provenance(&BlobProvenance {
+            builder_version: ctx.builder_version.clone(),
+            build_time: ctx.build_time,
+            source_digest,
+        });
+
+        // Builder version and build time differ between builds of the same source, so they're
+        // only recorded in the blob table of the bootstrap, to keep the blob id reproducible.
+        let mut ondisk_header = header;
+        ondisk_header.set_provenance(&BlobProvenance {
+            source_digest,
+            ..Default::default()
+        });
+
+        blob_ctx.blob_meta_header = header;
+        blob_ctx.blob_meta_digest = RafsDigest::from_buf(&buf, digest::Algorithm::Sha256);
+
+        if let Some(writer) = blob_ctx.writer.as_mut() {
+            writer.write_all(&buf)?;
+            writer.write_all(ondisk_header.as_bytes())?;
+        }
+        blob_ctx.blob_hash.update(&buf);
+        blob_ctx.blob_hash.update(ondisk_header.as_bytes());
+
+        Ok(())
+    }
+}
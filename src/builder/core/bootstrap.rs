@@ -5,30 +5,40 @@
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::ffi::OsString;
+use std::io::{Seek, SeekFrom, Write};
 use std::mem::size_of;
+use std::sync::Arc;
 
 use anyhow::{Context, Error, Result};
 use nydus_utils::digest::{DigestHasher, RafsDigest};
 use rafs::metadata::layout::v5::{
     RafsV5BlobTable, RafsV5ChunkInfo, RafsV5InodeTable, RafsV5SuperBlock, RafsV5XAttrsTable,
+    RAFSV5_IMAGE_DIGEST_OFFSET,
 };
 use rafs::metadata::layout::v6::{
-    align_offset, calculate_nid, RafsV6BlobTable, RafsV6Device, RafsV6SuperBlock,
-    RafsV6SuperBlockExt, EROFS_BLOCK_SIZE, EROFS_DEVTABLE_OFFSET, EROFS_INODE_SLOT_SIZE,
+    align_offset, calculate_nid, RafsV6BlobTable, RafsV6Device, RafsV6DirentBloom,
+    RafsV6DirentIndex, RafsV6SharedXattrs, RafsV6SuperBlock, RafsV6SuperBlockExt, EROFS_BLOCK_SIZE,
+    EROFS_DEVTABLE_OFFSET, EROFS_INODE_SLOT_SIZE, RAFSV6_DIRENT_INDEX_MIN_ENTRIES,
+    RAFSV6_IMAGE_DIGEST_OFFSET,
 };
 use rafs::RafsIoWrite;
 
-use rafs::metadata::layout::RAFS_ROOT_INODE;
-use rafs::metadata::{RafsMode, RafsStore, RafsSuper};
+use rafs::metadata::layout::{RafsXAttrs, RAFS_ROOT_INODE};
+use rafs::metadata::{calculate_image_digest, RafsMode, RafsStore, RafsSuper, RafsSuperFlags};
 
-use super::context::{BlobManager, BootstrapContext, BootstrapManager, BuildContext, SourceType};
-use super::node::{Node, WhiteoutType, OVERLAYFS_WHITEOUT_OPAQUE};
+use super::context::{
+    ArtifactBufferWriter, BlobManager, BootstrapContext, BootstrapManager, BuildContext, SourceType,
+};
+use super::node::{Node, WhiteoutType};
+use super::platform;
 use super::tree::Tree;
 
-pub(crate) const STARGZ_DEFAULT_BLOCK_SIZE: u32 = 4 << 20;
+pub const STARGZ_DEFAULT_BLOCK_SIZE: u32 = 4 << 20;
 const WRITE_PADDING_DATA: [u8; 4096] = [0u8; 4096];
+// Xattrs present in at least this number of inodes are stored in the shared xattr area.
+const RAFSV6_SHARED_XATTR_THRESHOLD: usize = 2;
 
-pub(crate) struct Bootstrap {}
+pub struct Bootstrap {}
 
 impl Bootstrap {
     /// Create a new instance of `BootStrap`.
@@ -74,6 +84,10 @@ impl Bootstrap {
             vec![tree.node.index],
         );
 
+        if ctx.fs_version.is_v6() {
+            Self::share_v6_xattrs(tree);
+        }
+
         // indicates where v6's meta_addr starts
         let root_offset = bootstrap_ctx.offset;
         let mut nodes = Vec::with_capacity(0x10000);
@@ -137,10 +151,11 @@ impl Bootstrap {
         let index = nodes.len() as u32 + 1;
         let parent = &mut nodes[tree.node.index as usize - 1];
 
-        // Sort children list by name, so that we can improve performance in fs read_dir using
-        // binary search.
-        tree.children
-            .sort_by_key(|child| child.node.name().to_os_string());
+        // Sort children list by bytes of names, so that we can improve performance in fs read_dir
+        // using binary search, and the image doesn't depend on the readdir order of the source.
+        tree.children.sort_by(|a, b| {
+            platform::os_str_to_bytes(a.node.name()).cmp(&platform::os_str_to_bytes(b.node.name()))
+        });
 
         // Maybe the parent is not a directory in multi-layers build scenario, so we check here.
         if parent.is_dir() {
@@ -172,7 +187,14 @@ impl Bootstrap {
             } else {
                 &mut bootstrap_ctx.upper_inode_map
             };
-            if let Some(indexes) = inode_map.get_mut(&(child.node.src_ino, child.node.src_dev)) {
+            // Inode number is unknown if the source platform has no inode, hardlinks can't be
+            // detected in that case.
+            let hardlinks = if child.node.src_ino == 0 {
+                None
+            } else {
+                inode_map.get_mut(&(child.node.src_ino, child.node.src_dev))
+            };
+            if let Some(indexes) = hardlinks {
                 let nlink = indexes.len() as u32 + 1;
                 let first_index = indexes[0];
                 child.node.inode.set_ino(first_index);
@@ -214,18 +236,14 @@ impl Bootstrap {
                     if whiteout_type == WhiteoutType::OverlayFsOpaque {
                         // For the overlayfs opaque, we need to remove the lower node that has the
                         // same name first, then apply upper node to the node tree of lower layer.
-                        child
-                            .node
-                            .remove_xattr(&OsString::from(OVERLAYFS_WHITEOUT_OPAQUE));
+                        child.node.remove_overlayfs_opaque();
                         nodes.push(child.node.clone());
                     }
                 }
                 (false, Some(whiteout_type)) => {
                     // Remove overlayfs opaque xattr for single layer build
                     if whiteout_type == WhiteoutType::OverlayFsOpaque {
-                        child
-                            .node
-                            .remove_xattr(&OsString::from(OVERLAYFS_WHITEOUT_OPAQUE));
+                        child.node.remove_overlayfs_opaque();
                     }
                     nodes.push(child.node.clone());
                 }
@@ -256,6 +274,35 @@ impl Bootstrap {
         Ok(())
     }
 
+    // Store xattrs present in multiple inodes only once in the shared xattr area.
+    fn share_v6_xattrs(tree: &mut Tree) {
+        fn collect<'a>(tree: &'a Tree, xattrs: &mut Vec<&'a RafsXAttrs>) {
+            xattrs.push(&tree.node.xattrs);
+            for child in tree.children.iter() {
+                collect(child, xattrs);
+            }
+        }
+
+        fn assign(tree: &mut Tree, shared: &Option<Arc<RafsV6SharedXattrs>>) {
+            tree.node.v6_shared_xattrs = shared.clone();
+            for child in tree.children.iter_mut() {
+                assign(child, shared);
+            }
+        }
+
+        let mut xattrs = Vec::new();
+        collect(tree, &mut xattrs);
+        let shared =
+            RafsV6SharedXattrs::from_xattrs(xattrs.into_iter(), RAFSV6_SHARED_XATTR_THRESHOLD);
+        let shared = if shared.is_empty() {
+            None
+        } else {
+            debug!("{} xattrs are shared by multiple inodes", shared.len());
+            Some(Arc::new(shared))
+        };
+        assign(tree, &shared);
+    }
+
     /// Rafsv6 update offset
     fn update_dirents(&self, nodes: &mut Vec<Node>, tree: &mut Tree, parent_offset: u64) {
         let node = &mut nodes[tree.node.index as usize - 1];
@@ -288,6 +335,9 @@ impl Bootstrap {
                 dirs.push(child);
             }
         }
+        // Erofs looks up dirents by binary search, dot and dotdot included.
+        node.dirents
+            .sort_by(|a, b| platform::os_str_to_bytes(&a.1).cmp(&platform::os_str_to_bytes(&b.1)));
 
         for dir in dirs {
             self.update_dirents(nodes, dir, tree.node.offset);
@@ -393,6 +443,16 @@ impl Bootstrap {
         let extended_blob_table_size = blob_table.extended.size();
         let extended_blob_table_entries = blob_table.extended.entries();
 
+        // Set blob prefetch table
+        let blob_prefetch_table = ctx
+            .prefetch
+            .get_rafsv5_blob_prefetch_table(&bootstrap_ctx.nodes, ctx.chunk_spill.as_deref())?;
+        let blob_prefetch_table_offset = extended_blob_table_offset + extended_blob_table_size;
+        let (blob_prefetch_table_size, blob_prefetch_table_entries) = match &blob_prefetch_table {
+            Some(table) => (table.size(), table.len() as u32),
+            None => (0, 0u32),
+        };
+
         // Set super block
         let mut super_block = RafsV5SuperBlock::new();
         let inodes_count =
@@ -406,12 +466,19 @@ impl Bootstrap {
         super_block.set_extended_blob_table_entries(u32::try_from(extended_blob_table_entries)?);
         super_block.set_prefetch_table_offset(prefetch_table_offset as u64);
         super_block.set_prefetch_table_entries(prefetch_table_entries);
+        if blob_prefetch_table_entries > 0 {
+            super_block.set_blob_prefetch_table_offset(blob_prefetch_table_offset as u64);
+            super_block.set_blob_prefetch_table_entries(blob_prefetch_table_entries);
+        }
         super_block.set_compressor(ctx.compressor);
         super_block.set_digester(ctx.digester);
         super_block.set_chunk_size(ctx.chunk_size);
         if ctx.explicit_uidgid {
             super_block.set_explicit_uidgid();
         }
+        if ctx.metadata_only {
+            super_block.set_metadata_only();
+        }
         if ctx.source_type == SourceType::StargzIndex {
             super_block.set_block_size(STARGZ_DEFAULT_BLOCK_SIZE);
         }
@@ -421,7 +488,8 @@ impl Bootstrap {
             + inode_table_size
             + prefetch_table_size
             + blob_table_size
-            + extended_blob_table_size) as u32;
+            + extended_blob_table_size
+            + blob_prefetch_table_size) as u32;
 
         let mut has_xattr = false;
         for node in &mut bootstrap_ctx.nodes {
@@ -473,12 +541,26 @@ impl Bootstrap {
             .store_extended(&mut bootstrap_writer)
             .context("failed to store extended blob table")?;
 
+        // Dump blob prefetch table
+        if let Some(table) = blob_prefetch_table.as_ref() {
+            table
+                .store(&mut bootstrap_writer)
+                .context("failed to store blob prefetch table")?;
+        }
+
         // Dump inodes and chunks
+        let mut analysis = ctx.analysis.take();
+        if let Some(analysis) = analysis.as_mut() {
+            analysis.reset();
+        }
         timing_tracer!(
             {
                 for node in &bootstrap_ctx.nodes {
                     node.dump_bootstrap_v5(&ctx, &mut bootstrap_writer)
                         .context("failed to dump bootstrap")?;
+                    if let Some(analysis) = analysis.as_mut() {
+                        analysis.record(node);
+                    }
                 }
 
                 Ok(())
@@ -486,7 +568,11 @@ impl Bootstrap {
             "dump_bootstrap",
             Result<()>
         )?;
+        ctx.analysis = analysis;
 
+        let image_digest =
+            Self::dump_image_digest(&mut bootstrap_writer, RAFSV5_IMAGE_DIGEST_OFFSET)?;
+        bootstrap_ctx.image_digest = Some(image_digest.to_string());
         bootstrap_writer.release(Some(bootstrap_ctx.name.as_str()))?;
 
         Ok(())
@@ -503,15 +589,16 @@ impl Bootstrap {
         //
         //  EROFS_SUPER_OFFSET
         //     |
-        // +---+---------+------------+-------------+-------------------------------------------------------------------+
-        // |   |         |            |             |                                                                   |
-        // |1k |super    |extended    | blob table  | inodes                                                            |
-        // |   |block    |superblock+ |             |                                                                   |
-        // |   |         |devslot     |             |                                                                   |
-        // +---+---------+------------+-------------+-------------------------------------------------------------------+
+        // +---+---------+------------+-------------+-------------+-----------------------------------------------------+
+        // |   |         |            |             |             |                                                     |
+        // |1k |super    |extended    | blob table  | shared      | inodes                                              |
+        // |   |block    |superblock+ |             | xattrs      |                                                     |
+        // |   |         |devslot     |             |             |                                                     |
+        // +---+---------+------------+-------------+-------------+-----------------------------------------------------+
 
         let blob_table_size = blob_table.size() as u64;
-        let bootstrap_writer = &mut bootstrap_ctx.create_writer()? as &mut dyn RafsIoWrite;
+        let mut writer = bootstrap_ctx.create_writer()?;
+        let bootstrap_writer = &mut writer as &mut dyn RafsIoWrite;
 
         // get devt_slotoff
         let mut devtable: Vec<RafsV6Device> = Vec::new();
@@ -540,11 +627,17 @@ impl Bootstrap {
         let blob_table_entries = blob_table.entries.len();
 
         let orig_meta_addr = bootstrap_ctx.nodes[0].offset;
-        let meta_addr = if blob_table_size > 0 {
+        let mut meta_addr = if blob_table_size > 0 {
             align_offset(blob_table_offset + blob_table_size, EROFS_BLOCK_SIZE as u64)
         } else {
             orig_meta_addr
         };
+        // The shared xattr area is placed right before inodes.
+        let shared_xattrs = bootstrap_ctx.nodes[0].v6_shared_xattrs.clone();
+        let xattr_addr = meta_addr;
+        if let Some(shared) = shared_xattrs.as_ref() {
+            meta_addr = align_offset(xattr_addr + shared.size() as u64, EROFS_BLOCK_SIZE as u64);
+        }
 
         let root_nid = calculate_nid(
             bootstrap_ctx.nodes[0].offset - orig_meta_addr + meta_addr,
@@ -558,6 +651,9 @@ impl Bootstrap {
         sb.set_blocks(EROFS_BLOCK_SIZE as u32);
         sb.set_root_nid(root_nid as u16);
         sb.set_meta_addr(meta_addr);
+        if shared_xattrs.is_some() {
+            sb.set_xattr_addr(xattr_addr);
+        }
 
         sb.set_extra_devices(blob_table_entries as u16);
 
@@ -570,6 +666,9 @@ impl Bootstrap {
         ext_sb.set_chunk_size(ctx.chunk_size);
         ext_sb.set_blob_table_offset(blob_table_offset);
         ext_sb.set_blob_table_size(blob_table_size as u32);
+        if ctx.metadata_only {
+            ext_sb.set_flags(ext_sb.flags() | RafsSuperFlags::METADATA_ONLY.bits());
+        }
 
         ext_sb
             .store(bootstrap_writer)
@@ -592,12 +691,29 @@ impl Bootstrap {
             .store(bootstrap_writer)
             .context("failed to store extended blob table")?;
 
+        // Dump shared xattr area
+        if let Some(shared) = shared_xattrs.as_ref() {
+            bootstrap_writer
+                .seek_to_offset(xattr_addr)
+                .context("failed to seek for shared xattr area")?;
+            shared
+                .store(bootstrap_writer)
+                .context("failed to store shared xattr area")?;
+        }
+
         // Dump bootstrap
+        let mut analysis = ctx.analysis.take();
+        if let Some(analysis) = analysis.as_mut() {
+            analysis.reset();
+        }
         timing_tracer!(
             {
                 for node in &mut bootstrap_ctx.nodes {
                     node.dump_bootstrap_v6(bootstrap_writer, orig_meta_addr, meta_addr, ctx)
                         .context("failed to dump bootstrap")?;
+                    if let Some(analysis) = analysis.as_mut() {
+                        analysis.record(node);
+                    }
                 }
 
                 Ok(())
@@ -605,11 +721,52 @@ impl Bootstrap {
             "dump_bootstrap",
             Result<()>
         )?;
+        ctx.analysis = analysis;
 
         // Flush remaining data in BufWriter to file
         bootstrap_writer
             .flush()
             .context("failed to flush bootstrap")?;
+
+        // Dump directory name index after all inodes and dirents
+        if ctx.dirent_index {
+            let mut index = RafsV6DirentIndex::new();
+            for node in bootstrap_ctx
+                .nodes
+                .iter()
+                .filter(|n| n.is_dir() && n.dirents.len() >= RAFSV6_DIRENT_INDEX_MIN_ENTRIES)
+            {
+                let mut filter = RafsV6DirentBloom::new(node.dirents.len());
+                for (_, name, _) in node.dirents.iter() {
+                    filter.insert(name);
+                }
+                index.insert(calculate_nid(node.offset, meta_addr), filter);
+            }
+
+            if !index.is_empty() {
+                let pos = bootstrap_writer
+                    .seek_to_end()
+                    .context("failed to seek to bootstrap's end")?;
+                let index_offset = align_offset(pos, EROFS_BLOCK_SIZE as u64);
+                bootstrap_writer
+                    .seek_to_offset(index_offset)
+                    .context("failed to seek for directory name index")?;
+                index
+                    .store(bootstrap_writer)
+                    .context("failed to store directory name index")?;
+
+                ext_sb.set_flags(ext_sb.flags() | RafsSuperFlags::HAS_DIRENT_INDEX.bits());
+                ext_sb.set_dirent_index_offset(index_offset);
+                ext_sb.set_dirent_index_size(index.size() as u32);
+                ext_sb
+                    .store(bootstrap_writer)
+                    .context("failed to update extended SB")?;
+                bootstrap_writer
+                    .flush()
+                    .context("failed to flush bootstrap")?;
+            }
+        }
+
         let pos = bootstrap_writer
             .seek_to_end()
             .context("failed to seek to bootstrap's end")?;
@@ -622,6 +779,31 @@ impl Bootstrap {
             .write_all(&WRITE_PADDING_DATA[0..padding as usize])
             .context("failed to write 0 to padding of bootstrap's end")?;
 
+        let image_digest = Self::dump_image_digest(&mut writer, RAFSV6_IMAGE_DIGEST_OFFSET)?;
+        bootstrap_ctx.image_digest = Some(image_digest.to_string());
+
         Ok(())
     }
+
+    // Calculate the image digest from the bootstrap written so far, which covers all metadata
+    // and the blob table, and store it into the digest field of the superblock.
+    fn dump_image_digest(writer: &mut ArtifactBufferWriter, offset: u64) -> Result<RafsDigest> {
+        let mut file = writer
+            .try_clone_file()
+            .context("failed to reopen bootstrap")?;
+        file.seek(SeekFrom::Start(0))
+            .context("failed to seek to bootstrap's start")?;
+        let digest = calculate_image_digest(&mut file, offset)
+            .context("failed to calculate image digest")?;
+
+        writer
+            .seek(SeekFrom::Start(offset))
+            .context("failed to seek to image digest")?;
+        writer
+            .write_all(&digest.data)
+            .context("failed to store image digest")?;
+        writer.flush().context("failed to flush bootstrap")?;
+
+        Ok(digest)
+    }
 }
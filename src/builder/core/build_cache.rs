@@ -0,0 +1,506 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content addressed build cache for diff build.
+//!
+//! Each layer of a diff build is keyed by the layer digest, which is computed from the build
+//! options affecting the generated blob, and the path, mode, symlink target and content digest
+//! of all files added or modified by the layer. The blob and chunk information generated for a
+//! layer is stored in the cache directory as:
+//!
+//! ```text
+//! <cache_dir>/<layer_digest>/entry.json
+//! <cache_dir>/<layer_digest>/blob
+//! ```
+//!
+//! So later builds of layers with the same content reuse the results instead of compressing and
+//! dumping the blob again, no matter where the source files are and when they are modified.
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::{Context, Result};
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+use rafs::metadata::layout::v5::RafsV5ChunkInfo;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use storage::compress;
+use storage::device::BlobChunkFlags;
+use storage::meta::BlobProvenance;
+
+use super::context::{ArtifactStorage, BlobContext, BuildContext, RafsVersion};
+use super::node::{ChunkWrapper, Node};
+
+const BUILD_CACHE_VERSION: u32 = 1;
+const ENTRY_FILE: &str = "entry.json";
+const BLOB_FILE: &str = "blob";
+
+#[derive(Serialize, Deserialize)]
+struct CacheChunk {
+    id: String,
+    index: u32,
+    flags: u32,
+    compressed_offset: u64,
+    compressed_size: u32,
+    uncompressed_offset: u64,
+    uncompressed_size: u32,
+    file_offset: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheNode {
+    path: PathBuf,
+    digest: String,
+    chunks: Vec<CacheChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheBlob {
+    blob_id: String,
+    /// Sha256 digest of the whole blob file.
+    blob_digest: String,
+    readahead_size: u64,
+    chunk_count: u32,
+    chunk_size: u32,
+    compressed_size: u64,
+    decompressed_size: u64,
+    ci_compressor: u32,
+    ci_entries: u32,
+    ci_compressed_offset: u64,
+    ci_compressed_size: u64,
+    ci_uncompressed_size: u64,
+    ci_digest: String,
+    /// Provenance information recorded in the blob metadata header.
+    #[serde(default)]
+    builder_version: String,
+    #[serde(default)]
+    build_time: u64,
+    #[serde(default)]
+    source_digest: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    version: u32,
+    layer_digest: String,
+    blob: Option<CacheBlob>,
+    nodes: Vec<CacheNode>,
+}
+
+fn parse_digest(s: &str) -> Result<RafsDigest> {
+    let mut digest = RafsDigest::default();
+    if s.len() != digest.data.len() * 2 || !s.is_ascii() {
+        bail!("invalid digest {}", s);
+    }
+    for (idx, byte) in digest.data.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[idx * 2..idx * 2 + 2], 16)
+            .with_context(|| format!("invalid digest {}", s))?;
+    }
+    Ok(digest)
+}
+
+fn hash_file(hasher: &mut dyn FnMut(&[u8]), path: &Path) -> Result<()> {
+    let mut file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut buf = vec![0u8; 0x10_0000];
+    loop {
+        let size = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {:?}", path))?;
+        if size == 0 {
+            break;
+        }
+        hasher(&buf[..size]);
+    }
+    Ok(())
+}
+
+/// Build cache to reuse blob and chunk information of layers across diff builds.
+#[derive(Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    pub fn new(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create build cache directory {:?}", dir))?;
+        Ok(BuildCache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Compute the digest of a layer from the metadata and content of files added or modified by
+    /// the layer.
+    pub fn layer_digest(ctx: &BuildContext, nodes: &[Node]) -> Result<RafsDigest> {
+        let mut hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+        let options = format!(
+            "nydus-build-cache-v{} {} {:?} {} {} {} {} {} {}",
+            BUILD_CACHE_VERSION,
+            ctx.blob_id,
+            ctx.fs_version,
+            ctx.compressor,
+            ctx.compress_heuristics,
+            ctx.digester,
+            ctx.chunk_size,
+            ctx.aligned_chunk,
+            ctx.sparse_file,
+        );
+        hasher.digest_update(options.as_bytes());
+
+        for node in nodes {
+            hasher.digest_update(node.target().as_os_str().as_bytes());
+            hasher.digest_update(&[0]);
+            hasher.digest_update(&node.inode.mode().to_le_bytes());
+            hasher.digest_update(&node.inode.size().to_le_bytes());
+            if let Some(symlink) = node.symlink.as_ref() {
+                hasher.digest_update(symlink.as_bytes());
+            }
+            if node.is_reg() {
+                let mut file_hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+                hash_file(&mut |buf| file_hasher.digest_update(buf), &node.path)?;
+                hasher.digest_update(file_hasher.digest_finalize().as_ref());
+            }
+        }
+
+        Ok(hasher.digest_finalize())
+    }
+
+    /// Load results of a layer from the cache.
+    ///
+    /// On cache hit, chunks and digests of `nodes` are updated, the cached blob is copied into
+    /// `blob_storage` and `Some(blob_ctx)` is returned, `blob_ctx` may be none if the layer has no
+    /// blob. Invalid cache entries are removed and treated as cache miss.
+    pub fn load(
+        &self,
+        ctx: &BuildContext,
+        layer_digest: &RafsDigest,
+        blob_storage: Option<&ArtifactStorage>,
+        nodes: &mut [Node],
+    ) -> Option<Option<BlobContext>> {
+        let entry_dir = self.dir.join(layer_digest.to_string());
+        if !entry_dir.exists() {
+            return None;
+        }
+        match self.load_entry(ctx, &entry_dir, layer_digest, blob_storage, nodes) {
+            Ok(blob_ctx) => {
+                info!("build cache hit for layer {}", layer_digest);
+                Some(blob_ctx)
+            }
+            Err(e) => {
+                warn!("invalid build cache entry {:?}, {:?}", entry_dir, e);
+                if let Err(e) = fs::remove_dir_all(&entry_dir) {
+                    warn!("failed to remove build cache entry {:?}, {}", entry_dir, e);
+                }
+                None
+            }
+        }
+    }
+
+    fn load_entry(
+        &self,
+        ctx: &BuildContext,
+        entry_dir: &Path,
+        layer_digest: &RafsDigest,
+        blob_storage: Option<&ArtifactStorage>,
+        nodes: &mut [Node],
+    ) -> Result<Option<BlobContext>> {
+        let file = File::open(entry_dir.join(ENTRY_FILE))?;
+        let entry: CacheEntry = serde_json::from_reader(file)?;
+        if entry.version != BUILD_CACHE_VERSION {
+            bail!("unsupported build cache version {}", entry.version);
+        }
+        if entry.layer_digest != layer_digest.to_string() {
+            bail!("mismatched layer digest {}", entry.layer_digest);
+        }
+
+        // Validate the whole entry before updating nodes.
+        if entry.nodes.len() != nodes.iter().filter(|n| !n.is_dir()).count() {
+            bail!("mismatched file count {}", entry.nodes.len());
+        }
+        let mut cached = Vec::with_capacity(entry.nodes.len());
+        for (node, cache) in nodes.iter().filter(|n| !n.is_dir()).zip(entry.nodes.iter()) {
+            if node.target() != &cache.path {
+                bail!("mismatched file {:?}", cache.path);
+            }
+            let mut chunks = Vec::with_capacity(cache.chunks.len());
+            for c in cache.chunks.iter() {
+                let mut info = RafsV5ChunkInfo::new();
+                info.block_id = parse_digest(&c.id)?;
+                info.index = c.index;
+                info.flags = BlobChunkFlags::from_bits(c.flags)
+                    .ok_or_else(|| anyhow!("invalid chunk flags {:x}", c.flags))?;
+                info.compress_offset = c.compressed_offset;
+                info.compress_size = c.compressed_size;
+                info.uncompress_offset = c.uncompressed_offset;
+                info.uncompress_size = c.uncompressed_size;
+                info.file_offset = c.file_offset;
+                chunks.push(match ctx.fs_version {
+                    RafsVersion::V5 => ChunkWrapper::V5(info),
+                    RafsVersion::V6 => ChunkWrapper::V6(info),
+                });
+            }
+            cached.push((chunks, parse_digest(&cache.digest)?));
+        }
+
+        let blob_ctx = match entry.blob.as_ref() {
+            Some(blob) => Some(self.load_blob(entry_dir, blob, blob_storage)?),
+            None => None,
+        };
+
+        for (node, (chunks, digest)) in nodes.iter_mut().filter(|n| !n.is_dir()).zip(cached) {
+            if node.is_reg() {
+                node.inode.set_child_count(chunks.len() as u32);
+            }
+            node.chunks = chunks;
+            node.inode.set_digest(digest);
+        }
+
+        Ok(blob_ctx)
+    }
+
+    fn load_blob(
+        &self,
+        entry_dir: &Path,
+        blob: &CacheBlob,
+        blob_storage: Option<&ArtifactStorage>,
+    ) -> Result<BlobContext> {
+        let mut blob_ctx = BlobContext::new_with_writer(blob.blob_id.clone(), None);
+        let blob_path = entry_dir.join(BLOB_FILE);
+        hash_file(&mut |buf| blob_ctx.blob_hash.update(buf), &blob_path)?;
+        let blob_digest = format!("{:x}", blob_ctx.blob_hash.clone().finalize());
+        if blob_digest != blob.blob_digest {
+            bail!("mismatched blob digest {}", blob_digest);
+        }
+        if fs::metadata(&blob_path)?.len() != blob.compressed_size {
+            bail!("mismatched blob size");
+        }
+
+        blob_ctx.blob_readahead_size = blob.readahead_size;
+        blob_ctx.chunk_count = blob.chunk_count;
+        blob_ctx.chunk_size = blob.chunk_size;
+        blob_ctx.compressed_blob_size = blob.compressed_size;
+        blob_ctx.decompressed_blob_size = blob.decompressed_size;
+        blob_ctx.compress_offset = blob.compressed_size;
+        blob_ctx.decompress_offset = blob.decompressed_size;
+        blob_ctx.blob_meta_info_enabled = true;
+        let header = &mut blob_ctx.blob_meta_header;
+        header.set_ci_compressor(
+            compress::Algorithm::try_from(blob.ci_compressor)
+                .map_err(|_| anyhow!("invalid compressor {}", blob.ci_compressor))?,
+        );
+        header.set_ci_entries(blob.ci_entries);
+        header.set_ci_compressed_offset(blob.ci_compressed_offset);
+        header.set_ci_compressed_size(blob.ci_compressed_size);
+        header.set_ci_uncompressed_size(blob.ci_uncompressed_size);
+        header.set_4k_aligned(true);
+        if !blob.source_digest.is_empty() {
+            header.set_provenance(&BlobProvenance {
+                builder_version: blob.builder_version.clone(),
+                build_time: blob.build_time,
+                source_digest: parse_digest(&blob.source_digest)?,
+            });
+        }
+        blob_ctx.blob_meta_digest = parse_digest(&blob.ci_digest)?;
+
+        if let Some(storage) = blob_storage {
+            let path = storage.get_path(&blob.blob_id);
+            // Never touch blobs that already exist, they may be in use.
+            if !path.exists() {
+                fs::copy(&blob_path, &path)
+                    .with_context(|| format!("failed to copy cached blob to {:?}", path))?;
+            }
+        }
+
+        Ok(blob_ctx)
+    }
+
+    /// Store results of a layer into the cache.
+    pub fn store(
+        &self,
+        layer_digest: &RafsDigest,
+        blob_ctx: Option<&BlobContext>,
+        blob_storage: Option<&ArtifactStorage>,
+        nodes: &[Node],
+    ) -> Result<()> {
+        let entry_dir = self.dir.join(layer_digest.to_string());
+        if entry_dir.exists() {
+            return Ok(());
+        }
+        // Write to a temporary directory and rename it, so partial entries are never visible.
+        let tmp_dir = self.dir.join(format!(
+            ".{}.{}.{:?}",
+            layer_digest,
+            std::process::id(),
+            thread::current().id()
+        ));
+        let result = self.store_entry(&tmp_dir, layer_digest, blob_ctx, blob_storage, nodes);
+        let result = result.and_then(|_| {
+            fs::rename(&tmp_dir, &entry_dir)
+                .with_context(|| format!("failed to rename {:?}", tmp_dir))
+        });
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&tmp_dir);
+        }
+
+        result
+    }
+
+    fn store_entry(
+        &self,
+        tmp_dir: &Path,
+        layer_digest: &RafsDigest,
+        blob_ctx: Option<&BlobContext>,
+        blob_storage: Option<&ArtifactStorage>,
+        nodes: &[Node],
+    ) -> Result<()> {
+        fs::create_dir_all(tmp_dir)
+            .with_context(|| format!("failed to create directory {:?}", tmp_dir))?;
+
+        let blob = match (blob_ctx, blob_storage) {
+            (Some(blob_ctx), Some(storage)) => {
+                fs::copy(storage.get_path(&blob_ctx.blob_id), tmp_dir.join(BLOB_FILE))
+                    .context("failed to copy blob into build cache")?;
+                let header = &blob_ctx.blob_meta_header;
+                let provenance = header.provenance().unwrap_or_default();
+                Some(CacheBlob {
+                    blob_id: blob_ctx.blob_id.clone(),
+                    blob_digest: format!("{:x}", blob_ctx.blob_hash.clone().finalize()),
+                    readahead_size: blob_ctx.blob_readahead_size,
+                    chunk_count: blob_ctx.chunk_count,
+                    chunk_size: blob_ctx.chunk_size,
+                    compressed_size: blob_ctx.compressed_blob_size,
+                    decompressed_size: blob_ctx.decompressed_blob_size,
+                    ci_compressor: header.ci_compressor() as u32,
+                    ci_entries: header.ci_entries(),
+                    ci_compressed_offset: header.ci_compressed_offset(),
+                    ci_compressed_size: header.ci_compressed_size(),
+                    ci_uncompressed_size: header.ci_uncompressed_size(),
+                    ci_digest: blob_ctx.blob_meta_digest.to_string(),
+                    builder_version: provenance.builder_version,
+                    build_time: provenance.build_time,
+                    source_digest: provenance.source_digest.to_string(),
+                })
+            }
+            (Some(_), None) => bail!("blob storage is required by build cache"),
+            _ => None,
+        };
+
+        let nodes = nodes
+            .iter()
+            .filter(|n| !n.is_dir())
+            .map(|node| CacheNode {
+                path: node.target().clone(),
+                digest: node.inode.digest().to_string(),
+                chunks: node
+                    .chunks
+                    .iter()
+                    .map(|chunk| {
+                        let info = match chunk {
+                            ChunkWrapper::V5(c) => c,
+                            ChunkWrapper::V6(c) => c,
+                        };
+                        CacheChunk {
+                            id: info.block_id.to_string(),
+                            index: info.index,
+                            flags: info.flags.bits(),
+                            compressed_offset: info.compress_offset,
+                            compressed_size: info.compress_size,
+                            uncompressed_offset: info.uncompress_offset,
+                            uncompressed_size: info.uncompress_size,
+                            file_offset: info.file_offset,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let entry = CacheEntry {
+            version: BUILD_CACHE_VERSION,
+            layer_digest: layer_digest.to_string(),
+            blob,
+            nodes,
+        };
+        let file = File::create(tmp_dir.join(ENTRY_FILE))?;
+        serde_json::to_writer(file, &entry).context("failed to write build cache entry")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::core::node::{Overlay, XattrFilter};
+    use crate::builder::core::platform::SourceDefaults;
+    use rafs::metadata::RAFS_DEFAULT_CHUNK_SIZE;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn layer_nodes(source: &Path) -> Vec<Node> {
+        vec![Node::new(
+            RafsVersion::V5,
+            source.to_path_buf(),
+            source.join("foo"),
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
+        )
+        .unwrap()]
+    }
+
+    #[test]
+    fn test_parse_digest() {
+        let digest = RafsDigest::from_buf(b"build cache", digest::Algorithm::Sha256);
+        assert_eq!(parse_digest(&digest.to_string()).unwrap(), digest);
+        assert!(parse_digest("1234").is_err());
+        assert!(parse_digest(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_build_cache_hit_and_miss() {
+        let source = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let cache = BuildCache::new(cache_dir.as_path()).unwrap();
+        let ctx = BuildContext::default();
+        let path = source.as_path().join("foo");
+        fs::write(&path, b"foo").unwrap();
+        let file_digest = RafsDigest::from_buf(b"foo", digest::Algorithm::Sha256);
+
+        let mut nodes = layer_nodes(source.as_path());
+        let layer_digest = BuildCache::layer_digest(&ctx, &nodes).unwrap();
+        assert!(cache.load(&ctx, &layer_digest, None, &mut nodes).is_none());
+        nodes[0].inode.set_digest(file_digest);
+        cache.store(&layer_digest, None, None, &nodes).unwrap();
+
+        let mut nodes = layer_nodes(source.as_path());
+        assert_eq!(
+            BuildCache::layer_digest(&ctx, &nodes).unwrap(),
+            layer_digest
+        );
+        let cached = cache.load(&ctx, &layer_digest, None, &mut nodes);
+        assert!(cached.unwrap().is_none());
+        assert_eq!(nodes[0].inode.digest(), &file_digest);
+
+        // Content changes without changing the file size change the layer digest.
+        fs::write(&path, b"bar").unwrap();
+        let mut nodes = layer_nodes(source.as_path());
+        let digest2 = BuildCache::layer_digest(&ctx, &nodes).unwrap();
+        assert_ne!(digest2, layer_digest);
+        assert!(cache.load(&ctx, &digest2, None, &mut nodes).is_none());
+
+        // Identical content at another location with another mtime hits the cache.
+        let source2 = TempDir::new().unwrap();
+        fs::write(source2.as_path().join("foo"), b"foo").unwrap();
+        let nodes = layer_nodes(source2.as_path());
+        assert_eq!(
+            BuildCache::layer_digest(&ctx, &nodes).unwrap(),
+            layer_digest
+        );
+    }
+}
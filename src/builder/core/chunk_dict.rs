@@ -0,0 +1,402 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nydus_utils::digest::RafsDigest;
+use rafs::metadata::layout::v5::RafsV5ChunkInfo;
+use rafs::metadata::{RafsMode, RafsSuper, RAFS_DEFAULT_CHUNK_SIZE};
+use serde::{Deserialize, Serialize};
+use storage::device::{BlobChunkFlags, BlobFeatures, BlobInfo};
+
+use crate::builder::core::context::{BuildContext, RafsVersion};
+use crate::builder::core::node::ChunkWrapper;
+use crate::builder::core::tree::Tree;
+
+// Maximum number of chunk digests to query from a remote chunk dictionary per request.
+const REMOTE_DICT_BATCH_SIZE: usize = 1024;
+// Timeout in seconds of requests to a remote chunk dictionary.
+const REMOTE_DICT_TIMEOUT_SECS: u64 = 60;
+
+pub trait ChunkDict: Sync + Send + 'static {
+    fn add_chunk(&mut self, chunk: ChunkWrapper);
+    fn get_chunk(&self, digest: &RafsDigest) -> Option<&ChunkWrapper>;
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>>;
+    fn set_real_blob_idx(&self, inner_idx: u32, out_idx: u32);
+    fn get_real_blob_idx(&self, inner_idx: u32) -> u32;
+
+    /// Get the remote chunk dictionary if chunks must be looked up by digests before use.
+    fn as_remote(&self) -> Option<&RemoteChunkDict> {
+        None
+    }
+}
+
+impl ChunkDict for () {
+    fn add_chunk(&mut self, _chunk: ChunkWrapper) {}
+
+    fn get_chunk(&self, _digest: &RafsDigest) -> Option<&ChunkWrapper> {
+        None
+    }
+
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>> {
+        Vec::new()
+    }
+
+    fn set_real_blob_idx(&self, _inner_idx: u32, _out_idx: u32) {
+        panic!("()::set_real_blob_idx() should not be invoked");
+    }
+
+    fn get_real_blob_idx(&self, inner_idx: u32) -> u32 {
+        inner_idx
+    }
+}
+
+#[derive(Default)]
+pub struct HashChunkDict {
+    pub m: HashMap<RafsDigest, (ChunkWrapper, AtomicU32)>,
+    blobs: Vec<Arc<BlobInfo>>,
+    blob_idx_m: Mutex<BTreeMap<u32, u32>>,
+}
+
+impl ChunkDict for HashChunkDict {
+    fn add_chunk(&mut self, chunk: ChunkWrapper) {
+        if let Some(e) = self.m.get(chunk.id()) {
+            e.1.fetch_add(1, Ordering::AcqRel);
+        } else {
+            self.m
+                .insert(chunk.id().to_owned(), (chunk, AtomicU32::new(1)));
+        }
+    }
+
+    fn get_chunk(&self, digest: &RafsDigest) -> Option<&ChunkWrapper> {
+        self.m.get(digest).map(|e| &e.0)
+    }
+
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>> {
+        self.blobs.clone()
+    }
+
+    fn set_real_blob_idx(&self, inner_idx: u32, out_idx: u32) {
+        self.blob_idx_m.lock().unwrap().insert(inner_idx, out_idx);
+    }
+
+    fn get_real_blob_idx(&self, inner_idx: u32) -> u32 {
+        *self
+            .blob_idx_m
+            .lock()
+            .unwrap()
+            .get(&inner_idx)
+            .unwrap_or(&inner_idx)
+    }
+}
+
+impl HashChunkDict {
+    fn from_bootstrap_file(path: &str) -> Result<Self> {
+        let rs = RafsSuper::load_from_metadata(path, RafsMode::Direct, true)
+            .with_context(|| format!("failed to open bootstrap file {:?}", path))?;
+        let mut d = HashChunkDict {
+            m: HashMap::new(),
+            blobs: rs.superblock.get_blob_infos(),
+            blob_idx_m: Mutex::new(BTreeMap::new()),
+        };
+
+        Tree::from_bootstrap(&rs, &mut d).context("failed to build tree from bootstrap")?;
+
+        Ok(d)
+    }
+}
+
+#[derive(Serialize)]
+struct RemoteDictRequest {
+    digester: String,
+    compressor: String,
+    digests: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RemoteDictBlob {
+    blob_id: String,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    chunk_count: u32,
+}
+
+#[derive(Deserialize)]
+struct RemoteDictChunk {
+    digest: String,
+    blob_id: String,
+    index: u32,
+    compressed_offset: u64,
+    compressed_size: u32,
+    uncompressed_offset: u64,
+    uncompressed_size: u32,
+    #[serde(default)]
+    compressed: bool,
+}
+
+#[derive(Deserialize)]
+struct RemoteDictResponse {
+    #[serde(default)]
+    blobs: Vec<RemoteDictBlob>,
+    #[serde(default)]
+    chunks: Vec<RemoteDictChunk>,
+}
+
+/// Chunk dictionary backed by a remote deduplication service over HTTP.
+///
+/// The dictionary can't be enumerated, so chunks of the image being built are looked up by
+/// digests in batches before dumping the data blob, with requests of:
+/// `POST <url> {"digester": "blake3", "compressor": "lz4_block", "digests": ["<hex>", ...]}`
+/// The service replies with the known chunks and their blobs:
+/// `{"blobs": [{"blob_id", "compressed_size", "uncompressed_size", "chunk_count"}],
+///   "chunks": [{"digest", "blob_id", "index", "compressed_offset", "compressed_size",
+///               "uncompressed_offset", "uncompressed_size", "compressed"}]}`
+pub struct RemoteChunkDict {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl RemoteChunkDict {
+    fn new(url: &str) -> Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(REMOTE_DICT_TIMEOUT_SECS))
+            .build()
+            .context("failed to create http client for remote chunk dict")?;
+
+        Ok(RemoteChunkDict {
+            url: url.to_string(),
+            client,
+        })
+    }
+
+    /// Look up chunks with `digests`, and return a dictionary of the found chunks.
+    pub fn lookup(&self, ctx: &BuildContext, digests: &[RafsDigest]) -> Result<HashChunkDict> {
+        let mut dict = HashChunkDict::default();
+        let mut blob_indexes: HashMap<String, u32> = HashMap::new();
+
+        for batch in digests.chunks(REMOTE_DICT_BATCH_SIZE) {
+            let batch: HashMap<String, RafsDigest> =
+                batch.iter().map(|d| (d.to_string(), *d)).collect();
+            let resp = self.query(ctx, batch.keys().cloned().collect())?;
+
+            for blob in resp.blobs {
+                if blob_indexes.contains_key(&blob.blob_id) {
+                    continue;
+                }
+                let index = dict.blobs.len() as u32;
+                blob_indexes.insert(blob.blob_id.clone(), index);
+                dict.blobs.push(Arc::new(BlobInfo::new(
+                    index,
+                    blob.blob_id,
+                    blob.uncompressed_size,
+                    blob.compressed_size,
+                    RAFS_DEFAULT_CHUNK_SIZE as u32,
+                    blob.chunk_count,
+                    BlobFeatures::empty(),
+                )));
+            }
+            for chunk in resp.chunks {
+                let digest = batch.get(&chunk.digest).ok_or_else(|| {
+                    anyhow!("remote chunk dict replied unknown digest {}", chunk.digest)
+                })?;
+                let blob_index = *blob_indexes.get(&chunk.blob_id).ok_or_else(|| {
+                    anyhow!("remote chunk dict replied unknown blob {}", chunk.blob_id)
+                })?;
+                let mut info = RafsV5ChunkInfo {
+                    block_id: *digest,
+                    blob_index,
+                    compress_size: chunk.compressed_size,
+                    uncompress_size: chunk.uncompressed_size,
+                    compress_offset: chunk.compressed_offset,
+                    uncompress_offset: chunk.uncompressed_offset,
+                    index: chunk.index,
+                    ..Default::default()
+                };
+                info.flags.set(BlobChunkFlags::COMPRESSED, chunk.compressed);
+                dict.add_chunk(match ctx.fs_version {
+                    RafsVersion::V5 => ChunkWrapper::V5(info),
+                    RafsVersion::V6 => ChunkWrapper::V6(info),
+                });
+            }
+        }
+        info!(
+            "found {} of {} chunks from remote chunk dict {}",
+            dict.m.len(),
+            digests.len(),
+            self.url
+        );
+
+        Ok(dict)
+    }
+
+    fn query(&self, ctx: &BuildContext, digests: Vec<String>) -> Result<RemoteDictResponse> {
+        let req = RemoteDictRequest {
+            digester: ctx.digester.to_string(),
+            compressor: ctx.compressor.to_string(),
+            digests,
+        };
+        self.client
+            .post(&self.url)
+            .json(&req)
+            .send()
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.json::<RemoteDictResponse>())
+            .with_context(|| format!("failed to query remote chunk dict {}", self.url))
+    }
+}
+
+impl ChunkDict for RemoteChunkDict {
+    fn add_chunk(&mut self, _chunk: ChunkWrapper) {}
+
+    fn get_chunk(&self, _digest: &RafsDigest) -> Option<&ChunkWrapper> {
+        None
+    }
+
+    fn get_blobs(&self) -> Vec<Arc<BlobInfo>> {
+        Vec::new()
+    }
+
+    fn set_real_blob_idx(&self, _inner_idx: u32, _out_idx: u32) {
+        panic!("RemoteChunkDict::set_real_blob_idx() should not be invoked");
+    }
+
+    fn get_real_blob_idx(&self, inner_idx: u32) -> u32 {
+        inner_idx
+    }
+
+    fn as_remote(&self) -> Option<&RemoteChunkDict> {
+        Some(self)
+    }
+}
+
+/// Load a chunk dictionary from external source.
+///
+/// # Argument
+/// `arg` may be in inform of:
+/// - type=path: type of external source and corresponding path
+/// - path: type default to "bootstrap"
+///
+/// for example:
+///     bootstrap=image.boot
+///     image.boot
+///     ~/image/image.boot
+///     boltdb=/var/db/dict.db (not supported yet)
+///     http://dedup.example.com/api/v1/chunks (remote chunk dictionary service)
+pub fn import_chunk_dict(arg: &str) -> Result<Arc<dyn ChunkDict>> {
+    if arg.starts_with("http://") || arg.starts_with("https://") {
+        info!("import remote chunk dict {}", arg);
+        return RemoteChunkDict::new(arg).map(|d| Arc::new(d) as Arc<dyn ChunkDict>);
+    }
+
+    let (file_type, file_path) = match arg.find('=') {
+        None => ("bootstrap", arg),
+        Some(idx) => (&arg[0..idx], &arg[idx + 1..]),
+    };
+
+    info!("import chunk dict file {}={}", file_type, file_path);
+    match file_type {
+        "bootstrap" => {
+            HashChunkDict::from_bootstrap_file(file_path).map(|d| Arc::new(d) as Arc<dyn ChunkDict>)
+        }
+        _ => Err(std::io::Error::from_raw_os_error(libc::EINVAL))
+            .with_context(|| format!("invalid chunk dict type {}", file_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::core::context::RafsVersion;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_null_dict() {
+        let mut dict = Box::new(()) as Box<dyn ChunkDict>;
+
+        let chunk = ChunkWrapper::new(RafsVersion::V5);
+        dict.add_chunk(chunk.clone());
+        assert!(dict.get_chunk(chunk.id()).is_none());
+        assert_eq!(dict.get_blobs().len(), 0);
+        assert_eq!(dict.get_real_blob_idx(5), 5);
+    }
+
+    #[test]
+    fn test_chunk_dict() {
+        let root_dir = &std::env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+        let mut source_path = PathBuf::from(root_dir);
+        source_path.push("tests/texture/bootstrap/image_v2.boot");
+        let path = source_path.to_str().unwrap();
+        let dict = import_chunk_dict(path).unwrap();
+
+        assert!(dict.get_chunk(&RafsDigest::default()).is_none());
+        assert_eq!(dict.get_blobs().len(), 18);
+        dict.set_real_blob_idx(0, 10);
+        assert_eq!(dict.get_real_blob_idx(0), 10);
+        assert_eq!(dict.get_real_blob_idx(1), 1);
+    }
+
+    #[test]
+    fn test_remote_chunk_dict() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/chunks", listener.local_addr().unwrap());
+        let found = RafsDigest::from_buf(b"found", nydus_utils::digest::Algorithm::Blake3);
+        let missing = RafsDigest::from_buf(b"missing", nydus_utils::digest::Algorithm::Blake3);
+        let body = format!(
+            r#"{{"blobs": [{{"blob_id": "blob1", "compressed_size": 4096, "uncompressed_size": 8192, "chunk_count": 2}}],
+               "chunks": [{{"digest": "{}", "blob_id": "blob1", "index": 1, "compressed_offset": 2048,
+                            "compressed_size": 2048, "uncompressed_offset": 4096, "uncompressed_size": 4096,
+                            "compressed": true}}]}}"#,
+            found
+        );
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse::<usize>().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut req = vec![0u8; len];
+            reader.read_exact(&mut req).unwrap();
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            (&stream).write_all(resp.as_bytes()).unwrap();
+            String::from_utf8(req).unwrap()
+        });
+
+        let dict = import_chunk_dict(&url).unwrap();
+        let remote = dict.as_remote().unwrap();
+        let ctx = BuildContext::default();
+        let resolved = remote.lookup(&ctx, &[found, missing]).unwrap();
+        let req = server.join().unwrap();
+        assert!(req.contains(&found.to_string()));
+        assert!(req.contains(&missing.to_string()));
+
+        assert_eq!(resolved.get_blobs().len(), 1);
+        assert_eq!(resolved.get_blobs()[0].blob_id(), "blob1");
+        assert!(resolved.get_chunk(&missing).is_none());
+        let chunk = resolved.get_chunk(&found).unwrap();
+        assert_eq!(chunk.index(), 1);
+        assert_eq!(chunk.blob_index(), 0);
+        assert_eq!(chunk.compressed_offset(), 2048);
+        assert_eq!(chunk.uncompressed_size(), 4096);
+        assert!(chunk.is_compressed());
+    }
+}
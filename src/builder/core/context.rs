@@ -5,10 +5,11 @@
 //! Struct to maintain context information for the image builder.
 
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fs::{remove_file, rename, File, OpenOptions};
 use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::mem::size_of;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -19,7 +20,7 @@ use serde::Serialize;
 use sha2::{Digest, Sha256};
 use vmm_sys_util::tempfile::TempFile;
 
-use nydus_utils::digest;
+use nydus_utils::digest::{self, RafsDigest};
 use nydus_utils::div_round_up;
 use rafs::metadata::layout::v5::RafsV5BlobTable;
 use rafs::metadata::layout::v6::RafsV6BlobTable;
@@ -32,10 +33,14 @@ use storage::device::BlobFeatures;
 use storage::device::BlobInfo;
 use storage::meta::{BlobChunkInfoOndisk, BlobMetaHeaderOndisk};
 
+use super::analysis::{BuildAnalysis, BuildAnalysisReport};
 use super::chunk_dict::{ChunkDict, HashChunkDict};
+use super::exclude::ExcludePatterns;
 use super::layout::BlobLayout;
-use super::node::{ChunkWrapper, Node, WhiteoutSpec};
+use super::node::{ChunkWrapper, Node, SpecialFilePolicy, WhiteoutSpec, XattrFilter};
+use super::platform::SourceDefaults;
 use super::prefetch::{Prefetch, PrefetchPolicy};
+use super::spill::ChunkSpill;
 
 // TODO: select BufWriter capacity by performance testing.
 pub const BUF_WRITER_CAPACITY: usize = 2 << 17;
@@ -69,6 +74,8 @@ pub enum SourceType {
     Directory,
     StargzIndex,
     Diff,
+    OciV1,
+    ZstdChunked,
 }
 
 impl Default for SourceType {
@@ -84,6 +91,8 @@ impl FromStr for SourceType {
             "directory" => Ok(Self::Directory),
             "stargz_index" => Ok(Self::StargzIndex),
             "diff" => Ok(Self::Diff),
+            "ociv1" => Ok(Self::OciV1),
+            "zstd_chunked" => Ok(Self::ZstdChunked),
             _ => Err(anyhow!("invalid source type")),
         }
     }
@@ -104,7 +113,7 @@ impl Default for ArtifactStorage {
 }
 
 impl ArtifactStorage {
-    fn get_path(&self, name: &str) -> PathBuf {
+    pub fn get_path(&self, name: &str) -> PathBuf {
         match self {
             Self::SingleFile(path) => path.to_path_buf(),
             Self::FileDir(base) => base.join(name),
@@ -117,6 +126,8 @@ impl ArtifactStorage {
 pub struct ArtifactBufferWriter {
     file: BufWriter<File>,
     storage: ArtifactStorage,
+    // Offset in the file to start writing data at, data before it is kept as is.
+    offset: u64,
     // Keep this because tmp file will be removed automatically when it is dropped.
     // But we will rename/link the tmp file before it is removed.
     tmp_file: Option<TempFile>,
@@ -146,11 +157,40 @@ impl std::io::Seek for ArtifactBufferWriter {
 
 impl ArtifactBufferWriter {
     pub fn new(storage: ArtifactStorage) -> Result<Self> {
+        Self::new_at(storage, 0)
+    }
+
+    /// Create a writer to write data starting from `offset` of the file, which is only
+    /// supported by `ArtifactStorage::SingleFile`.
+    pub fn new_at(storage: ArtifactStorage, offset: u64) -> Result<Self> {
         match storage {
+            ArtifactStorage::SingleFile(ref p) if offset > 0 => {
+                let mut f = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .open(p)
+                    .with_context(|| format!("failed to open file {:?}", p))?;
+                // Drop stale data after `offset` for regular files, block devices can't be
+                // truncated.
+                if f.metadata()?.is_file() {
+                    f.set_len(offset)
+                        .with_context(|| format!("failed to truncate file {:?}", p))?;
+                }
+                f.seek(SeekFrom::Start(offset))
+                    .with_context(|| format!("failed to seek file {:?} to {}", p, offset))?;
+                Ok(Self {
+                    file: BufWriter::with_capacity(BUF_WRITER_CAPACITY, f),
+                    storage,
+                    offset,
+                    tmp_file: None,
+                })
+            }
             ArtifactStorage::SingleFile(ref p) => {
                 let b = BufWriter::with_capacity(
                     BUF_WRITER_CAPACITY,
                     OpenOptions::new()
+                        .read(true)
                         .write(true)
                         .create(true)
                         .truncate(true)
@@ -160,9 +200,13 @@ impl ArtifactBufferWriter {
                 Ok(Self {
                     file: b,
                     storage,
+                    offset: 0,
                     tmp_file: None,
                 })
             }
+            ArtifactStorage::FileDir(_) if offset > 0 => {
+                bail!("writing data at an offset isn't supported by blob directory")
+            }
             ArtifactStorage::FileDir(ref p) => {
                 // Better we can use open(2) O_TMPFILE, but for compatibility sake, we delay this job.
                 // TODO: Blob dir existence?
@@ -172,6 +216,7 @@ impl ArtifactBufferWriter {
                 Ok(Self {
                     file: BufWriter::with_capacity(BUF_WRITER_CAPACITY, tmp2),
                     storage,
+                    offset: 0,
                     tmp_file: Some(tmp),
                 })
             }
@@ -188,6 +233,17 @@ impl ArtifactBufferWriter {
         Ok(pos)
     }
 
+    /// Flush buffered data and duplicate the file handle to read back data written so far.
+    ///
+    /// The duplicated handle shares the file offset with the writer, so the caller should seek
+    /// the writer to the expected position before writing again.
+    pub fn try_clone_file(&mut self) -> Result<File> {
+        self.file.flush()?;
+        let file = self.file.get_ref().try_clone()?;
+
+        Ok(file)
+    }
+
     pub fn release(self, name: Option<&str>) -> Result<()> {
         let mut f = self.file.into_inner()?;
         f.flush()?;
@@ -210,6 +266,10 @@ impl ArtifactBufferWriter {
                 })?;
             }
         } else if let ArtifactStorage::SingleFile(s) = &self.storage {
+            // The file is shared with other data before `offset`, so keep it.
+            if self.offset > 0 {
+                return Ok(());
+            }
             // `new_name` is None means no blob is really built, perhaps due to dedup.
             // We don't want to puzzle user, so delete it from here.
             // In the future, FIFO could be leveraged, don't remove it then.
@@ -234,12 +294,18 @@ pub struct BlobContext {
     pub blob_meta_info_enabled: bool,
     /// Blob metadata header stored in the data blob, for v6
     pub blob_meta_header: BlobMetaHeaderOndisk,
+    /// Sha256 digest of the compressed chunk information array stored in the data blob.
+    pub blob_meta_digest: RafsDigest,
+    /// Sha256 hasher over digests of chunks stored in the data blob, as the source digest.
+    pub source_hash: Sha256,
 
     /// Final compressed blob file size.
     pub compressed_blob_size: u64,
     /// Final expected blob cache file size.
     pub decompressed_blob_size: u64,
 
+    /// Offset of blob data in the blob file, compressed offsets of chunks include it.
+    pub blob_offset: u64,
     /// Current blob offset cursor for writing to disk file.
     pub compress_offset: u64,
     pub decompress_offset: u64,
@@ -255,17 +321,31 @@ pub struct BlobContext {
 
     // Blob writer for writing to disk file.
     pub writer: Option<ArtifactBufferWriter>,
+    /// Blobs sealed after reaching the blob size limit, in order of their blob indexes.
+    pub sealed_blobs: Vec<BlobContext>,
 }
 
 impl BlobContext {
-    pub fn new(blob_id: String, blob_stor: Option<ArtifactStorage>) -> Result<Self> {
+    pub fn new(
+        blob_id: String,
+        blob_stor: Option<ArtifactStorage>,
+        blob_offset: u64,
+    ) -> Result<Self> {
         let writer = if let Some(blob_stor) = blob_stor {
-            Some(ArtifactBufferWriter::new(blob_stor)?)
+            Some(ArtifactBufferWriter::new_at(blob_stor, blob_offset)?)
         } else {
             None
         };
 
-        Ok(Self::new_with_writer(blob_id, writer))
+        let mut ctx = Self::new_with_writer(blob_id, writer);
+        ctx.blob_offset = blob_offset;
+        ctx.compress_offset = blob_offset;
+        // Chunk data is still read to compute digests when not writing blob data.
+        if ctx.chunk_data_buf.is_empty() {
+            ctx.chunk_data_buf = vec![0u8; RAFS_MAX_CHUNK_SIZE as usize];
+        }
+
+        Ok(ctx)
     }
 
     pub fn new_with_writer(blob_id: String, writer: Option<ArtifactBufferWriter>) -> Self {
@@ -283,10 +363,13 @@ impl BlobContext {
             blob_meta_info_enabled: false,
             blob_meta_info: Vec::new(),
             blob_meta_header: BlobMetaHeaderOndisk::default(),
+            blob_meta_digest: RafsDigest::default(),
+            source_hash: Sha256::new(),
 
             compressed_blob_size: 0,
             decompressed_blob_size: 0,
 
+            blob_offset: 0,
             compress_offset: 0,
             decompress_offset: 0,
 
@@ -296,6 +379,7 @@ impl BlobContext {
             chunk_dict: Arc::new(()),
 
             writer,
+            sealed_blobs: Vec::new(),
         }
     }
 
@@ -309,13 +393,38 @@ impl BlobContext {
 
     pub fn set_blob_readahead_size(&mut self, ctx: &BuildContext) {
         if (self.compressed_blob_size > 0
-            || (ctx.source_type == SourceType::StargzIndex && !self.blob_id.is_empty()))
+            || ((ctx.source_type == SourceType::StargzIndex
+                || ctx.source_type == SourceType::ZstdChunked)
+                && !self.blob_id.is_empty()))
             && ctx.prefetch.policy != PrefetchPolicy::Blob
         {
             self.blob_readahead_size = 0;
         }
     }
 
+    /// Get the readahead range of the blob as `(offset, size)`.
+    ///
+    /// The blob table records readahead ranges in 32 bits, so readahead is disabled if the range
+    /// doesn't fit, such as for blobs written at an offset of 4GiB or more.
+    pub fn readahead_range(&self) -> (u32, u32) {
+        if self.blob_readahead_size == 0 {
+            return (0, 0);
+        }
+        match (
+            u32::try_from(self.blob_offset),
+            u32::try_from(self.blob_readahead_size),
+        ) {
+            (Ok(offset), Ok(size)) if offset.checked_add(size).is_some() => (offset, size),
+            _ => {
+                warn!(
+                    "readahead range of blob {} at {:#x} is out of 32 bits, disable readahead",
+                    self.blob_id, self.blob_offset
+                );
+                (0, 0)
+            }
+        }
+    }
+
     pub fn set_meta_info_enabled(&mut self, enable: bool) {
         self.blob_meta_info_enabled = enable;
     }
@@ -339,6 +448,19 @@ impl BlobContext {
         Ok(())
     }
 
+    /// Get the upper bound of the blob size after appending a chunk of `compressed_size` bytes,
+    /// including the chunk info array and its header.
+    pub fn blob_size_with_chunk(&self, compressed_size: u64) -> u64 {
+        let meta_size = if self.blob_meta_info_enabled {
+            (self.blob_meta_info.len() as u64 + 1) * size_of::<BlobChunkInfoOndisk>() as u64
+                + size_of::<BlobMetaHeaderOndisk>() as u64
+        } else {
+            0
+        };
+
+        self.compressed_blob_size + compressed_size + meta_size
+    }
+
     /// Allocate a count index sequentially in a blob.
     pub fn alloc_index(&mut self) -> Result<u32> {
         let index = self.chunk_count;
@@ -377,6 +499,9 @@ impl From<&BlobInfo> for BlobContext {
         ctx.chunk_count = blob.chunk_count();
         ctx.decompressed_blob_size = blob.uncompressed_size();
         ctx.compressed_blob_size = blob.compressed_size();
+        if let Some(provenance) = blob.provenance() {
+            ctx.blob_meta_header.set_provenance(provenance);
+        }
 
         ctx
     }
@@ -396,6 +521,12 @@ pub struct BlobManager {
     pub chunk_dict_cache: HashChunkDict,
 }
 
+impl Default for BlobManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlobManager {
     pub fn new() -> Self {
         Self {
@@ -434,6 +565,10 @@ impl BlobManager {
         self.blobs.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+
     /// Get all blob contexts (include the blob context that does not have a blob).
     pub fn get_blobs(&self) -> Vec<&Option<BlobContext>> {
         self.blobs.iter().collect()
@@ -493,6 +628,31 @@ impl BlobManager {
         Ok(())
     }
 
+    /// Look up chunks of `nodes` from the chunk dictionary if it's a remote one, and replace it
+    /// with a local dictionary of the found chunks.
+    ///
+    /// Should be called before `extend_blob_table_from_chunk_dict()`.
+    pub fn resolve_chunk_dict(&mut self, ctx: &BuildContext, nodes: &[Node]) -> Result<()> {
+        let dict = match self.chunk_dict_ref.as_remote() {
+            None => return Ok(()),
+            Some(remote) => {
+                let mut digests = Vec::new();
+                let mut seen = HashSet::new();
+                for node in nodes.iter().filter(|n| BlobLayout::should_dump_node(n)) {
+                    for digest in node.chunk_digests(ctx)? {
+                        if seen.insert(digest) {
+                            digests.push(digest);
+                        }
+                    }
+                }
+                remote.lookup(ctx, &digests)?
+            }
+        };
+        self.chunk_dict_ref = Arc::new(dict);
+
+        Ok(())
+    }
+
     pub fn to_blob_table_v5(
         &self,
         build_ctx: &BuildContext,
@@ -504,10 +664,12 @@ impl BlobManager {
         for (idx, ctx) in self.blobs.iter().enumerate() {
             if let Some(ctx) = ctx {
                 let blob_id = ctx.blob_id.clone();
-                let blob_readahead_size = u32::try_from(ctx.blob_readahead_size)?;
+                let (blob_readahead_offset, blob_readahead_size) = ctx.readahead_range();
                 let chunk_count = ctx.chunk_count;
                 let decompressed_blob_size = ctx.decompressed_blob_size;
-                let compressed_blob_size = ctx.compressed_blob_size;
+                // Size of the blob file, which covers data before `blob_offset` because compressed
+                // offsets of chunks count from the start of the file.
+                let compressed_blob_size = ctx.blob_offset + ctx.compressed_blob_size;
                 let blob_features = BlobFeatures::empty();
                 let mut flags = RafsSuperFlags::empty();
                 match build_ctx.fs_version {
@@ -517,9 +679,9 @@ impl BlobManager {
                     }
                     RafsVersion::V6 => todo!(),
                 }
-                blob_table.add(
+                let blob_index = blob_table.add(
                     blob_id,
-                    0,
+                    blob_readahead_offset,
                     blob_readahead_size,
                     ctx.chunk_size,
                     chunk_count,
@@ -528,6 +690,9 @@ impl BlobManager {
                     blob_features,
                     flags,
                 );
+                if let Some(provenance) = ctx.blob_meta_header.provenance() {
+                    blob_table.set_provenance(blob_index, &provenance)?;
+                }
             }
             if idx == up_idx {
                 break;
@@ -548,10 +713,12 @@ impl BlobManager {
         for (idx, ctx) in self.blobs.iter().enumerate() {
             if let Some(ctx) = ctx {
                 let blob_id = ctx.blob_id.clone();
-                let blob_readahead_size = u32::try_from(ctx.blob_readahead_size)?;
+                let (blob_readahead_offset, blob_readahead_size) = ctx.readahead_range();
                 let chunk_count = ctx.chunk_count;
                 let decompressed_blob_size = ctx.decompressed_blob_size;
-                let compressed_blob_size = ctx.compressed_blob_size;
+                // Size of the blob file, which covers data before `blob_offset` because compressed
+                // offsets of chunks count from the start of the file.
+                let compressed_blob_size = ctx.blob_offset + ctx.compressed_blob_size;
                 let blob_features = BlobFeatures::empty();
                 let mut flags = RafsSuperFlags::empty();
                 match build_ctx.fs_version {
@@ -563,7 +730,7 @@ impl BlobManager {
                 }
                 blob_table.add(
                     blob_id,
-                    0,
+                    blob_readahead_offset,
                     blob_readahead_size,
                     ctx.chunk_size,
                     chunk_count,
@@ -596,6 +763,8 @@ pub struct BootstrapContext {
     pub offset: u64,
     /// Bootstrap file name, only be used for diff build.
     pub name: String,
+    /// Image digest of the dumped bootstrap, in hex string.
+    pub image_digest: Option<String>,
     /// Bootstrap file writer.
     storage: ArtifactStorage,
 }
@@ -609,6 +778,7 @@ impl BootstrapContext {
             nodes: Vec::new(),
             offset: EROFS_BLOCK_SIZE,
             name: String::new(),
+            image_digest: None,
             storage,
         })
     }
@@ -667,6 +837,10 @@ impl BootstrapManager {
         self.bootstraps.last().map(|b| b.name.to_owned())
     }
 
+    pub fn get_last_image_digest(&self) -> Option<String> {
+        self.bootstraps.last().and_then(|b| b.image_digest.clone())
+    }
+
     pub fn get_bootstrap_path(&self, name: &str) -> PathBuf {
         self.bootstrap_storage.get_path(name)
     }
@@ -683,6 +857,8 @@ pub struct BuildContext {
     pub aligned_chunk: bool,
     /// Blob chunk compress flag.
     pub compressor: compress::Algorithm,
+    /// Store chunks uncompressed if compression doesn't help.
+    pub compress_heuristics: bool,
     /// Inode and chunk digest algorithm flag.
     pub digester: digest::Algorithm,
     /// Save host uid gid in each inode.
@@ -700,6 +876,7 @@ pub struct BuildContext {
     /// - Directory: `source_path` should be a directory path
     /// - StargzIndex: `source_path` should be a stargz index json file path
     /// - Diff: `source_path` should be a directory path
+    /// - OciV1: `source_path` should be an uncompressed OCI or docker image tarball path
     pub source_path: PathBuf,
 
     /// Track file/chunk prefetch state.
@@ -707,6 +884,41 @@ pub struct BuildContext {
 
     /// Storage writing blob to single file or a directory.
     pub blob_storage: Option<ArtifactStorage>,
+    /// Offset to write blob data at in the blob file, only for single file storage.
+    pub blob_offset: u64,
+    /// Append a TOC footer to the blob to make it self-describing.
+    pub blob_toc: bool,
+    /// Builder version recorded in the blob table and the blob TOC.
+    pub builder_version: String,
+    /// Build time recorded in the blob table and the blob TOC, seconds since UNIX epoch, 0 for
+    /// repeatable builds.
+    pub build_time: u64,
+    /// Compute chunks and digests without writing blob data, only generate the bootstrap.
+    pub metadata_only: bool,
+    /// Maximum size of each data blob, a new blob is started when exceeded, 0 means unlimited.
+    pub blob_size_limit: u64,
+    /// Move chunks of nodes into a spill file after dumping them to the data blob, if the image
+    /// has more nodes than the threshold, 0 means never.
+    pub chunk_spill_threshold: usize,
+    /// Directory to create the spill file in, defaults to the system temporary directory.
+    pub chunk_spill_dir: Option<PathBuf>,
+    /// Spill file created by `prepare_chunk_spill()`.
+    pub chunk_spill: Option<Arc<ChunkSpill>>,
+
+    /// Generate name index for large directories, only for Rafs v6.
+    pub dirent_index: bool,
+    /// Don't generate chunks for holes of sparse files.
+    pub sparse_file: bool,
+    /// Rules to exclude xattrs of source files from the image.
+    pub xattr_filter: XattrFilter,
+    /// Glob patterns to exclude files and directories from the source directory.
+    pub excludes: ExcludePatterns,
+    /// Owner and permission bits to apply to source files.
+    pub source_defaults: SourceDefaults,
+    /// Special files to keep in the image.
+    pub special_files: SpecialFilePolicy,
+    /// Statistics of files in the image, only collected if enabled.
+    pub analysis: Option<BuildAnalysis>,
 }
 
 impl BuildContext {
@@ -727,6 +939,7 @@ impl BuildContext {
             blob_id,
             aligned_chunk,
             compressor,
+            compress_heuristics: false,
             digester,
             explicit_uidgid,
             whiteout_spec,
@@ -739,6 +952,23 @@ impl BuildContext {
 
             prefetch,
             blob_storage,
+            blob_offset: 0,
+            blob_toc: false,
+            builder_version: String::new(),
+            build_time: 0,
+            metadata_only: false,
+            blob_size_limit: 0,
+            chunk_spill_threshold: 0,
+            chunk_spill_dir: None,
+            chunk_spill: None,
+
+            dirent_index: false,
+            sparse_file: false,
+            xattr_filter: XattrFilter::default(),
+            excludes: ExcludePatterns::default(),
+            source_defaults: SourceDefaults::default(),
+            special_files: SpecialFilePolicy::default(),
+            analysis: None,
         }
     }
 
@@ -749,12 +979,109 @@ impl BuildContext {
     pub fn set_chunk_size(&mut self, chunk_size: u32) {
         self.chunk_size = chunk_size;
     }
+
+    pub fn set_blob_offset(&mut self, blob_offset: u64) {
+        self.blob_offset = blob_offset;
+    }
+
+    pub fn set_blob_toc(&mut self, blob_toc: bool) {
+        self.blob_toc = blob_toc;
+    }
+
+    pub fn set_builder_version(&mut self, builder_version: String) {
+        self.builder_version = builder_version;
+    }
+
+    pub fn set_build_time(&mut self, build_time: u64) {
+        self.build_time = build_time;
+    }
+
+    pub fn set_metadata_only(&mut self, metadata_only: bool) {
+        self.metadata_only = metadata_only;
+    }
+
+    pub fn set_blob_size_limit(&mut self, blob_size_limit: u64) {
+        self.blob_size_limit = blob_size_limit;
+    }
+
+    pub fn set_chunk_spill(&mut self, threshold: usize, dir: Option<PathBuf>) {
+        self.chunk_spill_threshold = threshold;
+        self.chunk_spill_dir = dir;
+    }
+
+    /// Create the spill file if the image has more than `chunk_spill_threshold` nodes.
+    pub fn prepare_chunk_spill(&mut self, nodes: usize) -> Result<()> {
+        if self.chunk_spill_threshold == 0 || nodes <= self.chunk_spill_threshold {
+            return Ok(());
+        }
+
+        let dir = self
+            .chunk_spill_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        info!(
+            "image has {} nodes, spill chunks into a temporary file in {:?}",
+            nodes, dir
+        );
+        self.chunk_spill = Some(Arc::new(ChunkSpill::new(&dir, self.fs_version)?));
+
+        Ok(())
+    }
+
+    pub fn set_dirent_index(&mut self, dirent_index: bool) {
+        self.dirent_index = dirent_index;
+    }
+
+    pub fn set_compress_heuristics(&mut self, compress_heuristics: bool) {
+        self.compress_heuristics = compress_heuristics;
+    }
+
+    pub fn set_sparse_file(&mut self, sparse_file: bool) {
+        self.sparse_file = sparse_file;
+    }
+
+    pub fn set_xattr_filter(&mut self, mut xattr_filter: XattrFilter) {
+        // Whiteout markers are needed to build from overlayfs upper directories.
+        xattr_filter.set_keep_overlayfs_whiteouts(self.whiteout_spec == WhiteoutSpec::Overlayfs);
+        self.xattr_filter = xattr_filter;
+    }
+
+    pub fn set_excludes(&mut self, excludes: ExcludePatterns) {
+        self.excludes = excludes;
+    }
+
+    pub fn set_source_defaults(&mut self, source_defaults: SourceDefaults) {
+        self.source_defaults = source_defaults;
+    }
+
+    pub fn set_special_files(&mut self, special_files: SpecialFilePolicy) {
+        self.special_files = special_files;
+    }
+
+    pub fn set_analysis(&mut self, analysis: BuildAnalysis) {
+        self.analysis = Some(analysis);
+    }
 }
 
 #[derive(Serialize, Default, Debug, Clone)]
 pub struct BuildOutputBlob {
-    blob_id: String,
-    blob_size: u64,
+    pub blob_id: String,
+    pub blob_size: u64,
+}
+
+/// BuildOutputLayer represents the output of a snapshot layer in diff build.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct BuildOutputLayer {
+    /// Path of the snapshot directory of the layer.
+    pub source_path: String,
+    /// Id of the blob of the layer, none if the layer doesn't have a blob.
+    pub blob_id: Option<String>,
+    /// Name of the bootstrap dumped for the layer, none if the layer is skipped.
+    pub bootstrap_name: Option<String>,
+    /// The number of chunks stored in the blob of the layer.
+    pub chunk_count: u32,
+    /// Whether the layer is skipped (cached) in this build.
+    pub skipped: bool,
 }
 
 /// BuildOutput represents the output in this build.
@@ -762,6 +1089,9 @@ pub struct BuildOutputBlob {
 pub struct BuildOutput {
     /// Blob infos for all layer, some layers may not have a blob.
     pub blobs: Vec<Option<BuildOutputBlob>>,
+    /// Layer to blob and bootstrap mapping for all layers in diff build, index equals
+    /// layer index.
+    pub layers: Vec<BuildOutputLayer>,
     /// Bootstrap names for all layer in this build, index equals layer index.
     pub bootstraps: Vec<String>,
     /// The size of output blob in this build.
@@ -769,6 +1099,10 @@ pub struct BuildOutput {
     /// The name of output bootstrap in this build, for the bootstrap
     /// of last layer in diff build.
     pub bootstrap_name: String,
+    /// Image digest of the output bootstrap, which identifies the whole image.
+    pub image_digest: Option<String>,
+    /// Statistics of files in the output bootstrap if enabled.
+    pub analysis: Option<BuildAnalysisReport>,
 }
 
 impl BuildOutput {
@@ -788,11 +1122,15 @@ impl BuildOutput {
         let bootstrap_name = bootstrap_mgr
             .get_last_bootstrap()
             .ok_or_else(|| anyhow!("can't get last bootstrap"))?;
+        let image_digest = bootstrap_mgr.get_last_image_digest();
         Ok(Self {
             blobs,
+            layers: Vec::new(),
             bootstraps,
             blob_size,
             bootstrap_name,
+            image_digest,
+            analysis: None,
         })
     }
 
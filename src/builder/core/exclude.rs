@@ -0,0 +1,141 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Glob patterns to exclude files and directories from the source directory.
+//!
+//! Patterns are matched against the path relative to the source root directory, with syntax:
+//! - `*` matches any sequence of characters within a path component
+//! - `?` matches any single character within a path component
+//! - `**` as a whole path component matches zero or more path components
+//! - `**` at the beginning of a path component, e.g. `**.so*`, is a shortcut for `**/*.so*`
+//!
+//! So `var/cache/**` excludes everything under `/var/cache`, and `**/*.sock` excludes files with
+//! suffix `.sock` at any depth.
+//!
+//! The patterns are also used to select files to prefetch.
+
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path};
+
+use anyhow::Result;
+
+#[derive(Clone, Debug, Default)]
+pub struct ExcludePatterns {
+    patterns: Vec<Vec<String>>,
+}
+
+impl ExcludePatterns {
+    /// Add a glob pattern to exclude files.
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        let mut components: Vec<String> = Vec::new();
+        for c in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if c.len() > 2 && c.starts_with("**") {
+                components.push("**".to_string());
+                components.push(c[1..].to_string());
+            } else {
+                components.push(c.to_string());
+            }
+        }
+        if components.is_empty() {
+            bail!("invalid exclusion pattern {:?}", pattern);
+        }
+        if components.iter().any(|c| c == "..") {
+            bail!("exclusion pattern {:?} should not contain '..'", pattern);
+        }
+        self.patterns.push(components);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Check whether `path`, relative to the source root directory, should be excluded.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.is_match(path)
+    }
+
+    /// Check whether `path`, relative to the source root directory, matches any of the patterns.
+    pub fn is_match(&self, path: &Path) -> bool {
+        let components: Vec<&[u8]> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        if components.is_empty() {
+            return false;
+        }
+
+        self.patterns
+            .iter()
+            .any(|p| Self::match_components(p, &components))
+    }
+
+    fn match_components(pattern: &[String], path: &[&[u8]]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((p, rest)) if p == "**" => {
+                (0..=path.len()).any(|i| Self::match_components(rest, &path[i..]))
+            }
+            Some((p, rest)) => {
+                !path.is_empty()
+                    && Self::match_name(p.as_bytes(), path[0])
+                    && Self::match_components(rest, &path[1..])
+            }
+        }
+    }
+
+    fn match_name(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|i| Self::match_name(rest, &name[i..])),
+            Some((b'?', rest)) => !name.is_empty() && Self::match_name(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && Self::match_name(rest, &name[1..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_patterns() {
+        let mut patterns = ExcludePatterns::default();
+        assert!(patterns.is_empty());
+        assert!(patterns.add("/").is_err());
+        assert!(patterns.add("a/../b").is_err());
+
+        patterns.add("**/*.sock").unwrap();
+        patterns.add("var/cache/**").unwrap();
+        patterns.add("/tmp/file?").unwrap();
+        assert!(!patterns.is_empty());
+
+        assert!(patterns.is_excluded(Path::new("a.sock")));
+        assert!(patterns.is_excluded(Path::new("/run/docker/a.sock")));
+        assert!(!patterns.is_excluded(Path::new("/run/a.sock.bak")));
+        assert!(patterns.is_excluded(Path::new("var/cache")));
+        assert!(patterns.is_excluded(Path::new("/var/cache/apt/archives")));
+        assert!(!patterns.is_excluded(Path::new("/var/caches")));
+        assert!(!patterns.is_excluded(Path::new("/var")));
+        assert!(patterns.is_excluded(Path::new("/tmp/file1")));
+        assert!(!patterns.is_excluded(Path::new("/tmp/file")));
+        assert!(!patterns.is_excluded(Path::new("/tmp/file12")));
+        assert!(!patterns.is_excluded(Path::new("/")));
+    }
+
+    #[test]
+    fn test_recursive_component_patterns() {
+        let mut patterns = ExcludePatterns::default();
+        patterns.add("/usr/lib/**.so*").unwrap();
+
+        assert!(patterns.is_match(Path::new("/usr/lib/libc.so")));
+        assert!(patterns.is_match(Path::new("/usr/lib/x86_64-linux-gnu/libz.so.1")));
+        assert!(!patterns.is_match(Path::new("/usr/lib/x86_64-linux-gnu")));
+        assert!(!patterns.is_match(Path::new("/usr/lib64/libc.so")));
+        assert!(!patterns.is_match(Path::new("/usr/lib/libc.a")));
+    }
+}
@@ -4,9 +4,10 @@
 
 use anyhow::Result;
 
-use crate::core::node::{Node, Overlay};
-use crate::core::prefetch::Prefetch;
+use crate::builder::core::node::{Node, Overlay};
+use crate::builder::core::prefetch::Prefetch;
 
+#[derive(Default)]
 pub struct BlobLayout {}
 
 impl BlobLayout {
@@ -24,9 +25,9 @@ impl BlobLayout {
         // NOTE: Don't try to sort readahead files by their sizes,  thus to keep files
         // belonging to the same directory arranged in adjacent in blob file. Together with
         // BFS style collecting descendants inodes, it will have a higher merging possibility.
-        // Later, we might write chunks of data one by one according to inode number order.
-        let mut readahead_files = prefetch.get_file_indexes();
-        readahead_files.sort_unstable();
+        // Files with higher access frequency weights are arranged in front, and files of the
+        // same weight are arranged by inode number order.
+        let readahead_files = prefetch.get_file_indexes();
         for index in &readahead_files {
             let index = *index as usize - 1;
             let node = &nodes[index];
@@ -48,7 +49,7 @@ impl BlobLayout {
     }
 
     #[inline]
-    fn should_dump_node(node: &Node) -> bool {
+    pub fn should_dump_node(node: &Node) -> bool {
         node.overlay == Overlay::UpperAddition || node.overlay == Overlay::UpperModification
     }
 }
@@ -0,0 +1,17 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod analysis;
+pub mod blob;
+pub mod bootstrap;
+pub mod build_cache;
+pub mod chunk_dict;
+pub mod context;
+pub mod exclude;
+pub mod layout;
+pub mod node;
+pub mod platform;
+pub mod prefetch;
+pub mod spill;
+pub mod tree;
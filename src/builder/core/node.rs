@@ -5,17 +5,18 @@
 
 //! An in-memory RAFS inode for image building and inspection.
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display, Formatter};
-use std::fs::{self, File};
-use std::io::Read;
+use std::fs::{self, File, Metadata};
 use std::io::SeekFrom;
+use std::io::{Read, Seek};
 use std::mem::size_of;
-use std::os::linux::fs::MetadataExt;
-use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{Context, Error, Result};
 use nix::sys::stat;
@@ -32,8 +33,9 @@ use rafs::metadata::layout::v5::{
 };
 use rafs::metadata::layout::v6::{
     align_offset, calculate_nid, RafsV6Dirent, RafsV6InodeChunkAddr, RafsV6InodeChunkHeader,
-    RafsV6InodeCompact, RafsV6InodeExtended, RafsV6OndiskInodeTrait, EROFS_BLOCK_SIZE,
-    EROFS_INODE_CHUNK_BASED, EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN,
+    RafsV6InodeCompact, RafsV6InodeExtended, RafsV6OndiskInodeTrait, RafsV6SharedXattrs,
+    EROFS_BLOCK_SIZE, EROFS_INODE_CHUNK_BASED, EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN,
+    EROFS_NULL_ADDR,
 };
 use rafs::metadata::layout::RafsXAttrs;
 use rafs::metadata::{Inode, RafsInode, RafsStore};
@@ -42,18 +44,23 @@ use storage::compress;
 use storage::device::v5::BlobV5ChunkInfo;
 use storage::device::{BlobChunkFlags, BlobChunkInfo};
 
+use super::blob::Blob;
 use super::chunk_dict::ChunkDict;
 use super::context::{BlobContext, BootstrapContext, BuildContext, RafsVersion};
+use super::platform::{self, SourceDefaults, SourceStat};
+use super::spill::ChunkSpill;
 use super::tree::Tree;
 
-const ROOT_PATH_NAME: &[u8] = &[b'/'];
-
 /// Prefix for OCI whiteout file.
 pub const OCISPEC_WHITEOUT_PREFIX: &str = ".wh.";
 /// Prefix for OCI whiteout opaque.
 pub const OCISPEC_WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
 /// Extended attribute key for Overlayfs whiteout opaque.
 pub const OVERLAYFS_WHITEOUT_OPAQUE: &str = "trusted.overlay.opaque";
+/// Extended attribute key for Overlayfs whiteout opaque, when mounted with `userxattr`.
+pub const OVERLAYFS_USER_WHITEOUT_OPAQUE: &str = "user.overlay.opaque";
+/// Extended attribute keys marking a regular file as an Overlayfs whiteout ("xwhiteout").
+pub const OVERLAYFS_XWHITEOUT: &[&str] = &["trusted.overlay.whiteout", "user.overlay.whiteout"];
 
 // # Overlayfs Whiteout
 // In order to support rm and rmdir without changing the lower filesystem, an overlay filesystem
@@ -68,6 +75,11 @@ pub const OVERLAYFS_WHITEOUT_OPAQUE: &str = "trusted.overlay.opaque";
 // filesystem contains an opaque directory, any directory in the lower filesystem with the same
 // name is ignored.
 //
+// Overlayfs mounted with the `userxattr` option (Linux 5.11+) uses the “user.overlay.” xattr
+// namespace instead of “trusted.overlay.”. Since Linux 6.7, a whiteout may also be an empty
+// regular file with the xattr “overlay.whiteout” (an "xwhiteout"), and its parent directory is
+// marked by setting “overlay.opaque” to “x”, which doesn't make the directory opaque.
+//
 // # OCI Image Whiteout
 // - A whiteout file is an empty file with a special filename that signifies a path should be
 //   deleted.
@@ -102,6 +114,9 @@ pub enum WhiteoutSpec {
     Oci,
     /// "whiteouts and opaque directories" in https://www.kernel.org/doc/Documentation/filesystems/overlayfs.txt
     Overlayfs,
+    /// No whiteout semantics, e.g. for a directory exported from a container, all files are kept
+    /// verbatim.
+    None,
 }
 
 impl Default for WhiteoutSpec {
@@ -117,11 +132,155 @@ impl FromStr for WhiteoutSpec {
         match s {
             "oci" => Ok(Self::Oci),
             "overlayfs" => Ok(Self::Overlayfs),
+            "none" => Ok(Self::None),
             _ => Err(anyhow!("invalid whiteout spec")),
         }
     }
 }
 
+/// Rules to exclude extended attributes from built images.
+///
+/// A pattern matches a xattr key exactly, or matches all keys with the same prefix if it ends
+/// with `*`, e.g. `user.overlay.*`.
+///
+/// Xattrs which can't be read due to insufficient permission, e.g. `security.*` xattrs when
+/// building as non-root, are dropped and recorded, or fail the build in strict mode.
+#[derive(Clone, Debug, Default)]
+pub struct XattrFilter {
+    patterns: Vec<String>,
+    strict: bool,
+    // Keep overlayfs whiteout markers even if matching a pattern, e.g. `user.overlay.*`.
+    keep_overlayfs_whiteouts: bool,
+    // Number of files with unreadable xattrs, indexed by xattr namespace. Shared by clones.
+    denials: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl XattrFilter {
+    /// Xattrs excluded by the `default` profile, which are host specific and shouldn't leak
+    /// into images.
+    pub const DEFAULT_EXCLUDES: &'static [&'static str] = &[
+        "security.selinux",
+        "security.ima",
+        "security.evm",
+        "user.overlay.*",
+    ];
+
+    /// Create a filter from the named exclusion profile, `none` or `default`.
+    pub fn from_profile(profile: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        match profile {
+            "none" => {}
+            "default" => {
+                for pattern in Self::DEFAULT_EXCLUDES {
+                    filter.add(pattern)?;
+                }
+            }
+            _ => bail!("invalid xattr exclusion profile {}", profile),
+        }
+        Ok(filter)
+    }
+
+    /// Add a exclusion pattern.
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        let prefix = pattern.strip_suffix('*').unwrap_or(pattern);
+        if prefix.is_empty() || prefix.contains('*') {
+            bail!("invalid xattr exclusion pattern {:?}", pattern);
+        }
+        self.patterns.push(pattern.to_string());
+        Ok(())
+    }
+
+    /// Fail the build instead of dropping xattrs which can't be read due to permission.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Keep xattrs marking overlayfs whiteouts and opaque directories, which are needed to apply
+    /// the overlayfs whiteout spec and stripped after that.
+    pub fn set_keep_overlayfs_whiteouts(&mut self, keep: bool) {
+        self.keep_overlayfs_whiteouts = keep;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Get number of files with xattrs dropped due to insufficient permission, per namespace.
+    ///
+    /// The namespace is `*` if xattrs of the file can't be listed at all.
+    pub fn denials(&self) -> BTreeMap<String, u64> {
+        self.denials.lock().unwrap().clone()
+    }
+
+    // Record xattrs of `path` in `namespaces` which can't be read due to permission.
+    fn deny(&self, path: &Path, namespaces: BTreeSet<String>) -> Result<()> {
+        if self.strict {
+            bail!(
+                "permission denied to read xattrs of {:?} in namespace {:?}",
+                path,
+                namespaces
+            );
+        }
+        warn!(
+            "drop xattrs of {:?} in namespace {:?}, permission denied",
+            path, namespaces
+        );
+        let mut denials = self.denials.lock().unwrap();
+        for ns in namespaces {
+            *denials.entry(ns).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    /// Check whether the xattr `key` should be excluded.
+    pub fn is_excluded(&self, key: &OsStr) -> bool {
+        if self.keep_overlayfs_whiteouts
+            && (key == OVERLAYFS_WHITEOUT_OPAQUE
+                || key == OVERLAYFS_USER_WHITEOUT_OPAQUE
+                || OVERLAYFS_XWHITEOUT.iter().any(|k| key == *k))
+        {
+            return false;
+        }
+        let key = platform::os_str_to_bytes(key);
+        self.patterns.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix.as_bytes()),
+            None => key == p.as_bytes(),
+        })
+    }
+}
+
+fn is_permission_error(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EPERM) || e.raw_os_error() == Some(libc::EACCES)
+}
+
+// Get namespace of the xattr `key`, e.g. `security` for `security.capability`.
+fn xattr_namespace(key: &OsStr) -> String {
+    let key = key.to_string_lossy();
+    key.split('.').next().unwrap_or_default().to_string()
+}
+
+/// Policy to keep special files of the source in built images.
+///
+/// Fifos are always kept. Device files and sockets are kept by default too, but may be dropped
+/// since they're only meaningful on the host creating them, except overlayfs whiteouts which are
+/// character devices with 0/0 device number.
+#[derive(Clone, Copy, Debug)]
+pub struct SpecialFilePolicy {
+    /// Keep character and block device files.
+    pub allow_devices: bool,
+    /// Keep unix domain sockets.
+    pub allow_sockets: bool,
+}
+
+impl Default for SpecialFilePolicy {
+    fn default() -> Self {
+        SpecialFilePolicy {
+            allow_devices: true,
+            allow_sockets: true,
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Overlay {
@@ -168,6 +327,8 @@ pub struct Node {
     pub inode: InodeWrapper,
     /// Chunks info list of regular file
     pub chunks: Vec<ChunkWrapper>,
+    /// Offset of chunks in the spill file, if moved out of `chunks` to save memory.
+    pub spilled_chunks: Option<u64>,
     /// Extended attributes.
     pub xattrs: RafsXAttrs,
     /// Symlink info of symlink file
@@ -178,16 +339,18 @@ pub struct Node {
     pub v6_compact_inode: bool,
     /// Whether it forcely uses an extended inode.
     pub v6_force_extended_inode: bool,
+    /// Xattrs shared by multiple inodes, which are referenced instead of stored inline.
+    pub v6_shared_xattrs: Option<Arc<RafsV6SharedXattrs>>,
     /// Whether the explicit UID/GID feature is enabled or not.
     pub explicit_uidgid: bool,
     /// Absolute path of the source root directory.
-    pub(crate) source: PathBuf,
+    pub source: PathBuf,
     /// Absolute path of the source file/directory.
-    pub(crate) path: PathBuf,
+    pub path: PathBuf,
     /// Absolute path within the target RAFS filesystem.
-    pub(crate) target: PathBuf,
+    pub target: PathBuf,
     /// Parsed version of `target`.
-    pub(crate) target_vec: Vec<OsString>,
+    pub target_vec: Vec<OsString>,
     /// Last status change time of the file, in nanoseconds.
     pub ctime: i64,
     /// Used by rafsv6 inode datalayout
@@ -222,6 +385,7 @@ impl Display for Node {
 }
 
 impl Node {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         version: RafsVersion,
         source: PathBuf,
@@ -229,6 +393,8 @@ impl Node {
         overlay: Overlay,
         chunk_size: u32,
         explicit_uidgid: bool,
+        xattr_filter: &XattrFilter,
+        defaults: &SourceDefaults,
     ) -> Result<Node> {
         let target = Self::generate_target(&path, &source);
         let target_vec = Self::generate_target_vec(&target);
@@ -245,6 +411,7 @@ impl Node {
             overlay,
             inode: InodeWrapper::new(version),
             chunks: Vec::new(),
+            spilled_chunks: None,
             symlink: None,
             xattrs: RafsXAttrs::default(),
             explicit_uidgid,
@@ -254,14 +421,37 @@ impl Node {
             v6_datalayout: EROFS_INODE_FLAT_PLAIN,
             v6_force_extended_inode: false,
             v6_compact_inode: false,
+            v6_shared_xattrs: None,
         };
 
-        node.build_inode(chunk_size)
+        node.build_inode(chunk_size, xattr_filter, defaults)
             .context("failed to build inode")?;
 
         Ok(node)
     }
 
+    /// Move chunks into the spill file to save memory, they are loaded back by `load_chunks()`.
+    pub fn spill_chunks(&mut self, spill: &ChunkSpill) -> Result<()> {
+        if self.chunks.is_empty() {
+            return Ok(());
+        }
+        self.spilled_chunks = Some(spill.store(&self.chunks)?);
+        self.chunks = Vec::new();
+
+        Ok(())
+    }
+
+    /// Get chunks of the node, loading them from the spill file if they have been spilled.
+    pub fn load_chunks(&self, spill: Option<&ChunkSpill>) -> Result<Cow<[ChunkWrapper]>> {
+        match (self.spilled_chunks, spill) {
+            (Some(offset), Some(spill)) => {
+                Ok(Cow::Owned(spill.load(offset, self.inode.child_count())?))
+            }
+            (Some(_), None) => bail!("no spill file to load chunks of {}", self),
+            (None, _) => Ok(Cow::Borrowed(&self.chunks)),
+        }
+    }
+
     /// Delete an extend attribute with id `key`.
     pub fn remove_xattr(&mut self, key: &OsStr) {
         self.xattrs.remove(key);
@@ -281,8 +471,10 @@ impl Node {
             return Ok(0);
         } else if self.is_symlink() {
             if let Some(symlink) = self.symlink.as_ref() {
-                self.inode
-                    .set_digest(RafsDigest::from_buf(symlink.as_bytes(), ctx.digester));
+                self.inode.set_digest(RafsDigest::from_buf(
+                    &platform::os_str_to_bytes(symlink),
+                    ctx.digester,
+                ));
                 return Ok(0);
             } else {
                 return Err(Error::msg("inode's symblink is invalid."));
@@ -297,6 +489,7 @@ impl Node {
             .with_context(|| format!("failed to open node file {:?}", self.path))?;
         let mut inode_hasher = RafsDigest::hasher(ctx.digester);
         let mut blob_size = 0u64;
+        let mut has_hole = false;
 
         // `child_count` of regular file is reused as `chunk_count`.
         for i in 0..self.inode.child_count() {
@@ -312,6 +505,15 @@ impl Node {
                 chunk_size
             };
 
+            // Skip chunks within holes of sparse files, no chunk is generated for them.
+            if ctx.sparse_file && Self::is_hole(&file, file_offset, chunk_size as u64)? {
+                file.seek(SeekFrom::Start(file_offset + chunk_size as u64))
+                    .with_context(|| format!("failed to seek node file {:?}", self.path))?;
+                event_tracer!("hole_chunks", +1);
+                has_hole = true;
+                continue;
+            }
+
             let mut chunk_data = &mut blob_ctx.chunk_data_buf[0..chunk_size as usize];
             file.read_exact(&mut chunk_data)
                 .with_context(|| format!("failed to read node file {:?}", self.path))?;
@@ -359,10 +561,31 @@ impl Node {
             }
 
             // Compress chunk data
-            let (compressed, is_compressed) = compress::compress(&chunk_data, ctx.compressor)
-                .with_context(|| format!("failed to compress node file {:?}", self.path))?;
+            let (compressed, is_compressed) = if ctx.compress_heuristics {
+                compress::compress_with_heuristics(&chunk_data, ctx.compressor)
+            } else {
+                compress::compress(&chunk_data, ctx.compressor)
+            }
+            .with_context(|| format!("failed to compress node file {:?}", self.path))?;
             let compressed_size = compressed.len();
 
+            // Start a new blob if the chunk doesn't fit into the blob being dumped.
+            let compressed = if ctx.blob_size_limit > 0
+                && blob_ctx.chunk_count > 0
+                && blob_ctx.blob_size_with_chunk(compressed_size as u64) > ctx.blob_size_limit
+            {
+                // Compressed data may refer to the data buffer of the blob to be sealed.
+                let compressed = compressed.into_owned();
+                Blob::split(ctx, blob_ctx)?;
+                Cow::Owned(compressed)
+            } else {
+                compressed
+            };
+            let blob_index = blob_index + blob_ctx.sealed_blobs.len() as u32;
+            if blob_index > u8::MAX as u32 {
+                bail!("too many blobs after splitting by the blob size limit");
+            }
+
             // Move cursor to offset of next chunk
             let aligned_chunk_size = if ctx.aligned_chunk {
                 // Safe to unwrap because `chunk_size` is much less than u32::MAX.
@@ -380,6 +603,7 @@ impl Node {
             blob_ctx.compressed_blob_size += compressed_size as u64;
             blob_ctx.decompress_offset += aligned_chunk_size as u64;
             blob_ctx.blob_hash.update(&compressed);
+            blob_ctx.source_hash.update(chunk_id.as_ref());
 
             // Dump compressed chunk data to blob
             event_tracer!("blob_decompressed_size", +chunk_size);
@@ -410,10 +634,62 @@ impl Node {
 
         // Finish inode digest calculation
         self.inode.set_digest(inode_hasher.digest_finalize());
+        if has_hole {
+            // Rafs v6 keeps a chunk index for every chunk of the file, including holes.
+            if ctx.fs_version.is_v5() {
+                self.inode.set_child_count(self.chunks.len() as u32);
+            }
+            self.inode.set_has_hole(true);
+        }
 
         Ok(blob_size)
     }
 
+    /// Compute digests of data chunks of a regular file in the same way as `dump_blob()`.
+    pub fn chunk_digests(&self, ctx: &BuildContext) -> Result<Vec<RafsDigest>> {
+        if !self.is_reg() {
+            return Ok(Vec::new());
+        }
+
+        let mut file = File::open(&self.path)
+            .with_context(|| format!("failed to open node file {:?}", self.path))?;
+        let mut buf = vec![0u8; ctx.chunk_size as usize];
+        let mut digests = Vec::with_capacity(self.inode.child_count() as usize);
+
+        for i in 0..self.inode.child_count() {
+            let file_offset = i as u64 * ctx.chunk_size as u64;
+            let chunk_size = std::cmp::min(
+                self.inode.size().saturating_sub(file_offset),
+                ctx.chunk_size as u64,
+            );
+            if ctx.sparse_file && Self::is_hole(&file, file_offset, chunk_size)? {
+                continue;
+            }
+            let chunk_data = &mut buf[0..chunk_size as usize];
+            file.seek(SeekFrom::Start(file_offset))
+                .and_then(|_| file.read_exact(chunk_data))
+                .with_context(|| format!("failed to read node file {:?}", self.path))?;
+            digests.push(RafsDigest::from_buf(chunk_data, ctx.digester));
+        }
+
+        Ok(digests)
+    }
+
+    // Check whether the file range [offset, offset + size) is a hole, by SEEK_DATA.
+    fn is_hole(file: &File, offset: u64, size: u64) -> Result<bool> {
+        let ret = unsafe { libc::lseek64(file.as_raw_fd(), offset as i64, libc::SEEK_DATA) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            // No more data after `offset`.
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                return Ok(true);
+            }
+            return Err(err).context("failed to seek data of node file");
+        }
+
+        Ok(ret as u64 >= offset + size)
+    }
+
     pub fn dump_bootstrap_v5(
         &self,
         ctx: &BuildContext,
@@ -445,11 +721,12 @@ impl Node {
             }
 
             // Dump chunk info
-            if self.is_reg() && self.inode.child_count() as usize != self.chunks.len() {
-                bail!("invalid chunks count {}: {}", self.chunks.len(), self);
+            let chunks = self.load_chunks(ctx.chunk_spill.as_deref())?;
+            if self.is_reg() && self.inode.child_count() as usize != chunks.len() {
+                bail!("invalid chunks count {}: {}", chunks.len(), self);
             }
 
-            for chunk in &self.chunks {
+            for chunk in chunks.iter() {
                 let chunk_size = chunk
                     .store(f_bootstrap)
                     .context("failed to dump chunk info to bootstrap")?;
@@ -494,10 +771,11 @@ impl Node {
         inode.set_ino(self.inode.ino() as u32);
         inode.set_uidgid(self.inode.uid(), self.inode.gid());
         inode.set_mtime(self.inode.mtime(), self.inode.mtime_nsec());
+        inode.set_btime(self.inode.btime());
         inode.set_nlink(self.inode.nlink());
         inode.set_mode(self.inode.mode() as u16);
         inode.set_data_layout(self.v6_datalayout);
-        inode.set_xattr_inline_count(self.xattrs.count_v6() as u16);
+        inode.set_xattr_inline_count(self.xattrs.count_v6(self.v6_shared_xattrs.as_deref()) as u16);
 
         // update all the inodes's offset according to the new 'meta_addr'.
         self.offset = self.offset - orig_meta_addr + meta_addr;
@@ -518,7 +796,7 @@ impl Node {
             // Dump xattr
             if !self.xattrs.is_empty() {
                 self.xattrs
-                    .store_v6(f_bootstrap)
+                    .store_v6(f_bootstrap, self.v6_shared_xattrs.as_deref())
                     .context("failed to dump xattr to bootstrap")?;
             }
 
@@ -545,7 +823,7 @@ impl Node {
                     }
 
                     for name in entry_names.iter() {
-                        dir_data.extend(name.as_bytes());
+                        dir_data.extend(platform::os_str_to_bytes(name).iter());
                     }
 
                     f_bootstrap
@@ -610,7 +888,7 @@ impl Node {
                 }
 
                 for name in entry_names.iter() {
-                    dir_data.extend(name.as_bytes());
+                    dir_data.extend(platform::os_str_to_bytes(name).iter());
                 }
 
                 let tail_off = match self.v6_datalayout {
@@ -632,15 +910,29 @@ impl Node {
 
             // write chunk indexes, chunk contents has been written to blob file.
             let mut chunks: Vec<u8> = Vec::new();
-            for chunk in self.chunks.iter() {
+            let loaded = self.load_chunks(ctx.chunk_spill.as_deref())?;
+            let mut iter = loaded.iter().peekable();
+            for i in 0..self.inode.child_count() as u64 {
                 let mut v6_chunk = RafsV6InodeChunkAddr::new();
-                // for erofs, bump id by 1 since device id 0 is bootstrap.
-                v6_chunk.set_blob_index((chunk.blob_index() + 1) as u8);
-                v6_chunk.set_block_addr((chunk.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32);
-                trace!("name {:?} chunk {}", self.name(), chunk);
+                match iter.peek() {
+                    Some(chunk) if chunk.file_offset() == i * ctx.chunk_size as u64 => {
+                        // for erofs, bump id by 1 since device id 0 is bootstrap.
+                        v6_chunk.set_blob_index((chunk.blob_index() + 1) as u8);
+                        v6_chunk.set_block_addr(
+                            (chunk.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32,
+                        );
+                        trace!("name {:?} chunk {}", self.name(), chunk);
+                        iter.next();
+                    }
+                    // Chunks within holes of sparse files are skipped by `dump_blob()`.
+                    _ => v6_chunk.set_block_addr(EROFS_NULL_ADDR),
+                }
 
                 chunks.extend(v6_chunk.as_ref());
             }
+            if iter.next().is_some() {
+                bail!("invalid chunks count {}: {}", loaded.len(), self);
+            }
 
             f_bootstrap
                 .seek(SeekFrom::Start(self.offset))
@@ -650,7 +942,7 @@ impl Node {
             // Dump xattr
             if !self.xattrs.is_empty() {
                 self.xattrs
-                    .store_v6(f_bootstrap)
+                    .store_v6(f_bootstrap, self.v6_shared_xattrs.as_deref())
                     .context("failed to dump xattr to bootstrap")?;
             }
 
@@ -679,7 +971,7 @@ impl Node {
             // Dump xattr
             if !self.xattrs.is_empty() {
                 self.xattrs
-                    .store_v6(f_bootstrap)
+                    .store_v6(f_bootstrap, self.v6_shared_xattrs.as_deref())
                     .context("failed to dump xattr to bootstrap")?;
             }
 
@@ -698,10 +990,14 @@ impl Node {
                     .context("failed seek for dir inode")?;
 
                 f_bootstrap
-                    .write(symlink.as_bytes())
+                    .write(&platform::os_str_to_bytes(symlink))
                     .context("filed to store symlink")?;
             }
         } else {
+            // EROFS stores device number of device files in the union field.
+            if self.inode.is_chrdev() || self.inode.is_blkdev() {
+                inode.set_u(self.inode.rdev());
+            }
             f_bootstrap
                 .seek(SeekFrom::Start(self.offset))
                 .context("failed seek for dir inode")?;
@@ -710,7 +1006,7 @@ impl Node {
             // Dump xattr
             if !self.xattrs.is_empty() {
                 self.xattrs
-                    .store_v6(f_bootstrap)
+                    .store_v6(f_bootstrap, self.v6_shared_xattrs.as_deref())
                     .context("failed to dump xattr to bootstrap")?;
             }
         }
@@ -719,23 +1015,43 @@ impl Node {
         Ok(0)
     }
 
-    fn build_inode_xattr(&mut self) -> Result<()> {
-        let file_xattrs = match xattr::list(&self.path) {
+    fn build_inode_xattr(&mut self, xattr_filter: &XattrFilter) -> Result<()> {
+        let file_xattrs = match platform::list_xattrs(&self.path) {
             Ok(x) => x,
             Err(e) => {
                 if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
                     return Ok(());
+                } else if is_permission_error(&e) {
+                    let all = vec!["*".to_string()].into_iter().collect();
+                    return xattr_filter.deny(&self.path, all);
                 } else {
                     return Err(anyhow!("failed to list xattr of {:?}", self.path));
                 }
             }
         };
 
+        let mut denied = BTreeSet::new();
         for key in file_xattrs {
-            let value = xattr::get(&self.path, &key)
-                .context(format!("failed to get xattr {:?} of {:?}", key, self.path))?;
+            if xattr_filter.is_excluded(&key) {
+                debug!("exclude xattr {:?} of {:?}", key, self.path);
+                continue;
+            }
+            let value = match platform::get_xattr(&self.path, &key) {
+                Ok(v) => v,
+                Err(e) if is_permission_error(&e) => {
+                    denied.insert(xattr_namespace(&key));
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e)
+                        .context(format!("failed to get xattr {:?} of {:?}", key, self.path))
+                }
+            };
             self.xattrs.add(key, value.unwrap_or_default());
         }
+        if !denied.is_empty() {
+            xattr_filter.deny(&self.path, denied)?;
+        }
 
         if !self.xattrs.is_empty() {
             self.inode.set_has_xattr(true);
@@ -744,25 +1060,31 @@ impl Node {
         Ok(())
     }
 
-    fn build_inode_stat(&mut self) -> Result<()> {
+    fn build_inode_stat(&mut self, defaults: &SourceDefaults) -> Result<()> {
         let meta = self.meta()?;
+        let stat = SourceStat::new(&meta, defaults);
 
-        self.src_ino = meta.st_ino();
-        self.src_dev = meta.st_dev();
-        self.rdev = meta.st_rdev();
-        self.ctime = meta.st_ctime();
+        self.src_ino = stat.ino;
+        self.src_dev = stat.dev;
+        self.rdev = stat.rdev;
+        self.ctime = stat.ctime;
         self.inode
-            .set_inode_info(&meta, &self.xattrs, self.explicit_uidgid);
+            .set_inode_info(&stat, &self.xattrs, self.explicit_uidgid);
 
         Ok(())
     }
 
-    fn build_inode(&mut self, chunk_size: u32) -> Result<()> {
+    fn build_inode(
+        &mut self,
+        chunk_size: u32,
+        xattr_filter: &XattrFilter,
+        defaults: &SourceDefaults,
+    ) -> Result<()> {
         self.inode.set_name_size(self.name().byte_size());
 
         // NOTE: Always retrieve xattr before attr so that we can know the size of xattr pairs.
-        self.build_inode_xattr()?;
-        self.build_inode_stat()
+        self.build_inode_xattr(xattr_filter)?;
+        self.build_inode_stat(defaults)
             .with_context(|| format!("failed to build inode {:?}", self.path))?;
 
         if self.is_reg() {
@@ -779,7 +1101,7 @@ impl Node {
         Ok(())
     }
 
-    fn meta(&self) -> Result<impl MetadataExt> {
+    fn meta(&self) -> Result<Metadata> {
         self.path
             .symlink_metadata()
             .with_context(|| format!("failed to get metadata from {:?}", self.path))
@@ -805,6 +1127,32 @@ impl Node {
         self.inode.is_special()
     }
 
+    /// Check whether the node should be kept in the image as per the special file `policy`, and
+    /// clear the device number of fifos and sockets, which is meaningless for them.
+    pub fn apply_special_file_policy(
+        &mut self,
+        policy: &SpecialFilePolicy,
+        spec: WhiteoutSpec,
+    ) -> bool {
+        if !self.is_special() {
+            return true;
+        }
+
+        let allowed = if self.inode.is_fifo() || self.inode.is_sock() {
+            self.rdev = 0;
+            self.inode.set_rdev(0);
+            self.inode.is_fifo() || policy.allow_sockets
+        } else {
+            policy.allow_devices || self.is_overlayfs_whiteout(spec)
+        };
+        if !allowed {
+            debug!("skip special file {:?}", self.path);
+            event_tracer!("skipped_special_files", +1);
+        }
+
+        allowed
+    }
+
     pub fn chunk_count(&self, chunk_size: u64) -> u32 {
         if self.is_reg() {
             let chunks = div_round_up(self.inode.size(), chunk_size);
@@ -837,7 +1185,7 @@ impl Node {
     /// Get filename of the inode.
     pub fn name(&self) -> &OsStr {
         if self.path == self.source {
-            OsStr::from_bytes(ROOT_PATH_NAME)
+            OsStr::new("/")
         } else {
             // Safe to unwrap because `path` is returned from `path()` which is canonicalized
             self.path.file_name().unwrap()
@@ -853,9 +1201,11 @@ impl Node {
     pub fn generate_target_vec(target: &Path) -> Vec<OsString> {
         target
             .components()
-            .map(|comp| match comp {
-                Component::RootDir => OsString::from("/"),
-                Component::Normal(name) => name.to_os_string(),
+            .filter_map(|comp| match comp {
+                // Drive letters of Windows paths are meaningless within the image.
+                Component::Prefix(_) => None,
+                Component::RootDir => Some(OsString::from("/")),
+                Component::Normal(name) => Some(name.to_os_string()),
                 _ => panic!("invalid file component pattern!"),
             })
             .collect::<Vec<_>>()
@@ -896,7 +1246,9 @@ impl Node {
                     size_of::<RafsV6InodeCompact>()
                 } else {
                     size_of::<RafsV6InodeExtended>()
-                }) + self.xattrs.aligned_size_v6()
+                }) + self
+                    .xattrs
+                    .aligned_size_v6(self.v6_shared_xattrs.as_deref())
             }
         }
     }
@@ -910,7 +1262,15 @@ impl Node {
             return false;
         }
 
-        self.inode.is_chrdev() && stat::major(self.rdev) == 0 && stat::minor(self.rdev) == 0
+        if self.inode.is_chrdev() && stat::major(self.rdev) == 0 && stat::minor(self.rdev) == 0 {
+            return true;
+        }
+
+        self.is_reg()
+            && self.inode.size() == 0
+            && OVERLAYFS_XWHITEOUT
+                .iter()
+                .any(|key| self.xattrs.get(OsStr::new(key)).is_some())
     }
 
     /// Check whether the inode (directory) is a overlayfs whiteout opaque.
@@ -919,14 +1279,18 @@ impl Node {
             return false;
         }
 
-        // A directory is made opaque by setting the xattr "trusted.overlay.opaque" to "y".
-        if let Some(v) = self.xattrs.get(&OsString::from(OVERLAYFS_WHITEOUT_OPAQUE)) {
-            if let Ok(v) = std::str::from_utf8(v.as_slice()) {
-                return v == "y";
-            }
-        }
+        // A directory is made opaque by setting the xattr "overlay.opaque" to "y", in either the
+        // "trusted" or the "user" namespace.
+        [OVERLAYFS_WHITEOUT_OPAQUE, OVERLAYFS_USER_WHITEOUT_OPAQUE]
+            .iter()
+            .filter_map(|key| self.xattrs.get(OsStr::new(key)))
+            .any(|v| v.as_slice() == b"y")
+    }
 
-        false
+    /// Remove overlayfs opaque xattrs of both namespaces, after the opaque has been applied.
+    pub fn remove_overlayfs_opaque(&mut self) {
+        self.remove_xattr(OsStr::new(OVERLAYFS_WHITEOUT_OPAQUE));
+        self.remove_xattr(OsStr::new(OVERLAYFS_USER_WHITEOUT_OPAQUE));
     }
 
     /// Get whiteout type to process the inode.
@@ -952,6 +1316,7 @@ impl Node {
                     return Some(WhiteoutType::OverlayFsOpaque);
                 }
             }
+            WhiteoutSpec::None => {}
         }
 
         None
@@ -962,9 +1327,7 @@ impl Node {
         if let Some(name) = self.name().to_str() {
             if t == WhiteoutType::OciRemoval {
                 // the whiteout filename prefixes the basename of the path to be deleted with ".wh.".
-                return Some(OsStr::from_bytes(
-                    name[OCISPEC_WHITEOUT_PREFIX.len()..].as_bytes(),
-                ));
+                return Some(OsStr::new(&name[OCISPEC_WHITEOUT_PREFIX.len()..]));
             } else if t == WhiteoutType::OverlayFsRemoval {
                 // the whiteout file has the same name as the file to be deleted.
                 return Some(name.as_ref());
@@ -975,7 +1338,7 @@ impl Node {
     }
 
     /// Set node offset in bootstrap and return the next position.
-    pub(crate) fn set_v6_offset(&mut self, bootstrap_ctx: &mut BootstrapContext) {
+    pub fn set_v6_offset(&mut self, bootstrap_ctx: &mut BootstrapContext) {
         if self.is_reg() {
             let size = self.size_with_xattr() as u64;
             let unit = size_of::<RafsV6InodeChunkAddr>() as u64;
@@ -993,15 +1356,21 @@ impl Node {
         }
     }
 
-    pub(crate) fn get_dir_d_size(&self, tree: &Tree) -> Result<u64> {
+    pub fn get_dir_d_size(&self, tree: &Tree) -> Result<u64> {
         ensure!(self.is_dir(), "{} is not a directory", self);
 
-        let mut d_size: u64 =
-            (1 + size_of::<RafsV6Dirent>() + 2 + size_of::<RafsV6Dirent>()) as u64;
+        // Dirents are stored in the order of names, dot and dotdot included.
+        let mut names: Vec<Cow<[u8]>> = vec![Cow::Borrowed(&b"."[..]), Cow::Borrowed(&b".."[..])];
+        names.extend(
+            tree.children
+                .iter()
+                .map(|child| platform::os_str_to_bytes(child.node.name())),
+        );
+        names.sort();
 
-        // Safe as we have check tree above.
-        for child in tree.children.iter() {
-            let len = child.node.name().len() + size_of::<RafsV6Dirent>();
+        let mut d_size: u64 = 0;
+        for name in names.iter() {
+            let len = name.len() + size_of::<RafsV6Dirent>();
             // erofs disk format requires dirent to be aligned with 4096.
             if (d_size % EROFS_BLOCK_SIZE) + len as u64 > EROFS_BLOCK_SIZE {
                 d_size = div_round_up(d_size as u64, EROFS_BLOCK_SIZE) * EROFS_BLOCK_SIZE;
@@ -1147,6 +1516,13 @@ impl InodeWrapper {
         }
     }
 
+    pub fn set_mode(&mut self, mode: u32) {
+        match self {
+            InodeWrapper::V5(i) => i.i_mode = mode,
+            InodeWrapper::V6(i) => i.i_mode = mode,
+        }
+    }
+
     pub fn is_dir(&self) -> bool {
         match self {
             InodeWrapper::V5(i) => i.is_dir(),
@@ -1233,6 +1609,22 @@ impl InodeWrapper {
         }
     }
 
+    /// Mark the file as having chunks skipped for holes.
+    pub fn set_has_hole(&mut self, enable: bool) {
+        match self {
+            InodeWrapper::V5(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::HAS_HOLE;
+                }
+            }
+            // Rafs v6 inodes locate chunks by file offset, holes are encoded as chunk indexes
+            // with null block address instead.
+            InodeWrapper::V6(_) => {}
+        }
+    }
+
     pub fn ino(&self) -> Inode {
         match self {
             InodeWrapper::V5(i) => i.i_ino,
@@ -1282,6 +1674,13 @@ impl InodeWrapper {
         }
     }
 
+    pub fn set_uid(&mut self, uid: u32) {
+        match self {
+            InodeWrapper::V5(i) => i.i_uid = uid,
+            InodeWrapper::V6(i) => i.i_uid = uid,
+        }
+    }
+
     pub fn gid(&self) -> u32 {
         match self {
             InodeWrapper::V5(i) => i.i_gid,
@@ -1289,6 +1688,13 @@ impl InodeWrapper {
         }
     }
 
+    pub fn set_gid(&mut self, gid: u32) {
+        match self {
+            InodeWrapper::V5(i) => i.i_gid = gid,
+            InodeWrapper::V6(i) => i.i_gid = gid,
+        }
+    }
+
     pub fn mtime(&self) -> u64 {
         match self {
             InodeWrapper::V5(i) => i.i_mtime,
@@ -1303,6 +1709,40 @@ impl InodeWrapper {
         }
     }
 
+    pub fn set_mtime(&mut self, mtime: u64, mtime_nsec: u32) {
+        match self {
+            InodeWrapper::V5(i) => {
+                i.i_mtime = mtime;
+                i.i_mtime_nsec = mtime_nsec;
+            }
+            InodeWrapper::V6(i) => {
+                i.i_mtime = mtime;
+                i.i_mtime_nsec = mtime_nsec;
+            }
+        }
+    }
+
+    pub fn rdev(&self) -> u32 {
+        match self {
+            InodeWrapper::V5(i) => i.i_rdev,
+            InodeWrapper::V6(i) => i.i_rdev,
+        }
+    }
+
+    pub fn set_rdev(&mut self, rdev: u32) {
+        match self {
+            InodeWrapper::V5(i) => i.i_rdev = rdev,
+            InodeWrapper::V6(i) => i.i_rdev = rdev,
+        }
+    }
+
+    pub fn btime(&self) -> u64 {
+        match self {
+            InodeWrapper::V5(i) => i.i_btime,
+            InodeWrapper::V6(i) => i.i_btime,
+        }
+    }
+
     pub fn blocks(&self) -> u64 {
         match self {
             InodeWrapper::V5(i) => i.i_blocks,
@@ -1402,24 +1842,22 @@ impl InodeWrapper {
         }
     }
 
-    fn set_inode_info<T: MetadataExt>(
-        &mut self,
-        meta: &T,
-        xattrs: &RafsXAttrs,
-        explicit_uidgid: bool,
-    ) {
+    fn set_inode_info(&mut self, meta: &SourceStat, xattrs: &RafsXAttrs, explicit_uidgid: bool) {
         match self {
             InodeWrapper::V5(i) => {
-                i.i_mode = meta.st_mode();
+                i.i_mode = meta.mode;
                 if explicit_uidgid {
-                    i.i_uid = meta.st_uid();
-                    i.i_gid = meta.st_gid();
+                    i.i_uid = meta.uid;
+                    i.i_gid = meta.gid;
+                    // Birth time differs between copies of the source, so it's dropped by
+                    // repeatable builds together with uid/gid.
+                    i.i_btime = meta.btime;
                 }
-                i.i_mtime = meta.st_mtime() as u64;
-                i.i_mtime_nsec = meta.st_mtime_nsec() as u32;
+                i.i_mtime = meta.mtime as u64;
+                i.i_mtime_nsec = meta.mtime_nsec as u32;
                 i.i_projid = 0;
-                i.i_size = meta.st_size();
-                i.i_rdev = meta.st_rdev() as u32;
+                i.i_size = meta.size;
+                i.i_rdev = meta.rdev as u32;
                 // Ignore actual nlink value and calculate from rootfs directory instead
                 i.i_nlink = 1;
 
@@ -1431,16 +1869,19 @@ impl InodeWrapper {
                 i.i_blocks = div_round_up(i.i_size + xattrs.aligned_size_v5() as u64, 512);
             }
             InodeWrapper::V6(i) => {
-                i.i_mode = meta.st_mode();
+                i.i_mode = meta.mode;
                 if explicit_uidgid {
-                    i.i_uid = meta.st_uid();
-                    i.i_gid = meta.st_gid();
+                    i.i_uid = meta.uid;
+                    i.i_gid = meta.gid;
+                    // Birth time differs between copies of the source, so it's dropped by
+                    // repeatable builds together with uid/gid.
+                    i.i_btime = meta.btime;
                 }
-                i.i_mtime = meta.st_mtime() as u64;
-                i.i_mtime_nsec = meta.st_mtime_nsec() as u32;
+                i.i_mtime = meta.mtime as u64;
+                i.i_mtime_nsec = meta.mtime_nsec as u32;
                 i.i_projid = 0;
-                i.i_size = meta.st_size();
-                i.i_rdev = meta.st_rdev() as u32;
+                i.i_size = meta.size;
+                i.i_rdev = meta.rdev as u32;
                 // Ignore actual nlink value and calculate from rootfs directory instead
                 i.i_nlink = 1;
 
@@ -1572,6 +2013,36 @@ impl ChunkWrapper {
         }
     }
 
+    pub fn set_uncompressed_offset(&mut self, offset: u64) {
+        match self {
+            ChunkWrapper::V5(c) => c.uncompress_offset = offset,
+            ChunkWrapper::V6(c) => c.uncompress_offset = offset,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        match self {
+            ChunkWrapper::V5(c) => c.flags.contains(BlobChunkFlags::COMPRESSED),
+            ChunkWrapper::V6(c) => c.flags.contains(BlobChunkFlags::COMPRESSED),
+        }
+    }
+
+    /// Update location of the chunk data in the compressed blob.
+    pub fn set_compressed_info(&mut self, offset: u64, size: u32, is_compressed: bool) {
+        match self {
+            ChunkWrapper::V5(c) => {
+                c.compress_offset = offset;
+                c.compress_size = size;
+                c.flags.set(BlobChunkFlags::COMPRESSED, is_compressed);
+            }
+            ChunkWrapper::V6(c) => {
+                c.compress_offset = offset;
+                c.compress_size = size;
+                c.flags.set(BlobChunkFlags::COMPRESSED, is_compressed);
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     #[inline]
     fn set_chunk_info(
@@ -1676,7 +2147,7 @@ fn to_rafsv5_inode(inode: &dyn RafsInode) -> RafsV5Inode {
         i_rdev: attr.rdev,
         i_mtime_nsec: attr.mtimensec,
         i_mtime: attr.mtime,
-        i_reserved: [0u8; 8],
+        i_btime: inode.get_btime(),
     }
 }
 
@@ -1699,13 +2170,274 @@ fn to_rafsv5_chunk_info(cki: &dyn BlobV5ChunkInfo) -> RafsV5ChunkInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::context::{ArtifactStorage, BootstrapContext};
+    use crate::builder::core::context::{ArtifactStorage, BootstrapContext};
     use rafs::metadata::layout::v6::EROFS_INODE_CHUNK_BASED;
     use rafs::metadata::RAFS_DEFAULT_CHUNK_SIZE;
+    use rafs::RafsIoReader;
     use std::os::unix::fs;
     use std::path::Path;
     use vmm_sys_util::{tempdir::TempDir, tempfile::TempFile};
 
+    #[test]
+    fn test_xattr_filter() {
+        let mut filter = XattrFilter::from_profile("none").unwrap();
+        assert!(filter.is_empty());
+        assert!(!filter.is_excluded(OsStr::new("security.selinux")));
+
+        filter.add("user.foo").unwrap();
+        filter.add("trusted.bar.*").unwrap();
+        assert!(filter.add("*").is_err());
+        assert!(filter.add("user.*.foo").is_err());
+        assert!(filter.is_excluded(OsStr::new("user.foo")));
+        assert!(!filter.is_excluded(OsStr::new("user.foobar")));
+        assert!(filter.is_excluded(OsStr::new("trusted.bar.x")));
+        assert!(!filter.is_excluded(OsStr::new("trusted.overlay.opaque")));
+
+        let filter = XattrFilter::from_profile("default").unwrap();
+        assert!(filter.is_excluded(OsStr::new("security.selinux")));
+        assert!(filter.is_excluded(OsStr::new("user.overlay.origin")));
+        assert!(!filter.is_excluded(OsStr::new("security.capability")));
+        assert!(XattrFilter::from_profile("foo").is_err());
+    }
+
+    #[test]
+    fn test_xattr_filter_denials() {
+        let mut filter = XattrFilter::default();
+        assert_eq!(
+            xattr_namespace(OsStr::new("security.capability")),
+            "security"
+        );
+        assert_eq!(xattr_namespace(OsStr::new("user")), "user");
+
+        let namespaces: BTreeSet<String> = vec!["security".to_string(), "trusted".to_string()]
+            .into_iter()
+            .collect();
+        let clone = filter.clone();
+        filter.deny(Path::new("/a"), namespaces.clone()).unwrap();
+        clone
+            .deny(
+                Path::new("/b"),
+                vec!["security".to_string()].into_iter().collect(),
+            )
+            .unwrap();
+        let denials = filter.denials();
+        assert_eq!(denials.get("security"), Some(&2));
+        assert_eq!(denials.get("trusted"), Some(&1));
+
+        filter.set_strict(true);
+        assert!(filter.deny(Path::new("/c"), namespaces).is_err());
+        assert_eq!(filter.denials().get("security"), Some(&2));
+    }
+
+    #[test]
+    fn test_whiteout_spec_none() {
+        let pa = TempDir::new().unwrap();
+        let wh = pa.as_path().join(".wh.foo");
+        std::fs::write(&wh, b"").unwrap();
+        let node = Node::new(
+            RafsVersion::V6,
+            pa.as_path().to_path_buf(),
+            wh,
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            node.whiteout_type(WhiteoutSpec::Oci),
+            Some(WhiteoutType::OciRemoval)
+        );
+        let spec: WhiteoutSpec = "none".parse().unwrap();
+        assert!(node.whiteout_type(spec).is_none());
+        assert!("foo".parse::<WhiteoutSpec>().is_err());
+    }
+
+    #[test]
+    fn test_overlayfs_whiteout_variants() {
+        let pa = TempDir::new().unwrap();
+        let path = pa.as_path().join("foo");
+        std::fs::write(&path, b"").unwrap();
+        let mut node = Node::new(
+            RafsVersion::V6,
+            pa.as_path().to_path_buf(),
+            path,
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
+        )
+        .unwrap();
+        let spec = WhiteoutSpec::Overlayfs;
+        assert!(node.whiteout_type(spec).is_none());
+
+        // xwhiteouts in both namespaces.
+        for key in OVERLAYFS_XWHITEOUT {
+            let mut n = node.clone();
+            n.xattrs.add(OsString::from(key), vec![]);
+            assert_eq!(n.whiteout_type(spec), Some(WhiteoutType::OverlayFsRemoval));
+            assert_eq!(
+                n.origin_name(WhiteoutType::OverlayFsRemoval),
+                Some(OsStr::new("foo"))
+            );
+            assert!(n.whiteout_type(WhiteoutSpec::Oci).is_none());
+            n.inode.set_size(1);
+            assert!(n.whiteout_type(spec).is_none());
+        }
+
+        // Character device with 0/0 device number.
+        let mut n = node.clone();
+        n.inode.set_mode(libc::S_IFCHR | 0o644);
+        n.rdev = 0;
+        assert_eq!(n.whiteout_type(spec), Some(WhiteoutType::OverlayFsRemoval));
+
+        // Opaque directories in both namespaces.
+        node.inode.set_mode(libc::S_IFDIR | 0o755);
+        for key in &[OVERLAYFS_WHITEOUT_OPAQUE, OVERLAYFS_USER_WHITEOUT_OPAQUE] {
+            let mut n = node.clone();
+            n.xattrs.add(OsString::from(key), b"y".to_vec());
+            n.inode.set_has_xattr(true);
+            assert_eq!(n.whiteout_type(spec), Some(WhiteoutType::OverlayFsOpaque));
+            n.remove_overlayfs_opaque();
+            assert!(n.whiteout_type(spec).is_none());
+            assert!(!n.inode.has_xattr());
+        }
+
+        // Directories containing xwhiteouts are not opaque.
+        node.xattrs
+            .add(OsString::from(OVERLAYFS_WHITEOUT_OPAQUE), b"x".to_vec());
+        assert!(node.whiteout_type(spec).is_none());
+    }
+
+    #[test]
+    fn test_xattr_filter_keep_overlayfs_whiteouts() {
+        let mut filter = XattrFilter::from_profile("default").unwrap();
+        assert!(filter.is_excluded(OsStr::new(OVERLAYFS_USER_WHITEOUT_OPAQUE)));
+        assert!(filter.is_excluded(OsStr::new("user.overlay.whiteout")));
+
+        filter.set_keep_overlayfs_whiteouts(true);
+        assert!(!filter.is_excluded(OsStr::new(OVERLAYFS_USER_WHITEOUT_OPAQUE)));
+        assert!(!filter.is_excluded(OsStr::new("user.overlay.whiteout")));
+        assert!(filter.is_excluded(OsStr::new("user.overlay.origin")));
+    }
+
+    fn create_special_node(version: RafsVersion, dir: &Path) -> Node {
+        let path = dir.join("fifo");
+        if !path.exists() {
+            nix::unistd::mkfifo(&path, stat::Mode::S_IRUSR | stat::Mode::S_IWUSR).unwrap();
+        }
+        Node::new(
+            version,
+            dir.to_path_buf(),
+            path,
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_special_file_policy() {
+        let pa = TempDir::new().unwrap();
+        let default = SpecialFilePolicy::default();
+        let drop_all = SpecialFilePolicy {
+            allow_devices: false,
+            allow_sockets: false,
+        };
+
+        let mut fifo = create_special_node(RafsVersion::V6, pa.as_path());
+        assert!(fifo.inode.is_fifo());
+        fifo.rdev = stat::makedev(1, 1);
+        fifo.inode.set_rdev(fifo.rdev as u32);
+        assert!(fifo.apply_special_file_policy(&drop_all, WhiteoutSpec::Oci));
+        assert_eq!(fifo.rdev, 0);
+        assert_eq!(fifo.inode.rdev(), 0);
+
+        let sock_path = pa.as_path().join("sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&sock_path).unwrap();
+        let mut sock = Node::new(
+            RafsVersion::V6,
+            pa.as_path().to_path_buf(),
+            sock_path,
+            Overlay::UpperAddition,
+            RAFS_DEFAULT_CHUNK_SIZE as u32,
+            false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
+        )
+        .unwrap();
+        assert!(sock.inode.is_sock());
+        assert!(!sock.apply_special_file_policy(&drop_all, WhiteoutSpec::Oci));
+        assert!(sock.apply_special_file_policy(&default, WhiteoutSpec::Oci));
+
+        let mut dev = fifo.clone();
+        dev.inode.set_mode(libc::S_IFBLK | 0o600);
+        dev.rdev = stat::makedev(8, 1);
+        dev.inode.set_rdev(dev.rdev as u32);
+        assert!(!dev.apply_special_file_policy(&drop_all, WhiteoutSpec::Overlayfs));
+        assert!(dev.apply_special_file_policy(&default, WhiteoutSpec::Oci));
+        assert_eq!(dev.rdev, stat::makedev(8, 1));
+
+        // Overlayfs whiteouts are always kept.
+        dev.inode.set_mode(libc::S_IFCHR | 0o600);
+        dev.rdev = 0;
+        dev.inode.set_rdev(0);
+        assert!(!dev.apply_special_file_policy(&drop_all, WhiteoutSpec::Oci));
+        assert!(dev.apply_special_file_policy(&drop_all, WhiteoutSpec::Overlayfs));
+
+        let mut reg = fifo.clone();
+        reg.inode.set_mode(libc::S_IFREG | 0o644);
+        assert!(reg.apply_special_file_policy(&drop_all, WhiteoutSpec::Oci));
+    }
+
+    #[test]
+    fn test_special_file_metadata_round_trip() {
+        let pa = TempDir::new().unwrap();
+        let ctx = BuildContext::default();
+        let files = [
+            (libc::S_IFCHR | 0o620, stat::makedev(136, 0x1234) as u32),
+            (libc::S_IFBLK | 0o660, stat::makedev(8, 1) as u32),
+            (libc::S_IFIFO | 0o644, 0),
+            (libc::S_IFSOCK | 0o755, 0),
+        ];
+
+        for (mode, rdev) in files.iter() {
+            let mut node = create_special_node(RafsVersion::V5, pa.as_path());
+            node.inode.set_mode(*mode);
+            node.inode.set_rdev(*rdev);
+            let tmp = TempFile::new().unwrap();
+            let mut w = tmp.as_file().try_clone().unwrap();
+            node.dump_bootstrap_v5(&ctx, &mut w).unwrap();
+
+            let mut r = Box::new(File::open(tmp.as_path()).unwrap()) as RafsIoReader;
+            let mut inode = RafsV5Inode::default();
+            inode.load(&mut r).unwrap();
+            assert_eq!(inode.i_mode, *mode);
+            assert_eq!(inode.i_rdev, *rdev);
+
+            let mut node = create_special_node(RafsVersion::V6, pa.as_path());
+            node.inode.set_mode(*mode);
+            node.inode.set_rdev(*rdev);
+            node.offset = 0;
+            let tmp = TempFile::new().unwrap();
+            let mut w = tmp.as_file().try_clone().unwrap();
+            node.dump_bootstrap_v6(&mut w, 0, 0, &ctx).unwrap();
+            assert!(node.v6_compact_inode);
+
+            let mut r = Box::new(File::open(tmp.as_path()).unwrap()) as RafsIoReader;
+            let mut inode = RafsV6InodeCompact::new();
+            inode.load(&mut r).unwrap();
+            assert_eq!(u16::from_le(inode.i_mode) as u32, *mode);
+            assert_eq!(u32::from_le(inode.i_u), *rdev);
+        }
+    }
+
     #[test]
     fn test_set_v6_offset() {
         let pa = TempDir::new().unwrap();
@@ -1717,6 +2449,8 @@ mod tests {
             Overlay::UpperAddition,
             RAFS_DEFAULT_CHUNK_SIZE as u32,
             false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
         )
         .unwrap();
 
@@ -1744,6 +2478,8 @@ mod tests {
             Overlay::UpperAddition,
             RAFS_DEFAULT_CHUNK_SIZE as u32,
             false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
         )
         .unwrap();
 
@@ -1769,6 +2505,8 @@ mod tests {
             Overlay::UpperAddition,
             RAFS_DEFAULT_CHUNK_SIZE as u32,
             false,
+            &XattrFilter::default(),
+            &SourceDefaults::default(),
         )
         .unwrap();
 
@@ -0,0 +1,416 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access source files in a platform independent way.
+//!
+//! Images always carry Linux semantics, i.e. file type and permission bits in `st_mode`, numeric
+//! owners and device numbers, while source files may live on platforms without them, e.g. on
+//! Windows build machines. Metadata of source files is converted into `SourceStat` here, and
+//! attributes missing on the host platform are filled from `SourceDefaults`, which are set by
+//! the `--owner` and `--default-mode` options of `nydus-image create`. Owners of source files
+//! may also be remapped by `--uid-map` and `--gid-map`, e.g. for rootless builds where files are
+//! owned by subordinate ids of the builder.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io::Result;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Error};
+
+/// Overflow id for ids not covered by an `IdMap`, same as the one used by user namespaces.
+pub const OVERFLOW_ID: u32 = 65534;
+
+/// Map from ids on the build host to ids in the image.
+///
+/// Ranges are in format `container_id:host_id[:size]` as in `/proc/<pid>/uid_map`, and a range
+/// without size covers as many ids from `host_id` on as possible, so `0:100000` shifts ids by
+/// 100000. Ids not covered by any range are mapped to `OVERFLOW_ID`, and an empty map keeps ids
+/// as is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdMap {
+    // Tuples of (container_id, host_id, size).
+    ranges: Vec<(u32, u32, u64)>,
+}
+
+impl IdMap {
+    /// Add a range in format `container_id:host_id[:size]`.
+    pub fn add(&mut self, range: &str) -> anyhow::Result<()> {
+        let fields: Vec<&str> = range.split(':').collect();
+        ensure!(
+            fields.len() == 2 || fields.len() == 3,
+            "invalid id map {}, expected container_id:host_id[:size]",
+            range
+        );
+        let parse = |v: &str| -> anyhow::Result<u64> {
+            v.parse::<u32>()
+                .map(|v| v as u64)
+                .with_context(|| format!("invalid id {} in id map {}", v, range))
+        };
+        let container_id = parse(fields[0])?;
+        let host_id = parse(fields[1])?;
+        let size = match fields.get(2) {
+            Some(v) => v
+                .parse::<u64>()
+                .with_context(|| format!("invalid size {} in id map {}", v, range))?,
+            None => (1u64 << 32) - host_id.max(container_id),
+        };
+        ensure!(
+            size > 0 && container_id + size <= 1u64 << 32 && host_id + size <= 1u64 << 32,
+            "id map {} is out of range",
+            range
+        );
+        for (_, h, sz) in self.ranges.iter() {
+            ensure!(
+                host_id + size <= *h as u64 || *h as u64 + sz <= host_id,
+                "id map {} overlaps with other ranges",
+                range
+            );
+        }
+        self.ranges
+            .push((container_id as u32, host_id as u32, size));
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Map the host `id` into the image.
+    pub fn map(&self, id: u32) -> u32 {
+        if self.ranges.is_empty() {
+            return id;
+        }
+        for (container_id, host_id, size) in self.ranges.iter() {
+            if id >= *host_id && ((id - host_id) as u64) < *size {
+                return container_id + (id - host_id);
+            }
+        }
+        OVERFLOW_ID
+    }
+}
+
+/// Owner and permission bits to apply to source files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceDefaults {
+    /// Override owner of all source files, as `(uid, gid)`.
+    pub owner: Option<(u32, u32)>,
+    /// Remap uids of source files, not applied to `owner`.
+    pub uid_map: IdMap,
+    /// Remap gids of source files, not applied to `owner`.
+    pub gid_map: IdMap,
+    /// Permission bits of regular files on platforms without them.
+    pub file_mode: u32,
+    /// Permission bits of directories on platforms without them.
+    pub dir_mode: u32,
+}
+
+impl Default for SourceDefaults {
+    fn default() -> Self {
+        SourceDefaults {
+            owner: None,
+            uid_map: IdMap::default(),
+            gid_map: IdMap::default(),
+            file_mode: 0o644,
+            dir_mode: 0o755,
+        }
+    }
+}
+
+impl SourceDefaults {
+    /// Parse owner in format `uid:gid`, e.g. `1000:1000`.
+    pub fn parse_owner(s: &str) -> anyhow::Result<(u32, u32)> {
+        let (uid, gid) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid owner {}, expected uid:gid", s))?;
+        let uid = uid
+            .parse()
+            .with_context(|| format!("invalid uid in owner {}", s))?;
+        let gid = gid
+            .parse()
+            .with_context(|| format!("invalid gid in owner {}", s))?;
+
+        Ok((uid, gid))
+    }
+}
+
+impl FromStr for SourceDefaults {
+    type Err = Error;
+
+    /// Parse permission bits in format `file_mode:dir_mode`, in octal, e.g. `644:755`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (file_mode, dir_mode) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid mode {}, expected file_mode:dir_mode", s))?;
+        let parse = |m: &str| -> anyhow::Result<u32> {
+            let mode = u32::from_str_radix(m, 8)
+                .with_context(|| format!("invalid octal mode {} in {}", m, s))?;
+            ensure!(mode <= 0o7777, "mode {} in {} is out of range", m, s);
+            Ok(mode)
+        };
+
+        Ok(SourceDefaults {
+            file_mode: parse(file_mode)?,
+            dir_mode: parse(dir_mode)?,
+            ..Default::default()
+        })
+    }
+}
+
+/// Metadata of a source file in Linux semantics.
+#[derive(Clone, Debug, Default)]
+pub struct SourceStat {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub rdev: u64,
+    /// Inode number to detect hardlinks, 0 if unknown.
+    pub ino: u64,
+    pub dev: u64,
+    /// Birth time in seconds since epoch, 0 if unknown.
+    pub btime: u64,
+}
+
+impl SourceStat {
+    #[cfg(target_os = "linux")]
+    pub fn new(meta: &Metadata, defaults: &SourceDefaults) -> Self {
+        use std::os::linux::fs::MetadataExt;
+
+        let (uid, gid) = defaults.owner.unwrap_or_else(|| {
+            (
+                defaults.uid_map.map(meta.st_uid()),
+                defaults.gid_map.map(meta.st_gid()),
+            )
+        });
+        SourceStat {
+            mode: meta.st_mode(),
+            uid,
+            gid,
+            size: meta.st_size(),
+            mtime: meta.st_mtime(),
+            mtime_nsec: meta.st_mtime_nsec(),
+            ctime: meta.st_ctime(),
+            rdev: meta.st_rdev(),
+            ino: meta.st_ino(),
+            dev: meta.st_dev(),
+            btime: birth_time(meta),
+        }
+    }
+
+    /// Synthesize Linux metadata on platforms without it, only regular files, directories and
+    /// symlinks are supported. Write permission is dropped for readonly files, and hardlinks
+    /// are not detected.
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(meta: &Metadata, defaults: &SourceDefaults) -> Self {
+        use std::time::UNIX_EPOCH;
+
+        const S_IFREG: u32 = 0o100000;
+        const S_IFDIR: u32 = 0o040000;
+        const S_IFLNK: u32 = 0o120000;
+
+        let file_type = meta.file_type();
+        let (file_type, mut perm) = if file_type.is_symlink() {
+            (S_IFLNK, 0o777)
+        } else if file_type.is_dir() {
+            (S_IFDIR, defaults.dir_mode)
+        } else {
+            (S_IFREG, defaults.file_mode)
+        };
+        if meta.permissions().readonly() && file_type != S_IFLNK {
+            perm &= !0o222;
+        }
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let (uid, gid) = defaults.owner.unwrap_or((0, 0));
+
+        SourceStat {
+            mode: file_type | perm,
+            uid,
+            gid,
+            size: if file_type == S_IFREG { meta.len() } else { 0 },
+            mtime: mtime.as_secs() as i64,
+            mtime_nsec: mtime.subsec_nanos() as i64,
+            ctime: mtime.as_secs() as i64,
+            rdev: 0,
+            ino: 0,
+            dev: 0,
+            btime: birth_time(meta),
+        }
+    }
+}
+
+/// Get birth time of the file, which is not supported by all file systems.
+fn birth_time(meta: &Metadata) -> u64 {
+    meta.created()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// List extended attributes of the file at `path`, without following symlinks.
+#[cfg(unix)]
+pub fn list_xattrs(path: &Path) -> Result<Vec<OsString>> {
+    Ok(xattr::list(path)?.collect())
+}
+
+/// Extended attributes are not supported on the platform, so there's none.
+#[cfg(not(unix))]
+pub fn list_xattrs(_path: &Path) -> Result<Vec<OsString>> {
+    Ok(Vec::new())
+}
+
+/// Get value of the extended attribute `key` of the file at `path`.
+#[cfg(unix)]
+pub fn get_xattr(path: &Path, key: &OsStr) -> Result<Option<Vec<u8>>> {
+    xattr::get(path, key)
+}
+
+#[cfg(not(unix))]
+pub fn get_xattr(_path: &Path, _key: &OsStr) -> Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+/// Get raw bytes of the file name `s`, non-unix names are encoded in UTF-8 as on Linux.
+#[cfg(unix)]
+pub fn os_str_to_bytes(s: &OsStr) -> Cow<[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn os_str_to_bytes(s: &OsStr) -> Cow<[u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// Convert raw bytes of a file name from images or tarballs into `OsStr`.
+#[cfg(unix)]
+pub fn os_str_from_bytes(b: &[u8]) -> Cow<OsStr> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(OsStr::from_bytes(b))
+}
+
+#[cfg(not(unix))]
+pub fn os_str_from_bytes(b: &[u8]) -> Cow<OsStr> {
+    match String::from_utf8_lossy(b) {
+        Cow::Borrowed(s) => Cow::Borrowed(OsStr::new(s)),
+        Cow::Owned(s) => Cow::Owned(OsString::from(s)),
+    }
+}
+
+/// Create a symlink at `path` pointing to `target`.
+#[cfg(unix)]
+pub fn symlink(target: &Path, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Symlinks are created as file symlinks, which may need the `SeCreateSymbolicLinkPrivilege`
+/// privilege or the developer mode.
+#[cfg(windows)]
+pub fn symlink(target: &Path, path: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_parse_source_defaults() {
+        assert_eq!(
+            SourceDefaults::parse_owner("1000:100").unwrap(),
+            (1000, 100)
+        );
+        assert!(SourceDefaults::parse_owner("1000").is_err());
+        assert!(SourceDefaults::parse_owner("root:0").is_err());
+
+        let defaults: SourceDefaults = "600:700".parse().unwrap();
+        assert_eq!(defaults.file_mode, 0o600);
+        assert_eq!(defaults.dir_mode, 0o700);
+        assert!("644".parse::<SourceDefaults>().is_err());
+        assert!("644:789".parse::<SourceDefaults>().is_err());
+        assert!("644:17777".parse::<SourceDefaults>().is_err());
+    }
+
+    #[test]
+    fn test_id_map() {
+        let mut map = IdMap::default();
+        assert_eq!(map.map(1000), 1000);
+
+        map.add("0:1000:1").unwrap();
+        map.add("1:100000:65536").unwrap();
+        assert_eq!(map.map(1000), 0);
+        assert_eq!(map.map(100000), 1);
+        assert_eq!(map.map(165535), 65536);
+        assert_eq!(map.map(165536), OVERFLOW_ID);
+        assert_eq!(map.map(0), OVERFLOW_ID);
+
+        assert!(map.add("0:1000").is_err());
+        assert!(map.add("0:100010:10").is_err());
+        assert!(map.add("0:200000:0").is_err());
+        assert!(map.add("0").is_err());
+        assert!(map.add("0:a:1").is_err());
+        assert!(map.add("4294967295:0:2").is_err());
+
+        let mut map = IdMap::default();
+        map.add("0:100000").unwrap();
+        assert_eq!(map.map(100000), 0);
+        assert_eq!(map.map(u32::MAX), u32::MAX - 100000);
+        assert_eq!(map.map(99999), OVERFLOW_ID);
+    }
+
+    #[test]
+    fn test_source_stat() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.as_path().join("foo");
+        std::fs::write(&file, b"foo data").unwrap();
+
+        let meta = file.symlink_metadata().unwrap();
+        let stat = SourceStat::new(&meta, &SourceDefaults::default());
+        assert_eq!(stat.size, 8);
+        assert_eq!(stat.mode & 0o170000, 0o100000);
+
+        let defaults = SourceDefaults {
+            owner: Some((1234, 5678)),
+            ..Default::default()
+        };
+        let stat = SourceStat::new(&meta, &defaults);
+        assert_eq!((stat.uid, stat.gid), (1234, 5678));
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::fs::MetadataExt;
+
+            let mut defaults = SourceDefaults::default();
+            defaults
+                .uid_map
+                .add(&format!("0:{}:1", meta.st_uid()))
+                .unwrap();
+            defaults
+                .gid_map
+                .add(&format!("100:{}:1", meta.st_gid()))
+                .unwrap();
+            let stat = SourceStat::new(&meta, &defaults);
+            assert_eq!((stat.uid, stat.gid), (0, 100));
+        }
+
+        let name = OsStr::new("foo");
+        assert_eq!(os_str_to_bytes(name).as_ref(), b"foo");
+        assert_eq!(os_str_from_bytes(b"foo").as_ref(), name);
+    }
+}
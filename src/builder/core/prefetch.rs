@@ -0,0 +1,416 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result};
+use rafs::metadata::layout::v5::{RafsV5BlobPrefetchTable, RafsV5PrefetchTable};
+
+use crate::builder::core::exclude::ExcludePatterns;
+use crate::builder::core::node::Node;
+use crate::builder::core::spill::ChunkSpill;
+
+// Adjacent ranges of a blob to prefetch are merged into one if the gap between them is not
+// bigger than this, trading a little more data for fewer reads.
+const BLOB_PREFETCH_MERGE_GAP: u64 = 0x2_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrefetchPolicy {
+    None,
+    /// Readahead will be issued from Fs layer, which leverages inode/chunkinfo to prefetch data
+    /// from blob no mather where it resides(OSS/Localfs). Basically, it is willing to cache the
+    /// data into blobcache(if exists). It's more nimble. With this policy applied, image builder
+    /// currently puts readahead files' data into a continuous region within blob which behaves very
+    /// similar to `Blob` policy.
+    Fs,
+    /// Readahead will be issued directly from backend/blob layer
+    Blob,
+}
+
+impl Default for PrefetchPolicy {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl FromStr for PrefetchPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "fs" => Ok(Self::Fs),
+            "blob" => Ok(Self::Blob),
+            _ => Err(anyhow!("invalid prefetch policy")),
+        }
+    }
+}
+
+/// Readahead file paths and glob patterns, and access frequency weights of file paths.
+type ReadaheadPatterns = (
+    BTreeMap<PathBuf, Option<u64>>,
+    ExcludePatterns,
+    BTreeMap<PathBuf, u64>,
+);
+
+/// Gather readahead file paths and glob patterns line by line.
+///
+/// Input format:
+///    printf "/relative/path/to/rootfs/1\n/relative/path/to/rootfs/2\n/usr/lib/**.so*"
+/// A path may be followed by whitespace and its access frequency weight, e.g. `/usr/bin/bash 42`,
+/// weights of glob patterns are ignored.
+/// This routine does not guarantee that specified file must exist in local filesystem,
+/// this is because we can't guarantee that source rootfs directory of parent bootstrap
+/// is located in local file system. Glob patterns are resolved against the built tree.
+fn gather_readahead_patterns<R: BufRead>(mut reader: R) -> Result<ReadaheadPatterns> {
+    let mut files = BTreeMap::new();
+    let mut globs = ExcludePatterns::default();
+    let mut weights = BTreeMap::new();
+
+    loop {
+        let mut file = String::new();
+        let size = reader
+            .read_line(&mut file)
+            .context("failed to parse readahead files")?;
+        if size == 0 {
+            break;
+        }
+        if file.trim().is_empty() {
+            continue;
+        }
+        let weighted = file
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .and_then(|(path, weight)| Some((path.trim_end().to_string(), weight.parse().ok()?)));
+        let (file, weight) = match weighted {
+            Some((path, weight)) => (path, Some(weight)),
+            None => (file, None),
+        };
+
+        let file_trimmed: PathBuf = file.trim().into();
+        // Sanity check for the list format.
+        if !file_trimmed.starts_with(Path::new("/")) {
+            warn!(
+                "Illegal file path specified. It {:?} must start with '/'",
+                file
+            );
+            continue;
+        }
+
+        if file.contains(&['*', '?'][..]) {
+            debug!("readahead pattern: {}", file.trim());
+            globs
+                .add(file.trim())
+                .with_context(|| format!("invalid readahead pattern {}", file.trim()))?;
+            continue;
+        }
+
+        debug!(
+            "readahead file: {}, trimmed file name {:?}",
+            file, file_trimmed
+        );
+        if let Some(weight) = weight {
+            weights.insert(file_trimmed.clone(), weight);
+        }
+        // The inode index is not decided yet, but will do during fs-walk.
+        files.insert(file_trimmed, None);
+    }
+
+    Ok((files, globs, weights))
+}
+
+#[derive(Default, Clone)]
+pub struct Prefetch {
+    pub policy: PrefetchPolicy,
+
+    pub disabled: bool,
+
+    /// Specify patterns for prefetch.
+    /// Their inode numbers will be persist to prefetch table. They could be directory's or regular
+    /// file's inode number, by which its inode index of inode table can be calculated.
+    readahead_patterns: BTreeMap<PathBuf, Option<u64>>,
+
+    /// Glob patterns for prefetch, files and directories matching them are added to
+    /// `readahead_patterns` during fs-walk.
+    readahead_globs: ExcludePatterns,
+
+    /// Entries of `readahead_patterns` resolved from `readahead_globs`.
+    resolved_globs: BTreeSet<PathBuf>,
+
+    /// Readahead file list, use BTreeMap to keep stable iteration order.
+    /// Files from this collection are all regular files and will be persisted to blob following
+    /// a certain scheme.
+    readahead_files: BTreeMap<PathBuf, u64>,
+
+    /// Access frequency weights of readahead paths, files under a weighted directory share the
+    /// weight of the directory.
+    readahead_weights: BTreeMap<PathBuf, u64>,
+}
+
+impl Prefetch {
+    /// Create a prefetch object with `policy`, which reads readahead files and patterns from
+    /// `list_file`, or from stdin if `list_file` is `None`.
+    pub fn new(policy: PrefetchPolicy, list_file: Option<&Path>) -> Result<Self> {
+        let (readahead_patterns, readahead_globs, readahead_weights) =
+            if policy == PrefetchPolicy::None {
+                (BTreeMap::new(), ExcludePatterns::default(), BTreeMap::new())
+            } else if let Some(path) = list_file {
+                let file = File::open(path)
+                    .with_context(|| format!("failed to open prefetch list {:?}", path))?;
+                gather_readahead_patterns(BufReader::new(file))
+                    .context("failed to get readahead files")?
+            } else {
+                let stdin = std::io::stdin();
+                gather_readahead_patterns(stdin.lock()).context("failed to get readahead files")?
+            };
+
+        Ok(Self {
+            policy,
+            disabled: false,
+            readahead_patterns,
+            readahead_globs,
+            resolved_globs: BTreeSet::new(),
+            readahead_files: BTreeMap::new(),
+            readahead_weights,
+        })
+    }
+
+    /// Create a prefetch object with `policy` to prefetch files and directories at absolute
+    /// `paths`, e.g. those recorded in the prefetch table of an existing bootstrap.
+    pub fn from_paths(policy: PrefetchPolicy, paths: &[PathBuf]) -> Self {
+        Prefetch {
+            policy,
+            readahead_patterns: paths.iter().map(|p| (p.clone(), None)).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn insert_if_need(&mut self, node: &Node) {
+        let path = node.target();
+        let inode = node.inode.ino();
+        let index = node.index;
+        let mut remove_node = false;
+        let mut covered = false;
+
+        if self.policy == PrefetchPolicy::None || self.disabled || node.inode.size() == 0 {
+            return;
+        }
+
+        for (f, v) in self.readahead_patterns.iter_mut() {
+            // As path is canonicalized, it should be reliable.
+            if path == f {
+                if self.policy == PrefetchPolicy::Fs {
+                    *v = Some(inode);
+                }
+                self.readahead_files.insert(path.clone(), index);
+                covered = true;
+            } else if path.starts_with(f) {
+                remove_node = true;
+                covered = true;
+                self.readahead_files.insert(path.clone(), index);
+            }
+        }
+
+        // Nodes under a hinted directory are prefetched along with the directory, so only
+        // add nodes not covered yet for matched glob patterns.
+        if !covered && self.readahead_globs.is_match(path) {
+            let v = if self.policy == PrefetchPolicy::Fs {
+                Some(inode)
+            } else {
+                None
+            };
+            self.readahead_patterns.insert(path.clone(), v);
+            self.resolved_globs.insert(path.clone());
+            self.readahead_files.insert(path.clone(), index);
+        }
+
+        if remove_node {
+            // Users can specify hinted parent directory with its child files hinted as well.
+            // Only put the parent directory into prefetch table since a hinted directory's
+            // all child files will be prefetched after mount.
+            self.readahead_patterns.remove(path);
+        }
+    }
+
+    pub fn contains(&self, node: &Node) -> bool {
+        self.readahead_files.contains_key(node.target())
+    }
+
+    /// Get indexes of readahead files, sorted by weight in descending order if weights are
+    /// specified, then by index.
+    pub fn get_file_indexes(&self) -> Vec<u64> {
+        let mut indexes: Vec<(u64, u64)> = self
+            .readahead_files
+            .iter()
+            .map(|(path, index)| (self.get_weight(path), *index))
+            .collect();
+
+        indexes.sort_unstable_by_key(|(weight, index)| (Reverse(*weight), *index));
+        indexes.into_iter().map(|(_, index)| index).collect()
+    }
+
+    fn get_weight(&self, path: &Path) -> u64 {
+        if self.readahead_weights.is_empty() {
+            return 0;
+        }
+        path.ancestors()
+            .find_map(|p| self.readahead_weights.get(p).copied())
+            .unwrap_or(0)
+    }
+
+    pub fn get_rafsv5_prefetch_table(&mut self) -> Option<RafsV5PrefetchTable> {
+        if self.policy == PrefetchPolicy::Fs {
+            let mut prefetch_table = RafsV5PrefetchTable::new();
+            for i in self.readahead_patterns.values().filter_map(|v| *v) {
+                prefetch_table.add_entry(i as u32);
+            }
+            Some(prefetch_table)
+        } else {
+            None
+        }
+    }
+
+    /// Get ranges of blobs covering data of readahead files, for `PrefetchPolicy::Blob`.
+    ///
+    /// Ranges are sorted by blob index and offset, and adjacent ranges are merged, so they can
+    /// be prefetched by a small number of large reads.
+    pub fn get_rafsv5_blob_prefetch_table(
+        &self,
+        nodes: &[Node],
+        spill: Option<&ChunkSpill>,
+    ) -> Result<Option<RafsV5BlobPrefetchTable>> {
+        if self.policy != PrefetchPolicy::Blob || self.readahead_files.is_empty() {
+            return Ok(None);
+        }
+
+        let mut chunks = Vec::new();
+        for index in self.readahead_files.values() {
+            let node = &nodes[*index as usize - 1];
+            for chunk in node.load_chunks(spill)?.iter() {
+                if chunk.compressed_size() > 0 {
+                    chunks.push((
+                        chunk.blob_index(),
+                        chunk.compressed_offset(),
+                        chunk.compressed_offset() + chunk.compressed_size() as u64,
+                    ));
+                }
+            }
+        }
+        chunks.sort_unstable();
+        chunks.dedup();
+
+        let mut table = RafsV5BlobPrefetchTable::new();
+        let mut range: Option<(u32, u64, u64)> = None;
+        for (blob_index, start, end) in chunks {
+            if let Some((idx, s, e)) = range.as_mut() {
+                if *idx == blob_index
+                    && start <= *e + BLOB_PREFETCH_MERGE_GAP
+                    && end.max(*e) - *s <= u32::MAX as u64
+                {
+                    *e = end.max(*e);
+                    continue;
+                }
+                table.add_entry(*idx, *s, (*e - *s) as u32);
+            }
+            range = Some((blob_index, start, end));
+        }
+        if let Some((idx, s, e)) = range {
+            table.add_entry(idx, s, (e - s) as u32);
+        }
+
+        Ok(Some(table))
+    }
+
+    pub fn disable(&mut self) {
+        self.disabled = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.disabled = false;
+        self.readahead_files.clear();
+        // Glob patterns will be resolved again against the new tree.
+        for path in std::mem::take(&mut self.resolved_globs) {
+            self.readahead_patterns.remove(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::ImageBuilder;
+    use rafs::metadata::{RafsMode, RafsSuper};
+    use rafs::RafsIoReader;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_gather_readahead_patterns() {
+        let input = "/usr/bin/bash\n\nusr/bin/ls\n/usr/lib/**.so*\n/etc/*.conf\n";
+        let (files, globs, weights) = gather_readahead_patterns(input.as_bytes()).unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(files.contains_key(Path::new("/usr/bin/bash")));
+        assert!(weights.is_empty());
+        assert!(globs.is_match(Path::new("/usr/lib/x86_64-linux-gnu/libc.so.6")));
+        assert!(globs.is_match(Path::new("/etc/ld.so.conf")));
+        assert!(!globs.is_match(Path::new("/usr/bin/bash")));
+    }
+
+    #[test]
+    fn test_readahead_weights() {
+        let input = "/usr/bin/bash 42\n/usr/lib 100\n/etc/hosts\n/usr/lib/**.so* 7\n";
+        let (files, _, weights) = gather_readahead_patterns(input.as_bytes()).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights[Path::new("/usr/bin/bash")], 42);
+        assert_eq!(weights[Path::new("/usr/lib")], 100);
+
+        let prefetch = Prefetch {
+            policy: PrefetchPolicy::Fs,
+            readahead_files: vec![
+                (PathBuf::from("/etc/hosts"), 1),
+                (PathBuf::from("/usr/bin/bash"), 2),
+                (PathBuf::from("/usr/lib/libc.so"), 4),
+                (PathBuf::from("/usr/lib/libm.so"), 3),
+            ]
+            .into_iter()
+            .collect(),
+            readahead_weights: weights,
+            ..Default::default()
+        };
+        assert_eq!(prefetch.get_file_indexes(), vec![3, 4, 2, 1]);
+    }
+
+    #[test]
+    fn test_blob_prefetch_table() {
+        let source = TempDir::new().unwrap();
+        std::fs::write(source.as_path().join("foo"), vec![0x5au8; 0x1000]).unwrap();
+        std::fs::write(source.as_path().join("bar"), vec![0xa5u8; 0x1000]).unwrap();
+        std::fs::write(source.as_path().join("baz"), b"baz data").unwrap();
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+        let paths = vec![PathBuf::from("/foo"), PathBuf::from("/bar")];
+        ImageBuilder::new(source.as_path())
+            .prefetch(Prefetch::from_paths(PrefetchPolicy::Blob, &paths))
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .unwrap();
+
+        let rs = RafsSuper::load_from_metadata(bootstrap.to_str().unwrap(), RafsMode::Direct, true)
+            .unwrap();
+        assert_eq!(rs.meta.blob_prefetch_table_entries, 1);
+        let mut reader = Box::new(File::open(&bootstrap).unwrap()) as RafsIoReader;
+        let ranges = rs.get_blob_prefetch_ranges(&mut reader).unwrap();
+        // Prefetched files are arranged at the start of the blob and merged into one range.
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].0, 0);
+        assert_eq!(ranges[0].1, 0);
+        assert!(ranges[0].2 > 0);
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Temporary file to keep chunk arrays of nodes out of memory.
+//!
+//! Chunk arrays are the major part of memory used by the builder for images with lots of files.
+//! Once chunks of a node have been dumped to the data blob, they are only needed again when
+//! dumping the bootstrap, so they are appended to a spill file and loaded back on demand.
+
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use rafs::metadata::layout::v5::RafsV5ChunkInfo;
+use vmm_sys_util::tempfile::TempFile;
+
+use super::context::RafsVersion;
+use super::node::ChunkWrapper;
+
+/// Append-only file storing chunk arrays, indexed by their offsets in the file.
+pub struct ChunkSpill {
+    file: TempFile,
+    version: RafsVersion,
+    // Size of data stored in the spill file.
+    size: Mutex<u64>,
+}
+
+impl ChunkSpill {
+    /// Create a spill file in directory `dir`, which is removed when dropped.
+    pub fn new(dir: &Path, version: RafsVersion) -> Result<Self> {
+        let file = TempFile::new_with_prefix(dir.join("nydus-spill-"))
+            .with_context(|| format!("failed to create spill file in {:?}", dir))?;
+
+        Ok(ChunkSpill {
+            file,
+            version,
+            size: Mutex::new(0),
+        })
+    }
+
+    /// Store `chunks` and return the offset to load them back.
+    pub fn store(&self, chunks: &[ChunkWrapper]) -> Result<u64> {
+        let mut buf = Vec::with_capacity(chunks.len() * size_of::<RafsV5ChunkInfo>());
+        for chunk in chunks {
+            match chunk {
+                ChunkWrapper::V5(c) => buf.extend_from_slice(c.as_ref()),
+                ChunkWrapper::V6(c) => buf.extend_from_slice(c.as_ref()),
+            }
+        }
+
+        let mut size = self.size.lock().unwrap();
+        let offset = *size;
+        self.as_file()
+            .write_all_at(&buf, offset)
+            .context("failed to write spill file")?;
+        *size += buf.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Load `count` chunks stored at `offset`.
+    pub fn load(&self, offset: u64, count: u32) -> Result<Vec<ChunkWrapper>> {
+        let mut chunks = Vec::with_capacity(count as usize);
+        let mut buf = vec![0u8; count as usize * size_of::<RafsV5ChunkInfo>()];
+        self.as_file()
+            .read_exact_at(&mut buf, offset)
+            .context("failed to read spill file")?;
+
+        for data in buf.chunks_exact(size_of::<RafsV5ChunkInfo>()) {
+            let mut c = RafsV5ChunkInfo::new();
+            c.as_mut().copy_from_slice(data);
+            chunks.push(match self.version {
+                RafsVersion::V5 => ChunkWrapper::V5(c),
+                RafsVersion::V6 => ChunkWrapper::V6(c),
+            });
+        }
+
+        Ok(chunks)
+    }
+
+    fn as_file(&self) -> &File {
+        self.file.as_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_utils::digest::RafsDigest;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_chunk_spill() {
+        let dir = TempDir::new().unwrap();
+        let spill = ChunkSpill::new(dir.as_path(), RafsVersion::V6).unwrap();
+
+        let chunks = (0..3)
+            .map(|i| {
+                let mut c = ChunkWrapper::new(RafsVersion::V6);
+                c.set_index(i);
+                c.set_id(RafsDigest {
+                    data: [i as u8; 32],
+                });
+                c
+            })
+            .collect::<Vec<_>>();
+        let off1 = spill.store(&chunks).unwrap();
+        let off2 = spill.store(&chunks[1..]).unwrap();
+        assert_eq!(off1, 0);
+        assert_eq!(off2, 3 * size_of::<RafsV5ChunkInfo>() as u64);
+
+        let loaded = spill.load(off2, 2).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(matches!(loaded[0], ChunkWrapper::V6(_)));
+        assert_eq!(loaded[0].index(), 1);
+        assert_eq!(loaded[1].id(), chunks[2].id());
+        assert!(spill.load(off2, 3).is_err());
+    }
+}
@@ -29,7 +29,7 @@ use super::node::{ChunkWrapper, InodeWrapper, Node, Overlay, WhiteoutSpec, White
 
 /// An in-memory tree structure to maintain information and topology of filesystem nodes.
 #[derive(Clone)]
-pub(crate) struct Tree {
+pub struct Tree {
     /// Filesystem node.
     pub node: Node,
     /// Children tree nodes.
@@ -342,6 +342,7 @@ impl<'a> MetadataTreeBuilder<'a> {
             target_vec,
             inode: inode_wrapper,
             chunks,
+            spilled_chunks: None,
             symlink,
             xattrs,
             ctime: 0,
@@ -350,6 +351,7 @@ impl<'a> MetadataTreeBuilder<'a> {
             v6_datalayout: 0,
             v6_compact_inode: false,
             v6_force_extended_inode: false,
+            v6_shared_xattrs: None,
         })
     }
 }
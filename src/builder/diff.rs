@@ -106,6 +106,40 @@
 //!   ...
 //! }
 
+//! The `layers` field of output JSON file describes the blob and bootstrap
+//! of every snapshot layer, include the skipped layers:
+
+//! {
+//!   ...
+//!   "layers": [
+//!     {
+//!       "source_path": "/path/to/snapshot-0",
+//!       "blob_id": "blob-0",
+//!       "bootstrap_name": null,
+//!       "chunk_count": 10,
+//!       "skipped": true
+//!     },
+//!     ...
+//!     {
+//!       "source_path": "/path/to/snapshot-3",
+//!       "blob_id": "blob-3",
+//!       "bootstrap_name": "bootstrap-3",
+//!       "chunk_count": 5,
+//!       "skipped": false
+//!     }
+//!   ]
+//!   ...
+//! }
+
+//! Layer build cache:
+
+//! Repeated builds, e.g. in CI, may dump blobs for identical layers again
+//! and again. With `--build-cache /path/to/cache-dir`, the blob and chunk
+//! information of every layer is stored in the cache directory, keyed by
+//! the layer digest computed from build options and metadata of the files
+//! added or modified by the layer, and reused by later builds. Cache entries
+//! failing validation are removed and the layer is built again.
+
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
@@ -116,16 +150,17 @@ use std::thread;
 
 use anyhow::{anyhow, Context, Result};
 
-use crate::builder::Builder;
-use crate::core::blob::Blob;
-use crate::core::bootstrap::Bootstrap;
-use crate::core::chunk_dict::{ChunkDict, HashChunkDict};
-use crate::core::context::{
+use crate::builder::core::blob::Blob;
+use crate::builder::core::bootstrap::Bootstrap;
+use crate::builder::core::build_cache::BuildCache;
+use crate::builder::core::chunk_dict::{ChunkDict, HashChunkDict};
+use crate::builder::core::context::{
     ArtifactStorage, BlobContext, BlobManager, BootstrapContext, BootstrapManager, BuildContext,
-    BuildOutput, RafsVersion,
+    BuildOutput, BuildOutputLayer, RafsVersion,
 };
-use crate::core::node::{ChunkWrapper, Node, Overlay};
-use crate::core::tree::Tree;
+use crate::builder::core::node::{ChunkWrapper, Node, Overlay};
+use crate::builder::core::tree::Tree;
+use crate::builder::Builder;
 use nydus_utils::digest::RafsDigest;
 use rafs::metadata::layout::RAFS_ROOT_INODE;
 use rafs::metadata::{Inode, RafsInode, RafsMode, RafsSuper};
@@ -218,6 +253,8 @@ fn walk_diff(
             Overlay::UpperAddition,
             ctx.chunk_size,
             ctx.explicit_uidgid,
+            &ctx.xattr_filter,
+            &ctx.source_defaults,
         )
         .with_context(|| format!("failed to create node from {:?}", child_path))?;
 
@@ -231,6 +268,8 @@ fn walk_diff(
                     Overlay::Lower,
                     ctx.chunk_size,
                     ctx.explicit_uidgid,
+                    &ctx.xattr_filter,
+                    &ctx.source_defaults,
                 )?;
                 if same_file(&lower_node, &child_node) {
                     child_node.overlay = Overlay::Lower;
@@ -277,6 +316,8 @@ fn walk_all(ctx: &BuildContext, dir_root: PathBuf, dir_path: PathBuf) -> Result<
             Overlay::UpperAddition,
             ctx.chunk_size,
             ctx.explicit_uidgid,
+            &ctx.xattr_filter,
+            &ctx.source_defaults,
         )
         .with_context(|| format!("failed to create node from {:?}", child_path))?;
 
@@ -287,6 +328,7 @@ fn walk_all(ctx: &BuildContext, dir_root: PathBuf, dir_path: PathBuf) -> Result<
 }
 
 // Dump blob for addition and modification files from upper nodes.
+#[allow(clippy::too_many_arguments)]
 fn dump_blob(
     ctx: Arc<BuildContext>,
     snapshot_idx: u32,
@@ -295,29 +337,37 @@ fn dump_blob(
     cached_nodes: CachedNodes,
     blob_nodes: &mut Vec<Node>,
     chunk_dict: Arc<dyn ChunkDict>,
+    build_cache: Option<BuildCache>,
 ) -> Result<Option<BlobContext>> {
-    let mut blob_ctx = BlobContext::new(blob_id, blob_storage)?;
-    blob_ctx.set_chunk_dict(chunk_dict);
-    blob_ctx.set_chunk_size(ctx.chunk_size);
-    blob_ctx.set_meta_info_enabled(true);
-
-    // Since all layers are built concurrently, it is not possible to deduplicate
-    // chunk between layers while ensuring reproducible build, so we only do
-    // deduplication within layers here, and use chunk dict to deduplicate most
-    // of chunks shared between layers.
-    let mut chunk_cache = HashChunkDict::default();
-
-    let mut blob = Blob::new();
-    let blob_ctx = if blob.dump(
-        ctx.as_ref(),
-        &mut blob_ctx,
-        snapshot_idx,
-        blob_nodes,
-        &mut chunk_cache,
-    )? {
-        Some(blob_ctx)
-    } else {
-        None
+    // Reuse the blob and chunks of the layer from build cache if possible.
+    let cache = match build_cache {
+        Some(cache) => Some((BuildCache::layer_digest(ctx.as_ref(), blob_nodes)?, cache)),
+        None => None,
+    };
+    let cached = cache.as_ref().and_then(|(digest, cache)| {
+        cache.load(ctx.as_ref(), digest, blob_storage.as_ref(), blob_nodes)
+    });
+
+    let blob_ctx = match cached {
+        Some(blob_ctx) => blob_ctx,
+        None => {
+            let blob_ctx = dump_layer_blob(
+                ctx.as_ref(),
+                snapshot_idx,
+                blob_id,
+                blob_storage.clone(),
+                blob_nodes,
+                chunk_dict,
+            )?;
+            if let Some((digest, cache)) = cache.as_ref() {
+                if let Err(e) =
+                    cache.store(digest, blob_ctx.as_ref(), blob_storage.as_ref(), blob_nodes)
+                {
+                    warn!("failed to store layer {} into build cache, {:?}", digest, e);
+                }
+            }
+            blob_ctx
+        }
     };
 
     // Put the regular files from upper snapshot into CachedNodes, to make the
@@ -350,6 +400,39 @@ fn dump_blob(
     Ok(blob_ctx)
 }
 
+fn dump_layer_blob(
+    ctx: &BuildContext,
+    snapshot_idx: u32,
+    blob_id: String,
+    blob_storage: Option<ArtifactStorage>,
+    blob_nodes: &mut Vec<Node>,
+    chunk_dict: Arc<dyn ChunkDict>,
+) -> Result<Option<BlobContext>> {
+    let mut blob_ctx = BlobContext::new(blob_id, blob_storage, ctx.blob_offset)?;
+    blob_ctx.set_chunk_dict(chunk_dict);
+    blob_ctx.set_chunk_size(ctx.chunk_size);
+    blob_ctx.set_meta_info_enabled(true);
+
+    // Since all layers are built concurrently, it is not possible to deduplicate
+    // chunk between layers while ensuring reproducible build, so we only do
+    // deduplication within layers here, and use chunk dict to deduplicate most
+    // of chunks shared between layers.
+    let mut chunk_cache = HashChunkDict::default();
+
+    let mut blob = Blob::new();
+    if blob.dump(
+        ctx,
+        &mut blob_ctx,
+        snapshot_idx,
+        blob_nodes,
+        &mut chunk_cache,
+    )? {
+        Ok(Some(blob_ctx))
+    } else {
+        Ok(None)
+    }
+}
+
 pub struct DiffBuilder {
     /// The source_path and extra_paths compose up all the paths required
     /// by a diff build workflow, the paths have two formats, user can
@@ -400,6 +483,8 @@ pub struct DiffBuilder {
     /// The index of snapshot to skip and start building from there for
     /// speeding up diff build.
     skip_snapshot_idx: Option<u32>,
+    /// Cache to reuse blobs and chunks of layers built by former builds.
+    build_cache: Option<BuildCache>,
 }
 
 impl DiffBuilder {
@@ -407,6 +492,7 @@ impl DiffBuilder {
         extra_paths: Vec<PathBuf>,
         diff_hint: bool,
         skip_snapshot_idx: Option<&str>,
+        build_cache: Option<BuildCache>,
     ) -> Result<Self> {
         let skip_snapshot_idx = if let Some(idx) = skip_snapshot_idx {
             Some(
@@ -421,6 +507,7 @@ impl DiffBuilder {
             extra_paths,
             diff_hint,
             cached_nodes: Arc::new(RwLock::new(HashMap::new())),
+            build_cache,
         })
     }
 
@@ -511,6 +598,8 @@ impl DiffBuilder {
             Overlay::UpperAddition,
             ctx.chunk_size,
             ctx.explicit_uidgid,
+            &ctx.xattr_filter,
+            &ctx.source_defaults,
         )?;
         let mut tree = Tree::new(root);
         tree.children = self.build_tree_from_children(
@@ -549,8 +638,13 @@ impl DiffBuilder {
                 Overlay::UpperAddition,
                 ctx.chunk_size,
                 ctx.explicit_uidgid,
+                &ctx.xattr_filter,
+                &ctx.source_defaults,
             )
             .with_context(|| format!("failed to create node from {:?}", child_path))?;
+            if !child_node.apply_special_file_policy(&ctx.special_files, ctx.whiteout_spec) {
+                continue;
+            }
 
             let is_dir = child_node.is_dir();
 
@@ -680,6 +774,7 @@ impl DiffBuilder {
             let hint_path_idx = idx + base;
             let hint_path = paths[hint_path_idx].clone();
             let chunk_dict = blob_mgr.get_chunk_dict().clone();
+            let build_cache = self.build_cache.clone();
             let worker = thread::spawn(move || -> Result<Option<BlobContext>> {
                 info!("[{}] diff building with hint {:?}", idx, hint_path);
 
@@ -694,6 +789,7 @@ impl DiffBuilder {
                     cached_nodes,
                     &mut blob_nodes,
                     chunk_dict,
+                    build_cache,
                 )?;
 
                 Ok(blob_ctx)
@@ -721,7 +817,7 @@ impl DiffBuilder {
             bootstrap_mgr.add(bootstrap_ctx);
         }
 
-        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+        build_output(blob_mgr, bootstrap_mgr, &paths[..base], skip)
     }
 
     fn build_with_diff(
@@ -744,6 +840,7 @@ impl DiffBuilder {
             let cached_nodes = self.cached_nodes.clone();
             let (lower, upper) = (paths[idx].clone(), paths[idx + 1].clone());
             let chunk_dict = blob_mgr.get_chunk_dict().clone();
+            let build_cache = self.build_cache.clone();
             let worker = thread::spawn(move || -> Result<Option<BlobContext>> {
                 info!("[{}] diff building {:?} -> {:?}", idx, lower, upper);
 
@@ -761,6 +858,7 @@ impl DiffBuilder {
                     cached_nodes,
                     &mut blob_nodes,
                     chunk_dict,
+                    build_cache,
                 )?;
 
                 Ok(blob_ctx)
@@ -772,6 +870,7 @@ impl DiffBuilder {
             let blob_ctx = worker.join().expect("panic on diff build")?;
             blob_mgr.add(blob_ctx);
             let mut bootstrap_ctx = bootstrap_mgr.create_ctx()?;
+            bootstrap_ctx.name = format!("bootstrap-{}", snapshot_idx);
             let snapshot_path = paths[snapshot_idx + 1].clone().unwrap();
             self.build_bootstrap(
                 ctx,
@@ -780,12 +879,47 @@ impl DiffBuilder {
                 snapshot_idx as u32,
                 snapshot_path,
             )?;
+            bootstrap_mgr.add(bootstrap_ctx);
         }
 
-        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+        // Safe to unwrap because upper paths must be exist.
+        let paths: Vec<PathBuf> = paths.into_iter().skip(1).map(|p| p.unwrap()).collect();
+        build_output(blob_mgr, bootstrap_mgr, &paths, 0)
     }
 }
 
+/// Generate build output with the layer to blob and bootstrap mapping, the first `skip`
+/// layers are skipped and have no bootstrap dumped in this build.
+fn build_output(
+    blob_mgr: &BlobManager,
+    bootstrap_mgr: &BootstrapManager,
+    snapshot_paths: &[PathBuf],
+    skip: usize,
+) -> Result<BuildOutput> {
+    let mut output = BuildOutput::new(blob_mgr, bootstrap_mgr)?;
+    let blobs = blob_mgr.get_blobs();
+    let bootstraps = bootstrap_mgr.get_bootstraps();
+
+    output.layers = snapshot_paths
+        .iter()
+        .enumerate()
+        .map(|(idx, path)| {
+            let blob = blobs.get(idx).and_then(|b| b.as_ref());
+            BuildOutputLayer {
+                source_path: path.display().to_string(),
+                blob_id: blob.map(|b| b.blob_id.clone()),
+                bootstrap_name: idx
+                    .checked_sub(skip)
+                    .and_then(|i| bootstraps.get(i).cloned()),
+                chunk_count: blob.map(|b| b.chunk_count).unwrap_or(0),
+                skipped: idx < skip,
+            }
+        })
+        .collect();
+
+    Ok(output)
+}
+
 impl Builder for DiffBuilder {
     fn build(
         &mut self,
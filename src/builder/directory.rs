@@ -0,0 +1,301 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::fs::DirEntry;
+
+use anyhow::{Context, Result};
+
+use crate::builder::core::blob::Blob;
+use crate::builder::core::bootstrap::Bootstrap;
+use crate::builder::core::context::{
+    BlobContext, BlobManager, BootstrapContext, BootstrapManager, BuildContext, BuildOutput,
+    RafsVersion,
+};
+use crate::builder::core::node::{Node, Overlay};
+use crate::builder::core::tree::Tree;
+use crate::builder::Builder;
+
+struct FilesystemTreeBuilder {}
+
+impl FilesystemTreeBuilder {
+    fn new() -> Self {
+        Self {}
+    }
+
+    /// Walk directory to build node tree by DFS
+    fn load_children(
+        &self,
+        ctx: &mut BuildContext,
+        bootstrap_ctx: &mut BootstrapContext,
+        parent: &mut Node,
+    ) -> Result<Vec<Tree>> {
+        let mut result = Vec::new();
+        if !parent.is_dir() {
+            return Ok(result);
+        }
+
+        let children = fs::read_dir(parent.path())
+            .with_context(|| format!("failed to read dir {:?}", parent.path()))?;
+        let children = children.collect::<Result<Vec<DirEntry>, std::io::Error>>()?;
+
+        event_tracer!("load_from_directory", +children.len());
+        for child in children {
+            let path = child.path();
+            if !ctx.excludes.is_empty() {
+                // Safe to unwrap because all children are under the source directory.
+                let rel_path = path.strip_prefix(&ctx.source_path).unwrap();
+                if ctx.excludes.is_excluded(rel_path) {
+                    debug!("exclude {:?} from image", path);
+                    event_tracer!("excluded_files", +1);
+                    continue;
+                }
+            }
+            let mut child = Node::new(
+                ctx.fs_version,
+                ctx.source_path.clone(),
+                path.clone(),
+                Overlay::UpperAddition,
+                ctx.chunk_size,
+                parent.explicit_uidgid,
+                &ctx.xattr_filter,
+                &ctx.source_defaults,
+            )
+            .with_context(|| format!("failed to create node {:?}", path))?;
+
+            // as per OCI spec, whiteout file should not be present within final image
+            // or filesystem, only existed in layers.
+            if !bootstrap_ctx.layered
+                && child.whiteout_type(ctx.whiteout_spec).is_some()
+                && !child.is_overlayfs_opaque(ctx.whiteout_spec)
+            {
+                continue;
+            }
+            if !child.apply_special_file_policy(&ctx.special_files, ctx.whiteout_spec) {
+                continue;
+            }
+
+            let mut child = Tree::new(child);
+            child.children = self.load_children(ctx, bootstrap_ctx, &mut child.node)?;
+            result.push(child);
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Default)]
+pub struct DirectoryBuilder {}
+
+impl DirectoryBuilder {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Build node tree from a filesystem directory
+    fn build_tree_from_fs(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_ctx: &mut BootstrapContext,
+    ) -> Result<Tree> {
+        let node = Node::new(
+            ctx.fs_version,
+            ctx.source_path.clone(),
+            ctx.source_path.clone(),
+            Overlay::UpperAddition,
+            ctx.chunk_size,
+            ctx.explicit_uidgid,
+            &ctx.xattr_filter,
+            &ctx.source_defaults,
+        )?;
+        let mut tree = Tree::new(node);
+        let tree_builder = FilesystemTreeBuilder::new();
+
+        tree.children = timing_tracer!(
+            { tree_builder.load_children(ctx, bootstrap_ctx, &mut tree.node) },
+            "load_from_directory"
+        )?;
+
+        Ok(tree)
+    }
+}
+
+impl Builder for DirectoryBuilder {
+    fn build(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput> {
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx()?;
+        // Scan source directory to build upper layer tree.
+        let mut tree = self.build_tree_from_fs(ctx, &mut bootstrap_ctx)?;
+        let mut bootstrap = Bootstrap::new()?;
+        if bootstrap_ctx.layered {
+            // Merge with lower layer if there's one, do not prepare `prefetch` list during merging.
+            ctx.prefetch.disable();
+            bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree)?;
+            tree = bootstrap.apply(ctx, &mut bootstrap_ctx, bootstrap_mgr, blob_mgr, None)?;
+        }
+        // Convert the hierarchy tree into an array, stored in `bootstrap_ctx.nodes`.
+        timing_tracer!(
+            { bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree) },
+            "build_bootstrap"
+        )?;
+        // Nodes have been copied into `bootstrap_ctx.nodes`, release the tree to save memory.
+        drop(tree);
+        ctx.prepare_chunk_spill(bootstrap_ctx.nodes.len())?;
+
+        // Dump blob file
+        let mut blob_ctx = BlobContext::new(
+            ctx.blob_id.clone(),
+            ctx.blob_storage.clone(),
+            ctx.blob_offset,
+        )?;
+        blob_mgr.resolve_chunk_dict(ctx, &bootstrap_ctx.nodes)?;
+        blob_ctx.set_chunk_dict(blob_mgr.get_chunk_dict());
+        blob_ctx.set_chunk_size(ctx.chunk_size);
+        blob_ctx.set_meta_info_enabled(true);
+        blob_mgr.extend_blob_table_from_chunk_dict()?;
+
+        let blob_index = blob_mgr.alloc_index()?;
+        let mut blob = Blob::new();
+        let blob_exists = timing_tracer!(
+            {
+                blob.dump(
+                    ctx,
+                    &mut blob_ctx,
+                    blob_index,
+                    &mut bootstrap_ctx.nodes,
+                    &mut blob_mgr.chunk_dict_cache,
+                )
+            },
+            "dump_blob"
+        )?;
+
+        // Add new blobs to blob table, including those sealed by the blob size limit.
+        for sealed in std::mem::take(&mut blob_ctx.sealed_blobs) {
+            blob_mgr.add(Some(sealed));
+        }
+        blob_mgr.add(if blob_exists { Some(blob_ctx) } else { None });
+
+        // Dump bootstrap file
+        match ctx.fs_version {
+            RafsVersion::V5 => {
+                let blob_table = blob_mgr.to_blob_table_v5(ctx, None)?;
+                bootstrap.dump_rafsv5(ctx, &mut bootstrap_ctx, &blob_table)?
+            }
+            RafsVersion::V6 => {
+                let blob_table = blob_mgr.to_blob_table_v6(ctx, None)?;
+                bootstrap.dump_rafsv6(ctx, &mut bootstrap_ctx, &blob_table)?
+            }
+        }
+
+        if ctx.blob_toc && blob_exists {
+            if let Some(blob_ctx) = blob_mgr.get_last_blob() {
+                let bootstrap_path = bootstrap_mgr.get_bootstrap_path(&bootstrap_ctx.name);
+                Blob::dump_toc(ctx, blob_ctx, &bootstrap_path)?;
+            }
+        }
+
+        bootstrap_mgr.add(bootstrap_ctx);
+        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use nydus_utils::digest;
+    use storage::compress;
+    use vmm_sys_util::tempdir::TempDir;
+
+    use super::*;
+    use crate::builder::core::context::{ArtifactStorage, SourceType};
+    use crate::builder::core::node::WhiteoutSpec;
+    use crate::builder::core::prefetch::Prefetch;
+
+    fn shuffle_children(tree: &mut Tree) {
+        tree.children.reverse();
+        let len = tree.children.len();
+        if len > 2 {
+            tree.children.swap(0, len / 2);
+        }
+        for child in tree.children.iter_mut() {
+            shuffle_children(child);
+        }
+    }
+
+    fn build_bootstrap(source: &Path, version: RafsVersion, shuffle: bool) -> Vec<u8> {
+        let output = TempDir::new().unwrap();
+        let bootstrap_path = output.as_path().join("bootstrap");
+        let mut ctx = BuildContext::new(
+            String::new(),
+            version.is_v6(),
+            compress::Algorithm::Lz4Block,
+            digest::Algorithm::Blake3,
+            false,
+            WhiteoutSpec::Oci,
+            SourceType::Directory,
+            source.to_path_buf(),
+            Prefetch::default(),
+            None,
+        );
+        ctx.set_fs_version(version);
+        let bootstrap_mgr =
+            BootstrapManager::new(ArtifactStorage::SingleFile(bootstrap_path.clone()), None);
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx().unwrap();
+
+        // Simulate a source filesystem returning children in another order.
+        let mut tree = DirectoryBuilder::new()
+            .build_tree_from_fs(&mut ctx, &mut bootstrap_ctx)
+            .unwrap();
+        if shuffle {
+            shuffle_children(&mut tree);
+        }
+
+        let mut bootstrap = Bootstrap::new().unwrap();
+        bootstrap
+            .build(&mut ctx, &mut bootstrap_ctx, &mut tree)
+            .unwrap();
+        let blob_mgr = BlobManager::new();
+        match version {
+            RafsVersion::V5 => {
+                let blob_table = blob_mgr.to_blob_table_v5(&ctx, None).unwrap();
+                bootstrap
+                    .dump_rafsv5(&mut ctx, &mut bootstrap_ctx, &blob_table)
+                    .unwrap();
+            }
+            RafsVersion::V6 => {
+                let blob_table = blob_mgr.to_blob_table_v6(&ctx, None).unwrap();
+                bootstrap
+                    .dump_rafsv6(&mut ctx, &mut bootstrap_ctx, &blob_table)
+                    .unwrap();
+            }
+        }
+        drop(bootstrap_ctx);
+
+        fs::read(&bootstrap_path).unwrap()
+    }
+
+    #[test]
+    fn test_build_with_shuffled_readdir_order() {
+        let source = TempDir::new().unwrap();
+        let dir = source.as_path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        // Names sorting before and after dot and dotdot, and in different cases.
+        for name in ["-a", "+b", "B", "a", "b", "_c", "z", "\u{e9}"].iter() {
+            fs::write(source.as_path().join(name), b"").unwrap();
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        symlink("a", dir.join("link")).unwrap();
+
+        for version in [RafsVersion::V5, RafsVersion::V6].iter() {
+            let expected = build_bootstrap(source.as_path(), *version, false);
+            assert_eq!(build_bootstrap(source.as_path(), *version, true), expected);
+        }
+    }
+}
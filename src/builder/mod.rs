@@ -0,0 +1,784 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Build RAFS images in-process.
+//!
+//! [ImageBuilder](struct.ImageBuilder.html) is the stable interface to create an image from a
+//! source directory, a stargz index or an OCI image tarball, without shelling out to the
+//! `nydus-image` tool:
+//!
+//! ```no_run
+//! use nydus::builder::{ImageBuilder, RafsVersion};
+//!
+//! let output = ImageBuilder::new("/path/to/rootfs")
+//!     .fs_version(RafsVersion::V6)
+//!     .bootstrap("/path/to/bootstrap")
+//!     .blob_dir("/path/to/blobs")
+//!     .build()
+//!     .unwrap();
+//! println!("blob size {:?}", output.blob_size);
+//! ```
+//!
+//! The building blocks in [core](core/index.html), e.g. `BuildContext`, `BlobManager` and
+//! `BootstrapManager`, are also exported for advanced usages, but they may change between
+//! releases.
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use nydus_utils::digest;
+use rafs::RafsIoReader;
+use storage::{compress, RAFS_DEFAULT_CHUNK_SIZE};
+
+use crate::builder::core::chunk_dict::import_chunk_dict;
+pub use crate::builder::core::context::{ArtifactStorage, BuildOutput, RafsVersion, SourceType};
+use crate::builder::core::context::{BlobManager, BootstrapManager, BuildContext};
+use crate::builder::core::exclude::ExcludePatterns;
+use crate::builder::core::node::{SpecialFilePolicy, WhiteoutSpec, XattrFilter};
+use crate::builder::core::platform::SourceDefaults;
+use crate::builder::core::prefetch::Prefetch;
+
+pub use diff::DiffBuilder;
+pub use directory::DirectoryBuilder;
+pub use ociv1::OciV1Builder;
+pub use recompress::RecompressBuilder;
+pub use stargz::StargzBuilder;
+pub use zstd_chunked::ZstdChunkedBuilder;
+
+#[macro_use]
+pub mod trace;
+pub mod core;
+pub mod oci;
+
+mod diff;
+mod directory;
+mod ociv1;
+mod recompress;
+mod stargz;
+mod zstd_chunked;
+
+pub trait Builder {
+    fn build(
+        &mut self,
+        build_ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput>;
+}
+
+/// Builder to create a RAFS image, whose options default to those of `nydus-image create`.
+pub struct ImageBuilder {
+    source_type: SourceType,
+    source_path: PathBuf,
+    bootstrap: Option<PathBuf>,
+    blob_storage: Option<ArtifactStorage>,
+    blob_id: String,
+    blob_size_limit: u64,
+    chunk_spill_threshold: usize,
+    fs_version: RafsVersion,
+    chunk_size: u32,
+    compressor: compress::Algorithm,
+    digester: digest::Algorithm,
+    whiteout_spec: WhiteoutSpec,
+    repeatable: bool,
+    parent_bootstrap: Option<PathBuf>,
+    chunk_dict: Option<String>,
+    prefetch: Prefetch,
+    excludes: ExcludePatterns,
+    xattr_filter: XattrFilter,
+    source_defaults: SourceDefaults,
+    special_files: SpecialFilePolicy,
+    dirent_index: bool,
+    sparse_file: bool,
+    ociv1_work_dir: Option<String>,
+}
+
+impl ImageBuilder {
+    /// Create a builder to build an image from the directory at `source`.
+    pub fn new<P: AsRef<Path>>(source: P) -> Self {
+        ImageBuilder {
+            source_type: SourceType::Directory,
+            source_path: source.as_ref().to_path_buf(),
+            bootstrap: None,
+            blob_storage: None,
+            blob_id: String::new(),
+            blob_size_limit: 0,
+            chunk_spill_threshold: 0,
+            fs_version: RafsVersion::V5,
+            chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            compressor: compress::Algorithm::Lz4Block,
+            digester: digest::Algorithm::Blake3,
+            whiteout_spec: WhiteoutSpec::Oci,
+            repeatable: false,
+            parent_bootstrap: None,
+            chunk_dict: None,
+            prefetch: Prefetch::default(),
+            excludes: ExcludePatterns::default(),
+            xattr_filter: XattrFilter::default(),
+            source_defaults: SourceDefaults::default(),
+            special_files: SpecialFilePolicy::default(),
+            dirent_index: false,
+            sparse_file: false,
+            ociv1_work_dir: None,
+        }
+    }
+
+    /// Set type of the source, `SourceType::Diff` is not supported.
+    pub fn source_type(mut self, source_type: SourceType) -> Self {
+        self.source_type = source_type;
+        self
+    }
+
+    /// Write the bootstrap to the file at `path`, which is required.
+    pub fn bootstrap<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.bootstrap = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Write the data blob to the file at `path`.
+    pub fn blob<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.blob_storage = Some(ArtifactStorage::SingleFile(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Write the data blob into the directory at `path`, named by its digest.
+    pub fn blob_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.blob_storage = Some(ArtifactStorage::FileDir(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Set id of the data blob, which defaults to the digest of the blob.
+    pub fn blob_id(mut self, blob_id: &str) -> Self {
+        self.blob_id = blob_id.to_string();
+        self
+    }
+
+    /// Split data into multiple blobs of at most `limit` bytes, which requires `blob_dir()`.
+    pub fn blob_size_limit(mut self, limit: u64) -> Self {
+        self.blob_size_limit = limit;
+        self
+    }
+
+    /// Keep chunk information in a temporary file instead of memory if there are more than
+    /// `threshold` files, which is only supported by directory and OCIv1 sources.
+    pub fn chunk_spill_threshold(mut self, threshold: usize) -> Self {
+        self.chunk_spill_threshold = threshold;
+        self
+    }
+
+    pub fn fs_version(mut self, fs_version: RafsVersion) -> Self {
+        self.fs_version = fs_version;
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn compressor(mut self, compressor: compress::Algorithm) -> Self {
+        self.compressor = compressor;
+        self
+    }
+
+    pub fn digester(mut self, digester: digest::Algorithm) -> Self {
+        self.digester = digester;
+        self
+    }
+
+    pub fn whiteout_spec(mut self, whiteout_spec: WhiteoutSpec) -> Self {
+        self.whiteout_spec = whiteout_spec;
+        self
+    }
+
+    /// Ignore uid/gid and birth time of source files and don't record build time, to produce
+    /// reproducible images.
+    pub fn repeatable(mut self, repeatable: bool) -> Self {
+        self.repeatable = repeatable;
+        self
+    }
+
+    /// Build on top of the image whose bootstrap is at `path`.
+    pub fn parent_bootstrap<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.parent_bootstrap = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Deduplicate chunks with the chunk dictionary, in the same format as `--chunk-dict`,
+    /// e.g. `bootstrap=/path/to/bootstrap`.
+    pub fn chunk_dict(mut self, chunk_dict: &str) -> Self {
+        self.chunk_dict = Some(chunk_dict.to_string());
+        self
+    }
+
+    pub fn prefetch(mut self, prefetch: Prefetch) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Exclude files matching the glob pattern from the directory source.
+    pub fn exclude(mut self, pattern: &str) -> Result<Self> {
+        self.excludes.add(pattern)?;
+        Ok(self)
+    }
+
+    pub fn xattr_filter(mut self, xattr_filter: XattrFilter) -> Self {
+        self.xattr_filter = xattr_filter;
+        self
+    }
+
+    /// Set owner and permission bits of files in the directory source.
+    pub fn source_defaults(mut self, source_defaults: SourceDefaults) -> Self {
+        self.source_defaults = source_defaults;
+        self
+    }
+
+    /// Drop device files or sockets of the source, which are kept by default.
+    pub fn special_files(mut self, special_files: SpecialFilePolicy) -> Self {
+        self.special_files = special_files;
+        self
+    }
+
+    /// Generate a name index for large directories to speed up lookups of missing entries,
+    /// which is only supported by Rafs v6.
+    pub fn dirent_index(mut self, dirent_index: bool) -> Self {
+        self.dirent_index = dirent_index;
+        self
+    }
+
+    /// Don't generate chunks for holes of sparse files.
+    pub fn sparse_file(mut self, sparse_file: bool) -> Self {
+        self.sparse_file = sparse_file;
+        self
+    }
+
+    /// Set the directory to unpack OCI image tarballs into.
+    pub fn ociv1_work_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.ociv1_work_dir = Some(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    /// Build the image.
+    pub fn build(self) -> Result<BuildOutput> {
+        let bootstrap = self
+            .bootstrap
+            .ok_or_else(|| anyhow!("bootstrap path is not specified"))?;
+        let mut compressor = self.compressor;
+        let mut digester = self.digester;
+        let mut builder: Box<dyn Builder> = match self.source_type {
+            SourceType::Directory => Box::new(DirectoryBuilder::new()),
+            SourceType::StargzIndex => {
+                ensure!(
+                    !self.blob_id.is_empty(),
+                    "blob id of stargz index is required"
+                );
+                compressor = compress::Algorithm::GZip;
+                digester = digest::Algorithm::Sha256;
+                Box::new(StargzBuilder::new())
+            }
+            SourceType::ZstdChunked => {
+                ensure!(
+                    !self.blob_id.is_empty(),
+                    "blob id of zstd:chunked layer is required"
+                );
+                ensure!(
+                    self.fs_version.is_v5(),
+                    "zstd:chunked layer only supports RAFS v5"
+                );
+                compressor = compress::Algorithm::Zstd;
+                digester = digest::Algorithm::Sha256;
+                Box::new(ZstdChunkedBuilder::new())
+            }
+            SourceType::OciV1 => Box::new(OciV1Builder::new(self.ociv1_work_dir.as_deref())),
+            SourceType::Diff => bail!("diff source is not supported by image builder"),
+        };
+        if !self.excludes.is_empty() && self.source_type != SourceType::Directory {
+            bail!("exclude is only supported by directory source");
+        }
+        if self.dirent_index {
+            ensure!(
+                self.fs_version.is_v6(),
+                "dirent index is only supported by RAFS v6"
+            );
+        }
+        if self.chunk_spill_threshold > 0 {
+            ensure!(
+                self.source_type == SourceType::Directory || self.source_type == SourceType::OciV1,
+                "chunk spill is only supported by directory and ociv1 sources"
+            );
+        }
+        if self.blob_size_limit > 0 {
+            ensure!(
+                matches!(self.blob_storage, Some(ArtifactStorage::FileDir(_))),
+                "blob size limit requires a blob directory"
+            );
+            ensure!(
+                self.blob_id.is_empty(),
+                "blob size limit can't be used with a specified blob id"
+            );
+            ensure!(
+                self.source_type != SourceType::StargzIndex
+                    && self.source_type != SourceType::ZstdChunked,
+                "blob size limit is not supported by stargz index and zstd:chunked sources"
+            );
+            ensure!(
+                self.blob_size_limit >= self.chunk_size as u64,
+                "blob size limit is smaller than chunk size"
+            );
+        }
+
+        let mut build_ctx = BuildContext::new(
+            self.blob_id,
+            self.fs_version.is_v6(),
+            compressor,
+            digester,
+            !self.repeatable,
+            self.whiteout_spec,
+            self.source_type,
+            self.source_path,
+            self.prefetch,
+            self.blob_storage,
+        );
+        build_ctx.set_fs_version(self.fs_version);
+        build_ctx.set_chunk_size(self.chunk_size);
+        build_ctx.set_blob_size_limit(self.blob_size_limit);
+        build_ctx.set_chunk_spill(self.chunk_spill_threshold, None);
+        build_ctx.set_excludes(self.excludes);
+        build_ctx.set_xattr_filter(self.xattr_filter);
+        build_ctx.set_source_defaults(self.source_defaults);
+        build_ctx.set_special_files(self.special_files);
+        build_ctx.set_dirent_index(self.dirent_index);
+        build_ctx.set_sparse_file(self.sparse_file);
+        build_ctx.set_builder_version(env!("CARGO_PKG_VERSION").to_string());
+        if !self.repeatable {
+            build_ctx.set_build_time(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            );
+        }
+
+        let mut blob_mgr = BlobManager::new();
+        if let Some(chunk_dict) = self.chunk_dict {
+            blob_mgr.set_chunk_dict(import_chunk_dict(&chunk_dict)?);
+        }
+        let parent_bootstrap = match self.parent_bootstrap {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .open(&path)
+                    .with_context(|| format!("failed to open parent bootstrap file {:?}", path))?;
+                Some(Box::new(file) as RafsIoReader)
+            }
+            None => None,
+        };
+        let mut bootstrap_mgr =
+            BootstrapManager::new(ArtifactStorage::SingleFile(bootstrap), parent_bootstrap);
+
+        builder.build(&mut build_ctx, &mut bootstrap_mgr, &mut blob_mgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_utils::digest::RafsDigest;
+    use rafs::metadata::layout::v6::{
+        align_offset, RafsV6Dirent, RafsV6InodeChunkAddr, RafsV6InodeCompact, RafsV6InodeExtended,
+        RafsV6OndiskInodeTrait, EROFS_NULL_ADDR,
+    };
+    use std::ffi::OsStr;
+    use std::mem::size_of;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_image_builder() {
+        let source = TempDir::new().unwrap();
+        std::fs::write(source.as_path().join("foo"), b"foo data").unwrap();
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        assert!(ImageBuilder::new(source.as_path()).build().is_err());
+        assert!(ImageBuilder::new(source.as_path())
+            .source_type(SourceType::Diff)
+            .bootstrap(&bootstrap)
+            .build()
+            .is_err());
+
+        let output = ImageBuilder::new(source.as_path())
+            .fs_version(RafsVersion::V6)
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .unwrap();
+        assert!(output.blob_size.unwrap() > 0);
+        assert!(bootstrap.exists());
+
+        let rs = rafs::metadata::RafsSuper::load_from_metadata(
+            bootstrap.to_str().unwrap(),
+            rafs::metadata::RafsMode::Direct,
+            true,
+        )
+        .unwrap();
+        let image_digest = rs.meta.image_digest.unwrap();
+        assert_eq!(output.image_digest, Some(image_digest.to_string()));
+        let mut reader = Box::new(std::fs::File::open(&bootstrap).unwrap()) as RafsIoReader;
+        rs.verify_image_digest(&mut reader, output.image_digest.as_deref())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_image_builder_blob_size_limit() {
+        let source = TempDir::new().unwrap();
+        // Pseudo random data which can't be compressed.
+        let mut seed = 0x1234_5678u32;
+        for name in ["a", "b", "c"].iter() {
+            let data = (0..0x18000)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (seed >> 16) as u8
+                })
+                .collect::<Vec<u8>>();
+            std::fs::write(source.as_path().join(name), data).unwrap();
+        }
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        let output = ImageBuilder::new(source.as_path())
+            .fs_version(RafsVersion::V6)
+            .chunk_size(0x10000)
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .blob_size_limit(0x28000)
+            .build()
+            .unwrap();
+        let blobs = output.blobs.iter().flatten().collect::<Vec<_>>();
+        assert!(blobs.len() > 1);
+        for blob in blobs.iter() {
+            let path = output_dir.as_path().join(&blob.blob_id);
+            assert!(std::fs::metadata(path).unwrap().len() <= 0x28000);
+        }
+
+        let rs = rafs::metadata::RafsSuper::load_from_metadata(
+            bootstrap.to_str().unwrap(),
+            rafs::metadata::RafsMode::Direct,
+            true,
+        )
+        .unwrap();
+        assert_eq!(rs.superblock.get_blob_infos().len(), blobs.len());
+
+        assert!(ImageBuilder::new(source.as_path())
+            .bootstrap(&bootstrap)
+            .blob(output_dir.as_path().join("blob"))
+            .blob_size_limit(0x28000)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_image_builder_chunk_spill() {
+        let source = TempDir::new().unwrap();
+        std::fs::create_dir(source.as_path().join("dir")).unwrap();
+        std::fs::write(source.as_path().join("a"), vec![0x5au8; 0x30000]).unwrap();
+        std::fs::write(source.as_path().join("dir/b"), b"b data").unwrap();
+        let output_dir = TempDir::new().unwrap();
+
+        for version in [RafsVersion::V5, RafsVersion::V6].iter() {
+            let mut bootstraps = Vec::new();
+            for threshold in [0, 1].iter() {
+                let bootstrap = output_dir
+                    .as_path()
+                    .join(format!("bootstrap-{}", threshold));
+                ImageBuilder::new(source.as_path())
+                    .fs_version(*version)
+                    .chunk_size(0x10000)
+                    .repeatable(true)
+                    .bootstrap(&bootstrap)
+                    .blob_dir(output_dir.as_path())
+                    .chunk_spill_threshold(*threshold)
+                    .build()
+                    .unwrap();
+                bootstraps.push(std::fs::read(&bootstrap).unwrap());
+            }
+            // Spilling chunks doesn't change the generated image.
+            assert_eq!(bootstraps[0], bootstraps[1]);
+        }
+    }
+
+    #[test]
+    fn test_image_builder_provenance() {
+        let source = TempDir::new().unwrap();
+        std::fs::write(source.as_path().join("foo"), b"foo data").unwrap();
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        for version in [RafsVersion::V5, RafsVersion::V6].iter() {
+            for repeatable in [false, true].iter() {
+                ImageBuilder::new(source.as_path())
+                    .fs_version(*version)
+                    .repeatable(*repeatable)
+                    .bootstrap(&bootstrap)
+                    .blob_dir(output_dir.as_path())
+                    .build()
+                    .unwrap();
+
+                let rs = rafs::metadata::RafsSuper::load_from_metadata(
+                    bootstrap.to_str().unwrap(),
+                    rafs::metadata::RafsMode::Direct,
+                    true,
+                )
+                .unwrap();
+                let blobs = rs.superblock.get_blob_infos();
+                assert_eq!(blobs.len(), 1);
+                let provenance = blobs[0].provenance().unwrap();
+                assert_eq!(provenance.build_time == 0, *repeatable);
+                assert_ne!(provenance.source_digest, RafsDigest::default());
+                // Rafs v5 bootstrap has no room for the builder version.
+                if version.is_v6() {
+                    assert_eq!(provenance.builder_version, env!("CARGO_PKG_VERSION"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_image_builder_reproducible_blob_id() {
+        let source = TempDir::new().unwrap();
+        std::fs::write(source.as_path().join("foo"), b"foo data").unwrap();
+        let mut blob_ids = Vec::new();
+        let mut build_times = Vec::new();
+
+        for round in 0..2 {
+            if round > 0 {
+                // Build time is recorded in seconds.
+                std::thread::sleep(std::time::Duration::from_millis(1100));
+            }
+            let output_dir = TempDir::new().unwrap();
+            let bootstrap = output_dir.as_path().join("bootstrap");
+            let output = ImageBuilder::new(source.as_path())
+                .fs_version(RafsVersion::V6)
+                .bootstrap(&bootstrap)
+                .blob_dir(output_dir.as_path())
+                .build()
+                .unwrap();
+            let blob_id = output.blobs[0].as_ref().unwrap().blob_id.clone();
+            let data = std::fs::read(output_dir.as_path().join(&blob_id)).unwrap();
+            assert_eq!(
+                RafsDigest::from_buf(&data, digest::Algorithm::Sha256).to_string(),
+                blob_id
+            );
+
+            let rs = rafs::metadata::RafsSuper::load_from_metadata(
+                bootstrap.to_str().unwrap(),
+                rafs::metadata::RafsMode::Direct,
+                true,
+            )
+            .unwrap();
+            let provenance = rs.superblock.get_blob_infos()[0].provenance().unwrap();
+            build_times.push(provenance.build_time);
+            blob_ids.push(blob_id);
+        }
+
+        assert_ne!(build_times[0], build_times[1]);
+        assert_eq!(blob_ids[0], blob_ids[1]);
+    }
+
+    #[test]
+    fn test_image_builder_btime() {
+        let source = TempDir::new().unwrap();
+        // Rafs v6 stores python bytecode files in extended inodes, which carry birth time.
+        let file = source.as_path().join("foo.pyc");
+        std::fs::write(&file, b"foo data").unwrap();
+        let btime = std::fs::metadata(&file)
+            .unwrap()
+            .created()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        for version in [RafsVersion::V5, RafsVersion::V6].iter() {
+            for repeatable in [false, true].iter() {
+                ImageBuilder::new(source.as_path())
+                    .fs_version(*version)
+                    .repeatable(*repeatable)
+                    .bootstrap(&bootstrap)
+                    .blob_dir(output_dir.as_path())
+                    .build()
+                    .unwrap();
+
+                let rs = rafs::metadata::RafsSuper::load_from_metadata(
+                    bootstrap.to_str().unwrap(),
+                    rafs::metadata::RafsMode::Direct,
+                    false,
+                )
+                .unwrap();
+                let expected = if *repeatable { 0 } else { btime };
+                if version.is_v5() {
+                    let ino = rs.ino_from_path(Path::new("/foo.pyc")).unwrap();
+                    let inode = rs.get_inode(ino, false).unwrap();
+                    assert_eq!(inode.get_btime(), expected);
+                } else {
+                    let nid = rs
+                        .superblock
+                        .lookup_child(rs.meta.root_nid as u64, OsStr::new("foo.pyc"))
+                        .unwrap();
+                    let data = std::fs::read(&bootstrap).unwrap();
+                    let offset = (rs.meta.meta_addr + nid * 32) as usize;
+                    let mut inode = RafsV6InodeExtended::new();
+                    inode
+                        .as_mut()
+                        .copy_from_slice(&data[offset..offset + size_of::<RafsV6InodeExtended>()]);
+                    assert_eq!(inode.btime(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_image_builder_sparse_file() {
+        let source = TempDir::new().unwrap();
+        let file = source.as_path().join("sparse");
+        {
+            use std::io::{Seek, SeekFrom, Write};
+            let mut f = std::fs::File::create(&file).unwrap();
+            f.write_all(&[0x5a; 0x1000]).unwrap();
+            f.seek(SeekFrom::Start(0x3000)).unwrap();
+            f.write_all(&[0xa5; 0x800]).unwrap();
+        }
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        for version in [RafsVersion::V5, RafsVersion::V6].iter() {
+            ImageBuilder::new(source.as_path())
+                .fs_version(*version)
+                .chunk_size(0x1000)
+                .sparse_file(true)
+                .bootstrap(&bootstrap)
+                .blob_dir(output_dir.as_path())
+                .build()
+                .unwrap();
+
+            let rs = rafs::metadata::RafsSuper::load_from_metadata(
+                bootstrap.to_str().unwrap(),
+                rafs::metadata::RafsMode::Direct,
+                false,
+            )
+            .unwrap();
+            if version.is_v5() {
+                let ino = rs.ino_from_path(Path::new("/sparse")).unwrap();
+                let inode = rs.get_inode(ino, false).unwrap();
+                // Only chunks with data are stored.
+                assert_eq!(inode.get_child_count(), 2);
+                assert_eq!(inode.get_chunk_info(1).unwrap().uncompressed_size(), 0x800);
+            } else {
+                // Chunk indexes are kept for holes, with null block address.
+                let nid = rs
+                    .superblock
+                    .lookup_child(rs.meta.root_nid as u64, OsStr::new("sparse"))
+                    .unwrap();
+                let data = std::fs::read(&bootstrap).unwrap();
+                let offset = (rs.meta.meta_addr + nid * 32) as usize;
+                let mut inode = RafsV6InodeCompact::new();
+                inode
+                    .as_mut()
+                    .copy_from_slice(&data[offset..offset + size_of::<RafsV6InodeCompact>()]);
+                let unit = size_of::<RafsV6InodeChunkAddr>();
+                let mut pos = align_offset((offset + inode.size_with_xattr()) as u64, unit as u64);
+                let mut addrs = Vec::new();
+                for _ in 0..4 {
+                    let mut chunk = RafsV6InodeChunkAddr::new();
+                    chunk
+                        .as_mut()
+                        .copy_from_slice(&data[pos as usize..pos as usize + unit]);
+                    addrs.push(chunk.block_addr());
+                    pos += unit as u64;
+                }
+                assert_ne!(addrs[0], EROFS_NULL_ADDR);
+                assert_eq!(addrs[1], EROFS_NULL_ADDR);
+                assert_eq!(addrs[2], EROFS_NULL_ADDR);
+                assert_ne!(addrs[3], EROFS_NULL_ADDR);
+            }
+        }
+    }
+
+    #[test]
+    fn test_image_builder_dirent_index() {
+        use std::io::ErrorKind;
+
+        let source = TempDir::new().unwrap();
+        let dir = source.as_path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        for i in 0..100 {
+            std::fs::write(dir.join(format!("file-{:03}", i)), b"data").unwrap();
+        }
+        let output_dir = TempDir::new().unwrap();
+        let bootstrap = output_dir.as_path().join("bootstrap");
+
+        assert!(ImageBuilder::new(source.as_path())
+            .dirent_index(true)
+            .bootstrap(&bootstrap)
+            .blob_dir(output_dir.as_path())
+            .build()
+            .is_err());
+
+        for dirent_index in [true, false].iter() {
+            ImageBuilder::new(source.as_path())
+                .fs_version(RafsVersion::V6)
+                .dirent_index(*dirent_index)
+                .bootstrap(&bootstrap)
+                .blob_dir(output_dir.as_path())
+                .build()
+                .unwrap();
+            let load = || {
+                rafs::metadata::RafsSuper::load_from_metadata(
+                    bootstrap.to_str().unwrap(),
+                    rafs::metadata::RafsMode::Direct,
+                    false,
+                )
+                .unwrap()
+            };
+
+            let rs = load();
+            assert_eq!(rs.meta.has_dirent_index(), *dirent_index);
+            let sb = rs.superblock.clone();
+            let nid = sb
+                .lookup_child(rs.meta.root_nid as u64, OsStr::new("dir"))
+                .unwrap();
+            assert!(sb.lookup_child(nid, OsStr::new("file-050")).is_ok());
+            let err = sb.lookup_child(nid, OsStr::new("none")).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+
+            // Corrupt dirents of the directory, which precede the names ".", ".." and "file-*",
+            // so any scan of them fails with EINVAL.
+            let mut data = std::fs::read(&bootstrap).unwrap();
+            let names = b"...file-000file-001";
+            let pos = data.windows(names.len()).position(|w| w == names).unwrap();
+            let dirents = 102 * size_of::<RafsV6Dirent>();
+            for b in data[pos - dirents..pos].iter_mut() {
+                *b = 0xff;
+            }
+            std::fs::write(&bootstrap, &data).unwrap();
+
+            let rs = load();
+            let sb = rs.superblock.clone();
+            let err = sb.lookup_child(nid, OsStr::new("file-050")).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            let not_found = (0..100)
+                .map(|i| sb.lookup_child(nid, OsStr::new(&format!("none-{}", i))))
+                .filter(|r| matches!(r, Err(e) if e.kind() == ErrorKind::NotFound))
+                .count();
+            if *dirent_index {
+                assert!(not_found > 90);
+            } else {
+                assert_eq!(not_found, 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Export the builder output as an OCI artifact.
+//!
+//! The nydus bootstrap and data blobs are stored into an OCI image layout directory, using
+//! the same media types and annotations as nydusify. The layout can be pushed to registry by
+//! the registry storage backend, or by tools supporting OCI image layout (for example
+//! `oras copy --from-oci-layout` or `skopeo copy oci:`). When a subject manifest is given,
+//! the generated manifest references it through the `subject` field so registries supporting
+//! the OCI referrers API can link it to the original image.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage::backend::registry::Registry;
+
+use crate::builder::core::context::{ArtifactStorage, BuildOutput};
+
+pub const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const MEDIA_TYPE_OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const MEDIA_TYPE_OCI_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub const MEDIA_TYPE_NYDUS_BLOB: &str = "application/vnd.oci.image.layer.nydus.blob.v1";
+
+pub const MANIFEST_OS_FEATURE_NYDUS: &str = "nydus.remoteimage.v1";
+pub const BOOTSTRAP_FILE_NAME_IN_LAYER: &str = "image/image.boot";
+
+const LAYER_ANNOTATION_NYDUS_BLOB: &str = "containerd.io/snapshot/nydus-blob";
+const LAYER_ANNOTATION_NYDUS_BLOB_IDS: &str = "containerd.io/snapshot/nydus-blob-ids";
+const LAYER_ANNOTATION_NYDUS_BOOTSTRAP: &str = "containerd.io/snapshot/nydus-bootstrap";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OciDescriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<OciPlatform>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OciPlatform {
+    pub architecture: String,
+    pub os: String,
+    #[serde(rename = "os.features", default, skip_serializing_if = "Vec::is_empty")]
+    pub os_features: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OciManifest {
+    schema_version: u32,
+    media_type: String,
+    config: OciDescriptor,
+    layers: Vec<OciDescriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subject: Option<OciDescriptor>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OciIndex {
+    schema_version: u32,
+    media_type: String,
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Serialize, Debug)]
+struct OciRootFs {
+    #[serde(rename = "type")]
+    fs_type: String,
+    diff_ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct OciImageConfig {
+    architecture: String,
+    os: String,
+    rootfs: OciRootFs,
+}
+
+/// Subset of an image manifest needed to reference it as artifact subject.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubjectManifest {
+    #[serde(default)]
+    media_type: Option<String>,
+}
+
+/// Writer to generate an OCI image layout from the builder output.
+pub struct OciArtifact {
+    dir: PathBuf,
+}
+
+impl OciArtifact {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("blobs").join("sha256"))
+            .with_context(|| format!("failed to create OCI layout directory {:?}", dir))?;
+
+        Ok(Self { dir })
+    }
+
+    /// Build a descriptor referencing the image manifest stored in `path`.
+    pub fn load_subject<P: AsRef<Path>>(path: P) -> Result<OciDescriptor> {
+        let path = path.as_ref();
+        let data = fs::read(path)
+            .with_context(|| format!("failed to read subject manifest {:?}", path))?;
+        let manifest: SubjectManifest = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid subject manifest {:?}", path))?;
+
+        Ok(OciDescriptor {
+            media_type: manifest
+                .media_type
+                .unwrap_or_else(|| MEDIA_TYPE_OCI_MANIFEST.to_string()),
+            digest: format!("sha256:{:x}", Sha256::digest(&data)),
+            size: data.len() as u64,
+            ..Default::default()
+        })
+    }
+
+    /// Store bootstrap and blobs of the build output into the layout and generate the
+    /// referencing manifest, returning the descriptor of the generated manifest.
+    pub fn export(
+        &self,
+        build_output: &BuildOutput,
+        blob_storage: Option<&ArtifactStorage>,
+        bootstrap_path: &Path,
+        subject: Option<OciDescriptor>,
+    ) -> Result<OciDescriptor> {
+        let mut layers = Vec::new();
+        let mut diff_ids = Vec::new();
+        let blob_ids = build_output.get_exists_blobs();
+        let last_blob = build_output.blobs.iter().flatten().last();
+
+        for blob in build_output.blobs.iter().flatten() {
+            // Only the blob generated by this build is available for a single file storage,
+            // other blobs are referenced by their ids and should already exist in registry.
+            let local_path = match blob_storage {
+                Some(ArtifactStorage::FileDir(dir)) => Some(dir.join(&blob.blob_id)),
+                Some(ArtifactStorage::SingleFile(path))
+                    if build_output.blob_size.is_some()
+                        && last_blob.map(|b| b.blob_id == blob.blob_id) == Some(true) =>
+                {
+                    Some(path.to_path_buf())
+                }
+                _ => None,
+            };
+            let mut desc = match local_path {
+                Some(path) if path.is_file() => self.store_file(&path, MEDIA_TYPE_NYDUS_BLOB)?,
+                _ => OciDescriptor {
+                    media_type: MEDIA_TYPE_NYDUS_BLOB.to_string(),
+                    digest: format!("sha256:{}", blob.blob_id),
+                    size: blob.blob_size,
+                    ..Default::default()
+                },
+            };
+            desc.annotations
+                .insert(LAYER_ANNOTATION_NYDUS_BLOB.to_string(), "true".to_string());
+            diff_ids.push(desc.digest.clone());
+            layers.push(desc);
+        }
+
+        let (mut desc, diff_id) = self.store_bootstrap(bootstrap_path)?;
+        desc.annotations.insert(
+            LAYER_ANNOTATION_NYDUS_BOOTSTRAP.to_string(),
+            "true".to_string(),
+        );
+        desc.annotations.insert(
+            LAYER_ANNOTATION_NYDUS_BLOB_IDS.to_string(),
+            serde_json::to_string(&blob_ids)?,
+        );
+        diff_ids.push(diff_id);
+        layers.push(desc);
+
+        let config = OciImageConfig {
+            architecture: Self::arch().to_string(),
+            os: "linux".to_string(),
+            rootfs: OciRootFs {
+                fs_type: "layers".to_string(),
+                diff_ids,
+            },
+        };
+        let config = self.store_bytes(&serde_json::to_vec(&config)?, MEDIA_TYPE_OCI_CONFIG)?;
+
+        let manifest = OciManifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_OCI_MANIFEST.to_string(),
+            config,
+            layers,
+            subject,
+        };
+        let mut manifest =
+            self.store_bytes(&serde_json::to_vec(&manifest)?, MEDIA_TYPE_OCI_MANIFEST)?;
+        manifest.platform = Some(OciPlatform {
+            architecture: Self::arch().to_string(),
+            os: "linux".to_string(),
+            os_features: vec![MANIFEST_OS_FEATURE_NYDUS.to_string()],
+        });
+
+        let index = OciIndex {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_OCI_INDEX.to_string(),
+            manifests: vec![manifest.clone()],
+        };
+        fs::write(self.dir.join("index.json"), serde_json::to_vec(&index)?)
+            .context("failed to write OCI index")?;
+        fs::write(
+            self.dir.join("oci-layout"),
+            br#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .context("failed to write OCI layout file")?;
+
+        Ok(manifest)
+    }
+
+    /// Push blobs, config and the manifest described by `manifest` from the layout to
+    /// registry, and tag the manifest with `tag` if given, otherwise reference it by digest.
+    ///
+    /// Blobs missing in the layout, i.e. blobs referenced but not generated by this build,
+    /// must already exist in the registry repository.
+    pub fn push(
+        &self,
+        manifest: &OciDescriptor,
+        registry: &Registry,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        let data = fs::read(self.blob_path(Self::hex(&manifest.digest)))
+            .with_context(|| format!("failed to read manifest {}", manifest.digest))?;
+        let content: OciManifest = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid manifest {}", manifest.digest))?;
+
+        for desc in content.layers.iter().chain(Some(&content.config)) {
+            let path = self.blob_path(Self::hex(&desc.digest));
+            if path.is_file() {
+                let file =
+                    File::open(&path).with_context(|| format!("failed to open {:?}", path))?;
+                registry
+                    .push_blob(&desc.digest, desc.size, file)
+                    .map_err(|e| anyhow!("failed to push blob {}: {:?}", desc.digest, e))?;
+            } else if !registry
+                .blob_exists(&desc.digest)
+                .map_err(|e| anyhow!("failed to check blob {}: {:?}", desc.digest, e))?
+            {
+                bail!(
+                    "blob {} is neither in the OCI layout nor in registry",
+                    desc.digest
+                );
+            }
+        }
+
+        registry
+            .push_manifest(tag.unwrap_or(&manifest.digest), &manifest.media_type, data)
+            .map_err(|e| anyhow!("failed to push manifest {}: {:?}", manifest.digest, e))?;
+
+        Ok(())
+    }
+
+    /// Pack bootstrap into a gzipped tar layer, returns the descriptor and the diff id.
+    fn store_bootstrap(&self, bootstrap_path: &Path) -> Result<(OciDescriptor, String)> {
+        let mut bootstrap = File::open(bootstrap_path)
+            .with_context(|| format!("failed to open bootstrap {:?}", bootstrap_path))?;
+        let size = bootstrap.metadata()?.len();
+
+        let encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let mut builder = tar::Builder::new(HashWriter::new(encoder));
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(size);
+        builder
+            .append_data(&mut header, BOOTSTRAP_FILE_NAME_IN_LAYER, &mut bootstrap)
+            .context("failed to pack bootstrap")?;
+        let writer = builder.into_inner().context("failed to pack bootstrap")?;
+
+        let diff_id = format!("sha256:{:x}", writer.hasher.finalize());
+        let data = writer.inner.finish()?;
+        let desc = self.store_bytes(&data, MEDIA_TYPE_OCI_LAYER_GZIP)?;
+
+        Ok((desc, diff_id))
+    }
+
+    fn store_bytes(&self, data: &[u8], media_type: &str) -> Result<OciDescriptor> {
+        let digest = format!("{:x}", Sha256::digest(data));
+        let path = self.blob_path(&digest);
+        fs::write(&path, data).with_context(|| format!("failed to write {:?}", path))?;
+
+        Ok(OciDescriptor {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", digest),
+            size: data.len() as u64,
+            ..Default::default()
+        })
+    }
+
+    fn store_file(&self, src: &Path, media_type: &str) -> Result<OciDescriptor> {
+        let mut file = File::open(src).with_context(|| format!("failed to open {:?}", src))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 0x10_0000];
+        let mut size = 0u64;
+        loop {
+            let sz = file.read(&mut buf)?;
+            if sz == 0 {
+                break;
+            }
+            hasher.update(&buf[..sz]);
+            size += sz as u64;
+        }
+        let digest = format!("{:x}", hasher.finalize());
+        let dst = self.blob_path(&digest);
+
+        if !dst.exists() {
+            // Prefer hard link to avoid copying large blobs, fallback to copy across devices.
+            if fs::hard_link(src, &dst).is_err() {
+                let mut writer = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&dst)
+                    .with_context(|| format!("failed to create {:?}", dst))?;
+                io::copy(&mut File::open(src)?, &mut writer)
+                    .with_context(|| format!("failed to copy {:?} to {:?}", src, dst))?;
+            }
+        }
+
+        Ok(OciDescriptor {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", digest),
+            size,
+            ..Default::default()
+        })
+    }
+
+    fn hex(digest: &str) -> &str {
+        digest.trim_start_matches("sha256:")
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("blobs").join("sha256").join(digest)
+    }
+
+    fn arch() -> &'static str {
+        match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        }
+    }
+}
+
+/// Writer to calculate sha256 digest of data passed through.
+struct HashWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let sz = self.inner.write(buf)?;
+        self.hasher.update(&buf[..sz]);
+        Ok(sz)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_store_bootstrap() {
+        let dir = TempDir::new().unwrap();
+        let bootstrap = TempFile::new().unwrap();
+        fs::write(bootstrap.as_path(), b"bootstrap").unwrap();
+        let artifact = OciArtifact::new(dir.as_path()).unwrap();
+
+        let (desc, diff_id) = artifact.store_bootstrap(bootstrap.as_path()).unwrap();
+        assert_eq!(desc.media_type, MEDIA_TYPE_OCI_LAYER_GZIP);
+        let data = fs::read(artifact.blob_path(&desc.digest["sha256:".len()..])).unwrap();
+        assert_eq!(desc.size, data.len() as u64);
+
+        let mut layer = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .read_to_end(&mut layer)
+            .unwrap();
+        assert_eq!(diff_id, format!("sha256:{:x}", Sha256::digest(&layer)));
+
+        let mut archive = tar::Archive::new(layer.as_slice());
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(
+            entry.path().unwrap().to_str(),
+            Some(BOOTSTRAP_FILE_NAME_IN_LAYER)
+        );
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"bootstrap");
+        assert!(entries.next().is_none());
+    }
+}
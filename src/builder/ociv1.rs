@@ -0,0 +1,1203 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Build a nydus image from an OCI or docker image tarball.
+//!
+//! Layers of the image are located by the docker `manifest.json` or the OCI `index.json` within
+//! the tarball, then unpacked into a working directory one by one. Metadata from tar headers,
+//! such as owner, permission bits, device numbers and xattrs, is recorded separately instead of
+//! being applied to the unpacked files, so no privilege is needed for the conversion. Finally
+//! layers are merged into one tree by `Tree::apply()` with OCI whiteout handling, and dumped as
+//! a single RAFS filesystem.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use nix::sys::stat::makedev;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use vmm_sys_util::tempdir::TempDir;
+
+use rafs::metadata::layout::RafsXAttrs;
+
+use crate::builder::core::blob::Blob;
+use crate::builder::core::bootstrap::Bootstrap;
+use crate::builder::core::context::{
+    BlobContext, BlobManager, BootstrapManager, BuildContext, BuildOutput, RafsVersion,
+};
+use crate::builder::core::node::{Node, Overlay, SpecialFilePolicy, WhiteoutSpec};
+use crate::builder::core::platform;
+use crate::builder::core::tree::Tree;
+use crate::builder::oci::OciDescriptor;
+use crate::builder::Builder;
+
+const TAR_BLOCK_SIZE: u64 = 512;
+// Upper limit of tar extension headers and image manifests to load into memory.
+const MAX_METADATA_SIZE: u64 = 0x100_0000;
+// Upper limit of symlinks to follow when looking up a file in the image tarball.
+const MAX_LINK_DEPTH: usize = 8;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const DOCKER_MANIFEST_FILE: &str = "manifest.json";
+const OCI_INDEX_FILE: &str = "index.json";
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TarEntryType {
+    Regular,
+    HardLink,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    Fifo,
+    Other(u8),
+}
+
+impl From<u8> for TarEntryType {
+    fn from(flag: u8) -> Self {
+        match flag {
+            b'0' | b'\0' | b'7' => TarEntryType::Regular,
+            b'1' => TarEntryType::HardLink,
+            b'2' => TarEntryType::Symlink,
+            b'3' => TarEntryType::CharDevice,
+            b'4' => TarEntryType::BlockDevice,
+            b'5' => TarEntryType::Directory,
+            b'6' => TarEntryType::Fifo,
+            v => TarEntryType::Other(v),
+        }
+    }
+}
+
+/// Metadata of an entry in tar stream.
+#[derive(Clone, Debug)]
+struct TarEntry {
+    /// Normalized relative path, empty for the root directory.
+    path: PathBuf,
+    entry_type: TarEntryType,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u64,
+    mtime_nsec: u32,
+    size: u64,
+    /// Target of symlinks and hardlinks.
+    link: PathBuf,
+    dev_major: u64,
+    dev_minor: u64,
+    xattrs: Vec<(OsString, Vec<u8>)>,
+}
+
+impl TarEntry {
+    fn is_dir(&self) -> bool {
+        self.entry_type == TarEntryType::Directory
+    }
+
+    /// Apply metadata from tar header to a node built from the unpacked file.
+    fn apply_to(&self, node: &mut Node, ctx: &BuildContext) {
+        let file_type = match self.entry_type {
+            TarEntryType::CharDevice => libc::S_IFCHR,
+            TarEntryType::BlockDevice => libc::S_IFBLK,
+            TarEntryType::Fifo => libc::S_IFIFO,
+            _ => node.inode.mode() & libc::S_IFMT,
+        };
+        node.inode.set_mode(file_type | (self.mode & 0o7777));
+        if ctx.explicit_uidgid {
+            node.inode.set_uid(self.uid);
+            node.inode.set_gid(self.gid);
+        }
+        node.inode.set_mtime(self.mtime, self.mtime_nsec);
+        if matches!(
+            self.entry_type,
+            TarEntryType::CharDevice | TarEntryType::BlockDevice
+        ) {
+            node.rdev = makedev(self.dev_major, self.dev_minor);
+            node.inode.set_rdev(node.rdev as u32);
+        }
+
+        node.xattrs = RafsXAttrs::default();
+        for (key, value) in self.xattrs.iter() {
+            if !ctx.xattr_filter.is_excluded(key) {
+                node.xattrs.add(key.clone(), value.clone());
+            }
+        }
+        node.inode.set_has_xattr(!node.xattrs.is_empty());
+    }
+}
+
+/// Reader to iterate entries of a tar stream, supporting ustar, GNU and PAX formats.
+struct TarReader<R: Read> {
+    reader: R,
+    skip: fn(&mut R, u64) -> io::Result<()>,
+    // Position in the tar stream.
+    position: u64,
+    // Data of current entry which has not been consumed yet.
+    remain: u64,
+    // Padding after data of current entry.
+    padding: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    fn new(reader: R) -> Self {
+        TarReader {
+            reader,
+            skip: skip_by_read::<R>,
+            position: 0,
+            remain: 0,
+            padding: 0,
+        }
+    }
+
+    /// Get the next entry from the tar stream, data of the previous entry is skipped if not
+    /// consumed yet.
+    ///
+    /// After returning, the reader is positioned at data of the entry.
+    fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut pax = Vec::new();
+
+        loop {
+            self.skip_data()?;
+
+            let mut header = [0u8; TAR_BLOCK_SIZE as usize];
+            let mut cnt = 0;
+            while cnt < header.len() {
+                match self.reader.read(&mut header[cnt..]) {
+                    Ok(0) => break,
+                    Ok(n) => cnt += n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e).context("failed to read tar header"),
+                }
+            }
+            self.position += cnt as u64;
+            // Tolerate tar streams without the trailing zero blocks.
+            if cnt == 0 || header.iter().all(|b| *b == 0) {
+                return Ok(None);
+            }
+            ensure!(cnt == header.len(), "unexpected end of tar stream");
+            verify_checksum(&header)?;
+
+            let size = parse_number(&header[124..136])?;
+            let flag = header[156];
+            self.remain = size;
+            self.padding = (TAR_BLOCK_SIZE - size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+
+            // Extension headers, which apply to the next entry.
+            if matches!(flag, b'L' | b'K' | b'x' | b'g') {
+                ensure!(
+                    size <= MAX_METADATA_SIZE,
+                    "tar extension header is too big, {} bytes",
+                    size
+                );
+                let mut data = Vec::with_capacity(size as usize);
+                self.read_to_end(&mut data)
+                    .context("failed to read tar extension header")?;
+                match flag {
+                    b'L' => long_name = Some(trim_nul(&data).to_vec()),
+                    b'K' => long_link = Some(trim_nul(&data).to_vec()),
+                    b'x' => pax = parse_pax_records(&data)?,
+                    // Global PAX headers are ignored.
+                    _ => {}
+                }
+                continue;
+            }
+
+            let mut name = trim_nul(&header[0..100]).to_vec();
+            // Only POSIX ustar uses the prefix field, GNU tar stores other information there.
+            if &header[257..263] == b"ustar\0" {
+                let prefix = trim_nul(&header[345..500]);
+                if !prefix.is_empty() {
+                    name = [prefix, b"/", &name].concat();
+                }
+            }
+            let mut entry = TarEntry {
+                path: PathBuf::new(),
+                entry_type: TarEntryType::from(flag),
+                mode: parse_number(&header[100..108])? as u32,
+                uid: parse_number(&header[108..116])? as u32,
+                gid: parse_number(&header[116..124])? as u32,
+                mtime: parse_number(&header[136..148])?,
+                mtime_nsec: 0,
+                size,
+                link: PathBuf::from(
+                    platform::os_str_from_bytes(trim_nul(&header[157..257])).into_owned(),
+                ),
+                dev_major: parse_number(&header[329..337])?,
+                dev_minor: parse_number(&header[337..345])?,
+                xattrs: Vec::new(),
+            };
+            if let Some(v) = long_name.take() {
+                name = v;
+            }
+            if let Some(v) = long_link.take() {
+                entry.link = PathBuf::from(platform::os_str_from_bytes(&v).into_owned());
+            }
+            for (key, value) in pax.drain(..) {
+                match key.as_str() {
+                    "path" => name = value,
+                    "linkpath" => {
+                        entry.link = PathBuf::from(platform::os_str_from_bytes(&value).into_owned())
+                    }
+                    "size" => entry.size = parse_pax_number(&key, &value)?,
+                    "uid" => entry.uid = parse_pax_number(&key, &value)? as u32,
+                    "gid" => entry.gid = parse_pax_number(&key, &value)? as u32,
+                    "mtime" => {
+                        let (secs, nsecs) = parse_pax_time(&value)?;
+                        entry.mtime = secs;
+                        entry.mtime_nsec = nsecs;
+                    }
+                    _ => {
+                        if let Some(xattr) = key.strip_prefix(PAX_XATTR_PREFIX) {
+                            entry.xattrs.push((OsString::from(xattr), value));
+                        }
+                    }
+                }
+            }
+            // The size from PAX header overrides the one from tar header.
+            self.remain = entry.size;
+            self.padding = (TAR_BLOCK_SIZE - entry.size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+            entry.path = normalize_path(&name)?;
+
+            return Ok(Some(entry));
+        }
+    }
+
+    /// Get position of the reader in the tar stream.
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn skip_data(&mut self) -> Result<()> {
+        let size = self.remain + self.padding;
+        if size > 0 {
+            (self.skip)(&mut self.reader, size).context("failed to skip tar entry data")?;
+            self.position += size;
+            self.remain = 0;
+            self.padding = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> TarReader<R> {
+    /// Create a reader which skips entry data by seeking.
+    fn seekable(reader: R) -> Self {
+        TarReader {
+            skip: skip_by_seek::<R>,
+            ..Self::new(reader)
+        }
+    }
+}
+
+/// Read data of the current entry.
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remain == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let size = std::cmp::min(self.remain, buf.len() as u64) as usize;
+        let cnt = self.reader.read(&mut buf[..size])?;
+        if cnt == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected end of tar stream",
+            ));
+        }
+        self.remain -= cnt as u64;
+        self.position += cnt as u64;
+
+        Ok(cnt)
+    }
+}
+
+fn skip_by_read<R: Read>(reader: &mut R, size: u64) -> io::Result<()> {
+    let cnt = io::copy(&mut reader.take(size), &mut io::sink())?;
+    if cnt != size {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "unexpected end of tar stream",
+        ));
+    }
+    Ok(())
+}
+
+fn skip_by_seek<R: Read + Seek>(reader: &mut R, size: u64) -> io::Result<()> {
+    reader.seek(SeekFrom::Current(size as i64)).map(|_| ())
+}
+
+fn trim_nul(data: &[u8]) -> &[u8] {
+    match data.iter().position(|b| *b == 0) {
+        Some(pos) => &data[..pos],
+        None => data,
+    }
+}
+
+/// Parse a numeric field of tar header, in octal or GNU base-256 encoding.
+fn parse_number(field: &[u8]) -> Result<u64> {
+    if !field.is_empty() && field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for b in field[1..].iter() {
+            ensure!(value >> 56 == 0, "tar header number overflows");
+            value = value << 8 | *b as u64;
+        }
+        return Ok(value);
+    }
+
+    let value = std::str::from_utf8(trim_nul(field))
+        .context("invalid tar header number")?
+        .trim_matches(' ');
+    if value.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(value, 8).with_context(|| format!("invalid tar header number {}", value))
+}
+
+fn verify_checksum(header: &[u8]) -> Result<()> {
+    let expected = parse_number(&header[148..156])?;
+    let sum = header
+        .iter()
+        .enumerate()
+        .map(|(idx, b)| {
+            if (148..156).contains(&idx) {
+                b' ' as u64
+            } else {
+                *b as u64
+            }
+        })
+        .sum::<u64>();
+    ensure!(
+        sum == expected,
+        "invalid tar header checksum, expect {} but got {}",
+        expected,
+        sum
+    );
+
+    Ok(())
+}
+
+/// Parse PAX extended header records in form of "<length> <key>=<value>\n".
+fn parse_pax_records(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let sep = rest
+            .iter()
+            .position(|b| *b == b' ')
+            .ok_or_else(|| anyhow!("invalid pax record"))?;
+        let len = std::str::from_utf8(&rest[..sep])?
+            .parse::<usize>()
+            .context("invalid pax record length")?;
+        ensure!(
+            len > sep + 1 && len <= rest.len() && rest[len - 1] == b'\n',
+            "invalid pax record"
+        );
+        let record = &rest[sep + 1..len - 1];
+        let eq = record
+            .iter()
+            .position(|b| *b == b'=')
+            .ok_or_else(|| anyhow!("invalid pax record"))?;
+        let key = String::from_utf8(record[..eq].to_vec()).context("invalid pax record key")?;
+        records.push((key, record[eq + 1..].to_vec()));
+        rest = &rest[len..];
+    }
+
+    Ok(records)
+}
+
+fn parse_pax_number(key: &str, value: &[u8]) -> Result<u64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("invalid pax record {}", key))
+}
+
+/// Parse PAX timestamp in form of "<seconds>[.<fraction>]".
+fn parse_pax_time(value: &[u8]) -> Result<(u64, u32)> {
+    let value = std::str::from_utf8(value).context("invalid pax record mtime")?;
+    let mut parts = value.splitn(2, '.');
+    // Timestamps before the epoch are clamped to the epoch.
+    let secs = parts.next().unwrap_or_default();
+    if secs.starts_with('-') {
+        return Ok((0, 0));
+    }
+    let secs = secs
+        .parse::<u64>()
+        .with_context(|| format!("invalid pax record mtime {}", value))?;
+    let nsecs = match parts.next() {
+        Some(frac) if !frac.is_empty() => {
+            let frac = format!("{:0<9}", frac.get(..9).unwrap_or(frac));
+            frac.parse::<u32>()
+                .with_context(|| format!("invalid pax record mtime {}", value))?
+        }
+        _ => 0,
+    };
+
+    Ok((secs, nsecs))
+}
+
+/// Normalize path of a tar entry to a relative path, `..` is rejected to avoid escaping from
+/// the target directory.
+fn normalize_path(path: &[u8]) -> Result<PathBuf> {
+    let mut result = PathBuf::new();
+
+    let path = platform::os_str_from_bytes(path);
+    for comp in Path::new(&path).components() {
+        match comp {
+            Component::Normal(name) => result.push(name),
+            Component::RootDir | Component::CurDir => {}
+            _ => bail!("invalid path {:?} in tar stream", path),
+        }
+    }
+
+    Ok(result)
+}
+
+#[derive(Deserialize)]
+struct DockerManifest {
+    #[serde(rename = "Layers")]
+    layers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciImageManifest {
+    layers: Vec<OciDescriptor>,
+}
+
+// Location of a file in the image tarball.
+struct TarballFile {
+    offset: u64,
+    size: u64,
+    // Target of a symlink, relative to the root of the tarball.
+    link: Option<PathBuf>,
+}
+
+/// An uncompressed image tarball, e.g. generated by `docker save` or in OCI image layout.
+struct ImageTarball {
+    file: File,
+    files: HashMap<PathBuf, TarballFile>,
+}
+
+impl ImageTarball {
+    fn open(path: &Path) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("failed to open image tarball {:?}", path))?;
+        let mut magic = [0u8; 2];
+        if file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC {
+            bail!(
+                "compressed image tarball {:?} is not supported, please decompress it first",
+                path
+            );
+        }
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut files = HashMap::new();
+        let mut reader = TarReader::seekable(BufReader::new(file));
+        while let Some(entry) = reader
+            .next_entry()
+            .with_context(|| format!("failed to parse image tarball {:?}", path))?
+        {
+            let link = match entry.entry_type {
+                TarEntryType::Regular => None,
+                TarEntryType::Symlink => Some(Self::resolve_link(&entry.path, &entry.link)),
+                TarEntryType::HardLink => Some(normalize_path(&platform::os_str_to_bytes(
+                    entry.link.as_os_str(),
+                ))?),
+                _ => continue,
+            };
+            let file = TarballFile {
+                offset: reader.position(),
+                size: entry.size,
+                link,
+            };
+            files.insert(entry.path, file);
+        }
+
+        Ok(ImageTarball {
+            file: reader.reader.into_inner(),
+            files,
+        })
+    }
+
+    fn resolve_link(path: &Path, link: &Path) -> PathBuf {
+        let mut result = match path.parent() {
+            Some(parent) if !link.is_absolute() => parent.to_path_buf(),
+            _ => PathBuf::new(),
+        };
+        for comp in link.components() {
+            match comp {
+                Component::Normal(name) => result.push(name),
+                Component::ParentDir => {
+                    result.pop();
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    fn lookup(&self, name: &str) -> Result<&TarballFile> {
+        let mut path = normalize_path(name.as_bytes())?;
+        for _ in 0..MAX_LINK_DEPTH {
+            let file = self
+                .files
+                .get(&path)
+                .ok_or_else(|| anyhow!("file {} not found in image tarball", name))?;
+            match &file.link {
+                None => return Ok(file),
+                Some(link) => path = link.clone(),
+            }
+        }
+        bail!("too many levels of links for {} in image tarball", name)
+    }
+
+    fn open_file(&self, name: &str) -> Result<io::Take<&File>> {
+        let file = self.lookup(name)?;
+        let mut reader = &self.file;
+        reader.seek(SeekFrom::Start(file.offset))?;
+        Ok(reader.take(file.size))
+    }
+
+    fn read_json<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        let size = self.lookup(name)?.size;
+        ensure!(
+            size <= MAX_METADATA_SIZE,
+            "{} in image tarball is too big, {} bytes",
+            name,
+            size
+        );
+        serde_json::from_reader(self.open_file(name)?)
+            .with_context(|| format!("failed to parse {} in image tarball", name))
+    }
+
+    /// Get paths of image layers within the tarball, from the lowest to the uppermost.
+    fn layers(&self) -> Result<Vec<String>> {
+        if self.files.contains_key(Path::new(DOCKER_MANIFEST_FILE)) {
+            let manifests: Vec<DockerManifest> = self.read_json(DOCKER_MANIFEST_FILE)?;
+            ensure!(
+                manifests.len() == 1,
+                "image tarball should contain exactly one image, found {}",
+                manifests.len()
+            );
+            Ok(manifests[0].layers.clone())
+        } else if self.files.contains_key(Path::new(OCI_INDEX_FILE)) {
+            let index: OciIndex = self.read_json(OCI_INDEX_FILE)?;
+            ensure!(
+                index.manifests.len() == 1,
+                "image tarball should contain exactly one image, found {}",
+                index.manifests.len()
+            );
+            let manifest: OciImageManifest =
+                self.read_json(&Self::blob_path(&index.manifests[0])?)?;
+            manifest
+                .layers
+                .iter()
+                .map(|layer| {
+                    ensure!(
+                        !layer.media_type.contains("zstd"),
+                        "unsupported layer media type {}",
+                        layer.media_type
+                    );
+                    Self::blob_path(layer)
+                })
+                .collect()
+        } else {
+            bail!(
+                "neither {} nor {} found in image tarball",
+                DOCKER_MANIFEST_FILE,
+                OCI_INDEX_FILE
+            )
+        }
+    }
+
+    // Get path of a blob in OCI image layout.
+    fn blob_path(desc: &OciDescriptor) -> Result<String> {
+        let mut parts = desc.digest.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(algo), Some(hex)) if !algo.is_empty() && !hex.is_empty() => {
+                Ok(format!("blobs/{}/{}", algo, hex))
+            }
+            _ => bail!("invalid digest {}", desc.digest),
+        }
+    }
+
+    /// Open an uncompressed tar stream of a layer.
+    fn open_layer(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let mut reader = BufReader::new(self.open_file(name)?);
+        if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Ok(Box::new(MultiGzDecoder::new(reader)))
+        } else {
+            Ok(Box::new(reader))
+        }
+    }
+}
+
+/// An image layer unpacked into a directory.
+struct Layer {
+    dir: PathBuf,
+    // Metadata of tar entries, indexed by relative path.
+    entries: HashMap<PathBuf, TarEntry>,
+}
+
+impl Layer {
+    /// Unpack a layer from tar stream into `dir`, hardlinks to files missing from the layer
+    /// are resolved from `lowers`.
+    fn unpack<R: Read>(reader: R, dir: PathBuf, lowers: &[Layer]) -> Result<Self> {
+        let mut reader = TarReader::new(reader);
+        let mut entries = HashMap::new();
+
+        while let Some(mut entry) = reader.next_entry()? {
+            if entry.path.as_os_str().is_empty() {
+                if entry.is_dir() {
+                    entries.insert(entry.path.clone(), entry);
+                }
+                continue;
+            }
+
+            Self::prepare_parent(&dir, &entry.path)?;
+            let path = dir.join(&entry.path);
+            match path.symlink_metadata() {
+                Ok(m) if m.is_dir() && entry.is_dir() => {}
+                Ok(m) if m.is_dir() => fs::remove_dir_all(&path)?,
+                Ok(_) => fs::remove_file(&path)?,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("failed to stat {:?}", path)),
+            }
+
+            match entry.entry_type {
+                TarEntryType::Directory => {
+                    if !path.exists() {
+                        fs::create_dir(&path)?;
+                    }
+                }
+                TarEntryType::Regular => {
+                    let mut file = File::create(&path)?;
+                    io::copy(&mut reader, &mut file)
+                        .with_context(|| format!("failed to unpack {:?}", entry.path))?;
+                }
+                TarEntryType::Symlink => platform::symlink(&entry.link, &path)?,
+                TarEntryType::HardLink => {
+                    let target =
+                        normalize_path(&platform::os_str_to_bytes(entry.link.as_os_str()))?;
+                    let linked = Self::link(&dir, &target, &path, &entries, lowers)
+                        .with_context(|| format!("failed to unpack hardlink {:?}", entry.path))?;
+                    entry = TarEntry {
+                        path: entry.path,
+                        ..linked
+                    };
+                }
+                // Special files are kept as placeholders, with file type recorded in metadata.
+                TarEntryType::CharDevice | TarEntryType::BlockDevice | TarEntryType::Fifo => {
+                    File::create(&path)?;
+                }
+                TarEntryType::Other(flag) => {
+                    warn!(
+                        "skip tar entry {:?} with unsupported type {:?}",
+                        entry.path, flag as char
+                    );
+                    continue;
+                }
+            }
+            entries.insert(entry.path.clone(), entry);
+        }
+
+        Ok(Layer { dir, entries })
+    }
+
+    // Create missing parent directories of `path`, without following symlinks.
+    fn prepare_parent(dir: &Path, path: &Path) -> Result<()> {
+        let mut parent = dir.to_path_buf();
+        if let Some(p) = path.parent() {
+            for comp in p.components() {
+                parent.push(comp);
+                match parent.symlink_metadata() {
+                    Ok(m) if m.is_dir() => {}
+                    Ok(_) => bail!("parent of {:?} is not a directory", path),
+                    Err(e) if e.kind() == ErrorKind::NotFound => fs::create_dir(&parent)?,
+                    Err(e) => return Err(e).with_context(|| format!("failed to stat {:?}", p)),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Create a hardlink at `path` to `target` and return metadata of the target.
+    fn link(
+        dir: &Path,
+        target: &Path,
+        path: &Path,
+        entries: &HashMap<PathBuf, TarEntry>,
+        lowers: &[Layer],
+    ) -> Result<TarEntry> {
+        if let Some(entry) = entries.get(target) {
+            fs::hard_link(dir.join(target), path)?;
+            return Ok(entry.clone());
+        }
+
+        // Files in other layers can't be shared, so make a copy.
+        for lower in lowers.iter().rev() {
+            if let Some(entry) = lower.entries.get(target) {
+                ensure!(
+                    entry.entry_type == TarEntryType::Regular,
+                    "target {:?} is not a regular file",
+                    target
+                );
+                fs::copy(lower.dir.join(target), path)?;
+                return Ok(entry.clone());
+            }
+        }
+
+        bail!("target {:?} not found", target)
+    }
+
+    fn build_node(&self, ctx: &BuildContext, path: PathBuf) -> Result<Node> {
+        let mut node = Node::new(
+            ctx.fs_version,
+            self.dir.clone(),
+            path.clone(),
+            Overlay::UpperAddition,
+            ctx.chunk_size,
+            ctx.explicit_uidgid,
+            &ctx.xattr_filter,
+            &ctx.source_defaults,
+        )
+        .with_context(|| format!("failed to create node {:?}", path))?;
+
+        // Safe to unwrap because all nodes are under the layer directory.
+        let rel_path = path.strip_prefix(&self.dir).unwrap();
+        match self.entries.get(rel_path) {
+            Some(entry) => entry.apply_to(&mut node, ctx),
+            // Directories implied by tar entries are owned by root.
+            None if ctx.explicit_uidgid => {
+                node.inode.set_uid(0);
+                node.inode.set_gid(0);
+            }
+            None => {}
+        }
+
+        Ok(node)
+    }
+
+    /// Build node tree of the layer, with whiteout files kept.
+    fn build_tree(&self, ctx: &BuildContext) -> Result<Tree> {
+        let mut tree = Tree::new(self.build_node(ctx, self.dir.clone())?);
+        tree.children = self.load_children(ctx, &tree.node)?;
+        Ok(tree)
+    }
+
+    fn load_children(&self, ctx: &BuildContext, parent: &Node) -> Result<Vec<Tree>> {
+        let mut result = Vec::new();
+        if !parent.is_dir() {
+            return Ok(result);
+        }
+
+        let mut paths = fs::read_dir(parent.path())
+            .with_context(|| format!("failed to read dir {:?}", parent.path()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        paths.sort();
+        for path in paths {
+            let mut node = self.build_node(ctx, path)?;
+            if !node.apply_special_file_policy(&ctx.special_files, WhiteoutSpec::Oci) {
+                continue;
+            }
+            let mut child = Tree::new(node);
+            child.children = self.load_children(ctx, &child.node)?;
+            result.push(child);
+        }
+
+        Ok(result)
+    }
+}
+
+pub struct OciV1Builder {
+    work_dir: PathBuf,
+}
+
+impl OciV1Builder {
+    /// Create a builder which unpacks image layers under `work_dir`, or the system temporary
+    /// directory if not specified.
+    pub fn new(work_dir: Option<&str>) -> Self {
+        Self {
+            work_dir: work_dir
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir),
+        }
+    }
+
+    fn unpack_layers(&self, ctx: &BuildContext, work_dir: &Path) -> Result<Vec<Layer>> {
+        let tarball = ImageTarball::open(&ctx.source_path)?;
+        let names = tarball.layers()?;
+        ensure!(!names.is_empty(), "no layer found in image tarball");
+
+        let mut layers = Vec::with_capacity(names.len());
+        for (idx, name) in names.iter().enumerate() {
+            let dir = work_dir.join(format!("layer{}", idx));
+            fs::create_dir(&dir).with_context(|| format!("failed to create dir {:?}", dir))?;
+            let reader = tarball.open_layer(name)?;
+            let layer = Layer::unpack(reader, dir, &layers)
+                .with_context(|| format!("failed to unpack layer {}", name))?;
+            layers.push(layer);
+        }
+
+        Ok(layers)
+    }
+
+    /// Merge layers into one tree, from the lowest to the uppermost.
+    fn merge_layers(&self, ctx: &BuildContext, layers: &[Layer]) -> Result<Tree> {
+        let root = &layers[0];
+        let mut tree = Tree::new(root.build_node(ctx, root.dir.clone())?);
+
+        for layer in layers {
+            let upper = layer.build_tree(ctx)?;
+            if layer.entries.contains_key(Path::new("")) {
+                tree.node = upper.node.clone();
+            }
+
+            // Apply whiteouts before other files, so opaque directories won't hide files
+            // from the same layer.
+            let mut whiteouts = Vec::new();
+            let mut others = Vec::new();
+            for child in upper.children.iter() {
+                child.iterate(&mut |node: &Node| {
+                    if node.whiteout_type(WhiteoutSpec::Oci).is_some() {
+                        whiteouts.push(node.clone());
+                    } else {
+                        others.push(node.clone());
+                    }
+                    true
+                })?;
+            }
+            for node in whiteouts.iter().chain(others.iter()) {
+                tree.apply(node, true, WhiteoutSpec::Oci)
+                    .context("failed to apply tree")?;
+            }
+        }
+
+        Ok(tree)
+    }
+}
+
+impl Builder for OciV1Builder {
+    fn build(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput> {
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx()?;
+        ensure!(
+            !bootstrap_ctx.layered,
+            "parent bootstrap is not supported by ociv1 source"
+        );
+
+        // Layers must be kept until data blob has been dumped.
+        let work_dir = TempDir::new_with_prefix(self.work_dir.join("nydus-ociv1-"))
+            .map_err(|e| anyhow!("failed to create work dir in {:?}, {}", self.work_dir, e))?;
+        let layers = timing_tracer!(
+            { self.unpack_layers(ctx, work_dir.as_path()) },
+            "unpack_layers"
+        )?;
+        let mut tree = timing_tracer!({ self.merge_layers(ctx, &layers) }, "merge_layers")?;
+
+        // Convert the hierarchy tree into an array, stored in `bootstrap_ctx.nodes`.
+        let mut bootstrap = Bootstrap::new()?;
+        timing_tracer!(
+            { bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree) },
+            "build_bootstrap"
+        )?;
+        // Nodes have been copied into `bootstrap_ctx.nodes`, release the tree to save memory.
+        drop(tree);
+        ctx.prepare_chunk_spill(bootstrap_ctx.nodes.len())?;
+
+        // Dump blob file
+        let mut blob_ctx = BlobContext::new(
+            ctx.blob_id.clone(),
+            ctx.blob_storage.clone(),
+            ctx.blob_offset,
+        )?;
+        blob_mgr.resolve_chunk_dict(ctx, &bootstrap_ctx.nodes)?;
+        blob_ctx.set_chunk_dict(blob_mgr.get_chunk_dict());
+        blob_ctx.set_chunk_size(ctx.chunk_size);
+        blob_ctx.set_meta_info_enabled(true);
+        blob_mgr.extend_blob_table_from_chunk_dict()?;
+
+        let blob_index = blob_mgr.alloc_index()?;
+        let mut blob = Blob::new();
+        let blob_exists = timing_tracer!(
+            {
+                blob.dump(
+                    ctx,
+                    &mut blob_ctx,
+                    blob_index,
+                    &mut bootstrap_ctx.nodes,
+                    &mut blob_mgr.chunk_dict_cache,
+                )
+            },
+            "dump_blob"
+        )?;
+
+        // Add new blobs to blob table, including those sealed by the blob size limit.
+        for sealed in std::mem::take(&mut blob_ctx.sealed_blobs) {
+            blob_mgr.add(Some(sealed));
+        }
+        blob_mgr.add(if blob_exists { Some(blob_ctx) } else { None });
+
+        // Dump bootstrap file
+        match ctx.fs_version {
+            RafsVersion::V5 => {
+                let blob_table = blob_mgr.to_blob_table_v5(ctx, None)?;
+                bootstrap.dump_rafsv5(ctx, &mut bootstrap_ctx, &blob_table)?
+            }
+            RafsVersion::V6 => {
+                let blob_table = blob_mgr.to_blob_table_v6(ctx, None)?;
+                bootstrap.dump_rafsv6(ctx, &mut bootstrap_ctx, &blob_table)?
+            }
+        }
+
+        bootstrap_mgr.add(bootstrap_ctx);
+        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use storage::RAFS_DEFAULT_CHUNK_SIZE;
+
+    use super::*;
+
+    fn append_entry(tar: &mut Vec<u8>, path: &str, flag: u8, data: &[u8], link: &str) {
+        let mut header = [0u8; TAR_BLOCK_SIZE as usize];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[100..108].copy_from_slice(b"0000640\0");
+        header[108..116].copy_from_slice(b"0001750\0");
+        header[116..124].copy_from_slice(b"0001750\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[136..148].copy_from_slice(b"14220157500\0");
+        header[156] = flag;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        if flag == b'3' {
+            header[329..337].copy_from_slice(b"0000001\0");
+            header[337..345].copy_from_slice(b"0000003\0");
+        }
+        header[148..156].copy_from_slice(b"        ");
+        let sum = header.iter().map(|b| *b as u64).sum::<u64>();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        let padding = (TAR_BLOCK_SIZE as usize - data.len() % TAR_BLOCK_SIZE as usize)
+            % TAR_BLOCK_SIZE as usize;
+        tar.extend_from_slice(&vec![0u8; padding]);
+    }
+
+    fn pax_record(key: &str, value: &str) -> String {
+        // Length of the record includes the length field itself.
+        let base = key.len() + value.len() + 3;
+        let mut len = base + format!("{}", base).len();
+        len = base + format!("{}", len).len();
+        format!("{} {}={}\n", len, key, value)
+    }
+
+    #[test]
+    fn test_parse_tar_header_fields() {
+        assert_eq!(parse_number(b"0000644\0").unwrap(), 0o644);
+        assert_eq!(parse_number(b"   644 \0").unwrap(), 0o644);
+        assert_eq!(parse_number(b"\0\0\0\0").unwrap(), 0);
+        assert_eq!(
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0x01, 0, 0, 0, 0]).unwrap(),
+            0x1_0000_0000
+        );
+        assert!(parse_number(b"0000899\0").is_err());
+
+        assert_eq!(parse_pax_time(b"1650000000").unwrap(), (1650000000, 0));
+        assert_eq!(
+            parse_pax_time(b"1650000000.5").unwrap(),
+            (1650000000, 500000000)
+        );
+        assert_eq!(parse_pax_time(b"-100.5").unwrap(), (0, 0));
+
+        assert_eq!(normalize_path(b"./a/b/").unwrap(), PathBuf::from("a/b"));
+        assert_eq!(normalize_path(b"/a/./b").unwrap(), PathBuf::from("a/b"));
+        assert_eq!(normalize_path(b"./").unwrap(), PathBuf::new());
+        assert!(normalize_path(b"a/../../b").is_err());
+    }
+
+    #[test]
+    fn test_tar_reader() {
+        let long_name = format!("dir/{}", "x".repeat(120));
+        let pax = format!(
+            "{}{}{}",
+            pax_record("path", &long_name),
+            pax_record("SCHILY.xattr.user.key", "value"),
+            pax_record("mtime", "1650000000.25"),
+        );
+        let mut tar = Vec::new();
+        append_entry(&mut tar, "./", b'5', b"", "");
+        append_entry(&mut tar, "./dir/", b'5', b"", "");
+        append_entry(&mut tar, "PaxHeaders/file", b'x', pax.as_bytes(), "");
+        append_entry(&mut tar, "dir/short", b'0', b"data", "");
+        append_entry(&mut tar, "././@LongLink", b'L', b"dir/link\0", "");
+        append_entry(&mut tar, "dir/trunc", b'2', b"", "short");
+        tar.extend_from_slice(&[0u8; 2 * TAR_BLOCK_SIZE as usize]);
+
+        let mut reader = TarReader::new(tar.as_slice());
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::new());
+        assert!(entry.is_dir());
+        assert_eq!(entry.mode, 0o640);
+        assert_eq!(entry.uid, 1000);
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("dir"));
+
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from(&long_name));
+        assert_eq!(entry.entry_type, TarEntryType::Regular);
+        assert_eq!(entry.mtime, 1650000000);
+        assert_eq!(entry.mtime_nsec, 250000000);
+        assert_eq!(
+            entry.xattrs,
+            vec![(OsString::from("user.key"), b"value".to_vec())]
+        );
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"data");
+
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("dir/link"));
+        assert_eq!(entry.entry_type, TarEntryType::Symlink);
+        assert_eq!(entry.link, PathBuf::from("short"));
+        assert!(reader.next_entry().unwrap().is_none());
+
+        // Corrupted header should be rejected.
+        tar[0] = b'y';
+        let mut reader = TarReader::new(tar.as_slice());
+        assert!(reader.next_entry().is_err());
+    }
+
+    #[test]
+    fn test_merge_layers_from_docker_archive() {
+        let mut layer1 = Vec::new();
+        append_entry(&mut layer1, "etc/", b'5', b"", "");
+        append_entry(&mut layer1, "etc/a", b'0', b"aaa", "");
+        append_entry(&mut layer1, "etc/b", b'0', b"bbb", "");
+        append_entry(&mut layer1, "etc/c", b'1', b"", "etc/a");
+        append_entry(&mut layer1, "opt/x", b'0', b"xxx", "");
+
+        let mut layer2 = Vec::new();
+        append_entry(&mut layer2, "etc/.wh.b", b'0', b"", "");
+        append_entry(&mut layer2, "opt/.wh..wh..opq", b'0', b"", "");
+        append_entry(&mut layer2, "opt/y", b'0', b"yyy", "");
+        append_entry(&mut layer2, "dev/null", b'3', b"", "");
+        append_entry(&mut layer2, "dev/pipe", b'6', b"", "");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&layer2).unwrap();
+        let layer2 = encoder.finish().unwrap();
+
+        let manifest = br#"[{"Config":"config.json","RepoTags":null,"Layers":["l1/layer.tar","l2/layer.tar"]}]"#;
+        let mut archive = Vec::new();
+        append_entry(&mut archive, "manifest.json", b'0', manifest, "");
+        append_entry(&mut archive, "l1/layer.tar", b'0', &layer1, "");
+        append_entry(&mut archive, "l2/layer.tar", b'0', &layer2, "");
+
+        let tmp_dir = TempDir::new().unwrap();
+        let archive_path = tmp_dir.as_path().join("image.tar");
+        fs::write(&archive_path, &archive).unwrap();
+        let work_dir = tmp_dir.as_path().join("work");
+        fs::create_dir(&work_dir).unwrap();
+
+        let mut ctx = BuildContext {
+            explicit_uidgid: true,
+            chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            source_path: archive_path,
+            special_files: SpecialFilePolicy {
+                allow_devices: true,
+                allow_sockets: false,
+            },
+            ..Default::default()
+        };
+        let builder = OciV1Builder::new(None);
+        let layers = builder.unpack_layers(&ctx, &work_dir).unwrap();
+        assert_eq!(layers.len(), 2);
+        let tree = builder.merge_layers(&ctx, &layers).unwrap();
+
+        let names = |tree: &Tree| {
+            let mut names = tree
+                .children
+                .iter()
+                .map(|c| c.node.name().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&tree), vec!["dev", "etc", "opt"]);
+        let etc = tree
+            .children
+            .iter()
+            .find(|c| c.node.name() == "etc")
+            .unwrap();
+        assert_eq!(names(etc), vec!["a", "c"]);
+        let opt = tree
+            .children
+            .iter()
+            .find(|c| c.node.name() == "opt")
+            .unwrap();
+        assert_eq!(names(opt), vec!["y"]);
+        assert_eq!(opt.node.inode.uid(), 0);
+
+        let a = &etc
+            .children
+            .iter()
+            .find(|c| c.node.name() == "a")
+            .unwrap()
+            .node;
+        assert_eq!(a.inode.uid(), 1000);
+        assert_eq!(a.inode.mode() & 0o7777, 0o640);
+        assert_eq!(a.inode.mtime(), 0o14220157500);
+        let c = etc.children.iter().find(|c| c.node.name() == "c").unwrap();
+        assert_eq!(c.node.src_ino, a.src_ino);
+
+        let dev = tree
+            .children
+            .iter()
+            .find(|c| c.node.name() == "dev")
+            .unwrap();
+        assert_eq!(names(dev), vec!["null", "pipe"]);
+        let null = &dev.children[0].node;
+        assert!(null.inode.is_chrdev());
+        assert_eq!(null.rdev, makedev(1, 3));
+        let pipe = &dev.children[1].node;
+        assert!(pipe.inode.is_fifo());
+        assert_eq!(pipe.inode.rdev(), 0);
+
+        // Device files are dropped unless allowed, while fifos are always kept.
+        ctx.special_files.allow_devices = false;
+        let tree = builder.merge_layers(&ctx, &layers).unwrap();
+        let dev = tree
+            .children
+            .iter()
+            .find(|c| c.node.name() == "dev")
+            .unwrap();
+        assert_eq!(names(dev), vec!["pipe"]);
+    }
+}
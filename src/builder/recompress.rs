@@ -0,0 +1,280 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recompress data blobs of an existing image with another compression algorithm.
+//!
+//! The source bootstrap is loaded into a tree, chunks of each data blob are decompressed and
+//! compressed again into a new blob in the order of their compressed offsets, then a new bootstrap
+//! is dumped from the tree with compressed offsets and sizes of chunks patched. Uncompressed
+//! layout of blobs, and thus chunk digests and inode digests, are kept as is.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rafs::metadata::{RafsMode, RafsSuper};
+use rafs::RafsIoReader;
+use sha2::Digest;
+use storage::compress;
+use storage::device::BlobInfo;
+
+use super::core::bootstrap::Bootstrap;
+use super::core::context::{
+    BlobContext, BlobManager, BootstrapManager, BuildContext, BuildOutput, RafsVersion,
+};
+use super::core::node::{ChunkWrapper, Node};
+use super::core::prefetch::{Prefetch, PrefetchPolicy};
+use super::core::tree::Tree;
+use super::Builder;
+
+/// Builder to recompress data blobs of the image whose bootstrap is at `source_path` of the build
+/// context, with the compressor of the build context.
+///
+/// Only RAFS v5 images are supported.
+pub struct RecompressBuilder {
+    blob_dir: PathBuf,
+}
+
+impl RecompressBuilder {
+    /// Create a builder to read source data blobs, named by blob id, from `blob_dir`.
+    pub fn new(blob_dir: PathBuf) -> Self {
+        Self { blob_dir }
+    }
+
+    fn load_bootstrap(&self, ctx: &mut BuildContext) -> Result<(RafsSuper, Vec<PathBuf>)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&ctx.source_path)
+            .with_context(|| format!("failed to open bootstrap {:?}", ctx.source_path))?;
+        let mut reader = Box::new(file) as RafsIoReader;
+        let mut rs = RafsSuper {
+            mode: RafsMode::Direct,
+            validate_digest: true,
+            ..Default::default()
+        };
+        rs.load(&mut reader)
+            .context("failed to load superblock from bootstrap")?;
+        if !rs.meta.is_v5() {
+            bail!("only RAFS v5 images can be recompressed");
+        }
+
+        ctx.fs_version = RafsVersion::V5;
+        ctx.digester = rs.meta.get_digester();
+        ctx.explicit_uidgid = rs.meta.explicit_uidgid();
+        ctx.chunk_size = rs.meta.chunk_size;
+
+        // Keep files in the prefetch table of the source bootstrap.
+        let mut prefetch_files = Vec::new();
+        if rs.meta.prefetch_table_entries > 0 {
+            for ino in rs.get_prefetch_inodes(&mut reader)? {
+                prefetch_files.push(rs.path_from_ino(ino)?);
+            }
+        }
+
+        Ok((rs, prefetch_files))
+    }
+
+    // Recompress chunks of the blob in the order of compressed offsets, return the new blob
+    // context and a map from chunk index to the recompressed chunk.
+    fn recompress_blob(
+        &self,
+        ctx: &BuildContext,
+        blob_info: &BlobInfo,
+        mut chunks: Vec<ChunkWrapper>,
+    ) -> Result<(BlobContext, HashMap<u32, ChunkWrapper>)> {
+        if blob_info.is_stargz() {
+            bail!("stargz blob {} can't be recompressed", blob_info.blob_id());
+        }
+        let path = self.blob_dir.join(blob_info.blob_id());
+        let file = File::open(&path).with_context(|| format!("failed to open blob {:?}", path))?;
+
+        let mut blob_ctx = BlobContext::new(String::new(), ctx.blob_storage.clone(), 0)?;
+        blob_ctx.set_chunk_size(blob_info.chunk_size());
+        blob_ctx.chunk_count = blob_info.chunk_count();
+        blob_ctx.decompressed_blob_size = blob_info.uncompressed_size();
+
+        chunks.sort_by_key(|c| c.compressed_offset());
+        let mut recompressed = HashMap::with_capacity(chunks.len());
+        let mut compressed_buf = Vec::new();
+        let mut data_buf = Vec::new();
+        for mut chunk in chunks {
+            compressed_buf.resize(chunk.compressed_size() as usize, 0);
+            file.read_exact_at(&mut compressed_buf, chunk.compressed_offset())
+                .with_context(|| format!("failed to read chunk from blob {:?}", path))?;
+            let data = if chunk.is_compressed() {
+                data_buf.resize(chunk.uncompressed_size() as usize, 0);
+                compress::decompress(&compressed_buf, None, &mut data_buf, blob_info.compressor())
+                    .with_context(|| format!("failed to decompress chunk {}", chunk))?;
+                &data_buf
+            } else {
+                &compressed_buf
+            };
+
+            let (compressed, is_compressed) = compress::compress(data, ctx.compressor)
+                .with_context(|| format!("failed to compress chunk {}", chunk))?;
+            let compressed_size = compressed.len();
+            // Blob readahead range covers the same chunks as the source blob.
+            if chunk.compressed_offset() + chunk.compressed_size() as u64
+                <= blob_info.readahead_size()
+            {
+                blob_ctx.blob_readahead_size = blob_ctx.compress_offset + compressed_size as u64;
+            }
+            chunk.set_compressed_info(
+                blob_ctx.compress_offset,
+                compressed_size as u32,
+                is_compressed,
+            );
+
+            blob_ctx.blob_hash.update(&compressed);
+            if let Some(writer) = &mut blob_ctx.writer {
+                writer
+                    .write_all(&compressed)
+                    .context("failed to write blob")?;
+            }
+            event_tracer!("blob_decompressed_size", +data.len());
+            event_tracer!("blob_compressed_size", +compressed_size);
+            blob_ctx.compress_offset += compressed_size as u64;
+            blob_ctx.compressed_blob_size += compressed_size as u64;
+            recompressed.insert(chunk.index(), chunk);
+        }
+
+        if blob_ctx.compressed_blob_size > 0 {
+            blob_ctx.blob_id = format!("{:x}", blob_ctx.blob_hash.clone().finalize());
+        } else {
+            blob_ctx.blob_id = blob_info.blob_id().to_string();
+        }
+        blob_ctx.flush()?;
+
+        Ok((blob_ctx, recompressed))
+    }
+}
+
+impl Builder for RecompressBuilder {
+    fn build(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput> {
+        let (rs, prefetch_files) = self.load_bootstrap(ctx)?;
+        if !prefetch_files.is_empty() {
+            ctx.prefetch = Prefetch::from_paths(PrefetchPolicy::Fs, &prefetch_files);
+        }
+        let mut tree =
+            Tree::from_bootstrap(&rs, &mut ()).context("failed to build tree from bootstrap")?;
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx()?;
+        let mut bootstrap = Bootstrap::new()?;
+        timing_tracer!(
+            { bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree) },
+            "build_bootstrap"
+        )?;
+
+        // Collect chunks by blob, chunks shared by multiple files are recompressed once.
+        let blob_infos: Vec<Arc<BlobInfo>> = rs.superblock.get_blob_infos();
+        let mut blob_chunks: Vec<HashMap<u32, ChunkWrapper>> =
+            vec![HashMap::new(); blob_infos.len()];
+        for node in bootstrap_ctx.nodes.iter() {
+            for chunk in node.chunks.iter() {
+                let chunks = blob_chunks
+                    .get_mut(chunk.blob_index() as usize)
+                    .ok_or_else(|| anyhow!("invalid blob index of chunk {}", chunk))?;
+                chunks.entry(chunk.index()).or_insert_with(|| chunk.clone());
+            }
+        }
+
+        let mut recompressed = Vec::with_capacity(blob_infos.len());
+        for (blob_info, chunks) in blob_infos.iter().zip(blob_chunks.into_iter()) {
+            let chunks = chunks.into_iter().map(|(_, c)| c).collect();
+            let (blob_ctx, chunks) = timing_tracer!(
+                { self.recompress_blob(ctx, blob_info, chunks) },
+                "recompress_blob"
+            )?;
+            blob_mgr.add(Some(blob_ctx));
+            recompressed.push(chunks);
+        }
+        for node in bootstrap_ctx.nodes.iter_mut() {
+            patch_chunks(node, &recompressed);
+        }
+
+        let blob_table = blob_mgr.to_blob_table_v5(ctx, None)?;
+        bootstrap.dump_rafsv5(ctx, &mut bootstrap_ctx, &blob_table)?;
+        bootstrap_mgr.add(bootstrap_ctx);
+
+        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+    }
+}
+
+fn patch_chunks(node: &mut Node, recompressed: &[HashMap<u32, ChunkWrapper>]) {
+    for chunk in node.chunks.iter_mut() {
+        // Chunks were collected from the same nodes, so they must exist.
+        let new = &recompressed[chunk.blob_index() as usize][&chunk.index()];
+        chunk.set_compressed_info(
+            new.compressed_offset(),
+            new.compressed_size(),
+            new.is_compressed(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::core::context::{ArtifactStorage, SourceType};
+    use crate::builder::core::node::WhiteoutSpec;
+    use crate::builder::ImageBuilder;
+    use nydus_utils::digest;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_recompress_blobs() {
+        let source = TempDir::new().unwrap();
+        std::fs::write(source.as_path().join("foo"), vec![0x5au8; 0x20_0000]).unwrap();
+        std::fs::write(source.as_path().join("bar"), vec![0x5au8; 0x1000]).unwrap();
+        let blob_dir = TempDir::new().unwrap();
+        let bootstrap = blob_dir.as_path().join("bootstrap");
+        let output = ImageBuilder::new(source.as_path())
+            .compressor(compress::Algorithm::Lz4Block)
+            .bootstrap(&bootstrap)
+            .blob_dir(blob_dir.as_path())
+            .build()
+            .unwrap();
+
+        let output_dir = TempDir::new().unwrap();
+        let mut ctx = BuildContext::new(
+            String::new(),
+            false,
+            compress::Algorithm::GZip,
+            digest::Algorithm::Blake3,
+            true,
+            WhiteoutSpec::Oci,
+            SourceType::Directory,
+            bootstrap,
+            Prefetch::default(),
+            Some(ArtifactStorage::FileDir(output_dir.as_path().to_path_buf())),
+        );
+        let new_bootstrap = output_dir.as_path().join("bootstrap");
+        let mut bootstrap_mgr =
+            BootstrapManager::new(ArtifactStorage::SingleFile(new_bootstrap.clone()), None);
+        let mut blob_mgr = BlobManager::new();
+        let new_output = RecompressBuilder::new(blob_dir.as_path().to_path_buf())
+            .build(&mut ctx, &mut bootstrap_mgr, &mut blob_mgr)
+            .unwrap();
+        assert_eq!(new_output.blobs.len(), 1);
+        assert_ne!(
+            new_output.blobs[0].as_ref().unwrap().blob_id,
+            output.blobs[0].as_ref().unwrap().blob_id
+        );
+
+        let mut reader = Box::new(File::open(&new_bootstrap).unwrap()) as RafsIoReader;
+        let mut rs = RafsSuper::default();
+        rs.load(&mut reader).unwrap();
+        assert_eq!(rs.meta.get_compressor(), compress::Algorithm::GZip);
+        let blob_id = rs.superblock.get_blob_infos()[0].blob_id().to_string();
+        assert!(output_dir.as_path().join(blob_id).exists());
+    }
+}
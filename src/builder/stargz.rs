@@ -23,20 +23,21 @@ use rafs::metadata::layout::v5::{RafsV5ChunkInfo, RafsV5Inode, RafsV5InodeFlags}
 use rafs::metadata::layout::RafsXAttrs;
 use rafs::metadata::Inode;
 use storage::device::BlobChunkFlags;
+use storage::RAFS_MAX_CHUNK_SIZE;
 
-use crate::builder::Builder;
-use crate::core::bootstrap::Bootstrap;
-use crate::core::context::{
+use crate::builder::core::bootstrap::Bootstrap;
+use crate::builder::core::context::{
     BlobContext, BlobManager, BootstrapContext, BootstrapManager, BuildContext, BuildOutput,
-    RafsVersion,
+    RafsVersion, SourceType,
 };
-use crate::core::node::{ChunkWrapper, InodeWrapper, Node, Overlay};
-use crate::core::tree::Tree;
+use crate::builder::core::node::{ChunkWrapper, InodeWrapper, Node, Overlay};
+use crate::builder::core::tree::Tree;
+use crate::builder::Builder;
 
 type RcTocEntry = Rc<RefCell<TocEntry>>;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
-struct TocEntry {
+pub(crate) struct TocEntry {
     // Name is the tar entry's name. It is the complete path
     // stored in the tar file, not just the base name.
     pub name: PathBuf,
@@ -137,6 +138,24 @@ struct TocEntry {
     #[serde(default, rename = "chunkSize")]
     pub chunk_size: u64,
 
+    // EndOffset, for zstd:chunked layers, is the offset where the
+    // zstd frame of the chunk ends.
+    //
+    // Fields only used by zstd:chunked layers are not serialized, so
+    // block ids of stargz chunks stay the same.
+    #[serde(default, rename = "endOffset", skip_serializing)]
+    pub end_offset: u64,
+
+    // ChunkDigest, for zstd:chunked layers, is the OCI checksum of
+    // uncompressed data of the chunk.
+    #[serde(default, rename = "chunkDigest", skip_serializing)]
+    pub chunk_digest: String,
+
+    // ChunkType, for zstd:chunked layers, is "zeros" for chunks of
+    // holes which have no data in the layer.
+    #[serde(default, rename = "chunkType", skip_serializing)]
+    pub chunk_type: String,
+
     #[serde(skip)]
     pub children: Vec<RcTocEntry>,
 
@@ -228,14 +247,14 @@ impl TocEntry {
     }
 
     // Convert entry name to rootfs absolute path
-    // For example: `` to `/`, `a/b` to `/a/b`, `a/b/` to `/a/b`
+    // For example: `` to `/`, `a/b` to `/a/b`, `a/b/` to `/a/b`, `./a/b` to `/a/b`
     pub fn path(&self) -> Result<PathBuf> {
         let root_path = PathBuf::from("/");
-        let empty_path = PathBuf::from("");
-        if self.name == empty_path || self.name == root_path {
+        let name = self.name.strip_prefix(".").unwrap_or(&self.name);
+        if name == Path::new("") || name == root_path {
             return Ok(root_path);
         }
-        let path = PathBuf::from("/").join(&self.name);
+        let path = PathBuf::from("/").join(name);
         Ok(path
             .parent()
             .ok_or_else(|| anyhow!("invalid entry path"))?
@@ -246,9 +265,10 @@ impl TocEntry {
     }
 
     // Convert link path of hardlink entry to rootfs absolute path
-    // For example: `a/b` to `/a/b`
+    // For example: `a/b` to `/a/b`, `./a/b` to `/a/b`
     pub fn hardlink_link_path(&self) -> PathBuf {
-        PathBuf::from("/").join(&self.link_name)
+        let link_name = self.link_name.strip_prefix(".").unwrap_or(&self.link_name);
+        PathBuf::from("/").join(link_name)
     }
 
     pub fn symlink_link_path(&self) -> PathBuf {
@@ -256,7 +276,12 @@ impl TocEntry {
     }
 
     pub fn is_supported(&self) -> bool {
-        self.is_dir() || self.is_reg() || self.is_symlink() || self.is_hardlink() || self.is_chunk()
+        self.is_dir()
+            || self.is_reg()
+            || self.is_symlink()
+            || self.is_hardlink()
+            || self.is_chunk()
+            || self.is_special()
     }
 
     // TODO: think about chunk deduplicate
@@ -272,6 +297,45 @@ impl TocEntry {
         ))
     }
 
+    // Get digest of uncompressed data of the chunk, only for zstd:chunked layers.
+    pub fn content_digest(&self) -> Result<RafsDigest> {
+        let digest = if self.chunk_digest.is_empty() {
+            &self.digest
+        } else {
+            &self.chunk_digest
+        };
+        let hex = digest
+            .strip_prefix("sha256:")
+            .ok_or_else(|| anyhow!("unsupported chunk digest {:?} of {:?}", digest, self.name))?;
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("invalid chunk digest {:?} of {:?}", digest, self.name);
+        }
+
+        let mut data = [0u8; 32];
+        for (idx, v) in data.iter_mut().enumerate() {
+            *v = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16)
+                .with_context(|| format!("invalid chunk digest {:?}", digest))?;
+        }
+
+        Ok(RafsDigest { data })
+    }
+
+    // Get size of compressed data of the chunk, only for zstd:chunked layers.
+    pub fn compressed_size(&self) -> Result<u32> {
+        if self.chunk_type == "zeros" {
+            bail!("sparse chunks of {:?} are not supported", self.name);
+        }
+        match self.end_offset.checked_sub(self.offset) {
+            Some(size) if size > 0 && size <= u32::MAX as u64 => Ok(size as u32),
+            _ => bail!(
+                "invalid compressed data range [{}, {}) of {:?}",
+                self.offset,
+                self.end_offset,
+                self.name
+            ),
+        }
+    }
+
     pub fn new_dir(path: PathBuf) -> Self {
         TocEntry {
             name: path,
@@ -284,7 +348,7 @@ impl TocEntry {
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
-struct TocIndex {
+pub(crate) struct TocIndex {
     pub version: u32,
     pub entries: Vec<TocEntry>,
 }
@@ -295,24 +359,37 @@ impl TocIndex {
             .with_context(|| format!("failed to open stargz index file {:?}", path))?;
         let toc_index: TocIndex = serde_json::from_reader(index_file)
             .with_context(|| format!("invalid stargz index file {:?}", path))?;
+        toc_index.validate()?;
+
+        Ok(toc_index)
+    }
+
+    /// Parse the TOC index from JSON data, e.g. the manifest of zstd:chunked layers.
+    pub fn from_slice(buf: &[u8]) -> Result<TocIndex> {
+        let toc_index: TocIndex = serde_json::from_slice(buf).context("invalid TOC index")?;
+        toc_index.validate()?;
+
+        Ok(toc_index)
+    }
 
-        if toc_index.version != 1 {
+    fn validate(&self) -> Result<()> {
+        if self.version != 1 {
             return Err(Error::msg(format!(
                 "unsupported index version {}",
-                toc_index.version
+                self.version
             )));
         }
 
-        Ok(toc_index)
+        Ok(())
     }
 }
 
-struct StargzIndexTreeBuilder {
+pub(crate) struct StargzIndexTreeBuilder {
     path_inode_map: HashMap<PathBuf, Inode>,
 }
 
 impl StargzIndexTreeBuilder {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             path_inode_map: HashMap::new(),
         }
@@ -336,6 +413,16 @@ impl StargzIndexTreeBuilder {
     fn build(&mut self, ctx: &mut BuildContext) -> Result<Tree> {
         // Parse stargz TOC index from a file
         let toc_index = TocIndex::load(&ctx.source_path)?;
+        self.build_from_index(ctx, &toc_index)
+    }
+
+    /// Build the tree from TOC entries, chunks of zstd:chunked layers are identified by their
+    /// data digests and have known compressed sizes.
+    pub fn build_from_index(
+        &mut self,
+        ctx: &mut BuildContext,
+        toc_index: &TocIndex,
+    ) -> Result<Tree> {
         if toc_index.entries.is_empty() {
             return Err(Error::msg("the stargz index has no toc entry"));
         }
@@ -366,7 +453,19 @@ impl StargzIndexTreeBuilder {
             }
 
             if (entry.is_reg() || entry.is_chunk()) && decompress_size != 0 {
-                let block_id = entry.block_id(&ctx.blob_id)?;
+                let (block_id, compress_size) = if ctx.source_type == SourceType::ZstdChunked {
+                    if decompress_size > RAFS_MAX_CHUNK_SIZE {
+                        bail!(
+                            "chunk of {:?} is larger than {} bytes",
+                            entry.name,
+                            RAFS_MAX_CHUNK_SIZE
+                        );
+                    }
+                    (entry.content_digest()?, entry.compressed_size()?)
+                } else {
+                    // No available data on entry
+                    (entry.block_id(&ctx.blob_id)?, 0)
+                };
                 let chunk = match ctx.fs_version {
                     RafsVersion::V5 => {
                         ChunkWrapper::V5(RafsV5ChunkInfo {
@@ -374,8 +473,7 @@ impl StargzIndexTreeBuilder {
                             // Will be set later
                             blob_index: 0,
                             flags: BlobChunkFlags::COMPRESSED,
-                            // No available data on entry
-                            compress_size: 0,
+                            compress_size,
                             uncompress_size: decompress_size as u32,
                             compress_offset: entry.offset as u64,
                             // No available data on entry
@@ -409,6 +507,10 @@ impl StargzIndexTreeBuilder {
             self.make_lost_dirs(&entry, &mut lost_dirs)?;
             for dir in &lost_dirs {
                 let node = self.parse_node(dir, ctx.explicit_uidgid, ctx.fs_version)?;
+                // Layers may have no entry for the root directory.
+                if tree.is_none() && dir.path()? == PathBuf::from("/") {
+                    tree = Some(Tree::new(node.clone()));
+                }
                 nodes.push(node);
             }
 
@@ -416,7 +518,10 @@ impl StargzIndexTreeBuilder {
                 hardlink_map.insert(entry.path()?, entry.hardlink_link_path());
             }
 
-            let node = self.parse_node(entry, ctx.explicit_uidgid, ctx.fs_version)?;
+            let mut node = self.parse_node(entry, ctx.explicit_uidgid, ctx.fs_version)?;
+            if !node.apply_special_file_policy(&ctx.special_files, ctx.whiteout_spec) {
+                continue;
+            }
             if entry.path()? == PathBuf::from("/") {
                 tree = Some(Tree::new(node.clone()));
             }
@@ -519,7 +624,7 @@ impl StargzIndexTreeBuilder {
                     // TODO: add mtime from entry.ModTime()
                     i_mtime: 0,
                     i_mtime_nsec: 0,
-                    i_reserved: [0; 8],
+                    i_btime: 0,
                 })
             }
             RafsVersion::V6 => todo!(),
@@ -543,6 +648,7 @@ impl StargzIndexTreeBuilder {
             target_vec,
             inode,
             chunks,
+            spilled_chunks: None,
             symlink,
             xattrs,
             ctime: 0,
@@ -551,11 +657,13 @@ impl StargzIndexTreeBuilder {
             v6_datalayout: 0,
             v6_compact_inode: false,
             v6_force_extended_inode: false,
+            v6_shared_xattrs: None,
         })
     }
 }
 
-pub(crate) struct StargzBuilder {}
+#[derive(Default)]
+pub struct StargzBuilder {}
 
 impl StargzBuilder {
     pub fn new() -> Self {
@@ -571,7 +679,11 @@ impl StargzBuilder {
         let mut decompressed_blob_size = 0u64;
         let mut compressed_blob_size = 0u64;
         let blob_index = blob_mgr.alloc_index()?;
-        let mut blob_ctx = BlobContext::new(ctx.blob_id.clone(), ctx.blob_storage.clone())?;
+        let mut blob_ctx = BlobContext::new(
+            ctx.blob_id.clone(),
+            ctx.blob_storage.clone(),
+            ctx.blob_offset,
+        )?;
         blob_ctx.set_chunk_dict(blob_mgr.get_chunk_dict());
         blob_ctx.set_chunk_size(ctx.chunk_size);
 
@@ -0,0 +1,556 @@
+// Copyright 2020 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Trace image building procedure
+//!
+//! Tracer classes can be disabled at runtime, and the number of records kept by each tracer class
+//! is capped to bound memory usage of long builds. Records beyond the cap are dropped and counted
+//! in `dropped_records` of the tracer class.
+
+use std::any::Any;
+use std::cmp::{Eq, PartialEq};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use serde_json::{error::Error, value::Value};
+
+// Default maximum number of records kept by each tracer class.
+const DEFAULT_MAX_RECORDS: usize = 4096;
+const DROPPED_RECORDS_KEY: &str = "dropped_records";
+
+// Maximum number of records kept by each tracer class, 0 means unlimited.
+static MAX_RECORDS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_RECORDS);
+// Only one of every `SAMPLE_RATE` invocations of a timing tracing point is measured.
+static SAMPLE_RATE: AtomicU32 = AtomicU32::new(1);
+
+fn is_full(len: usize) -> bool {
+    let max = MAX_RECORDS.load(Ordering::Relaxed);
+    max != 0 && len >= max
+}
+
+// Add number of dropped records to the serialized records of a tracer class.
+fn with_dropped(mut value: Value, dropped: &AtomicU64) -> Value {
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        if let Some(map) = value.as_object_mut() {
+            map.insert(DROPPED_RECORDS_KEY.to_string(), Value::from(dropped));
+        }
+    }
+    value
+}
+
+impl Display for TraceClass {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            TraceClass::Timing => write!(f, "consumed_time"),
+            TraceClass::Event => write!(f, "registered_events"),
+        }
+    }
+}
+
+macro_rules! enum_str {
+    ($m:meta
+    pub enum $name:ident {
+        $($variant:ident = $val:expr),*,
+    }) => {
+        #[$m]
+        pub enum $name {
+            $($variant = $val),*
+        }
+
+        impl $name {
+            fn name(&self) -> String {
+                match self {
+                    $($name::$variant => format!("{}", $name::$variant)),*
+                }
+            }
+        }
+    };
+}
+
+enum_str! {
+derive(Clone, Copy, Debug, Hash, Eq, PartialEq)
+pub enum TraceClass {
+    Timing = 1,
+    Event = 2,
+}
+}
+
+impl FromStr for TraceClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "timing" => Ok(TraceClass::Timing),
+            "event" => Ok(TraceClass::Event),
+            _ => Err(anyhow!("invalid tracer class {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Serde(Error),
+    Io(std::io::Error),
+}
+
+type Result<T> = std::result::Result<T, TraceError>;
+
+/// Used to measure time consuming and gather all tracing points when building image.
+#[derive(Serialize, Default)]
+pub struct TimingTracerClass {
+    // Generally speaking, we won't have many timing tracers act from multiple points.
+    // So `Mutex` should fill our requirements.
+    #[serde(flatten)]
+    records: Mutex<HashMap<String, f32>>,
+    // Number of invocations of each tracing point, for sampling.
+    #[serde(skip)]
+    invocations: Mutex<HashMap<String, u64>>,
+    #[serde(skip)]
+    dropped: AtomicU64,
+}
+
+impl TimingTracerClass {
+    // Check whether the current invocation of tracing point `point` should be measured.
+    fn sample(&self, point: &str) -> bool {
+        let rate = SAMPLE_RATE.load(Ordering::Relaxed) as u64;
+        if rate <= 1 {
+            return true;
+        }
+        let mut invocations = self.invocations.lock().unwrap();
+        if !invocations.contains_key(point) && is_full(invocations.len()) {
+            return false;
+        }
+        let count = invocations.entry(point.to_string()).or_default();
+        *count += 1;
+        (*count - 1) % rate == 0
+    }
+
+    fn record(&self, point: &str, secs: f32) {
+        // Not expect poisoned lock.
+        let mut records = self.records.lock().unwrap();
+        if let Some(v) = records.get_mut(point) {
+            *v = secs;
+        } else if is_full(records.len()) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            records.insert(point.to_string(), secs);
+        }
+    }
+}
+
+pub trait TracerClass: Send + Sync + 'static {
+    fn release(&self) -> Result<Value>;
+    /// Remove all records of the tracer class.
+    fn trim(&self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl TracerClass for TimingTracerClass {
+    fn release(&self) -> Result<Value> {
+        let value = serde_json::to_value(self).map_err(TraceError::Serde)?;
+        Ok(with_dropped(value, &self.dropped))
+    }
+    fn trim(&self) {
+        self.records.lock().unwrap().clear();
+        self.invocations.lock().unwrap().clear();
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub fn trace_timing<F: FnOnce() -> T, T>(
+    point: &str,
+    tracer: Option<&TimingTracerClass>,
+    f: F,
+) -> T {
+    let tracer = tracer.filter(|t| t.sample(point));
+    let begin = SystemTime::now();
+    let r = f();
+    let elapsed = SystemTime::now().duration_since(begin).unwrap();
+
+    if let Some(t) = tracer {
+        t.record(point, elapsed.as_secs_f32());
+    }
+
+    r
+}
+
+/// The root tracer manages all kinds of tracers registered to it.
+/// The statistics/events/records can be printed out or persisted from the root
+/// tracer. When building procedure is finished, root tracer can dump all tracing
+/// points to specified output file.
+pub struct BuildRootTracer {
+    tracers: RwLock<HashMap<TraceClass, Arc<dyn TracerClass>>>,
+    disabled: RwLock<HashSet<TraceClass>>,
+}
+
+impl BuildRootTracer {
+    pub fn register(&self, class: TraceClass, tracer: Arc<dyn TracerClass>) {
+        let mut guard = self.tracers.write().unwrap();
+        // In case a certain class is registered multiple times, e.g. from several
+        // concurrently running test cases.
+        if guard.get(&class).is_none() {
+            guard.insert(class, tracer);
+        }
+    }
+
+    /// Get the tracer of `class`, `None` if it's not registered or has been disabled.
+    pub fn tracer(&self, class: TraceClass) -> Option<Arc<dyn TracerClass>> {
+        if self.disabled.read().unwrap().contains(&class) {
+            return None;
+        }
+        let g = self.tracers.read().unwrap();
+        (&g).get(&class).cloned()
+    }
+
+    /// Enable or disable a tracer class at runtime.
+    pub fn set_enabled(&self, class: TraceClass, enabled: bool) {
+        let mut disabled = self.disabled.write().unwrap();
+        if enabled {
+            disabled.remove(&class);
+        } else {
+            disabled.insert(class);
+        }
+    }
+
+    /// Set maximum number of records kept by each tracer class, 0 means unlimited.
+    pub fn set_max_records(&self, max_records: usize) {
+        MAX_RECORDS.store(max_records, Ordering::Relaxed);
+    }
+
+    /// Measure only one of every `rate` invocations of each timing tracing point.
+    pub fn set_sample_rate(&self, rate: u32) {
+        SAMPLE_RATE.store(std::cmp::max(rate, 1), Ordering::Relaxed);
+    }
+
+    /// Remove all records of tracer `class`.
+    pub fn trim(&self, class: TraceClass) {
+        if let Some(t) = self.tracers.read().unwrap().get(&class) {
+            t.trim();
+        }
+    }
+
+    pub fn dump_summary_map(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let mut map = serde_json::Map::new();
+        for c in self.tracers.write().unwrap().iter() {
+            map.insert(c.0.name(), c.1.release()?);
+        }
+        Ok(map)
+    }
+
+    /// Write a snapshot of the traces collected so far to file `path`.
+    ///
+    /// The snapshot is written to a temporary file and then renamed, so readers always see a
+    /// complete snapshot.
+    pub fn dump_partial(&self, path: &Path) -> Result<()> {
+        let map = self.dump_summary_map()?;
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let file = OpenOptions::new()
+            .truncate(true)
+            .create(true)
+            .write(true)
+            .open(&tmp_path)
+            .map_err(TraceError::Io)?;
+        serde_json::to_writer(file, &map).map_err(TraceError::Serde)?;
+        fs::rename(&tmp_path, path).map_err(TraceError::Io)
+    }
+}
+
+/// Dump traces into a file periodically, to watch progress of long builds.
+///
+/// The background thread exits when the object is dropped, after writing the last snapshot.
+pub struct PeriodicTraceDumper {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PeriodicTraceDumper {
+    pub fn start(path: PathBuf, interval: Duration) -> std::io::Result<Self> {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let state = stop.clone();
+        let handle = thread::Builder::new()
+            .name("trace-dumper".to_string())
+            .spawn(move || {
+                let (lock, cvar) = &*state;
+                let mut stopped = lock.lock().unwrap();
+                loop {
+                    stopped = cvar.wait_timeout(stopped, interval).unwrap().0;
+                    if let Err(e) = root_tracer!().dump_partial(&path) {
+                        warn!("failed to dump traces to {:?}, {:?}", path, e);
+                    }
+                    if *stopped {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(PeriodicTraceDumper {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for PeriodicTraceDumper {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+#[allow(dead_code)]
+pub enum TraceEvent {
+    Counter(AtomicU64),
+    Fixed(u64),
+    Desc(String),
+}
+
+#[derive(Serialize, Default)]
+pub struct EventTracerClass {
+    #[serde(flatten)]
+    pub events: RwLock<HashMap<String, TraceEvent>>,
+    #[serde(skip)]
+    dropped: AtomicU64,
+}
+
+impl EventTracerClass {
+    /// Add `value` to counter `event`.
+    pub fn add_counter(&self, event: &str, value: u64) {
+        if let Some(TraceEvent::Counter(ref e)) = self.events.read().unwrap().get(event) {
+            e.fetch_add(value, Ordering::Relaxed);
+            return;
+        }
+
+        // Double check to close the race that another thread has already inserted.
+        let mut guard = self.events.write().unwrap();
+        if let Some(TraceEvent::Counter(ref e)) = guard.get(event) {
+            e.fetch_add(value, Ordering::Relaxed);
+        } else {
+            self.insert(
+                &mut guard,
+                event,
+                TraceEvent::Counter(AtomicU64::new(value)),
+            );
+        }
+    }
+
+    /// Set event `event` to `value`.
+    pub fn set(&self, event: &str, value: TraceEvent) {
+        let mut guard = self.events.write().unwrap();
+        self.insert(&mut guard, event, value);
+    }
+
+    fn insert(&self, events: &mut HashMap<String, TraceEvent>, event: &str, value: TraceEvent) {
+        if !events.contains_key(event) && is_full(events.len()) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            events.insert(event.to_string(), value);
+        }
+    }
+}
+
+impl TracerClass for EventTracerClass {
+    fn release(&self) -> Result<Value> {
+        let value = serde_json::to_value(self).map_err(TraceError::Serde)?;
+        Ok(with_dropped(value, &self.dropped))
+    }
+    fn trim(&self) {
+        self.events.write().unwrap().clear();
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+lazy_static! {
+    pub static ref BUILDING_RECORDER: BuildRootTracer = BuildRootTracer {
+        tracers: RwLock::new(HashMap::default()),
+        disabled: RwLock::new(HashSet::default()),
+    };
+}
+
+#[macro_export]
+macro_rules! root_tracer {
+    () => {
+        &$crate::builder::trace::BUILDING_RECORDER as &$crate::builder::trace::BuildRootTracer
+    };
+}
+
+#[macro_export]
+macro_rules! timing_tracer {
+    () => {
+        root_tracer!()
+            .tracer($crate::builder::trace::TraceClass::Timing)
+            .as_ref()
+            .map(|t| {
+                t.as_any()
+                    .downcast_ref::<$crate::builder::trace::TimingTracerClass>()
+                    .unwrap()
+            })
+    };
+    ($f:block, $key:expr) => {
+        $crate::builder::trace::trace_timing($key, timing_tracer!(), || $f)
+    };
+    ($f:block, $key:expr, $t:ty) => {
+        $crate::builder::trace::trace_timing::<_, $t>($key, timing_tracer!(), || $f)
+    };
+}
+
+#[macro_export]
+macro_rules! register_tracer {
+    ($class:expr, $r:ty) => {
+        root_tracer!().register($class, std::sync::Arc::new(<$r>::default()));
+    };
+}
+
+#[macro_export]
+macro_rules! event_tracer {
+    () => {
+        root_tracer!()
+            .tracer($crate::builder::trace::TraceClass::Event)
+            .as_ref()
+            .map(|t| {
+                t.as_any()
+                    .downcast_ref::<$crate::builder::trace::EventTracerClass>()
+                    .unwrap()
+            })
+    };
+    ($event:expr, $desc:expr) => {
+        if let Some(t) = event_tracer!() {
+            t.set(
+                $event,
+                $crate::builder::trace::TraceEvent::Fixed($desc as u64),
+            );
+        }
+    };
+    ($event:expr, +$value:expr) => {
+        if let Some(t) = event_tracer!() {
+            // Cast integer to u64 should be reliable for most cases.
+            t.add_counter($event, $value as u64);
+        }
+    };
+    ($event:expr, $format:expr, $value:expr) => {
+        if let Some(t) = event_tracer!() {
+            t.set(
+                $event,
+                $crate::builder::trace::TraceEvent::Desc(format!($format, $value)),
+            );
+        }
+    };
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_event_trace() {
+        register_tracer!(TraceClass::Event, EventTracerClass);
+
+        let t1 = thread::Builder::new()
+            .spawn(move || {
+                for _i in 0..100 {
+                    event_tracer!("event_1", +2);
+                    event_tracer!("event_2", +3);
+                }
+            })
+            .unwrap();
+
+        let t2 = thread::Builder::new()
+            .spawn(move || {
+                for _i in 0..100 {
+                    event_tracer!("event_1", +2);
+                    event_tracer!("event_2", +3);
+                }
+            })
+            .unwrap();
+
+        let t3 = thread::Builder::new()
+            .spawn(move || {
+                for _i in 0..100 {
+                    event_tracer!("event_1", +2);
+                    event_tracer!("event_2", +3);
+                }
+            })
+            .unwrap();
+
+        t1.join().unwrap();
+        t2.join().unwrap();
+        t3.join().unwrap();
+
+        let map = root_tracer!().dump_summary_map().unwrap();
+        assert_eq!(
+            map["registered_events"]["event_1"].as_u64(),
+            serde::export::Some(600)
+        );
+        assert_eq!(
+            map["registered_events"]["event_2"].as_u64(),
+            serde::export::Some(900)
+        );
+    }
+
+    #[test]
+    fn test_tracer_max_records() {
+        let tracer = EventTracerClass::default();
+        for i in 0..DEFAULT_MAX_RECORDS + 10 {
+            tracer.add_counter(&format!("event_{}", i), 1);
+        }
+        // Existing records are still updated when the tracer is full.
+        tracer.add_counter("event_0", 1);
+
+        let value = tracer.release().unwrap();
+        assert_eq!(value["event_0"].as_u64(), Some(2));
+        assert!(value
+            .get(&format!("event_{}", DEFAULT_MAX_RECORDS))
+            .is_none());
+        assert_eq!(value[DROPPED_RECORDS_KEY].as_u64(), Some(10));
+
+        tracer.trim();
+        let value = tracer.release().unwrap();
+        assert!(value.as_object().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_trace_class_from_str() {
+        assert_eq!("timing".parse::<TraceClass>().unwrap(), TraceClass::Timing);
+        assert_eq!("event".parse::<TraceClass>().unwrap(), TraceClass::Event);
+        assert!("all".parse::<TraceClass>().is_err());
+    }
+
+    #[test]
+    fn test_dump_partial() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("trace.json");
+        register_tracer!(TraceClass::Timing, TimingTracerClass);
+        timing_tracer!(
+            { thread::sleep(Duration::from_millis(1)) },
+            "test_dump_partial"
+        );
+
+        root_tracer!().dump_partial(&path).unwrap();
+        let map: serde_json::Map<String, Value> =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert!(map.contains_key("consumed_time"));
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Build a nydus image from an OCI `zstd:chunked` layer.
+//!
+//! A `zstd:chunked` layer is a zstd compressed tarball, where each file (or each chunk of large
+//! files) is compressed into separate zstd frames. A manifest describing the files and their
+//! frames is appended to the layer in a zstd skippable frame, and located by a footer frame at
+//! the end of the layer. The manifest uses the same format as the stargz TOC index, so RAFS
+//! metadata is generated by the stargz index builder, with chunks referencing frames of the
+//! original layer blob by their offsets, compressed sizes and data digests.
+
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+use storage::compress;
+
+use crate::builder::core::bootstrap::Bootstrap;
+use crate::builder::core::context::{
+    BlobContext, BlobManager, BootstrapContext, BootstrapManager, BuildContext, BuildOutput,
+    RafsVersion,
+};
+use crate::builder::core::tree::Tree;
+use crate::builder::stargz::{StargzIndexTreeBuilder, TocIndex};
+use crate::builder::Builder;
+
+const ZSTD_SKIPPABLE_FRAME_MAGIC: u32 = 0x184D_2A50;
+const ZSTD_SKIPPABLE_FRAME_MAGIC_MASK: u32 = 0xffff_fff0;
+const ZSTD_SKIPPABLE_FRAME_HEADER_SIZE: u64 = 8;
+const FOOTER_MAGIC: &[u8; 8] = b"GNUlInUx";
+// Footer data sizes, with and without the tar-split fields.
+const FOOTER_DATA_SIZES: [u64; 2] = [64, 40];
+// Manifest in the stargz TOC JSON format.
+const MANIFEST_TYPE_CRFS: u64 = 1;
+// Upper limit of the manifest to load into memory.
+const MAX_MANIFEST_SIZE: u64 = 0x1000_0000;
+
+/// Footer of `zstd:chunked` layers, to locate the manifest.
+#[derive(Debug, PartialEq)]
+struct ZstdChunkedFooter {
+    // Offset of the compressed manifest, after the skippable frame header.
+    manifest_offset: u64,
+    manifest_compressed_size: u64,
+    manifest_uncompressed_size: u64,
+}
+
+impl ZstdChunkedFooter {
+    fn load(file: &File, file_size: u64) -> Result<Self> {
+        for data_size in FOOTER_DATA_SIZES.iter() {
+            let frame_size = ZSTD_SKIPPABLE_FRAME_HEADER_SIZE + data_size;
+            if file_size < frame_size {
+                continue;
+            }
+            let mut buf = vec![0u8; frame_size as usize];
+            file.read_exact_at(&mut buf, file_size - frame_size)?;
+            if let Some(footer) = Self::parse(&buf) {
+                return Ok(footer);
+            }
+        }
+
+        bail!("no zstd:chunked footer found")
+    }
+
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let read_u32 = |pos: usize| {
+            let mut v = [0u8; 4];
+            v.copy_from_slice(&buf[pos..pos + 4]);
+            u32::from_le_bytes(v)
+        };
+        let read_u64 = |pos: usize| {
+            let mut v = [0u8; 8];
+            v.copy_from_slice(&buf[pos..pos + 8]);
+            u64::from_le_bytes(v)
+        };
+
+        let header_size = ZSTD_SKIPPABLE_FRAME_HEADER_SIZE as usize;
+        if buf.len() < header_size + 40
+            || read_u32(0) & ZSTD_SKIPPABLE_FRAME_MAGIC_MASK != ZSTD_SKIPPABLE_FRAME_MAGIC
+            || read_u32(4) as usize != buf.len() - header_size
+            || &buf[buf.len() - FOOTER_MAGIC.len()..] != FOOTER_MAGIC
+            || read_u64(header_size + 24) != MANIFEST_TYPE_CRFS
+        {
+            return None;
+        }
+
+        Some(Self {
+            manifest_offset: read_u64(header_size),
+            manifest_compressed_size: read_u64(header_size + 8),
+            manifest_uncompressed_size: read_u64(header_size + 16),
+        })
+    }
+}
+
+/// Load the TOC index from the manifest of a `zstd:chunked` layer.
+fn load_manifest(path: &Path) -> Result<TocIndex> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open zstd:chunked layer {:?}", path))?;
+    let file_size = file.metadata()?.len();
+    let footer = ZstdChunkedFooter::load(&file, file_size)
+        .with_context(|| format!("invalid zstd:chunked layer {:?}", path))?;
+
+    if footer.manifest_compressed_size > MAX_MANIFEST_SIZE
+        || footer.manifest_uncompressed_size > MAX_MANIFEST_SIZE
+        || footer
+            .manifest_offset
+            .checked_add(footer.manifest_compressed_size)
+            .map(|end| end > file_size)
+            .unwrap_or(true)
+    {
+        bail!("invalid manifest range in zstd:chunked layer {:?}", path);
+    }
+
+    let mut buf = vec![0u8; footer.manifest_compressed_size as usize];
+    file.read_exact_at(&mut buf, footer.manifest_offset)?;
+    let mut manifest = vec![0u8; footer.manifest_uncompressed_size as usize];
+    compress::decompress(&buf, None, &mut manifest, compress::Algorithm::Zstd)
+        .with_context(|| format!("failed to decompress manifest of {:?}", path))?;
+
+    TocIndex::from_slice(&manifest)
+        .with_context(|| format!("invalid manifest of zstd:chunked layer {:?}", path))
+}
+
+#[derive(Default)]
+pub struct ZstdChunkedBuilder {}
+
+impl ZstdChunkedBuilder {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn generate_nodes(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_ctx: &mut BootstrapContext,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<()> {
+        let mut decompressed_blob_size = 0u64;
+        let blob_index = blob_mgr.alloc_index()?;
+        let mut blob_ctx = BlobContext::new(
+            ctx.blob_id.clone(),
+            ctx.blob_storage.clone(),
+            ctx.blob_offset,
+        )?;
+        blob_ctx.set_chunk_dict(blob_mgr.get_chunk_dict());
+        blob_ctx.set_chunk_size(ctx.chunk_size);
+
+        // Set blob index, uncompressed offset and inode digest for upper nodes
+        for node in &mut bootstrap_ctx.nodes {
+            if node.overlay.is_lower_layer() {
+                continue;
+            }
+
+            let mut inode_hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+            let chunk_count = node.chunks.len();
+            // Files may be split into chunks of any size, which can't be located by the file
+            // offset and the chunk size of the filesystem.
+            let mut uniform = true;
+
+            for (idx, chunk) in node.chunks.iter_mut().enumerate() {
+                let chunk_index = blob_ctx.alloc_index()?;
+                chunk.set_index(chunk_index);
+                chunk.set_blob_index(blob_index);
+                chunk.set_uncompressed_offset(decompressed_blob_size);
+                decompressed_blob_size += chunk.uncompressed_size() as u64;
+                inode_hasher.digest_update(chunk.id().as_ref());
+
+                if chunk.file_offset() != idx as u64 * ctx.chunk_size as u64
+                    || (idx + 1 < chunk_count && chunk.uncompressed_size() != ctx.chunk_size)
+                {
+                    uniform = false;
+                }
+            }
+            if !uniform {
+                node.inode.set_has_hole(true);
+            }
+
+            let digest = if node.is_symlink() {
+                RafsDigest::from_buf(
+                    node.symlink.as_ref().unwrap().as_bytes(),
+                    digest::Algorithm::Sha256,
+                )
+            } else {
+                inode_hasher.digest_finalize()
+            };
+            node.inode.set_digest(digest);
+        }
+
+        blob_ctx.decompressed_blob_size = decompressed_blob_size;
+        blob_ctx.compressed_blob_size = fs::metadata(&ctx.source_path)
+            .with_context(|| format!("failed to stat {:?}", ctx.source_path))?
+            .len();
+        blob_mgr.add(if blob_ctx.decompressed_blob_size > 0 {
+            Some(blob_ctx)
+        } else {
+            None
+        });
+
+        Ok(())
+    }
+
+    fn build_tree_from_manifest(&mut self, ctx: &mut BuildContext) -> Result<Tree> {
+        let toc_index = load_manifest(&ctx.source_path)?;
+        let mut tree_builder = StargzIndexTreeBuilder::new();
+        tree_builder
+            .build_from_index(ctx, &toc_index)
+            .context("failed to build tree from zstd:chunked manifest")
+    }
+}
+
+impl Builder for ZstdChunkedBuilder {
+    fn build(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput> {
+        if ctx.fs_version != RafsVersion::V5 {
+            bail!("zstd:chunked layers only support RAFS v5");
+        }
+
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx()?;
+        // Build tree from source
+        let mut tree = self.build_tree_from_manifest(ctx)?;
+        let mut bootstrap = Bootstrap::new()?;
+        if bootstrap_mgr.f_parent_bootstrap.is_some() {
+            // Merge with lower layer if there's one.
+            bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree)?;
+            tree = bootstrap.apply(ctx, &mut bootstrap_ctx, bootstrap_mgr, blob_mgr, None)?;
+        }
+        timing_tracer!(
+            { bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree) },
+            "build_bootstrap"
+        )?;
+
+        // Generate node chunks and digest
+        self.generate_nodes(ctx, &mut bootstrap_ctx, blob_mgr)?;
+
+        // Dump bootstrap file
+        let blob_table = blob_mgr.to_blob_table_v5(ctx, None)?;
+        bootstrap.dump_rafsv5(ctx, &mut bootstrap_ctx, &blob_table)?;
+
+        bootstrap_mgr.add(bootstrap_ctx);
+        BuildOutput::new(&blob_mgr, &bootstrap_mgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+
+    fn skippable_frame(data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&ZSTD_SKIPPABLE_FRAME_MAGIC.to_le_bytes());
+        frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn make_layer(footer_size: u64) -> Vec<u8> {
+        let content = vec![b'a'; 0x1000];
+        let (frame, compressed) = compress::compress(&content, compress::Algorithm::Zstd).unwrap();
+        assert!(compressed);
+        let manifest = format!(
+            r#"{{"version":1,"entries":[
+                {{"type":"dir","name":"./","mode":493}},
+                {{"type":"reg","name":"./foo","mode":420,"size":4096,"offset":0,
+                  "endOffset":{},"digest":"sha256:{}"}},
+                {{"type":"symlink","name":"./bar","linkName":"foo"}}]}}"#,
+            frame.len(),
+            RafsDigest::from_buf(&content, digest::Algorithm::Sha256)
+        );
+        let (compressed_manifest, _) =
+            compress::compress(manifest.as_bytes(), compress::Algorithm::Zstd).unwrap();
+
+        let mut layer = frame.to_vec();
+        let manifest_offset = layer.len() as u64 + ZSTD_SKIPPABLE_FRAME_HEADER_SIZE;
+        layer.extend_from_slice(&skippable_frame(&compressed_manifest));
+
+        let mut footer = Vec::new();
+        footer.extend_from_slice(&manifest_offset.to_le_bytes());
+        footer.extend_from_slice(&(compressed_manifest.len() as u64).to_le_bytes());
+        footer.extend_from_slice(&(manifest.len() as u64).to_le_bytes());
+        footer.extend_from_slice(&MANIFEST_TYPE_CRFS.to_le_bytes());
+        footer.resize(footer_size as usize - FOOTER_MAGIC.len(), 0);
+        footer.extend_from_slice(FOOTER_MAGIC);
+        layer.extend_from_slice(&skippable_frame(&footer));
+
+        layer
+    }
+
+    #[test]
+    fn test_load_zstd_chunked_manifest() {
+        for footer_size in FOOTER_DATA_SIZES.iter() {
+            let file = TempFile::new().unwrap();
+            file.as_file().write_all(&make_layer(*footer_size)).unwrap();
+
+            let toc_index = load_manifest(file.as_path()).unwrap();
+            assert_eq!(toc_index.entries.len(), 3);
+            let entry = &toc_index.entries[1];
+            assert!(entry.is_reg());
+            assert_eq!(entry.size, 0x1000);
+            assert_eq!(
+                entry.content_digest().unwrap(),
+                RafsDigest::from_buf(&[b'a'; 0x1000], digest::Algorithm::Sha256)
+            );
+            assert!(entry.compressed_size().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_zstd_chunked_layer() {
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&[0u8; 128]).unwrap();
+        assert!(load_manifest(file.as_path()).is_err());
+
+        let mut layer = make_layer(FOOTER_DATA_SIZES[1]);
+        let len = layer.len();
+        // Point the manifest beyond the end of the layer.
+        layer[len - 40..len - 32].copy_from_slice(&u64::MAX.to_le_bytes());
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&layer).unwrap();
+        assert!(load_manifest(file.as_path()).is_err());
+    }
+}
@@ -2,6 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
 extern crate serde_json;
 
 use std::fmt::{self, Display};
@@ -11,6 +17,8 @@ use chrono::{self, DateTime, Local};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 
+pub mod builder;
+
 /// Error code related to Nydus library.
 #[derive(Debug)]
 pub enum NydusError {
@@ -30,6 +38,9 @@ pub type Result<T> = std::result::Result<T, NydusError>;
 #[derive(Clone, Debug, Serialize, PartialEq, Deserialize)]
 pub enum FsBackendType {
     Rafs,
+    /// Read-only overlay of multiple RAFS bootstraps, whose source is a JSON array of bootstrap
+    /// files from lower to upper.
+    RafsOverlay,
     PassthroughFs,
 }
 
@@ -38,9 +49,10 @@ impl FromStr for FsBackendType {
     fn from_str(s: &str) -> Result<FsBackendType> {
         match s {
             "rafs" => Ok(FsBackendType::Rafs),
+            "rafs_overlay" => Ok(FsBackendType::RafsOverlay),
             "passthrough_fs" => Ok(FsBackendType::PassthroughFs),
             o => Err(NydusError::InvalidArguments(format!(
-                "Fs backend type only accepts 'rafs' and 'passthrough_fs', but {} was specified",
+                "Fs backend type only accepts 'rafs', 'rafs_overlay' and 'passthrough_fs', but {} was specified",
                 o
             ))),
         }
@@ -58,6 +70,9 @@ impl Display for FsBackendType {
 pub struct FsBackendDesc {
     pub backend_type: FsBackendType,
     pub mountpoint: String,
+    /// Path to the RAFS bootstrap, or the source directory of passthrough filesystem.
+    #[serde(default)]
+    pub source: String,
     #[serde_as(as = "DisplayFromStr")]
     pub mounted_time: DateTime<Local>,
     pub config: serde_json::Value,
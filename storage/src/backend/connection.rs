@@ -4,13 +4,20 @@
 
 //! Help library to manage network connections.
 use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::io::Read;
 use std::io::Result;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use nydus_utils::metrics::{BackendErrorCategory, BackendMetrics, BackendRequestClass};
 
 use reqwest::header::HeaderMap;
 use reqwest::{
@@ -20,19 +27,37 @@ use reqwest::{
     Method, StatusCode, Url,
 };
 
-use crate::backend::CommonConfig;
+use crate::backend::{current_request_class, http_status_category, CommonConfig, RequestPolicy};
 
 const HEADER_AUTHORIZATION: &str = "Authorization";
 
+/// URL scheme of HTTP servers listening on Unix domain sockets, with the percent-encoded socket
+/// path as host, e.g. `http+unix://%2Frun%2Fregistry.sock/v2/`.
+pub(crate) const UNIX_SCHEME: &str = "http+unix";
+
 /// Error codes related to network communication.
 #[derive(Debug)]
 pub enum ConnectionError {
     Disconnected,
     ErrorWithMsg(String),
+    ErrorWithStatus(StatusCode, String),
     Common(reqwest::Error),
     Format(reqwest::Error),
 }
 
+impl ConnectionError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            ConnectionError::Common(e) | ConnectionError::Format(e) if e.is_timeout() => {
+                BackendErrorCategory::Timeout
+            }
+            ConnectionError::ErrorWithStatus(status, _) => http_status_category(status.as_u16()),
+            _ => BackendErrorCategory::Other,
+        }
+    }
+}
+
 /// Specialized `Result` for network communication.
 type ConnectionResult<T> = std::result::Result<T, ConnectionError>;
 
@@ -75,6 +100,17 @@ pub enum ReqBody<R> {
     Form(HashMap<String, String>),
 }
 
+impl<R> ReqBody<R> {
+    /// Clone the body to send it again, streamed bodies can't be cloned.
+    pub fn try_clone(&self) -> Option<ReqBody<R>> {
+        match self {
+            ReqBody::Read(..) => None,
+            ReqBody::Buf(buf) => Some(ReqBody::Buf(buf.clone())),
+            ReqBody::Form(form) => Some(ReqBody::Form(form.clone())),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ProxyHealth {
     status: AtomicBool,
@@ -102,11 +138,61 @@ impl ProxyHealth {
 
 #[derive(Debug)]
 struct Proxy {
-    client: Client,
+    clients: ClassClients,
     health: ProxyHealth,
     fallback: bool,
 }
 
+/// Counting semaphore to limit number of concurrent requests.
+#[derive(Debug)]
+struct ConcurrencyLimiter {
+    limit: usize,
+    inflight: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl ConcurrencyLimiter {
+    fn new(limit: usize) -> Self {
+        ConcurrencyLimiter {
+            limit,
+            inflight: Mutex::new(0),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Get a free slot, and return whether it has been blocked due to saturation.
+    fn acquire(&self) -> bool {
+        let mut inflight = self.inflight.lock().unwrap();
+        let saturated = *inflight >= self.limit;
+        while *inflight >= self.limit {
+            inflight = self.cond.wait(inflight).unwrap();
+        }
+        *inflight += 1;
+        saturated
+    }
+
+    fn release(&self) {
+        let mut inflight = self.inflight.lock().unwrap();
+        *inflight -= 1;
+        self.cond.notify_one();
+    }
+}
+
+/// Guard object to hold slots of the connection pool, slots get released when it's dropped.
+pub(crate) struct ConnectionPermit<'a> {
+    limiters: Vec<Arc<ConcurrencyLimiter>>,
+    metrics: &'a BackendMetrics,
+}
+
+impl<'a> Drop for ConnectionPermit<'a> {
+    fn drop(&mut self) {
+        for limiter in self.limiters.iter().rev() {
+            limiter.release();
+        }
+        self.metrics.pool_released();
+    }
+}
+
 /// Check whether the HTTP status code is a success result.
 pub(crate) fn is_success_status(status: StatusCode) -> bool {
     status >= StatusCode::OK && status < StatusCode::BAD_REQUEST
@@ -117,32 +203,219 @@ pub(crate) fn respond(resp: Response, catch_status: bool) -> ConnectionResult<Re
     if !catch_status || is_success_status(resp.status()) {
         Ok(resp)
     } else {
+        let status = resp.status();
         let msg = resp.text().map_err(ConnectionError::Format)?;
-        Err(ConnectionError::ErrorWithMsg(msg))
+        Err(ConnectionError::ErrorWithStatus(status, msg))
+    }
+}
+
+/// Format the base URL of server `host`, which is percent-encoded if it's a Unix socket path.
+pub(crate) fn endpoint_url(scheme: &str, host: &str) -> String {
+    if scheme == UNIX_SCHEME && host.starts_with('/') {
+        let host = host.replace('%', "%25").replace('/', "%2F");
+        format!("{}://{}", scheme, host)
+    } else {
+        format!("{}://{}", scheme, host)
+    }
+}
+
+fn percent_decode(s: &str) -> Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let v = s
+                .get(idx + 1..idx + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| einval!(format!("invalid percent-encoding in {}", s)))?;
+            result.push(v);
+            idx += 3;
+        } else {
+            result.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Forwarder from a loopback TCP port to a Unix domain socket, because the HTTP client can only
+/// connect to servers over TCP.
+#[derive(Debug)]
+struct UnixBridge {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl UnixBridge {
+    fn new(path: &Path) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        let socket = path.to_path_buf();
+
+        thread::Builder::new()
+            .name("unix-bridge".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if let Err(e) = stream.and_then(|s| Self::forward(s, &socket)) {
+                        warn!("failed to forward connection to {:?}, {}", socket, e);
+                    }
+                }
+            })?;
+        info!(
+            "forward connections from {} to unix socket {:?}",
+            addr, path
+        );
+
+        Ok(UnixBridge { addr, shutdown })
+    }
+
+    fn forward(tcp: TcpStream, path: &Path) -> Result<()> {
+        let unix = UnixStream::connect(path)?;
+        let mut tcp_reader = tcp.try_clone()?;
+        let mut unix_writer = unix.try_clone()?;
+
+        thread::Builder::new()
+            .name("unix-bridge-io".to_string())
+            .spawn(move || {
+                let _ = std::io::copy(&mut tcp_reader, &mut unix_writer);
+                let _ = unix_writer.shutdown(Shutdown::Write);
+            })?;
+        let (mut unix_reader, mut tcp_writer) = (unix, tcp);
+        thread::Builder::new()
+            .name("unix-bridge-io".to_string())
+            .spawn(move || {
+                let _ = std::io::copy(&mut unix_reader, &mut tcp_writer);
+                let _ = tcp_writer.shutdown(Shutdown::Write);
+            })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for UnixBridge {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        // Wake up the listener thread blocking in accept().
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Bridges to Unix sockets of `http+unix` URLs, created on demand.
+#[derive(Debug, Default)]
+struct UnixBridges(Mutex<HashMap<PathBuf, UnixBridge>>);
+
+impl UnixBridges {
+    /// Rewrite an `http+unix` URL to the loopback address forwarding to its socket, other URLs
+    /// are returned as is.
+    fn resolve(&self, url: &str) -> Result<String> {
+        let prefix = format!("{}://", UNIX_SCHEME);
+        if !url.starts_with(&prefix) {
+            return Ok(url.to_string());
+        }
+
+        let rest = &url[prefix.len()..];
+        let end = rest
+            .find(|c: char| c == '/' || c == '?')
+            .unwrap_or(rest.len());
+        let path = PathBuf::from(OsStr::from_bytes(&percent_decode(&rest[..end])?));
+        if !path.is_absolute() {
+            return Err(einval!(format!("invalid unix socket path in url {}", url)));
+        }
+
+        let mut bridges = self.0.lock().unwrap();
+        let addr = match bridges.get(&path) {
+            Some(bridge) => bridge.addr,
+            None => {
+                let bridge = UnixBridge::new(&path)?;
+                let addr = bridge.addr;
+                bridges.insert(path, bridge);
+                addr
+            }
+        };
+
+        Ok(format!("http://{}{}", addr, &rest[end..]))
+    }
+}
+
+/// HTTP clients for each class of requests, classes with the same timeouts share one client.
+#[derive(Debug)]
+struct ClassClients {
+    clients: Vec<Client>,
+    // Index into `clients` for each class of requests.
+    index: [usize; 3],
+}
+
+impl ClassClients {
+    fn new(proxy: &str, config: &CommonConfig) -> Result<Self> {
+        let mut clients = Vec::new();
+        let mut timeouts = Vec::new();
+        let mut index = [0usize; 3];
+
+        for class in [
+            BackendRequestClass::Metadata,
+            BackendRequestClass::Data,
+            BackendRequestClass::Prefetch,
+        ]
+        .iter()
+        {
+            let policy = config.request_policy(*class);
+            let key = (policy.timeout, policy.connect_timeout);
+            index[*class as usize] = match timeouts.iter().position(|t| *t == key) {
+                Some(idx) => idx,
+                None => {
+                    clients.push(Connection::build_connection(proxy, config, &policy)?);
+                    timeouts.push(key);
+                    clients.len() - 1
+                }
+            };
+        }
+
+        Ok(ClassClients { clients, index })
+    }
+
+    // Get the client for requests issued by current thread.
+    fn get(&self) -> &Client {
+        &self.clients[self.index[current_request_class() as usize]]
     }
 }
 
 /// A network connection to communicate with remote server.
 #[derive(Debug)]
 pub(crate) struct Connection {
-    client: Client,
+    clients: ClassClients,
     proxy: Option<Proxy>,
+    unix_bridges: UnixBridges,
     shutdown: AtomicBool,
+    limiter: Option<Arc<ConcurrencyLimiter>>,
+    blob_limit: usize,
+    // Per-blob limiters with the number of open readers of each blob.
+    blob_limiters: Mutex<HashMap<String, (Arc<ConcurrencyLimiter>, usize)>>,
 }
 
 impl Connection {
     /// Create a new connection according to the configuration.
     pub fn new(config: &CommonConfig) -> Result<Arc<Connection>> {
         info!("backend config: {:?}", config);
-        let client = Self::build_connection("", config)?;
+        let clients = ClassClients::new("", config)?;
+        let unix_bridges = UnixBridges::default();
         let proxy = if !config.proxy.url.is_empty() {
             let ping_url = if !config.proxy.ping_url.is_empty() {
-                Some(Url::from_str(&config.proxy.ping_url).map_err(|e| einval!(e))?)
+                let ping_url = unix_bridges.resolve(&config.proxy.ping_url)?;
+                Some(Url::from_str(&ping_url).map_err(|e| einval!(e))?)
             } else {
                 None
             };
+            let proxy_url = unix_bridges.resolve(&config.proxy.url)?;
             Some(Proxy {
-                client: Self::build_connection(&config.proxy.url, config)?,
+                clients: ClassClients::new(&proxy_url, config)?,
                 health: ProxyHealth::new(config.proxy.check_interval, ping_url),
                 fallback: config.proxy.fallback,
             })
@@ -150,9 +423,17 @@ impl Connection {
             None
         };
         let connection = Arc::new(Connection {
-            client,
+            clients,
             proxy,
+            unix_bridges,
             shutdown: AtomicBool::new(false),
+            limiter: if config.max_concurrency > 0 {
+                Some(Arc::new(ConcurrencyLimiter::new(config.max_concurrency)))
+            } else {
+                None
+            },
+            blob_limit: config.blob_max_concurrency,
+            blob_limiters: Mutex::new(HashMap::new()),
         });
 
         if let Some(proxy) = &connection.proxy {
@@ -196,6 +477,66 @@ impl Connection {
         self.shutdown.store(true, Ordering::Release);
     }
 
+    /// Register a reader of blob `blob_id`, which shares the per-blob limit with other readers
+    /// of the same blob.
+    ///
+    /// Each call must be paired with a call to [`release_blob()`](Self::release_blob).
+    pub fn open_blob(&self, blob_id: &str) {
+        if self.blob_limit > 0 {
+            self.blob_limiters
+                .lock()
+                .unwrap()
+                .entry(blob_id.to_string())
+                .or_insert_with(|| (Arc::new(ConcurrencyLimiter::new(self.blob_limit)), 0))
+                .1 += 1;
+        }
+    }
+
+    /// Unregister a reader of blob `blob_id`, the per-blob limiter is removed when the last
+    /// reader of the blob is released.
+    pub fn release_blob(&self, blob_id: &str) {
+        let mut limiters = self.blob_limiters.lock().unwrap();
+        if let Some((_, readers)) = limiters.get_mut(blob_id) {
+            *readers -= 1;
+            if *readers == 0 {
+                limiters.remove(blob_id);
+            }
+        }
+    }
+
+    /// Get a slot of the connection pool to access blob `blob_id`.
+    ///
+    /// It blocks until the number of concurrent requests to the blob and to the backend are
+    /// both below the configured limits. The per-blob limit only applies to blobs opened by
+    /// [`open_blob()`](Self::open_blob). The slot is released when the returned permit is
+    /// dropped.
+    pub fn acquire<'a>(&self, blob_id: &str, metrics: &'a BackendMetrics) -> ConnectionPermit<'a> {
+        let mut limiters = Vec::with_capacity(2);
+        if let Some((limiter, _)) = self.blob_limiters.lock().unwrap().get(blob_id) {
+            limiters.push(limiter.clone());
+        }
+        if let Some(limiter) = self.limiter.as_ref() {
+            limiters.push(limiter.clone());
+        }
+
+        // Acquire the per-blob slot before the backend slot, so a blob with too many pending
+        // requests won't occupy backend slots needed by other blobs.
+        let begin = Instant::now();
+        let mut saturated = false;
+        for limiter in limiters.iter() {
+            saturated |= limiter.acquire();
+        }
+        if saturated {
+            let wait = begin.elapsed();
+            debug!("connection pool saturated, waited {:?}", wait);
+            metrics.pool_acquired(Some(&wait));
+        } else {
+            metrics.pool_acquired(None);
+        }
+
+        ConnectionPermit { limiters, metrics }
+    }
+
     /// Send a request to server and wait for response.
     pub fn call<R: Read + Send + 'static>(
         &self,
@@ -210,15 +551,16 @@ impl Connection {
             return Err(ConnectionError::Disconnected);
         }
 
-        if let Some(proxy) = &self.proxy {
+        // Streamed bodies can't be sent twice for fallback, so uploads skip the proxy server.
+        let proxy = match data {
+            Some(ReqBody::Read(..)) => None,
+            _ => self.proxy.as_ref(),
+        };
+        if let Some(proxy) = proxy {
             if proxy.health.ok() {
-                let data_cloned: Option<ReqBody<R>> = match data.as_ref() {
-                    Some(ReqBody::Form(form)) => Some(ReqBody::Form(form.clone())),
-                    Some(ReqBody::Buf(buf)) => Some(ReqBody::Buf(buf.clone())),
-                    _ => None,
-                };
+                let data_cloned = data.as_ref().and_then(|d| d.try_clone());
                 let result = self.call_inner(
-                    &proxy.client,
+                    proxy.clients.get(),
                     method.clone(),
                     url,
                     &query,
@@ -249,7 +591,7 @@ impl Connection {
         }
 
         self.call_inner(
-            &self.client,
+            self.clients.get(),
             method,
             url,
             &query,
@@ -260,14 +602,29 @@ impl Connection {
         )
     }
 
-    fn build_connection(proxy: &str, config: &CommonConfig) -> Result<Client> {
-        let connect_timeout = if config.connect_timeout != 0 {
-            Some(Duration::from_secs(config.connect_timeout))
+    fn build_connection(
+        proxy: &str,
+        config: &CommonConfig,
+        policy: &RequestPolicy,
+    ) -> Result<Client> {
+        let connect_timeout = if policy.connect_timeout != 0 {
+            Some(Duration::from_secs(policy.connect_timeout))
         } else {
             None
         };
-        let timeout = if config.timeout != 0 {
-            Some(Duration::from_secs(config.timeout))
+        let timeout = if policy.timeout != 0 {
+            Some(Duration::from_secs(policy.timeout))
+        } else {
+            None
+        };
+
+        let pool_idle_timeout = if config.pool_idle_timeout != 0 {
+            Some(Duration::from_secs(config.pool_idle_timeout))
+        } else {
+            None
+        };
+        let tcp_keepalive = if config.tcp_keepalive != 0 {
+            Some(Duration::from_secs(config.tcp_keepalive))
         } else {
             None
         };
@@ -275,8 +632,14 @@ impl Connection {
         let mut cb = Client::builder()
             .timeout(timeout)
             .connect_timeout(connect_timeout)
+            .pool_idle_timeout(pool_idle_timeout)
+            .tcp_keepalive(tcp_keepalive)
             .redirect(Policy::none());
 
+        if config.pool_max_idle_per_host != 0 {
+            cb = cb.pool_max_idle_per_host(config.pool_max_idle_per_host);
+        }
+
         if !proxy.is_empty() {
             cb = cb.proxy(reqwest::Proxy::all(proxy).map_err(|e| einval!(e))?)
         }
@@ -309,7 +672,11 @@ impl Connection {
             data.is_some(),
         );
 
-        let mut rb = client.request(method, url).headers(headers);
+        let url = self
+            .unix_bridges
+            .resolve(url)
+            .map_err(|e| ConnectionError::ErrorWithMsg(e.to_string()))?;
+        let mut rb = client.request(method, url.as_str()).headers(headers);
         if let Some(q) = query.as_ref() {
             rb = rb.query(q);
         }
@@ -342,7 +709,9 @@ impl Connection {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{Cursor, Write};
+    use std::os::unix::net::UnixListener;
+    use vmm_sys_util::tempdir::TempDir;
 
     #[test]
     fn test_progress() {
@@ -376,6 +745,103 @@ mod tests {
         assert!(checker.ok());
     }
 
+    #[test]
+    fn test_concurrency_limiter() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(2));
+
+        assert!(!limiter.acquire());
+        assert!(!limiter.acquire());
+
+        let limiter2 = limiter.clone();
+        let handle = thread::spawn(move || limiter2.acquire());
+        thread::sleep(Duration::from_millis(50));
+        limiter.release();
+        assert!(handle.join().unwrap());
+        assert_eq!(*limiter.inflight.lock().unwrap(), 2);
+
+        limiter.release();
+        limiter.release();
+        assert_eq!(*limiter.inflight.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_blob_limiters() {
+        let config = CommonConfig {
+            blob_max_concurrency: 1,
+            ..Default::default()
+        };
+        let connection = Connection::new(&config).unwrap();
+        let metrics = BackendMetrics::new("test_blob_limiters", "mock");
+
+        connection.open_blob("blob1");
+        connection.open_blob("blob1");
+        connection.open_blob("blob2");
+        assert_eq!(connection.blob_limiters.lock().unwrap().len(), 2);
+        {
+            let permit = connection.acquire("blob1", &metrics);
+            assert_eq!(permit.limiters.len(), 1);
+            let permit = connection.acquire("blob3", &metrics);
+            assert!(permit.limiters.is_empty());
+        }
+
+        connection.release_blob("blob1");
+        assert_eq!(connection.blob_limiters.lock().unwrap().len(), 2);
+        connection.release_blob("blob1");
+        connection.release_blob("blob2");
+        connection.release_blob("blob3");
+        assert!(connection.blob_limiters.lock().unwrap().is_empty());
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_endpoint_url() {
+        assert_eq!(endpoint_url("https", "[::1]:5000"), "https://[::1]:5000");
+        assert_eq!(
+            endpoint_url(UNIX_SCHEME, "/run/registry.sock"),
+            "http+unix://%2Frun%2Fregistry.sock"
+        );
+        assert_eq!(
+            endpoint_url(UNIX_SCHEME, "%2Frun%2Fregistry.sock"),
+            "http+unix://%2Frun%2Fregistry.sock"
+        );
+        assert_eq!(percent_decode("%2Frun%2Fa%25b").unwrap(), b"/run/a%b");
+        assert!(percent_decode("%2").is_err());
+        assert!(percent_decode("%zz").is_err());
+    }
+
+    #[test]
+    fn test_unix_bridge() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("server.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        let bridges = UnixBridges::default();
+        assert_eq!(
+            bridges.resolve("http://[::1]:5000/v2/").unwrap(),
+            "http://[::1]:5000/v2/"
+        );
+        assert!(bridges.resolve("http+unix://run.sock/v2/").is_err());
+        let url = format!(
+            "{}/v2/?a=b",
+            endpoint_url(UNIX_SCHEME, path.to_str().unwrap())
+        );
+        let resolved = bridges.resolve(&url).unwrap();
+        assert!(resolved.starts_with("http://127.0.0.1:"));
+        assert!(resolved.ends_with("/v2/?a=b"));
+        assert_eq!(bridges.resolve(&url).unwrap(), resolved);
+
+        let addr = bridges.0.lock().unwrap().get(&path).unwrap().addr;
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        handle.join().unwrap();
+    }
+
     #[test]
     fn test_is_success_status() {
         assert_eq!(is_success_status(StatusCode::CONTINUE), false);
@@ -383,4 +849,14 @@ mod tests {
         assert_eq!(is_success_status(StatusCode::PERMANENT_REDIRECT), true);
         assert_eq!(is_success_status(StatusCode::BAD_REQUEST), false);
     }
+
+    #[test]
+    fn test_connection_error_category() {
+        let err = ConnectionError::ErrorWithStatus(StatusCode::FORBIDDEN, String::new());
+        assert_eq!(err.category(), BackendErrorCategory::Auth);
+        let err = ConnectionError::ErrorWithStatus(StatusCode::GATEWAY_TIMEOUT, String::new());
+        assert_eq!(err.category(), BackendErrorCategory::Timeout);
+        let err = ConnectionError::ErrorWithMsg("failed".to_string());
+        assert_eq!(err.category(), BackendErrorCategory::Other);
+    }
 }
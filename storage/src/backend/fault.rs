@@ -0,0 +1,322 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to inject faults into another storage backend, for testing only.
+//!
+//! The wrapper forwards requests to the inner storage backend, and injects latency, timeouts,
+//! partial reads and HTTP error codes into read requests at configured probabilities. Rules may
+//! be limited to specific blobs.
+
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nydus_utils::metrics::{BackendErrorCategory, BackendMetrics, BackendRequestClass};
+use serde_json::Value;
+
+use crate::backend::{
+    http_status_category, BackendError, BackendResult, BlobBackend, BlobReader, RequestPolicy,
+};
+use crate::factory::{BackendConfig, BlobFactory};
+
+/// Error codes injected by the fault injection storage backend.
+#[derive(Debug)]
+pub enum FaultError {
+    /// Injected request timeout.
+    Timeout,
+    /// Injected HTTP error status code.
+    Http(u16),
+}
+
+impl FaultError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            FaultError::Timeout => BackendErrorCategory::Timeout,
+            FaultError::Http(code) => http_status_category(*code),
+        }
+    }
+}
+
+impl From<FaultError> for BackendError {
+    fn from(error: FaultError) -> Self {
+        BackendError::Fault(error)
+    }
+}
+
+fn default_error_code() -> u16 {
+    503
+}
+
+/// Rule to inject faults into read requests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FaultRule {
+    /// Blob to inject faults into, all blobs if not specified.
+    #[serde(default)]
+    blob_id: Option<String>,
+    /// Probability to delay requests by `latency_ms`.
+    #[serde(default)]
+    latency_probability: f64,
+    #[serde(default)]
+    latency_ms: u64,
+    /// Probability to fail requests with timeout after `timeout_ms`.
+    #[serde(default)]
+    timeout_probability: f64,
+    #[serde(default)]
+    timeout_ms: u64,
+    /// Probability to return less data than requested.
+    #[serde(default)]
+    partial_read_probability: f64,
+    /// Probability to fail requests with HTTP status code `error_code`.
+    #[serde(default)]
+    error_probability: f64,
+    #[serde(default = "default_error_code")]
+    error_code: u16,
+}
+
+/// Configuration information for fault injection storage backend.
+#[derive(Clone, Deserialize, Serialize)]
+struct FaultConfig {
+    /// The storage backend to inject faults into.
+    backend: BackendConfig,
+    #[serde(default)]
+    rules: Vec<FaultRule>,
+    /// Seed of the random number generator, generated from current time if it's zero.
+    #[serde(default)]
+    seed: u64,
+}
+
+// A xorshift pseudo random number generator, which is good enough for fault injection.
+struct Random {
+    state: AtomicU64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        let seed = if seed != 0 {
+            seed
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+                | 1
+        };
+
+        Random {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.store(x, Ordering::Relaxed);
+        x
+    }
+
+    fn hit(&self, probability: f64) -> bool {
+        probability > 0.0 && (self.next() as f64 / u64::MAX as f64) < probability
+    }
+}
+
+struct FaultEntry {
+    blob_id: String,
+    reader: Arc<dyn BlobReader>,
+    rule: Option<FaultRule>,
+    random: Arc<Random>,
+}
+
+impl BlobReader for FaultEntry {
+    fn blob_size(&self) -> BackendResult<u64> {
+        self.reader.blob_size()
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        let rule = match self.rule.as_ref() {
+            None => return self.reader.try_read(buf, offset),
+            Some(rule) => rule,
+        };
+
+        if self.random.hit(rule.latency_probability) {
+            debug!(
+                "inject {}ms latency for blob {}",
+                rule.latency_ms, self.blob_id
+            );
+            thread::sleep(Duration::from_millis(rule.latency_ms));
+        }
+        if self.random.hit(rule.timeout_probability) {
+            warn!("inject timeout for blob {}", self.blob_id);
+            thread::sleep(Duration::from_millis(rule.timeout_ms));
+            return Err(FaultError::Timeout.into());
+        }
+        if self.random.hit(rule.error_probability) {
+            warn!("inject error {} for blob {}", rule.error_code, self.blob_id);
+            return Err(FaultError::Http(rule.error_code).into());
+        }
+
+        let size = self.reader.try_read(buf, offset)?;
+        if size > 0 && self.random.hit(rule.partial_read_probability) {
+            let partial = (self.random.next() % size as u64) as usize;
+            warn!(
+                "inject partial read for blob {}, {} of {} bytes",
+                self.blob_id, partial, size
+            );
+            return Ok(partial);
+        }
+
+        Ok(size)
+    }
+
+    fn prefetch_blob_data_range(&self, ra_offset: u32, ra_size: u32) -> BackendResult<()> {
+        self.reader.prefetch_blob_data_range(ra_offset, ra_size)
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        self.reader.stop_data_prefetch()
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        self.reader.metrics()
+    }
+
+    fn retry_limit(&self) -> u8 {
+        self.reader.retry_limit()
+    }
+
+    fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        self.reader.request_policy(class)
+    }
+}
+
+/// Storage backend to inject faults into another storage backend.
+pub struct FaultBackend {
+    backend: Arc<dyn BlobBackend>,
+    rules: Vec<FaultRule>,
+    random: Arc<Random>,
+}
+
+impl FaultBackend {
+    pub fn new(config: Value, id: Option<&str>) -> Result<FaultBackend> {
+        let config: FaultConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let id = id.ok_or_else(|| einval!("fault injection backend requires blob_id"))?;
+        if config.backend.backend_type == "fault" {
+            return Err(einval!("fault injection backend can't be nested"));
+        }
+        for rule in config.rules.iter() {
+            let probabilities = [
+                rule.latency_probability,
+                rule.timeout_probability,
+                rule.partial_read_probability,
+                rule.error_probability,
+            ];
+            if probabilities.iter().any(|p| !(0.0..=1.0).contains(p)) {
+                return Err(einval!("fault injection probability should be in [0, 1]"));
+            }
+        }
+
+        warn!("fault injection is enabled for storage backend, for testing only");
+        let backend = BlobFactory::new_backend(config.backend, id)?;
+
+        Ok(FaultBackend {
+            backend,
+            rules: config.rules,
+            random: Arc::new(Random::new(config.seed)),
+        })
+    }
+}
+
+impl BlobBackend for FaultBackend {
+    fn shutdown(&self) {
+        self.backend.shutdown()
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        self.backend.metrics()
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        let reader = self.backend.get_reader(blob_id)?;
+        let rule = self
+            .rules
+            .iter()
+            .find(|r| r.blob_id.as_ref().map(|id| id == blob_id).unwrap_or(true))
+            .cloned();
+
+        Ok(Arc::new(FaultEntry {
+            blob_id: blob_id.to_string(),
+            reader,
+            rule,
+            random: self.random.clone(),
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "backend-localfs"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn new_backend(dir: &str, rules: Value) -> FaultBackend {
+        let config = serde_json::json!({
+            "backend": {"type": "localfs", "config": {"dir": dir}},
+            "rules": rules,
+            "seed": 1,
+        });
+        FaultBackend::new(config, Some("test_fault_injection")).unwrap()
+    }
+
+    #[test]
+    fn test_fault_injection() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path().to_str().unwrap();
+        fs::write(tmp_dir.as_path().join("blob1"), b"fault injection").unwrap();
+        fs::write(tmp_dir.as_path().join("blob2"), b"fault injection").unwrap();
+        let mut buf = [0u8; 5];
+
+        let backend = new_backend(
+            dir,
+            serde_json::json!([{"blob_id": "blob1", "error_probability": 1.0, "error_code": 404}]),
+        );
+        let reader = backend.get_reader("blob1").unwrap();
+        match reader.read(&mut buf, 0) {
+            Err(e @ BackendError::Fault(FaultError::Http(404))) => {
+                assert_eq!(e.category(), BackendErrorCategory::NotFound)
+            }
+            _ => panic!("expect injected error"),
+        }
+        let reader = backend.get_reader("blob2").unwrap();
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 5);
+        assert_eq!(&buf, b"fault");
+
+        let backend = new_backend(dir, serde_json::json!([{"timeout_probability": 1.0}]));
+        match backend.get_reader("blob2").unwrap().read(&mut buf, 0) {
+            Err(e @ BackendError::Fault(FaultError::Timeout)) => {
+                assert_eq!(e.category(), BackendErrorCategory::Timeout)
+            }
+            _ => panic!("expect injected timeout"),
+        }
+
+        let backend = new_backend(dir, serde_json::json!([{"partial_read_probability": 1.0}]));
+        assert!(
+            backend
+                .get_reader("blob1")
+                .unwrap()
+                .read(&mut buf, 0)
+                .unwrap()
+                < 5
+        );
+
+        let config = serde_json::json!({
+            "backend": {"type": "localfs", "config": {"dir": dir}},
+            "rules": [{"error_probability": 2.0}],
+        });
+        assert!(FaultBackend::new(config, Some("test_fault_injection")).is_err());
+    }
+}
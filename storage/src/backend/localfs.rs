@@ -6,7 +6,7 @@
 
 use std::collections::HashMap;
 use std::fs::{remove_file, File, OpenOptions};
-use std::io::{Error, Result};
+use std::io::{Error, ErrorKind, Result};
 use std::mem::{size_of, ManuallyDrop};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
@@ -17,7 +17,9 @@ use std::time::Duration;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
 use nix::sys::uio;
-use nydus_utils::{metrics::BackendMetrics, round_down_4k, try_round_up_4k};
+use nydus_utils::metrics::{BackendErrorCategory, BackendMetrics};
+use nydus_utils::{round_down_4k, try_round_up_4k};
+use vm_memory::Bytes;
 
 use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader};
 use crate::utils::{readahead, readv, MemSliceCursor};
@@ -43,6 +45,22 @@ pub enum LocalFsError {
     CopyData(Error),
     Readahead(Error),
     AccessLog(Error),
+    Mmap(Error),
+}
+
+impl LocalFsError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            LocalFsError::BlobFile(e) if e.kind() == ErrorKind::NotFound => {
+                BackendErrorCategory::NotFound
+            }
+            LocalFsError::BlobFile(e) if e.kind() == ErrorKind::PermissionDenied => {
+                BackendErrorCategory::Auth
+            }
+            _ => BackendErrorCategory::Other,
+        }
+    }
 }
 
 impl From<LocalFsError> for BackendError {
@@ -66,6 +84,92 @@ struct LocalFsConfig {
     blob_file: String,
     #[serde(default)]
     dir: String,
+    // Access pattern hint of blob files: "normal", "sequential", "willneed" or "random".
+    #[serde(default)]
+    fadvise: String,
+    // Read blob files through memory mapping instead of pread.
+    #[serde(default)]
+    mmap: bool,
+}
+
+// Convert the access pattern hint to `posix_fadvise(2)` and `madvise(2)` advices.
+fn parse_fadvise(advice: &str) -> Result<Option<(libc::c_int, libc::c_int)>> {
+    match advice {
+        "" | "normal" => Ok(None),
+        "sequential" => Ok(Some((libc::POSIX_FADV_SEQUENTIAL, libc::MADV_SEQUENTIAL))),
+        "willneed" => Ok(Some((libc::POSIX_FADV_WILLNEED, libc::MADV_WILLNEED))),
+        "random" => Ok(Some((libc::POSIX_FADV_RANDOM, libc::MADV_RANDOM))),
+        _ => Err(einval!(format!("invalid fadvise hint {}", advice))),
+    }
+}
+
+/// Read-only memory mapping of a blob file.
+struct BlobMmap {
+    base: *const u8,
+    size: usize,
+}
+
+// Safe because the mapping is read-only and never changes once created.
+unsafe impl Send for BlobMmap {}
+
+unsafe impl Sync for BlobMmap {}
+
+impl BlobMmap {
+    fn new(file: &File, advice: Option<libc::c_int>) -> Result<Option<Self>> {
+        let size = file.metadata()?.len();
+        if size == 0 {
+            return Ok(None);
+        } else if size > usize::MAX as u64 {
+            return Err(einval!("blob file is too big to mmap"));
+        }
+
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size as usize,
+                libc::PROT_READ,
+                libc::MAP_NORESERVE | libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(last_error!("failed to mmap blob file"));
+        } else if base.is_null() {
+            return Err(ebadf!("failed to mmap blob file"));
+        }
+        if let Some(advice) = advice {
+            if unsafe { libc::madvise(base, size as usize, advice) } < 0 {
+                warn!(
+                    "localfs: failed to madvise blob file, {}",
+                    Error::last_os_error()
+                );
+            }
+        }
+
+        Ok(Some(BlobMmap {
+            base: base as *const u8,
+            size: size as usize,
+        }))
+    }
+
+    // Get the mapped data in range [offset, offset + size), truncated at the end of the file.
+    fn slice(&self, offset: u64, size: usize) -> &[u8] {
+        if offset >= self.size as u64 {
+            return &[];
+        }
+        let offset = offset as usize;
+        let size = std::cmp::min(size, self.size - offset);
+        // Safe because the range has been validated.
+        unsafe { std::slice::from_raw_parts(self.base.add(offset), size) }
+    }
+}
+
+impl Drop for BlobMmap {
+    fn drop(&mut self) {
+        let ptr = self.base as *mut u8 as *mut libc::c_void;
+        unsafe { libc::munmap(ptr, self.size) };
+    }
 }
 
 struct LocalFsEntry {
@@ -77,6 +181,7 @@ struct LocalFsEntry {
     trace: Arc<LocalFsTracer>,
     trace_sec: u32,
     trace_condvar: Arc<(Mutex<bool>, Condvar)>,
+    mmap: Option<BlobMmap>,
 }
 
 impl BlobReader for LocalFsEntry {
@@ -95,6 +200,13 @@ impl BlobReader for LocalFsEntry {
             self.id,
         );
 
+        if let Some(mmap) = self.mmap.as_ref() {
+            let data = mmap.slice(offset, buf.len());
+            buf[..data.len()].copy_from_slice(data);
+            self.trace.record(offset, data.len() as u32);
+            return Ok(data.len());
+        }
+
         uio::pread(self.file.as_raw_fd(), buf, offset as i64)
             .map(|v| {
                 debug!("local blob file read {} bytes", v);
@@ -110,6 +222,22 @@ impl BlobReader for LocalFsEntry {
         offset: u64,
         max_size: usize,
     ) -> BackendResult<usize> {
+        if let Some(mmap) = self.mmap.as_ref() {
+            let data = mmap.slice(offset, max_size);
+            let mut pos = 0;
+            for buf in bufs.iter() {
+                if pos >= data.len() {
+                    break;
+                }
+                let cnt = std::cmp::min(buf.len(), data.len() - pos);
+                buf.write_slice(&data[pos..pos + cnt], 0)
+                    .map_err(|e| LocalFsError::CopyData(eio!(e)))?;
+                pos += cnt;
+            }
+            self.trace.record(offset, pos as u32);
+            return Ok(pos);
+        }
+
         let mut c = MemSliceCursor::new(bufs);
         let iovec = c.consume(max_size);
 
@@ -214,6 +342,10 @@ pub struct LocalFs {
     readahead: bool,
     // Number of seconds to collect blob access logs
     readahead_sec: u32,
+    // Advices for `posix_fadvise(2)` and `madvise(2)` about access pattern of blob files.
+    advice: Option<(libc::c_int, libc::c_int)>,
+    // Whether to read blob files through memory mapping.
+    mmap: bool,
     // Metrics collector.
     metrics: Arc<BackendMetrics>,
     // Hashmap to map blob id to blob file.
@@ -228,12 +360,15 @@ impl LocalFs {
         if config.blob_file.is_empty() && config.dir.is_empty() {
             return Err(einval!("blob file or dir is required"));
         }
+        let advice = parse_fadvise(&config.fadvise)?;
 
         Ok(LocalFs {
             blob_file: config.blob_file,
             dir: config.dir,
             readahead: config.readahead,
             readahead_sec: config.readahead_sec,
+            advice,
+            mmap: config.mmap,
             metrics: BackendMetrics::new(id, "localfs"),
             entries: RwLock::new(HashMap::new()),
         })
@@ -263,6 +398,22 @@ impl LocalFs {
             .read(true)
             .open(&blob_file_path)
             .map_err(LocalFsError::BlobFile)?;
+        if let Some((advice, _)) = self.advice {
+            let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
+            if ret != 0 {
+                warn!(
+                    "localfs: failed to fadvise blob file {}, {}",
+                    blob_file_path.display(),
+                    Error::from_raw_os_error(ret)
+                );
+            }
+        }
+        let mmap = if self.mmap {
+            BlobMmap::new(&file, self.advice.map(|(_, advice)| advice))
+                .map_err(LocalFsError::Mmap)?
+        } else {
+            None
+        };
         // Don't expect poisoned lock here.
         let mut table_guard = self.entries.write().unwrap();
         if let Some(entry) = table_guard.get(blob_id) {
@@ -277,6 +428,7 @@ impl LocalFs {
                 trace: Arc::new(LocalFsTracer::new()),
                 trace_sec: self.readahead_sec,
                 trace_condvar: Arc::new((Mutex::new(false), Condvar::new())),
+                mmap,
             });
             table_guard.insert(blob_id.to_string(), entry.clone());
             Ok(entry)
@@ -480,6 +632,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "".to_string(),
             dir: "".to_string(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         assert!(LocalFs::new(json, Some("test")).is_err());
@@ -489,6 +643,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "/a/b/c".to_string(),
             dir: "/a/b".to_string(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         assert!(LocalFs::new(json, None).is_err());
@@ -501,6 +657,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "/a/b/cxxxxxxxxxxxxxxxxxxxxxxx".to_string(),
             dir: "/a/b".to_string(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some("test")).unwrap();
@@ -515,6 +673,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: path.to_str().unwrap().to_owned(),
             dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some("test")).unwrap();
@@ -525,6 +685,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "".to_string(),
             dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some(filename)).unwrap();
@@ -541,6 +703,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "".to_string(),
             dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some(filename)).unwrap();
@@ -567,6 +731,8 @@ mod tests {
             readahead_sec: 20,
             blob_file: "".to_string(),
             dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some(filename)).unwrap();
@@ -598,6 +764,59 @@ mod tests {
         assert_eq!(blob4.blob_size().unwrap(), 4);
     }
 
+    #[test]
+    fn test_localfs_mmap_and_fadvise() {
+        let tempfile = TempFile::new().unwrap();
+        let path = tempfile.as_path();
+        let filename = path.file_name().unwrap().to_str().unwrap();
+        tempfile
+            .as_file()
+            .write_all_at(&[0x1u8, 0x2, 0x3, 0x4], 0)
+            .unwrap();
+
+        let mut config = LocalFsConfig {
+            readahead: false,
+            readahead_sec: 10,
+            blob_file: "".to_string(),
+            dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "invalid".to_string(),
+            mmap: true,
+        };
+        let json = serde_json::to_value(&config).unwrap();
+        assert!(LocalFs::new(json, Some(filename)).is_err());
+
+        config.fadvise = "sequential".to_string();
+        let json = serde_json::to_value(&config).unwrap();
+        let fs = LocalFs::new(json, Some(filename)).unwrap();
+        let blob = fs.get_reader(filename).unwrap();
+
+        let mut buf1 = [0x0u8; 2];
+        assert_eq!(blob.read(&mut buf1, 0x1).unwrap(), 2);
+        assert_eq!(buf1, [0x2, 0x3]);
+        assert_eq!(blob.read(&mut buf1, 0x3).unwrap(), 1);
+        assert_eq!(buf1[0], 0x4);
+        assert_eq!(blob.read(&mut buf1, 0x4).unwrap(), 0);
+
+        let mut buf2 = [0x0u8];
+        let mut buf3 = [0x0u8; 2];
+        let bufs = [
+            unsafe { FileVolatileSlice::new(buf2.as_mut_ptr(), 1) },
+            unsafe { FileVolatileSlice::new(buf3.as_mut_ptr(), 2) },
+        ];
+        assert_eq!(blob.readv(&bufs, 0x0, 3).unwrap(), 3);
+        assert_eq!(buf2[0], 0x1);
+        assert_eq!(buf3, [0x2, 0x3]);
+        assert_eq!(blob.readv(&bufs, 0x2, 3).unwrap(), 2);
+        assert_eq!(buf2[0], 0x3);
+        assert_eq!(buf3[0], 0x4);
+
+        // Empty blob files can't be mapped, fall back to pread.
+        let tempfile = TempFile::new().unwrap();
+        let filename = tempfile.as_path().file_name().unwrap().to_str().unwrap();
+        let blob = fs.get_reader(filename).unwrap();
+        assert_eq!(blob.read(&mut buf1, 0).unwrap(), 0);
+    }
+
     #[test]
     fn test_localfs_trace_and_prefetch() {
         let tempfile = TempFile::new().unwrap();
@@ -616,6 +835,8 @@ mod tests {
             readahead_sec: 10,
             blob_file: "".to_string(),
             dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+            fadvise: "".to_string(),
+            mmap: false,
         };
         let json = serde_json::to_value(&config).unwrap();
         let fs = LocalFs::new(json, Some(filename)).unwrap();
@@ -659,4 +880,45 @@ mod tests {
         assert_eq!(trace[1].1, 0x1000);
         assert_eq!(trace[1].2, 0);
     }
+
+    #[test]
+    fn test_localfs_async_read_parity() {
+        use crate::backend::{AsyncBlobReader, BlobReaderBridge};
+
+        let tempfile = TempFile::new().unwrap();
+        let path = tempfile.as_path();
+        let filename = path.file_name().unwrap().to_str().unwrap();
+        let data: Vec<u8> = (0..0x12345u32).map(|v| (v % 253) as u8).collect();
+        tempfile.as_file().write_all_at(&data, 0).unwrap();
+
+        for mmap in [false, true].iter() {
+            let config = LocalFsConfig {
+                readahead: false,
+                readahead_sec: 10,
+                blob_file: "".to_string(),
+                dir: path.parent().unwrap().to_str().unwrap().to_owned(),
+                fadvise: "".to_string(),
+                mmap: *mmap,
+            };
+            let json = serde_json::to_value(&config).unwrap();
+            let fs = LocalFs::new(json, Some(filename)).unwrap();
+            let reader = fs.get_reader(filename).unwrap();
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .build()
+                .unwrap();
+            let bridge = BlobReaderBridge::new(reader.clone(), runtime.handle().clone());
+
+            for (offset, size, split) in
+                [(0u64, 0x12345usize, 0x1000usize), (0x11000, 0x4000, 0x800)].iter()
+            {
+                let mut sync_buf = vec![0u8; *size];
+                let mut async_buf = vec![0u8; *size];
+                let sync_cnt = reader.read(&mut sync_buf, *offset).unwrap();
+                let async_cnt = bridge.read_split(&mut async_buf, *offset, *split).unwrap();
+                assert_eq!(sync_cnt, async_cnt);
+                assert_eq!(sync_buf, async_buf);
+            }
+        }
+    }
 }
@@ -12,23 +12,42 @@
 //! - [LocalFs](localfs/struct.LocalFs.html): backend driver to access blobs on local file system.
 //!   The [LocalFs](localfs/struct.LocalFs.html) storage backend supports backend level data
 //!   prefetching, which is to load data into page cache.
+//! - [FaultBackend](fault/struct.FaultBackend.html): backend driver to inject faults into another
+//!   storage backend, only for testing.
+//! - [TieredBackend](tiered/struct.TieredBackend.html): backend driver to compose multiple storage
+//!   backends in tiers, falling back to the next tier on failure.
+//! - [P2pBackend](p2p/struct.P2pBackend.html): backend driver to fetch blob data from peer caches
+//!   selected by consistent hashing, falling back to the origin backend on failure.
 
+use std::cell::Cell;
+use std::io::Error;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use fuse_backend_rs::transport::FileVolatileSlice;
-use nydus_utils::metrics::{BackendMetrics, ERROR_HOLDER};
+use futures::executor::block_on;
+use futures::future::{try_join_all, BoxFuture};
+use nydus_utils::metrics::{
+    BackendErrorCategory, BackendMetrics, BackendRequestClass, ERROR_HOLDER,
+};
+use tokio::runtime::Handle;
 
-use crate::utils::copyv;
+use crate::utils::{alloc_buf, copyv};
 use crate::StorageError;
 
 #[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
 pub mod connection;
+#[cfg(feature = "backend-fault-injection")]
+pub mod fault;
 #[cfg(feature = "backend-localfs")]
 pub mod localfs;
 #[cfg(feature = "backend-oss")]
 pub mod oss;
+pub mod p2p;
 #[cfg(feature = "backend-registry")]
 pub mod registry;
+pub mod tiered;
 
 /// Error codes related to storage backend operations.
 #[derive(Debug)]
@@ -37,6 +56,8 @@ pub enum BackendError {
     Unsupported(String),
     /// Failed to copy data from/into blob.
     CopyData(StorageError),
+    /// Failed to run concurrent read tasks.
+    Task(String),
     #[cfg(feature = "backend-registry")]
     /// Error from Registry storage backend.
     Registry(self::registry::RegistryError),
@@ -46,11 +67,151 @@ pub enum BackendError {
     #[cfg(feature = "backend-oss")]
     /// Error from OSS storage backend.
     Oss(self::oss::OssError),
+    #[cfg(feature = "backend-fault-injection")]
+    /// Error injected by the fault injection storage backend.
+    Fault(self::fault::FaultError),
+}
+
+impl BackendError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            #[cfg(feature = "backend-registry")]
+            BackendError::Registry(e) => e.category(),
+            #[cfg(feature = "backend-localfs")]
+            BackendError::LocalFs(e) => e.category(),
+            #[cfg(feature = "backend-oss")]
+            BackendError::Oss(e) => e.category(),
+            #[cfg(feature = "backend-fault-injection")]
+            BackendError::Fault(e) => e.category(),
+            _ => BackendErrorCategory::Other,
+        }
+    }
 }
 
 /// Specialized `Result` for storage backends.
 pub type BackendResult<T> = std::result::Result<T, BackendError>;
 
+thread_local! {
+    // Class of storage backend requests issued by current thread.
+    static CURRENT_REQUEST_CLASS: Cell<BackendRequestClass> = Cell::new(BackendRequestClass::Data);
+}
+
+/// Get class of storage backend requests issued by current thread, `Data` by default.
+pub fn current_request_class() -> BackendRequestClass {
+    CURRENT_REQUEST_CLASS.with(|c| c.get())
+}
+
+/// Guard to issue storage backend requests of a class from current thread, the previous class
+/// is restored when the guard is dropped.
+pub struct RequestClassGuard {
+    prev: BackendRequestClass,
+}
+
+impl RequestClassGuard {
+    /// Issue requests of `class` from current thread until the returned guard is dropped.
+    pub fn enter(class: BackendRequestClass) -> Self {
+        let prev = CURRENT_REQUEST_CLASS.with(|c| c.replace(class));
+        RequestClassGuard { prev }
+    }
+}
+
+impl Drop for RequestClassGuard {
+    fn drop(&mut self) {
+        CURRENT_REQUEST_CLASS.with(|c| c.set(self.prev));
+    }
+}
+
+/// Get category of errors for a HTTP status code.
+pub(crate) fn http_status_category(status: u16) -> BackendErrorCategory {
+    match status {
+        401 | 403 => BackendErrorCategory::Auth,
+        404 => BackendErrorCategory::NotFound,
+        408 | 504 => BackendErrorCategory::Timeout,
+        _ => BackendErrorCategory::Other,
+    }
+}
+
+/// Configuration information to convert storage backend errors into errno values.
+///
+/// All backend errors are reported as `EIO` by default. Errors of some categories may be
+/// reported with other errno values instead, such as `ETIMEDOUT`/`EAGAIN` for timeouts and
+/// `EACCES` for authentication failures, so applications may tell causes of failures apart.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrnoMapping {
+    /// Errno name for requests timed out.
+    pub timeout: String,
+    /// Errno name for authentication or authorization failures.
+    pub auth: String,
+    /// Errno name for blobs not found on the backend.
+    pub not_found: String,
+}
+
+impl Default for ErrnoMapping {
+    fn default() -> Self {
+        Self {
+            timeout: "EIO".to_string(),
+            auth: "EIO".to_string(),
+            not_found: "EIO".to_string(),
+        }
+    }
+}
+
+/// Names of errno values supported by [ErrnoMapping].
+pub(crate) const ERRNO_NAMES: [&str; 6] =
+    ["EIO", "EAGAIN", "ETIMEDOUT", "EACCES", "EPERM", "ENOENT"];
+
+impl ErrnoMapping {
+    /// Validate the configured errno names.
+    pub fn validate(&self) -> std::io::Result<()> {
+        for name in [&self.timeout, &self.auth, &self.not_found].iter() {
+            if Self::errno_by_name(name).is_none() {
+                return Err(einval!(format!(
+                    "unsupported errno {} in errno_mapping",
+                    name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the errno value to report errors of `category`.
+    pub fn errno(&self, category: BackendErrorCategory) -> i32 {
+        let name = match category {
+            BackendErrorCategory::Timeout => &self.timeout,
+            BackendErrorCategory::Auth => &self.auth,
+            BackendErrorCategory::NotFound => &self.not_found,
+            BackendErrorCategory::Other => return libc::EIO,
+        };
+
+        Self::errno_by_name(name).unwrap_or(libc::EIO)
+    }
+
+    /// Convert a storage backend error into an IO error.
+    pub fn convert(&self, err: BackendError) -> Error {
+        let errno = self.errno(err.category());
+        error!(
+            "storage backend error {:?}, reported as errno {}",
+            err, errno
+        );
+        Error::from_raw_os_error(errno)
+    }
+
+    fn errno_by_name(name: &str) -> Option<i32> {
+        match name {
+            "EIO" => Some(libc::EIO),
+            "EAGAIN" => Some(libc::EAGAIN),
+            "ETIMEDOUT" => Some(libc::ETIMEDOUT),
+            "EACCES" => Some(libc::EACCES),
+            "EPERM" => Some(libc::EPERM),
+            "ENOENT" => Some(libc::ENOENT),
+            _ => None,
+        }
+    }
+}
+
 /// Configuration information for network proxy.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
@@ -72,6 +233,19 @@ impl Default for ProxyConfig {
     }
 }
 
+/// Timeouts and retry policy for a class of storage backend requests, unset fields inherit
+/// values from [CommonConfig](struct.CommonConfig.html).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RequestClassConfig {
+    timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    retry_limit: Option<u8>,
+    retry_backoff_ms: Option<u64>,
+    retry_backoff_max_ms: Option<u64>,
+    retry_jitter: Option<bool>,
+}
+
 /// Generic configuration for storage backends.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
@@ -80,6 +254,29 @@ pub struct CommonConfig {
     timeout: u64,
     connect_timeout: u64,
     retry_limit: u8,
+    /// Delay before the first retry of failed requests, doubled for each following retry, in
+    /// milliseconds. 0 means retrying immediately.
+    retry_backoff_ms: u64,
+    /// Upper limit of the delay between retries, in milliseconds. 0 means no limit.
+    retry_backoff_max_ms: u64,
+    /// Randomize the delay between retries in [delay / 2, delay].
+    retry_jitter: bool,
+    /// Overrides for requests fetching metadata, such as the chunk information array of blobs.
+    metadata_request: RequestClassConfig,
+    /// Overrides for requests fetching data on demand.
+    data_request: RequestClassConfig,
+    /// Overrides for requests fetching data for prefetch.
+    prefetch_request: RequestClassConfig,
+    /// Maximum number of concurrent requests to the backend, 0 means no limit.
+    max_concurrency: usize,
+    /// Maximum number of concurrent requests to a blob, 0 means no limit.
+    blob_max_concurrency: usize,
+    /// Timeout to close idle connections in the pool, in seconds. 0 means never close.
+    pool_idle_timeout: u64,
+    /// Maximum number of idle connections kept in the pool for each host, 0 means no limit.
+    pool_max_idle_per_host: usize,
+    /// Interval of TCP keepalive probes, in seconds. 0 means disabled.
+    tcp_keepalive: u64,
 }
 
 impl Default for CommonConfig {
@@ -89,10 +286,86 @@ impl Default for CommonConfig {
             timeout: 5,
             connect_timeout: 5,
             retry_limit: 0,
+            retry_backoff_ms: 0,
+            retry_backoff_max_ms: 10_000,
+            retry_jitter: false,
+            metadata_request: RequestClassConfig::default(),
+            data_request: RequestClassConfig::default(),
+            prefetch_request: RequestClassConfig::default(),
+            max_concurrency: 0,
+            blob_max_concurrency: 0,
+            pool_idle_timeout: 90,
+            pool_max_idle_per_host: 0,
+            tcp_keepalive: 0,
         }
     }
 }
 
+impl CommonConfig {
+    /// Get timeouts and retry policy for a class of requests.
+    pub fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        let config = match class {
+            BackendRequestClass::Metadata => &self.metadata_request,
+            BackendRequestClass::Data => &self.data_request,
+            BackendRequestClass::Prefetch => &self.prefetch_request,
+        };
+
+        RequestPolicy {
+            timeout: config.timeout.unwrap_or(self.timeout),
+            connect_timeout: config.connect_timeout.unwrap_or(self.connect_timeout),
+            retry_limit: config.retry_limit.unwrap_or(self.retry_limit),
+            retry_backoff_ms: config.retry_backoff_ms.unwrap_or(self.retry_backoff_ms),
+            retry_backoff_max_ms: config
+                .retry_backoff_max_ms
+                .unwrap_or(self.retry_backoff_max_ms),
+            retry_jitter: config.retry_jitter.unwrap_or(self.retry_jitter),
+        }
+    }
+}
+
+/// Timeouts and retry policy of storage backend requests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestPolicy {
+    /// Timeout of requests in seconds, 0 means no timeout.
+    pub timeout: u64,
+    /// Timeout to connect to servers in seconds, 0 means no timeout.
+    pub connect_timeout: u64,
+    /// Maximum number of times to retry failed requests.
+    pub retry_limit: u8,
+    /// Delay before the first retry in milliseconds, doubled for each following retry.
+    pub retry_backoff_ms: u64,
+    /// Upper limit of the delay between retries in milliseconds, 0 means no limit.
+    pub retry_backoff_max_ms: u64,
+    /// Randomize the delay between retries in [delay / 2, delay].
+    pub retry_jitter: bool,
+}
+
+impl RequestPolicy {
+    /// Get the delay before retrying a failed request, `retry` counts from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        if self.retry_backoff_ms == 0 {
+            return Duration::from_millis(0);
+        }
+
+        let mut delay = self
+            .retry_backoff_ms
+            .saturating_mul(1u64.checked_shl(retry).unwrap_or(u64::MAX));
+        if self.retry_backoff_max_ms > 0 && delay > self.retry_backoff_max_ms {
+            delay = self.retry_backoff_max_ms;
+        }
+        if self.retry_jitter {
+            // Spread retries of concurrent requests failed at the same time.
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos() as u64)
+                .unwrap_or(0);
+            delay = delay / 2 + nanos % (delay - delay / 2 + 1);
+        }
+
+        Duration::from_millis(delay)
+    }
+}
+
 /// Trait to read data from a on storage backend.
 pub trait BlobReader: Send + Sync {
     /// Get size of the blob file.
@@ -111,10 +384,12 @@ pub trait BlobReader: Send + Sync {
     /// - bytes of data read, which may be smaller than buf.len()
     /// - error code if error happens
     ///
-    /// It will try `BlobBackend::retry_limit()` times at most and return the first successfully
-    /// read data.
+    /// Failed requests are retried according to `BlobReader::request_policy()` of the request
+    /// class of current thread, and the first successfully read data is returned.
     fn read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
-        let mut retry_count = self.retry_limit();
+        let class = current_request_class();
+        let policy = self.request_policy(class);
+        let mut retry = 0u32;
         let begin_time = self.metrics().begin();
 
         loop {
@@ -124,14 +399,18 @@ pub trait BlobReader: Send + Sync {
                     return Ok(size);
                 }
                 Err(err) => {
-                    if retry_count > 0 {
+                    if retry < policy.retry_limit as u32 {
+                        let delay = policy.backoff(retry);
+                        retry += 1;
                         warn!(
-                            "Read from backend failed: {:?}, retry count {}",
-                            err, retry_count
+                            "Read from backend failed: {:?}, {:?} request retry {} after {:?}",
+                            err, class, retry, delay
                         );
-                        retry_count -= 1;
+                        self.metrics().read_retry(class);
+                        thread::sleep(delay);
                     } else {
                         self.metrics().end(&begin_time, buf.len(), true);
+                        self.metrics().read_error(err.category());
                         ERROR_HOLDER
                             .lock()
                             .unwrap()
@@ -150,8 +429,7 @@ pub trait BlobReader: Send + Sync {
     /// - bytes of data read, which may be smaller than max_size
     /// - error code if error happens
     ///
-    /// It will try `BlobBackend::retry_limit()` times at most and return the first successfully
-    /// read data.
+    /// Failed requests are retried in the same way as `BlobReader::read()`.
     fn readv(
         &self,
         bufs: &[FileVolatileSlice],
@@ -192,6 +470,14 @@ pub trait BlobReader: Send + Sync {
     fn retry_limit(&self) -> u8 {
         0
     }
+
+    /// Get timeouts and retry policy for a class of requests.
+    fn request_policy(&self, _class: BackendRequestClass) -> RequestPolicy {
+        RequestPolicy {
+            retry_limit: self.retry_limit(),
+            ..Default::default()
+        }
+    }
 }
 
 /// Trait to access blob files on backend storages, such as OSS, registry, local fs etc.
@@ -206,6 +492,79 @@ pub trait BlobBackend: Send + Sync {
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>>;
 }
 
+/// Trait to read data from a storage backend asynchronously.
+pub trait AsyncBlobReader: Send + Sync {
+    /// Read a range of data [offset, offset + size) from the blob file.
+    ///
+    /// The returned buffer may be smaller than `size` if reaching end of the blob file.
+    fn async_read(&self, offset: u64, size: usize) -> BoxFuture<'static, BackendResult<Vec<u8>>>;
+
+    /// Read a range of data from the blob file into the provided buffer, by issuing concurrent
+    /// requests of `split_size` bytes at most.
+    ///
+    /// Read data of range [offset, offset + buf.len()) from the blob file, and returns:
+    /// - bytes of data read, which may be smaller than buf.len()
+    /// - error code if any of the requests fails
+    ///
+    /// It blocks the current thread until all requests complete, so it must not be called from
+    /// worker threads of a tokio runtime.
+    fn read_split(&self, buf: &mut [u8], offset: u64, split_size: usize) -> BackendResult<usize> {
+        let split_size = std::cmp::max(split_size, 1);
+        let requests = buf
+            .chunks(split_size)
+            .enumerate()
+            .map(|(idx, b)| self.async_read(offset + (idx * split_size) as u64, b.len()))
+            .collect::<Vec<_>>();
+        let results = block_on(try_join_all(requests))?;
+
+        let mut total = 0;
+        for (data, b) in results.iter().zip(buf.chunks_mut(split_size)) {
+            let size = std::cmp::min(data.len(), b.len());
+            b[..size].copy_from_slice(&data[..size]);
+            total += size;
+            if size < b.len() {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+}
+
+/// Bridge to read data from a blocking [BlobReader](trait.BlobReader.html) asynchronously, by
+/// running requests on the blocking thread pool of a tokio runtime.
+///
+/// Requests inherit the [BackendRequestClass] of the calling thread.
+pub struct BlobReaderBridge {
+    reader: Arc<dyn BlobReader>,
+    handle: Handle,
+}
+
+impl BlobReaderBridge {
+    /// Create a new instance of `BlobReaderBridge`.
+    pub fn new(reader: Arc<dyn BlobReader>, handle: Handle) -> Self {
+        BlobReaderBridge { reader, handle }
+    }
+}
+
+impl AsyncBlobReader for BlobReaderBridge {
+    fn async_read(&self, offset: u64, size: usize) -> BoxFuture<'static, BackendResult<Vec<u8>>> {
+        let reader = self.reader.clone();
+        let class = current_request_class();
+        let task = self
+            .handle
+            .spawn_blocking(move || -> BackendResult<Vec<u8>> {
+                let _guard = RequestClassGuard::enter(class);
+                let mut buf = alloc_buf(size);
+                let cnt = reader.read(&mut buf, offset)?;
+                buf.truncate(cnt);
+                Ok(buf)
+            });
+
+        Box::pin(async move { task.await.map_err(|e| BackendError::Task(e.to_string()))? })
+    }
+}
+
 #[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
 /// Get default http scheme for network connection.
 fn default_http_scheme() -> String {
@@ -215,6 +574,8 @@ fn default_http_scheme() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::MockBackend;
+    use std::sync::atomic::Ordering;
 
     #[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
     #[test]
@@ -229,9 +590,183 @@ mod tests {
         assert_eq!(config.timeout, 5);
         assert_eq!(config.connect_timeout, 5);
         assert_eq!(config.retry_limit, 0);
+        assert_eq!(config.max_concurrency, 0);
+        assert_eq!(config.blob_max_concurrency, 0);
+        assert_eq!(config.pool_idle_timeout, 90);
+        assert_eq!(config.pool_max_idle_per_host, 0);
+        assert_eq!(config.tcp_keepalive, 0);
         assert_eq!(config.proxy.check_interval, 5);
         assert_eq!(config.proxy.fallback, true);
         assert_eq!(config.proxy.ping_url, "");
         assert_eq!(config.proxy.url, "");
+        assert_eq!(
+            config.request_policy(BackendRequestClass::Prefetch),
+            RequestPolicy {
+                timeout: 5,
+                connect_timeout: 5,
+                retry_limit: 0,
+                retry_backoff_ms: 0,
+                retry_backoff_max_ms: 10_000,
+                retry_jitter: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_request_class_config() {
+        let config: CommonConfig = serde_json::from_str(
+            r#"{"timeout": 10, "retry_limit": 2, "retry_backoff_ms": 100,
+                "prefetch_request": {"timeout": 60, "retry_limit": 5, "retry_jitter": true},
+                "metadata_request": {"connect_timeout": 1}}"#,
+        )
+        .unwrap();
+
+        let data = config.request_policy(BackendRequestClass::Data);
+        assert_eq!(data.timeout, 10);
+        assert_eq!(data.retry_limit, 2);
+        let prefetch = config.request_policy(BackendRequestClass::Prefetch);
+        assert_eq!(prefetch.timeout, 60);
+        assert_eq!(prefetch.retry_limit, 5);
+        assert_eq!(prefetch.retry_backoff_ms, 100);
+        assert!(prefetch.retry_jitter);
+        let metadata = config.request_policy(BackendRequestClass::Metadata);
+        assert_eq!(metadata.connect_timeout, 1);
+        assert_eq!(metadata.timeout, 10);
+    }
+
+    #[test]
+    fn test_request_policy_backoff() {
+        let mut policy = RequestPolicy {
+            retry_backoff_ms: 100,
+            retry_backoff_max_ms: 500,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+
+        policy.retry_jitter = true;
+        for retry in 0..4 {
+            let delay = policy.backoff(retry);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(500));
+        }
+
+        policy.retry_backoff_ms = 0;
+        assert_eq!(policy.backoff(1), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_request_class_guard() {
+        assert_eq!(current_request_class(), BackendRequestClass::Data);
+        {
+            let _guard = RequestClassGuard::enter(BackendRequestClass::Prefetch);
+            assert_eq!(current_request_class(), BackendRequestClass::Prefetch);
+            {
+                let _guard = RequestClassGuard::enter(BackendRequestClass::Metadata);
+                assert_eq!(current_request_class(), BackendRequestClass::Metadata);
+            }
+            assert_eq!(current_request_class(), BackendRequestClass::Prefetch);
+        }
+        assert_eq!(current_request_class(), BackendRequestClass::Data);
+    }
+
+    #[test]
+    fn test_errno_mapping() {
+        assert_eq!(http_status_category(401), BackendErrorCategory::Auth);
+        assert_eq!(http_status_category(404), BackendErrorCategory::NotFound);
+        assert_eq!(http_status_category(504), BackendErrorCategory::Timeout);
+        assert_eq!(http_status_category(500), BackendErrorCategory::Other);
+
+        let mapping = ErrnoMapping::default();
+        mapping.validate().unwrap();
+        assert_eq!(mapping.errno(BackendErrorCategory::Timeout), libc::EIO);
+
+        let mapping: ErrnoMapping =
+            serde_json::from_str(r#"{"timeout": "ETIMEDOUT", "auth": "EACCES"}"#).unwrap();
+        mapping.validate().unwrap();
+        assert_eq!(
+            mapping.errno(BackendErrorCategory::Timeout),
+            libc::ETIMEDOUT
+        );
+        assert_eq!(mapping.errno(BackendErrorCategory::Auth), libc::EACCES);
+        assert_eq!(mapping.errno(BackendErrorCategory::NotFound), libc::EIO);
+        assert_eq!(mapping.errno(BackendErrorCategory::Other), libc::EIO);
+        let err = mapping.convert(BackendError::Unsupported("test".to_string()));
+        assert_eq!(err.raw_os_error(), Some(libc::EIO));
+
+        let mapping: ErrnoMapping = serde_json::from_str(r#"{"timeout": "EBUSY"}"#).unwrap();
+        assert!(mapping.validate().is_err());
+    }
+
+    #[test]
+    fn test_async_read_parity() {
+        let data: Vec<u8> = (0..0x10000u32).map(|v| (v % 251) as u8).collect();
+        let metrics = BackendMetrics::new("test_async_read", "mock");
+        let reader: Arc<dyn BlobReader> = Arc::new(MockBackend::with_data(data, metrics.clone()));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .build()
+            .unwrap();
+        let bridge = BlobReaderBridge::new(reader.clone(), runtime.handle().clone());
+
+        for (offset, size, split) in [
+            (0u64, 0x10000usize, 0x1000usize),
+            (0x123, 0x4567, 0x1000),
+            (0x123, 0x4567, 0x10000),
+            (0xf000, 0x2000, 0x300),
+            (0x10000, 0x100, 0x10),
+        ]
+        .iter()
+        {
+            let mut sync_buf = vec![0u8; *size];
+            let mut async_buf = vec![0u8; *size];
+            let sync_cnt = reader.read(&mut sync_buf, *offset).unwrap();
+            let async_cnt = bridge.read_split(&mut async_buf, *offset, *split).unwrap();
+            assert_eq!(sync_cnt, async_cnt);
+            assert_eq!(sync_buf, async_buf);
+
+            let data = block_on(bridge.async_read(*offset, *size)).unwrap();
+            assert_eq!(data.len(), sync_cnt);
+            assert_eq!(&data[..], &sync_buf[..sync_cnt]);
+        }
+
+        let failing = MockBackend::with_data(vec![0u8; 0x1000], metrics.clone());
+        failing.failures.store(1, Ordering::Relaxed);
+        let bridge = BlobReaderBridge::new(Arc::new(failing), runtime.handle().clone());
+        let mut buf = vec![0u8; 0x1000];
+        assert!(bridge.read_split(&mut buf, 0, 0x100).is_err());
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_read_retry_by_request_class() {
+        let mut reader = MockBackend::new(BackendMetrics::new(
+            "test_read_retry_by_request_class",
+            "mock",
+        ));
+        reader.failures.store(2, Ordering::Relaxed);
+        reader.request_policy = Some(|class| RequestPolicy {
+            retry_limit: if class == BackendRequestClass::Prefetch {
+                2
+            } else {
+                0
+            },
+            retry_backoff_ms: 1,
+            ..Default::default()
+        });
+        let mut buf = vec![0u8; 0x100];
+
+        assert!(reader.read(&mut buf, 0).is_err());
+        assert_eq!(reader.failures.load(Ordering::Relaxed), 1);
+
+        reader.failures.store(2, Ordering::Relaxed);
+        let _guard = RequestClassGuard::enter(BackendRequestClass::Prefetch);
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 0x100);
+        assert_eq!(reader.failures.load(Ordering::Relaxed), 0);
+
+        reader.failures.store(3, Ordering::Relaxed);
+        assert!(reader.read(&mut buf, 0).is_err());
+        reader.metrics.release().unwrap();
     }
 }
@@ -5,18 +5,20 @@
 
 //! Storage backend driver to access blobs on Oss(Object Storage System).
 use std::io::{Error, Result};
+use std::net::Ipv4Addr;
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use hmac::{Hmac, Mac, NewMac};
-use nydus_utils::metrics::BackendMetrics;
+use nydus_utils::metrics::{BackendErrorCategory, BackendMetrics, BackendRequestClass};
 use reqwest::header::{HeaderMap, CONTENT_LENGTH};
 use reqwest::Method;
 use sha1::Sha1;
 
-use crate::backend::connection::{Connection, ConnectionError};
+use crate::backend::connection::{endpoint_url, Connection, ConnectionError, UNIX_SCHEME};
 use crate::backend::{
     default_http_scheme, BackendError, BackendResult, BlobBackend, BlobReader, CommonConfig,
+    RequestPolicy,
 };
 
 const HEADER_DATE: &str = "Date";
@@ -35,6 +37,18 @@ pub enum OssError {
     Response(String),
 }
 
+impl OssError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            OssError::Auth(_) => BackendErrorCategory::Auth,
+            OssError::Request(e) => e.category(),
+            OssError::Transport(e) if e.is_timeout() => BackendErrorCategory::Timeout,
+            _ => BackendErrorCategory::Other,
+        }
+    }
+}
+
 impl From<OssError> for BackendError {
     fn from(error: OssError) -> Self {
         BackendError::Oss(error)
@@ -66,7 +80,8 @@ struct OssState {
     object_prefix: String,
     endpoint: String,
     bucket_name: String,
-    retry_limit: u8,
+    // Timeouts and retry policy of requests.
+    common_config: CommonConfig,
 }
 
 impl OssState {
@@ -74,12 +89,30 @@ impl OssState {
         format!("/{}/{}{}", self.bucket_name, object_key, query_str)
     }
 
+    // The bucket can't be a subdomain of IP addresses or Unix socket paths, so put it in the path.
+    fn is_path_style(&self) -> bool {
+        if self.scheme == UNIX_SCHEME || self.endpoint.starts_with('[') {
+            return true;
+        }
+        let host = self.endpoint.split(':').next().unwrap_or_default();
+        host.parse::<Ipv4Addr>().is_ok()
+    }
+
     fn url(&self, object_key: &str, query: &[&str]) -> (String, String) {
         let object_key = &format!("{}{}", self.object_prefix, object_key);
-        let url = format!(
-            "{}://{}.{}/{}",
-            self.scheme, self.bucket_name, self.endpoint, object_key
-        );
+        let url = if self.is_path_style() {
+            format!(
+                "{}/{}/{}",
+                endpoint_url(&self.scheme, &self.endpoint),
+                self.bucket_name,
+                object_key
+            )
+        } else {
+            format!(
+                "{}://{}.{}/{}",
+                self.scheme, self.bucket_name, self.endpoint, object_key
+            )
+        };
 
         if query.is_empty() {
             (self.resource(object_key, ""), url)
@@ -148,6 +181,12 @@ struct OssReader {
     metrics: Arc<BackendMetrics>,
 }
 
+impl Drop for OssReader {
+    fn drop(&mut self) {
+        self.connection.release_blob(&self.blob_id);
+    }
+}
+
 impl BlobReader for OssReader {
     fn blob_size(&self) -> BackendResult<u64> {
         let (resource, url) = self.state.url(&self.blob_id, &[]);
@@ -191,6 +230,7 @@ impl BlobReader for OssReader {
             .sign(Method::GET, &mut headers, resource.as_str())
             .map_err(OssError::Auth)?;
 
+        let _permit = self.connection.acquire(&self.blob_id, &self.metrics);
         // Safe because the the call() is a synchronous operation.
         let mut resp = self
             .connection
@@ -220,7 +260,11 @@ impl BlobReader for OssReader {
     }
 
     fn retry_limit(&self) -> u8 {
-        self.state.retry_limit
+        self.state.common_config.retry_limit
+    }
+
+    fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        self.state.common_config.request_policy(class)
     }
 }
 
@@ -238,7 +282,6 @@ impl Oss {
     pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Oss> {
         let common_config: CommonConfig =
             serde_json::from_value(config.clone()).map_err(|e| einval!(e))?;
-        let retry_limit = common_config.retry_limit;
         let connection = Connection::new(&common_config)?;
         let oss_config: OssConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
         let state = Arc::new(OssState {
@@ -248,7 +291,7 @@ impl Oss {
             access_key_id: oss_config.access_key_id,
             access_key_secret: oss_config.access_key_secret,
             bucket_name: oss_config.bucket_name,
-            retry_limit,
+            common_config,
         });
         let metrics = id.map(|i| BackendMetrics::new(i, "oss"));
 
@@ -274,6 +317,7 @@ impl BlobBackend for Oss {
 
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
         if let Some(metrics) = self.metrics.as_ref() {
+            self.connection.open_blob(blob_id);
             Ok(Arc::new(OssReader {
                 blob_id: blob_id.to_string(),
                 state: self.state.clone(),
@@ -301,6 +345,45 @@ mod tests {
     use super::*;
     use serde_json::Value;
 
+    #[test]
+    fn test_oss_path_style_url() {
+        let mut state = OssState {
+            access_key_id: "key".to_string(),
+            access_key_secret: "secret".to_string(),
+            scheme: "http".to_string(),
+            object_prefix: "nydus/".to_string(),
+            endpoint: "[::1]:9000".to_string(),
+            bucket_name: "images".to_string(),
+            common_config: CommonConfig {
+                retry_limit: 5,
+                ..Default::default()
+            },
+        };
+        let (resource, url) = state.url("obj_key", &[]);
+        assert_eq!(resource, "/images/nydus/obj_key");
+        assert_eq!(url, "http://[::1]:9000/images/nydus/obj_key");
+
+        state.endpoint = "10.0.0.1:9000".to_string();
+        let (_, url) = state.url("obj_key", &[]);
+        assert_eq!(url, "http://10.0.0.1:9000/images/nydus/obj_key");
+
+        state.scheme = "http+unix".to_string();
+        state.endpoint = "/run/oss.sock".to_string();
+        let (_, url) = state.url("obj_key", &["a=b"]);
+        assert_eq!(
+            url,
+            "http+unix://%2Frun%2Foss.sock/images/nydus/obj_key?a=b"
+        );
+
+        state.scheme = "https".to_string();
+        state.endpoint = "oss-cn-hangzhou.aliyuncs.com".to_string();
+        let (_, url) = state.url("obj_key", &[]);
+        assert_eq!(
+            url,
+            "https://images.oss-cn-hangzhou.aliyuncs.com/nydus/obj_key"
+        );
+    }
+
     #[test]
     fn test_oss_state() {
         let state = OssState {
@@ -310,7 +393,10 @@ mod tests {
             object_prefix: "nydus".to_string(),
             endpoint: "oss".to_string(),
             bucket_name: "images".to_string(),
-            retry_limit: 5,
+            common_config: CommonConfig {
+                retry_limit: 5,
+                ..Default::default()
+            },
         };
 
         assert_eq!(
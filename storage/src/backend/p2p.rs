@@ -0,0 +1,512 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to fetch blob data from a cluster of peer caches.
+//!
+//! Blob data is divided into chunks of `chunk_size` bytes, and each chunk is fetched from the
+//! peer selected by consistent hashing on the blob id and chunk index. So a chunk is always
+//! served by the same peer across nodes, and only a small portion of chunks are remapped when
+//! peers join or leave. Requests fall back to the origin backend if the selected peer fails.
+//!
+//! Membership of peers may be updated at runtime by rewriting `peers_file`, which is reloaded
+//! when its modification time changes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use arc_swap::ArcSwap;
+use nydus_utils::digest::{Algorithm, RafsDigest};
+use nydus_utils::metrics::BackendMetrics;
+use serde_json::Value;
+
+use crate::backend::{BackendResult, BlobBackend, BlobReader};
+use crate::factory::{BackendConfig, BlobFactory};
+
+fn default_chunk_size() -> u64 {
+    0x10_0000
+}
+
+fn default_virtual_nodes() -> u32 {
+    100
+}
+
+fn default_reload_secs() -> u64 {
+    10
+}
+
+/// Configuration information for a peer.
+#[derive(Clone, Deserialize, Serialize)]
+struct PeerConfig {
+    /// Unique id of the peer, which decides position of the peer on the hash ring.
+    id: String,
+    #[serde(flatten)]
+    backend: BackendConfig,
+}
+
+/// Configuration information for P2P storage backend.
+#[derive(Clone, Deserialize, Serialize)]
+struct P2pConfig {
+    /// Backend to fall back to if the selected peer fails.
+    origin: BackendConfig,
+    /// Peers to fetch blob data from.
+    #[serde(default)]
+    peers: Vec<PeerConfig>,
+    /// File containing a JSON array of peers, overrides `peers` if not empty.
+    #[serde(default)]
+    peers_file: String,
+    /// Minimal interval to check modification of `peers_file`, in seconds.
+    #[serde(default = "default_reload_secs")]
+    reload_secs: u64,
+    /// Size of data chunks distributed among peers.
+    #[serde(default = "default_chunk_size")]
+    chunk_size: u64,
+    /// Number of virtual nodes on the hash ring for each peer.
+    #[serde(default = "default_virtual_nodes")]
+    virtual_nodes: u32,
+    /// Seconds to skip a peer after it fails, 0 means never skip.
+    #[serde(default)]
+    skip_secs: u64,
+}
+
+fn hash_key(key: &str) -> u64 {
+    let digest = RafsDigest::from_buf(key.as_bytes(), Algorithm::Blake3);
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&digest.data[..8]);
+    u64::from_le_bytes(buf)
+}
+
+struct Peer {
+    id: String,
+    config: BackendConfig,
+    backend: Arc<dyn BlobBackend>,
+    // Readers of blobs, created on demand.
+    readers: Mutex<HashMap<String, Arc<dyn BlobReader>>>,
+    skip_duration: Duration,
+    // The peer is skipped until the instant after a failure.
+    skip_until: Mutex<Option<Instant>>,
+}
+
+impl Peer {
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        let mut readers = self.readers.lock().unwrap();
+        if let Some(reader) = readers.get(blob_id) {
+            return Ok(reader.clone());
+        }
+        let reader = self.backend.get_reader(blob_id)?;
+        readers.insert(blob_id.to_string(), reader.clone());
+        Ok(reader)
+    }
+
+    fn is_skipped(&self) -> bool {
+        match *self.skip_until.lock().unwrap() {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    fn set_failed(&self) {
+        if self.skip_duration.as_secs() > 0 {
+            *self.skip_until.lock().unwrap() = Some(Instant::now() + self.skip_duration);
+        }
+    }
+}
+
+/// Consistent hash ring of peers.
+#[derive(Default)]
+struct PeerRing {
+    // Virtual nodes sorted by hash value, with index into `peers`.
+    nodes: Vec<(u64, usize)>,
+    peers: Vec<Arc<Peer>>,
+}
+
+impl PeerRing {
+    fn new(peers: Vec<Arc<Peer>>, virtual_nodes: u32) -> Self {
+        let mut nodes = Vec::with_capacity(peers.len() * virtual_nodes as usize);
+        for (idx, peer) in peers.iter().enumerate() {
+            for vnode in 0..virtual_nodes {
+                nodes.push((hash_key(&format!("{}#{}", peer.id, vnode)), idx));
+            }
+        }
+        nodes.sort_unstable();
+
+        PeerRing { nodes, peers }
+    }
+
+    /// Get the first peer clockwise from `hash` on the ring.
+    fn lookup(&self, hash: u64) -> Option<&Arc<Peer>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let idx = match self.nodes.binary_search_by_key(&hash, |(h, _)| *h) {
+            Ok(idx) => idx,
+            Err(idx) if idx == self.nodes.len() => 0,
+            Err(idx) => idx,
+        };
+        Some(&self.peers[self.nodes[idx].1])
+    }
+}
+
+// State of `peers_file` to detect modification.
+struct PeersFileState {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+/// Peers shared by all blobs of the backend.
+struct PeerGroup {
+    id: String,
+    ring: ArcSwap<PeerRing>,
+    peers_file: Option<Mutex<PeersFileState>>,
+    reload_interval: Duration,
+    virtual_nodes: u32,
+    skip_duration: Duration,
+}
+
+impl PeerGroup {
+    fn new(config: &P2pConfig, id: &str) -> Result<Self> {
+        let mut group = PeerGroup {
+            id: id.to_string(),
+            ring: ArcSwap::new(Arc::new(PeerRing::default())),
+            peers_file: None,
+            reload_interval: Duration::from_secs(config.reload_secs),
+            virtual_nodes: std::cmp::max(config.virtual_nodes, 1),
+            skip_duration: Duration::from_secs(config.skip_secs),
+        };
+
+        if config.peers_file.is_empty() {
+            group.update(config.peers.clone())?;
+        } else {
+            let path = PathBuf::from(&config.peers_file);
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            group.update(Self::load_peers(&path)?)?;
+            group.peers_file = Some(Mutex::new(PeersFileState {
+                path,
+                modified,
+                last_check: Instant::now(),
+            }));
+        }
+
+        Ok(group)
+    }
+
+    fn load_peers(path: &Path) -> Result<Vec<PeerConfig>> {
+        let data = fs::read(path)?;
+        serde_json::from_slice(&data)
+            .map_err(|e| einval!(format!("invalid peers file {:?}, {}", path, e)))
+    }
+
+    /// Rebuild the hash ring with `peers`, reusing existing peers with the same configuration.
+    fn update(&self, peers: Vec<PeerConfig>) -> Result<()> {
+        let current = self.ring.load_full();
+        let mut result = Vec::with_capacity(peers.len());
+
+        for config in peers {
+            if config.backend.backend_type == "p2p" {
+                return Err(einval!("p2p backend can't be nested"));
+            }
+            if result.iter().any(|p: &Arc<Peer>| p.id == config.id) {
+                return Err(einval!(format!("duplicated peer id {}", config.id)));
+            }
+            let existing = current
+                .peers
+                .iter()
+                .find(|p| p.id == config.id && p.config == config.backend);
+            let peer = match existing {
+                Some(p) => p.clone(),
+                None => {
+                    let backend = BlobFactory::new_backend(
+                        config.backend.clone(),
+                        &format!("{}-peer-{}", self.id, config.id),
+                    )?;
+                    Arc::new(Peer {
+                        id: config.id,
+                        config: config.backend,
+                        backend,
+                        readers: Mutex::new(HashMap::new()),
+                        skip_duration: self.skip_duration,
+                        skip_until: Mutex::new(None),
+                    })
+                }
+            };
+            result.push(peer);
+        }
+
+        info!(
+            "p2p backend {} updates peers to {:?}",
+            self.id,
+            result.iter().map(|p| p.id.as_str()).collect::<Vec<_>>()
+        );
+        self.ring
+            .store(Arc::new(PeerRing::new(result, self.virtual_nodes)));
+
+        Ok(())
+    }
+
+    /// Reload peers if `peers_file` has been modified since the last check.
+    fn reload_if_needed(&self) {
+        let mut state = match self.peers_file.as_ref().map(|s| s.try_lock()) {
+            Some(Ok(state)) => state,
+            _ => return,
+        };
+        if state.last_check.elapsed() < self.reload_interval {
+            return;
+        }
+        state.last_check = Instant::now();
+
+        let modified = fs::metadata(&state.path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == state.modified {
+            return;
+        }
+        match Self::load_peers(&state.path).and_then(|peers| self.update(peers)) {
+            Ok(_) => state.modified = modified,
+            Err(e) => warn!(
+                "p2p backend {} failed to reload peers from {:?}, {}",
+                self.id, state.path, e
+            ),
+        }
+    }
+}
+
+struct P2pEntry {
+    blob_id: String,
+    group: Arc<PeerGroup>,
+    origin: Arc<dyn BlobReader>,
+    chunk_size: u64,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl P2pEntry {
+    fn read_chunk(
+        &self,
+        ring: &PeerRing,
+        index: u64,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> BackendResult<usize> {
+        if let Some(peer) = ring.lookup(hash_key(&format!("{}#{}", self.blob_id, index))) {
+            if !peer.is_skipped() {
+                match peer
+                    .get_reader(&self.blob_id)
+                    .and_then(|r| r.read(buf, offset))
+                {
+                    Ok(size) if size == buf.len() => return Ok(size),
+                    // The peer may have incomplete data of the blob, fetch the rest from origin.
+                    Ok(size) => {
+                        let cnt = self.origin.read(&mut buf[size..], offset + size as u64)?;
+                        return Ok(size + cnt);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "peer {} failed to read blob {} at offset {}, {:?}",
+                            peer.id, self.blob_id, offset, e
+                        );
+                        peer.set_failed();
+                    }
+                }
+            }
+        }
+
+        self.origin.read(buf, offset)
+    }
+}
+
+impl BlobReader for P2pEntry {
+    fn blob_size(&self) -> BackendResult<u64> {
+        self.origin.blob_size()
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        self.group.reload_if_needed();
+        let ring = self.group.ring.load_full();
+        let mut total = 0;
+
+        // Split the request at chunk boundaries, so each chunk is fetched from its own peer.
+        while total < buf.len() {
+            let pos = offset + total as u64;
+            let index = pos / self.chunk_size;
+            let size = std::cmp::min(
+                buf.len() - total,
+                ((index + 1) * self.chunk_size - pos) as usize,
+            );
+            let cnt = self.read_chunk(&ring, index, &mut buf[total..total + size], pos)?;
+            total += cnt;
+            if cnt < size {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn prefetch_blob_data_range(&self, ra_offset: u32, ra_size: u32) -> BackendResult<()> {
+        self.origin.prefetch_blob_data_range(ra_offset, ra_size)
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        self.origin.stop_data_prefetch()
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+}
+
+/// Storage backend to fetch blob data from peers selected by consistent hashing.
+pub struct P2pBackend {
+    origin: Arc<dyn BlobBackend>,
+    group: Arc<PeerGroup>,
+    chunk_size: u64,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl P2pBackend {
+    pub fn new(config: Value, id: Option<&str>) -> Result<P2pBackend> {
+        let config: P2pConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let id = id.ok_or_else(|| einval!("p2p backend requires blob_id"))?;
+        if config.chunk_size == 0 {
+            return Err(einval!("p2p backend requires non-zero chunk_size"));
+        }
+        if config.origin.backend_type == "p2p" {
+            return Err(einval!("p2p backend can't be nested"));
+        }
+
+        let group = PeerGroup::new(&config, id)?;
+        let origin = BlobFactory::new_backend(config.origin, &format!("{}-origin", id))?;
+
+        Ok(P2pBackend {
+            origin,
+            group: Arc::new(group),
+            chunk_size: config.chunk_size,
+            metrics: BackendMetrics::new(id, "p2p"),
+        })
+    }
+}
+
+impl BlobBackend for P2pBackend {
+    fn shutdown(&self) {
+        self.origin.shutdown();
+        for peer in self.group.ring.load().peers.iter() {
+            peer.backend.shutdown();
+        }
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        Ok(Arc::new(P2pEntry {
+            blob_id: blob_id.to_string(),
+            group: self.group.clone(),
+            origin: self.origin.get_reader(blob_id)?,
+            chunk_size: self.chunk_size,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+impl Drop for P2pBackend {
+    fn drop(&mut self) {
+        self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+    }
+}
+
+#[cfg(all(test, feature = "backend-localfs"))]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn peer_config(id: &str, dir: &str) -> Value {
+        serde_json::json!({"id": id, "type": "localfs", "config": {"dir": dir}})
+    }
+
+    #[test]
+    fn test_peer_ring() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path().to_str().unwrap();
+        let config = serde_json::json!({
+            "origin": {"type": "localfs", "config": {"dir": dir}},
+            "peers": [peer_config("p1", dir), peer_config("p2", dir), peer_config("p3", dir)],
+        });
+        let config: P2pConfig = serde_json::from_value(config).unwrap();
+        assert_eq!(config.chunk_size, 0x10_0000);
+        assert_eq!(config.virtual_nodes, 100);
+        let group = PeerGroup::new(&config, "test_peer_ring").unwrap();
+
+        let lookup = |group: &PeerGroup, index: u64| {
+            let ring = group.ring.load();
+            ring.lookup(hash_key(&format!("blob#{}", index)))
+                .unwrap()
+                .id
+                .clone()
+        };
+        let before = (0..1000).map(|i| lookup(&group, i)).collect::<Vec<_>>();
+        for id in ["p1", "p2", "p3"].iter() {
+            assert!(before.iter().filter(|p| p == id).count() > 100);
+        }
+
+        // Only chunks of the removed peer are remapped.
+        let p1 = group.ring.load().peers[0].clone();
+        let mut peers = config.peers.clone();
+        peers.remove(1);
+        group.update(peers).unwrap();
+        assert!(Arc::ptr_eq(&p1, &group.ring.load().peers[0]));
+        for (idx, id) in before.iter().enumerate() {
+            let after = lookup(&group, idx as u64);
+            if id != "p2" {
+                assert_eq!(&after, id);
+            } else {
+                assert_ne!(after, "p2");
+            }
+        }
+
+        let mut peers = config.peers.clone();
+        peers.push(config.peers[0].clone());
+        assert!(group.update(peers).is_err());
+    }
+
+    #[test]
+    fn test_p2p_backend_fallback() {
+        let origin_dir = TempDir::new().unwrap();
+        let peer_dir = TempDir::new().unwrap();
+        let data = (0..0x3000u32).map(|v| (v % 251) as u8).collect::<Vec<_>>();
+        fs::write(origin_dir.as_path().join("blob1"), &data).unwrap();
+        fs::write(peer_dir.as_path().join("blob1"), &data[..0x1000]).unwrap();
+
+        let peers_file = origin_dir.as_path().join("peers.json");
+        let peers = serde_json::json!([peer_config("p1", "/nonexistent/blobs")]);
+        fs::write(&peers_file, peers.to_string()).unwrap();
+        let config = serde_json::json!({
+            "origin": {"type": "localfs", "config": {"dir": origin_dir.as_path().to_str().unwrap()}},
+            "peers_file": peers_file.to_str().unwrap(),
+            "reload_secs": 0,
+            "chunk_size": 0x1000,
+        });
+        let backend = P2pBackend::new(config, Some("test_p2p_fallback")).unwrap();
+        let reader = backend.get_reader("blob1").unwrap();
+        let mut buf = vec![0u8; 0x2800];
+        assert_eq!(reader.read(&mut buf, 0x800).unwrap(), 0x2800);
+        assert_eq!(buf, &data[0x800..]);
+        assert_eq!(reader.blob_size().unwrap(), 0x3000);
+
+        // The peer only has the first chunk, data of other chunks come from the origin.
+        let peers = serde_json::json!([peer_config("p2", peer_dir.as_path().to_str().unwrap())]);
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&peers_file, peers.to_string()).unwrap();
+        let mut buf = vec![0u8; 0x3000];
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 0x3000);
+        assert_eq!(buf, data);
+        assert_eq!(backend.group.ring.load().peers[0].id, "p2");
+
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&peers_file, b"invalid").unwrap();
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 0x3000);
+        assert_eq!(backend.group.ring.load().peers[0].id, "p2");
+    }
+}
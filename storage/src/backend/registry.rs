@@ -4,21 +4,25 @@
 
 //! Storage backend driver to access blobs on container image registry.
 use std::collections::HashMap;
+use std::fs;
 use std::io::{Error, Read, Result};
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use nydus_utils::metrics::BackendMetrics;
+use nydus_utils::metrics::{BackendErrorCategory, BackendMetrics, BackendRequestClass};
 use reqwest::blocking::Response;
 pub use reqwest::header::HeaderMap;
-use reqwest::header::{HeaderValue, CONTENT_LENGTH};
+use reqwest::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
 use reqwest::{Method, StatusCode};
 use url::{ParseError, Url};
 
 use crate::backend::connection::{
-    is_success_status, respond, Connection, ConnectionError, ReqBody,
+    endpoint_url, is_success_status, respond, Connection, ConnectionError, Progress, ReqBody,
 };
 use crate::backend::{
     default_http_scheme, BackendError, BackendResult, BlobBackend, BlobReader, CommonConfig,
+    RequestPolicy,
 };
 
 const REGISTRY_CLIENT_ID: &str = "nydus-registry-client";
@@ -38,6 +42,18 @@ pub enum RegistryError {
     Transport(reqwest::Error),
 }
 
+impl RegistryError {
+    /// Get category of the error, to tell causes of backend failures apart.
+    pub fn category(&self) -> BackendErrorCategory {
+        match self {
+            RegistryError::Auth(_) => BackendErrorCategory::Auth,
+            RegistryError::Request(e) => e.category(),
+            RegistryError::Transport(e) if e.is_timeout() => BackendErrorCategory::Timeout,
+            _ => BackendErrorCategory::Other,
+        }
+    }
+}
+
 impl From<RegistryError> for BackendError {
     fn from(error: RegistryError) -> Self {
         BackendError::Registry(error)
@@ -68,6 +84,11 @@ impl Cache {
             *cached_guard = current;
         }
     }
+
+    fn replace(&self, current: String) {
+        let mut cached_guard = self.0.write().unwrap();
+        *cached_guard = current;
+    }
 }
 
 #[derive(Default)]
@@ -108,12 +129,43 @@ struct RegistryConfig {
     // to authorize registry requests.
     #[serde(default)]
     registry_token: Option<String>,
+    // File containing `auth` and/or `registry_token`, or a docker `config.json`, overrides the
+    // two fields above if not empty. It's reloaded when modified to rotate credentials.
+    #[serde(default)]
+    auth_file: String,
+    // Minimal interval to check modification of `auth_file`, in seconds.
+    #[serde(default = "default_auth_reload_secs")]
+    auth_reload_secs: u64,
     #[serde(default)]
     blob_url_scheme: String,
     #[serde(default)]
     blob_redirected_host: String,
 }
 
+fn default_auth_reload_secs() -> u64 {
+    10
+}
+
+// Registry credentials loaded from `auth_file`.
+#[derive(Deserialize)]
+struct CredentialsFile {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    registry_token: Option<String>,
+    // Credentials keyed by registry host, in the format of docker `config.json`.
+    #[serde(default)]
+    auths: HashMap<String, DockerAuthEntry>,
+}
+
+#[derive(Deserialize)]
+struct DockerAuthEntry {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    registrytoken: Option<String>,
+}
+
 #[derive(Clone, Deserialize)]
 struct TokenResponse {
     token: String,
@@ -138,18 +190,44 @@ enum Auth {
     Bearer(BearerAuth),
 }
 
+#[derive(Default)]
+struct RegistryCredentials {
+    // Base64 encoded registry auth
+    auth: Option<String>,
+    username: String,
+    password: String,
+}
+
+impl RegistryCredentials {
+    fn new(auth: Option<String>) -> Result<Self> {
+        let (username, password) = Registry::get_authorization_info(&auth)?;
+        Ok(RegistryCredentials {
+            auth,
+            username,
+            password,
+        })
+    }
+}
+
+// State of `auth_file` to detect credential rotation.
+struct AuthFileState {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
 struct RegistryState {
     // HTTP scheme like: https, http
     scheme: String,
     host: String,
     // Image repo name like: library/ubuntu
     repo: String,
-    // Base64 encoded registry auth
-    auth: Option<String>,
-    username: String,
-    password: String,
-    // Retry limit for read operation
-    retry_limit: u8,
+    // Use RwLock here to rotate credentials on the fly.
+    credentials: RwLock<RegistryCredentials>,
+    auth_file: Option<Mutex<AuthFileState>>,
+    auth_reload_interval: Duration,
+    // Timeouts and retry policy of requests
+    common_config: CommonConfig,
     // Scheme specified for blob server
     blob_url_scheme: String,
     // Replace registry redirected url host with the given host
@@ -172,25 +250,109 @@ impl RegistryState {
         } else {
             format!("/v2/{}{}?{}", self.repo, path, query.join("&"))
         };
-        let url = format!("{}://{}", self.scheme, self.host.as_str());
+        let url = endpoint_url(&self.scheme, &self.host);
         let url = Url::parse(url.as_str())?;
         let url = url.join(path.as_str())?;
 
         Ok(url.to_string())
     }
 
+    fn auth(&self) -> Option<String> {
+        self.credentials.read().unwrap().auth.clone()
+    }
+
+    /// Load `auth` and `registry_token` from a credentials file.
+    fn load_credentials(path: &Path, host: &str) -> Result<(Option<String>, Option<String>)> {
+        let data = fs::read(path)?;
+        let file: CredentialsFile = serde_json::from_slice(&data)
+            .map_err(|e| einval!(format!("invalid credentials file {:?}, {}", path, e)))?;
+        if file.auths.is_empty() {
+            return Ok((trim(file.auth), trim(file.registry_token)));
+        }
+
+        // Keys of docker `config.json` may contain the scheme, e.g. `https://index.docker.io/v1/`.
+        let entry = file
+            .auths
+            .iter()
+            .find(|(k, _)| {
+                let k = k.splitn(2, "://").last().unwrap_or_default();
+                k.split('/').next() == Some(host)
+            })
+            .map(|(_, v)| v)
+            .ok_or_else(|| {
+                enoent!(format!(
+                    "no credentials for {} in credentials file {:?}",
+                    host, path
+                ))
+            })?;
+
+        Ok((trim(entry.auth.clone()), trim(entry.registrytoken.clone())))
+    }
+
+    /// Replace credentials, and drop the cached authorization header to authenticate again.
+    fn update_credentials(
+        &self,
+        auth: Option<String>,
+        registry_token: Option<String>,
+    ) -> Result<()> {
+        let credentials = RegistryCredentials::new(auth)?;
+        *self.credentials.write().unwrap() = credentials;
+        match registry_token {
+            Some(token) => self.cached_auth.replace(format!("Bearer {}", token)),
+            None => self.cached_auth.replace(String::new()),
+        }
+
+        Ok(())
+    }
+
+    /// Reload credentials if `auth_file` has been modified since the last check.
+    fn reload_auth_if_needed(&self) {
+        let mut state = match self.auth_file.as_ref().map(|s| s.try_lock()) {
+            Some(Ok(state)) => state,
+            _ => return,
+        };
+        if state.last_check.elapsed() < self.auth_reload_interval {
+            return;
+        }
+        state.last_check = Instant::now();
+
+        let modified = fs::metadata(&state.path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == state.modified {
+            return;
+        }
+        match Self::load_credentials(&state.path, &self.host)
+            .and_then(|(auth, token)| self.update_credentials(auth, token))
+        {
+            Ok(_) => {
+                state.modified = modified;
+                info!(
+                    "registry backend for {}/{} rotated credentials from {:?}",
+                    self.host, self.repo, state.path
+                );
+            }
+            Err(e) => warn!(
+                "registry backend for {}/{} failed to reload credentials from {:?}, {}",
+                self.host, self.repo, state.path, e
+            ),
+        }
+    }
+
     /// Request registry authentication server to get bearer token
     fn get_token(&self, auth: BearerAuth, connection: &Arc<Connection>) -> Result<String> {
         // The information needed for getting token needs to be placed both in
         // the query and in the body to be compatible with different registry
         // implementations, which have been tested on these platforms:
         // docker hub, harbor, github ghcr, aliyun acr.
+        let (username, password) = {
+            let credentials = self.credentials.read().unwrap();
+            (credentials.username.clone(), credentials.password.clone())
+        };
         let query = vec![
             ("service", auth.service.as_str()),
             ("scope", auth.scope.as_str()),
             ("grant_type", "password"),
-            ("username", self.username.as_str()),
-            ("password", self.password.as_str()),
+            ("username", username.as_str()),
+            ("password", password.as_str()),
             ("client_id", REGISTRY_CLIENT_ID),
         ];
 
@@ -226,8 +388,7 @@ impl RegistryState {
     fn get_auth_header(&self, auth: Auth, connection: &Arc<Connection>) -> Result<String> {
         match auth {
             Auth::Basic(_) => self
-                .auth
-                .as_ref()
+                .auth()
                 .map(|auth| format!("Basic {}", auth))
                 .ok_or_else(|| einval!("invalid auth config")),
             Auth::Bearer(auth) => {
@@ -237,69 +398,6 @@ impl RegistryState {
         }
     }
 
-    /// Parse `www-authenticate` response header respond from registry server
-    /// The header format like: `Bearer realm="https://auth.my-registry.com/token",service="my-registry.com",scope="repository:test/repo:pull,push"`
-    fn parse_auth(source: &HeaderValue, auth: &Option<String>) -> Option<Auth> {
-        let source = source.to_str().unwrap();
-        let source: Vec<&str> = source.splitn(2, ' ').collect();
-        if source.len() < 2 {
-            return None;
-        }
-        let scheme = source[0].trim();
-        let pairs = source[1].trim();
-        let pairs = pairs.split("\",");
-        let mut paras = HashMap::new();
-        for pair in pairs {
-            let pair: Vec<&str> = pair.trim().split('=').collect();
-            if pair.len() < 2 {
-                return None;
-            }
-            let key = pair[0].trim();
-            let value = pair[1].trim().trim_matches('"');
-            paras.insert(key, value);
-        }
-
-        match scheme {
-            "Basic" => {
-                let realm = if let Some(realm) = paras.get("realm") {
-                    (*realm).to_string()
-                } else {
-                    String::new()
-                };
-                Some(Auth::Basic(BasicAuth { realm }))
-            }
-            "Bearer" => {
-                if paras.get("realm").is_none()
-                    || paras.get("service").is_none()
-                    || paras.get("scope").is_none()
-                {
-                    return None;
-                }
-
-                let header = auth
-                    .as_ref()
-                    .map(|auth| HeaderValue::from_str(&format!("Basic {}", auth)).unwrap());
-
-                Some(Auth::Bearer(BearerAuth {
-                    realm: (*paras.get("realm").unwrap()).to_string(),
-                    service: (*paras.get("service").unwrap()).to_string(),
-                    scope: (*paras.get("scope").unwrap()).to_string(),
-                    header,
-                }))
-            }
-            _ => None,
-        }
-    }
-}
-
-struct RegistryReader {
-    blob_id: String,
-    connection: Arc<Connection>,
-    state: Arc<RegistryState>,
-    metrics: Arc<BackendMetrics>,
-}
-
-impl RegistryReader {
     /// Request registry server with `authorization` header
     ///
     /// Bearer token authenticate workflow:
@@ -328,15 +426,18 @@ impl RegistryReader {
     /// Response: status: 200 Ok
     fn request<R: Read + Send + 'static>(
         &self,
+        connection: &Arc<Connection>,
         method: Method,
         url: &str,
         data: Option<ReqBody<R>>,
         mut headers: HeaderMap,
         catch_status: bool,
     ) -> RegistryResult<Response> {
+        self.reload_auth_if_needed();
+
         // Try get authorization header from cache for this request
         let mut last_cached_auth = String::new();
-        let cached_auth = self.state.cached_auth.get();
+        let cached_auth = self.cached_auth.get();
         if !cached_auth.is_empty() {
             last_cached_auth = cached_auth.clone();
             headers.insert(
@@ -345,43 +446,47 @@ impl RegistryReader {
             );
         }
 
-        // For upload request with payload, the auth header should be cached
-        // after create_upload(), so we can request registry server directly
-        if let Some(data) = data {
-            return self
-                .connection
-                .call(method, url, None, Some(data), headers, catch_status)
+        // Streamed payload can't be sent twice, the auth header should be cached after
+        // starting the upload, so we can request registry server directly
+        if let Some(ReqBody::Read(..)) = data {
+            return connection
+                .call(method, url, None, data, headers, catch_status)
                 .map_err(RegistryError::Request);
         }
 
         // Try to request registry server with `authorization` header
-        let resp = self
-            .connection
-            .call::<&[u8]>(method.clone(), url, None, None, headers.clone(), false)
+        let resp = connection
+            .call(
+                method.clone(),
+                url,
+                None,
+                data.as_ref().and_then(|d| d.try_clone()),
+                headers.clone(),
+                false,
+            )
             .map_err(RegistryError::Request)?;
         if resp.status() == StatusCode::UNAUTHORIZED {
             if let Some(resp_auth_header) = resp.headers().get(HEADER_WWW_AUTHENTICATE) {
                 // Get token from registry authorization server
-                if let Some(auth) = RegistryState::parse_auth(resp_auth_header, &self.state.auth) {
+                let auth = self.auth();
+                if let Some(auth) = RegistryState::parse_auth(resp_auth_header, &auth) {
                     let auth_header = self
-                        .state
-                        .get_auth_header(auth, &self.connection)
-                        .map_err(|e| RegistryError::Common(e.to_string()))?;
+                        .get_auth_header(auth, connection)
+                        .map_err(|e| RegistryError::Auth(e.to_string()))?;
                     headers.insert(
                         HEADER_AUTHORIZATION,
                         HeaderValue::from_str(auth_header.as_str()).unwrap(),
                     );
 
                     // Try to request registry server with `authorization` header again
-                    let resp = self
-                        .connection
+                    let resp = connection
                         .call(method, url, None, data, headers, catch_status)
                         .map_err(RegistryError::Request)?;
 
                     let status = resp.status();
                     if is_success_status(status) {
                         // Cache authorization header for next request
-                        self.state.cached_auth.set(&last_cached_auth, auth_header)
+                        self.cached_auth.set(&last_cached_auth, auth_header)
                     }
                     return respond(resp, catch_status).map_err(RegistryError::Request);
                 }
@@ -391,6 +496,69 @@ impl RegistryReader {
         respond(resp, catch_status).map_err(RegistryError::Request)
     }
 
+    /// Parse `www-authenticate` response header respond from registry server
+    /// The header format like: `Bearer realm="https://auth.my-registry.com/token",service="my-registry.com",scope="repository:test/repo:pull,push"`
+    fn parse_auth(source: &HeaderValue, auth: &Option<String>) -> Option<Auth> {
+        let source = source.to_str().unwrap();
+        let source: Vec<&str> = source.splitn(2, ' ').collect();
+        if source.len() < 2 {
+            return None;
+        }
+        let scheme = source[0].trim();
+        let pairs = source[1].trim();
+        let pairs = pairs.split("\",");
+        let mut paras = HashMap::new();
+        for pair in pairs {
+            let pair: Vec<&str> = pair.trim().split('=').collect();
+            if pair.len() < 2 {
+                return None;
+            }
+            let key = pair[0].trim();
+            let value = pair[1].trim().trim_matches('"');
+            paras.insert(key, value);
+        }
+
+        match scheme {
+            "Basic" => {
+                let realm = if let Some(realm) = paras.get("realm") {
+                    (*realm).to_string()
+                } else {
+                    String::new()
+                };
+                Some(Auth::Basic(BasicAuth { realm }))
+            }
+            "Bearer" => {
+                if paras.get("realm").is_none()
+                    || paras.get("service").is_none()
+                    || paras.get("scope").is_none()
+                {
+                    return None;
+                }
+
+                let header = auth
+                    .as_ref()
+                    .map(|auth| HeaderValue::from_str(&format!("Basic {}", auth)).unwrap());
+
+                Some(Auth::Bearer(BearerAuth {
+                    realm: (*paras.get("realm").unwrap()).to_string(),
+                    service: (*paras.get("service").unwrap()).to_string(),
+                    scope: (*paras.get("scope").unwrap()).to_string(),
+                    header,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+struct RegistryReader {
+    blob_id: String,
+    connection: Arc<Connection>,
+    state: Arc<RegistryState>,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl RegistryReader {
     /// Read data from registry server
     ///
     /// Step:
@@ -447,8 +615,14 @@ impl RegistryReader {
                 return self._try_read(buf, offset, false);
             }
         } else {
-            resp =
-                self.request::<&[u8]>(Method::GET, url.as_str(), None, headers.clone(), false)?;
+            resp = self.state.request::<&[u8]>(
+                &self.connection,
+                Method::GET,
+                url.as_str(),
+                None,
+                headers.clone(),
+                false,
+            )?;
             let status = resp.status();
             // Handle redirect request and cache redirect url
             if vec![
@@ -509,14 +683,26 @@ impl RegistryReader {
     }
 }
 
+impl Drop for RegistryReader {
+    fn drop(&mut self) {
+        self.connection.release_blob(&self.blob_id);
+    }
+}
+
 impl BlobReader for RegistryReader {
     fn blob_size(&self) -> BackendResult<u64> {
         let url = self
             .state
             .url(&format!("/blobs/sha256:{}", self.blob_id), &[])
             .map_err(RegistryError::Url)?;
-        let resp =
-            self.request::<&[u8]>(Method::HEAD, url.as_str(), None, HeaderMap::new(), true)?;
+        let resp = self.state.request::<&[u8]>(
+            &self.connection,
+            Method::HEAD,
+            url.as_str(),
+            None,
+            HeaderMap::new(),
+            true,
+        )?;
         let content_length = resp
             .headers()
             .get(CONTENT_LENGTH)
@@ -530,6 +716,7 @@ impl BlobReader for RegistryReader {
     }
 
     fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        let _permit = self.connection.acquire(&self.blob_id, &self.metrics);
         self._try_read(buf, offset, true)
             .map_err(BackendError::Registry)
     }
@@ -551,7 +738,11 @@ impl BlobReader for RegistryReader {
     }
 
     fn retry_limit(&self) -> u8 {
-        self.state.retry_limit
+        self.state.common_config.retry_limit
+    }
+
+    fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        self.state.common_config.request_policy(class)
     }
 }
 
@@ -568,12 +759,24 @@ impl Registry {
         let id = id.ok_or_else(|| einval!("Registry backend requires blob_id"))?;
         let common_config: CommonConfig =
             serde_json::from_value(config.clone()).map_err(|e| einval!(e))?;
-        let retry_limit = common_config.retry_limit;
         let connection = Connection::new(&common_config)?;
         let config: RegistryConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
-        let auth = trim(config.auth);
-        let registry_token = trim(config.registry_token);
-        let (username, password) = Self::get_authorization_info(&auth)?;
+        let mut auth = trim(config.auth);
+        let mut registry_token = trim(config.registry_token);
+        let mut auth_file = None;
+        if !config.auth_file.is_empty() {
+            let path = PathBuf::from(&config.auth_file);
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let (a, t) = RegistryState::load_credentials(&path, &config.host)?;
+            auth = a;
+            registry_token = t;
+            auth_file = Some(Mutex::new(AuthFileState {
+                path,
+                modified,
+                last_check: Instant::now(),
+            }));
+        }
+        let credentials = RegistryCredentials::new(auth)?;
         let cached_auth = if let Some(registry_token) = registry_token {
             // Store the registry bearer token to cached_auth, prefer to
             // use the token stored in cached_auth to request registry.
@@ -586,11 +789,11 @@ impl Registry {
             scheme: config.scheme,
             host: config.host,
             repo: config.repo,
-            auth,
+            credentials: RwLock::new(credentials),
+            auth_file,
+            auth_reload_interval: Duration::from_secs(config.auth_reload_secs),
             cached_auth,
-            username,
-            password,
-            retry_limit,
+            common_config,
             blob_url_scheme: config.blob_url_scheme,
             blob_redirected_host: config.blob_redirected_host,
             cached_redirect: HashCache::new(),
@@ -603,6 +806,119 @@ impl Registry {
         })
     }
 
+    /// Check whether the blob with `digest`, like `sha256:<hex>`, exists in the repository.
+    pub fn blob_exists(&self, digest: &str) -> BackendResult<bool> {
+        let url = self
+            .state
+            .url(&format!("/blobs/{}", digest), &[])
+            .map_err(RegistryError::Url)?;
+        let resp = self.state.request::<&[u8]>(
+            &self.connection,
+            Method::HEAD,
+            url.as_str(),
+            None,
+            HeaderMap::new(),
+            false,
+        )?;
+
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => respond(resp, true)
+                .map(|_| true)
+                .map_err(|e| RegistryError::Request(e).into()),
+        }
+    }
+
+    /// Upload a blob of `size` bytes from `reader` to the repository, skip it if it exists.
+    ///
+    /// Monolithic upload workflow:
+    ///
+    /// Request:  POST /blobs/uploads/
+    /// Response: status: 202 Accepted
+    ///           header: location: /v2/<repo>/blobs/uploads/<uuid>
+    ///
+    /// Request:  PUT /v2/<repo>/blobs/uploads/<uuid>?digest=sha256:<hex>
+    ///           body: <blob data>
+    /// Response: status: 201 Created
+    pub fn push_blob<R: Read + Send + 'static>(
+        &self,
+        digest: &str,
+        size: u64,
+        reader: R,
+    ) -> BackendResult<()> {
+        if self.blob_exists(digest)? {
+            debug!("blob {} exists in registry, skip uploading", digest);
+            return Ok(());
+        }
+
+        let url = self
+            .state
+            .url("/blobs/uploads/", &[])
+            .map_err(RegistryError::Url)?;
+        let resp = self.state.request::<&[u8]>(
+            &self.connection,
+            Method::POST,
+            url.as_str(),
+            None,
+            HeaderMap::new(),
+            true,
+        )?;
+        let location = resp
+            .headers()
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| RegistryError::ResponseHead("invalid upload location".to_string()))?;
+        // The location may be relative to the registry and may already contain a query.
+        let mut location = Url::parse(url.as_str())
+            .and_then(|base| base.join(location))
+            .map_err(RegistryError::Url)?;
+        location.query_pairs_mut().append_pair("digest", digest);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        let data = ReqBody::Read(Progress::new(reader, size as usize, |_| {}), size as usize);
+        self.state.request(
+            &self.connection,
+            Method::PUT,
+            location.as_str(),
+            Some(data),
+            headers,
+            true,
+        )?;
+
+        Ok(())
+    }
+
+    /// Upload a manifest of `media_type` to the repository, referenced by tag or digest.
+    pub fn push_manifest(
+        &self,
+        reference: &str,
+        media_type: &str,
+        data: Vec<u8>,
+    ) -> BackendResult<()> {
+        let url = self
+            .state
+            .url(&format!("/manifests/{}", reference), &[])
+            .map_err(RegistryError::Url)?;
+        let mut headers = HeaderMap::new();
+        let content_type = HeaderValue::from_str(media_type)
+            .map_err(|e| RegistryError::Common(format!("invalid media type: {:?}", e)))?;
+        headers.insert(CONTENT_TYPE, content_type);
+        self.state.request::<&[u8]>(
+            &self.connection,
+            Method::PUT,
+            url.as_str(),
+            Some(ReqBody::Buf(data)),
+            headers,
+            true,
+        )?;
+
+        Ok(())
+    }
+
     fn get_authorization_info(auth: &Option<String>) -> Result<(String, String)> {
         if let Some(auth) = &auth {
             let auth: Vec<u8> = base64::decode(auth.as_bytes()).map_err(|e| {
@@ -639,6 +955,7 @@ impl BlobBackend for Registry {
     }
 
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        self.connection.open_blob(blob_id);
         Ok(Arc::new(RegistryReader {
             blob_id: blob_id.to_owned(),
             state: self.state.clone(),
@@ -672,6 +989,7 @@ fn trim(value: Option<String>) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vmm_sys_util::tempfile::TempFile;
 
     #[test]
     fn test_string_cache() {
@@ -704,10 +1022,17 @@ mod tests {
             scheme: "http".to_string(),
             host: "alibaba-inc.com".to_string(),
             repo: "nydus".to_string(),
-            auth: None,
-            username: "test".to_string(),
-            password: "password".to_string(),
-            retry_limit: 5,
+            credentials: RwLock::new(RegistryCredentials {
+                auth: None,
+                username: "test".to_string(),
+                password: "password".to_string(),
+            }),
+            auth_file: None,
+            auth_reload_interval: Duration::from_secs(10),
+            common_config: CommonConfig {
+                retry_limit: 5,
+                ..Default::default()
+            },
             blob_url_scheme: "https".to_string(),
             blob_redirected_host: "oss.alibaba-inc.com".to_string(),
             cached_auth: Default::default(),
@@ -723,7 +1048,20 @@ mod tests {
             "http://alibaba-inc.com/v2/nydusimage".to_owned()
         );
 
+        state.host = "[::1]:5000".to_owned();
+        assert_eq!(
+            state.url("image", &[]).unwrap(),
+            "http://[::1]:5000/v2/nydusimage".to_owned()
+        );
+        state.scheme = "http+unix".to_owned();
+        state.host = "/run/registry.sock".to_owned();
+        assert_eq!(
+            state.url("image", &[]).unwrap(),
+            "http+unix://%2Frun%2Fregistry.sock/v2/nydusimage".to_owned()
+        );
+
         state.scheme = "unknown_schema".to_owned();
+        state.host = "alibaba-inc.com".to_owned();
         assert!(state.url("image", &[]).is_err());
     }
 
@@ -754,6 +1092,53 @@ mod tests {
         assert!(RegistryState::parse_auth(&header, &None).is_none());
     }
 
+    #[test]
+    fn test_rotate_credentials() {
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path();
+        let auth = base64::encode("user:pass");
+
+        fs::write(path, format!("{{\"auth\": \"{}\"}}", auth)).unwrap();
+        let (a, t) = RegistryState::load_credentials(path, "my-registry.com").unwrap();
+        assert_eq!(a, Some(auth.clone()));
+        assert_eq!(t, None);
+
+        let config = format!(
+            "{{\"auths\": {{\"https://my-registry.com/v1/\": {{\"auth\": \"{}\", \"registrytoken\": \"token\"}}}}}}",
+            auth
+        );
+        fs::write(path, config).unwrap();
+        let (a, t) = RegistryState::load_credentials(path, "my-registry.com").unwrap();
+        assert_eq!(a, Some(auth.clone()));
+        assert_eq!(t, Some("token".to_string()));
+        assert!(RegistryState::load_credentials(path, "other-registry.com").is_err());
+
+        let state = RegistryState {
+            scheme: "http".to_string(),
+            host: "my-registry.com".to_string(),
+            repo: "nydus".to_string(),
+            credentials: Default::default(),
+            auth_file: None,
+            auth_reload_interval: Duration::from_secs(10),
+            common_config: CommonConfig {
+                retry_limit: 5,
+                ..Default::default()
+            },
+            blob_url_scheme: "https".to_string(),
+            blob_redirected_host: String::new(),
+            cached_auth: Cache::new("Bearer old".to_string()),
+            cached_redirect: Default::default(),
+        };
+        state.update_credentials(a, None).unwrap();
+        assert_eq!(state.auth(), Some(auth));
+        assert_eq!(state.credentials.read().unwrap().username, "user");
+        assert_eq!(state.credentials.read().unwrap().password, "pass");
+        assert_eq!(state.cached_auth.get(), "");
+        state.update_credentials(None, t).unwrap();
+        assert_eq!(state.auth(), None);
+        assert_eq!(state.cached_auth.get(), "Bearer token");
+    }
+
     #[test]
     fn test_trim() {
         assert_eq!(trim(None), None);
@@ -765,4 +1150,95 @@ mod tests {
         assert_eq!(trim(Some("  te st  ".to_owned())), Some("te st".to_owned()));
         assert_eq!(trim(Some("te st".to_owned())), Some("te st".to_owned()));
     }
+
+    #[test]
+    fn test_push() {
+        use std::io::{BufRead, BufReader, Cursor, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let auth = base64::encode("user:pass");
+        let config = serde_json::json!({
+            "scheme": "http",
+            "host": listener.local_addr().unwrap().to_string(),
+            "repo": "test/repo",
+            "auth": auth,
+        });
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..6 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut head = line.trim().splitn(3, ' ');
+                let method = head.next().unwrap().to_string();
+                let path = head.next().unwrap().to_string();
+                let mut len = 0;
+                let mut authorized = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let lower = line.to_lowercase();
+                    if let Some(v) = lower.strip_prefix("content-length:") {
+                        len = v.trim().parse::<usize>().unwrap();
+                    }
+                    if lower.starts_with("authorization: basic") {
+                        authorized = true;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0u8; len];
+                reader.read_exact(&mut body).unwrap();
+
+                let resp = match (authorized, method.as_str()) {
+                    (false, _) => "401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"test\"",
+                    (true, "HEAD") => "404 Not Found",
+                    (true, "POST") => {
+                        "202 Accepted\r\nLocation: /v2/test/repo/blobs/uploads/uuid?state=s"
+                    }
+                    _ => "201 Created",
+                };
+                let resp = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    resp
+                );
+                (&stream).write_all(resp.as_bytes()).unwrap();
+                requests.push((method, path, authorized, body));
+            }
+            requests
+        });
+
+        let registry = Registry::new(config, Some("test-push")).unwrap();
+        registry
+            .push_blob("sha256:abcd", 5, Cursor::new(b"hello".to_vec()))
+            .unwrap();
+        registry
+            .push_manifest("latest", "application/json", b"{}".to_vec())
+            .unwrap();
+
+        let requests = server.join().unwrap();
+        let requests: Vec<(&str, &str, bool, &[u8])> = requests
+            .iter()
+            .map(|(m, p, a, b)| (m.as_str(), p.as_str(), *a, b.as_slice()))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("HEAD", "/v2/test/repo/blobs/sha256:abcd", false, &b""[..]),
+                ("HEAD", "/v2/test/repo/blobs/sha256:abcd", true, &b""[..]),
+                ("POST", "/v2/test/repo/blobs/uploads/", false, &b""[..]),
+                ("POST", "/v2/test/repo/blobs/uploads/", true, &b""[..]),
+                (
+                    "PUT",
+                    "/v2/test/repo/blobs/uploads/uuid?state=s&digest=sha256%3Aabcd",
+                    true,
+                    &b"hello"[..]
+                ),
+                ("PUT", "/v2/test/repo/manifests/latest", true, &b"{}"[..]),
+            ]
+        );
+    }
 }
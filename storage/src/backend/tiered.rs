@@ -0,0 +1,288 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to compose multiple storage backends in tiers.
+//!
+//! Requests are sent to tiers in order and fall back to the next tier on failure, e.g. to fetch
+//! data from a local P2P proxy first and fall back to the registry. Each tier has its own
+//! metrics, registered with id `<blob_id>-tier<index>`.
+
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nydus_utils::metrics::BackendMetrics;
+use serde_json::Value;
+
+use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader};
+use crate::factory::{BackendConfig, BlobFactory};
+
+fn default_fallback() -> bool {
+    true
+}
+
+/// Configuration information for a tier of the tiered storage backend.
+#[derive(Clone, Deserialize, Serialize)]
+struct TierConfig {
+    #[serde(flatten)]
+    backend: BackendConfig,
+    /// Timeout of requests to the tier in seconds, which overrides `timeout` of the backend.
+    #[serde(default)]
+    timeout: Option<u64>,
+    /// Whether to fall back to the next tier if the tier fails.
+    #[serde(default = "default_fallback")]
+    fallback: bool,
+    /// Seconds to skip the tier after it fails, 0 means never skip.
+    #[serde(default)]
+    skip_secs: u64,
+}
+
+/// Configuration information for tiered storage backend.
+#[derive(Clone, Deserialize, Serialize)]
+struct TieredConfig {
+    tiers: Vec<TierConfig>,
+}
+
+struct Tier {
+    index: usize,
+    backend: Arc<dyn BlobBackend>,
+    fallback: bool,
+    skip_duration: Duration,
+    // The tier is skipped until the instant after a failure.
+    skip_until: Mutex<Option<Instant>>,
+}
+
+impl Tier {
+    fn is_skipped(&self) -> bool {
+        match *self.skip_until.lock().unwrap() {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    fn set_failed(&self) {
+        if self.skip_duration.as_secs() > 0 {
+            *self.skip_until.lock().unwrap() = Some(Instant::now() + self.skip_duration);
+        }
+    }
+}
+
+struct TieredEntry {
+    blob_id: String,
+    readers: Vec<(Arc<Tier>, Arc<dyn BlobReader>)>,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl TieredEntry {
+    fn for_each_tier<T, F>(&self, mut f: F) -> BackendResult<T>
+    where
+        F: FnMut(&Arc<dyn BlobReader>) -> BackendResult<T>,
+    {
+        let mut last_err = None;
+
+        for (idx, (tier, reader)) in self.readers.iter().enumerate() {
+            // Always try the last tier, even if it's in the skipped state.
+            if tier.is_skipped() && idx + 1 < self.readers.len() {
+                continue;
+            }
+            match f(reader) {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    warn!(
+                        "tier {} failed to access blob {}, {:?}",
+                        tier.index, self.blob_id, e
+                    );
+                    tier.set_failed();
+                    if !tier.fallback {
+                        return Err(e);
+                    }
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            BackendError::Unsupported(format!("no available tier for blob {}", self.blob_id))
+        }))
+    }
+}
+
+impl BlobReader for TieredEntry {
+    fn blob_size(&self) -> BackendResult<u64> {
+        self.for_each_tier(|r| r.blob_size())
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        self.for_each_tier(|r| r.read(buf, offset))
+    }
+
+    fn prefetch_blob_data_range(&self, ra_offset: u32, ra_size: u32) -> BackendResult<()> {
+        for (_, reader) in self.readers.iter() {
+            let _ = reader.prefetch_blob_data_range(ra_offset, ra_size);
+        }
+        Ok(())
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        for (_, reader) in self.readers.iter() {
+            let _ = reader.stop_data_prefetch();
+        }
+        Ok(())
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+}
+
+/// Storage backend composed of multiple storage backends in tiers.
+pub struct TieredBackend {
+    tiers: Vec<Arc<Tier>>,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl TieredBackend {
+    pub fn new(config: Value, id: Option<&str>) -> Result<TieredBackend> {
+        let config: TieredConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let id = id.ok_or_else(|| einval!("tiered backend requires blob_id"))?;
+        if config.tiers.is_empty() {
+            return Err(einval!("tiered backend requires at least one tier"));
+        }
+
+        let mut tiers = Vec::with_capacity(config.tiers.len());
+        for (index, tier) in config.tiers.into_iter().enumerate() {
+            let mut backend = tier.backend;
+            if backend.backend_type == "tiered" {
+                return Err(einval!("tiered backend can't be nested"));
+            }
+            if let Some(timeout) = tier.timeout {
+                match backend.backend_config.as_object_mut() {
+                    Some(obj) => {
+                        obj.insert("timeout".to_string(), Value::from(timeout));
+                    }
+                    None => return Err(einval!(format!("invalid config for tier {}", index))),
+                }
+            }
+            let backend = BlobFactory::new_backend(backend, &format!("{}-tier{}", id, index))?;
+            tiers.push(Arc::new(Tier {
+                index,
+                backend,
+                fallback: tier.fallback,
+                skip_duration: Duration::from_secs(tier.skip_secs),
+                skip_until: Mutex::new(None),
+            }));
+        }
+
+        Ok(TieredBackend {
+            tiers,
+            metrics: BackendMetrics::new(id, "tiered"),
+        })
+    }
+}
+
+impl BlobBackend for TieredBackend {
+    fn shutdown(&self) {
+        for tier in self.tiers.iter() {
+            tier.backend.shutdown();
+        }
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        let mut readers = Vec::with_capacity(self.tiers.len());
+
+        for tier in self.tiers.iter() {
+            match tier.backend.get_reader(blob_id) {
+                Ok(reader) => readers.push((tier.clone(), reader)),
+                Err(e) => {
+                    warn!(
+                        "tier {} failed to get reader for blob {}, {:?}",
+                        tier.index, blob_id, e
+                    );
+                    if !tier.fallback {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        if readers.is_empty() {
+            return Err(BackendError::Unsupported(format!(
+                "no available tier for blob {}",
+                blob_id
+            )));
+        }
+
+        Ok(Arc::new(TieredEntry {
+            blob_id: blob_id.to_string(),
+            readers,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+impl Drop for TieredBackend {
+    fn drop(&mut self) {
+        self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+    }
+}
+
+#[cfg(all(test, feature = "backend-localfs"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_tiered_backend_fallback() {
+        let tmp_dir = TempDir::new().unwrap();
+        let blob_dir = tmp_dir.as_path().to_str().unwrap();
+        fs::write(tmp_dir.as_path().join("blob1"), b"tiered backend data").unwrap();
+
+        let config = serde_json::json!({
+            "tiers": [
+                {"type": "localfs", "config": {"dir": "/nonexistent/blobs"}, "skip_secs": 60},
+                {"type": "localfs", "config": {"dir": blob_dir}},
+            ]
+        });
+        let backend = TieredBackend::new(config, Some("test_tiered_fallback")).unwrap();
+        let reader = backend.get_reader("blob1").unwrap();
+        let mut buf = [0u8; 6];
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 6);
+        assert_eq!(&buf, b"tiered");
+        assert_eq!(reader.blob_size().unwrap(), 19);
+        assert!(backend.get_reader("blob2").is_err());
+
+        let config = serde_json::json!({
+            "tiers": [
+                {"type": "localfs", "config": {"dir": "/nonexistent/blobs"}, "fallback": false},
+                {"type": "localfs", "config": {"dir": blob_dir}},
+            ]
+        });
+        let backend = TieredBackend::new(config, Some("test_tiered_no_fallback")).unwrap();
+        assert!(backend.get_reader("blob1").is_err());
+    }
+
+    #[test]
+    fn test_tiered_backend_config() {
+        let config = serde_json::json!({ "tiers": [] });
+        assert!(TieredBackend::new(config, Some("test_tiered_config")).is_err());
+
+        let config = serde_json::json!({
+            "tiers": [{"type": "tiered", "config": {"tiers": []}}]
+        });
+        assert!(TieredBackend::new(config, Some("test_tiered_config")).is_err());
+
+        let config = serde_json::json!({
+            "tiers": [{"type": "localfs", "config": {"dir": "/tmp"}}]
+        });
+        assert!(TieredBackend::new(config.clone(), None).is_err());
+        let tiered: TieredConfig = serde_json::from_value(config).unwrap();
+        assert!(tiered.tiers[0].fallback);
+        assert_eq!(tiered.tiers[0].skip_secs, 0);
+        assert_eq!(tiered.tiers[0].backend.backend_type, "localfs");
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Size-classed pool of data buffers shared by read requests.
+//!
+//! Each read request allocates scratch buffers for compressed and decompressed chunk data, which
+//! stresses the allocator under high QPS. With the pool started, buffers are rounded up to power
+//! of two size classes, and returned to the pool when dropped at the end of the request, so they
+//! can be reused by following requests from any thread. Total size of cached buffers is limited,
+//! buffers beyond the limit or larger than the largest class are freed as usual.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use nydus_utils::metrics::{Metric, BUFFER_POOL_METRICS};
+
+use crate::utils::alloc_buf;
+
+/// Size of the smallest buffer class.
+pub const BUFFER_POOL_MIN_SIZE: usize = 0x1000;
+/// Size of the largest buffer class, enough for chunks of stargz images.
+pub const BUFFER_POOL_MAX_SIZE: usize = 0x40_0000;
+
+const BUFFER_CLASSES: usize = 11;
+
+lazy_static::lazy_static! {
+    static ref BUFFER_POOL: RwLock<Option<Arc<BufferPool>>> = RwLock::new(None);
+}
+
+/// Start the shared buffer pool caching at most `capacity` bytes of buffers, which lives until
+/// the process exits.
+pub fn start_buffer_pool(capacity: usize) -> std::io::Result<()> {
+    if capacity < BUFFER_POOL_MIN_SIZE {
+        return Err(einval!(format!(
+            "buffer pool should be at least {} bytes",
+            BUFFER_POOL_MIN_SIZE
+        )));
+    }
+    let mut guard = BUFFER_POOL.write().unwrap();
+    if guard.is_some() {
+        return Err(eother!("buffer pool has been started"));
+    }
+
+    *guard = Some(Arc::new(BufferPool::new(capacity)));
+    BUFFER_POOL_METRICS.capacity.add(capacity as u64);
+    info!("buffer pool started with capacity {} bytes", capacity);
+
+    Ok(())
+}
+
+/// Allocate a buffer of `size` bytes from the shared pool if started, with unspecified content.
+pub(crate) fn alloc_buffer(size: usize) -> PooledBuffer {
+    match BUFFER_POOL.read().unwrap().as_ref() {
+        Some(pool) => pool.alloc(size),
+        None => PooledBuffer {
+            buf: alloc_buf(size),
+            pool: None,
+        },
+    }
+}
+
+// Get index of the smallest class which fits `size` bytes.
+fn class_index(size: usize) -> Option<usize> {
+    if size > BUFFER_POOL_MAX_SIZE {
+        return None;
+    }
+    let size = std::cmp::max(size, BUFFER_POOL_MIN_SIZE).next_power_of_two();
+    Some((size / BUFFER_POOL_MIN_SIZE).trailing_zeros() as usize)
+}
+
+pub(crate) struct BufferPool {
+    // Free buffers indexed by size class.
+    classes: Vec<Mutex<Vec<Vec<u8>>>>,
+    capacity: usize,
+    // Total capacity of free buffers in the pool.
+    cached: AtomicUsize,
+}
+
+impl BufferPool {
+    fn new(capacity: usize) -> Self {
+        BufferPool {
+            classes: (0..BUFFER_CLASSES)
+                .map(|_| Mutex::new(Vec::new()))
+                .collect(),
+            capacity,
+            cached: AtomicUsize::new(0),
+        }
+    }
+
+    fn alloc(self: &Arc<Self>, size: usize) -> PooledBuffer {
+        let idx = match class_index(size) {
+            Some(idx) => idx,
+            None => {
+                BUFFER_POOL_METRICS.oversized.inc();
+                return PooledBuffer {
+                    buf: alloc_buf(size),
+                    pool: None,
+                };
+            }
+        };
+
+        let reused = self.classes[idx].lock().unwrap().pop();
+        let buf = match reused {
+            Some(mut buf) => {
+                self.cached.fetch_sub(buf.capacity(), Ordering::Relaxed);
+                BUFFER_POOL_METRICS.cached_size.sub(buf.capacity() as u64);
+                BUFFER_POOL_METRICS.reused.inc();
+                // Safe because the capacity of buffers in the class is larger than `size`.
+                unsafe { buf.set_len(size) };
+                buf
+            }
+            None => {
+                BUFFER_POOL_METRICS.allocated.inc();
+                let mut buf = alloc_buf(BUFFER_POOL_MIN_SIZE << idx);
+                buf.truncate(size);
+                buf
+            }
+        };
+
+        PooledBuffer {
+            buf,
+            pool: Some(self.clone()),
+        }
+    }
+
+    fn release(&self, buf: Vec<u8>) {
+        let cap = buf.capacity();
+        let idx = match class_index(cap) {
+            Some(idx) if BUFFER_POOL_MIN_SIZE << idx == cap => idx,
+            _ => return,
+        };
+        // Racing releases may exceed the capacity slightly, which is harmless.
+        if self.cached.load(Ordering::Relaxed) + cap > self.capacity {
+            BUFFER_POOL_METRICS.freed.inc();
+            return;
+        }
+
+        self.cached.fetch_add(cap, Ordering::Relaxed);
+        BUFFER_POOL_METRICS.cached_size.add(cap as u64);
+        self.classes[idx].lock().unwrap().push(buf);
+    }
+}
+
+/// A data buffer returned to the shared pool when dropped.
+pub(crate) struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl PooledBuffer {
+    pub fn as_slice(&self) -> &[u8] {
+        self.buf.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.buf.as_mut_slice()
+    }
+
+    /// Capacity of the underlying memory.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buf.as_slice()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf.as_mut_slice()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.release(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_index() {
+        assert_eq!(class_index(0), Some(0));
+        assert_eq!(class_index(0x1000), Some(0));
+        assert_eq!(class_index(0x1001), Some(1));
+        assert_eq!(class_index(0x10_0000), Some(8));
+        assert_eq!(class_index(BUFFER_POOL_MAX_SIZE), Some(BUFFER_CLASSES - 1));
+        assert_eq!(class_index(BUFFER_POOL_MAX_SIZE + 1), None);
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = Arc::new(BufferPool::new(0x3000));
+
+        let mut b1 = pool.alloc(0x1800);
+        assert_eq!(b1.len(), 0x1800);
+        assert_eq!(b1.capacity(), 0x2000);
+        b1.as_mut_slice()[0x17ff] = 1;
+        let ptr = b1.as_ptr();
+        drop(b1);
+        assert_eq!(pool.cached.load(Ordering::Relaxed), 0x2000);
+
+        // Buffers are reused by requests of the same class.
+        let b2 = pool.alloc(0x1001);
+        assert_eq!(b2.len(), 0x1001);
+        assert_eq!(b2.as_ptr(), ptr);
+        assert_eq!(pool.cached.load(Ordering::Relaxed), 0);
+        let b3 = pool.alloc(0x2000);
+        assert_ne!(b3.as_ptr(), ptr);
+        let b4 = pool.alloc(0x100);
+        assert_eq!(b4.capacity(), 0x1000);
+
+        // Buffers beyond the capacity are freed.
+        drop(b2);
+        drop(b3);
+        assert_eq!(pool.cached.load(Ordering::Relaxed), 0x2000);
+        drop(b4);
+        assert_eq!(pool.cached.load(Ordering::Relaxed), 0x3000);
+        assert_eq!(pool.classes[1].lock().unwrap().len(), 1);
+        assert_eq!(pool.classes[0].lock().unwrap().len(), 1);
+
+        let b5 = pool.alloc(BUFFER_POOL_MAX_SIZE + 1);
+        assert!(b5.pool.is_none());
+        assert_eq!(b5.len(), BUFFER_POOL_MAX_SIZE + 1);
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shared pool of threads to decompress chunk data.
+//!
+//! Chunks are decompressed inline by threads serving filesystem requests by default, so prefetch
+//! and read storms cause CPU spikes proportional to the number of service threads. With the pool
+//! started, chunks are decompressed by a fixed number of worker threads shared by all filesystem
+//! instances. Requests are queued per filesystem instance and served in round-robin order so a
+//! busy instance can't starve others, and callers are blocked when the queue is full.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::Result;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+
+use super::buffer_pool::alloc_buffer;
+use crate::compress;
+
+/// Maximum number of queued requests per worker thread.
+pub const DECOMPRESS_QUEUE_SIZE_PER_WORKER: usize = 16;
+
+lazy_static::lazy_static! {
+    static ref DECOMPRESS_POOL: RwLock<Option<Arc<DecompressPool>>> = RwLock::new(None);
+}
+
+/// Start the shared decompression pool with `workers` threads, which lives until the process
+/// exits.
+pub fn start_decompress_pool(workers: usize) -> Result<()> {
+    if workers == 0 {
+        return Err(einval!("decompression pool needs at least one worker"));
+    }
+    let mut guard = DECOMPRESS_POOL.write().unwrap();
+    if guard.is_some() {
+        return Err(eother!("decompression pool has been started"));
+    }
+
+    let pool = Arc::new(DecompressPool::new(
+        workers * DECOMPRESS_QUEUE_SIZE_PER_WORKER,
+    ));
+    for idx in 0..workers {
+        let pool = pool.clone();
+        thread::Builder::new()
+            .name(format!("decompress_{}", idx))
+            .spawn(move || pool.run())?;
+    }
+    *guard = Some(pool);
+    info!("decompression pool started with {} workers", workers);
+
+    Ok(())
+}
+
+/// Get the shared decompression pool if it has been started.
+pub(crate) fn decompress_pool() -> Option<Arc<DecompressPool>> {
+    DECOMPRESS_POOL.read().unwrap().clone()
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct PoolState {
+    // Queued requests keyed by their owners.
+    queues: BTreeMap<String, VecDeque<Job>>,
+    // Owner of the request dequeued last time.
+    last: Option<String>,
+    queued: usize,
+}
+
+impl PoolState {
+    fn push(&mut self, owner: &str, job: Job) {
+        self.queues
+            .entry(owner.to_string())
+            .or_default()
+            .push_back(job);
+        self.queued += 1;
+    }
+
+    // Pop a request of the owner next to the one served last time, in round-robin order.
+    fn pop(&mut self) -> Option<Job> {
+        let next = self
+            .last
+            .as_ref()
+            .and_then(|last| {
+                self.queues
+                    .range::<String, _>((Excluded(last), Unbounded))
+                    .next()
+            })
+            .or_else(|| self.queues.iter().next())
+            .map(|(k, _)| k.clone())?;
+
+        // Empty queues are removed, so the queue always has a request.
+        let queue = self.queues.get_mut(&next).unwrap();
+        let job = queue.pop_front();
+        if queue.is_empty() {
+            self.queues.remove(&next);
+        }
+        self.queued -= 1;
+        self.last = Some(next);
+
+        job
+    }
+}
+
+pub(crate) struct DecompressPool {
+    state: Mutex<PoolState>,
+    // Signalled when requests are queued.
+    job_cond: Condvar,
+    // Signalled when requests are dequeued.
+    space_cond: Condvar,
+    queue_size: usize,
+}
+
+impl DecompressPool {
+    fn new(queue_size: usize) -> Self {
+        DecompressPool {
+            state: Mutex::new(PoolState::default()),
+            job_cond: Condvar::new(),
+            space_cond: Condvar::new(),
+            queue_size,
+        }
+    }
+
+    fn submit(&self, owner: &str, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        while state.queued >= self.queue_size {
+            state = self.space_cond.wait(state).unwrap();
+        }
+        state.push(owner, job);
+        self.job_cond.notify_one();
+    }
+
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if let Some(job) = state.pop() {
+                        self.space_cond.notify_one();
+                        break job;
+                    }
+                    state = self.job_cond.wait(state).unwrap();
+                }
+            };
+            // The caller gets an error if the job panics, keep the worker alive.
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("decompression job panicked");
+            }
+        }
+    }
+
+    /// Decompress `src`, or `src_file` if specified, into `dst` on behalf of `owner`, and wait
+    /// for the result.
+    pub fn decompress(
+        &self,
+        owner: &str,
+        src: &[u8],
+        src_file: Option<File>,
+        dst: &mut [u8],
+        algorithm: compress::Algorithm,
+    ) -> Result<usize> {
+        let (tx, rx) = mpsc::channel();
+        let src = src.to_vec();
+        let size = dst.len();
+        self.submit(
+            owner,
+            Box::new(move || {
+                let mut buf = alloc_buffer(size);
+                let result = compress::decompress(&src, src_file, &mut buf, algorithm);
+                let _ = tx.send(result.map(|size| (size, buf)));
+            }),
+        );
+
+        let (size, buf) = rx
+            .recv()
+            .map_err(|_| eio!("decompression job is aborted"))??;
+        dst.copy_from_slice(&buf);
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_pool_round_robin() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut state = PoolState::default();
+        for (owner, count) in [("a", 3), ("b", 1), ("c", 2)].iter() {
+            for _ in 0..*count {
+                let (order, owner) = (order.clone(), *owner);
+                state.push(owner, Box::new(move || order.lock().unwrap().push(owner)));
+            }
+        }
+        assert_eq!(state.queued, 6);
+        while let Some(job) = state.pop() {
+            job();
+        }
+        assert_eq!(state.queued, 0);
+        assert!(state.queues.is_empty());
+        assert_eq!(*order.lock().unwrap(), vec!["a", "b", "c", "a", "c", "a"]);
+    }
+
+    #[test]
+    fn test_pool_decompress() {
+        let pool = Arc::new(DecompressPool::new(2));
+        for _ in 0..2 {
+            let pool = pool.clone();
+            thread::spawn(move || pool.run());
+        }
+
+        let text = b"compressible text data ".repeat(0x1000);
+        let (data, compressed) = compress::compress(&text, compress::Algorithm::Lz4Block).unwrap();
+        assert!(compressed);
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for idx in 0..8 {
+            let (pool, data, text, done) =
+                (pool.clone(), data.to_vec(), text.clone(), done.clone());
+            handles.push(thread::spawn(move || {
+                let mut buf = vec![0u8; text.len()];
+                let owner = format!("fs{}", idx % 3);
+                let size = pool
+                    .decompress(&owner, &data, None, &mut buf, compress::Algorithm::Lz4Block)
+                    .unwrap();
+                assert_eq!(size, text.len());
+                assert_eq!(buf, text);
+                done.fetch_add(1, Ordering::Relaxed);
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(done.load(Ordering::Relaxed), 8);
+
+        let mut buf = vec![0u8; 16];
+        assert!(pool
+            .decompress(
+                "fs0",
+                b"garbage",
+                None,
+                &mut buf,
+                compress::Algorithm::Lz4Block
+            )
+            .is_err());
+    }
+}
@@ -24,9 +24,9 @@ use std::sync::Arc;
 use fuse_backend_rs::transport::FileVolatileSlice;
 use nydus_utils::digest;
 
-use crate::backend::{BlobBackend, BlobReader};
+use crate::backend::{BlobBackend, BlobReader, ErrnoMapping};
 use crate::cache::state::{ChunkMap, NoopChunkMap};
-use crate::cache::{BlobCache, BlobCacheMgr};
+use crate::cache::{BlobCache, BlobCacheMgr, SwitchableReader};
 use crate::device::{BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoVec, BlobPrefetchRequest};
 use crate::factory::CacheConfig;
 use crate::utils::{alloc_buf, copyv};
@@ -35,12 +35,14 @@ use crate::{compress, StorageError, StorageResult};
 struct DummyCache {
     blob_id: String,
     chunk_map: Arc<dyn ChunkMap>,
-    reader: Arc<dyn BlobReader>,
+    reader: SwitchableReader,
+    compressed_size: u64,
     compressor: compress::Algorithm,
     digester: digest::Algorithm,
     is_stargz: bool,
     prefetch: bool,
     validate: bool,
+    errno_mapping: Arc<ErrnoMapping>,
 }
 
 impl BlobCache for DummyCache {
@@ -49,7 +51,7 @@ impl BlobCache for DummyCache {
     }
 
     fn blob_size(&self) -> Result<u64> {
-        self.reader.blob_size().map_err(|e| eother!(e))
+        self.reader().blob_size().map_err(|e| eother!(e))
     }
 
     fn compressor(&self) -> compress::Algorithm {
@@ -68,8 +70,12 @@ impl BlobCache for DummyCache {
         self.validate
     }
 
-    fn reader(&self) -> &dyn BlobReader {
-        &*self.reader
+    fn reader(&self) -> Arc<dyn BlobReader> {
+        self.reader.get()
+    }
+
+    fn errno_mapping(&self) -> &ErrnoMapping {
+        &self.errno_mapping
     }
 
     fn get_chunk_map(&self) -> &Arc<dyn ChunkMap> {
@@ -87,7 +93,7 @@ impl BlobCache for DummyCache {
             for p in prefetches.iter() {
                 if p.blob_id == self.blob_id
                     && self
-                        .reader
+                        .reader()
                         .prefetch_blob_data_range(p.offset, p.len)
                         .is_ok()
                 {
@@ -103,12 +109,29 @@ impl BlobCache for DummyCache {
 
     fn stop_prefetch(&self) -> StorageResult<()> {
         if self.prefetch {
-            let _ = self.reader.stop_data_prefetch();
+            let _ = self.reader().stop_data_prefetch();
         }
 
         Ok(())
     }
 
+    fn switch_backend(
+        &self,
+        backend: Option<Arc<dyn BlobBackend>>,
+        object_id: &str,
+        _flush_cache: bool,
+    ) -> Result<()> {
+        // Nothing is cached, so no need to flush.
+        self.reader
+            .switch(backend, object_id, self.compressed_size)?;
+        info!(
+            "blob {} switched to backend object {}",
+            self.blob_id, object_id
+        );
+
+        Ok(())
+    }
+
     fn read(&self, iovec: &BlobIoVec, bufs: &[FileVolatileSlice]) -> Result<usize> {
         let bios = &iovec.bi_vec;
 
@@ -157,6 +180,7 @@ pub struct DummyCacheMgr {
     cached: bool,
     prefetch: bool,
     validate: bool,
+    errno_mapping: Arc<ErrnoMapping>,
 }
 
 impl DummyCacheMgr {
@@ -167,11 +191,14 @@ impl DummyCacheMgr {
         cached: bool,
         enable_prefetch: bool,
     ) -> Result<DummyCacheMgr> {
+        config.errno_mapping.validate()?;
+
         Ok(DummyCacheMgr {
             backend,
             cached,
             validate: config.cache_validate,
             prefetch: enable_prefetch,
+            errno_mapping: Arc::new(config.errno_mapping),
         })
     }
 }
@@ -196,12 +223,14 @@ impl BlobCacheMgr for DummyCacheMgr {
         Ok(Arc::new(DummyCache {
             blob_id,
             chunk_map: Arc::new(NoopChunkMap::new(self.cached)),
-            reader,
+            reader: SwitchableReader::new(self.backend.clone(), reader),
+            compressed_size: blob_info.compressed_size(),
             compressor: blob_info.compressor(),
             digester: blob_info.digester(),
             is_stargz: blob_info.is_stargz(),
             prefetch: self.prefetch,
             validate: self.validate,
+            errno_mapping: self.errno_mapping.clone(),
         }))
     }
 }
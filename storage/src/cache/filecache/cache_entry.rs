@@ -10,40 +10,56 @@ use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::slice;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
 use nix::sys::uio;
 use nix::unistd::dup;
 use nydus_utils::digest;
-use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+use nydus_utils::metrics::{self, BlobcacheMetrics, Metric};
+use nydus_utils::trace;
 use tokio::runtime::Runtime;
 
-use crate::backend::BlobReader;
+use crate::backend::{
+    AsyncBlobReader, BackendResult, BlobBackend, BlobReader, BlobReaderBridge, ErrnoMapping,
+};
+use crate::cache::buffer_pool::{alloc_buffer, PooledBuffer};
+use crate::cache::filecache::compact::{data_extents, overlaps_extents};
+use crate::cache::filecache::evict::{punch_hole, ChunkAccessTable};
+use crate::cache::filecache::fallback::WholeBlobFallback;
+use crate::cache::filecache::reclaim::BlobFileRef;
 use crate::cache::filecache::FileCacheMgr;
 use crate::cache::state::{BlobStateMap, ChunkMap, DigestedChunkMap, IndexedChunkMap};
 use crate::cache::worker::{
     AsyncPrefetchConfig, AsyncRequestMessage, AsyncRequestState, AsyncWorkerMgr,
 };
-use crate::cache::{BlobCache, BlobIoMergeState};
+use crate::cache::{BlobCache, BlobIoMergeState, SwitchableReader};
 use crate::device::{
     BlobChunkInfo, BlobFeatures, BlobInfo, BlobIoChunk, BlobIoDesc, BlobIoRange, BlobIoSegment,
     BlobIoTag, BlobIoVec, BlobObject, BlobPrefetchRequest,
 };
 use crate::meta::BlobMetaInfo;
-use crate::utils::{alloc_buf, copyv, readv, MemSliceCursor};
+use crate::utils::{alloc_buf, copyv, digest_check, readv, MemSliceCursor};
 use crate::{compress, StorageError, StorageResult, RAFS_DEFAULT_CHUNK_SIZE};
 
 pub(crate) struct FileCacheEntry {
+    // Last access time of chunks, to punch holes for cold chunks.
+    access_table: Option<ChunkAccessTable>,
     blob_info: Arc<BlobInfo>,
     chunk_map: Arc<dyn ChunkMap>,
+    errno_mapping: Arc<ErrnoMapping>,
+    // Download the whole blob after repeated failures of range reads if enabled.
+    fallback: Option<Arc<WholeBlobFallback>>,
     file: Arc<File>,
     meta: Option<Arc<BlobMetaInfo>>,
     metrics: Arc<BlobcacheMetrics>,
     prefetch_state: Arc<AtomicU32>,
-    reader: Arc<dyn BlobReader>,
+    reader: SwitchableReader,
     runtime: Arc<Runtime>,
     workers: Arc<AsyncWorkerMgr>,
 
+    // Split backend requests larger than it and fetch data concurrently, 0 means disabled.
+    async_fetch_size: usize,
     blob_size: u64,
     compressor: compress::Algorithm,
     digester: digest::Algorithm,
@@ -58,6 +74,8 @@ pub(crate) struct FileCacheEntry {
     // Data from the file cache should be validated before use.
     need_validate: bool,
     prefetch_config: Arc<AsyncPrefetchConfig>,
+    // Reference to the cache files, dropped after all other fields.
+    _file_ref: BlobFileRef,
 }
 
 impl FileCacheEntry {
@@ -68,7 +86,9 @@ impl FileCacheEntry {
         runtime: Arc<Runtime>,
         workers: Arc<AsyncWorkerMgr>,
     ) -> Result<Self> {
-        let blob_file_path = format!("{}/{}", mgr.work_dir, blob_info.blob_id());
+        let blob_file_path = mgr.get_blob_file_path(blob_info.blob_id())?;
+        // Take the reference before opening cache files, so they won't be reclaimed.
+        let file_ref = BlobFileRef::new(&blob_file_path, mgr.get_gc_grace_period());
         let file = OpenOptions::new()
             .create(true)
             .write(true)
@@ -88,6 +108,24 @@ impl FileCacheEntry {
         let is_compressed = mgr.is_compressed || is_stargz;
         let need_validate = (mgr.validate || !is_direct_chunkmap) && !is_stargz;
         let is_get_blob_object_supported = !mgr.is_compressed && is_direct_chunkmap && !is_stargz;
+        // Holes can only be punched for chunks at fixed locations in the cache file.
+        let access_table = if mgr.punch_cold_secs.is_some() && is_direct_chunkmap && !is_compressed
+        {
+            Some(ChunkAccessTable::new(blob_info.chunk_count()))
+        } else {
+            None
+        };
+        let fallback = if mgr.whole_blob_fallback_threshold > 0 {
+            Some(WholeBlobFallback::new(
+                &blob_file_path,
+                blob_info.compressed_size(),
+                mgr.whole_blob_fallback_threshold,
+                mgr.metrics.clone(),
+                runtime.clone(),
+            ))
+        } else {
+            None
+        };
 
         trace!(
             "comp {} direct {} startgz {}",
@@ -114,16 +152,20 @@ impl FileCacheEntry {
         };
 
         Ok(FileCacheEntry {
+            access_table,
             blob_info,
             chunk_map,
+            errno_mapping: mgr.errno_mapping.clone(),
+            fallback,
             file: Arc::new(file),
             meta,
             metrics: mgr.metrics.clone(),
             prefetch_state: Arc::new(AtomicU32::new(AsyncRequestState::Init as u32)),
-            reader,
+            reader: SwitchableReader::new(mgr.backend.clone(), reader),
             runtime,
             workers,
 
+            async_fetch_size: mgr.async_fetch_size,
             blob_size,
             compressor,
             digester,
@@ -133,6 +175,7 @@ impl FileCacheEntry {
             is_stargz,
             need_validate,
             prefetch_config,
+            _file_ref: file_ref,
         })
     }
 
@@ -161,6 +204,97 @@ impl FileCacheEntry {
         Ok((chunk_map, direct_chunkmap))
     }
 
+    /// Punch holes in the cache file for chunks not accessed for `cold`, and return the number of
+    /// chunks punched.
+    pub fn punch_cold_chunks(&self, cold: Duration) -> Result<usize> {
+        let (table, bitmap) = match (self.access_table.as_ref(), self.chunk_map.as_range_map()) {
+            (Some(table), Some(bitmap)) => (table, bitmap),
+            _ => return Ok(0),
+        };
+
+        let mut punched = 0;
+        for (index, stamp) in table.cold_chunks(cold) {
+            if !bitmap.is_range_ready(index, 1)? {
+                continue;
+            }
+            // Mark the chunk as pending, so readers wait for the hole to be punched and then fetch
+            // the chunk from the storage backend again.
+            bitmap.clear_range_ready(index, 1)?;
+            match bitmap.check_range_ready_and_mark_pending(index, 1)? {
+                Some(v) if v.len() == 1 => {}
+                // Someone else is fetching the chunk again.
+                _ => continue,
+            }
+            if table.is_touched_since(index, stamp) {
+                // A reader may have found the chunk ready before it's cleared, so keep the data.
+                bitmap.set_range_ready_and_clear_pending(index, 1)?;
+                continue;
+            }
+            let (offset, size) = table.extent(index);
+            let result = punch_hole(&self.file, offset, size);
+            bitmap.clear_range_pending(index, 1);
+            result?;
+            punched += 1;
+        }
+
+        if punched > 0 {
+            self.metrics.punched_chunks.add(punched as u64);
+            info!(
+                "blobcache: punched holes for {} cold chunks of blob {}",
+                punched,
+                self.blob_info.blob_id()
+            );
+        }
+
+        Ok(punched)
+    }
+
+    /// Punch holes in the cache file for allocated data of chunks which are not ready, e.g. left
+    /// by interrupted writes, and return the number of bytes released.
+    pub fn compact(&self) -> Result<u64> {
+        // Only chunks at fixed locations in the cache file are known.
+        let meta = match self.meta.as_ref() {
+            Some(meta) => meta,
+            None => return Ok(0),
+        };
+        let extents = data_extents(&self.file, self.blob_info.uncompressed_size())?;
+        if extents.is_empty() {
+            return Ok(0);
+        }
+
+        let mut released = 0u64;
+        for chunk in meta.get_chunks_uncompressed(0, self.blob_info.uncompressed_size())? {
+            let (offset, size) = (chunk.uncompress_offset(), chunk.uncompress_size());
+            if !overlaps_extents(&extents, offset, offset + size as u64) {
+                continue;
+            }
+            // Mark the chunk as pending, so it won't be fetched while punching the hole.
+            if self.chunk_map.check_ready_and_mark_pending(&chunk)? {
+                continue;
+            }
+            let result = punch_hole(&self.file, offset, size);
+            self.chunk_map.clear_pending(&chunk);
+            result?;
+            released += size as u64;
+        }
+
+        if released > 0 {
+            info!(
+                "blobcache: released {} bytes of unready chunks of blob {}",
+                released,
+                self.blob_info.blob_id()
+            );
+        }
+
+        Ok(released)
+    }
+
+    fn touch_chunk(&self, chunk: &dyn BlobChunkInfo) {
+        if let Some(table) = self.access_table.as_ref() {
+            table.touch(chunk);
+        }
+    }
+
     fn get_blob_size(reader: &Arc<dyn BlobReader>, blob_info: &BlobInfo) -> Result<u64> {
         // Stargz needs blob size information, so hacky!
         let size = if blob_info.is_stargz() {
@@ -198,8 +332,27 @@ impl BlobCache for FileCacheEntry {
         self.need_validate
     }
 
-    fn reader(&self) -> &dyn BlobReader {
-        &*self.reader
+    fn reader(&self) -> Arc<dyn BlobReader> {
+        match self.fallback.as_ref() {
+            Some(fallback) => fallback.wrap(self.reader.get()),
+            None => self.reader.get(),
+        }
+    }
+
+    fn read_backend(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        if self.async_fetch_size > 0 && buf.len() > self.async_fetch_size {
+            BlobReaderBridge::new(self.reader(), self.runtime.handle().clone()).read_split(
+                buf,
+                offset,
+                self.async_fetch_size,
+            )
+        } else {
+            self.reader().read(buf, offset)
+        }
+    }
+
+    fn errno_mapping(&self) -> &ErrnoMapping {
+        &self.errno_mapping
     }
 
     fn get_chunk_map(&self) -> &Arc<dyn ChunkMap> {
@@ -214,6 +367,10 @@ impl BlobCache for FileCacheEntry {
         }
     }
 
+    fn record_chunk_access(&self, chunk: &dyn BlobChunkInfo) {
+        self.touch_chunk(chunk);
+    }
+
     fn prefetch(
         &self,
         blob_cache: Arc<dyn BlobCache>,
@@ -260,6 +417,14 @@ impl BlobCache for FileCacheEntry {
         Ok(())
     }
 
+    fn restart_prefetch(&self) -> Result<()> {
+        AsyncWorkerMgr::restart(self.workers.clone())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.file.sync_data()
+    }
+
     fn prefetch_range(&self, range: &BlobIoRange) -> Result<usize> {
         let mut pending = Vec::with_capacity(range.chunks.len());
         if !self.chunk_map.is_persist() {
@@ -267,7 +432,7 @@ impl BlobCache for FileCacheEntry {
             for c in range.chunks.iter() {
                 d_size = std::cmp::max(d_size, c.uncompress_size() as usize);
             }
-            let mut buf = alloc_buf(d_size);
+            let mut buf = alloc_buffer(d_size);
 
             for c in range.chunks.iter() {
                 if let Ok(true) = self.chunk_map.check_ready_and_mark_pending(c.as_base()) {
@@ -294,6 +459,7 @@ impl BlobCache for FileCacheEntry {
             }
         } else {
             for c in range.chunks.iter() {
+                self.touch_chunk(c.as_base());
                 if let Ok(true) = self.chunk_map.check_ready_and_mark_pending(c.as_base()) {
                     // The chunk is ready, so skip it.
                     continue;
@@ -345,8 +511,93 @@ impl BlobCache for FileCacheEntry {
         Ok(total_size)
     }
 
+    fn scrub_chunk(&self, chunk: &BlobIoChunk) -> Result<Option<bool>> {
+        if self.is_stargz {
+            return Err(enosys!("doesn't support scrubbing stargz blob cache"));
+        }
+        if !self.chunk_map.is_ready(chunk.as_base())? {
+            return Ok(None);
+        }
+
+        let mut buffer = alloc_buf(chunk.uncompress_size() as usize);
+        let valid = match self.read_file_cache(chunk, &mut buffer) {
+            Ok(_) => digest_check(&buffer, chunk.chunk_id(), self.digester),
+            Err(e) => {
+                debug!("failed to read chunk {} from cache file, {}", chunk.id(), e);
+                false
+            }
+        };
+
+        self.metrics.scrubbed_chunks.inc();
+        if !valid {
+            warn!(
+                "blob {} chunk {} cached data is corrupted, invalidate it",
+                self.blob_info.blob_id(),
+                chunk.id()
+            );
+            self.metrics.scrub_corrupted_chunks.inc();
+            self.chunk_map.clear_ready(chunk.as_base())?;
+        }
+
+        Ok(Some(valid))
+    }
+
+    fn invalidate_chunk(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        if self.is_stargz {
+            return Err(enosys!("doesn't support invalidating stargz blob cache"));
+        }
+        if !self.chunk_map.is_ready(chunk)? {
+            return Ok(false);
+        }
+
+        // Mark the chunk as pending after clearing its ready state, so readers wait for the hole
+        // to be punched and then fetch the chunk from the storage backend again.
+        self.chunk_map.clear_ready(chunk)?;
+        if self.chunk_map.check_ready_and_mark_pending(chunk)? {
+            // The chunk has been fetched again by others in the meantime.
+            return Ok(true);
+        }
+        let (offset, size) = if self.is_compressed {
+            (chunk.compress_offset(), chunk.compress_size())
+        } else {
+            (chunk.uncompress_offset(), chunk.uncompress_size())
+        };
+        let result = punch_hole(&self.file, offset, size);
+        self.chunk_map.clear_pending(chunk);
+        result?;
+        self.metrics.invalidated_chunks.inc();
+
+        Ok(true)
+    }
+
+    fn switch_backend(
+        &self,
+        backend: Option<Arc<dyn BlobBackend>>,
+        object_id: &str,
+        flush_cache: bool,
+    ) -> Result<()> {
+        self.reader
+            .switch(backend, object_id, self.blob_info.compressed_size())?;
+        if flush_cache {
+            self.chunk_map.clear_all_ready()?;
+        }
+        if let Some(fallback) = self.fallback.as_ref() {
+            fallback.reset();
+        }
+        info!(
+            "blob {} switched to backend object {}, flush cache {}",
+            self.blob_info.blob_id(),
+            object_id,
+            flush_cache
+        );
+
+        Ok(())
+    }
+
     fn read(&self, iovec: &BlobIoVec, buffers: &[FileVolatileSlice]) -> Result<usize> {
         debug_assert!(iovec.validate());
+        let mut span = trace::span("cache_lookup");
+        span.arg("chunks", iovec.bi_vec.len() as u64);
         self.metrics.total.inc();
         self.workers.consume_prefetch_budget(buffers);
 
@@ -422,6 +673,9 @@ impl FileCacheEntry {
         let bitmap = self.chunk_map.as_range_map().ok_or_else(|| einval!())?;
         let chunk_index = chunks[0].id();
         let count = chunks.len() as u32;
+        for chunk in chunks {
+            self.touch_chunk(chunk.as_base());
+        }
 
         // Get chunks not ready yet, also marking them as inflight.
         let pending = match bitmap.check_range_ready_and_mark_pending(chunk_index, count)? {
@@ -527,6 +781,7 @@ impl FileCacheEntry {
 
         debug!("dispatch a blob io range {:?}", req);
         for (i, chunk) in req.chunks.iter().enumerate() {
+            self.touch_chunk(chunk.as_base());
             let is_ready = self
                 .chunk_map
                 .check_ready_and_mark_pending(chunk.as_base())?;
@@ -602,7 +857,10 @@ impl FileCacheEntry {
         let iovec = cursor.consume(size);
 
         self.metrics.partial_hits.inc();
-        readv(self.file.as_raw_fd(), &iovec, offset)
+        let size = readv(self.file.as_raw_fd(), &iovec, offset)?;
+        metrics::account_cache_read(size);
+
+        Ok(size)
     }
 
     fn dispatch_cache_slow(&self, cursor: &mut MemSliceCursor, region: &Region) -> Result<usize> {
@@ -742,7 +1000,7 @@ impl FileCacheEntry {
         let is_ready = self.chunk_map.is_ready(chunk.as_base())?;
         let buffer_holder;
         let d_size = chunk.uncompress_size() as usize;
-        let mut d = DataBuffer::Allocated(alloc_buf(d_size));
+        let mut d = DataBuffer::Pooled(alloc_buffer(d_size));
 
         // Try to read and validate data from cache if:
         // - it's an stargz image and the chunk is ready.
@@ -751,6 +1009,7 @@ impl FileCacheEntry {
         let try_cache = is_ready || (!self.is_stargz && !self.is_direct_chunkmap);
         let buffer = if try_cache && self.read_file_cache(chunk, d.mut_slice()).is_ok() {
             self.metrics.whole_hits.inc();
+            metrics::account_cache_read(d_size);
             self.chunk_map
                 .set_ready_and_clear_pending(chunk.as_base())?;
             trace!(
@@ -819,7 +1078,7 @@ impl FileCacheEntry {
             // gzip is special that it doesn't carry compress_size, instead, we make an IO stream
             // out of the file cache. So no need for an internal buffer here.
             let c_size = chunk.compress_size() as usize;
-            d = alloc_buf(c_size);
+            d = alloc_buffer(c_size);
             d.as_mut_slice()
         } else {
             // We have this unsafe assignment as it can directly store data into call's buffer.
@@ -885,6 +1144,7 @@ impl FileCacheEntry {
 enum DataBuffer {
     Reuse(ManuallyDrop<Vec<u8>>),
     Allocated(Vec<u8>),
+    Pooled(PooledBuffer),
 }
 
 impl DataBuffer {
@@ -892,6 +1152,7 @@ impl DataBuffer {
         match self {
             Self::Reuse(data) => data.as_slice(),
             Self::Allocated(data) => data.as_slice(),
+            Self::Pooled(data) => data.as_slice(),
         }
     }
 
@@ -899,6 +1160,7 @@ impl DataBuffer {
         match self {
             Self::Reuse(ref mut data) => data.as_mut_slice(),
             Self::Allocated(ref mut data) => data.as_mut_slice(),
+            Self::Pooled(ref mut data) => data.as_mut_slice(),
         }
     }
 
@@ -906,6 +1168,7 @@ impl DataBuffer {
         match self {
             Self::Reuse(_) => 0,
             Self::Allocated(data) => data.capacity(),
+            Self::Pooled(data) => data.capacity(),
         }
     }
 
@@ -0,0 +1,210 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compact cache files of a blob cache manager.
+//!
+//! Long-lived nodes accumulate lots of partially filled cache files. Cache files of different
+//! blobs can't be merged, because data of a chunk is always addressed by its offset in the cache
+//! file of its blob, so compaction reclaims space and directory entries in place:
+//! - punch holes for data of chunks which are not ready, e.g. left by interrupted writes;
+//! - remove unreferenced cache files with low space utilization, even in their grace period;
+//! - remove chunk map and blob meta files whose cache file is gone, and empty blob directories.
+
+use std::fs::{self, File};
+use std::io::Result;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::cache::filecache::reclaim::ASSOCIATED_FILE_SUFFIXES;
+use crate::cache::filecache::FileCacheMgr;
+use crate::cache::{BlobCacheMgr, CacheCompactStat};
+
+// Empty blob directories modified recently may be about to be used.
+const EMPTY_DIR_MIN_AGE: Duration = Duration::from_secs(60);
+
+/// Get data extents `[start, end)` of the file within `[0, size)`.
+pub(crate) fn data_extents(file: &File, size: u64) -> Result<Vec<(u64, u64)>> {
+    let fd = file.as_raw_fd();
+    let mut extents = Vec::new();
+    let mut pos = 0u64;
+
+    while pos < size {
+        // Safe because the fd is valid and lseek() doesn't touch memory.
+        let start = unsafe { libc::lseek64(fd, pos as i64, libc::SEEK_DATA) };
+        if start < 0 {
+            let err = std::io::Error::last_os_error();
+            // No more data after `pos`.
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                break;
+            }
+            return Err(err);
+        }
+        let end = unsafe { libc::lseek64(fd, start, libc::SEEK_HOLE) };
+        if end < 0 {
+            return Err(last_error!("failed to seek hole"));
+        }
+        if start as u64 >= size {
+            break;
+        }
+        extents.push((start as u64, std::cmp::min(end as u64, size)));
+        pos = end as u64;
+    }
+
+    Ok(extents)
+}
+
+/// Check whether range `[start, end)` overlaps any of the sorted `extents`.
+pub(crate) fn overlaps_extents(extents: &[(u64, u64)], start: u64, end: u64) -> bool {
+    let idx = extents.partition_point(|e| e.1 <= start);
+    idx < extents.len() && extents[idx].0 < end
+}
+
+/// Remove chunk map and blob meta files without the cache file, and empty directories under
+/// `dir` if `remove_empty_dirs`, return whether `dir` is empty.
+pub(crate) fn remove_orphan_files(
+    dir: &Path,
+    remove_empty_dirs: bool,
+    removed: &mut Vec<String>,
+) -> Result<bool> {
+    let mut empty = true;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if remove_orphan_files(&path, remove_empty_dirs, removed)?
+                && remove_empty_dirs
+                && is_old_enough(&path)
+                && fs::remove_dir(&path).is_ok()
+            {
+                info!("blobcache: removed empty cache directory {:?}", path);
+                removed.push(path.display().to_string());
+                continue;
+            }
+        } else if file_type.is_file() {
+            let name = path.display().to_string();
+            let orphan = ASSOCIATED_FILE_SUFFIXES
+                .iter()
+                .filter(|s| !s.is_empty())
+                .any(|s| name.ends_with(s) && !Path::new(&name[..name.len() - s.len()]).exists());
+            if orphan && fs::remove_file(&path).is_ok() {
+                info!("blobcache: removed orphan cache file {}", name);
+                removed.push(name);
+                continue;
+            }
+        }
+        empty = false;
+    }
+
+    Ok(empty)
+}
+
+fn is_old_enough(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .map(|age| age >= EMPTY_DIR_MIN_AGE)
+        .unwrap_or(false)
+}
+
+/// Background thread to compact cache files of a blob cache manager periodically.
+pub(crate) struct CacheCompactor {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CacheCompactor {
+    /// Start to compact cache files of `mgr` every `interval` in background.
+    pub fn start(mgr: FileCacheMgr, interval: Duration) -> Result<Self> {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop2 = stop.clone();
+        let handle = thread::Builder::new()
+            .name("cache-compactor".to_string())
+            .spawn(move || {
+                let (lock, cvar) = &*stop2;
+                let mut stopped = lock.lock().unwrap();
+                while !*stopped {
+                    stopped = cvar.wait_timeout(stopped, interval).unwrap().0;
+                    if *stopped {
+                        break;
+                    }
+                    drop(stopped);
+                    let stat: CacheCompactStat = mgr.compact();
+                    debug!(
+                        "blobcache: compacted cache files, punched {} bytes, removed {} files",
+                        stat.punched_bytes,
+                        stat.removed.len()
+                    );
+                    stopped = lock.lock().unwrap();
+                }
+            })?;
+
+        Ok(CacheCompactor {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop the background thread and wait for it to exit.
+    pub fn stop(&mut self) {
+        let (lock, cvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("blobcache: failed to join cache compactor");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::filecache::evict::punch_hole;
+    use std::fs::OpenOptions;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_data_extents() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("blob");
+        fs::write(&path, vec![0x5au8; 0x4000]).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        punch_hole(&file, 0x1000, 0x2000).unwrap();
+
+        let extents = data_extents(&file, 0x4000).unwrap();
+        assert_eq!(extents, vec![(0, 0x1000), (0x3000, 0x4000)]);
+        assert!(overlaps_extents(&extents, 0, 0x1000));
+        assert!(!overlaps_extents(&extents, 0x1000, 0x3000));
+        assert!(overlaps_extents(&extents, 0x2000, 0x3001));
+        assert!(!overlaps_extents(&extents, 0x4000, 0x5000));
+        assert_eq!(data_extents(&file, 0x800).unwrap(), vec![(0, 0x800)]);
+    }
+
+    #[test]
+    fn test_remove_orphan_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        fs::create_dir(dir.join("ab")).unwrap();
+        fs::write(dir.join("blob1"), b"data").unwrap();
+        fs::write(dir.join("blob1.chunk_map"), b"map").unwrap();
+        fs::write(dir.join("blob2.chunk_map"), b"map").unwrap();
+        fs::write(dir.join("ab/blob3.blob.meta"), b"meta").unwrap();
+
+        let mut removed = Vec::new();
+        assert!(!remove_orphan_files(dir, true, &mut removed).unwrap());
+        assert_eq!(removed.len(), 2);
+        assert!(dir.join("blob1.chunk_map").exists());
+        assert!(!dir.join("blob2.chunk_map").exists());
+        assert!(!dir.join("ab/blob3.blob.meta").exists());
+        // The empty directory is kept since it's just modified.
+        assert!(dir.join("ab").exists());
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Punch holes in cache files for cold chunks.
+//!
+//! Cache files are dense, so removing a whole cache file to reclaim space also drops its hot data.
+//! Instead, the last access time of each cached chunk is tracked, and holes are punched by
+//! `fallocate(FALLOC_FL_PUNCH_HOLE)` for chunks not accessed for a while, with their ready bits
+//! cleared so they will be fetched from the storage backend again on demand.
+//!
+//! Only uncompressed cache files with indexed chunk maps are supported, where each chunk has a
+//! fixed location in the cache file. Accesses from other processes sharing the cache files are not
+//! tracked.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Result;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::cache::filecache::cache_entry::FileCacheEntry;
+use crate::device::BlobChunkInfo;
+
+// Maximum interval for the evictor to check for cold chunks.
+const EVICT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct ChunkAccessSlot {
+    // Seconds since creation of the table plus one when the chunk was last accessed, zero if the
+    // chunk hasn't been accessed yet.
+    last_access: AtomicU32,
+    offset: AtomicU64,
+    size: AtomicU32,
+}
+
+/// Last access time and location in the cache file of each chunk, indexed by chunk index.
+pub(crate) struct ChunkAccessTable {
+    base: Instant,
+    slots: Vec<ChunkAccessSlot>,
+}
+
+impl ChunkAccessTable {
+    pub fn new(chunk_count: u32) -> Self {
+        let mut slots = Vec::with_capacity(chunk_count as usize);
+        slots.resize_with(chunk_count as usize, Default::default);
+
+        ChunkAccessTable {
+            base: Instant::now(),
+            slots,
+        }
+    }
+
+    /// Record an access to the chunk, which must happen before checking whether it's ready.
+    pub fn touch(&self, chunk: &dyn BlobChunkInfo) {
+        if let Some(slot) = self.slots.get(chunk.id() as usize) {
+            if slot.last_access.load(Ordering::Acquire) == 0 {
+                slot.offset
+                    .store(chunk.uncompress_offset(), Ordering::Relaxed);
+                slot.size.store(chunk.uncompress_size(), Ordering::Relaxed);
+            }
+            slot.last_access.store(self.now(), Ordering::Release);
+        }
+    }
+
+    /// Get indexes and access stamps of chunks which haven't been accessed for `cold`.
+    pub fn cold_chunks(&self, cold: Duration) -> Vec<(u32, u32)> {
+        let now = self.now() as u64;
+        let cold = cold.as_secs();
+
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| {
+                let stamp = slot.last_access.load(Ordering::Acquire);
+                if stamp != 0 && now.saturating_sub(stamp as u64) >= cold {
+                    Some((idx as u32, stamp))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Check whether the chunk has been accessed since `stamp` returned by `cold_chunks()`.
+    pub fn is_touched_since(&self, index: u32, stamp: u32) -> bool {
+        self.slots[index as usize]
+            .last_access
+            .load(Ordering::Acquire)
+            != stamp
+    }
+
+    /// Get offset and size of the chunk in the cache file.
+    pub fn extent(&self, index: u32) -> (u64, u32) {
+        let slot = &self.slots[index as usize];
+        (
+            slot.offset.load(Ordering::Relaxed),
+            slot.size.load(Ordering::Relaxed),
+        )
+    }
+
+    fn now(&self) -> u32 {
+        std::cmp::min(self.base.elapsed().as_secs(), u32::MAX as u64 - 1) as u32 + 1
+    }
+}
+
+/// Deallocate space of the range [offset, offset + size) in the file, keeping the file size.
+pub(crate) fn punch_hole(file: &File, offset: u64, size: u32) -> Result<()> {
+    // Safe because the fd is valid and fallocate() doesn't touch memory.
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            size as libc::off_t,
+        )
+    };
+    if ret < 0 {
+        return Err(last_error!(format!(
+            "failed to punch hole at offset {} size {}",
+            offset, size
+        )));
+    }
+
+    Ok(())
+}
+
+/// Background thread to punch holes for cold chunks of all blobs of a blob cache manager.
+pub(crate) struct ChunkEvictor {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ChunkEvictor {
+    /// Start to punch holes for chunks not accessed for `cold` in background.
+    pub fn start(
+        blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
+        cold: Duration,
+    ) -> Result<Self> {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let interval = std::cmp::min(
+            std::cmp::max(cold / 2, Duration::from_secs(1)),
+            EVICT_CHECK_INTERVAL,
+        );
+        let stop2 = stop.clone();
+        let handle = thread::Builder::new()
+            .name("cache-evictor".to_string())
+            .spawn(move || {
+                let (lock, cvar) = &*stop2;
+                let mut stopped = lock.lock().unwrap();
+                while !*stopped {
+                    stopped = cvar.wait_timeout(stopped, interval).unwrap().0;
+                    if *stopped {
+                        break;
+                    }
+                    drop(stopped);
+                    let entries: Vec<Arc<FileCacheEntry>> =
+                        blobs.read().unwrap().values().cloned().collect();
+                    for entry in entries {
+                        if let Err(e) = entry.punch_cold_chunks(cold) {
+                            warn!("blobcache: failed to punch holes for cold chunks, {}", e);
+                        }
+                    }
+                    stopped = lock.lock().unwrap();
+                }
+            })?;
+
+        Ok(ChunkEvictor {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop the background thread and wait for it to exit.
+    pub fn stop(&mut self) {
+        let (lock, cvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("blobcache: failed to join cache evictor");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockChunkInfo;
+    use std::fs::OpenOptions;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_chunk_access_table() {
+        let table = ChunkAccessTable::new(4);
+        let chunk = MockChunkInfo {
+            index: 1,
+            uncompress_offset: 0x1000,
+            uncompress_size: 0x1000,
+            ..Default::default()
+        };
+        assert!(table.cold_chunks(Duration::from_secs(0)).is_empty());
+
+        table.touch(&chunk);
+        let cold = table.cold_chunks(Duration::from_secs(0));
+        assert_eq!(cold.len(), 1);
+        assert_eq!(cold[0].0, 1);
+        assert!(!table.is_touched_since(1, cold[0].1));
+        assert_eq!(table.extent(1), (0x1000, 0x1000));
+        assert!(table.cold_chunks(Duration::from_secs(3600)).is_empty());
+    }
+
+    #[test]
+    fn test_punch_hole() {
+        let tmp_file = TempFile::new().unwrap();
+        std::fs::write(tmp_file.as_path(), vec![0x5au8; 0x3000]).unwrap();
+        let file = OpenOptions::new()
+            .write(true)
+            .open(tmp_file.as_path())
+            .unwrap();
+
+        punch_hole(&file, 0x1000, 0x1000).unwrap();
+        let data = std::fs::read(tmp_file.as_path()).unwrap();
+        assert_eq!(data.len(), 0x3000);
+        assert!(data[..0x1000].iter().all(|v| *v == 0x5a));
+        assert!(data[0x1000..0x2000].iter().all(|v| *v == 0));
+        assert!(data[0x2000..].iter().all(|v| *v == 0x5a));
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Download whole blobs when range requests to the storage backend keep failing.
+//!
+//! Some storage backends throttle range requests, so chunks can't be fetched on demand. Once the
+//! number of failed range reads for a blob reaches the configured threshold, the blob is
+//! downloaded once in background, streaming into a `.blob.data` file next to the cache file in a
+//! sequential pass of large requests. Further reads of the blob are then served from the local
+//! copy, which is kept with the cache file and reused after restart if it's complete.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Result;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use nydus_utils::metrics::{BackendMetrics, BackendRequestClass, BlobcacheMetrics, Metric};
+use tokio::runtime::Runtime;
+
+use crate::backend::{BackendResult, BlobReader, RequestClassGuard, RequestPolicy};
+use crate::utils::alloc_buf;
+
+/// Suffix of the file to store the downloaded blob, next to the cache file.
+pub(crate) const WHOLE_BLOB_FILE_SUFFIX: &str = ".blob.data";
+// Size of each request to download the whole blob.
+const WHOLE_BLOB_DOWNLOAD_SIZE: usize = 0x100_0000;
+
+const STATE_IDLE: u8 = 0;
+const STATE_DOWNLOADING: u8 = 1;
+const STATE_READY: u8 = 2;
+
+/// Policy to download the whole blob after repeated failures of range reads.
+pub(crate) struct WholeBlobFallback {
+    path: String,
+    // Size of the blob, or zero if it should be queried from the storage backend.
+    blob_size: u64,
+    // Number of failed range reads to trigger downloading the whole blob.
+    threshold: u32,
+    failures: AtomicU32,
+    state: AtomicU8,
+    // Bumped when the local copy is discarded, to drop results of downloads in progress.
+    generation: AtomicU64,
+    local: Mutex<Option<Arc<File>>>,
+    metrics: Arc<BlobcacheMetrics>,
+    runtime: Arc<Runtime>,
+}
+
+impl WholeBlobFallback {
+    /// Create a fallback policy for the blob cached in `blob_file`, reusing a complete local copy
+    /// left by former instances.
+    pub fn new(
+        blob_file: &str,
+        blob_size: u64,
+        threshold: u32,
+        metrics: Arc<BlobcacheMetrics>,
+        runtime: Arc<Runtime>,
+    ) -> Arc<Self> {
+        let fallback = Arc::new(WholeBlobFallback {
+            path: format!("{}{}", blob_file, WHOLE_BLOB_FILE_SUFFIX),
+            blob_size,
+            threshold,
+            failures: AtomicU32::new(0),
+            state: AtomicU8::new(STATE_IDLE),
+            generation: AtomicU64::new(0),
+            local: Mutex::new(None),
+            metrics,
+            runtime,
+        });
+
+        if blob_size > 0 {
+            if let Ok(file) = File::open(&fallback.path) {
+                if file.metadata().map(|m| m.len()).ok() == Some(blob_size) {
+                    info!("blobcache: reuse downloaded blob file {}", fallback.path);
+                    *fallback.local.lock().unwrap() = Some(Arc::new(file));
+                    fallback.state.store(STATE_READY, Ordering::Release);
+                }
+            }
+        }
+
+        fallback
+    }
+
+    /// Check whether the whole blob has been downloaded.
+    pub fn is_ready(&self) -> bool {
+        self.state.load(Ordering::Acquire) == STATE_READY
+    }
+
+    /// Get a reader to read from the local copy if the whole blob has been downloaded, or from
+    /// the storage backend `reader` with failures counted otherwise.
+    pub fn wrap(self: &Arc<Self>, reader: Arc<dyn BlobReader>) -> Arc<dyn BlobReader> {
+        let local = if self.is_ready() {
+            self.local.lock().unwrap().clone()
+        } else {
+            None
+        };
+
+        Arc::new(FallbackReader {
+            inner: reader,
+            local,
+            fallback: self.clone(),
+        })
+    }
+
+    /// Discard the local copy and failure count, e.g. when switching to another backend object.
+    pub fn reset(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.failures.store(0, Ordering::Release);
+        if self
+            .state
+            .compare_exchange(STATE_READY, STATE_IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            *self.local.lock().unwrap() = None;
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn record_failure(self: &Arc<Self>, reader: &Arc<dyn BlobReader>) {
+        let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+        if failures < self.threshold
+            || self
+                .state
+                .compare_exchange(
+                    STATE_IDLE,
+                    STATE_DOWNLOADING,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+        {
+            return;
+        }
+
+        warn!(
+            "blobcache: {} range reads failed, download whole blob into {}",
+            failures, self.path
+        );
+        self.metrics.whole_blob_fallbacks.inc();
+        let fallback = self.clone();
+        let reader = reader.clone();
+        self.runtime
+            .spawn_blocking(move || fallback.download(reader));
+    }
+
+    fn download(&self, reader: Arc<dyn BlobReader>) {
+        // Download in background, in the same way as prefetch.
+        let _guard = RequestClassGuard::enter(BackendRequestClass::Prefetch);
+        let generation = self.generation.load(Ordering::Acquire);
+        let result = self.do_download(&reader);
+        let mut local = self.local.lock().unwrap();
+
+        match result {
+            Ok(file) if generation == self.generation.load(Ordering::Acquire) => {
+                info!("blobcache: downloaded whole blob into {}", self.path);
+                *local = Some(Arc::new(file));
+                self.metrics.whole_blob_downloaded.inc();
+                self.state.store(STATE_READY, Ordering::Release);
+            }
+            Ok(_) => {
+                info!("blobcache: discard outdated blob file {}", self.path);
+                let _ = fs::remove_file(&self.path);
+                self.state.store(STATE_IDLE, Ordering::Release);
+            }
+            Err(e) => {
+                // Retry after another round of failed range reads.
+                warn!(
+                    "blobcache: failed to download whole blob {}, {}",
+                    self.path, e
+                );
+                let _ = fs::remove_file(&self.path);
+                self.failures.store(0, Ordering::Release);
+                self.state.store(STATE_IDLE, Ordering::Release);
+            }
+        }
+    }
+
+    fn do_download(&self, reader: &Arc<dyn BlobReader>) -> Result<File> {
+        let blob_size = if self.blob_size > 0 {
+            self.blob_size
+        } else {
+            reader.blob_size().map_err(|e| eio!(e))?
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.metrics.whole_blob_download_total.add(blob_size);
+
+        let mut buf = alloc_buf(std::cmp::min(blob_size, WHOLE_BLOB_DOWNLOAD_SIZE as u64) as usize);
+        let mut offset = 0;
+        let result = loop {
+            if offset >= blob_size {
+                break Ok(());
+            }
+            let size = std::cmp::min(blob_size - offset, buf.len() as u64) as usize;
+            match reader.read(&mut buf[..size], offset) {
+                Ok(n) if n == size => {}
+                Ok(n) => {
+                    break Err(eio!(format!(
+                        "request for {} bytes at offset {} but got {} bytes",
+                        size, offset, n
+                    )))
+                }
+                Err(e) => break Err(eio!(e)),
+            }
+            if let Err(e) = file.write_all_at(&buf[..size], offset) {
+                break Err(e);
+            }
+            offset += size as u64;
+            self.metrics.whole_blob_download_progress.add(size as u64);
+        };
+
+        // Only account blobs being downloaded in progress metrics.
+        self.metrics.whole_blob_download_total.sub(blob_size);
+        self.metrics.whole_blob_download_progress.sub(offset);
+        result.map(|_| file)
+    }
+}
+
+// Reader to serve range reads from the local copy of the blob if available, and to count failures
+// of range reads from the storage backend.
+struct FallbackReader {
+    inner: Arc<dyn BlobReader>,
+    local: Option<Arc<File>>,
+    fallback: Arc<WholeBlobFallback>,
+}
+
+impl BlobReader for FallbackReader {
+    fn blob_size(&self) -> BackendResult<u64> {
+        self.inner.blob_size()
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        self.inner.try_read(buf, offset)
+    }
+
+    fn read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        if let Some(local) = self.local.as_ref() {
+            // Reads from the local copy are not accounted as backend reads.
+            match local.read_exact_at(buf, offset) {
+                Ok(_) => return Ok(buf.len()),
+                Err(e) => warn!(
+                    "blobcache: failed to read downloaded blob file {}, {}",
+                    self.fallback.path, e
+                ),
+            }
+        }
+
+        self.inner.read(buf, offset).map_err(|e| {
+            if self.local.is_none() {
+                self.fallback.record_failure(&self.inner);
+            }
+            e
+        })
+    }
+
+    fn prefetch_blob_data_range(&self, ra_offset: u32, ra_size: u32) -> BackendResult<()> {
+        if self.local.is_some() {
+            Ok(())
+        } else {
+            self.inner.prefetch_blob_data_range(ra_offset, ra_size)
+        }
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        self.inner.stop_data_prefetch()
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        self.inner.metrics()
+    }
+
+    fn retry_limit(&self) -> u8 {
+        self.inner.retry_limit()
+    }
+
+    fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        self.inner.request_policy(class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockBackend;
+    use std::thread;
+    use std::time::Duration;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn wait_for_ready(fallback: &WholeBlobFallback) -> bool {
+        for _ in 0..100 {
+            if fallback.is_ready() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn test_whole_blob_fallback() {
+        let tmp_dir = TempDir::new().unwrap();
+        let blob_file = tmp_dir.as_path().join("blob1").display().to_string();
+        let data: Vec<u8> = (0..0x10000u32).map(|v| (v % 251) as u8).collect();
+        let mut backend = MockBackend::with_data(
+            data.clone(),
+            BackendMetrics::new("test_whole_blob_fallback", "mock"),
+        );
+        backend.whole_blob_only = true;
+        let reader: Arc<dyn BlobReader> = Arc::new(backend);
+        let metrics = BlobcacheMetrics::new("test_whole_blob_fallback", "/tmp");
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .build()
+                .unwrap(),
+        );
+        let fallback = WholeBlobFallback::new(
+            &blob_file,
+            data.len() as u64,
+            2,
+            metrics.clone(),
+            runtime.clone(),
+        );
+
+        let mut buf = vec![0u8; 0x1000];
+        assert!(fallback
+            .wrap(reader.clone())
+            .read(&mut buf, 0x1000)
+            .is_err());
+        assert_eq!(metrics.whole_blob_fallbacks.count(), 0);
+        assert!(fallback
+            .wrap(reader.clone())
+            .read(&mut buf, 0x1000)
+            .is_err());
+        assert!(wait_for_ready(&fallback));
+        assert_eq!(metrics.whole_blob_fallbacks.count(), 1);
+        assert_eq!(metrics.whole_blob_downloaded.count(), 1);
+        assert_eq!(metrics.whole_blob_download_total.count(), 0);
+
+        assert_eq!(
+            fallback
+                .wrap(reader.clone())
+                .read(&mut buf, 0x1000)
+                .unwrap(),
+            0x1000
+        );
+        assert_eq!(buf, data[0x1000..0x2000]);
+
+        // A complete local copy is reused by new instances.
+        let fallback2 =
+            WholeBlobFallback::new(&blob_file, data.len() as u64, 2, metrics.clone(), runtime);
+        assert!(fallback2.is_ready());
+        fallback2.reset();
+        assert!(!fallback2.is_ready());
+        assert!(fallback2.wrap(reader).read(&mut buf, 0x1000).is_err());
+        assert!(fs::metadata(format!("{}{}", blob_file, WHOLE_BLOB_FILE_SUFFIX)).is_err());
+        metrics.release().unwrap();
+    }
+}
@@ -6,7 +6,8 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io::Result;
-use std::sync::{Arc, RwLock};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
 use tokio::runtime::{Builder, Runtime};
@@ -14,48 +15,184 @@ use tokio::runtime::{Builder, Runtime};
 use nydus_utils::metrics::BlobcacheMetrics;
 
 use self::cache_entry::FileCacheEntry;
-use crate::backend::BlobBackend;
+use self::compact::{remove_orphan_files, CacheCompactor};
+use self::evict::ChunkEvictor;
+use crate::backend::{BlobBackend, ErrnoMapping};
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncWorkerMgr};
-use crate::cache::{BlobCache, BlobCacheMgr};
+use crate::cache::{BlobCache, BlobCacheMgr, CacheCompactStat};
 use crate::device::BlobInfo;
 use crate::factory::CacheConfig;
 
 mod cache_entry;
+mod compact;
+mod evict;
+mod fallback;
+mod reclaim;
+
+pub(crate) use self::reclaim::reclaim_blob_files;
+use self::reclaim::reclaim_sparse_blob_files;
 
 fn default_work_dir() -> String {
     ".".to_string()
 }
 
+fn default_async_fetch_size() -> usize {
+    0x100000
+}
+
+fn default_compact_min_utilization() -> u32 {
+    10
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct BlobCacheConfig {
+    /// Directory to store cache files, `{id}` will be replaced by id of the blob cache manager,
+    /// which defaults to id of the filesystem instance, so each mount gets its own directory.
     #[serde(default = "default_work_dir")]
     work_dir: String,
+    /// Optional sub-directory of `work_dir` to store cache files for a blob, `{blob_id}` will be
+    /// replaced by the blob id and `{blob_id:N}` by the first N characters of the blob id.
+    #[serde(default)]
+    blob_dir: Option<String>,
+    /// Remove directories created for the blob cache manager when it's destroyed, e.g. when the
+    /// filesystem instance using it is umounted.
+    #[serde(default)]
+    cleanup_on_umount: bool,
+    /// Seconds to keep cache files of a blob after it's not referenced by any filesystem
+    /// instance, the cache files are kept until reclaimed on demand if not set.
+    #[serde(default)]
+    gc_grace_secs: Option<u64>,
     #[serde(default)]
     disable_indexed_map: bool,
+    /// Fetch data from the storage backend with concurrent requests for large reads.
+    #[serde(default)]
+    async_fetch: bool,
+    /// Maximum size of each concurrent request when `async_fetch` is enabled.
+    #[serde(default = "default_async_fetch_size")]
+    async_fetch_size: usize,
+    /// Seconds since last access after which a cached chunk is cold, holes are punched in cache
+    /// files for cold chunks to reclaim space. Only for uncompressed cache files with indexed
+    /// chunk maps.
+    #[serde(default)]
+    punch_cold_secs: Option<u64>,
+    /// Interval in seconds to compact cache files in background, cache files are only compacted
+    /// on demand if not set.
+    #[serde(default)]
+    compact_interval_secs: Option<u64>,
+    /// Unreferenced cache files with less than the percentage of space allocated are removed by
+    /// compaction, even if they are in grace period.
+    #[serde(default = "default_compact_min_utilization")]
+    compact_min_utilization: u32,
+    /// Number of failed range reads of a blob after which the whole blob is downloaded once into
+    /// the cache directory and further reads are served locally, 0 means disabled.
+    #[serde(default)]
+    whole_blob_fallback_threshold: u32,
 }
 
 impl BlobCacheConfig {
-    fn get_work_dir(&self) -> Result<&str> {
-        let path = fs::metadata(&self.work_dir)
-            .or_else(|_| {
-                fs::create_dir_all(&self.work_dir)?;
-                fs::metadata(&self.work_dir)
-            })
-            .map_err(|e| {
-                last_error!(format!(
-                    "fail to stat blobcache work_dir {}: {}",
-                    self.work_dir, e
+    // Get the work directory for blob cache manager `id`, and whether it's newly created.
+    fn get_work_dir(&self, id: &str) -> Result<(String, bool)> {
+        let id = sanitize_id(id);
+        let work_dir = expand_template(&self.work_dir, &[("id", id.as_str())])?;
+        let created = create_dir(&work_dir)?;
+
+        Ok((work_dir, created))
+    }
+
+    // Get the sub-directory of work directory to store cache files for blob `blob_id`.
+    fn get_blob_dir(&self, blob_id: &str) -> Result<Option<String>> {
+        match self.blob_dir.as_ref() {
+            None => Ok(None),
+            Some(template) => {
+                let dir = expand_template(template, &[("blob_id", blob_id)])?;
+                if dir.is_empty() || Path::new(&dir).is_absolute() || dir.contains("..") {
+                    return Err(einval!(format!(
+                        "invalid blobcache blob_dir {}, should be a relative path",
+                        template
+                    )));
+                }
+                Ok(Some(dir))
+            }
+        }
+    }
+}
+
+// Make the id usable as a directory name, e.g. "/mnt/rafs" => "mnt_rafs".
+fn sanitize_id(id: &str) -> String {
+    let id = id
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+        .collect::<String>();
+    if id.is_empty() || id == "." || id == ".." {
+        "default".to_string()
+    } else {
+        id
+    }
+}
+
+// Replace `{name}` and `{name:N}` in the template with values of the variables.
+fn expand_template(template: &str, vars: &[(&str, &str)]) -> Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut remain = template;
+
+    while let Some(start) = remain.find('{') {
+        result.push_str(&remain[..start]);
+        let end = remain[start..]
+            .find('}')
+            .ok_or_else(|| einval!(format!("unclosed placeholder in template {}", template)))?;
+        let placeholder = &remain[start + 1..start + end];
+        let (name, len) = match placeholder.find(':') {
+            None => (placeholder, None),
+            Some(pos) => {
+                let len = placeholder[pos + 1..].parse::<usize>().map_err(|_| {
+                    einval!(format!(
+                        "invalid placeholder {{{}}} in template",
+                        placeholder
+                    ))
+                })?;
+                (&placeholder[..pos], Some(len))
+            }
+        };
+        let value = vars
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| {
+                einval!(format!(
+                    "unknown placeholder {{{}}} in template {}",
+                    placeholder, template
                 ))
             })?;
-
-        if path.is_dir() {
-            Ok(&self.work_dir)
-        } else {
-            Err(enoent!(format!(
-                "blobcache work_dir {} is not a directory",
-                self.work_dir
-            )))
+        match len {
+            Some(len) => result.extend(value.chars().take(len)),
+            None => result.push_str(value),
         }
+        remain = &remain[start + end + 1..];
+    }
+    result.push_str(remain);
+
+    Ok(result)
+}
+
+// Create directory `dir` if it doesn't exist yet, return true if it's newly created.
+fn create_dir(dir: &str) -> Result<bool> {
+    let mut created = false;
+    let path = fs::metadata(dir)
+        .or_else(|_| {
+            fs::create_dir_all(dir)?;
+            created = true;
+            fs::metadata(dir)
+        })
+        .map_err(|e| last_error!(format!("fail to stat blobcache directory {}: {}", dir, e)))?;
+
+    if path.is_dir() {
+        Ok(created)
+    } else {
+        Err(enoent!(format!(
+            "blobcache directory {} is not a directory",
+            dir
+        )))
     }
 }
 
@@ -70,9 +207,23 @@ pub struct FileCacheMgr {
     runtime: Arc<Runtime>,
     worker_mgr: Arc<AsyncWorkerMgr>,
     work_dir: String,
+    blob_config: Arc<BlobCacheConfig>,
+    errno_mapping: Arc<ErrnoMapping>,
+    // Directories created by the manager, to be removed on destroy if `cleanup_on_umount`.
+    created_dirs: Arc<Mutex<Vec<String>>>,
     validate: bool,
     disable_indexed_map: bool,
     is_compressed: bool,
+    // Size to split backend requests when fetching data concurrently, 0 means disabled.
+    async_fetch_size: usize,
+    // Punch holes for chunks not accessed for the duration if set.
+    punch_cold_secs: Option<u64>,
+    // Download whole blobs after the number of failed range reads if not zero.
+    whole_blob_fallback_threshold: u32,
+    evictor: Arc<Mutex<Option<ChunkEvictor>>>,
+    compactor: Arc<Mutex<Option<CacheCompactor>>>,
+    // Serialize creating blob directories and removing empty ones.
+    dir_lock: Arc<Mutex<()>>,
 }
 
 impl FileCacheMgr {
@@ -84,8 +235,17 @@ impl FileCacheMgr {
     ) -> Result<FileCacheMgr> {
         let blob_config: BlobCacheConfig =
             serde_json::from_value(config.cache_config).map_err(|e| einval!(e))?;
-        let work_dir = blob_config.get_work_dir()?;
-        let metrics = BlobcacheMetrics::new(id, work_dir);
+        // Validate the template for blob directories early.
+        blob_config.get_blob_dir(&"0".repeat(64))?;
+        if blob_config.punch_cold_secs == Some(0) {
+            return Err(einval!("blobcache punch_cold_secs must be positive"));
+        }
+        if blob_config.compact_interval_secs == Some(0) {
+            return Err(einval!("blobcache compact_interval_secs must be positive"));
+        }
+        config.errno_mapping.validate()?;
+        let (work_dir, created) = blob_config.get_work_dir(id)?;
+        let metrics = BlobcacheMetrics::new(id, &work_dir);
         let runtime = Arc::new(
             Builder::new_multi_thread()
                 .worker_threads(1) // Limit the number of worker thread to 1 since this runtime is generally used to do blocking IO.
@@ -105,13 +265,64 @@ impl FileCacheMgr {
             prefetch_config,
             runtime,
             worker_mgr: Arc::new(worker_mgr),
-            work_dir: work_dir.to_owned(),
+            created_dirs: Arc::new(Mutex::new(if created {
+                vec![work_dir.clone()]
+            } else {
+                Vec::new()
+            })),
+            work_dir,
+            blob_config: Arc::new(blob_config.clone()),
+            errno_mapping: Arc::new(config.errno_mapping),
             disable_indexed_map: blob_config.disable_indexed_map,
             validate: config.cache_validate,
             is_compressed: config.cache_compressed,
+            async_fetch_size: if blob_config.async_fetch {
+                blob_config.async_fetch_size
+            } else {
+                0
+            },
+            punch_cold_secs: blob_config.punch_cold_secs,
+            whole_blob_fallback_threshold: blob_config.whole_blob_fallback_threshold,
+            evictor: Arc::new(Mutex::new(None)),
+            compactor: Arc::new(Mutex::new(None)),
+            dir_lock: Arc::new(Mutex::new(())),
         })
     }
 
+    // Get path of the cache file for blob `blob_id`, creating its parent directory if needed.
+    fn get_blob_file_path(&self, blob_id: &str) -> Result<String> {
+        match self.blob_config.get_blob_dir(blob_id)? {
+            None => Ok(format!("{}/{}", self.work_dir, blob_id)),
+            Some(dir) => {
+                let dir = format!("{}/{}", self.work_dir, dir);
+                let _guard = self.dir_lock.lock().unwrap();
+                if create_dir(&dir)? {
+                    self.created_dirs.lock().unwrap().push(dir.clone());
+                }
+                Ok(format!("{}/{}", dir, blob_id))
+            }
+        }
+    }
+
+    // Get the grace period to keep cache files of unreferenced blobs.
+    fn get_gc_grace_period(&self) -> Option<Duration> {
+        self.blob_config.gc_grace_secs.map(Duration::from_secs)
+    }
+
+    // Remove directories created by the manager, in reverse order of creation.
+    fn cleanup(&self) {
+        let dirs = std::mem::take(&mut *self.created_dirs.lock().unwrap());
+        for dir in dirs.iter().rev() {
+            if !Path::new(dir).exists() {
+                continue;
+            }
+            match fs::remove_dir_all(dir) {
+                Ok(_) => info!("blobcache: removed cache directory {}", dir),
+                Err(e) => warn!("blobcache: failed to remove cache directory {}, {}", dir, e),
+            }
+        }
+    }
+
     // Get the file cache entry for the specified blob object.
     fn get(&self, blob: &Arc<BlobInfo>) -> Option<Arc<FileCacheEntry>> {
         self.blobs.read().unwrap().get(blob.blob_id()).cloned()
@@ -149,16 +360,35 @@ impl FileCacheMgr {
 
 impl BlobCacheMgr for FileCacheMgr {
     fn init(&self) -> Result<()> {
-        AsyncWorkerMgr::start(self.worker_mgr.clone())
+        AsyncWorkerMgr::start(self.worker_mgr.clone())?;
+        if let Some(secs) = self.punch_cold_secs {
+            let evictor = ChunkEvictor::start(self.blobs.clone(), Duration::from_secs(secs))?;
+            *self.evictor.lock().unwrap() = Some(evictor);
+        }
+        if let Some(secs) = self.blob_config.compact_interval_secs {
+            let compactor = CacheCompactor::start(self.clone(), Duration::from_secs(secs))?;
+            *self.compactor.lock().unwrap() = Some(compactor);
+        }
+
+        Ok(())
     }
 
     fn destroy(&self) {
+        if let Some(mut compactor) = self.compactor.lock().unwrap().take() {
+            compactor.stop();
+        }
+        if let Some(mut evictor) = self.evictor.lock().unwrap().take() {
+            evictor.stop();
+        }
         self.worker_mgr.stop();
         self.backend().shutdown();
         self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+        if self.blob_config.cleanup_on_umount {
+            self.cleanup();
+        }
     }
 
-    fn gc(&self) {
+    fn gc(&self) -> bool {
         let mut reclaim = Vec::new();
 
         {
@@ -179,6 +409,38 @@ impl BlobCacheMgr for FileCacheMgr {
             }
             guard.remove(key);
         }
+
+        self.blobs.read().unwrap().is_empty()
+    }
+
+    fn compact(&self) -> CacheCompactStat {
+        let mut stat = CacheCompactStat::default();
+
+        let entries: Vec<Arc<FileCacheEntry>> =
+            self.blobs.read().unwrap().values().cloned().collect();
+        for entry in entries {
+            match entry.compact() {
+                Ok(size) => stat.punched_bytes += size,
+                Err(e) => warn!("blobcache: failed to compact cache file, {}", e),
+            }
+        }
+
+        stat.removed =
+            reclaim_sparse_blob_files(&self.work_dir, self.blob_config.compact_min_utilization);
+        let _guard = self.dir_lock.lock().unwrap();
+        let remove_empty_dirs = self.blob_config.blob_dir.is_some();
+        if let Err(e) = remove_orphan_files(
+            Path::new(&self.work_dir),
+            remove_empty_dirs,
+            &mut stat.removed,
+        ) {
+            warn!(
+                "blobcache: failed to remove orphan files in {}, {}",
+                self.work_dir, e
+            );
+        }
+
+        stat
     }
 
     fn backend(&self) -> &(dyn BlobBackend) {
@@ -248,7 +510,62 @@ pub mod blob_cache_tests {
         let tmp_file = TempFile::new().unwrap();
         let file = tmp_file.as_path().to_path_buf();
         blob_config.work_dir = file.to_str().unwrap().to_owned();
-        assert!(blob_config.get_work_dir().is_err());
+        assert!(blob_config.get_work_dir("id").is_err());
+    }
+
+    #[test]
+    fn test_blob_cache_dir_template() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path().to_str().unwrap().to_owned();
+        let s = format!(
+            r###"
+        {{
+            "work_dir": "{}/{{id}}",
+            "blob_dir": "{{blob_id:2}}"
+        }}
+        "###,
+            dir
+        );
+
+        let blob_config: BlobCacheConfig = serde_json::from_str(&s).unwrap();
+        assert!(!blob_config.cleanup_on_umount);
+        let (work_dir, created) = blob_config.get_work_dir("/mnt/rafs").unwrap();
+        assert_eq!(work_dir, format!("{}/mnt_rafs", dir));
+        assert!(created);
+        let (_, created) = blob_config.get_work_dir("/mnt/rafs").unwrap();
+        assert!(!created);
+        assert_eq!(
+            blob_config.get_blob_dir("abcdef").unwrap(),
+            Some("ab".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let vars = [("id", "mnt"), ("blob_id", "abcdef")];
+        assert_eq!(expand_template("/cache", &vars).unwrap(), "/cache");
+        assert_eq!(expand_template("/cache/{id}", &vars).unwrap(), "/cache/mnt");
+        assert_eq!(
+            expand_template("{blob_id:3}/{blob_id:10}", &vars).unwrap(),
+            "abc/abcdef"
+        );
+        assert!(expand_template("/cache/{id", &vars).is_err());
+        assert!(expand_template("/cache/{unknown}", &vars).is_err());
+        assert!(expand_template("/cache/{id:x}", &vars).is_err());
+
+        assert_eq!(sanitize_id("/mnt/rafs/"), "mnt_rafs");
+        assert_eq!(sanitize_id("/"), "default");
+        assert_eq!(sanitize_id(""), "default");
+    }
+
+    #[test]
+    fn test_invalid_blob_dir() {
+        let mut blob_config: BlobCacheConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(blob_config.get_blob_dir("abc").unwrap(), None);
+        blob_config.blob_dir = Some("/{blob_id}".to_string());
+        assert!(blob_config.get_blob_dir("abc").is_err());
+        blob_config.blob_dir = Some("../{blob_id}".to_string());
+        assert!(blob_config.get_blob_dir("abc").is_err());
     }
 
     /*
@@ -0,0 +1,288 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reclaim cache files of blobs which are not referenced by any filesystem instance.
+//!
+//! Cache files of a blob may be shared by multiple blob cache managers, e.g. when the same image
+//! layer is used by multiple mounts, so references to cache files are tracked by file path across
+//! all blob cache managers. When the last reference to a blob is dropped, its cache files may be
+//! removed after a grace period, or on demand.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Suffixes of files associated with a blob cache file, e.g. chunk map, blob meta and the
+// downloaded blob.
+pub(crate) const ASSOCIATED_FILE_SUFFIXES: [&str; 4] =
+    ["", ".chunk_map", ".blob.meta", ".blob.data"];
+// Maximum interval for the reclaimer to check for expired cache files.
+const RECLAIM_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct BlobFileState {
+    refs: u32,
+    // Grace period before removing the cache files after the last reference is dropped,
+    // `None` means keeping the files until reclaimed on demand.
+    grace_period: Option<Duration>,
+    // When the last reference was dropped.
+    released_at: Option<Instant>,
+}
+
+impl BlobFileState {
+    fn is_expired(&self, now: Instant) -> bool {
+        match (self.released_at, self.grace_period) {
+            (Some(released), Some(grace)) => self.refs == 0 && now >= released + grace,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct BlobFileTable {
+    files: HashMap<String, BlobFileState>,
+    // Whether the background reclaimer thread is running.
+    reclaimer_running: bool,
+}
+
+impl BlobFileTable {
+    // Get the earliest time when an unreferenced blob becomes expired.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.files
+            .values()
+            .filter(|s| s.refs == 0)
+            .filter_map(|s| match (s.released_at, s.grace_period) {
+                (Some(released), Some(grace)) => Some(released + grace),
+                _ => None,
+            })
+            .min()
+    }
+
+    // Remove cache files for unreferenced blobs, those in grace period are kept unless `force`.
+    fn reclaim(&mut self, force: bool) -> Vec<String> {
+        let now = Instant::now();
+        let victims = self
+            .files
+            .iter()
+            .filter(|(_, s)| s.refs == 0 && (force || s.is_expired(now)))
+            .map(|(path, _)| path.to_owned())
+            .collect::<Vec<_>>();
+
+        for path in victims.iter() {
+            self.files.remove(path);
+            remove_blob_files(path);
+        }
+
+        victims
+    }
+
+    // Remove cache files under `dir` for unreferenced blobs with less than `min_utilization`
+    // percent of space allocated, even if they are in grace period.
+    fn reclaim_sparse(&mut self, dir: &str, min_utilization: u32) -> Vec<String> {
+        let victims = self
+            .files
+            .iter()
+            .filter(|(path, s)| {
+                s.refs == 0
+                    && Path::new(path).starts_with(dir)
+                    && space_utilization(path).map_or(false, |u| u < min_utilization)
+            })
+            .map(|(path, _)| path.to_owned())
+            .collect::<Vec<_>>();
+
+        for path in victims.iter() {
+            self.files.remove(path);
+            remove_blob_files(path);
+        }
+
+        victims
+    }
+}
+
+// Get percentage of allocated space to size of the file.
+fn space_utilization(path: &str) -> Option<u32> {
+    let md = fs::metadata(path).ok()?;
+    if md.len() == 0 {
+        return Some(0);
+    }
+    let allocated = md.blocks() * 512;
+    Some(std::cmp::min(allocated * 100 / md.len(), 100) as u32)
+}
+
+lazy_static::lazy_static! {
+    static ref BLOB_FILES: Mutex<BlobFileTable> = Mutex::new(BlobFileTable::default());
+}
+
+/// Reference to cache files of a blob, the reference is dropped when the object is dropped.
+pub(crate) struct BlobFileRef {
+    path: String,
+}
+
+impl BlobFileRef {
+    /// Take a reference to cache files at `path`, which may be removed `grace_period` after the
+    /// last reference is dropped.
+    pub fn new(path: &str, grace_period: Option<Duration>) -> Self {
+        let mut table = BLOB_FILES.lock().unwrap();
+        let state = table.files.entry(path.to_owned()).or_default();
+        state.refs += 1;
+        state.released_at = None;
+        state.grace_period = grace_period;
+
+        BlobFileRef {
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl Drop for BlobFileRef {
+    fn drop(&mut self) {
+        let mut table = BLOB_FILES.lock().unwrap();
+        let need_reclaimer = match table.files.get_mut(&self.path) {
+            Some(state) => {
+                state.refs -= 1;
+                if state.refs == 0 {
+                    state.released_at = Some(Instant::now());
+                    state.grace_period.is_some()
+                } else {
+                    false
+                }
+            }
+            None => false,
+        };
+
+        if need_reclaimer && !table.reclaimer_running {
+            match thread::Builder::new()
+                .name("cache-reclaimer".to_string())
+                .spawn(reclaimer)
+            {
+                Ok(_) => table.reclaimer_running = true,
+                Err(e) => warn!("blobcache: failed to start cache reclaimer, {}", e),
+            }
+        }
+    }
+}
+
+// Remove expired cache files in background, exit when there's nothing to wait for.
+fn reclaimer() {
+    loop {
+        let timeout = {
+            let mut table = BLOB_FILES.lock().unwrap();
+            table.reclaim(false);
+            match table.next_deadline() {
+                None => {
+                    table.reclaimer_running = false;
+                    return;
+                }
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            }
+        };
+        thread::sleep(std::cmp::min(timeout, RECLAIM_CHECK_INTERVAL));
+    }
+}
+
+fn remove_blob_files(path: &str) {
+    for suffix in ASSOCIATED_FILE_SUFFIXES.iter() {
+        let file = format!("{}{}", path, suffix);
+        match fs::remove_file(&file) {
+            Ok(_) => info!("blobcache: removed unreferenced cache file {}", file),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("blobcache: failed to remove cache file {}, {}", file, e),
+        }
+    }
+}
+
+/// Remove cache files for blobs not referenced by any blob cache manager, and return paths of
+/// the removed blob cache files.
+///
+/// Cache files still in grace period are kept unless `force` is true.
+pub(crate) fn reclaim_blob_files(force: bool) -> Vec<String> {
+    BLOB_FILES.lock().unwrap().reclaim(force)
+}
+
+/// Remove cache files under `dir` for unreferenced blobs with less than `min_utilization`
+/// percent of space allocated, and return paths of the removed blob cache files.
+pub(crate) fn reclaim_sparse_blob_files(dir: &str, min_utilization: u32) -> Vec<String> {
+    BLOB_FILES
+        .lock()
+        .unwrap()
+        .reclaim_sparse(dir, min_utilization)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_reclaim_blob_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = format!("{}/blob1", tmp_dir.as_path().to_str().unwrap());
+        fs::write(&path, b"data").unwrap();
+        fs::write(format!("{}.chunk_map", path), b"map").unwrap();
+
+        let ref1 = BlobFileRef::new(&path, None);
+        let ref2 = BlobFileRef::new(&path, None);
+        drop(ref1);
+        assert!(!reclaim_blob_files(true).contains(&path));
+        assert!(fs::metadata(&path).is_ok());
+
+        // Unreferenced files without grace period are only removed on demand.
+        drop(ref2);
+        assert!(!reclaim_blob_files(false).contains(&path));
+        assert!(reclaim_blob_files(true).contains(&path));
+        assert!(fs::metadata(&path).is_err());
+        assert!(fs::metadata(format!("{}.chunk_map", path)).is_err());
+    }
+
+    #[test]
+    fn test_blob_file_grace_period() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = format!("{}/blob2", tmp_dir.as_path().to_str().unwrap());
+        fs::write(&path, b"data").unwrap();
+
+        let now = Instant::now();
+        let mut state = BlobFileState {
+            refs: 0,
+            grace_period: Some(Duration::from_secs(3600)),
+            released_at: Some(now),
+        };
+        assert!(!state.is_expired(now));
+        assert!(state.is_expired(now + Duration::from_secs(3600)));
+        state.refs = 1;
+        assert!(!state.is_expired(now + Duration::from_secs(3600)));
+
+        // Files are kept while still referenced by others.
+        let _ref = BlobFileRef::new(&path, Some(Duration::from_secs(3600)));
+        drop(BlobFileRef::new(&path, Some(Duration::from_secs(3600))));
+        assert!(!reclaim_blob_files(true).contains(&path));
+        assert!(fs::metadata(&path).is_ok());
+    }
+
+    #[test]
+    fn test_reclaim_sparse_blob_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path().to_str().unwrap();
+        let sparse = format!("{}/blob3", dir);
+        let dense = format!("{}/blob4", dir);
+        fs::File::create(&sparse)
+            .unwrap()
+            .set_len(0x100000)
+            .unwrap();
+        fs::write(&dense, vec![0x5au8; 0x10000]).unwrap();
+
+        drop(BlobFileRef::new(&sparse, Some(Duration::from_secs(3600))));
+        drop(BlobFileRef::new(&dense, Some(Duration::from_secs(3600))));
+        let removed = reclaim_sparse_blob_files(dir, 10);
+        assert!(removed.contains(&sparse));
+        assert!(!removed.contains(&dense));
+        assert!(fs::metadata(&sparse).is_err());
+        assert!(fs::metadata(&dense).is_ok());
+        assert!(reclaim_blob_files(true).contains(&dense));
+    }
+}
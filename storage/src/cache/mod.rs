@@ -20,15 +20,21 @@ use std::cmp;
 use std::fs::File;
 use std::io::Result;
 use std::slice;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use arc_swap::ArcSwap;
 use fuse_backend_rs::transport::FileVolatileSlice;
 
+pub use buffer_pool::start_buffer_pool;
+pub use decompress::start_decompress_pool;
 pub use dummycache::DummyCacheMgr;
+pub(crate) use filecache::reclaim_blob_files;
 pub use filecache::FileCacheMgr;
 use nydus_utils::digest;
+use nydus_utils::metrics;
+use nydus_utils::trace;
 
-use crate::backend::{BlobBackend, BlobReader};
+use crate::backend::{BackendResult, BlobBackend, BlobReader, ErrnoMapping};
 use crate::cache::state::ChunkMap;
 use crate::device::{
     BlobChunkInfo, BlobInfo, BlobIoChunk, BlobIoDesc, BlobIoRange, BlobIoVec, BlobObject,
@@ -37,6 +43,8 @@ use crate::device::{
 use crate::utils::{alloc_buf, digest_check};
 use crate::{compress, StorageResult, RAFS_MAX_CHUNK_SIZE};
 
+mod buffer_pool;
+mod decompress;
 mod dummycache;
 mod filecache;
 pub mod state;
@@ -125,6 +133,52 @@ pub struct BlobPrefetchConfig {
     pub bandwidth_rate: u32,
 }
 
+/// Reader to fetch blob data from storage backend, which may be switched at runtime.
+pub(crate) struct SwitchableReader {
+    backend: Mutex<Arc<dyn BlobBackend>>,
+    reader: ArcSwap<Arc<dyn BlobReader>>,
+}
+
+impl SwitchableReader {
+    pub fn new(backend: Arc<dyn BlobBackend>, reader: Arc<dyn BlobReader>) -> Self {
+        SwitchableReader {
+            backend: Mutex::new(backend),
+            reader: ArcSwap::new(Arc::new(reader)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<dyn BlobReader> {
+        (*self.reader.load_full()).clone()
+    }
+
+    /// Atomically switch to read from object `object_id` of `backend`, or of the current backend
+    /// if `backend` is None. The size of the new object is checked if `expected_size` isn't zero.
+    pub fn switch(
+        &self,
+        backend: Option<Arc<dyn BlobBackend>>,
+        object_id: &str,
+        expected_size: u64,
+    ) -> Result<()> {
+        let mut guard = self.backend.lock().unwrap();
+        let backend = backend.unwrap_or_else(|| guard.clone());
+        let reader = backend.get_reader(object_id).map_err(|e| eother!(e))?;
+        if expected_size != 0 {
+            let size = reader.blob_size().map_err(|e| eio!(e))?;
+            if size != expected_size {
+                return Err(einval!(format!(
+                    "size of object {} is {}, expect {}",
+                    object_id, size, expected_size
+                )));
+            }
+        }
+
+        self.reader.store(Arc::new(reader));
+        *guard = backend;
+
+        Ok(())
+    }
+}
+
 /// Trait representing a cache object for a blob on backend storage.
 ///
 /// The caller may use the `BlobCache` trait to access blob data on backend storage, with an
@@ -149,7 +203,17 @@ pub trait BlobCache: Send + Sync {
     fn need_validate(&self) -> bool;
 
     /// Get the [BlobReader](../backend/trait.BlobReader.html) to read data from storage backend.
-    fn reader(&self) -> &dyn BlobReader;
+    fn reader(&self) -> Arc<dyn BlobReader>;
+
+    /// Read a range of data [offset, offset + buf.len()) from the storage backend into the buffer.
+    ///
+    /// Implementations may split large requests and fetch data concurrently.
+    fn read_backend(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        self.reader().read(buf, offset)
+    }
+
+    /// Get the policy to convert storage backend errors into errno values.
+    fn errno_mapping(&self) -> &ErrnoMapping;
 
     /// Get the underlying `ChunkMap` object.
     fn get_chunk_map(&self) -> &Arc<dyn ChunkMap>;
@@ -159,6 +223,10 @@ pub trait BlobCache: Send + Sync {
         None
     }
 
+    /// Record an access to the chunk which is about to be read from the cache file directly,
+    /// e.g. to find cold chunks.
+    fn record_chunk_access(&self, _chunk: &dyn BlobChunkInfo) {}
+
     /// Start to prefetch requested data in background.
     fn prefetch(
         &self,
@@ -170,11 +238,50 @@ pub trait BlobCache: Send + Sync {
     /// Stop prefetching blob data in background.
     fn stop_prefetch(&self) -> StorageResult<()>;
 
+    /// Restart background prefetch workers after they have been stopped by `stop_prefetch()`.
+    fn restart_prefetch(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Flush data written into the blob cache to the underlying storage.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Execute filesystem data prefetch.
     fn prefetch_range(&self, _range: &BlobIoRange) -> Result<usize> {
         Err(enosys!("doesn't support prefetch_range()"))
     }
 
+    /// Verify cached data of the chunk, and invalidate the chunk if its data is corrupted.
+    ///
+    /// Returns `Ok(None)` if the chunk isn't cached yet, `Ok(Some(true))` if the cached data is
+    /// valid, and `Ok(Some(false))` if the cached data is corrupted and has been invalidated.
+    fn scrub_chunk(&self, _chunk: &BlobIoChunk) -> Result<Option<bool>> {
+        Err(enosys!("doesn't support scrub_chunk()"))
+    }
+
+    /// Discard cached data of the chunk, so it will be fetched from the storage backend again.
+    ///
+    /// Returns `Ok(false)` if the chunk isn't cached.
+    fn invalidate_chunk(&self, _chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        Err(enosys!("doesn't support invalidate_chunk()"))
+    }
+
+    /// Switch to fetch blob data from object `object_id` of storage backend `backend`, e.g. when
+    /// the blob has been relocated or mirrored.
+    ///
+    /// The current storage backend is kept if `backend` is None. Cached data is kept for use,
+    /// unless `flush_cache` is true.
+    fn switch_backend(
+        &self,
+        _backend: Option<Arc<dyn BlobBackend>>,
+        _object_id: &str,
+        _flush_cache: bool,
+    ) -> Result<()> {
+        Err(enosys!("doesn't support switch_backend()"))
+    }
+
     /// Read chunk data described by the blob Io descriptors from the blob cache into the buffer.
     fn read(&self, iovec: &BlobIoVec, buffers: &[FileVolatileSlice]) -> Result<usize>;
 
@@ -194,11 +301,15 @@ pub trait BlobCache: Send + Sync {
         chunks: &[BlobIoChunk],
     ) -> Result<Vec<Vec<u8>>> {
         // Read requested data from the backend by altogether.
-        let mut c_buf = alloc_buf(blob_size);
-        let nr_read = self
-            .reader()
-            .read(c_buf.as_mut_slice(), blob_offset)
-            .map_err(|e| eio!(e))?;
+        let mut c_buf = buffer_pool::alloc_buffer(blob_size);
+        let nr_read = {
+            let mut span = trace::span("backend_fetch");
+            span.arg("blob_offset", blob_offset);
+            span.arg("size", blob_size as u64);
+            self.read_backend(c_buf.as_mut_slice(), blob_offset)
+                .map_err(|e| self.errno_mapping().convert(e))?
+        };
+        metrics::account_backend_read(nr_read);
         if nr_read != blob_size {
             return Err(eio!(format!(
                 "request for {} bytes but got {} bytes",
@@ -263,14 +374,18 @@ pub trait BlobCache: Send + Sync {
             } else {
                 chunk.compress_size() as usize
             };
-            d = alloc_buf(c_size);
+            d = buffer_pool::alloc_buffer(c_size);
             d.as_mut_slice()
         } else {
             // We have this unsafe assignment as it can directly store data into call's buffer.
             unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr(), buffer.len()) }
         };
 
-        let size = self.reader().read(raw_chunk, offset).map_err(|e| eio!(e))?;
+        let size = self
+            .reader()
+            .read(raw_chunk, offset)
+            .map_err(|e| self.errno_mapping().convert(e))?;
+        metrics::account_backend_read(size);
         if size != raw_chunk.len() {
             return Err(eio!("storage backend returns less data than requested"));
         }
@@ -304,12 +419,21 @@ pub trait BlobCache: Send + Sync {
         force_validation: bool,
     ) -> Result<usize> {
         if need_decompress {
-            compress::decompress(raw_buffer, raw_stream, buffer, self.compressor()).map_err(
-                |e| {
-                    error!("failed to decompress chunk: {}", e);
-                    e
-                },
-            )?;
+            let mut span = trace::span("decompress");
+            span.arg("size", chunk.uncompress_size() as u64);
+            let result = match decompress::decompress_pool() {
+                Some(pool) => {
+                    // Queue requests by filesystem instance for fairness if known.
+                    let owner = metrics::current_accounting_id()
+                        .unwrap_or_else(|| self.blob_id().to_string());
+                    pool.decompress(&owner, raw_buffer, raw_stream, buffer, self.compressor())
+                }
+                None => compress::decompress(raw_buffer, raw_stream, buffer, self.compressor()),
+            };
+            result.map_err(|e| {
+                error!("failed to decompress chunk: {}", e);
+                e
+            })?;
         } else if raw_buffer.as_ptr() != buffer.as_ptr() {
             // raw_chunk and chunk may point to the same buffer, so only copy data when needed.
             buffer.copy_from_slice(raw_buffer);
@@ -339,8 +463,15 @@ pub trait BlobCacheMgr: Send + Sync {
     /// Tear down the blob cache manager.
     fn destroy(&self);
 
-    /// Garbage-collect unused resources.
-    fn gc(&self) {}
+    /// Garbage-collect unused resources, return true if the manager is not in use anymore.
+    fn gc(&self) -> bool {
+        false
+    }
+
+    /// Compact cache files managed by the manager to reclaim space and directory entries.
+    fn compact(&self) -> CacheCompactStat {
+        CacheCompactStat::default()
+    }
 
     /// Get the underlying `BlobBackend` object of the blob cache object.
     fn backend(&self) -> &(dyn BlobBackend);
@@ -349,6 +480,15 @@ pub trait BlobCacheMgr: Send + Sync {
     fn get_blob_cache(&self, blob_info: &Arc<BlobInfo>) -> Result<Arc<dyn BlobCache>>;
 }
 
+/// Result of compacting cache files.
+#[derive(Debug, Default, Serialize)]
+pub struct CacheCompactStat {
+    /// Bytes released by punching holes in cache files.
+    pub punched_bytes: u64,
+    /// Paths of removed cache files and directories.
+    pub removed: Vec<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::device::{BlobChunkFlags, BlobFeatures};
@@ -4,11 +4,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::io::Result;
-use std::sync::{Arc, Condvar, Mutex, WaitTimeoutResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, WaitTimeoutResult};
 use std::time::Duration;
 
 use crate::cache::state::{BlobRangeMap, ChunkIndexGetter, ChunkMap, IndexedChunkMap, RangeMap};
@@ -22,9 +24,15 @@ enum Status {
     Complete,
 }
 
+// Number of shards of the inflight tracer.
+const INFLIGHT_TRACER_SHARDS: usize = 64;
+
 struct Slot {
     state: Mutex<Status>,
     condvar: Condvar,
+    // Lockless copy of `state == Status::Complete`, so waiters woken up together don't serialize
+    // on the state lock.
+    complete: AtomicBool,
 }
 
 impl Slot {
@@ -32,6 +40,7 @@ impl Slot {
         Slot {
             state: Mutex::new(Status::Inflight),
             condvar: Condvar::new(),
+            complete: AtomicBool::new(false),
         }
     }
 
@@ -42,10 +51,15 @@ impl Slot {
     fn done(&self) {
         // Not expect poisoned lock here
         *self.state.lock().unwrap() = Status::Complete;
+        self.complete.store(true, Ordering::Release);
         self.notify();
     }
 
     fn wait_for_inflight(&self, timeout: Duration) -> StorageResult<Status> {
+        if self.complete.load(Ordering::Acquire) {
+            return Ok(Status::Complete);
+        }
+
         let mut state = self.state.lock().unwrap();
         let mut tor: WaitTimeoutResult;
 
@@ -63,6 +77,49 @@ impl Slot {
     }
 }
 
+/// Slots of chunks being filled, sharded by chunk index.
+///
+/// A hot chunk of a highly deduplicated image may be referenced by thousands of files, so
+/// requests for it shouldn't serialize on a single lock together with requests for other chunks.
+struct InflightTracer<I> {
+    shards: Vec<Mutex<HashMap<I, Arc<Slot>>>>,
+}
+
+impl<I: Eq + Hash> InflightTracer<I> {
+    fn new() -> Self {
+        let mut shards = Vec::with_capacity(INFLIGHT_TRACER_SHARDS);
+        for _ in 0..INFLIGHT_TRACER_SHARDS {
+            shards.push(Mutex::new(HashMap::new()));
+        }
+        InflightTracer { shards }
+    }
+
+    // Lock the shard containing `index`.
+    fn lock(&self, index: &I) -> MutexGuard<HashMap<I, Arc<Slot>>> {
+        let mut hasher = DefaultHasher::new();
+        index.hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.shards.len();
+        // Not expect poisoned lock here
+        self.shards[shard].lock().unwrap()
+    }
+
+    fn get(&self, index: &I) -> Option<Arc<Slot>> {
+        self.lock(index).get(index).cloned()
+    }
+
+    // Remove the slot of `index` and wake up its waiters.
+    fn remove(&self, index: &I) {
+        if let Some(i) = self.lock(index).remove(index) {
+            i.done();
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+}
+
 /// Adapter structure to enable concurrent chunk readiness manipulating based on a base [ChunkMap]
 /// object.
 ///
@@ -72,7 +129,7 @@ impl Slot {
 /// state manipulation.
 pub struct BlobStateMap<C, I> {
     c: C,
-    inflight_tracer: Mutex<HashMap<I, Arc<Slot>>>,
+    inflight_tracer: InflightTracer<I>,
 }
 
 impl<C, I> From<C> for BlobStateMap<C, I>
@@ -83,7 +140,7 @@ where
     fn from(c: C) -> Self {
         Self {
             c,
-            inflight_tracer: Mutex::new(HashMap::new()),
+            inflight_tracer: InflightTracer::new(),
         }
     }
 }
@@ -104,8 +161,8 @@ where
         }
 
         let index = C::get_index(chunk);
-        let mut guard = self.inflight_tracer.lock().unwrap();
-        trace!("chunk index {}, tracer scale {}", index, guard.len());
+        let mut guard = self.inflight_tracer.lock(&index);
+        trace!("chunk index {}, tracer shard scale {}", index, guard.len());
 
         if let Some(i) = guard.get(&index).cloned() {
             drop(guard);
@@ -145,11 +202,15 @@ where
     }
 
     fn clear_pending(&self, chunk: &dyn BlobChunkInfo) {
-        let index = C::get_index(chunk);
-        let mut guard = self.inflight_tracer.lock().unwrap();
-        if let Some(i) = guard.remove(&index) {
-            i.done();
-        }
+        self.inflight_tracer.remove(&C::get_index(chunk));
+    }
+
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<()> {
+        self.c.clear_ready(chunk)
+    }
+
+    fn clear_all_ready(&self) -> Result<()> {
+        self.c.clear_all_ready()
     }
 
     fn is_persist(&self) -> bool {
@@ -192,8 +253,8 @@ impl RangeMap for BlobStateMap<IndexedChunkMap, u32> {
         };
 
         let mut res = Vec::with_capacity(pending.len());
-        let mut guard = self.inflight_tracer.lock().unwrap();
         for index in pending.iter() {
+            let mut guard = self.inflight_tracer.lock(index);
             if guard.get(index).is_none() {
                 // Double check to close the window where prior slot was just removed after backend
                 // IO returned.
@@ -216,15 +277,16 @@ impl RangeMap for BlobStateMap<IndexedChunkMap, u32> {
     fn clear_range_pending(&self, start: Self::I, count: Self::I) {
         let count = std::cmp::min(count, u32::MAX - start);
         let end = start + count;
-        let mut guard = self.inflight_tracer.lock().unwrap();
 
         for index in start..end {
-            if let Some(i) = guard.remove(&index) {
-                i.done();
-            }
+            self.inflight_tracer.remove(&index);
         }
     }
 
+    fn clear_range_ready(&self, start: Self::I, count: Self::I) -> Result<()> {
+        self.c.clear_range_ready(start, count)
+    }
+
     fn wait_for_range_ready(&self, start: Self::I, count: Self::I) -> Result<bool> {
         let count = std::cmp::min(count, u32::MAX - start);
         let end = start + count;
@@ -232,10 +294,8 @@ impl RangeMap for BlobStateMap<IndexedChunkMap, u32> {
             return Ok(true);
         }
 
-        let mut guard = self.inflight_tracer.lock().unwrap();
         for index in start..end {
-            if let Some(i) = guard.get(&index).cloned() {
-                drop(guard);
+            if let Some(i) = self.inflight_tracer.get(&index) {
                 let result =
                     i.wait_for_inflight(Duration::from_millis(SINGLE_INFLIGHT_WAIT_TIMEOUT));
                 if let Err(StorageError::Timeout) = result {
@@ -251,7 +311,6 @@ impl RangeMap for BlobStateMap<IndexedChunkMap, u32> {
                 if !self.c.is_range_ready(index, 1)? {
                     return Ok(false);
                 }
-                guard = self.inflight_tracer.lock().unwrap();
             }
         }
 
@@ -287,8 +346,8 @@ impl RangeMap for BlobStateMap<BlobRangeMap, u64> {
         };
 
         let mut res = Vec::with_capacity(pending.len());
-        let mut guard = self.inflight_tracer.lock().unwrap();
         for index in pending.iter() {
+            let mut guard = self.inflight_tracer.lock(index);
             if guard.get(index).is_none() {
                 // Double check to close the window where prior slot was just removed after backend
                 // IO returned.
@@ -317,12 +376,9 @@ impl RangeMap for BlobStateMap<BlobRangeMap, u64> {
             }
         };
 
-        let mut guard = self.inflight_tracer.lock().unwrap();
         for index in start_index..end_index {
             let idx = (index as u64) << self.c.shift;
-            if let Some(i) = guard.remove(&idx) {
-                i.done();
-            }
+            self.inflight_tracer.remove(&idx);
         }
     }
 
@@ -332,11 +388,9 @@ impl RangeMap for BlobStateMap<BlobRangeMap, u64> {
         }
 
         let (start_index, end_index) = self.c.get_range(start, count)?;
-        let mut guard = self.inflight_tracer.lock().unwrap();
         for index in start_index..end_index {
             let idx = (index as u64) << self.c.shift;
-            if let Some(i) = guard.get(&idx).cloned() {
-                drop(guard);
+            if let Some(i) = self.inflight_tracer.get(&idx) {
                 let result =
                     i.wait_for_inflight(Duration::from_millis(SINGLE_INFLIGHT_WAIT_TIMEOUT));
                 if let Err(StorageError::Timeout) = result {
@@ -352,7 +406,6 @@ impl RangeMap for BlobStateMap<BlobRangeMap, u64> {
                 if !self.c.is_range_ready(idx, 1)? {
                     return Ok(false);
                 }
-                guard = self.inflight_tracer.lock().unwrap();
             }
         }
 
@@ -365,7 +418,7 @@ impl BlobStateMap<BlobRangeMap, u64> {
     pub fn from_range_map(map: BlobRangeMap) -> Self {
         Self {
             c: map,
-            inflight_tracer: Mutex::new(HashMap::new()),
+            inflight_tracer: InflightTracer::new(),
         }
     }
 }
@@ -588,18 +641,18 @@ pub(crate) mod tests {
         index_map
             .check_ready_and_mark_pending(chunk_1.as_ref())
             .unwrap();
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(index_map.inflight_tracer.len(), 1);
         index_map
             .check_ready_and_mark_pending(chunk_2.as_ref())
             .unwrap();
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 2);
+        assert_eq!(index_map.inflight_tracer.len(), 2);
         index_map
             .check_ready_and_mark_pending(chunk_1.as_ref())
             .unwrap_err();
         index_map
             .check_ready_and_mark_pending(chunk_2.as_ref())
             .unwrap_err();
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 2);
+        assert_eq!(index_map.inflight_tracer.len(), 2);
 
         index_map
             .set_ready_and_clear_pending(chunk_1.as_ref())
@@ -610,19 +663,19 @@ pub(crate) mod tests {
                 .unwrap(),
             true
         );
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(index_map.inflight_tracer.len(), 1);
 
         index_map.clear_pending(chunk_2.as_ref());
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(index_map.inflight_tracer.len(), 0);
         assert_eq!(
             index_map
                 .check_ready_and_mark_pending(chunk_2.as_ref())
                 .unwrap(),
             false
         );
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(index_map.inflight_tracer.len(), 1);
         index_map.clear_pending(chunk_2.as_ref());
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(index_map.inflight_tracer.len(), 0);
         index_map
             .set_ready_and_clear_pending(chunk_2.as_ref())
             .unwrap();
@@ -632,18 +685,18 @@ pub(crate) mod tests {
                 .unwrap(),
             true
         );
-        assert_eq!(index_map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(index_map.inflight_tracer.len(), 0);
 
         // digested ChunkMap
         let digest_map = Arc::new(BlobStateMap::from(DigestedChunkMap::new()));
         digest_map
             .check_ready_and_mark_pending(chunk_1.as_ref())
             .unwrap();
-        assert_eq!(digest_map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(digest_map.inflight_tracer.len(), 1);
         digest_map
             .check_ready_and_mark_pending(chunk_2.as_ref())
             .unwrap();
-        assert_eq!(digest_map.inflight_tracer.lock().unwrap().len(), 2);
+        assert_eq!(digest_map.inflight_tracer.len(), 2);
         digest_map
             .check_ready_and_mark_pending(chunk_1.as_ref())
             .unwrap_err();
@@ -667,7 +720,7 @@ pub(crate) mod tests {
             false
         );
         digest_map.clear_pending(chunk_2.as_ref());
-        assert_eq!(digest_map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(digest_map.inflight_tracer.len(), 0);
     }
 
     #[test]
@@ -690,7 +743,7 @@ pub(crate) mod tests {
             false
         );
         let map_cloned = map.clone();
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(map.inflight_tracer.len(), 1);
 
         let chunk_4_cloned = chunk_4.clone();
         let t1 = thread::Builder::new()
@@ -725,7 +778,7 @@ pub(crate) mod tests {
         map.set_ready_and_clear_pending(chunk_4.as_ref()).unwrap();
         map.set_ready_and_clear_pending(chunk_4.as_ref()).unwrap();
 
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(map.inflight_tracer.len(), 0);
 
         t1.join().unwrap();
         t2.join().unwrap();
@@ -756,7 +809,7 @@ pub(crate) mod tests {
             .unwrap();
         let map_cloned = map.clone();
 
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(map.inflight_tracer.len(), 1);
 
         let chunk_4_cloned = chunk_4.clone();
         let t1 = thread::Builder::new()
@@ -771,15 +824,56 @@ pub(crate) mod tests {
 
         t1.join().unwrap();
 
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(map.inflight_tracer.len(), 1);
 
         map.as_ref()
             .check_ready_and_mark_pending(chunk_4.as_ref())
             .unwrap_err();
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 1);
+        assert_eq!(map.inflight_tracer.len(), 1);
 
         map.clear_pending(chunk_4.as_ref());
-        assert_eq!(map.inflight_tracer.lock().unwrap().len(), 0);
+        assert_eq!(map.inflight_tracer.len(), 0);
+    }
+
+    #[test]
+    fn test_inflight_tracer_hot_chunks() {
+        let tmp_file = TempFile::new().unwrap();
+        let chunk_count = 8;
+        let map = Arc::new(BlobStateMap::from(
+            IndexedChunkMap::new(tmp_file.as_path().to_str().unwrap(), chunk_count).unwrap(),
+        ));
+        let fills = Arc::new(
+            (0..chunk_count)
+                .map(|_| std::sync::atomic::AtomicU32::new(0))
+                .collect::<Vec<_>>(),
+        );
+
+        // Files of a highly deduplicated image reference the same few chunks, so all readers
+        // race for them, and each chunk should be filled only once.
+        let mut threads = Vec::new();
+        for t in 0..32 {
+            let map = map.clone();
+            let fills = fills.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..256 {
+                    let chunk = Chunk::new((t + i) % chunk_count);
+                    if !map.check_ready_and_mark_pending(chunk.as_ref()).unwrap() {
+                        fills[chunk.index as usize].fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(10));
+                        map.set_ready_and_clear_pending(chunk.as_ref()).unwrap();
+                    }
+                    assert!(map.is_ready(chunk.as_ref()).unwrap());
+                }
+            }));
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        for fill in fills.iter() {
+            assert_eq!(fill.load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(map.inflight_tracer.len(), 0);
     }
 
     #[test]
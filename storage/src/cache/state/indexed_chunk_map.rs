@@ -63,6 +63,15 @@ impl ChunkMap for IndexedChunkMap {
         self.map.set_chunk_ready(chunk.id())
     }
 
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<()> {
+        self.map.clear_chunk_ready(chunk.id())
+    }
+
+    fn clear_all_ready(&self) -> Result<()> {
+        self.map.clear_all_chunks_ready();
+        Ok(())
+    }
+
     fn is_persist(&self) -> bool {
         true
     }
@@ -131,6 +140,17 @@ impl RangeMap for IndexedChunkMap {
 
         Ok(())
     }
+
+    fn clear_range_ready(&self, start_index: u32, count: u32) -> Result<()> {
+        let count = std::cmp::min(count, u32::MAX - start_index);
+        let end = start_index + count;
+
+        for index in start_index..end {
+            self.map.clear_chunk_ready(index)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl ChunkIndexGetter for IndexedChunkMap {
@@ -146,6 +166,8 @@ mod tests {
     use std::fs::OpenOptions;
     use std::io::Write;
     use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
     use vmm_sys_util::tempdir::TempDir;
 
     use super::super::persist_map::*;
@@ -182,6 +204,37 @@ mod tests {
         assert!(IndexedChunkMap::new(&blob_path, 1).is_err());
     }
 
+    #[test]
+    fn test_indexed_clear_all_ready_concurrently() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        let map = Arc::new(IndexedChunkMap::new(&blob_path, 4096).unwrap());
+
+        let mut threads = Vec::new();
+        for i in 0..4 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for index in (i..4096).step_by(4) {
+                    map.map.set_chunk_ready(index).unwrap();
+                }
+            }));
+        }
+        for _ in 0..16 {
+            map.clear_all_ready().unwrap();
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let ready = (0..4096).filter(|i| map.map.is_chunk_ready(*i).0).count() as u32;
+        assert_eq!(
+            map.map.not_ready_count.load(Ordering::Acquire),
+            4096 - ready
+        );
+        assert_eq!(map.is_range_all_ready(), ready == 4096);
+    }
+
     #[test]
     fn test_indexed_new_zero_file_size() {
         let dir = TempDir::new().unwrap();
@@ -296,6 +349,13 @@ mod tests {
         assert_eq!(map.is_ready(chunk.as_base()).unwrap(), true);
         map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
         assert_eq!(map.is_ready(chunk.as_base()).unwrap(), true);
+
+        map.clear_ready(chunk.as_base()).unwrap();
+        assert_eq!(map.is_range_all_ready(), false);
+        assert_eq!(map.is_ready(chunk.as_base()).unwrap(), false);
+        map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
+        assert_eq!(map.is_range_all_ready(), true);
+        assert_eq!(map.is_ready(chunk.as_base()).unwrap(), true);
     }
 
     #[test]
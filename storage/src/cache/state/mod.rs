@@ -76,6 +76,17 @@ pub trait ChunkMap: Any + Send + Sync {
         panic!("no support of clear_pending()");
     }
 
+    /// Mark the chunk as not ready, so it will be fetched from the backend again.
+    ///
+    /// It's used to invalidate chunks when the cached data is found to be corrupted.
+    fn clear_ready(&self, _chunk: &dyn BlobChunkInfo) -> Result<()> {
+        Err(enosys!("no support of clear_ready()"))
+    }
+
+    fn clear_all_ready(&self) -> Result<()> {
+        Err(enosys!("no support of clear_all_ready()"))
+    }
+
     /// Check whether the implementation supports state persistence.
     fn is_persist(&self) -> bool {
         false
@@ -131,6 +142,11 @@ pub trait RangeMap: Send + Sync {
     /// Clear the pending state for all chunks or data in the range.
     fn clear_range_pending(&self, _start: Self::I, _count: Self::I) {}
 
+    /// Mark all chunks or data in the range as not ready, e.g. when the cached data is discarded.
+    fn clear_range_ready(&self, _start: Self::I, _count: Self::I) -> Result<()> {
+        Err(enosys!())
+    }
+
     /// Wait for all chunks or data in the range to be ready until timeout.
     fn wait_for_range_ready(&self, _start: Self::I, _count: Self::I) -> Result<bool> {
         Err(enosys!())
@@ -8,6 +8,7 @@ use std::fs::{File, OpenOptions};
 use std::io::{Result, Write};
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::RwLock;
 
 use nydus_utils::div_round_up;
 
@@ -46,6 +47,9 @@ pub(crate) struct PersistMap {
     pub size: usize,
     pub base: *const u8,
     pub not_ready_count: AtomicU32,
+    // Chunks are marked ready concurrently with the read lock, while clearing all chunks takes
+    // the write lock to keep bitmap and `not_ready_count` consistent.
+    lock: RwLock<()>,
 }
 
 impl PersistMap {
@@ -163,6 +167,7 @@ impl PersistMap {
             size: expected_size as usize,
             base: base as *const u8,
             not_ready_count: AtomicU32::new(not_ready_count),
+            lock: RwLock::new(()),
         })
     }
 
@@ -234,6 +239,8 @@ impl PersistMap {
 
     pub fn set_chunk_ready(&self, index: u32) -> Result<()> {
         let index = self.validate_index(index)?;
+        // Not expect poisoned lock here.
+        let _guard = self.lock.read().unwrap();
 
         // Loop to atomically update the state bit corresponding to the chunk index.
         loop {
@@ -253,6 +260,57 @@ impl PersistMap {
         Ok(())
     }
 
+    pub fn clear_chunk_ready(&self, index: u32) -> Result<()> {
+        let index = self.validate_index(index)?;
+        let _guard = self.lock.read().unwrap();
+
+        if self.is_range_all_ready() {
+            // The bitmap may be stale if all chunks are marked as ready by the header, so fill
+            // the bitmap before clearing the header flag.
+            for idx in 0..self.count {
+                self.atomic_u8(idx)
+                    .fetch_or(Self::index_to_mask(idx), Ordering::AcqRel);
+            }
+            self.clear_all_ready();
+        }
+
+        let mask = Self::index_to_mask(index);
+        if self.atomic_u8(index).fetch_and(!mask, Ordering::AcqRel) & mask == mask {
+            self.not_ready_count.fetch_add(1, Ordering::AcqRel);
+        }
+
+        Ok(())
+    }
+
+    /// Mark all chunks as not ready.
+    ///
+    /// Chunks being marked as ready concurrently are either cleared or stay ready afterwards.
+    pub fn clear_all_chunks_ready(&self) {
+        let _guard = self.lock.write().unwrap();
+        self.clear_all_ready();
+        for idx in (0..self.count).step_by(8) {
+            self.atomic_u8(idx).store(0, Ordering::Release);
+        }
+        self.not_ready_count.store(self.count, Ordering::Release);
+    }
+
+    #[inline]
+    fn atomic_u8(&self, idx: u32) -> &AtomicU8 {
+        let start = HEADER_SIZE + (idx as usize >> 3);
+        unsafe { &*(self.base.add(start) as *const AtomicU8) }
+    }
+
+    fn clear_all_ready(&self) {
+        let base = self.base as *const c_void as *mut c_void;
+        unsafe {
+            let header = &mut *(self.base as *mut Header);
+            if header.all_ready == MAGIC_ALL_READY {
+                header.all_ready = 0;
+                let _ = libc::msync(base, HEADER_SIZE, libc::MS_SYNC);
+            }
+        }
+    }
+
     fn mark_all_ready(&self) {
         let base = self.base as *const c_void as *mut c_void;
         unsafe {
@@ -14,9 +14,10 @@ use futures::executor::block_on;
 use governor::clock::QuantaClock;
 use governor::state::{InMemoryState, NotKeyed};
 use governor::{Quota, RateLimiter};
-use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+use nydus_utils::metrics::{BackendRequestClass, BlobcacheMetrics, Metric};
 use spmc::{channel, Receiver, Sender};
 
+use crate::backend::RequestClassGuard;
 use crate::cache::{BlobCache, BlobIoRange, BlobPrefetchConfig};
 use crate::RAFS_MAX_CHUNK_SIZE;
 use fuse_backend_rs::transport::FileVolatileSlice;
@@ -189,6 +190,15 @@ impl AsyncWorkerMgr {
         }
     }
 
+    /// Restart working threads if all of them have been stopped.
+    pub fn restart(mgr: Arc<AsyncWorkerMgr>) -> Result<()> {
+        if mgr.workers.load(Ordering::Relaxed) == 0 {
+            Self::start(mgr)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Send an asynchronous service request message to the workers.
     pub fn send(&self, msg: AsyncRequestMessage) -> Result<()> {
         match &msg {
@@ -250,6 +260,8 @@ impl AsyncWorkerMgr {
     }
 
     fn run(&self, rx: Receiver<AsyncRequestMessage>) {
+        // All backend requests issued by prefetch workers are prefetch requests.
+        let _guard = RequestClassGuard::enter(BackendRequestClass::Prefetch);
         while let Ok(msg) = rx.recv() {
             match msg {
                 AsyncRequestMessage::FsPrefetch(state, blob_cache, req) => {
@@ -382,6 +394,13 @@ mod tests {
         assert_eq!(mgr.pings.load(Ordering::Relaxed), 3);
         mgr.stop();
         assert_eq!(mgr.workers.load(Ordering::Relaxed), 0);
+
+        AsyncWorkerMgr::restart(mgr.clone()).unwrap();
+        mgr.send(AsyncRequestMessage::Ping).unwrap();
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(mgr.workers.load(Ordering::Relaxed), 2);
+        assert_eq!(mgr.pings.load(Ordering::Relaxed), 4);
+        mgr.stop();
     }
 
     #[test]
@@ -17,12 +17,22 @@ mod lz4_standard;
 use self::lz4_standard::*;
 
 const COMPRESSION_MINIMUM_RATIO: usize = 100;
+// Compressed data should save at least this percentage of space when heuristics are enabled.
+const HEURISTIC_MINIMUM_SAVING: usize = 3;
+// Data with estimated entropy higher than this, in bits per byte, is treated as incompressible.
+const HEURISTIC_ENTROPY_THRESHOLD: f64 = 7.5;
+// Sample `HEURISTIC_SAMPLE_SIZE` bytes out of every `HEURISTIC_SAMPLE_STRIDE` bytes of data.
+const HEURISTIC_SAMPLE_SIZE: usize = 256;
+const HEURISTIC_SAMPLE_STRIDE: usize = 4096;
+// Data smaller than this is always compressed because sampling is unreliable.
+const HEURISTIC_MINIMUM_SIZE: usize = 4096;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Algorithm {
     None,
     Lz4Block,
     GZip,
+    Zstd,
 }
 
 impl Default for Algorithm {
@@ -45,7 +55,10 @@ impl FromStr for Algorithm {
             "none" => Ok(Self::None),
             "lz4_block" => Ok(Self::Lz4Block),
             "gzip" => Ok(Self::GZip),
-            _ => Err(einval!("compression algorithm should be none or lz4_block")),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(einval!(
+                "compression algorithm should be none, lz4_block, gzip or zstd"
+            )),
         }
     }
 }
@@ -60,6 +73,8 @@ impl TryFrom<u32> for Algorithm {
             Ok(Algorithm::Lz4Block)
         } else if value == Algorithm::GZip as u32 {
             Ok(Algorithm::GZip)
+        } else if value == Algorithm::Zstd as u32 {
+            Ok(Algorithm::Zstd)
         } else {
             Err(())
         }
@@ -94,6 +109,7 @@ pub fn compress(src: &[u8], algorithm: Algorithm) -> Result<(Cow<[u8]>, bool)> {
             gz.write_all(src)?;
             gz.finish()?
         }
+        Algorithm::Zstd => zstd::bulk::compress(src, zstd::DEFAULT_COMPRESSION_LEVEL)?,
     };
 
     // Abandon compressed data when compression ratio greater than COMPRESSION_MINIMUM_RATIO
@@ -106,6 +122,55 @@ pub fn compress(src: &[u8], algorithm: Algorithm) -> Result<(Cow<[u8]>, bool)> {
     }
 }
 
+/// Compress data with heuristics to skip incompressible data.
+///
+/// Compression is skipped if data is estimated incompressible by sampling its entropy, and
+/// compressed data is abandoned if it doesn't save enough space. Returns the same result as
+/// `compress()`, the second element tells whether the returned data is compressed.
+pub fn compress_with_heuristics(src: &[u8], algorithm: Algorithm) -> Result<(Cow<[u8]>, bool)> {
+    if algorithm.is_none() || is_incompressible(src) {
+        return Ok((Cow::Borrowed(src), false));
+    }
+
+    let (compressed, is_compressed) = compress(src, algorithm)?;
+    if is_compressed && compressed.len() * 100 > src.len() * (100 - HEURISTIC_MINIMUM_SAVING) {
+        Ok((Cow::Borrowed(src), false))
+    } else {
+        Ok((compressed, is_compressed))
+    }
+}
+
+/// Estimate whether data is incompressible by sampling its byte entropy.
+pub fn is_incompressible(src: &[u8]) -> bool {
+    if src.len() < HEURISTIC_MINIMUM_SIZE {
+        return false;
+    }
+
+    let mut histogram = [0u32; 256];
+    let mut total = 0usize;
+    for sample in src
+        .chunks(HEURISTIC_SAMPLE_STRIDE)
+        .map(|c| &c[..std::cmp::min(c.len(), HEURISTIC_SAMPLE_SIZE)])
+    {
+        for b in sample {
+            histogram[*b as usize] += 1;
+        }
+        total += sample.len();
+    }
+
+    let total = total as f64;
+    let entropy: f64 = histogram
+        .iter()
+        .filter(|v| **v != 0)
+        .map(|v| {
+            let p = *v as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+
+    entropy > HEURISTIC_ENTROPY_THRESHOLD
+}
+
 /// Decompress a source slice or file stream into destination slice, with provided compression algorithm.
 /// Use the file as decompress source if provided.
 pub fn decompress(
@@ -127,6 +192,22 @@ pub fn decompress(
             };
             Ok(dst.len())
         }
+        Algorithm::Zstd => {
+            if let Some(f) = src_file {
+                let mut decoder = zstd::stream::read::Decoder::new(f)?;
+                decoder.read_exact(dst)?;
+            } else {
+                let size = zstd::bulk::decompress_to_buffer(src, dst)?;
+                if size != dst.len() {
+                    return Err(eio!(format!(
+                        "decompressed {} bytes of zstd data, expect {}",
+                        size,
+                        dst.len()
+                    )));
+                }
+            }
+            Ok(dst.len())
+        }
     }
 }
 
@@ -171,6 +252,51 @@ mod tests {
         assert_eq!(buf, decompressed);
     }
 
+    #[test]
+    fn test_compress_algorithm_zstd() {
+        let buf = vec![0x2u8; 4095];
+        let (compressed, is_compressed) = compress(&buf, Algorithm::Zstd).unwrap();
+        assert!(is_compressed);
+
+        let mut decompressed = vec![0; buf.len()];
+        let sz = decompress(
+            &compressed,
+            None,
+            decompressed.as_mut_slice(),
+            Algorithm::Zstd,
+        )
+        .unwrap();
+        assert_eq!(sz, 4095);
+        assert_eq!(buf, decompressed);
+
+        let mut tmp_file = TempFile::new().unwrap().into_file();
+        tmp_file.write_all(&compressed).unwrap();
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+        let mut decompressed = vec![0; buf.len()];
+        decompress(
+            &compressed,
+            Some(tmp_file),
+            decompressed.as_mut_slice(),
+            Algorithm::Zstd,
+        )
+        .unwrap();
+        assert_eq!(buf, decompressed);
+
+        let mut decompressed = vec![0; buf.len() + 1];
+        assert!(decompress(
+            &compressed,
+            None,
+            decompressed.as_mut_slice(),
+            Algorithm::Zstd
+        )
+        .is_err());
+        assert_eq!("zstd".parse::<Algorithm>().unwrap(), Algorithm::Zstd);
+        assert_eq!(
+            Algorithm::try_from(Algorithm::Zstd as u32),
+            Ok(Algorithm::Zstd)
+        );
+    }
+
     #[test]
     fn test_compress_algorithm_none() {
         let buf = [
@@ -286,6 +412,38 @@ mod tests {
         assert_eq!(sz, 4097);
         assert_eq!(buf, decompressed);
     }
+
+    #[test]
+    fn test_compress_with_heuristics() {
+        // Pseudo random data generated by xorshift.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let random: Vec<u8> = (0..0x10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect();
+        assert!(is_incompressible(&random));
+        let (data, compressed) = compress_with_heuristics(&random, Algorithm::Lz4Block).unwrap();
+        assert!(!compressed);
+        assert_eq!(data.as_ref(), random.as_slice());
+
+        let text = b"compressible text data ".repeat(0x1000);
+        assert!(!is_incompressible(&text));
+        let (data, compressed) = compress_with_heuristics(&text, Algorithm::Lz4Block).unwrap();
+        assert!(compressed);
+        assert!(data.len() < text.len());
+        let mut decompressed = vec![0; text.len()];
+        decompress(&data, None, &mut decompressed, Algorithm::Lz4Block).unwrap();
+        assert_eq!(decompressed, text);
+
+        // Small data is not sampled.
+        assert!(!is_incompressible(&random[..0x100]));
+        let (_, compressed) = compress_with_heuristics(&text, Algorithm::None).unwrap();
+        assert!(!compressed);
+    }
 }
 
 /// Estimate the maximum compressed data size from uncompressed data size.
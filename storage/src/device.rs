@@ -21,10 +21,11 @@
 //! - [BlobPrefetchRequest](struct.BlobPrefetchRequest.html): a blob data prefetching request.
 use std::any::Any;
 use std::cmp;
+use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
 use std::io::{self, Error};
-use std::os::unix::io::AsRawFd;
-use std::sync::Arc;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
 
 use arc_swap::ArcSwap;
 use fuse_backend_rs::api::filesystem::ZeroCopyWriter;
@@ -32,9 +33,13 @@ use fuse_backend_rs::transport::{FileReadWriteVolatile, FileVolatileSlice};
 use nydus_utils::digest::{self, RafsDigest};
 use vm_memory::Bytes;
 
+use crate::backend::BlobBackend;
 use crate::cache::BlobCache;
 use crate::compress;
-use crate::factory::{FactoryConfig, BLOB_FACTORY};
+use crate::factory::{BackendConfig, BlobFactory, FactoryConfig, BLOB_FACTORY};
+use crate::meta::toc::BlobTocOndisk;
+use crate::meta::BlobProvenance;
+use crate::utils::{alloc_buf, copyv};
 
 static ZEROS: &[u8] = &[0u8; 4096]; // why 4096? volatile slice default size, unfortunately
 
@@ -97,6 +102,9 @@ pub struct BlobInfo {
     meta_ci_compressed_size: u64,
     /// V6: Size of the uncompressed chunk information array.
     meta_ci_uncompressed_size: u64,
+
+    /// Provenance information recorded by the builder.
+    provenance: Option<BlobProvenance>,
 }
 
 impl BlobInfo {
@@ -130,6 +138,7 @@ impl BlobInfo {
             meta_ci_offset: 0,
             meta_ci_compressed_size: 0,
             meta_ci_uncompressed_size: 0,
+            provenance: None,
         };
 
         blob_info.compute_features();
@@ -318,6 +327,16 @@ impl BlobInfo {
             && self.meta_ci_compressed_size != 0
             && self.meta_ci_uncompressed_size != 0
     }
+
+    /// Get provenance information of the blob, if recorded by the builder.
+    pub fn provenance(&self) -> Option<&BlobProvenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Set provenance information of the blob.
+    pub fn set_provenance(&mut self, provenance: Option<BlobProvenance>) {
+        self.provenance = provenance;
+    }
 }
 
 bitflags! {
@@ -795,6 +814,18 @@ pub trait BlobObject: AsRawFd {
     fn fetch_chunks(&self, range: &BlobIoRange) -> io::Result<usize>;
 }
 
+/// A range of uncompressed blob data cached in a local file.
+pub struct BlobCachedExtent {
+    // Keep the cache file open while the extent is in use.
+    _cache: Arc<dyn BlobCache>,
+    /// File descriptor of the cache file.
+    pub fd: RawFd,
+    /// Offset into the cache file.
+    pub offset: u64,
+    /// Size of the range.
+    pub size: usize,
+}
+
 /// A wrapping object over an underlying [BlobCache] object.
 ///
 /// All blob Io requests are actually served by the underlying [BlobCache] object. A new method
@@ -804,6 +835,8 @@ pub struct BlobDevice {
     //meta: ArcSwap<Arc<dyn BlobCache>>,
     blobs: ArcSwap<Vec<Arc<dyn BlobCache>>>,
     blob_count: usize,
+    config: ArcSwap<FactoryConfig>,
+    uncached_chunks: Arc<UncachedChunkBuffer>,
 }
 
 impl BlobDevice {
@@ -821,6 +854,8 @@ impl BlobDevice {
         Ok(BlobDevice {
             blobs: ArcSwap::new(Arc::new(blobs)),
             blob_count: blob_infos.len(),
+            config: ArcSwap::new(config.clone()),
+            uncached_chunks: Arc::new(UncachedChunkBuffer::new()),
         })
     }
 
@@ -850,6 +885,76 @@ impl BlobDevice {
         }
 
         self.blobs.store(Arc::new(blobs));
+        self.config.store(config.clone());
+        self.uncached_chunks.clear();
+
+        Ok(())
+    }
+
+    /// Get type of the blob cache manager in use.
+    pub fn cache_type(&self) -> String {
+        self.config.load().cache.cache_type.clone()
+    }
+
+    /// Switch to another type of blob cache manager, without remounting the filesystem.
+    ///
+    /// Background prefetch into the current blob caches is stopped and cached data is flushed
+    /// before switching, so cache files are left in consistent state. Cache files are kept, and
+    /// data already cached is used again after switching back to `blobcache`.
+    pub fn switch_cache_type(
+        &self,
+        cache_type: &str,
+        blob_infos: &[Arc<BlobInfo>],
+    ) -> io::Result<()> {
+        match cache_type {
+            "blobcache" | "dummy" | "direct" => {}
+            _ => return Err(einval!(format!("unsupported cache type '{}'", cache_type))),
+        }
+        if self.blobs.load().len() != blob_infos.len() {
+            return Err(einval!("number of blobs doesn't match"));
+        }
+        let current = self.config.load_full();
+        if current.cache.cache_type == cache_type {
+            return Ok(());
+        }
+        let mut config = current.as_ref().clone();
+        config.cache.cache_type = cache_type.to_string();
+        let config = Arc::new(config);
+
+        // Drain pending writes into the current blob caches.
+        let old = self.blobs.load_full();
+        for blob in old.iter() {
+            blob.stop_prefetch().unwrap_or_else(|e| error!("{:?}", e));
+        }
+        let restore = |e: io::Error| {
+            for blob in old.iter() {
+                blob.restart_prefetch()
+                    .unwrap_or_else(|e| error!("{:?}", e));
+            }
+            e
+        };
+        for blob in old.iter() {
+            blob.flush().map_err(restore)?;
+        }
+
+        let mut blobs = Vec::with_capacity(blob_infos.len());
+        for blob_info in blob_infos.iter() {
+            let blob = BLOB_FACTORY
+                .new_blob_cache(&config, blob_info)
+                .map_err(restore)?;
+            // Cache managers created before may be reused, with prefetch workers stopped.
+            blob.restart_prefetch().map_err(restore)?;
+            blobs.push(blob);
+        }
+        info!(
+            "switch blob cache from '{}' to '{}'",
+            current.cache.cache_type, cache_type
+        );
+        self.blobs.store(Arc::new(blobs));
+        self.config.store(config);
+        drop(old);
+        // Release cache managers not used anymore.
+        BLOB_FACTORY.gc();
 
         Ok(())
     }
@@ -865,6 +970,28 @@ impl BlobDevice {
 
     /// Read a range of data from blob into the provided writer
     pub fn read_to(&self, w: &mut dyn ZeroCopyWriter, desc: &mut BlobIoVec) -> io::Result<usize> {
+        self.do_read_to(w, desc, false)
+    }
+
+    /// Read a range of data from the storage backend into the provided writer, bypassing the
+    /// blob cache.
+    ///
+    /// Data already cached is not used, and data fetched from the storage backend is not written
+    /// into the blob cache, so reading huge files once won't evict other data from the cache.
+    pub fn read_to_uncached(
+        &self,
+        w: &mut dyn ZeroCopyWriter,
+        desc: &mut BlobIoVec,
+    ) -> io::Result<usize> {
+        self.do_read_to(w, desc, true)
+    }
+
+    fn do_read_to(
+        &self,
+        w: &mut dyn ZeroCopyWriter,
+        desc: &mut BlobIoVec,
+        uncached: bool,
+    ) -> io::Result<usize> {
         // Validate that:
         // - bi_vec[0] is valid
         // - bi_vec[0].blob.blob_index() is valid
@@ -880,13 +1007,41 @@ impl BlobDevice {
         } else if desc.bi_vec[0].blob.blob_index() as usize >= self.blob_count {
             Err(einval!("BlobIoVec has out of range blob_index."))
         } else {
-            let mut f = BlobDeviceIoVec::new(self, desc);
+            let mut f = BlobDeviceIoVec::new(self, desc, uncached);
             // The `off` parameter to w.write_from() is actually ignored by
             // BlobV5IoVec::read_vectored_at_volatile()
             w.write_from(&mut f, desc.bi_size, 0)
         }
     }
 
+    /// Read uncompressed data of the blob at `blob_index` from `offset` into `buf`, fetching
+    /// data from the storage backend on demand.
+    ///
+    /// It's only supported by blob caches keeping data in uncompressed form, which may be
+    /// addressed by uncompressed blob offset.
+    pub fn read_uncompressed(
+        &self,
+        blob_index: u32,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let blobs = self.blobs.load();
+        let blob = blobs
+            .get(blob_index as usize)
+            .ok_or_else(|| einval!("blob index is out of range"))?;
+        let object = blob
+            .get_blob_object()
+            .ok_or_else(|| enosys!("blob cache doesn't support uncompressed data access"))?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        object.fetch_range_uncompressed(offset, buf.len() as u64)?;
+        let pos = object.base_offset() + offset;
+        nix::sys::uio::pread(object.as_raw_fd(), buf, pos as i64)
+            .map_err(|e| eio!(format!("failed to read blob {}, {}", blob.blob_id(), e)))
+    }
+
     /// Try to prefetch specified blob data.
     pub fn prefetch(
         &self,
@@ -916,6 +1071,14 @@ impl BlobDevice {
         }
     }
 
+    /// Restart background prefetch workers stopped by `stop_prefetch()`.
+    pub fn restart_prefetch(&self) -> io::Result<()> {
+        for blob in self.blobs.load().iter() {
+            blob.restart_prefetch()?;
+        }
+        Ok(())
+    }
+
     /// Check all chunks related to the blob io vector are ready.
     pub fn is_all_chunk_ready(&self, io_vecs: &[BlobIoVec]) -> bool {
         for io_vec in io_vecs.iter() {
@@ -934,6 +1097,223 @@ impl BlobDevice {
         true
     }
 
+    /// Get extents of local cache files holding data of the blob io vectors.
+    ///
+    /// Returns `None` unless data of all chunks is ready in cache files in uncompressed form and
+    /// needs no validation, so it may be transferred directly from the cache files.
+    pub fn get_cached_extents(&self, io_vecs: &[BlobIoVec]) -> Option<Vec<BlobCachedExtent>> {
+        let mut extents: Vec<BlobCachedExtent> = Vec::new();
+
+        for io_vec in io_vecs.iter() {
+            let blob = self.get_blob_by_iovec(io_vec)?;
+            if blob.need_validate() {
+                return None;
+            }
+            let object = blob.get_blob_object()?;
+            let fd = object.as_raw_fd();
+            let base = object.base_offset();
+            let chunk_map = blob.get_chunk_map();
+            for desc in io_vec.bi_vec.iter() {
+                blob.record_chunk_access(&desc.chunkinfo);
+                if !chunk_map.is_ready(&desc.chunkinfo).unwrap_or(false) {
+                    return None;
+                }
+                let offset = base + desc.chunkinfo.uncompress_offset() + desc.offset as u64;
+                match extents.last_mut() {
+                    Some(last) if last.fd == fd && last.offset + last.size as u64 == offset => {
+                        last.size += desc.size
+                    }
+                    _ => extents.push(BlobCachedExtent {
+                        _cache: blob.clone(),
+                        fd,
+                        offset,
+                        size: desc.size,
+                    }),
+                }
+            }
+        }
+
+        Some(extents)
+    }
+
+    /// Verify cached data of chunks related to the blob io vectors.
+    ///
+    /// Corrupted chunks are invalidated so they will be fetched from the storage backend again.
+    pub fn scrub(&self, io_vecs: &[BlobIoVec], stat: &mut BlobScrubStat) {
+        for io_vec in io_vecs.iter() {
+            if let Some(blob) = self.get_blob_by_iovec(io_vec) {
+                for desc in io_vec.bi_vec.iter() {
+                    stat.sampled += 1;
+                    match blob.scrub_chunk(&desc.chunkinfo) {
+                        Ok(Some(valid)) => {
+                            stat.verified += 1;
+                            if !valid {
+                                stat.corrupted += 1;
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            debug!("failed to scrub blob {}, {}", blob.blob_id(), e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Discard cached data of chunks related to the blob io vectors, so they will be fetched from
+    /// the storage backend again, and return the number of chunks invalidated.
+    pub fn invalidate(&self, io_vecs: &[BlobIoVec]) -> io::Result<u64> {
+        let mut count = 0;
+        for io_vec in io_vecs.iter() {
+            if let Some(blob) = self.get_blob_by_iovec(io_vec) {
+                for desc in io_vec.bi_vec.iter() {
+                    if blob.invalidate_chunk(&desc.chunkinfo)? {
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Switch the storage backend object to fetch data of blob `blob_id` from.
+    ///
+    /// Data is fetched from object `object_id`, or `blob_id` if it's None, of a new storage backend
+    /// created from `backend_config`, or of the current storage backend if it's None.
+    pub fn switch_blob_backend(
+        &self,
+        blob_id: &str,
+        backend_config: Option<BackendConfig>,
+        object_id: Option<&str>,
+        flush_cache: bool,
+    ) -> io::Result<()> {
+        let blob = self
+            .blobs
+            .load()
+            .iter()
+            .find(|b| b.blob_id() == blob_id)
+            .cloned()
+            .ok_or_else(|| enoent!(format!("blob {} not found", blob_id)))?;
+        let object_id = object_id.unwrap_or(blob_id);
+        let backend = match backend_config {
+            Some(config) => {
+                Some(BlobFactory::new_backend(config, object_id)? as Arc<dyn BlobBackend>)
+            }
+            None => None,
+        };
+
+        blob.switch_backend(backend, object_id, flush_cache)?;
+        self.uncached_chunks.clear();
+
+        Ok(())
+    }
+
+    /// Get cache state of all blobs, in the same order as the blob information array.
+    pub fn cache_states(&self) -> Vec<BlobCacheState> {
+        self.blobs
+            .load()
+            .iter()
+            .map(|blob| {
+                let chunk_map = blob.get_chunk_map();
+                BlobCacheState {
+                    persistent: chunk_map.is_persist(),
+                    ready: chunk_map
+                        .as_range_map()
+                        .map(|m| m.is_range_all_ready())
+                        .unwrap_or(false),
+                }
+            })
+            .collect()
+    }
+
+    /// Check consistency between blobs in the storage backend and the blob information array.
+    ///
+    /// Blobs are checked for existence and size, and against the TOC if the blob has one. Blob
+    /// data is verified against digests in the TOC only if `verify_digest` is true, which needs
+    /// to read the whole blob. Returns a failure record for each missing or mismatched blob.
+    ///
+    /// It reads blobs from the storage backend directly, bypassing the blob cache, so it should
+    /// be called before creating the blob device.
+    pub fn check_blobs(
+        config: &Arc<FactoryConfig>,
+        blob_infos: &[Arc<BlobInfo>],
+        verify_digest: bool,
+    ) -> io::Result<Vec<BlobCheckFailure>> {
+        let backend = match blob_infos.first() {
+            Some(info) => BlobFactory::new_backend(config.backend.clone(), info.blob_id())?,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(blob_infos
+            .iter()
+            .filter_map(|info| Self::check_blob(backend.as_ref(), info, verify_digest).err())
+            .collect())
+    }
+
+    fn check_blob(
+        backend: &(dyn BlobBackend + Send + Sync),
+        info: &BlobInfo,
+        verify_digest: bool,
+    ) -> std::result::Result<(), BlobCheckFailure> {
+        let missing = |reason: String| BlobCheckFailure {
+            blob_id: info.blob_id().to_string(),
+            missing: true,
+            reason,
+        };
+        let mismatch = |reason: String| BlobCheckFailure {
+            blob_id: info.blob_id().to_string(),
+            missing: false,
+            reason,
+        };
+        let reader = backend
+            .get_reader(info.blob_id())
+            .map_err(|e| missing(format!("{:?}", e)))?;
+        let size = reader
+            .blob_size()
+            .map_err(|e| missing(format!("{:?}", e)))?;
+
+        let mut expected = info.compressed_size();
+        if info.meta_ci_is_valid() {
+            expected = cmp::max(
+                expected,
+                info.meta_ci_offset() + info.meta_ci_compressed_size(),
+            );
+        }
+        if size < expected {
+            return Err(mismatch(format!(
+                "blob size {} is less than expected {}",
+                size, expected
+            )));
+        }
+
+        // The TOC is an optional footer at the end of the blob.
+        let toc = match BlobTocOndisk::read_from(reader.as_ref()) {
+            Ok(Some(toc)) => toc,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(mismatch(format!("invalid TOC, {}", e))),
+        };
+        if info.chunk_count() != 0 && toc.chunk_count() != info.chunk_count() {
+            return Err(mismatch(format!(
+                "chunk count {} in TOC doesn't match {}",
+                toc.chunk_count(),
+                info.chunk_count()
+            )));
+        }
+        match toc.compressor() {
+            Ok(algo) if algo == info.compressor() => {}
+            _ => return Err(mismatch("compressor in TOC doesn't match".to_string())),
+        }
+        if verify_digest {
+            toc.verify(reader.as_ref(), None)
+                .map_err(|e| mismatch(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
     fn get_blob_by_iovec(&self, iovec: &BlobIoVec) -> Option<Arc<dyn BlobCache>> {
         if let Some(blob_index) = iovec.get_target_blob_index() {
             if (blob_index as usize) < self.blob_count {
@@ -991,15 +1371,183 @@ impl BlobDevice {
     }
 }
 
+/// Statistics about verifying cached data of chunks.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BlobScrubStat {
+    /// Number of chunks sampled.
+    pub sampled: u64,
+    /// Number of cached chunks which have been verified.
+    pub verified: u64,
+    /// Number of cached chunks found corrupted and invalidated.
+    pub corrupted: u64,
+}
+
+/// Cache state of a blob.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BlobCacheState {
+    /// Whether the chunk readiness state of the blob is persisted.
+    pub persistent: bool,
+    /// Whether all chunks of the blob are ready in the local cache.
+    pub ready: bool,
+}
+
+/// Failure record of checking a blob against its blob information.
+#[derive(Clone, Debug)]
+pub struct BlobCheckFailure {
+    /// Id of the blob.
+    pub blob_id: String,
+    /// Whether the blob is missing or inaccessible in the storage backend.
+    pub missing: bool,
+    /// Description of the failure.
+    pub reason: String,
+}
+
+// Number of chunks kept in memory for uncached reads.
+const UNCACHED_CHUNK_BUFFERS: usize = 4;
+
+/// Chunks partially read by uncached reads.
+///
+/// Uncached reads don't populate the blob cache, so reading a file sequentially with requests
+/// smaller than the chunk size would fetch and decompress the same chunk once per request. The
+/// last few chunks not completely consumed are kept in memory to serve following requests.
+struct UncachedChunkBuffer {
+    // Keyed by blob index and compressed offset, most recently used at the back.
+    chunks: Mutex<VecDeque<(u32, u64, Arc<Vec<u8>>)>>,
+}
+
+impl UncachedChunkBuffer {
+    fn new() -> Self {
+        UncachedChunkBuffer {
+            chunks: Mutex::new(VecDeque::with_capacity(UNCACHED_CHUNK_BUFFERS)),
+        }
+    }
+
+    fn get(&self, blob_index: u32, chunk: &BlobIoChunk) -> Option<Arc<Vec<u8>>> {
+        let offset = chunk.compress_offset();
+        self.chunks
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(index, off, _)| *index == blob_index && *off == offset)
+            .map(|(_, _, buf)| buf.clone())
+    }
+
+    fn insert(&self, blob_index: u32, chunk: &BlobIoChunk, buf: Arc<Vec<u8>>) {
+        let offset = chunk.compress_offset();
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.retain(|(index, off, _)| *index != blob_index || *off != offset);
+        if chunks.len() >= UNCACHED_CHUNK_BUFFERS {
+            chunks.pop_front();
+        }
+        chunks.push_back((blob_index, offset, buf));
+    }
+
+    fn clear(&self) {
+        self.chunks.lock().unwrap().clear();
+    }
+}
+
 /// Struct to execute Io requests with a single blob.
 struct BlobDeviceIoVec<'a> {
     dev: &'a BlobDevice,
     iovec: &'a BlobIoVec,
+    // Read data from the storage backend without populating the blob cache.
+    uncached: bool,
 }
 
 impl<'a> BlobDeviceIoVec<'a> {
-    fn new(dev: &'a BlobDevice, iovec: &'a BlobIoVec) -> Self {
-        BlobDeviceIoVec { dev, iovec }
+    fn new(dev: &'a BlobDevice, iovec: &'a BlobIoVec, uncached: bool) -> Self {
+        BlobDeviceIoVec {
+            dev,
+            iovec,
+            uncached,
+        }
+    }
+
+    // Fetch chunks from the storage backend and copy requested data into the buffers.
+    //
+    // Continuous chunks are fetched by one backend request, except for stargz images whose
+    // compressed chunk size is unknown. Chunks partially read are kept in the uncached chunk
+    // buffer, so sequential reads smaller than the chunk size fetch each chunk only once.
+    fn read_uncached(
+        &self,
+        blob: &Arc<dyn BlobCache>,
+        buffers: &[FileVolatileSlice],
+    ) -> Result<usize, Error> {
+        let descs = &self.iovec.bi_vec;
+        let buffered = &self.dev.uncached_chunks;
+        let mut total = 0;
+        let mut dst_index = 0;
+        let mut dst_offset = 0;
+        let mut start = 0;
+
+        while start < descs.len() {
+            let blob_index = descs[start].blob.blob_index();
+            let mut end = start + 1;
+            let cached = buffered.get(blob_index, &descs[start].chunkinfo);
+            let chunk_buffers = if let Some(buf) = cached {
+                vec![buf]
+            } else {
+                if !blob.is_stargz() {
+                    while end < descs.len()
+                        && descs[end].is_continuous(&descs[end - 1])
+                        && buffered.get(blob_index, &descs[end].chunkinfo).is_none()
+                    {
+                        end += 1;
+                    }
+                }
+                self.fetch_chunks(blob, &descs[start..end])?
+                    .into_iter()
+                    .map(Arc::new)
+                    .collect::<Vec<_>>()
+            };
+
+            for (desc, buf) in descs[start..end].iter().zip(chunk_buffers.iter()) {
+                let (cnt, (index, offset)) = copyv(
+                    &[buf.as_slice()],
+                    buffers,
+                    desc.offset as usize,
+                    desc.size,
+                    dst_index,
+                    dst_offset,
+                )
+                .map_err(|e| {
+                    error!("failed to copy from chunk buf to buf: {:?}", e);
+                    eio!(e)
+                })?;
+                total += cnt;
+                dst_index = index;
+                dst_offset = offset;
+                if desc.offset as usize + desc.size < buf.len() {
+                    buffered.insert(blob_index, &desc.chunkinfo, buf.clone());
+                }
+            }
+            start = end;
+        }
+
+        Ok(total)
+    }
+
+    fn fetch_chunks(
+        &self,
+        blob: &Arc<dyn BlobCache>,
+        descs: &[BlobIoDesc],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        if descs.len() == 1 {
+            let chunk = &descs[0].chunkinfo;
+            let mut buf = alloc_buf(chunk.uncompress_size() as usize);
+            blob.read_raw_chunk(chunk, &mut buf, false, None)?;
+            Ok(vec![buf])
+        } else {
+            let blob_offset = descs[0].chunkinfo.compress_offset();
+            let last = &descs[descs.len() - 1].chunkinfo;
+            let blob_end = last.compress_offset() + last.compress_size() as u64;
+            let chunks = descs
+                .iter()
+                .map(|d| d.chunkinfo.clone())
+                .collect::<Vec<_>>();
+            blob.read_chunks(blob_offset, (blob_end - blob_offset) as usize, &chunks)
+        }
     }
 }
 
@@ -1059,6 +1607,9 @@ impl FileReadWriteVolatile for BlobDeviceIoVec<'_> {
         if let Some(index) = self.iovec.get_target_blob_index() {
             let blobs = &self.dev.blobs.load();
             if (index as usize) < blobs.len() {
+                if self.uncached {
+                    return self.read_uncached(&blobs[index as usize], buffers);
+                }
                 return blobs[index as usize].read(&self.iovec, buffers);
             }
         }
@@ -1137,8 +1688,122 @@ mod tests {
         assert_eq!(iochunk.is_hole(), false);
     }
 
+    #[test]
+    fn test_uncached_chunk_buffer() {
+        let chunk = |offset: u64| -> BlobIoChunk {
+            let chunk: Arc<dyn BlobChunkInfo> = Arc::new(MockChunkInfo {
+                compress_offset: offset,
+                ..Default::default()
+            });
+            chunk.into()
+        };
+        let buffer = UncachedChunkBuffer::new();
+
+        assert!(buffer.get(0, &chunk(0)).is_none());
+        for idx in 0..UNCACHED_CHUNK_BUFFERS as u64 {
+            buffer.insert(0, &chunk(idx * 0x100), Arc::new(vec![idx as u8]));
+        }
+        assert_eq!(buffer.get(0, &chunk(0)).unwrap().as_slice(), &[0u8]);
+        assert!(buffer.get(1, &chunk(0)).is_none());
+
+        // Re-inserting refreshes the chunk, so the least recently inserted one is evicted.
+        buffer.insert(0, &chunk(0), Arc::new(vec![0xff]));
+        buffer.insert(1, &chunk(0), Arc::new(vec![0xfe]));
+        assert_eq!(buffer.get(0, &chunk(0)).unwrap().as_slice(), &[0xffu8]);
+        assert_eq!(buffer.get(1, &chunk(0)).unwrap().as_slice(), &[0xfeu8]);
+        assert!(buffer.get(0, &chunk(0x100)).is_none());
+        assert!(buffer.get(0, &chunk(0x200)).is_some());
+
+        buffer.clear();
+        assert!(buffer.get(0, &chunk(0)).is_none());
+    }
+
     #[test]
     fn test_is_all_chunk_ready() {
         // TODO
     }
+
+    #[cfg(feature = "backend-localfs")]
+    #[test]
+    fn test_check_blobs() {
+        let tmp_dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        let data = vec![0x5au8; 0x1000];
+        let mut toc = BlobTocOndisk::default();
+        toc.set_chunk_count(2);
+        toc.set_blob_range(0, data.len() as u64);
+        toc.set_blob_digest(&RafsDigest::from_buf(&data, digest::Algorithm::Sha256));
+        let mut blob = data.clone();
+        blob.extend_from_slice(toc.as_bytes());
+        std::fs::write(tmp_dir.as_path().join("blob1"), &blob).unwrap();
+        std::fs::write(tmp_dir.as_path().join("blob2"), &data).unwrap();
+
+        let config = Arc::new(FactoryConfig {
+            backend: serde_json::from_value(serde_json::json!({
+                "type": "localfs",
+                "config": {"dir": tmp_dir.as_path().to_str().unwrap()},
+            }))
+            .unwrap(),
+            ..Default::default()
+        });
+        let blob_info = |id: &str, size: u64, chunks: u32| {
+            Arc::new(BlobInfo::new(
+                0,
+                id.to_string(),
+                size,
+                size,
+                0x1000,
+                chunks,
+                BlobFeatures::empty(),
+            ))
+        };
+
+        let infos = vec![blob_info("blob1", 0x1000, 2), blob_info("blob2", 0x1000, 1)];
+        assert!(BlobDevice::check_blobs(&config, &infos, true)
+            .unwrap()
+            .is_empty());
+
+        let infos = vec![
+            blob_info("blob1", 0x1000, 3),
+            blob_info("blob2", 0x2000, 1),
+            blob_info("blob3", 0x1000, 1),
+        ];
+        let failures = BlobDevice::check_blobs(&config, &infos, false).unwrap();
+        assert_eq!(failures.len(), 3);
+        assert!(!failures[0].missing && failures[0].reason.contains("chunk count"));
+        assert!(!failures[1].missing && failures[1].reason.contains("blob size"));
+        assert!(failures[2].missing);
+        assert_eq!(failures[2].blob_id, "blob3");
+    }
+    #[test]
+    fn test_switch_cache_type() {
+        let tmp_dir = vmm_sys_util::tempdir::TempDir::new().unwrap();
+        std::fs::write(tmp_dir.as_path().join("blob1"), vec![0x5au8; 0x1000]).unwrap();
+        let config = Arc::new(FactoryConfig {
+            backend: serde_json::from_value(serde_json::json!({
+                "type": "localfs",
+                "config": {"dir": tmp_dir.as_path().to_str().unwrap()},
+            }))
+            .unwrap(),
+            ..Default::default()
+        });
+        let infos = vec![Arc::new(BlobInfo::new(
+            0,
+            "blob1".to_string(),
+            0x1000,
+            0x1000,
+            0x1000,
+            1,
+            BlobFeatures::empty(),
+        ))];
+
+        let device = BlobDevice::new(&config, &infos).unwrap();
+        assert_eq!(device.cache_type(), "");
+        assert!(device.switch_cache_type("fscache", &infos).is_err());
+        assert!(device.switch_cache_type("dummy", &[]).is_err());
+        device.switch_cache_type("dummy", &infos).unwrap();
+        assert_eq!(device.cache_type(), "dummy");
+        device.switch_cache_type("direct", &infos).unwrap();
+        assert_eq!(device.cache_type(), "direct");
+        assert_eq!(device.blobs.load().len(), 1);
+    }
 }
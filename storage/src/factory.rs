@@ -16,20 +16,32 @@ use std::hash::{Hash, Hasher};
 use std::io::Result as IOResult;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::Deserialize;
 use serde_json::value::Value;
 
+#[cfg(feature = "backend-fault-injection")]
+use crate::backend::fault;
 #[cfg(feature = "backend-oss")]
 use crate::backend::oss;
 #[cfg(feature = "backend-registry")]
 use crate::backend::registry;
-use crate::backend::{localfs, BlobBackend};
-use crate::cache::{BlobCache, BlobCacheMgr, BlobPrefetchConfig, DummyCacheMgr, FileCacheMgr};
+use crate::backend::{localfs, p2p, tiered, BlobBackend, ErrnoMapping, ERRNO_NAMES};
+use crate::cache::{
+    reclaim_blob_files, BlobCache, BlobCacheMgr, BlobPrefetchConfig, CacheCompactStat,
+    DummyCacheMgr, FileCacheMgr,
+};
 use crate::device::BlobInfo;
 
+/// Types of storage backends, some of them may be disabled at build time.
+pub const BACKEND_TYPES: [&str; 6] = ["localfs", "oss", "registry", "p2p", "tiered", "fault"];
+
+/// Types of blob cache managers, data is not cached by the `dummy` and `direct` types.
+pub const CACHE_TYPES: [&str; 3] = ["blobcache", "dummy", "direct"];
+
 /// Configuration information for storage backend.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct BackendConfig {
     /// Type of storage backend.
     #[serde(rename = "type")]
@@ -67,6 +79,7 @@ impl BackendConfig {
 
 /// Configuration information for blob cache manager.
 #[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct CacheConfig {
     /// Type of blob cache.
     #[serde(default, rename = "type")]
@@ -83,10 +96,14 @@ pub struct CacheConfig {
     /// Configuration for blob data prefetching.
     #[serde(skip_serializing, skip_deserializing)]
     pub prefetch_config: BlobPrefetchConfig,
+    /// Policy to convert storage backend errors into errno values.
+    #[serde(default)]
+    pub errno_mapping: ErrnoMapping,
 }
 
 /// Configuration information to create blob cache manager.
 #[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct FactoryConfig {
     /// Id of the factory.
     #[serde(default)]
@@ -98,6 +115,49 @@ pub struct FactoryConfig {
     pub cache: CacheConfig,
 }
 
+impl FactoryConfig {
+    /// Validate the configuration before creating storage backends and blob caches, reporting
+    /// the invalid field and its expected values.
+    pub fn validate(&self) -> Result<()> {
+        let backend_type = self.backend.backend_type.as_str();
+        if !BACKEND_TYPES.contains(&backend_type) {
+            bail!(
+                "invalid value '{}' for `backend.type`, expected one of {}",
+                backend_type,
+                BACKEND_TYPES.join(", ")
+            );
+        }
+        if !self.backend.backend_config.is_object() {
+            bail!(
+                "invalid value '{}' for `backend.config`, expected a JSON object",
+                self.backend.backend_config
+            );
+        }
+
+        let cache_type = self.cache.cache_type.as_str();
+        if !cache_type.is_empty() && !CACHE_TYPES.contains(&cache_type) {
+            bail!(
+                "invalid value '{}' for `cache.type`, expected one of {}",
+                cache_type,
+                CACHE_TYPES.join(", ")
+            );
+        }
+        if !self.cache.cache_config.is_null() && !self.cache.cache_config.is_object() {
+            bail!(
+                "invalid value '{}' for `cache.config`, expected a JSON object",
+                self.cache.cache_config
+            );
+        }
+        self.cache.errno_mapping.validate().map_err(|_| {
+            anyhow!(
+                "invalid value {:?} for `cache.errno_mapping`, expected names of {}",
+                self.cache.errno_mapping,
+                ERRNO_NAMES.join(", ")
+            )
+        })
+    }
+}
+
 #[derive(Eq, PartialEq)]
 struct BlobCacheMgrKey {
     config: Arc<FactoryConfig>,
@@ -166,11 +226,42 @@ impl BlobFactory {
 
     /// Garbage-collect unused blob cache managers and blob caches.
     pub fn gc(&self) {
-        unimplemented!("TODO")
+        self.mgrs.lock().unwrap().retain(|_, mgr| {
+            if mgr.gc() {
+                mgr.destroy();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Remove cache files of blobs not used by any filesystem instance, and return paths of the
+    /// removed blob cache files.
+    ///
+    /// Cache files still in the grace period specified by `gc_grace_secs` are kept unless `force`.
+    pub fn reclaim_cache_files(&self, force: bool) -> Vec<String> {
+        self.gc();
+        reclaim_blob_files(force)
+    }
+
+    /// Compact cache files of all blob cache managers, after removing unused blob caches.
+    pub fn compact_cache_files(&self) -> CacheCompactStat {
+        self.gc();
+        let mgrs: Vec<Arc<dyn BlobCacheMgr>> =
+            self.mgrs.lock().unwrap().values().cloned().collect();
+        let mut stat = CacheCompactStat::default();
+        for mgr in mgrs {
+            let s = mgr.compact();
+            stat.punched_bytes += s.punched_bytes;
+            stat.removed.extend(s.removed);
+        }
+
+        stat
     }
 
     /// Create a storage backend for the blob with id `blob_id`.
-    fn new_backend(
+    pub fn new_backend(
         config: BackendConfig,
         blob_id: &str,
     ) -> IOResult<Arc<dyn BlobBackend + Send + Sync>> {
@@ -190,6 +281,19 @@ impl BlobFactory {
                 config.backend_config,
                 Some(blob_id),
             )?)),
+            #[cfg(feature = "backend-fault-injection")]
+            "fault" => Ok(Arc::new(fault::FaultBackend::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
+            "tiered" => Ok(Arc::new(tiered::TieredBackend::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
+            "p2p" => Ok(Arc::new(p2p::P2pBackend::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
             _ => Err(einval!(format!(
                 "unsupported backend type '{}'",
                 config.backend_type
@@ -219,4 +323,34 @@ mod tests {
 
         assert_eq!(config, config2);
     }
+
+    #[test]
+    fn test_factory_config_validate() {
+        let mut config: FactoryConfig = serde_json::from_str(
+            r#"{"backend":{"type":"localfs","config":{"dir":"/tmp"}},"cache":{"type":"blobcache"}}"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        config.cache.cache_type = "filecache".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`cache.type`"));
+        assert!(err.contains("blobcache, dummy, direct"));
+        config.cache.cache_type = String::new();
+        config.validate().unwrap();
+
+        config.backend.backend_config = serde_json::Value::from("/tmp");
+        assert!(config.validate().is_err());
+        config.backend.backend_type = "s3".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("`backend.type`"));
+
+        let err = serde_json::from_str::<FactoryConfig>(
+            r#"{"backend":{"type":"localfs","config":{}},"cahce":{"type":"blobcache"}}"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(err.contains("unknown field `cahce`"));
+    }
 }
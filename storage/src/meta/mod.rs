@@ -22,16 +22,20 @@ use std::os::unix::io::AsRawFd;
 use std::sync::Arc;
 
 use nydus_utils::digest::RafsDigest;
+use nydus_utils::metrics::BackendRequestClass;
 
-use crate::backend::BlobReader;
+use crate::backend::{BlobReader, RequestClassGuard};
 use crate::compress;
 use crate::device::{BlobChunkInfo, BlobInfo, BlobIoChunk};
 use std::any::Any;
 
+pub mod toc;
+
 const BLOB_METADATA_MAX_CHUNKS: u32 = 0xf_ffff;
 const BLOB_METADATA_MAX_SIZE: u64 = 0x100_0000u64;
 const BLOB_METADTAT_HEADER_SIZE: u64 = 0x1000u64;
-const BLOB_METADATA_RESERVED_SIZE: u64 = BLOB_METADTAT_HEADER_SIZE - 44;
+const BLOB_METADATA_RESERVED_SIZE: u64 = BLOB_METADTAT_HEADER_SIZE - 132;
+const BLOB_METADATA_BUILDER_VERSION_SIZE: usize = 48;
 const BLOB_METADATA_MAGIC: u32 = 0xb10bb10bu32;
 const BLOB_CHUNK_COMP_OFFSET_MASK: u64 = 0xfff_ffff_ffff;
 const BLOB_CHUNK_UNCOMP_OFFSET_MASK: u64 = 0xfff_ffff_f000;
@@ -58,6 +62,17 @@ pub struct BlobMetaHeaderOndisk {
     s_ci_compressed_size: u64,
     /// Size of uncompressed chunk information array
     s_ci_uncompressed_size: u64,
+    /// Seconds since UNIX epoch when the blob was built, 0 for repeatable builds.
+    ///
+    /// It's only passed to the blob table of the bootstrap, and always 0 in the blob itself to
+    /// keep the blob id reproducible.
+    s_build_time: u64,
+    /// Sha256 digest of the source content stored in the blob.
+    s_source_digest: [u8; 32],
+    /// Version of the builder which generated the blob, padded with '\0'.
+    ///
+    /// Like `s_build_time`, it's always empty in the blob itself.
+    s_builder_version: [u8; BLOB_METADATA_BUILDER_VERSION_SIZE],
     s_reserved: [u8; BLOB_METADATA_RESERVED_SIZE as usize],
     /// Second blob metadata magic number
     s_magic2: u32,
@@ -73,6 +88,9 @@ impl Default for BlobMetaHeaderOndisk {
             s_ci_offset: 0,
             s_ci_compressed_size: 0,
             s_ci_uncompressed_size: 0,
+            s_build_time: 0,
+            s_source_digest: [0u8; 32],
+            s_builder_version: [0u8; BLOB_METADATA_BUILDER_VERSION_SIZE],
             s_reserved: [0u8; BLOB_METADATA_RESERVED_SIZE as usize],
             s_magic2: BLOB_METADATA_MAGIC,
         }
@@ -153,6 +171,28 @@ impl BlobMetaHeaderOndisk {
         self.s_features
     }
 
+    /// Get provenance information of the blob, `None` if it's not recorded by the builder.
+    pub fn provenance(&self) -> Option<BlobProvenance> {
+        let provenance = BlobProvenance {
+            builder_version: BlobProvenance::decode_builder_version(&self.s_builder_version),
+            build_time: self.s_build_time,
+            source_digest: RafsDigest::from(self.s_source_digest),
+        };
+
+        if provenance.is_empty() {
+            None
+        } else {
+            Some(provenance)
+        }
+    }
+
+    /// Set provenance information of the blob.
+    pub fn set_provenance(&mut self, provenance: &BlobProvenance) {
+        self.s_build_time = provenance.build_time;
+        self.s_source_digest = provenance.source_digest.data;
+        provenance.encode_builder_version(&mut self.s_builder_version);
+    }
+
     /// Convert the header as an `&[u8]`.
     pub fn as_bytes(&self) -> &[u8] {
         unsafe {
@@ -164,6 +204,39 @@ impl BlobMetaHeaderOndisk {
     }
 }
 
+/// Provenance information of a blob, recorded by the builder.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlobProvenance {
+    /// Version of the builder which generated the blob, may be empty.
+    pub builder_version: String,
+    /// Seconds since UNIX epoch when the blob was built, 0 for repeatable builds.
+    pub build_time: u64,
+    /// Sha256 digest over digests of all chunks stored in the blob, in order.
+    pub source_digest: RafsDigest,
+}
+
+impl BlobProvenance {
+    /// Check whether there's no provenance information at all.
+    pub fn is_empty(&self) -> bool {
+        self.builder_version.is_empty()
+            && self.build_time == 0
+            && self.source_digest == RafsDigest::default()
+    }
+
+    /// Encode the builder version into a '\0' padded buffer, truncated if it's too long.
+    pub fn encode_builder_version(&self, buf: &mut [u8]) {
+        let len = std::cmp::min(self.builder_version.len(), buf.len());
+        buf.iter_mut().for_each(|v| *v = 0);
+        buf[..len].copy_from_slice(&self.builder_version.as_bytes()[..len]);
+    }
+
+    /// Decode the builder version from a '\0' padded buffer.
+    pub fn decode_builder_version(buf: &[u8]) -> String {
+        let len = buf.iter().position(|v| *v == 0).unwrap_or(buf.len());
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    }
+}
+
 /// Blob chunk compression information on disk format.
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
@@ -538,6 +611,7 @@ impl BlobMetaInfo {
             blob_info.meta_ci_uncompressed_size(),
         );
 
+        let _guard = RequestClassGuard::enter(BackendRequestClass::Metadata);
         if blob_info.meta_ci_compressor() == compress::Algorithm::None {
             let size = reader
                 .read(buffer, blob_info.meta_ci_offset())
@@ -1023,4 +1097,38 @@ mod tests {
 
         assert_eq!(buffer, data);
     }
+
+    #[test]
+    fn test_blob_meta_header_provenance() {
+        let mut header = BlobMetaHeaderOndisk::default();
+        assert_eq!(
+            size_of::<BlobMetaHeaderOndisk>() as u64,
+            BLOB_METADTAT_HEADER_SIZE
+        );
+        assert!(header.provenance().is_none());
+
+        let provenance = BlobProvenance {
+            builder_version: "v2.1.0-0123456789abcdef".to_string(),
+            build_time: 1_600_000_000,
+            source_digest: RafsDigest { data: [0xa5; 32] },
+        };
+        header.set_provenance(&provenance);
+        assert_eq!(header.provenance().unwrap(), provenance);
+
+        let provenance = BlobProvenance {
+            builder_version: "v".repeat(BLOB_METADATA_BUILDER_VERSION_SIZE + 1),
+            build_time: 0,
+            source_digest: RafsDigest::default(),
+        };
+        header.set_provenance(&provenance);
+        let loaded = header.provenance().unwrap();
+        assert_eq!(loaded.build_time, 0);
+        assert_eq!(
+            loaded.builder_version,
+            "v".repeat(BLOB_METADATA_BUILDER_VERSION_SIZE)
+        );
+
+        header.set_provenance(&BlobProvenance::default());
+        assert!(header.provenance().is_none());
+    }
 }
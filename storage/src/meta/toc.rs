@@ -0,0 +1,438 @@
+// Copyright 2021 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Table of contents (TOC) footer to make blobs self-describing.
+//!
+//! The builder may append a TOC to the end of a blob file, after the blob data and blob metadata,
+//! so the blob is laid out as `[blob data], [TOC]`. The TOC records the compression algorithm,
+//! location and digest of the chunk information array, digest of blob data, digest of the
+//! bootstrap built together with the blob, and the builder version and build time, so the blob
+//! can be verified without the bootstrap. The TOC is not covered by the blob digest, which is the
+//! blob id if the blob is named by its digest, so build metadata in the TOC doesn't change the
+//! blob id.
+
+use std::convert::TryFrom;
+use std::io::Result;
+use std::mem::size_of;
+
+use nydus_utils::digest::{self, DigestHasher, RafsDigest};
+use nydus_utils::metrics::BackendRequestClass;
+
+use crate::backend::{BlobReader, RequestClassGuard};
+use crate::compress;
+
+/// Size of the blob TOC footer.
+pub const BLOB_TOC_SIZE: u64 = 0x400;
+
+const BLOB_TOC_MAGIC: u32 = 0xb10c_70c0;
+const BLOB_TOC_VERSION: u32 = 1;
+const BLOB_TOC_BUILDER_VERSION_SIZE: usize = 128;
+const BLOB_TOC_RESERVED_SIZE: usize = 732;
+const BLOB_TOC_READ_SIZE: usize = 0x10_0000;
+
+/// Blob TOC on disk format.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BlobTocOndisk {
+    /// Blob TOC magic number.
+    s_magic: u32,
+    /// Blob TOC format version.
+    s_version: u32,
+    /// Compression algorithm for chunk data.
+    s_compressor: u32,
+    /// Digest algorithm for chunk data.
+    s_digester: u32,
+    /// Number of chunks in the blob.
+    s_chunk_count: u32,
+    /// Size of uncompressed chunks.
+    s_chunk_size: u32,
+    /// Offset of blob data in the blob file.
+    s_blob_offset: u64,
+    /// Size of blob data, including chunk data and blob metadata.
+    s_blob_size: u64,
+    /// Offset of compressed chunk information array in the blob file.
+    s_ci_offset: u64,
+    /// Size of compressed chunk information array.
+    s_ci_size: u64,
+    /// Seconds since the UNIX epoch when the blob is built, 0 for repeatable builds.
+    s_build_time: u64,
+    /// Sha256 digest of blob data.
+    s_blob_digest: [u8; 32],
+    /// Sha256 digest of compressed chunk information array.
+    s_ci_digest: [u8; 32],
+    /// Sha256 digest of the bootstrap built together with the blob.
+    s_bootstrap_digest: [u8; 32],
+    /// Version of the builder, padded with NUL.
+    s_builder_version: [u8; BLOB_TOC_BUILDER_VERSION_SIZE],
+    s_reserved: [u8; BLOB_TOC_RESERVED_SIZE],
+    /// Second blob TOC magic number.
+    s_magic2: u32,
+}
+
+impl Default for BlobTocOndisk {
+    fn default() -> Self {
+        BlobTocOndisk {
+            s_magic: BLOB_TOC_MAGIC,
+            s_version: BLOB_TOC_VERSION,
+            s_compressor: compress::Algorithm::None as u32,
+            s_digester: digest::Algorithm::Blake3 as u32,
+            s_chunk_count: 0,
+            s_chunk_size: 0,
+            s_blob_offset: 0,
+            s_blob_size: 0,
+            s_ci_offset: 0,
+            s_ci_size: 0,
+            s_build_time: 0,
+            s_blob_digest: [0u8; 32],
+            s_ci_digest: [0u8; 32],
+            s_bootstrap_digest: [0u8; 32],
+            s_builder_version: [0u8; BLOB_TOC_BUILDER_VERSION_SIZE],
+            s_reserved: [0u8; BLOB_TOC_RESERVED_SIZE],
+            s_magic2: BLOB_TOC_MAGIC,
+        }
+    }
+}
+
+impl BlobTocOndisk {
+    /// Get compression algorithm for chunk data.
+    pub fn compressor(&self) -> Result<compress::Algorithm> {
+        compress::Algorithm::try_from(self.s_compressor)
+            .map_err(|_| einval!(format!("invalid compressor {}", self.s_compressor)))
+    }
+
+    /// Set compression algorithm for chunk data.
+    pub fn set_compressor(&mut self, algo: compress::Algorithm) {
+        self.s_compressor = algo as u32;
+    }
+
+    /// Get digest algorithm for chunk data.
+    pub fn digester(&self) -> Result<digest::Algorithm> {
+        digest::Algorithm::try_from(self.s_digester)
+            .map_err(|_| einval!(format!("invalid digester {}", self.s_digester)))
+    }
+
+    /// Set digest algorithm for chunk data.
+    pub fn set_digester(&mut self, algo: digest::Algorithm) {
+        self.s_digester = algo as u32;
+    }
+
+    /// Get number of chunks in the blob.
+    pub fn chunk_count(&self) -> u32 {
+        self.s_chunk_count
+    }
+
+    /// Set number of chunks in the blob.
+    pub fn set_chunk_count(&mut self, count: u32) {
+        self.s_chunk_count = count;
+    }
+
+    /// Get size of uncompressed chunks.
+    pub fn chunk_size(&self) -> u32 {
+        self.s_chunk_size
+    }
+
+    /// Set size of uncompressed chunks.
+    pub fn set_chunk_size(&mut self, size: u32) {
+        self.s_chunk_size = size;
+    }
+
+    /// Get offset of blob data in the blob file.
+    pub fn blob_offset(&self) -> u64 {
+        self.s_blob_offset
+    }
+
+    /// Get size of blob data.
+    pub fn blob_size(&self) -> u64 {
+        self.s_blob_size
+    }
+
+    /// Set offset and size of blob data in the blob file.
+    pub fn set_blob_range(&mut self, offset: u64, size: u64) {
+        self.s_blob_offset = offset;
+        self.s_blob_size = size;
+    }
+
+    /// Get offset of compressed chunk information array.
+    pub fn ci_offset(&self) -> u64 {
+        self.s_ci_offset
+    }
+
+    /// Get size of compressed chunk information array.
+    pub fn ci_size(&self) -> u64 {
+        self.s_ci_size
+    }
+
+    /// Set offset and size of compressed chunk information array.
+    pub fn set_ci_range(&mut self, offset: u64, size: u64) {
+        self.s_ci_offset = offset;
+        self.s_ci_size = size;
+    }
+
+    /// Get seconds since the UNIX epoch when the blob is built.
+    pub fn build_time(&self) -> u64 {
+        self.s_build_time
+    }
+
+    /// Set seconds since the UNIX epoch when the blob is built.
+    pub fn set_build_time(&mut self, time: u64) {
+        self.s_build_time = time;
+    }
+
+    /// Get digest of blob data.
+    pub fn blob_digest(&self) -> RafsDigest {
+        RafsDigest::from(self.s_blob_digest)
+    }
+
+    /// Set digest of blob data.
+    pub fn set_blob_digest(&mut self, digest: &RafsDigest) {
+        self.s_blob_digest = digest.data;
+    }
+
+    /// Get digest of compressed chunk information array.
+    pub fn ci_digest(&self) -> RafsDigest {
+        RafsDigest::from(self.s_ci_digest)
+    }
+
+    /// Set digest of compressed chunk information array.
+    pub fn set_ci_digest(&mut self, digest: &RafsDigest) {
+        self.s_ci_digest = digest.data;
+    }
+
+    /// Get digest of the bootstrap built together with the blob.
+    pub fn bootstrap_digest(&self) -> RafsDigest {
+        RafsDigest::from(self.s_bootstrap_digest)
+    }
+
+    /// Set digest of the bootstrap built together with the blob.
+    pub fn set_bootstrap_digest(&mut self, digest: &RafsDigest) {
+        self.s_bootstrap_digest = digest.data;
+    }
+
+    /// Get version of the builder.
+    pub fn builder_version(&self) -> String {
+        let len = self
+            .s_builder_version
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(BLOB_TOC_BUILDER_VERSION_SIZE);
+        String::from_utf8_lossy(&self.s_builder_version[..len]).to_string()
+    }
+
+    /// Set version of the builder, which is truncated if it's too long.
+    pub fn set_builder_version(&mut self, version: &str) {
+        let len = std::cmp::min(version.len(), BLOB_TOC_BUILDER_VERSION_SIZE);
+        self.s_builder_version = [0u8; BLOB_TOC_BUILDER_VERSION_SIZE];
+        self.s_builder_version[..len].copy_from_slice(&version.as_bytes()[..len]);
+    }
+
+    /// Convert the TOC as an `&[u8]`.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self as *const BlobTocOndisk as *const u8,
+                size_of::<BlobTocOndisk>(),
+            )
+        }
+    }
+
+    /// Create a TOC from its on disk format.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() != size_of::<BlobTocOndisk>() {
+            return Err(einval!("invalid blob TOC size"));
+        }
+        let mut toc = BlobTocOndisk::default();
+        // Safe because the buffer has the same size as the TOC, which is plain old data.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                buf.as_ptr(),
+                &mut toc as *mut BlobTocOndisk as *mut u8,
+                buf.len(),
+            )
+        };
+        toc.validate()?;
+
+        Ok(toc)
+    }
+
+    /// Validate fields of the TOC.
+    pub fn validate(&self) -> Result<()> {
+        if self.s_magic != BLOB_TOC_MAGIC || self.s_magic2 != BLOB_TOC_MAGIC {
+            return Err(einval!("invalid blob TOC magic"));
+        }
+        if self.s_version != BLOB_TOC_VERSION {
+            return Err(einval!(format!(
+                "unsupported blob TOC version {}",
+                self.s_version
+            )));
+        }
+        self.compressor()?;
+        self.digester()?;
+        let blob_end = self
+            .s_blob_offset
+            .checked_add(self.s_blob_size)
+            .ok_or_else(|| einval!("invalid blob data range in TOC"))?;
+        match self.s_ci_offset.checked_add(self.s_ci_size) {
+            Some(end)
+                if self.s_ci_size == 0
+                    || (self.s_ci_offset >= self.s_blob_offset && end <= blob_end) =>
+            {
+                Ok(())
+            }
+            _ => Err(einval!("invalid chunk information array range in TOC")),
+        }
+    }
+
+    /// Read the TOC from the end of a blob.
+    ///
+    /// Returns `Ok(None)` if the blob has no TOC.
+    pub fn read_from(reader: &dyn BlobReader) -> Result<Option<Self>> {
+        let size = reader
+            .blob_size()
+            .map_err(|e| eio!(format!("failed to get blob size, {:?}", e)))?;
+        if size < BLOB_TOC_SIZE {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; BLOB_TOC_SIZE as usize];
+        Self::read_exact(reader, &mut buf, size - BLOB_TOC_SIZE)?;
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if magic != BLOB_TOC_MAGIC {
+            return Ok(None);
+        }
+
+        Self::from_bytes(&buf).map(Some)
+    }
+
+    /// Verify digests of blob data and chunk information array, and digest of the bootstrap if
+    /// it's provided.
+    pub fn verify(&self, reader: &dyn BlobReader, bootstrap: Option<&[u8]>) -> Result<()> {
+        let blob_digest = Self::digest_range(reader, self.s_blob_offset, self.s_blob_size)?;
+        if blob_digest != self.blob_digest() {
+            return Err(einval!(format!(
+                "blob digest mismatch, expect {} but got {}",
+                self.blob_digest(),
+                blob_digest
+            )));
+        }
+        if self.s_ci_size > 0 {
+            let ci_digest = Self::digest_range(reader, self.s_ci_offset, self.s_ci_size)?;
+            if ci_digest != self.ci_digest() {
+                return Err(einval!(format!(
+                    "chunk information array digest mismatch, expect {} but got {}",
+                    self.ci_digest(),
+                    ci_digest
+                )));
+            }
+        }
+        if let Some(bootstrap) = bootstrap {
+            let digest = RafsDigest::from_buf(bootstrap, digest::Algorithm::Sha256);
+            if digest != self.bootstrap_digest() {
+                return Err(einval!(format!(
+                    "bootstrap digest mismatch, expect {} but got {}",
+                    self.bootstrap_digest(),
+                    digest
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn digest_range(reader: &dyn BlobReader, offset: u64, size: u64) -> Result<RafsDigest> {
+        let mut hasher = RafsDigest::hasher(digest::Algorithm::Sha256);
+        let mut buf = vec![0u8; BLOB_TOC_READ_SIZE];
+        let mut pos = 0;
+
+        while pos < size {
+            let len = std::cmp::min(size - pos, BLOB_TOC_READ_SIZE as u64) as usize;
+            Self::read_exact(reader, &mut buf[..len], offset + pos)?;
+            hasher.digest_update(&buf[..len]);
+            pos += len as u64;
+        }
+
+        Ok(hasher.digest_finalize())
+    }
+
+    fn read_exact(reader: &dyn BlobReader, buf: &mut [u8], offset: u64) -> Result<()> {
+        let _guard = RequestClassGuard::enter(BackendRequestClass::Metadata);
+        let mut pos = 0;
+
+        while pos < buf.len() {
+            let size = reader
+                .read(&mut buf[pos..], offset + pos as u64)
+                .map_err(|e| eio!(format!("failed to read blob, {:?}", e)))?;
+            if size == 0 {
+                return Err(eio!("unexpected end of blob"));
+            }
+            pos += size;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockBackend;
+    use nydus_utils::metrics::BackendMetrics;
+
+    #[test]
+    fn test_blob_toc_ondisk() {
+        assert_eq!(size_of::<BlobTocOndisk>() as u64, BLOB_TOC_SIZE);
+
+        let mut toc = BlobTocOndisk::default();
+        toc.set_compressor(compress::Algorithm::Lz4Block);
+        toc.set_digester(digest::Algorithm::Sha256);
+        toc.set_chunk_count(2);
+        toc.set_blob_range(0, 0x2000);
+        toc.set_ci_range(0x1000, 0x100);
+        toc.set_builder_version("v1.0.0-abcdef");
+        toc.set_bootstrap_digest(&RafsDigest::from_buf(
+            b"bootstrap",
+            digest::Algorithm::Sha256,
+        ));
+
+        let toc2 = BlobTocOndisk::from_bytes(toc.as_bytes()).unwrap();
+        assert_eq!(toc2.compressor().unwrap(), compress::Algorithm::Lz4Block);
+        assert_eq!(toc2.digester().unwrap(), digest::Algorithm::Sha256);
+        assert_eq!(toc2.chunk_count(), 2);
+        assert_eq!(toc2.ci_offset(), 0x1000);
+        assert_eq!(toc2.builder_version(), "v1.0.0-abcdef");
+        assert_eq!(toc2.bootstrap_digest(), toc.bootstrap_digest());
+
+        toc.set_ci_range(0x2000, 0x100);
+        assert!(BlobTocOndisk::from_bytes(toc.as_bytes()).is_err());
+        assert!(BlobTocOndisk::from_bytes(&[0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_blob_toc_read_from() {
+        let metrics = BackendMetrics::new("test_blob_toc_read_from", "mock");
+        let data = vec![0x5au8; 0x2000];
+        let mut toc = BlobTocOndisk::default();
+        toc.set_chunk_count(3);
+        toc.set_blob_range(0, data.len() as u64);
+        toc.set_blob_digest(&RafsDigest::from_buf(&data, digest::Algorithm::Sha256));
+        toc.set_build_time(1234);
+        toc.set_builder_version("v2.0.0-abcdef");
+
+        // Blobs without TOC, e.g. built without the option.
+        let reader = MockBackend::with_data(data.clone(), metrics.clone());
+        assert!(BlobTocOndisk::read_from(&reader).unwrap().is_none());
+        let reader = MockBackend::with_data(vec![0u8; 0x10], metrics.clone());
+        assert!(BlobTocOndisk::read_from(&reader).unwrap().is_none());
+
+        let mut blob = data;
+        blob.extend_from_slice(toc.as_bytes());
+        let reader = MockBackend::with_data(blob, metrics.clone());
+        let toc2 = BlobTocOndisk::read_from(&reader).unwrap().unwrap();
+        assert_eq!(toc2.chunk_count(), 3);
+        assert_eq!(toc2.build_time(), 1234);
+        assert_eq!(toc2.builder_version(), "v2.0.0-abcdef");
+        // The TOC itself is excluded from the blob digest.
+        toc2.verify(&reader, None).unwrap();
+        metrics.release().unwrap();
+    }
+}
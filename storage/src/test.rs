@@ -3,33 +3,79 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use nydus_utils::digest::RafsDigest;
-use nydus_utils::metrics::BackendMetrics;
+use nydus_utils::metrics::{BackendMetrics, BackendRequestClass};
 
 use super::impl_getter;
-use crate::backend::{BackendResult, BlobBackend, BlobReader};
+use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader, RequestPolicy};
 use crate::device::v5::BlobV5ChunkInfo;
 use crate::device::{BlobChunkFlags, BlobChunkInfo};
 use std::any::Any;
 
 pub(crate) struct MockBackend {
     pub metrics: Arc<BackendMetrics>,
+    /// Content of the blob, or buffers are filled with their indexes if it's None.
+    pub data: Option<Vec<u8>>,
+    /// Only serve requests for the whole blob, as a backend throttling range requests.
+    pub whole_blob_only: bool,
+    /// Number of following requests to fail.
+    pub failures: AtomicUsize,
+    /// Timeouts and retry policy for each class of requests.
+    pub request_policy: Option<fn(BackendRequestClass) -> RequestPolicy>,
+}
+
+impl MockBackend {
+    pub fn new(metrics: Arc<BackendMetrics>) -> Self {
+        MockBackend {
+            metrics,
+            data: None,
+            whole_blob_only: false,
+            failures: AtomicUsize::new(0),
+            request_policy: None,
+        }
+    }
+
+    pub fn with_data(data: Vec<u8>, metrics: Arc<BackendMetrics>) -> Self {
+        MockBackend {
+            data: Some(data),
+            ..Self::new(metrics)
+        }
+    }
 }
 
 impl BlobReader for MockBackend {
     fn blob_size(&self) -> BackendResult<u64> {
-        Ok(0)
+        Ok(self.data.as_ref().map(|d| d.len() as u64).unwrap_or(0))
     }
 
-    fn try_read(&self, buf: &mut [u8], _offset: u64) -> BackendResult<usize> {
-        let mut i = 0;
-        while i < buf.len() {
-            buf[i] = i as u8;
-            i += 1;
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        if self.failures.load(Ordering::Relaxed) > 0 {
+            self.failures.fetch_sub(1, Ordering::Relaxed);
+            return Err(BackendError::Task("mock failure".to_string()));
+        }
+
+        match self.data.as_ref() {
+            Some(data) => {
+                if self.whole_blob_only && (offset != 0 || buf.len() != data.len()) {
+                    return Err(BackendError::Unsupported("throttled".to_string()));
+                }
+                let offset = std::cmp::min(offset as usize, data.len());
+                let size = std::cmp::min(buf.len(), data.len() - offset);
+                buf[..size].copy_from_slice(&data[offset..offset + size]);
+                Ok(size)
+            }
+            None => {
+                let mut i = 0;
+                while i < buf.len() {
+                    buf[i] = i as u8;
+                    i += 1;
+                }
+                Ok(i)
+            }
         }
-        Ok(i)
     }
 
     fn prefetch_blob_data_range(
@@ -49,6 +95,16 @@ impl BlobReader for MockBackend {
         // but use backend instance to upload blob.
         &self.metrics
     }
+
+    fn request_policy(&self, class: BackendRequestClass) -> RequestPolicy {
+        match self.request_policy {
+            Some(policy) => policy(class),
+            None => RequestPolicy {
+                retry_limit: self.retry_limit(),
+                ..Default::default()
+            },
+        }
+    }
 }
 
 impl BlobBackend for MockBackend {
@@ -63,6 +119,10 @@ impl BlobBackend for MockBackend {
     fn get_reader(&self, _blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
         Ok(Arc::new(MockBackend {
             metrics: self.metrics.clone(),
+            data: self.data.clone(),
+            whole_blob_only: self.whole_blob_only,
+            failures: AtomicUsize::new(self.failures.load(Ordering::Relaxed)),
+            request_policy: self.request_policy,
         }))
     }
 }
@@ -270,7 +270,7 @@ impl<'a> Builder<'a> {
 
         exec(
             format!(
-                "{:?} create --bootstrap {:?} --backend-type localfs --backend-config '{{\"blob_file\": {:?}}}' --log-level info --compressor {} --whiteout-spec {} {:?}",
+                "{:?} create --allow-devices --allow-sockets --bootstrap {:?} --backend-type localfs --backend-config '{{\"blob_file\": {:?}}}' --log-level info --compressor {} --whiteout-spec {} {:?}",
                 self.builder,
                 self.work_dir.join("bootstrap-specialfiles"),
                 self.work_dir.join("smoke-localfs-blob"),
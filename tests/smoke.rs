@@ -256,3 +256,63 @@ fn integration_test_stargz() {
     nydusd.check("directory/overlay.result", "mnt");
     nydusd.umount("mnt");
 }
+
+fn inspect_dedup(bootstrap: &Path, chunk_dict: Option<&Path>) -> Vec<serde_json::Value> {
+    let builder = std::env::var("NYDUS_IMAGE")
+        .unwrap_or_else(|_| String::from("./target-fusedev/release/nydus-image"));
+    let request = match chunk_dict {
+        Some(dict) => format!("dedup bootstrap={}", dict.display()),
+        None => "dedup".to_string(),
+    };
+    let output = exec(
+        format!("{:?} inspect -B {:?} -R {:?}", builder, bootstrap, request).as_str(),
+        true,
+    )
+    .unwrap();
+
+    match serde_json::from_str(&output).unwrap() {
+        serde_json::Value::Array(blobs) => blobs,
+        v => panic!("unexpected dedup output {:?}", v),
+    }
+}
+
+#[test]
+fn integration_test_inspect_dedup() {
+    info!("\n\n==================== testing run: inspect dedup test");
+
+    let texture = Path::new("tests/texture/repeatable");
+    for bs in COMPAT_BOOTSTRAPS.iter() {
+        let bootstrap = texture.join(bs);
+
+        let blobs = inspect_dedup(&bootstrap, None);
+        assert!(!blobs.is_empty());
+        for blob in blobs.iter() {
+            let blob_id = blob["blob_id"].as_str().unwrap();
+            assert!(texture.join("blobs").join(blob_id).is_file());
+
+            let total = blob["total_chunks"].as_u64().unwrap();
+            let unique = blob["unique_chunks"].as_u64().unwrap();
+            let duplicated = blob["duplicated_chunks"].as_u64().unwrap();
+            assert!(total > 0);
+            // Each chunk reference is either the first one to a chunk in the image, or a
+            // duplicated one, and the first references to a chunk are unique in the blob.
+            assert!(unique <= total);
+            assert!(unique >= total - duplicated);
+            assert!(blob["unique_bytes"].as_u64().unwrap() > 0);
+            let duplicated_bytes = blob["duplicated_bytes"].as_u64().unwrap();
+            assert_eq!(duplicated == 0, duplicated_bytes == 0);
+            assert_eq!(blob["dict_chunks"].as_u64(), Some(0));
+            assert_eq!(blob["dict_bytes"].as_u64(), Some(0));
+        }
+
+        // All chunks of an image exist in the chunk dictionary generated from itself.
+        let dict_blobs = inspect_dedup(&bootstrap, Some(&bootstrap));
+        assert_eq!(dict_blobs.len(), blobs.len());
+        for (blob, dict_blob) in blobs.iter().zip(dict_blobs.iter()) {
+            assert_eq!(dict_blob["blob_id"], blob["blob_id"]);
+            assert_eq!(dict_blob["total_chunks"], blob["total_chunks"]);
+            assert_eq!(dict_blob["dict_chunks"], blob["unique_chunks"]);
+            assert!(dict_blob["dict_bytes"].as_u64().unwrap() > 0);
+        }
+    }
+}
@@ -21,6 +21,7 @@ pub mod digest;
 pub mod exec;
 pub mod inode_bitmap;
 pub mod metrics;
+pub mod trace;
 pub mod types;
 
 /// Round up and divide the value `n` by `d`.
@@ -4,15 +4,16 @@
 
 //! Rafs fop stats accounting and exporting.
 
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::{Deref, Drop};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use nydus_error::logger::ErrorHolder;
-use serde_json::Error as SerdeError;
+use serde_json::{json, Error as SerdeError, Value};
 
 use crate::InodeBitmap;
 
@@ -38,6 +39,70 @@ pub enum StatsFop {
     Max,
 }
 
+// Names of file operations used when exporting metrics, indexed by `StatsFop`.
+const STATS_FOP_NAMES: [&str; StatsFop::Max as usize] = [
+    "getattr",
+    "readlink",
+    "open",
+    "release",
+    "read",
+    "statfs",
+    "getxattr",
+    "listxattr",
+    "opendir",
+    "lookup",
+    "readdir",
+    "readdirplus",
+    "access",
+    "forget",
+    "batch_forget",
+];
+
+/// File operations modifying the filesystem, which are rejected by read-only filesystems.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum WriteFop {
+    Open,
+    Setattr,
+    Mknod,
+    Mkdir,
+    Unlink,
+    Rmdir,
+    Symlink,
+    Rename,
+    Link,
+    Create,
+    Write,
+    Fallocate,
+    Setxattr,
+    Removexattr,
+    Max,
+}
+
+// Names of write-class file operations used when exporting metrics, indexed by `WriteFop`.
+const WRITE_FOP_NAMES: [&str; WriteFop::Max as usize] = [
+    "open",
+    "setattr",
+    "mknod",
+    "mkdir",
+    "unlink",
+    "rmdir",
+    "symlink",
+    "rename",
+    "link",
+    "create",
+    "write",
+    "fallocate",
+    "setxattr",
+    "removexattr",
+];
+
+impl WriteFop {
+    /// Get name of the file operation.
+    pub fn name(&self) -> &'static str {
+        WRITE_FOP_NAMES[*self as usize]
+    }
+}
+
 #[derive(Debug)]
 pub enum IoStatsError {
     NoCounter,
@@ -89,6 +154,18 @@ fn latency_micros_range_index(elapsed: u64) -> usize {
     }
 }
 
+// Upper bounds of file operation latency histogram buckets, in unit of micro-second.
+// <=10us, <=50us, <=100us, <=500us, <=1ms, <=10ms, <=100ms, <=1s, >1s
+const FOP_LATENCY_BUCKETS: [u64; 8] = [10, 50, 100, 500, 1_000, 10_000, 100_000, 1_000_000];
+const FOP_LATENCY_RANGE_MAX: usize = FOP_LATENCY_BUCKETS.len() + 1;
+
+fn fop_latency_range_index(elapsed: u64) -> usize {
+    FOP_LATENCY_BUCKETS
+        .iter()
+        .position(|b| elapsed <= *b)
+        .unwrap_or(FOP_LATENCY_BUCKETS.len())
+}
+
 // Defining below global static metrics set so that a specific metrics counter can
 // be found as per the rafs backend mountpoint/id. Remind that nydusd can have
 // multiple backends mounted.
@@ -105,6 +182,20 @@ lazy_static! {
         Default::default();
 }
 
+lazy_static! {
+    static ref ACCOUNTING_SET: RwLock<HashMap<String, Weak<AccessAccounting>>> = Default::default();
+}
+
+thread_local! {
+    // Access accounting of the filesystem instance serving the request on current thread.
+    static CURRENT_ACCOUNTING: RefCell<Option<Arc<AccessAccounting>>> = RefCell::new(None);
+}
+
+lazy_static! {
+    /// Metrics of the buffer pool shared by read requests of all filesystem instances.
+    pub static ref BUFFER_POOL_METRICS: BufferPoolMetrics = BufferPoolMetrics::default();
+}
+
 lazy_static! {
     pub static ref ERROR_HOLDER: Arc<Mutex<ErrorHolder>> =
         Arc::new(Mutex::new(ErrorHolder::new(500, 50 * 1024)));
@@ -138,8 +229,12 @@ pub struct GlobalIoStats {
     // Record how many times read latency drops to the ranges.
     // This helps us to understand the io service time stability.
     read_latency_dist: [BasicMetric; READ_LATENCY_RANGE_MAX],
+    // Latency histograms of each type of file operation.
+    fop_latency_dist: [LatencyHistogram; StatsFop::Max as usize],
     // Total number of files that are currently open.
     nr_opens: BasicMetric,
+    // Counters for write-class file operations rejected by the read-only filesystem.
+    rejected_writes: [BasicMetric; WriteFop::Max as usize],
     // Rwlock closes the race that more than one threads are creating counters concurrently.
     #[serde(skip_serializing, skip_deserializing)]
     file_counters: RwLock<HashMap<Inode, Arc<InodeIoStats>>>,
@@ -150,6 +245,24 @@ pub struct GlobalIoStats {
     recent_read_files: InodeBitmap,
 }
 
+/// Latency histogram of a type of file operation.
+///
+/// Bucket counters are not cumulative, the last bucket counts operations slower than the
+/// largest bound of `FOP_LATENCY_BUCKETS`.
+#[derive(Default, Debug, Serialize)]
+pub struct LatencyHistogram {
+    buckets: [BasicMetric; FOP_LATENCY_RANGE_MAX],
+    // Cumulative latency in unit of micro-second.
+    sum: BasicMetric,
+}
+
+impl LatencyHistogram {
+    fn observe(&self, elapsed: u64) {
+        self.buckets[fop_latency_range_index(elapsed)].inc();
+        self.sum.add(elapsed);
+    }
+}
+
 #[derive(Default, Debug, Serialize)]
 pub struct InodeIoStats {
     total_fops: BasicMetric,
@@ -343,6 +456,16 @@ impl GlobalIoStats {
         }
     }
 
+    /// Count a write-class file operation rejected by the read-only filesystem.
+    pub fn write_rejected(&self, fop: WriteFop) {
+        self.rejected_writes[fop as usize].inc();
+    }
+
+    /// Get number of rejected write-class file operations of type `fop`.
+    pub fn rejected_writes(&self, fop: WriteFop) -> u64 {
+        self.rejected_writes[fop as usize].count()
+    }
+
     /// Paired with `latency_end` to record elapsed time for a certain type of fop.
     pub fn latency_start(&self) -> Option<SystemTime> {
         if !self.measure_latency.load(Ordering::Relaxed) {
@@ -362,6 +485,14 @@ impl GlobalIoStats {
         }
     }
 
+    fn fop_latency_update(&self, start: &Option<SystemTime>, fop: StatsFop) {
+        if let Some(start) = start {
+            if let Ok(d) = SystemTime::elapsed(start) {
+                self.fop_latency_dist[fop as usize].observe(saturating_duration_micros(&d));
+            }
+        }
+    }
+
     fn export_files_stats(&self) -> Result<String, IoStatsError> {
         serde_json::to_string(
             self.file_counters
@@ -393,6 +524,85 @@ impl GlobalIoStats {
     fn export_global_stats(&self) -> Result<String, IoStatsError> {
         serde_json::to_string(self).map_err(IoStatsError::Serialize)
     }
+
+    // Append latency histograms in Prometheus text exposition format, with cumulative buckets
+    // and latency in unit of second.
+    fn export_prometheus_latency(&self, out: &mut String) {
+        for (idx, name) in STATS_FOP_NAMES.iter().enumerate() {
+            let histogram = &self.fop_latency_dist[idx];
+            let labels = format!("id=\"{}\",fop=\"{}\"", self.id, name);
+            let mut count = 0;
+            for (bucket, bound) in FOP_LATENCY_BUCKETS.iter().enumerate() {
+                count += histogram.buckets[bucket].count();
+                out.push_str(&format!(
+                    "nydus_fop_latency_seconds_bucket{{{},le=\"{}\"}} {}\n",
+                    labels,
+                    *bound as f64 / 1_000_000f64,
+                    count
+                ));
+            }
+            count += histogram.buckets[FOP_LATENCY_BUCKETS.len()].count();
+            out.push_str(&format!(
+                "nydus_fop_latency_seconds_bucket{{{},le=\"+Inf\"}} {}\n",
+                labels, count
+            ));
+            out.push_str(&format!(
+                "nydus_fop_latency_seconds_sum{{{}}} {}\n",
+                labels,
+                histogram.sum.count() as f64 / 1_000_000f64
+            ));
+            out.push_str(&format!(
+                "nydus_fop_latency_seconds_count{{{}}} {}\n",
+                labels, count
+            ));
+        }
+    }
+
+    // Append latency histograms as OTLP histogram data points, with latency in unit of second.
+    fn export_otlp_latency(&self, points: &mut Vec<Value>, start: &str, now: &str) {
+        let bounds: Vec<f64> = FOP_LATENCY_BUCKETS
+            .iter()
+            .map(|b| *b as f64 / 1_000_000f64)
+            .collect();
+        for (idx, name) in STATS_FOP_NAMES.iter().enumerate() {
+            let histogram = &self.fop_latency_dist[idx];
+            let buckets: Vec<u64> = histogram.buckets.iter().map(|b| b.count()).collect();
+            points.push(json!({
+                "attributes": otlp_attributes(&[("id", self.id.as_str()), ("fop", *name)]),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "count": buckets.iter().sum::<u64>().to_string(),
+                "sum": histogram.sum.count() as f64 / 1_000_000f64,
+                "bucketCounts": buckets.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+                "explicitBounds": bounds,
+            }));
+        }
+    }
+
+    // Append counters of rejected write-class file operations as OTLP sum data points.
+    fn export_otlp_rejected_writes(&self, points: &mut Vec<Value>, start: &str, now: &str) {
+        for (idx, name) in WRITE_FOP_NAMES.iter().enumerate() {
+            points.push(otlp_int_point(
+                &[("id", self.id.as_str()), ("fop", *name)],
+                self.rejected_writes[idx].count(),
+                start,
+                now,
+            ));
+        }
+    }
+
+    // Append counters of rejected write-class file operations in Prometheus text exposition
+    // format.
+    fn export_prometheus_rejected_writes(&self, out: &mut String) {
+        for (idx, name) in WRITE_FOP_NAMES.iter().enumerate() {
+            out.push_str(&format!(
+                "nydus_rejected_writes_total{{id=\"{}\",fop=\"{}\"}} {}\n",
+                self.id,
+                name,
+                self.rejected_writes[idx].count()
+            ));
+        }
+    }
 }
 
 /// If you need FOP recorder count file system operations.
@@ -406,6 +616,7 @@ pub struct FopRecorder<'a> {
     success: bool,
     // Now, the size only makes sense for `Read` FOP.
     size: usize,
+    start: Option<SystemTime>,
     ios: &'a GlobalIoStats,
 }
 
@@ -413,6 +624,7 @@ impl<'a> Drop for FopRecorder<'a> {
     fn drop(&mut self) {
         self.ios
             .file_stats_update(self.inode, self.fop, self.size, self.success);
+        self.ios.fop_latency_update(&self.start, self.fop);
     }
 }
 
@@ -422,12 +634,14 @@ impl<'a> FopRecorder<'a> {
         T: AsRef<GlobalIoStats>,
         'b: 'a,
     {
+        let ios = ios.as_ref();
         FopRecorder {
             fop,
             inode,
             success: false,
             size: 0,
-            ios: ios.as_ref(),
+            start: ios.latency_start(),
+            ios,
         }
     }
 
@@ -504,6 +718,187 @@ pub fn export_global_stats(name: &Option<String>) -> Result<String, IoStatsError
     }
 }
 
+/// Export file operation latency histograms and rejected write counters of all filesystem
+/// instances in Prometheus text exposition format.
+pub fn export_prometheus_metrics() -> IoStatsResult<String> {
+    let mut out = String::from(
+        "# HELP nydus_fop_latency_seconds Latency of file operations.\n\
+         # TYPE nydus_fop_latency_seconds histogram\n",
+    );
+    for ios in IOS_SET.read().unwrap().values() {
+        ios.export_prometheus_latency(&mut out);
+    }
+    out.push_str(
+        "# HELP nydus_rejected_writes_total Write operations rejected by read-only filesystems.\n\
+         # TYPE nydus_rejected_writes_total counter\n",
+    );
+    for ios in IOS_SET.read().unwrap().values() {
+        ios.export_prometheus_rejected_writes(&mut out);
+    }
+
+    Ok(out)
+}
+
+/// Resource attributes of metrics pushed by OTLP, e.g. host name and daemon id.
+#[derive(Clone, Debug, Default)]
+pub struct OtlpResource {
+    attributes: Vec<Value>,
+}
+
+impl OtlpResource {
+    /// Add a string attribute.
+    pub fn add_str(&mut self, key: &str, value: &str) {
+        self.attributes
+            .push(json!({"key": key, "value": {"stringValue": value}}));
+    }
+
+    /// Add a string array attribute.
+    pub fn add_str_array(&mut self, key: &str, values: &[String]) {
+        let values: Vec<Value> = values.iter().map(|v| json!({ "stringValue": v })).collect();
+        self.attributes
+            .push(json!({"key": key, "value": {"arrayValue": {"values": values}}}));
+    }
+}
+
+fn otlp_attributes(attrs: &[(&str, &str)]) -> Vec<Value> {
+    attrs
+        .iter()
+        .map(|(k, v)| json!({"key": k, "value": {"stringValue": v}}))
+        .collect()
+}
+
+// 64-bit integers are encoded as strings in OTLP/JSON.
+fn otlp_int_point(attrs: &[(&str, &str)], value: u64, start: &str, now: &str) -> Value {
+    json!({
+        "attributes": otlp_attributes(attrs),
+        "startTimeUnixNano": start,
+        "timeUnixNano": now,
+        "asInt": value.to_string(),
+    })
+}
+
+fn otlp_sum(name: &str, description: &str, unit: &str, points: Vec<Value>) -> Value {
+    json!({
+        "name": name,
+        "description": description,
+        "unit": unit,
+        // Cumulative temporality.
+        "sum": {"aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points},
+    })
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Export file operation latency histograms, rejected write counters, storage backend and blob
+/// cache read counters of all instances as an OTLP/HTTP JSON `ExportMetricsServiceRequest`.
+///
+/// Counters are cumulative since `start`, the start time of the process.
+pub fn export_otlp_metrics(resource: &OtlpResource, start: SystemTime) -> IoStatsResult<String> {
+    let start = unix_nanos(start);
+    let now = unix_nanos(SystemTime::now());
+    let (start, now) = (start.as_str(), now.as_str());
+
+    let mut latency = Vec::new();
+    let mut rejected_writes = Vec::new();
+    for ios in IOS_SET.read().unwrap().values() {
+        ios.export_otlp_latency(&mut latency, start, now);
+        ios.export_otlp_rejected_writes(&mut rejected_writes, start, now);
+    }
+
+    let (mut backend_reads, mut backend_errors, mut backend_bytes) =
+        (Vec::new(), Vec::new(), Vec::new());
+    for m in BACKEND_METRICS.read().unwrap().values() {
+        let attrs = [
+            ("id", m.id.as_str()),
+            ("backend_type", m.backend_type.as_str()),
+        ];
+        backend_reads.push(otlp_int_point(&attrs, m.read_count.count(), start, now));
+        backend_errors.push(otlp_int_point(&attrs, m.read_errors.count(), start, now));
+        backend_bytes.push(otlp_int_point(
+            &attrs,
+            m.read_amount_total.count(),
+            start,
+            now,
+        ));
+    }
+
+    let (mut cache_reads, mut cache_hits) = (Vec::new(), Vec::new());
+    for m in BLOBCACHE_METRICS.read().unwrap().values() {
+        let id = m.id.as_str();
+        cache_reads.push(otlp_int_point(&[("id", id)], m.total.count(), start, now));
+        cache_hits.push(otlp_int_point(
+            &[("id", id), ("hit", "partial")],
+            m.partial_hits.count(),
+            start,
+            now,
+        ));
+        cache_hits.push(otlp_int_point(
+            &[("id", id), ("hit", "whole")],
+            m.whole_hits.count(),
+            start,
+            now,
+        ));
+    }
+
+    let metrics = vec![
+        json!({
+            "name": "nydus_fop_latency_seconds",
+            "description": "Latency of file operations.",
+            "unit": "s",
+            "histogram": {"aggregationTemporality": 2, "dataPoints": latency},
+        }),
+        otlp_sum(
+            "nydus_rejected_writes_total",
+            "Write operations rejected by read-only filesystems.",
+            "1",
+            rejected_writes,
+        ),
+        otlp_sum(
+            "nydus_backend_reads_total",
+            "Read requests to storage backends.",
+            "1",
+            backend_reads,
+        ),
+        otlp_sum(
+            "nydus_backend_read_errors_total",
+            "Failed read requests to storage backends.",
+            "1",
+            backend_errors,
+        ),
+        otlp_sum(
+            "nydus_backend_read_bytes_total",
+            "Data read from storage backends.",
+            "By",
+            backend_bytes,
+        ),
+        otlp_sum(
+            "nydus_blobcache_reads_total",
+            "Read requests to blob caches.",
+            "1",
+            cache_reads,
+        ),
+        otlp_sum(
+            "nydus_blobcache_hits_total",
+            "Read requests served by blob caches.",
+            "1",
+            cache_hits,
+        ),
+    ];
+    let request = json!({
+        "resourceMetrics": [{
+            "resource": {"attributes": resource.attributes},
+            "scopeMetrics": [{"scope": {"name": "nydusd"}, "metrics": metrics}],
+        }],
+    });
+
+    serde_json::to_string(&request).map_err(IoStatsError::Serialize)
+}
+
 pub fn export_backend_metrics(name: &Option<String>) -> IoStatsResult<String> {
     let metrics = BACKEND_METRICS.read().unwrap();
 
@@ -542,6 +937,35 @@ pub fn export_blobcache_metrics(id: &Option<String>) -> IoStatsResult<String> {
     }
 }
 
+/// Export access accounting of filesystem instances, optionally resetting the counters.
+///
+/// Accounting of all filesystem instances is exported as a map keyed by instance id if `id`
+/// is not specified.
+pub fn export_accounting(id: &Option<String>, reset: bool) -> IoStatsResult<String> {
+    let set = ACCOUNTING_SET.read().unwrap();
+
+    match id {
+        Some(k) => set
+            .get(k)
+            .and_then(|v| v.upgrade())
+            .ok_or(IoStatsError::NoCounter)
+            .map(|v| serde_json::to_string(&v.report(reset)).map_err(IoStatsError::Serialize))?,
+        None => {
+            let reports = set
+                .iter()
+                .filter_map(|(k, v)| v.upgrade().map(|v| (k.to_owned(), v.report(reset))))
+                .collect::<BTreeMap<String, AccessAccountingReport>>();
+            serde_json::to_string(&reports).map_err(IoStatsError::Serialize)
+        }
+    }
+}
+
+/// Export metrics of the shared buffer pool, with the ratio of allocations served by reused
+/// buffers.
+pub fn export_buffer_pool_metrics() -> IoStatsResult<String> {
+    serde_json::to_string(&BUFFER_POOL_METRICS.report()).map_err(IoStatsError::Serialize)
+}
+
 pub fn export_events() -> IoStatsResult<String> {
     serde_json::to_string(ERROR_HOLDER.lock().unwrap().deref()).map_err(IoStatsError::Serialize)
 }
@@ -597,6 +1021,52 @@ pub struct BackendMetrics {
     read_count_block_size_dist: [BasicMetric; BLOCK_READ_SIZES_MAX],
     // Categorize metrics as per their latency and request size
     read_latency_sizes_dist: [[BasicMetric; READ_LATENCY_RANGE_MAX]; BLOCK_READ_SIZES_MAX],
+    // Number of requests holding a slot of the connection pool
+    pool_inflight_requests: BasicMetric,
+    // Cumulative count of requests which have to wait for a free slot of the connection pool
+    pool_saturated_count: BasicMetric,
+    // Cumulative time waiting for a free slot of the connection pool, in unit of millisecond
+    pool_wait_millis_total: BasicMetric,
+    // Cumulative count of read failure to backend, by error category
+    read_errors_timeout: BasicMetric,
+    read_errors_auth: BasicMetric,
+    read_errors_not_found: BasicMetric,
+    read_errors_other: BasicMetric,
+    // Cumulative count of retried read requests to backend, by request class
+    read_retries_metadata: BasicMetric,
+    read_retries_data: BasicMetric,
+    read_retries_prefetch: BasicMetric,
+}
+
+/// Categories of storage backend errors, to tell causes of failures apart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BackendErrorCategory {
+    /// Requests timed out.
+    Timeout,
+    /// Authentication or authorization failures.
+    Auth,
+    /// Blob or object not found.
+    NotFound,
+    /// All other errors.
+    Other,
+}
+
+/// Classes of storage backend requests, which may have different timeouts and retry policies.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BackendRequestClass {
+    /// Fetching metadata, such as the chunk information array of blobs.
+    Metadata = 0,
+    /// Fetching data on demand to serve filesystem reads.
+    Data = 1,
+    /// Fetching data in background for prefetch.
+    Prefetch = 2,
+}
+
+impl BasicMetric {
+    // Get current value of the counter and reset it to zero.
+    fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
 }
 
 impl Metric for BasicMetric {
@@ -684,6 +1154,41 @@ impl BackendMetrics {
         }
     }
 
+    /// Account a request which has got a slot of the connection pool, `wait` is the time spent
+    /// on waiting for a free slot if the pool has been saturated.
+    pub fn pool_acquired(&self, wait: Option<&Duration>) {
+        self.pool_inflight_requests.inc();
+        if let Some(d) = wait {
+            self.pool_saturated_count.inc();
+            self.pool_wait_millis_total
+                .add(saturating_duration_millis(d));
+        }
+    }
+
+    /// Account a request which has released its slot of the connection pool.
+    pub fn pool_released(&self) {
+        self.pool_inflight_requests.dec();
+    }
+
+    /// Account a failed read request by the category of the error.
+    pub fn read_error(&self, category: BackendErrorCategory) {
+        match category {
+            BackendErrorCategory::Timeout => self.read_errors_timeout.inc(),
+            BackendErrorCategory::Auth => self.read_errors_auth.inc(),
+            BackendErrorCategory::NotFound => self.read_errors_not_found.inc(),
+            BackendErrorCategory::Other => self.read_errors_other.inc(),
+        }
+    }
+
+    /// Account a retry of failed read request by the class of the request.
+    pub fn read_retry(&self, class: BackendRequestClass) {
+        match class {
+            BackendRequestClass::Metadata => self.read_retries_metadata.inc(),
+            BackendRequestClass::Data => self.read_retries_data.inc(),
+            BackendRequestClass::Prefetch => self.read_retries_prefetch.inc(),
+        }
+    }
+
     fn export_metrics(&self) -> IoStatsResult<String> {
         serde_json::to_string(self).map_err(IoStatsError::Serialize)
     }
@@ -714,6 +1219,21 @@ pub struct BlobcacheMetrics {
     pub prefetch_workers: AtomicUsize,
     pub prefetch_unmerged_chunks: BasicMetric,
     pub buffered_backend_size: BasicMetric,
+    // Number of cached chunks whose data has been verified by the scrubber.
+    pub scrubbed_chunks: BasicMetric,
+    // Number of cached chunks found corrupted and invalidated by the scrubber.
+    pub scrub_corrupted_chunks: BasicMetric,
+    // Number of cold chunks whose space has been reclaimed by punching holes in cache files.
+    pub punched_chunks: BasicMetric,
+    // Number of cached chunks invalidated on demand.
+    pub invalidated_chunks: BasicMetric,
+    // Number of blobs being downloaded as a whole after repeated failures of range reads.
+    pub whole_blob_fallbacks: BasicMetric,
+    // Number of blobs downloaded as a whole, whose data is then read locally.
+    pub whole_blob_downloaded: BasicMetric,
+    // Total size and downloaded bytes of blobs being downloaded as a whole.
+    pub whole_blob_download_total: BasicMetric,
+    pub whole_blob_download_progress: BasicMetric,
 }
 
 impl BlobcacheMetrics {
@@ -749,6 +1269,200 @@ impl BlobcacheMetrics {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct BufferPoolMetrics {
+    // Size limit of buffers cached in the pool, 0 if the pool is not started.
+    pub capacity: BasicMetric,
+    // Total size of buffers cached in the pool.
+    pub cached_size: BasicMetric,
+    // Number of buffers allocated since no cached buffer of the size class is available.
+    pub allocated: BasicMetric,
+    // Number of allocations served by cached buffers.
+    pub reused: BasicMetric,
+    // Number of buffers freed instead of cached since the pool is full.
+    pub freed: BasicMetric,
+    // Number of allocations larger than the largest size class, which bypass the pool.
+    pub oversized: BasicMetric,
+}
+
+/// Report of buffer pool metrics exported by the HTTP API.
+#[derive(Debug, Default, Serialize)]
+pub struct BufferPoolReport {
+    pub capacity: u64,
+    pub cached_size: u64,
+    pub allocated: u64,
+    pub reused: u64,
+    pub freed: u64,
+    pub oversized: u64,
+    /// Ratio of allocations served by cached buffers, in range [0, 1].
+    pub reuse_rate: f64,
+}
+
+impl BufferPoolMetrics {
+    pub fn report(&self) -> BufferPoolReport {
+        let allocated = self.allocated.count();
+        let reused = self.reused.count();
+        let total = allocated + reused + self.oversized.count();
+        BufferPoolReport {
+            capacity: self.capacity.count(),
+            cached_size: self.cached_size.count(),
+            allocated,
+            reused,
+            freed: self.freed.count(),
+            oversized: self.oversized.count(),
+            reuse_rate: if total == 0 {
+                0.0
+            } else {
+                reused as f64 / total as f64
+            },
+        }
+    }
+}
+
+/// Access accounting of a filesystem instance, to attribute resource usage to images in
+/// multi-tenant environments, e.g. for billing.
+///
+/// Storage backend and cache traffic is attributed to the filesystem instance which has entered
+/// accounting on current thread, so traffic caused by background prefetch workers is not
+/// accounted. Counters are logged when the accounting is released with the filesystem instance.
+#[derive(Debug, Default)]
+pub struct AccessAccounting {
+    id: String,
+    // Seconds since the UNIX epoch when the counters were reset last time.
+    since: AtomicU64,
+    // Number of read requests against the filesystem.
+    requests: BasicMetric,
+    // Bytes returned to the user.
+    read_bytes: BasicMetric,
+    // Number of requests sent to the storage backend.
+    backend_requests: BasicMetric,
+    // Bytes fetched from the storage backend.
+    backend_bytes: BasicMetric,
+    // Bytes read from the local cache.
+    cache_bytes: BasicMetric,
+}
+
+/// Snapshot of access accounting counters of a filesystem instance.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AccessAccountingReport {
+    /// Seconds since the UNIX epoch when the counters were reset last time.
+    pub since: u64,
+    /// Seconds since the UNIX epoch when the report is generated.
+    pub until: u64,
+    pub requests: u64,
+    pub read_bytes: u64,
+    pub backend_requests: u64,
+    pub backend_bytes: u64,
+    pub cache_bytes: u64,
+}
+
+/// Guard to restore access accounting of current thread on drop.
+pub struct AccountingGuard {
+    prev: Option<Arc<AccessAccounting>>,
+}
+
+impl Drop for AccountingGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT_ACCOUNTING.with(|c| *c.borrow_mut() = prev);
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl AccessAccounting {
+    pub fn new(id: &str) -> Arc<Self> {
+        let accounting = Arc::new(Self {
+            id: id.to_string(),
+            since: AtomicU64::new(now_secs()),
+            ..Default::default()
+        });
+
+        let mut set = ACCOUNTING_SET.write().unwrap();
+        set.retain(|_, v| v.strong_count() > 0);
+        set.insert(id.to_string(), Arc::downgrade(&accounting));
+
+        accounting
+    }
+
+    /// Attribute storage traffic on current thread to the accounting until the guard is dropped.
+    pub fn enter(self: &Arc<Self>) -> AccountingGuard {
+        let prev = CURRENT_ACCOUNTING.with(|c| c.borrow_mut().replace(self.clone()));
+        AccountingGuard { prev }
+    }
+
+    /// Account a read request against the filesystem, which returns `size` bytes to the user.
+    pub fn account_request(&self, size: usize) {
+        self.requests.inc();
+        self.read_bytes.add(size as u64);
+    }
+
+    /// Generate a report of the counters, and reset them if `reset` is true.
+    pub fn report(&self, reset: bool) -> AccessAccountingReport {
+        let until = now_secs();
+
+        if reset {
+            AccessAccountingReport {
+                since: self.since.swap(until, Ordering::Relaxed),
+                until,
+                requests: self.requests.take(),
+                read_bytes: self.read_bytes.take(),
+                backend_requests: self.backend_requests.take(),
+                backend_bytes: self.backend_bytes.take(),
+                cache_bytes: self.cache_bytes.take(),
+            }
+        } else {
+            AccessAccountingReport {
+                since: self.since.load(Ordering::Relaxed),
+                until,
+                requests: self.requests.count(),
+                read_bytes: self.read_bytes.count(),
+                backend_requests: self.backend_requests.count(),
+                backend_bytes: self.backend_bytes.count(),
+                cache_bytes: self.cache_bytes.count(),
+            }
+        }
+    }
+}
+
+impl Drop for AccessAccounting {
+    fn drop(&mut self) {
+        match serde_json::to_string(&self.report(false)) {
+            Ok(r) => info!("access accounting of {} on release: {}", self.id, r),
+            Err(e) => error!("failed to serialize access accounting, {}", e),
+        }
+    }
+}
+
+/// Account `size` bytes fetched from the storage backend on current thread.
+pub fn account_backend_read(size: usize) {
+    CURRENT_ACCOUNTING.with(|c| {
+        if let Some(a) = c.borrow().as_ref() {
+            a.backend_requests.inc();
+            a.backend_bytes.add(size as u64);
+        }
+    })
+}
+
+/// Get id of the filesystem instance accounted on current thread.
+pub fn current_accounting_id() -> Option<String> {
+    CURRENT_ACCOUNTING.with(|c| c.borrow().as_ref().map(|a| a.id.clone()))
+}
+
+/// Account `size` bytes read from the local cache on current thread.
+pub fn account_cache_read(size: usize) {
+    CURRENT_ACCOUNTING.with(|c| {
+        if let Some(a) = c.borrow().as_ref() {
+            a.cache_bytes.add(size as u64);
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -795,4 +1509,192 @@ mod tests {
         g.global_update(StatsFop::Read, 2015520, true);
         assert_eq!(g.block_count_read[3].count(), 2);
     }
+
+    #[test]
+    fn test_rejected_writes() {
+        let g = new("test_rejected_writes");
+        g.write_rejected(WriteFop::Write);
+        g.write_rejected(WriteFop::Write);
+        g.write_rejected(WriteFop::Mkdir);
+        assert_eq!(g.rejected_writes(WriteFop::Write), 2);
+        assert_eq!(g.rejected_writes(WriteFop::Mkdir), 1);
+        assert_eq!(g.rejected_writes(WriteFop::Unlink), 0);
+        assert_eq!(WriteFop::Removexattr.name(), "removexattr");
+
+        let out = export_prometheus_metrics().unwrap();
+        assert!(out.contains(
+            "nydus_rejected_writes_total{id=\"test_rejected_writes\",fop=\"write\"} 2\n"
+        ));
+        IOS_SET.write().unwrap().remove("test_rejected_writes");
+    }
+
+    #[test]
+    fn test_backend_read_error_category() {
+        let m = BackendMetrics::new("test_backend_read_error_category", "mock");
+        m.read_error(BackendErrorCategory::Timeout);
+        m.read_error(BackendErrorCategory::Timeout);
+        m.read_error(BackendErrorCategory::Auth);
+        assert_eq!(m.read_errors_timeout.count(), 2);
+        assert_eq!(m.read_errors_auth.count(), 1);
+        assert_eq!(m.read_errors_not_found.count(), 0);
+
+        let v: serde_json::Value = serde_json::from_str(&m.export_metrics().unwrap()).unwrap();
+        assert_eq!(v["read_errors_timeout"], 2);
+        assert_eq!(v["read_errors_other"], 0);
+        m.release().unwrap();
+    }
+
+    #[test]
+    fn test_backend_read_retry_class() {
+        let m = BackendMetrics::new("test_backend_read_retry_class", "mock");
+        m.read_retry(BackendRequestClass::Prefetch);
+        m.read_retry(BackendRequestClass::Prefetch);
+        m.read_retry(BackendRequestClass::Metadata);
+        assert_eq!(m.read_retries_data.count(), 0);
+
+        let v: serde_json::Value = serde_json::from_str(&m.export_metrics().unwrap()).unwrap();
+        assert_eq!(v["read_retries_metadata"], 1);
+        assert_eq!(v["read_retries_prefetch"], 2);
+        m.release().unwrap();
+    }
+
+    #[test]
+    fn test_access_accounting() {
+        let a = AccessAccounting::new("/accounting");
+        account_backend_read(100);
+        assert_eq!(a.backend_bytes.count(), 0);
+
+        {
+            let _guard = a.enter();
+            a.account_request(4096);
+            account_backend_read(1000);
+            account_cache_read(3096);
+        }
+        account_cache_read(100);
+
+        let r = a.report(true);
+        assert_eq!(r.requests, 1);
+        assert_eq!(r.read_bytes, 4096);
+        assert_eq!(r.backend_requests, 1);
+        assert_eq!(r.backend_bytes, 1000);
+        assert_eq!(r.cache_bytes, 3096);
+        assert_eq!(a.report(false).read_bytes, 0);
+
+        let all = export_accounting(&None, false).unwrap();
+        assert!(all.contains("\"/accounting\""));
+        assert!(export_accounting(&Some("/accounting".to_string()), false).is_ok());
+        drop(a);
+        assert!(export_accounting(&Some("/accounting".to_string()), false).is_err());
+    }
+
+    #[test]
+    fn test_buffer_pool_report() {
+        let m = BufferPoolMetrics::default();
+        assert_eq!(m.report().reuse_rate, 0.0);
+
+        m.allocated.add(1);
+        m.reused.add(2);
+        m.oversized.inc();
+        m.cached_size.add(4096);
+        let r = m.report();
+        assert_eq!(r.reused, 2);
+        assert_eq!(r.cached_size, 4096);
+        assert!((r.reuse_rate - 0.5).abs() < f64::EPSILON);
+        assert!(export_buffer_pool_metrics()
+            .unwrap()
+            .contains("\"reuse_rate\""));
+    }
+
+    #[test]
+    fn test_fop_latency_histogram() {
+        assert_eq!(fop_latency_range_index(0), 0);
+        assert_eq!(fop_latency_range_index(10), 0);
+        assert_eq!(fop_latency_range_index(11), 1);
+        assert_eq!(fop_latency_range_index(1_000_000), 7);
+        assert_eq!(fop_latency_range_index(1_000_001), 8);
+
+        let g = GlobalIoStats {
+            id: "/m".to_string(),
+            ..Default::default()
+        };
+        g.fop_latency_dist[StatsFop::Lookup as usize].observe(5);
+        g.fop_latency_dist[StatsFop::Lookup as usize].observe(2_000_000);
+
+        let mut out = String::new();
+        g.export_prometheus_latency(&mut out);
+        assert!(out.contains(
+            "nydus_fop_latency_seconds_bucket{id=\"/m\",fop=\"lookup\",le=\"0.00001\"} 1\n"
+        ));
+        assert!(
+            out.contains("nydus_fop_latency_seconds_bucket{id=\"/m\",fop=\"lookup\",le=\"1\"} 1\n")
+        );
+        assert!(out.contains(
+            "nydus_fop_latency_seconds_bucket{id=\"/m\",fop=\"lookup\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(out.contains("nydus_fop_latency_seconds_sum{id=\"/m\",fop=\"lookup\"} 2.000005\n"));
+        assert!(out.contains("nydus_fop_latency_seconds_count{id=\"/m\",fop=\"read\"} 0\n"));
+    }
+
+    #[test]
+    fn test_export_otlp_metrics() {
+        let g = new("test_export_otlp_metrics");
+        g.fop_latency_dist[StatsFop::Lookup as usize].observe(5);
+        g.fop_latency_dist[StatsFop::Lookup as usize].observe(2_000_000);
+        g.write_rejected(WriteFop::Mkdir);
+
+        let mut resource = OtlpResource::default();
+        resource.add_str("host.name", "node1");
+        resource.add_str_array("nydus.image.ids", &["image1".to_string()]);
+        let out = export_otlp_metrics(&resource, UNIX_EPOCH).unwrap();
+        IOS_SET.write().unwrap().remove("test_export_otlp_metrics");
+
+        let v: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let rm = &v["resourceMetrics"][0];
+        assert_eq!(rm["resource"]["attributes"][0]["key"], "host.name");
+        assert_eq!(
+            rm["resource"]["attributes"][1]["value"]["arrayValue"]["values"][0]["stringValue"],
+            "image1"
+        );
+        let metrics = rm["scopeMetrics"][0]["metrics"].as_array().unwrap();
+        let has_attr = |p: &serde_json::Value, key: &str, value: &str| {
+            p["attributes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|a| a["key"] == key && a["value"]["stringValue"] == value)
+        };
+
+        let latency = metrics
+            .iter()
+            .find(|m| m["name"] == "nydus_fop_latency_seconds")
+            .unwrap();
+        let point = latency["histogram"]["dataPoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| has_attr(p, "id", "test_export_otlp_metrics") && has_attr(p, "fop", "lookup"))
+            .unwrap();
+        assert_eq!(point["count"], "2");
+        assert_eq!(point["sum"], 2.000005);
+        assert_eq!(point["startTimeUnixNano"], "0");
+        assert_eq!(point["bucketCounts"][0], "1");
+        assert_eq!(point["bucketCounts"][FOP_LATENCY_BUCKETS.len()], "1");
+        assert_eq!(
+            point["explicitBounds"].as_array().unwrap().len(),
+            FOP_LATENCY_BUCKETS.len()
+        );
+
+        let rejected = metrics
+            .iter()
+            .find(|m| m["name"] == "nydus_rejected_writes_total")
+            .unwrap();
+        assert_eq!(rejected["sum"]["isMonotonic"], true);
+        let point = rejected["sum"]["dataPoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| has_attr(p, "id", "test_export_otlp_metrics") && has_attr(p, "fop", "mkdir"))
+            .unwrap();
+        assert_eq!(point["asInt"], "1");
+    }
 }
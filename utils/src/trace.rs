@@ -0,0 +1,495 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracing of the read path, to analyze long-tail latencies with existing tooling.
+//!
+//! When enabled, spans are recorded for each stage of a read request, i.e. the FUSE request, the
+//! cache lookup, fetching data from the storage backend and decompressing it. Spans started by
+//! the same thread while another span is active are its children, so stages of a request form
+//! a trace. The latest spans are kept in a bounded buffer, and can be exported as a Chrome trace
+//! JSON file, to be loaded by `chrome://tracing` or Perfetto, or sent to an OpenTelemetry
+//! collector by OTLP/HTTP in JSON encoding.
+//!
+//! Recording a span takes a global lock, so tracing should only be enabled while analyzing.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{Read, Result, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+/// Default path of the OTLP/HTTP traces endpoint.
+pub const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+const OTLP_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref READ_TRACER: Tracer = Tracer::new();
+}
+
+thread_local! {
+    // Stack of (trace id, span id) of active spans of the thread.
+    static ACTIVE_SPANS: RefCell<Vec<(u64, u64)>> = RefCell::new(Vec::new());
+    static THREAD_ID: Cell<u32> = Cell::new(0);
+}
+
+fn thread_id() -> u32 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            // Safe because gettid() has no side effect.
+            id.set(unsafe { libc::syscall(libc::SYS_gettid) } as u32);
+        }
+        id.get()
+    })
+}
+
+/// A finished span.
+#[derive(Clone, Debug)]
+pub struct SpanRecord {
+    pub name: &'static str,
+    pub trace_id: u64,
+    pub span_id: u64,
+    /// Span id of the parent span, 0 for root spans.
+    pub parent_id: u64,
+    pub tid: u32,
+    /// Start time in nanoseconds since the Unix epoch.
+    pub start_ns: u64,
+    pub duration_ns: u64,
+    pub args: Vec<(&'static str, u64)>,
+}
+
+/// State of the tracer.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TraceStatus {
+    pub enabled: bool,
+    /// Upper limit of spans to keep.
+    pub capacity: usize,
+    /// Number of spans kept now.
+    pub spans: usize,
+}
+
+/// Recorder of spans.
+pub struct Tracer {
+    enabled: AtomicBool,
+    next_id: AtomicU64,
+    // Monotonic clock is used to measure spans, and converted to wall clock when exporting.
+    base_instant: Instant,
+    base_ns: u64,
+    spans: Mutex<(usize, VecDeque<SpanRecord>)>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        let base_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Tracer {
+            enabled: AtomicBool::new(false),
+            next_id: AtomicU64::new(1),
+            base_instant: Instant::now(),
+            base_ns,
+            spans: Mutex::new((0, VecDeque::new())),
+        }
+    }
+
+    /// Start recording spans, and keep at most `capacity` latest spans.
+    pub fn start(&self, capacity: usize) {
+        let mut spans = self.spans.lock().unwrap();
+        spans.0 = capacity;
+        while spans.1.len() > capacity {
+            spans.1.pop_front();
+        }
+        self.enabled.store(capacity > 0, Ordering::Release);
+    }
+
+    /// Stop recording spans, spans recorded are kept for exporting.
+    pub fn stop(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> TraceStatus {
+        let spans = self.spans.lock().unwrap();
+        TraceStatus {
+            enabled: self.is_enabled(),
+            capacity: spans.0,
+            spans: spans.1.len(),
+        }
+    }
+
+    /// Take all recorded spans out of the tracer.
+    pub fn take_spans(&self) -> Vec<SpanRecord> {
+        self.spans.lock().unwrap().1.drain(..).collect()
+    }
+
+    /// Start a span named `name`, which is finished when the returned guard is dropped.
+    pub fn span(&self, name: &'static str) -> SpanGuard {
+        if !self.is_enabled() {
+            return SpanGuard { active: None };
+        }
+
+        let span_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (trace_id, parent_id) = ACTIVE_SPANS.with(|s| {
+            let mut s = s.borrow_mut();
+            let ids = s.last().copied().unwrap_or((span_id, 0));
+            s.push((ids.0, span_id));
+            ids
+        });
+
+        SpanGuard {
+            active: Some(ActiveSpan {
+                tracer: self,
+                record: SpanRecord {
+                    name,
+                    trace_id,
+                    span_id,
+                    parent_id,
+                    tid: thread_id(),
+                    start_ns: 0,
+                    duration_ns: 0,
+                    args: Vec::new(),
+                },
+                start: Instant::now(),
+            }),
+        }
+    }
+
+    fn finish(&self, mut span: ActiveSpan) {
+        ACTIVE_SPANS.with(|s| {
+            let mut s = s.borrow_mut();
+            if let Some(pos) = s.iter().rposition(|(_, id)| *id == span.record.span_id) {
+                s.truncate(pos);
+            }
+        });
+
+        span.record.start_ns = self.base_ns
+            + span
+                .start
+                .saturating_duration_since(self.base_instant)
+                .as_nanos() as u64;
+        span.record.duration_ns = span.start.elapsed().as_nanos() as u64;
+        let mut spans = self.spans.lock().unwrap();
+        if spans.0 == 0 {
+            return;
+        }
+        if spans.1.len() >= spans.0 {
+            spans.1.pop_front();
+        }
+        spans.1.push_back(span.record);
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct ActiveSpan<'a> {
+    tracer: &'a Tracer,
+    record: SpanRecord,
+    start: Instant,
+}
+
+/// Guard of an active span, which does nothing if tracing is disabled.
+pub struct SpanGuard<'a> {
+    active: Option<ActiveSpan<'a>>,
+}
+
+impl<'a> SpanGuard<'a> {
+    /// Attach a numeric argument to the span, e.g. inode number or size of the request.
+    pub fn arg(&mut self, key: &'static str, value: u64) {
+        if let Some(a) = self.active.as_mut() {
+            a.record.args.push((key, value));
+        }
+    }
+}
+
+impl<'a> Drop for SpanGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(a) = self.active.take() {
+            a.tracer.finish(a);
+        }
+    }
+}
+
+/// Get the global tracer of the read path.
+pub fn read_tracer() -> &'static Tracer {
+    &READ_TRACER
+}
+
+/// Start a span of the read path, see [Tracer::span].
+pub fn span(name: &'static str) -> SpanGuard<'static> {
+    READ_TRACER.span(name)
+}
+
+/// Convert spans into the Chrome trace event format.
+pub fn to_chrome_trace(spans: &[SpanRecord]) -> Value {
+    let pid = std::process::id();
+    let events: Vec<Value> = spans
+        .iter()
+        .map(|s| {
+            let mut args = serde_json::Map::new();
+            args.insert("trace_id".to_string(), json!(format!("{:x}", s.trace_id)));
+            args.insert("span_id".to_string(), json!(format!("{:x}", s.span_id)));
+            for (k, v) in s.args.iter() {
+                args.insert(k.to_string(), json!(v));
+            }
+            json!({
+                "name": s.name,
+                "cat": "read",
+                "ph": "X",
+                "ts": s.start_ns as f64 / 1000.0,
+                "dur": s.duration_ns as f64 / 1000.0,
+                "pid": pid,
+                "tid": s.tid,
+                "args": args,
+            })
+        })
+        .collect();
+
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+/// Convert spans into an OTLP `ExportTraceServiceRequest` in JSON encoding.
+pub fn to_otlp_json(spans: &[SpanRecord], service_name: &str) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|s| {
+            let attributes: Vec<Value> = s
+                .args
+                .iter()
+                .map(|(k, v)| json!({"key": k, "value": {"intValue": v.to_string()}}))
+                .chain(std::iter::once(
+                    json!({"key": "thread.id", "value": {"intValue": s.tid.to_string()}}),
+                ))
+                .collect();
+            let parent = if s.parent_id == 0 {
+                String::new()
+            } else {
+                format!("{:016x}", s.parent_id)
+            };
+            json!({
+                "traceId": format!("{:032x}", s.trace_id),
+                "spanId": format!("{:016x}", s.span_id),
+                "parentSpanId": parent,
+                "name": s.name,
+                // SPAN_KIND_INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": s.start_ns.to_string(),
+                "endTimeUnixNano": (s.start_ns + s.duration_ns).to_string(),
+                "attributes": attributes,
+            })
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}]
+            },
+            "scopeSpans": [{"scope": {"name": "nydus"}, "spans": spans}]
+        }]
+    })
+}
+
+// Split an endpoint in format `http://host:port[/path]` into address and path.
+fn parse_http_endpoint(endpoint: &str) -> Result<(String, String)> {
+    let rest = endpoint
+        .strip_prefix("http://")
+        .ok_or_else(|| einval!(format!("only http endpoint is supported, {}", endpoint)))?;
+    let (addr, path) = match rest.find('/') {
+        Some(pos) if pos + 1 < rest.len() => (&rest[..pos], &rest[pos..]),
+        Some(pos) => (&rest[..pos], OTLP_TRACES_PATH),
+        None => (rest, OTLP_TRACES_PATH),
+    };
+    if addr.is_empty() {
+        return Err(einval!(format!("invalid endpoint {}", endpoint)));
+    }
+    let addr = if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:80", addr)
+    };
+
+    Ok((addr, path.to_string()))
+}
+
+/// Send spans to an OTLP/HTTP collector at `endpoint`, e.g. `http://127.0.0.1:4318`, whose path
+/// defaults to `/v1/traces`.
+pub fn export_otlp(endpoint: &str, spans: &[SpanRecord], service_name: &str) -> Result<()> {
+    let (addr, path) = parse_http_endpoint(endpoint)?;
+    let body = to_otlp_json(spans, service_name).to_string();
+
+    let mut stream = TcpStream::connect(&addr)?;
+    stream.set_read_timeout(Some(OTLP_TIMEOUT))?;
+    stream.set_write_timeout(Some(OTLP_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        addr,
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+
+    let mut resp = Vec::new();
+    stream.read_to_end(&mut resp)?;
+    let resp = String::from_utf8_lossy(&resp);
+    let status = resp
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| eother!(format!("invalid response from {}", endpoint)))?;
+    if !(200..300).contains(&status) {
+        return Err(eother!(format!(
+            "failed to export spans to {}, status {}",
+            endpoint, status
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_tracer_spans() {
+        let tracer = Tracer::new();
+        drop(tracer.span("disabled"));
+        assert_eq!(tracer.status().spans, 0);
+
+        tracer.start(3);
+        {
+            let mut root = tracer.span("fuse_read");
+            root.arg("ino", 5);
+            {
+                let _lookup = tracer.span("cache_lookup");
+                let _fetch = tracer.span("backend_fetch");
+            }
+        }
+        let spans = tracer.take_spans();
+        assert_eq!(spans.len(), 3);
+        let (fetch, lookup, root) = (&spans[0], &spans[1], &spans[2]);
+        assert_eq!(root.name, "fuse_read");
+        assert_eq!(root.parent_id, 0);
+        assert_eq!(root.trace_id, root.span_id);
+        assert_eq!(root.args, vec![("ino", 5)]);
+        assert_eq!(lookup.parent_id, root.span_id);
+        assert_eq!(fetch.parent_id, lookup.span_id);
+        assert_eq!(fetch.trace_id, root.trace_id);
+        assert!(root.duration_ns >= lookup.duration_ns);
+        assert!(lookup.start_ns >= root.start_ns);
+
+        // Only the latest spans are kept.
+        for _ in 0..5 {
+            drop(tracer.span("fuse_read"));
+        }
+        assert_eq!(
+            tracer.status(),
+            TraceStatus {
+                enabled: true,
+                capacity: 3,
+                spans: 3
+            }
+        );
+        let spans = tracer.take_spans();
+        assert!(spans.iter().all(|s| s.parent_id == 0));
+
+        tracer.stop();
+        drop(tracer.span("fuse_read"));
+        assert_eq!(tracer.status().spans, 0);
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let spans = vec![SpanRecord {
+            name: "backend_fetch",
+            trace_id: 1,
+            span_id: 2,
+            parent_id: 1,
+            tid: 100,
+            start_ns: 2_000_000,
+            duration_ns: 1_500,
+            args: vec![("size", 4096)],
+        }];
+
+        let chrome = to_chrome_trace(&spans);
+        let event = &chrome["traceEvents"][0];
+        assert_eq!(event["name"], "backend_fetch");
+        assert_eq!(event["ph"], "X");
+        assert_eq!(event["ts"], 2000.0);
+        assert_eq!(event["dur"], 1.5);
+        assert_eq!(event["tid"], 100);
+        assert_eq!(event["args"]["size"], 4096);
+
+        let otlp = to_otlp_json(&spans, "nydusd");
+        let span = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "00000000000000000000000000000001");
+        assert_eq!(span["spanId"], "0000000000000002");
+        assert_eq!(span["parentSpanId"], "0000000000000001");
+        assert_eq!(span["startTimeUnixNano"], "2000000");
+        assert_eq!(span["endTimeUnixNano"], "2001500");
+        assert_eq!(span["attributes"][0]["value"]["intValue"], "4096");
+    }
+
+    #[test]
+    fn test_export_otlp() {
+        assert_eq!(
+            parse_http_endpoint("http://127.0.0.1:4318").unwrap(),
+            ("127.0.0.1:4318".to_string(), OTLP_TRACES_PATH.to_string())
+        );
+        assert_eq!(
+            parse_http_endpoint("http://collector/traces").unwrap(),
+            ("collector:80".to_string(), "/traces".to_string())
+        );
+        assert!(parse_http_endpoint("https://collector").is_err());
+        assert!(parse_http_endpoint("http:///v1/traces").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Read the whole request before responding.
+            let mut req = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                req.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&req).to_string();
+                if let Some(pos) = text.find("\r\n\r\n") {
+                    let len: usize = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if req.len() >= pos + 4 + len {
+                        break;
+                    }
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&req).to_string()
+        });
+        export_otlp(&endpoint, &[], "nydusd").unwrap();
+        let req = server.join().unwrap();
+        assert!(req.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+    }
+}